@@ -11,6 +11,7 @@ fn main() {
 
     let linker_script = fs::read_to_string(linker_dir.join("bootloader_rp2040.x"))
         .expect("Failed to read bootloader_rp2040.x");
+    let linker_script = apply_flash_size(&linker_script, flash_size());
     fs::write(out_dir.join("memory.x"), linker_script).expect("Failed to write memory.x");
     println!("cargo:rustc-link-search={}", out_dir.display());
     println!("cargo:rustc-link-arg=-Tlink.x");
@@ -21,3 +22,35 @@ fn main() {
     );
     println!("cargo:rerun-if-changed=build.rs");
 }
+
+/// Flash size selected through the `flash-*` features (2MB by default).
+fn flash_size() -> u32 {
+    if env::var_os("CARGO_FEATURE_FLASH_16MB").is_some() {
+        16 * 1024 * 1024
+    } else if env::var_os("CARGO_FEATURE_FLASH_8MB").is_some() {
+        8 * 1024 * 1024
+    } else if env::var_os("CARGO_FEATURE_FLASH_4MB").is_some() {
+        4 * 1024 * 1024
+    } else {
+        2 * 1024 * 1024
+    }
+}
+
+/// Replace the `__flash_size` assignment in the linker script.
+fn apply_flash_size(script: &str, size: u32) -> String {
+    let mut found = false;
+    let out = script
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("__flash_size ") {
+                found = true;
+                format!("__flash_size       = 0x{:X};", size)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    assert!(found, "__flash_size not found in bootloader_rp2040.x");
+    out
+}
@@ -4,13 +4,21 @@
 //! Boot management: memory layout, firmware validation, bank selection, and jump.
 
 use crate::flash;
-use crispy_common::protocol::{BootData, RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC};
-
-const MAX_BOOT_ATTEMPTS: u8 = 3;
+use crispy_common::boot_fsm::{
+    needs_rollback, select_boot_bank_fsm, BankInfo, BankPair, BankValidation, BootDecision,
+};
+use crispy_common::boot_recovery::{reconstruct_boot_data, scan_banks, RamWindow};
+use crispy_common::led::{error_halt, StatusLed, ERR_LAYOUT};
+use crispy_common::memory_layout::{FlashLayout, LayoutError, MemoryLayout};
+use crispy_common::protocol::{
+    BootData, BootDataError, BootDataIssue, FLASH_SIZE, RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC,
+};
+use crispy_common::vector_table::VectorTable;
 
 unsafe extern "C" {
     static __fw_a_entry: u32;
     static __fw_b_entry: u32;
+    static __fw_bank_size: u32;
     static __fw_ram_base: u32;
     static __fw_copy_size: u32;
     static __boot_data_addr: u32;
@@ -24,49 +32,82 @@ macro_rules! linker_addr {
     };
 }
 
-#[allow(dead_code)]
-pub struct MemoryLayout {
-    pub fw_a: u32,
-    pub fw_b: u32,
-    pub ram_base: u32,
-    pub copy_size: u32,
-    pub boot_data: u32,
-}
-
-impl MemoryLayout {
-    pub fn from_linker() -> Self {
-        Self {
+/// The layout the linker script placed the bootloader in.
+pub fn layout_from_linker() -> MemoryLayout {
+    MemoryLayout {
+        flash: FlashLayout {
             fw_a: linker_addr!(__fw_a_entry),
             fw_b: linker_addr!(__fw_b_entry),
-            ram_base: linker_addr!(__fw_ram_base),
-            copy_size: linker_addr!(__fw_copy_size),
+            bank_size: linker_addr!(__fw_bank_size),
             boot_data: linker_addr!(__boot_data_addr),
-        }
+            ..FlashLayout::DEFAULT
+        },
+        ram_base: linker_addr!(__fw_ram_base),
+        copy_size: linker_addr!(__fw_copy_size),
     }
 }
 
-struct VectorTable {
-    initial_sp: u32,
-    reset_vector: u32,
-}
-
-impl VectorTable {
-    unsafe fn read_from(addr: u32) -> Self {
-        Self {
-            initial_sp: (addr as *const u32).read_volatile(),
-            reset_vector: (addr as *const u32).offset(1).read_volatile(),
+/// Check that the linker layout matches the protocol constants and that the
+/// configured flash size fits the detected part.
+///
+/// A layout mismatch is fatal: update mode would flash one address while
+/// boot validates another, so the LED shows `ERR_LAYOUT` and the core halts.
+/// A flash size that does not fit only inhibits flash writes for the rest of
+/// the session.
+pub fn check_layout(led: &mut impl StatusLed) -> bool {
+    if let Err(err) = layout_from_linker().check() {
+        match err {
+            LayoutError::FwAMismatch(addr) => defmt::println!("Linker bank A at 0x{:08x}", addr),
+            LayoutError::FwBMismatch(addr) => defmt::println!("Linker bank B at 0x{:08x}", addr),
+            LayoutError::BankSizeMismatch(size) => defmt::println!("Linker bank size 0x{:x}", size),
+            LayoutError::BootDataMismatch(addr) => {
+                defmt::println!("Linker BootData at 0x{:08x}", addr)
+            }
+            _ => {}
         }
+        defmt::println!("Linker layout does not match crispy-common constants");
+        error_halt(led, ERR_LAYOUT);
     }
 
-    fn is_valid_for_ram_execution(&self) -> bool {
-        is_in_ram(self.initial_sp) && is_in_ram(self.reset_vector)
+    let detected = flash::detected_flash_size();
+    if detected == 0 {
+        defmt::println!(
+            "Unknown flash size (JEDEC 0x{:06x}), assuming layout fits",
+            flash::jedec_id()
+        );
+        return true;
     }
+    if FLASH_SIZE > detected {
+        defmt::println!(
+            "Layout needs {}KB flash but chip has {}KB, flash writes disabled",
+            FLASH_SIZE / 1024,
+            detected / 1024
+        );
+        flash::inhibit_writes();
+        return false;
+    }
+
+    true
 }
 
-fn is_in_ram(addr: u32) -> bool {
-    let start = linker_addr!(__fw_ram_start);
-    let end = linker_addr!(__fw_ram_end);
-    (start..=end).contains(&addr)
+/// Read the vector table at `addr`.
+///
+/// # Safety
+/// `addr` must be readable (mapped flash or RAM).
+unsafe fn read_vector_table(addr: u32) -> VectorTable {
+    VectorTable::read(|a| (a as *const u32).read_volatile(), addr)
+}
+
+fn is_valid_for_ram_execution(vt: &VectorTable) -> bool {
+    vt.check(fw_ram_window()).is_ok()
+}
+
+/// RAM the firmware is copied to and runs from.
+fn fw_ram_window() -> RamWindow {
+    RamWindow {
+        start: linker_addr!(__fw_ram_start),
+        end: linker_addr!(__fw_ram_end),
+    }
 }
 
 /// Check if update mode is requested via GP2 pin (LOW) or RAM magic flag.
@@ -85,12 +126,13 @@ pub fn validate_bank_with_crc(addr: u32, crc: u32, size: u32) -> bool {
         return false;
     }
 
-    let vt = unsafe { VectorTable::read_from(addr) };
-    if !vt.is_valid_for_ram_execution() {
+    let vt = unsafe { read_vector_table(addr) };
+    if !is_valid_for_ram_execution(&vt) {
         return false;
     }
 
-    let actual_crc = flash::compute_crc32(addr, size);
+    // The watchdog only runs in update mode, but feeding it here costs nothing
+    let actual_crc = flash::compute_crc32(addr, size, |_| crate::watchdog::feed());
     if actual_crc != crc {
         defmt::println!(
             "CRC mismatch at 0x{:08x}: expected 0x{:08x}, got 0x{:08x}",
@@ -106,83 +148,127 @@ pub fn validate_bank_with_crc(addr: u32, crc: u32, size: u32) -> bool {
 
 /// Simple vector table validation without CRC (fallback mode).
 pub fn validate_bank(flash_addr: u32) -> Option<(u32, u32)> {
-    let vt = unsafe { VectorTable::read_from(flash_addr) };
-    if vt.is_valid_for_ram_execution() {
+    let vt = unsafe { read_vector_table(flash_addr) };
+    if is_valid_for_ram_execution(&vt) {
         Some((vt.initial_sp, vt.reset_vector))
     } else {
         None
     }
 }
 
-/// Select which bank to boot from, with automatic rollback on failure.
-pub fn select_boot_bank(bd: &BootData, layout: &MemoryLayout) -> (u32, BootData) {
-    let mut bd = *bd;
-
-    if bd.boot_attempts >= MAX_BOOT_ATTEMPTS && bd.confirmed == 0 {
-        defmt::println!(
-            "Boot attempts exhausted ({}), rolling back",
-            bd.boot_attempts
-        );
-        bd.active_bank = toggle_bank(bd.active_bank);
-        bd.boot_attempts = 0;
-        bd.confirmed = 0;
+/// Run both validation levels against a bank.
+fn validate(bank: &BankInfo) -> BankValidation {
+    BankValidation {
+        crc_valid: validate_bank_with_crc(bank.addr, bank.crc, bank.size),
+        basic_valid: validate_bank(bank.addr).is_some(),
     }
+}
 
-    let (primary_addr, fallback_addr) = bank_addresses(&bd, layout);
-    let (primary_crc, primary_size) = bank_metadata(&bd, bd.active_bank);
-    let (fallback_crc, fallback_size) = bank_metadata(&bd, toggle_bank(bd.active_bank));
-
-    if validate_bank_with_crc(primary_addr, primary_crc, primary_size) {
-        bd.boot_attempts += 1;
-        return (primary_addr, bd);
+/// Read BootData, rebuilding it from the banks if the stored record is corrupt.
+///
+/// A record that decodes but has impossible fields is used as is when it
+/// has none, repaired when every issue is repairable, and rebuilt otherwise
+/// (see `BootData::issues_for`). A rebuilt or repaired record is persisted and
+/// flagged (see `crispy_common::boot_recovery`). Only when neither bank
+/// looks bootable is an empty record returned, which sends the caller into
+/// update mode.
+fn load_boot_data(layout: &MemoryLayout) -> BootData {
+    match flash::read_stored_boot_data() {
+        Ok(bd) if bd.validate_extended_for(&layout.flash).is_ok() => return bd,
+        Ok(bd) => {
+            for issue in bd.issues_for(&layout.flash) {
+                log_issue(issue);
+            }
+            if let Some(fixed) = bd.repaired_for(&layout.flash) {
+                defmt::println!("BootData repaired");
+                unsafe {
+                    flash::write_boot_data(&fixed);
+                }
+                return fixed;
+            }
+        }
+        Err(BootDataError::BadMagic(magic)) => {
+            defmt::println!("BootData invalid (magic 0x{:08x})", magic)
+        }
+        Err(BootDataError::UnknownVersion(version)) => {
+            defmt::println!("BootData has unknown layout version {}", version)
+        }
+        Err(BootDataError::BadCrc) => defmt::println!("BootData record CRC mismatch"),
     }
 
-    defmt::println!("Primary bank invalid, trying fallback");
-
-    if validate_bank_with_crc(fallback_addr, fallback_crc, fallback_size) {
-        bd.active_bank = toggle_bank(bd.active_bank);
-        bd.boot_attempts = 1;
-        bd.confirmed = 0;
-        return (fallback_addr, bd);
-    }
+    let read_word = |addr: u32| unsafe { (addr as *const u32).read_volatile() };
+    let scan = scan_banks(
+        read_word,
+        layout.flash.fw_a,
+        layout.flash.fw_b,
+        fw_ram_window(),
+    );
 
-    if validate_bank(primary_addr).is_some() {
-        bd.boot_attempts += 1;
-        return (primary_addr, bd);
-    }
+    let Some(bd) = reconstruct_boot_data(scan) else {
+        defmt::println!("No bootable bank");
+        return BootData::default_new();
+    };
 
-    if validate_bank(fallback_addr).is_some() {
-        bd.active_bank = toggle_bank(bd.active_bank);
-        bd.boot_attempts = 1;
-        return (fallback_addr, bd);
+    defmt::println!(
+        "BootData reconstructed: A={}, B={}, active={}",
+        scan.bank_a,
+        scan.bank_b,
+        bd.active_bank
+    );
+    unsafe {
+        flash::write_boot_data(&bd);
     }
-
-    bd.boot_attempts += 1;
-    (primary_addr, bd)
+    bd
 }
 
-fn toggle_bank(bank: u8) -> u8 {
-    if bank == 0 {
-        1
-    } else {
-        0
+fn log_issue(issue: BootDataIssue) {
+    match issue {
+        BootDataIssue::BadMagic(magic) => {
+            defmt::println!("BootData issue: magic 0x{:08x}", magic)
+        }
+        BootDataIssue::ActiveBankOutOfRange(bank) => {
+            defmt::println!("BootData issue: active_bank={}", bank)
+        }
+        BootDataIssue::ConfirmedNotBool(value) => {
+            defmt::println!("BootData issue: confirmed={}", value)
+        }
+        BootDataIssue::AttemptsTooHigh(attempts) => {
+            defmt::println!("BootData issue: boot_attempts={}", attempts)
+        }
+        BootDataIssue::MaxAttemptsOutOfRange(max) => {
+            defmt::println!("BootData issue: max_boot_attempts={}", max)
+        }
+        BootDataIssue::SizeTooLarge { bank, size } => {
+            defmt::println!(
+                "BootData issue: bank {} size {} exceeds the bank",
+                bank,
+                size
+            )
+        }
+        BootDataIssue::UnknownFlags(flags) => {
+            defmt::println!("BootData issue: flags=0x{:02x}", flags)
+        }
     }
 }
 
-fn bank_addresses(bd: &BootData, layout: &MemoryLayout) -> (u32, u32) {
-    if bd.active_bank == 0 {
-        (layout.fw_a, layout.fw_b)
-    } else {
-        (layout.fw_b, layout.fw_a)
+/// Select which bank to boot from, with automatic rollback on failure.
+///
+/// The policy lives in `crispy_common::boot_fsm`; this only gathers the
+/// hardware validation results it needs.
+pub fn select_boot_bank(bd: &BootData, layout: &MemoryLayout) -> BootDecision {
+    let max_attempts = bd.boot_attempt_limit();
+    if needs_rollback(bd, max_attempts) {
+        defmt::println!(
+            "Boot attempts exhausted ({}/{}), rolling back",
+            bd.boot_attempts,
+            max_attempts
+        );
     }
-}
 
-fn bank_metadata(bd: &BootData, bank: u8) -> (u32, u32) {
-    if bank == 0 {
-        (bd.crc_a, bd.size_a)
-    } else {
-        (bd.crc_b, bd.size_b)
-    }
+    let banks = BankPair::new(bd.active_bank, &layout.flash, bd);
+    let (primary, fallback) = (validate(&banks.primary), validate(&banks.fallback));
+
+    select_boot_bank_fsm(bd, banks.with_validation(primary, fallback), max_attempts)
 }
 
 /// # Safety
@@ -195,7 +281,7 @@ pub unsafe fn load_and_jump(flash_addr: u32, layout: &MemoryLayout) -> ! {
 
     relocate_vector_table(layout.ram_base);
 
-    let vt = VectorTable::read_from(layout.ram_base);
+    let vt = read_vector_table(layout.ram_base);
     jump_to_firmware(vt.initial_sp, vt.reset_vector);
 }
 
@@ -317,33 +403,45 @@ pub fn run_normal_boot(p: &mut crate::peripherals::Peripherals) -> ! {
 
     defmt::println!("Normal boot path");
 
-    let layout = MemoryLayout::from_linker();
-    let bd = crate::flash::read_boot_data();
+    let layout = layout_from_linker();
+    let bd = load_boot_data(&layout);
 
     defmt::println!(
-        "BOOT_DATA: bank={}, confirmed={}, attempts={}, size_a={}, size_b={}, valid={}",
+        "BOOT_DATA: bank={}, confirmed={}, attempts={}, size_a={}, size_b={}, valid={}, reconstructed={}, repaired={}",
         bd.active_bank,
         bd.confirmed,
         bd.boot_attempts,
         bd.size_a,
         bd.size_b,
-        bd.is_valid()
+        bd.is_valid(),
+        bd.is_reconstructed(),
+        bd.is_repaired()
     );
 
-    // If BootData is valid but no firmware uploaded (both sizes 0), enter update mode
-    if bd.is_valid() && bd.size_a == 0 && bd.size_b == 0 {
+    // If BootData is valid but no firmware uploaded (both sizes 0), enter update mode.
+    // A reconstructed record has zero sizes because they are unknown, not empty.
+    if bd.is_valid() && bd.size_a == 0 && bd.size_b == 0 && !bd.is_reconstructed() {
         defmt::println!("No firmware uploaded, entering update mode");
         crate::update::enter_update_mode(p);
     }
 
-    let (flash_addr, updated_bd) = select_boot_bank(&bd, &layout);
+    let decision = select_boot_bank(&bd, &layout);
+    let flash_addr = decision.flash_addr;
     defmt::println!("Selected bank at 0x{:08x}", flash_addr);
 
-    unsafe {
-        crate::flash::write_boot_data(&updated_bd);
+    // A confirmed boot changes nothing: spare the sector an erase
+    let updated = decision.apply_to(&bd);
+    if updated != bd {
+        unsafe {
+            crate::flash::write_boot_data(&updated);
+        }
     }
 
-    let bank_label = if flash_addr == layout.fw_a { "A" } else { "B" };
+    let bank_label = if flash_addr == layout.flash.fw_a {
+        "A"
+    } else {
+        "B"
+    };
     if validate_bank(flash_addr).is_none() {
         defmt::println!("No valid firmware in any bank, entering update mode");
         crate::update::enter_update_mode(p);
@@ -1,161 +1,167 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2026 ADNT Sarl <info@adnt.io>
 
-//! Flash read/write/erase wrappers using RP2040 ROM routines.
+//! The bootloader's flash access, over
+//! [`RomFlash`](crispy_common::flash_ops::RomFlash).
 //!
-//! On RP2040, flash operations (erase/program) require disabling XIP first.
-//! The full sequence is:
-//!   1. connect_internal_flash()
-//!   2. flash_exit_xip()
-//!   3. flash_range_erase() or flash_range_program()
-//!   4. flash_flush_cache()
-//!   5. flash_enter_cmd_xip()
-//!
-//! All code executing during steps 1-5 must run from RAM, not flash.
-//! We use `#[link_section = ".data"]` to place critical functions in RAM,
-//! and pre-resolve all ROM function pointers at init time.
-
-use crc::{Crc, CRC_32_ISO_HDLC};
-use crispy_common::protocol::{
-    BootData, BOOT_DATA_ADDR, FLASH_BASE, FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE,
-};
-
-const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-
-// ROM function pointer types
-type RomFnVoid = unsafe extern "C" fn();
-type RomFnErase = unsafe extern "C" fn(u32, usize, u32, u8);
-type RomFnProgram = unsafe extern "C" fn(u32, *const u8, usize);
-
-/// ROM function pointers, resolved once at init from the ROM table.
-/// Stored in static RAM so RAM-resident functions can call them without
-/// accessing flash-based code.
-static mut ROM_CONNECT_INTERNAL_FLASH: RomFnVoid = dummy_void;
-static mut ROM_FLASH_EXIT_XIP: RomFnVoid = dummy_void;
-static mut ROM_FLASH_RANGE_ERASE: RomFnErase = dummy_erase;
-static mut ROM_FLASH_RANGE_PROGRAM: RomFnProgram = dummy_program;
-static mut ROM_FLASH_FLUSH_CACHE: RomFnVoid = dummy_void;
-static mut ROM_FLASH_ENTER_CMD_XIP: RomFnVoid = dummy_void;
-
-unsafe extern "C" fn dummy_void() {}
-unsafe extern "C" fn dummy_erase(_: u32, _: usize, _: u32, _: u8) {}
-unsafe extern "C" fn dummy_program(_: u32, _: *const u8, _: usize) {}
-
-/// Look up a ROM function by its two-character tag.
-/// ROM table pointer at 0x14 and lookup function at 0x18 are 16-bit halfword pointers.
-unsafe fn rom_func_lookup(tag: &[u8; 2]) -> usize {
-    let fn_table = *(0x14 as *const u16) as *const u16;
-    let lookup: unsafe extern "C" fn(*const u16, u32) -> usize =
-        core::mem::transmute::<usize, unsafe extern "C" fn(*const u16, u32) -> usize>(
-            *(0x18 as *const u16) as usize,
-        );
-    let code = u16::from_le_bytes(*tag) as u32;
-    lookup(fn_table, code)
-}
-
-/// Initialize ROM flash function pointers. Must be called once before any flash operations.
-/// This performs ROM table lookups which require XIP to be active.
+//! The ROM routines are resolved once in [`init`], while XIP is up, and the
+//! XIP-critical erase, program and raw command sequences are `RomFlash`'s,
+//! run from RAM. This module adds what only the bootloader needs: flash part
+//! detection, the write inhibit of a failed layout check, and BootData
+//! helpers that log instead of failing.
+
+use crispy_common::flash::FlashError;
+use crispy_common::flash_ops::{FlashOps, RomFlash};
+use crispy_common::protocol::{BootData, BootDataError};
+use crispy_common::{crc32, xip};
+
+/// Size of the attached flash part, or 0 if the JEDEC ID could not be decoded.
+static mut DETECTED_FLASH_SIZE: u32 = 0;
+static mut JEDEC_ID: u32 = 0;
+static mut FLASH_UID: u64 = 0;
+/// Cleared at startup when the built-in layout does not fit the flash part.
+static mut WRITES_ALLOWED: bool = true;
+static mut ROM_FLASH: Option<RomFlash> = None;
+
+/// Resolve the ROM flash routines. Must be called once before any flash
+/// operation, while XIP is up.
 pub fn init() {
     unsafe {
-        ROM_CONNECT_INTERNAL_FLASH =
-            core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"IF"));
-        ROM_FLASH_EXIT_XIP = core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"EX"));
-        ROM_FLASH_RANGE_ERASE = core::mem::transmute::<usize, RomFnErase>(rom_func_lookup(b"RE"));
-        ROM_FLASH_RANGE_PROGRAM =
-            core::mem::transmute::<usize, RomFnProgram>(rom_func_lookup(b"RP"));
-        ROM_FLASH_FLUSH_CACHE = core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"FC"));
-        ROM_FLASH_ENTER_CMD_XIP = core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"CX"));
+        ROM_FLASH = Some(RomFlash::new());
     }
 }
 
-/// Convert an absolute XIP flash address to a flash-relative offset.
-pub fn addr_to_offset(abs_addr: u32) -> u32 {
-    abs_addr - FLASH_BASE
+/// The ROM flash routines [`init`] resolved.
+fn rom_flash() -> RomFlash {
+    unsafe { *core::ptr::addr_of!(ROM_FLASH) }.expect("flash::init() not called")
 }
 
-/// Erase flash at the given flash-relative offset.
-/// Runs entirely from RAM with proper XIP teardown/setup.
-///
-/// # Safety
-/// The `init()` function must have been called first.
-#[link_section = ".data"]
-#[inline(never)]
-pub unsafe fn flash_erase(offset: u32, size: u32) {
-    cortex_m::interrupt::disable();
-    ROM_CONNECT_INTERNAL_FLASH();
-    ROM_FLASH_EXIT_XIP();
-    ROM_FLASH_RANGE_ERASE(offset, size as usize, FLASH_SECTOR_SIZE, 0x20);
-    ROM_FLASH_FLUSH_CACHE();
-    ROM_FLASH_ENTER_CMD_XIP();
-    cortex_m::interrupt::enable();
-}
-
-/// Program flash at the given flash-relative offset.
-/// Runs entirely from RAM with proper XIP teardown/setup.
-///
-/// # Safety
-/// The `init()` function must have been called first.
-#[link_section = ".data"]
-#[inline(never)]
-pub unsafe fn flash_program(offset: u32, data: *const u8, len: usize) {
-    cortex_m::interrupt::disable();
-    ROM_CONNECT_INTERNAL_FLASH();
-    ROM_FLASH_EXIT_XIP();
-    ROM_FLASH_RANGE_PROGRAM(offset, data, len);
-    ROM_FLASH_FLUSH_CACHE();
-    ROM_FLASH_ENTER_CMD_XIP();
-    cortex_m::interrupt::enable();
-}
-
-/// Read bytes from an absolute XIP flash address via volatile reads.
-pub fn flash_read(abs_addr: u32, buf: &mut [u8]) {
-    for (i, byte) in buf.iter_mut().enumerate() {
-        *byte = unsafe { ((abs_addr + i as u32) as *const u8).read_volatile() };
+/// Read the 3-byte JEDEC ID (manufacturer, memory type, capacity).
+fn read_jedec_id() -> u32 {
+    let mut buf = [0x9Fu8, 0, 0, 0];
+    rom_flash().transfer(&mut buf);
+    u32::from_be_bytes([0, buf[1], buf[2], buf[3]])
+}
+
+/// Read the 64-bit unique ID (command 0x4B, then 4 dummy bytes).
+fn read_unique_id() -> u64 {
+    let mut buf = [0u8; 13];
+    buf[0] = 0x4B;
+    rom_flash().transfer(&mut buf);
+    u64::from_be_bytes(buf[5..].try_into().unwrap())
+}
+
+/// Decode the capacity byte of a JEDEC ID as a size in bytes (2^n).
+/// Returns 0 for values that cannot be a real RP2040 flash part.
+fn jedec_capacity(id: u32) -> u32 {
+    match id & 0xFF {
+        n @ 0x10..=0x1F => 1 << n,
+        _ => 0,
     }
 }
 
-/// Compute CRC-32 (ISO HDLC) over flash data at the given absolute address.
-pub fn compute_crc32(abs_addr: u32, size: u32) -> u32 {
-    let mut digest = CRC32.digest();
-    let mut remaining = size as usize;
-    let mut addr = abs_addr;
-    let mut chunk = [0u8; 256];
-
-    while remaining > 0 {
-        let n = remaining.min(chunk.len());
-        flash_read(addr, &mut chunk[..n]);
-        digest.update(&chunk[..n]);
-        addr += n as u32;
-        remaining -= n;
+/// Query the flash part and remember its JEDEC ID, size and unique ID.
+/// Must be called after `init()`.
+pub fn detect_flash() {
+    let jedec_id = read_jedec_id();
+    let uid = read_unique_id();
+    unsafe {
+        JEDEC_ID = jedec_id;
+        DETECTED_FLASH_SIZE = jedec_capacity(jedec_id);
+        FLASH_UID = uid;
     }
+}
+
+pub fn jedec_id() -> u32 {
+    unsafe { JEDEC_ID }
+}
+
+/// Unique ID of the flash part; all ones or zeros if it has none.
+pub fn flash_uid() -> u64 {
+    unsafe { FLASH_UID }
+}
+
+/// Flash size reported by the chip, or 0 if unknown.
+pub fn detected_flash_size() -> u32 {
+    unsafe { DETECTED_FLASH_SIZE }
+}
 
-    digest.finalize()
+/// Refuse all further erase/program operations for this session.
+pub fn inhibit_writes() {
+    unsafe { WRITES_ALLOWED = false };
 }
 
-/// Read BootData from flash. Returns default if magic is invalid.
+/// Whether erase/program operations are permitted (layout check passed).
+pub fn writes_allowed() -> bool {
+    unsafe { WRITES_ALLOWED }
+}
+
+/// Read bytes from an absolute XIP flash address via word-wise volatile reads.
+pub fn flash_read(abs_addr: u32, buf: &mut [u8]) {
+    unsafe { xip::read_at(abs_addr, buf) }
+}
+
+/// Compute CRC-32 (ISO HDLC) over flash data at the given absolute address.
+///
+/// `on_chunk` runs every 4KB with the number of bytes hashed so far; pass
+/// `|_| {}` where nothing needs servicing.
+pub fn compute_crc32(abs_addr: u32, size: u32, on_chunk: impl FnMut(u32)) -> u32 {
+    unsafe { crc32::compute_over_flash_with(abs_addr, size, on_chunk) }
+}
+
+/// Read BootData from flash. Returns default if the stored record is invalid.
 pub fn read_boot_data() -> BootData {
-    let bd = unsafe { BootData::read_from(BOOT_DATA_ADDR) };
-    if bd.is_valid() {
-        bd
-    } else {
-        BootData::default_new()
-    }
+    read_stored_boot_data().unwrap_or_else(|_| BootData::default_new())
+}
+
+/// Read BootData from flash, or why the stored record was rejected.
+pub fn read_stored_boot_data() -> Result<BootData, BootDataError> {
+    crispy_common::flash::read_boot_data(&BootFlash::new())
 }
 
 /// Write BootData to flash (erase sector, then program padded to 256B page).
 ///
+/// A record that does not read back is logged; the boot carries on with
+/// the in-memory copy either way.
+///
 /// # Safety
 /// The `init()` function must have been called first.
 pub unsafe fn write_boot_data(bd: &BootData) {
-    let offset = addr_to_offset(BOOT_DATA_ADDR);
+    // With a mismatched layout BOOT_DATA_ADDR may alias the bootloader itself
+    if !writes_allowed() {
+        return;
+    }
 
-    // Erase the 4KB sector containing boot data
-    flash_erase(offset, FLASH_SECTOR_SIZE);
+    if let Err(FlashError::Verify(offset)) =
+        crispy_common::flash::write_boot_data(&mut BootFlash::new(), bd)
+    {
+        defmt::println!("BootData write did not verify at +0x{:x}", offset);
+    }
+}
+
+/// [`FlashOps`] over the [`RomFlash`] that [`init`] resolved.
+///
+/// Only constructed after `init()`.
+pub struct BootFlash {
+    rom: RomFlash,
+}
 
-    // Pad to a full 256-byte page
-    let mut page = [0xFFu8; FLASH_PAGE_SIZE as usize];
-    let src = bd.as_bytes();
-    page[..src.len()].copy_from_slice(src);
+impl BootFlash {
+    pub fn new() -> Self {
+        Self { rom: rom_flash() }
+    }
+}
 
-    flash_program(offset, page.as_ptr(), page.len());
+impl FlashOps for BootFlash {
+    fn erase(&mut self, offset: u32, len: u32) {
+        self.rom.erase(offset, len)
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        self.rom.program(offset, data)
+    }
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        self.rom.read(addr, buf)
+    }
 }
@@ -11,6 +11,9 @@ mod flash;
 mod peripherals;
 mod update;
 mod usb_transport;
+mod watchdog;
+#[cfg(feature = "webusb")]
+mod webusb;
 
 use defmt_rtt as _;
 use embedded_hal::digital::InputPin;
@@ -30,13 +33,20 @@ fn main() -> ! {
 
     let mut p = peripherals::init();
 
-    crispy_common::blink(&mut p.led_pin, &mut p.timer, 3, 200);
+    crispy_common::blink(&mut p.led, &mut p.timer, 3, 200);
     flash::init();
+    flash::detect_flash();
+    boot::check_layout(&mut p.led);
 
     let gp2_low = p.gp2.is_low().unwrap_or(false);
     if boot::check_update_trigger(gp2_low) {
         update::enter_update_mode(&mut p);
     }
+    if watchdog::update_interrupted() {
+        // Don't boot a bank that may have been half-written
+        defmt::println!("Reset during update mode, re-entering update mode");
+        update::enter_update_mode(&mut p);
+    }
 
     boot::run_normal_boot(&mut p);
 }
@@ -3,15 +3,17 @@
 
 //! Peripheral initialization for the bootloader.
 
+use crispy_common::{clocks, BoardLed};
 use rp2040_hal as hal;
 use rp2040_hal::usb::UsbBus;
 use usb_device::class_prelude::UsbBusAllocator;
 
-pub type LedPin =
-    hal::gpio::Pin<hal::gpio::bank0::Gpio25, hal::gpio::FunctionSioOutput, hal::gpio::PullDown>;
 pub type Gp2Pin =
     hal::gpio::Pin<hal::gpio::bank0::Gpio2, hal::gpio::FunctionSioInput, hal::gpio::PullUp>;
 
+// GP2 is the update-mode trigger and cannot double as the LED
+const _: () = assert!(crispy_common::led::LED_PIN != 2);
+
 /// Static storage for UsbBusAllocator (required by usb-device for 'static lifetime).
 static mut USB_BUS: Option<UsbBusAllocator<UsbBus>> = None;
 
@@ -26,7 +28,7 @@ pub fn store_usb_bus(bus: UsbBusAllocator<UsbBus>) {
 }
 
 pub struct Peripherals {
-    pub led_pin: LedPin,
+    pub led: BoardLed,
     pub gp2: Gp2Pin,
     pub timer: hal::Timer,
     pub usb: Option<UsbPeripherals>,
@@ -43,18 +45,6 @@ pub fn init() -> Peripherals {
     let mut pac = unsafe { hal::pac::Peripherals::steal() };
 
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
-    let clocks = hal::clocks::init_clocks_and_plls(
-        12_000_000u32,
-        pac.XOSC,
-        pac.CLOCKS,
-        pac.PLL_SYS,
-        pac.PLL_USB,
-        &mut pac.RESETS,
-        &mut watchdog,
-    )
-    .unwrap();
-
-    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
     let sio = hal::Sio::new(pac.SIO);
     let pins = hal::gpio::Pins::new(
         pac.IO_BANK0,
@@ -62,12 +52,30 @@ pub fn init() -> Peripherals {
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
+    // LED first so a clock failure can be signalled
+    let mut led = crispy_common::board_led();
+
+    let clocks = clocks::init_clocks(
+        clocks::ClockPeripherals {
+            xosc: pac.XOSC,
+            rosc: pac.ROSC,
+            clocks: pac.CLOCKS,
+            pll_sys: pac.PLL_SYS,
+            pll_usb: pac.PLL_USB,
+        },
+        &mut pac.RESETS,
+        &mut watchdog,
+        &mut led,
+    );
+
+    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    crate::watchdog::init(watchdog);
 
     Peripherals {
-        led_pin: pins.gpio25.into_push_pull_output(),
+        led,
         gp2: pins.gpio2.into_pull_up_input(),
         timer,
-        usb: Some(UsbPeripherals {
+        usb: clocks::USB_AVAILABLE.then(|| UsbPeripherals {
             regs: pac.USBCTRL_REGS,
             dpram: pac.USBCTRL_DPRAM,
             clock: clocks.usb_clock,
@@ -1,30 +1,47 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2026 ADNT Sarl <info@adnt.io>
 
-//! Firmware update state machine over USB CDC.
+//! Firmware update mode over USB CDC.
 //!
-//! This module implements the update protocol:
+//! The state machine itself lives in `crispy_common::update_fsm`; this module
+//! wires it to the USB transport, the watchdog and the real flash, and answers
+//! the commands that need the hardware. The protocol:
 //! - GetStatus: Query current bootloader state
 //! - StartUpdate: Begin firmware upload to a bank
 //! - DataBlock: Send firmware data chunks
 //! - FinishUpdate: Verify CRC and commit the update
 //! - Reboot: Restart the device
+//! - GetFlashInfo: Report the flash part and bank layout
+//! - BlankCheck: Verify a flash range reads back erased
+//! - CopyBank: Duplicate one bank and its metadata into the other
+//! - GetBootData: Send the raw BootData record and both banks' verification
+//! - GetDeviceInfo: Report bootloader version, chip/flash IDs and last error
+//! - ReadFlash: Stream a flash range back to the host
 
 use crate::flash;
 use crate::peripherals::{self, Peripherals};
-use crate::usb_transport::UsbTransport;
+use crate::usb_transport::{LinkEvent, UsbTransport};
+use crate::watchdog;
 use crispy_common::protocol::*;
-use embedded_hal::digital::OutputPin;
+use crispy_common::update_fsm::{self, ResponseSink, UpdateState};
+use crispy_common::{LedPattern, PatternPlayer, StatusLed};
 use rp2040_hal as hal;
 use usb_device::class_prelude::UsbBusAllocator;
 
+/// RP2040 `SYSINFO.CHIP_ID` register.
+const SYSINFO_CHIP_ID: *const u32 = 0x4000_0000 as *const u32;
+
 /// Enter update mode: initialize USB and run the update loop.
 pub fn enter_update_mode(p: &mut Peripherals) -> ! {
     defmt::println!("Update mode requested");
 
-    crispy_common::blink(&mut p.led_pin, &mut p.timer, 10, 50);
+    crispy_common::blink(&mut p.led, &mut p.timer, 10, 50);
 
-    let mut usb = p.usb.take().expect("USB peripherals already taken");
+    let Some(mut usb) = p.usb.take() else {
+        // rosc-only builds have no 48 MHz USB clock
+        defmt::println!("USB unavailable, cannot enter update mode");
+        crispy_common::led::error_halt(&mut p.led, crispy_common::led::ERR_NO_USB);
+    };
 
     let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
         usb.regs,
@@ -35,316 +52,227 @@ pub fn enter_update_mode(p: &mut Peripherals) -> ! {
     ));
 
     peripherals::store_usb_bus(usb_bus);
-    let mut transport = UsbTransport::new(peripherals::usb_bus_ref());
+    let mut transport = UsbTransport::new(peripherals::usb_bus_ref(), p.timer);
 
     defmt::println!("USB CDC initialized, entering update loop");
-    p.led_pin.set_high().ok();
+    watchdog::start_update_supervision();
 
-    run_update_mode(&mut transport)
+    let mut heartbeat = Heartbeat::new(&mut p.led, p.timer);
+    run_update_mode(&mut transport, &mut heartbeat)
 }
 
-/// Update state machine states.
-enum UpdateState {
-    /// Waiting for a new update to start.
-    Idle,
-    /// Actively receiving firmware data.
-    Receiving {
-        bank: u8,
-        bank_addr: u32,
-        expected_size: u32,
-        expected_crc: u32,
-        version: u32,
-        bytes_received: u32,
-    },
+/// Non-blocking LED heartbeat driven from the update loop.
+///
+/// A LED that stops blinking means the loop is wedged.
+pub struct Heartbeat<'a, L: StatusLed> {
+    led: &'a mut L,
+    timer: hal::Timer,
+    player: PatternPlayer,
 }
 
-/// Run the update mode loop. Does not return (reboot via SCB::sys_reset).
-pub fn run_update_mode(transport: &mut UsbTransport) -> ! {
-    let mut state = UpdateState::Idle;
-
-    loop {
-        transport.poll();
-
-        if let Some(cmd) = transport.try_receive() {
-            state = handle_command(transport, state, cmd);
+impl<'a, L: StatusLed> Heartbeat<'a, L> {
+    pub fn new(led: &'a mut L, timer: hal::Timer) -> Self {
+        Self {
+            led,
+            timer,
+            player: PatternPlayer::new(LedPattern::SLOW_BLINK),
         }
     }
-}
 
-/// Dispatch a command to its handler.
-fn handle_command(transport: &mut UsbTransport, state: UpdateState, cmd: Command) -> UpdateState {
-    match cmd {
-        Command::GetStatus => handle_get_status(transport, state),
-        Command::StartUpdate {
-            bank,
-            size,
-            crc32,
-            version,
-        } => handle_start_update(transport, state, bank, size, crc32, version),
-        Command::DataBlock { offset, data } => handle_data_block(transport, state, offset, data),
-        Command::FinishUpdate => handle_finish_update(transport, state),
-        Command::Reboot => handle_reboot(transport),
-        Command::SetActiveBank { bank } => handle_set_active_bank(transport, state, bank),
-        Command::WipeAll => handle_wipe_all(transport, state),
+    /// Play `pattern`, restarting it only if it differs from the current one.
+    pub fn tick(&mut self, pattern: LedPattern) {
+        self.player.set_pattern(pattern);
+        let now = self.timer.get_counter().ticks();
+        self.player.tick(self.led, now);
     }
 }
 
-/// Handle GetStatus command: return current bootloader status.
-fn handle_get_status(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
-    let bd = flash::read_boot_data();
-    let boot_state = match &state {
-        UpdateState::Idle => BootState::UpdateMode,
-        UpdateState::Receiving { .. } => BootState::Receiving,
-    };
-    transport.send(&Response::Status {
-        active_bank: bd.active_bank,
-        version_a: bd.version_a,
-        version_b: bd.version_b,
-        state: boot_state,
-    });
-    state
+/// Verifications shorter than this finish without progress frames.
+const PROGRESS_DELAY_US: u64 = 1_000_000;
+/// Minimum spacing of progress frames once they start.
+const PROGRESS_INTERVAL_US: u64 = 250_000;
+
+/// [`ResponseSink`] over the USB transport for the duration of one command.
+///
+/// Keeps USB polled and the watchdog fed during long flash operations, and
+/// sends `Progress` frames once a verification has run longer than
+/// `PROGRESS_DELAY_US`, so the host sees the device is still working.
+struct UsbSink<'t> {
+    transport: &'t mut UsbTransport,
+    started: u64,
+    last_report: u64,
 }
 
-/// Handle StartUpdate command: validate parameters, erase bank, begin receiving.
-fn handle_start_update(
-    transport: &mut UsbTransport,
-    state: UpdateState,
-    bank: u8,
-    size: u32,
-    crc32: u32,
-    version: u32,
-) -> UpdateState {
-    // Must be in Idle state
-    if !matches!(state, UpdateState::Idle) {
-        transport.send(&Response::Ack(AckStatus::BadState));
-        return state;
-    }
-
-    // Validate bank number
-    if bank > 1 {
-        transport.send(&Response::Ack(AckStatus::BankInvalid));
-        return state;
-    }
-
-    // Validate size
-    if size == 0 || size > FW_BANK_SIZE {
-        transport.send(&Response::Ack(AckStatus::BankInvalid));
-        return state;
-    }
-
-    let bank_addr = if bank == 0 { FW_A_ADDR } else { FW_B_ADDR };
-
-    // Erase the entire bank (rounded up to sector boundary)
-    let erase_size = size.div_ceil(FLASH_SECTOR_SIZE) * FLASH_SECTOR_SIZE;
-    let offset = flash::addr_to_offset(bank_addr);
-    unsafe {
-        flash::flash_erase(offset, erase_size);
-    }
-
-    transport.send(&Response::Ack(AckStatus::Ok));
-
-    UpdateState::Receiving {
-        bank,
-        bank_addr,
-        expected_size: size,
-        expected_crc: crc32,
-        version,
-        bytes_received: 0,
+impl<'t> UsbSink<'t> {
+    fn new(transport: &'t mut UsbTransport) -> Self {
+        let started = transport.now_us();
+        Self {
+            transport,
+            started,
+            last_report: started,
+        }
     }
 }
 
-/// Handle DataBlock command: validate offset, program flash.
-fn handle_data_block(
-    transport: &mut UsbTransport,
-    mut state: UpdateState,
-    offset: u32,
-    data: heapless::Vec<u8, MAX_DATA_BLOCK_SIZE>,
-) -> UpdateState {
-    let UpdateState::Receiving {
-        bank_addr,
-        ref mut bytes_received,
-        expected_size,
-        ..
-    } = state
-    else {
-        transport.send(&Response::Ack(AckStatus::BadState));
-        return state;
-    };
-
-    // Validate sequential offset
-    if offset != *bytes_received {
-        transport.send(&Response::Ack(AckStatus::BadCommand));
-        return state;
+impl ResponseSink for UsbSink<'_> {
+    fn send(&mut self, response: &Response) {
+        self.transport.send(response);
     }
 
-    // Validate data doesn't exceed expected size
-    let data_len = data.len() as u32;
-    if *bytes_received + data_len > expected_size {
-        transport.send(&Response::Ack(AckStatus::BadCommand));
-        return state;
+    fn send_encoded(&mut self, payload: &mut dyn Iterator<Item = u8>) {
+        self.transport.send_streamed(payload);
     }
 
-    // Pad data to 256-byte page boundary for flash programming
-    let mut page_buf = [0xFFu8; MAX_DATA_BLOCK_SIZE + FLASH_PAGE_SIZE as usize];
-    let actual_len = data.len();
-    page_buf[..actual_len].copy_from_slice(&data);
-    let padded_len = actual_len.div_ceil(FLASH_PAGE_SIZE as usize) * FLASH_PAGE_SIZE as usize;
-
-    let flash_offset = flash::addr_to_offset(bank_addr) + *bytes_received;
-    unsafe {
-        flash::flash_program(flash_offset, page_buf.as_ptr(), padded_len);
+    fn log(&mut self, line: &str) {
+        defmt::println!("{}", line);
+        self.transport.log(line);
     }
 
-    *bytes_received += data_len;
-    transport.send(&Response::Ack(AckStatus::Ok));
-    state
-}
-
-/// Handle FinishUpdate command: verify CRC, update BootData.
-fn handle_finish_update(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
-    let UpdateState::Receiving {
-        bank,
-        bank_addr,
-        expected_size,
-        expected_crc,
-        version,
-        bytes_received,
-    } = state
-    else {
-        transport.send(&Response::Ack(AckStatus::BadState));
-        return state;
-    };
-
-    // Verify all data was received
-    if bytes_received != expected_size {
-        transport.send(&Response::Ack(AckStatus::BadCommand));
-        return UpdateState::Receiving {
-            bank,
-            bank_addr,
-            expected_size,
-            expected_crc,
-            version,
-            bytes_received,
-        };
+    fn flush(&mut self) {
+        self.transport.flush();
     }
 
-    // Verify CRC
-    let actual_crc = flash::compute_crc32(bank_addr, expected_size);
-    if actual_crc != expected_crc {
-        defmt::println!(
-            "CRC mismatch: expected 0x{:08x}, got 0x{:08x}",
-            expected_crc,
-            actual_crc
-        );
-        transport.send(&Response::Ack(AckStatus::CrcError));
-        return UpdateState::Idle;
+    fn keep_alive(&mut self) {
+        watchdog::feed();
+        self.transport.poll();
     }
 
-    // Update BootData
-    let mut bd = flash::read_boot_data();
-    bd.active_bank = bank;
-    bd.confirmed = 0; // unconfirmed until firmware confirms
-    bd.boot_attempts = 0;
-
-    if bank == 0 {
-        bd.version_a = version;
-        bd.crc_a = expected_crc;
-        bd.size_a = expected_size;
-    } else {
-        bd.version_b = version;
-        bd.crc_b = expected_crc;
-        bd.size_b = expected_size;
-    }
+    fn progress(&mut self, done: u32, total: u32) {
+        self.keep_alive();
 
-    unsafe {
-        flash::write_boot_data(&bd);
+        let now = self.transport.now_us();
+        if now - self.started >= PROGRESS_DELAY_US && now - self.last_report >= PROGRESS_INTERVAL_US
+        {
+            self.last_report = now;
+            self.transport.send(&Response::Progress { done, total });
+        }
     }
-
-    transport.send(&Response::Ack(AckStatus::Ok));
-    UpdateState::Idle
 }
 
-/// Handle Reboot command: send ACK and reset the system.
-fn handle_reboot(transport: &mut UsbTransport) -> ! {
-    transport.send(&Response::Ack(AckStatus::Ok));
-    // Small delay to let the ACK be sent
-    cortex_m::asm::delay(12_000_000); // ~1s at 12MHz
-    cortex_m::peripheral::SCB::sys_reset();
-}
-
-/// Handle SetActiveBank command: change the active bank for next boot.
-fn handle_set_active_bank(
+/// Run the update mode loop. Does not return (reboot via SCB::sys_reset).
+///
+/// The LED blinks slowly while waiting for a host, beats like a heart once
+/// one has the port open, and blinks rapidly while a transfer is in progress. The watchdog
+/// is fed once per iteration and per sector in long flash operations.
+pub fn run_update_mode<L: StatusLed>(
     transport: &mut UsbTransport,
-    state: UpdateState,
-    bank: u8,
-) -> UpdateState {
-    // Must be in Idle state
-    if !matches!(state, UpdateState::Idle) {
-        transport.send(&Response::Ack(AckStatus::BadState));
-        return state;
-    }
-
-    // Validate bank number
-    if bank > 1 {
-        transport.send(&Response::Ack(AckStatus::BankInvalid));
-        return state;
-    }
-
-    // Read current BootData and update active bank
-    let mut bd = flash::read_boot_data();
+    heartbeat: &mut Heartbeat<'_, L>,
+) -> ! {
+    let mut state = UpdateState::Idle;
 
-    // Check that the target bank has valid firmware
-    let (size, crc) = if bank == 0 {
-        (bd.size_a, bd.crc_a)
-    } else {
-        (bd.size_b, bd.crc_b)
-    };
+    loop {
+        watchdog::feed();
+        transport.poll();
 
-    if size == 0 {
-        defmt::println!("SetActiveBank: bank {} has no firmware", bank);
-        transport.send(&Response::Ack(AckStatus::BankInvalid));
-        return state;
-    }
+        match transport.take_link_event() {
+            Some(LinkEvent::Connected) => defmt::println!("Host connected"),
+            Some(LinkEvent::Disconnected) => {
+                if state.is_receiving() {
+                    defmt::println!("Host disconnected mid-transfer, aborting update");
+                    transport.log("Update aborted: host disconnected");
+                } else {
+                    defmt::println!("Host disconnected");
+                }
+                state = UpdateState::Idle;
+            }
+            None => {}
+        }
 
-    // Verify CRC of the target bank
-    let bank_addr = if bank == 0 { FW_A_ADDR } else { FW_B_ADDR };
-    let actual_crc = flash::compute_crc32(bank_addr, size);
-    if actual_crc != crc {
-        defmt::println!(
-            "SetActiveBank: bank {} CRC mismatch (expected 0x{:08x}, got 0x{:08x})",
-            bank,
-            crc,
-            actual_crc
-        );
-        transport.send(&Response::Ack(AckStatus::CrcError));
-        return state;
+        heartbeat.tick(match state {
+            UpdateState::Receiving { .. } => LedPattern::FAST_BLINK,
+            UpdateState::Idle if transport.host_connected() => LedPattern::HEARTBEAT,
+            UpdateState::Idle => LedPattern::SLOW_BLINK,
+        });
+
+        match transport.try_receive() {
+            Some(Ok(cmd)) => state = handle_command(transport, state, cmd),
+            Some(Err(err)) => {
+                defmt::println!("Rejected malformed command");
+                transport.send(&Response::Ack(err.status()));
+            }
+            None => {}
+        }
     }
+}
 
-    // Update BootData
-    bd.active_bank = bank;
-    bd.confirmed = 0; // unconfirmed until firmware confirms
-    bd.boot_attempts = 0;
-
-    unsafe {
-        flash::write_boot_data(&bd);
+/// Answer platform commands here and hand the rest to the shared state machine.
+fn handle_command(transport: &mut UsbTransport, state: UpdateState, cmd: Command) -> UpdateState {
+    match cmd {
+        // Refuse anything that erases or programs flash when the layout check failed
+        Command::StartUpdate { .. }
+        | Command::SetActiveBank { .. }
+        | Command::WipeAll
+        | Command::SetBootAttempts { .. }
+        | Command::CopyBank { .. }
+        | Command::SetVersion { .. }
+        | Command::SetBootData { .. }
+        | Command::SetProvision { .. }
+            if !flash::writes_allowed() =>
+        {
+            transport.send(&Response::Ack(AckStatus::LayoutMismatch));
+            state
+        }
+        Command::GetStatus => handle_get_status(transport, state),
+        Command::GetFlashInfo => handle_get_flash_info(transport, state),
+        Command::GetDeviceInfo => handle_get_device_info(transport, state),
+        Command::Reboot => handle_reboot(transport),
+        cmd => update_fsm::handle_command(
+            &mut flash::BootFlash::new(),
+            &mut UsbSink::new(transport),
+            state,
+            cmd,
+        ),
     }
+}
 
-    defmt::println!("SetActiveBank: switched to bank {}", bank);
-    transport.send(&Response::Ack(AckStatus::Ok));
+/// Handle GetStatus command: return current bootloader status.
+fn handle_get_status(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
+    let bd = flash::read_boot_data();
+    transport.send(&Response::Status {
+        active_bank: bd.active_bank,
+        version_a: bd.version_a,
+        version_b: bd.version_b,
+        state: state.boot_state(),
+        host_connected: transport.host_connected(),
+        tx_stalled: transport.tx_stalled(),
+        bootdata_reconstructed: bd.is_reconstructed(),
+        update_interrupted: watchdog::update_interrupted(),
+    });
     state
 }
 
-fn handle_wipe_all(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
-    if !matches!(state, UpdateState::Idle) {
-        transport.send(&Response::Ack(AckStatus::BadState));
-        return state;
-    }
+/// Handle GetFlashInfo command: report the detected part and the built-in layout.
+fn handle_get_flash_info(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
+    let layout = crate::boot::layout_from_linker().flash;
+    transport.send(&Response::FlashInfo {
+        jedec_id: flash::jedec_id(),
+        detected_size: flash::detected_flash_size(),
+        layout_size: layout.flash_size,
+        bank_size: layout.bank_size,
+        fw_a_addr: layout.fw_a,
+        fw_b_addr: layout.fw_b,
+        boot_data_addr: layout.boot_data,
+    });
+    state
+}
 
-    defmt::println!("Resetting boot data");
-    unsafe {
-        flash::write_boot_data(&BootData::default_new());
-    }
+/// Handle GetDeviceInfo command: report what identifies this unit for support.
+fn handle_get_device_info(transport: &mut UsbTransport, state: UpdateState) -> UpdateState {
+    transport.send(&Response::DeviceInfo {
+        bootloader_version: env!("CARGO_PKG_VERSION").parse().unwrap_or_default(),
+        chip_id: unsafe { SYSINFO_CHIP_ID.read_volatile() },
+        flash_uid: flash::flash_uid(),
+        last_error: transport.last_error(),
+    });
+    state
+}
 
+/// Handle Reboot command: send ACK and reset the system.
+fn handle_reboot(transport: &mut UsbTransport) -> ! {
     transport.send(&Response::Ack(AckStatus::Ok));
-    state
+    transport.flush();
+    // Small delay to let the host read the ACK before the port disappears
+    cortex_m::asm::delay(12_000_000); // ~1s at 12MHz
+    watchdog::stop();
+    cortex_m::peripheral::SCB::sys_reset();
 }
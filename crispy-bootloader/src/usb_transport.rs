@@ -2,93 +2,375 @@
 // Copyright (c) 2026 ADNT Sarl <info@adnt.io>
 
 //! USB CDC transport with COBS-framed postcard serialization.
+//!
+//! The bootloader enumerates as a composite device: a text console CDC on
+//! interfaces 0/1 for human-readable logging, and the protocol CDC on
+//! interfaces 2/3 (`PROTOCOL_INTERFACE`). The `single-cdc` feature keeps the
+//! old single-port layout for existing installs.
+//!
+//! With the `webusb` feature (default) a vendor bulk interface follows the
+//! CDCs and carries the same frames for browser-based updaters. Responses
+//! go back on whichever channel the last command arrived on.
 
-use crispy_common::protocol::{Command, Response};
+use crispy_common::cobs::{CobsFrameDecoder, CobsStreamEncoder};
+use crispy_common::protocol::{
+    AckStatus, Command, ProtocolError, Response, BOOTLOADER_PID, USB_VID,
+};
+use crispy_common::tx_queue::TxQueue;
 use rp2040_hal::usb::UsbBus;
+use rp2040_hal::Timer;
 use usb_device::class_prelude::UsbBusAllocator;
 use usb_device::prelude::*;
 use usbd_serial::SerialPort;
 
+#[cfg(feature = "webusb")]
+use crate::webusb::WebUsbClass;
+
 const RX_BUF_SIZE: usize = 2048;
 const TX_BUF_SIZE: usize = 2048;
 
+/// Give up on a response if the host hasn't drained it within this time.
+const TX_TIMEOUT_US: u64 = 250_000;
+
+/// Change in host presence, derived from the CDC DTR line (or, for WebUSB,
+/// from the first command until the device is deconfigured).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LinkEvent {
+    Connected,
+    Disconnected,
+}
+
+/// Host-facing channel a command arrived on.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    Cdc,
+    #[cfg(feature = "webusb")]
+    WebUsb,
+}
+
+/// Bytes read from the USB endpoint per call; one full-speed bulk packet,
+/// which is also the minimum `WebUsbClass::read` accepts.
+const RX_PACKET_SIZE: usize = 64;
+
+/// COBS frame reassembly for one channel.
+struct FrameRx {
+    decoder: CobsFrameDecoder<RX_BUF_SIZE>,
+    /// Last packet read from the endpoint; bytes past `packet_pos` have not
+    /// been fed to the decoder yet.
+    packet: [u8; RX_PACKET_SIZE],
+    packet_pos: usize,
+    packet_len: usize,
+}
+
+impl FrameRx {
+    const fn new() -> Self {
+        Self {
+            decoder: CobsFrameDecoder::new(),
+            packet: [0u8; RX_PACKET_SIZE],
+            packet_pos: 0,
+            packet_len: 0,
+        }
+    }
+
+    /// Read from `read` until a complete frame is received or no more data
+    /// is available. Bytes belonging to the next frame stay buffered.
+    /// A frame that does not decode as a command is returned as
+    /// `ProtocolError::Malformed`, so the host gets an answer.
+    fn receive(
+        &mut self,
+        mut read: impl FnMut(&mut [u8]) -> usb_device::Result<usize>,
+    ) -> Option<Result<Command, ProtocolError>> {
+        loop {
+            while self.packet_pos < self.packet_len {
+                let byte = self.packet[self.packet_pos];
+                self.packet_pos += 1;
+                if let Some(frame) = self.decoder.feed(byte) {
+                    return Some(
+                        postcard::from_bytes::<Command>(frame)
+                            .map_err(|_| ProtocolError::Malformed),
+                    );
+                }
+            }
+
+            match read(&mut self.packet) {
+                Ok(count) if count > 0 => {
+                    self.packet_pos = 0;
+                    self.packet_len = count;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
 pub struct UsbTransport {
+    /// Text console, allocated first so it takes interfaces 0/1.
+    #[cfg(not(feature = "single-cdc"))]
+    console: SerialPort<'static, UsbBus>,
+    #[cfg(not(feature = "single-cdc"))]
+    console_open: bool,
+    /// Protocol port.
     serial: SerialPort<'static, UsbBus>,
+    /// Vendor bulk interface for WebUSB, allocated last.
+    #[cfg(feature = "webusb")]
+    webusb: WebUsbClass<'static, UsbBus>,
+    /// Set once a command arrives over WebUSB; cleared when deconfigured.
+    #[cfg(feature = "webusb")]
+    webusb_active: bool,
     usb_dev: UsbDevice<'static, UsbBus>,
-    rx_buf: [u8; RX_BUF_SIZE],
-    rx_pos: usize,
+    timer: Timer,
+    serial_rx: FrameRx,
+    #[cfg(feature = "webusb")]
+    webusb_rx: FrameRx,
+    reply_to: Channel,
+    tx: TxQueue<TX_BUF_SIZE>,
+    tx_stalled: bool,
+    last_error: Option<AckStatus>,
+    host_connected: bool,
+    link_event: Option<LinkEvent>,
 }
 
 impl UsbTransport {
-    pub fn new(usb_bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+    pub fn new(usb_bus: &'static UsbBusAllocator<UsbBus>, timer: Timer) -> Self {
+        #[cfg(not(feature = "single-cdc"))]
+        let console = SerialPort::new(usb_bus);
         let serial = SerialPort::new(usb_bus);
-        let usb_dev = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x2E8A, 0x000A))
+        #[cfg(feature = "webusb")]
+        let webusb = WebUsbClass::new(usb_bus);
+
+        let builder = UsbDeviceBuilder::new(usb_bus, UsbVidPid(USB_VID, BOOTLOADER_PID))
             .strings(&[StringDescriptors::default()
                 .manufacturer("ADNT")
                 .product("Crispy Bootloader")
                 .serial_number("0001")])
-            .unwrap()
-            .device_class(usbd_serial::USB_CLASS_CDC)
-            .build();
+            .unwrap();
+        #[cfg(any(not(feature = "single-cdc"), feature = "webusb"))]
+        let builder = builder.composite_with_iads();
+        #[cfg(all(feature = "single-cdc", not(feature = "webusb")))]
+        let builder = builder.device_class(usbd_serial::USB_CLASS_CDC);
+        let usb_dev = builder.build();
 
         Self {
+            #[cfg(not(feature = "single-cdc"))]
+            console,
+            #[cfg(not(feature = "single-cdc"))]
+            console_open: false,
             serial,
+            #[cfg(feature = "webusb")]
+            webusb,
+            #[cfg(feature = "webusb")]
+            webusb_active: false,
             usb_dev,
-            rx_buf: [0u8; RX_BUF_SIZE],
-            rx_pos: 0,
+            timer,
+            serial_rx: FrameRx::new(),
+            #[cfg(feature = "webusb")]
+            webusb_rx: FrameRx::new(),
+            reply_to: Channel::Cdc,
+            tx: TxQueue::new(),
+            tx_stalled: false,
+            last_error: None,
+            host_connected: false,
+            link_event: None,
         }
     }
 
     /// Poll USB device. Must be called frequently.
     pub fn poll(&mut self) -> bool {
-        self.usb_dev.poll(&mut [&mut self.serial])
-    }
-
-    /// Try to receive a complete COBS-framed command.
-    /// Returns `Some(Command)` when a full frame has been decoded.
-    pub fn try_receive(&mut self) -> Option<Command> {
-        // Read available bytes from USB serial
-        let mut tmp = [0u8; 64];
-        match self.serial.read(&mut tmp) {
-            Ok(count) if count > 0 => {
-                for &byte in &tmp[..count] {
-                    if byte == 0x00 {
-                        // COBS delimiter — try to decode the accumulated frame
-                        if self.rx_pos > 0 {
-                            let result = postcard::from_bytes_cobs::<Command>(
-                                &mut self.rx_buf[..self.rx_pos],
-                            );
-                            self.rx_pos = 0;
-                            return result.ok();
-                        }
-                    } else if self.rx_pos < RX_BUF_SIZE {
-                        self.rx_buf[self.rx_pos] = byte;
-                        self.rx_pos += 1;
-                    } else {
-                        // Overflow — discard frame
-                        self.rx_pos = 0;
-                    }
-                }
+        let activity = self.usb_dev.poll(&mut [
+            #[cfg(not(feature = "single-cdc"))]
+            &mut self.console,
+            &mut self.serial,
+            #[cfg(feature = "webusb")]
+            &mut self.webusb,
+        ]);
+        #[cfg(not(feature = "single-cdc"))]
+        self.drain_console();
+
+        #[cfg(feature = "webusb")]
+        if self.usb_dev.state() != UsbDeviceState::Configured {
+            self.webusb_active = false;
+        }
+        #[cfg(feature = "webusb")]
+        let connected = self.serial.dtr() || self.webusb_active;
+        #[cfg(not(feature = "webusb"))]
+        let connected = self.serial.dtr();
+        if connected != self.host_connected {
+            self.host_connected = connected;
+            self.link_event = Some(if connected {
+                LinkEvent::Connected
+            } else {
+                // Nobody will read what's queued; don't stall on it
+                self.tx.clear();
+                LinkEvent::Disconnected
+            });
+        }
+
+        activity
+    }
+
+    /// Discard anything typed into the console; it is output-only.
+    #[cfg(not(feature = "single-cdc"))]
+    fn drain_console(&mut self) {
+        let mut sink = [0u8; 64];
+        while matches!(self.console.read(&mut sink), Ok(n) if n > 0) {}
+
+        let open = self.console.dtr();
+        if open && !self.console_open {
+            // Tell whoever opened a terminal here that this is not the protocol port
+            self.log("Crispy Bootloader console (log only)");
+            self.log("Use crispy-upload for updates; it talks to the other interface.");
+        }
+        self.console_open = open;
+    }
+
+    /// Write a line to the text console if a terminal has it open.
+    ///
+    /// Best effort: output that does not fit the endpoint is dropped rather
+    /// than stalling the update loop. A no-op in `single-cdc` builds.
+    pub fn log(&mut self, line: &str) {
+        #[cfg(not(feature = "single-cdc"))]
+        if self.console.dtr() {
+            let _ = self.console.write(line.as_bytes());
+            let _ = self.console.write(b"\r\n");
+        }
+        #[cfg(feature = "single-cdc")]
+        let _ = line;
+    }
+
+    /// Microseconds since boot, from the transport's timer.
+    pub fn now_us(&self) -> u64 {
+        self.timer.get_counter().ticks()
+    }
+
+    /// Whether a host has the port open (DTR asserted) or is talking WebUSB.
+    pub fn host_connected(&self) -> bool {
+        self.host_connected
+    }
+
+    /// Take the most recent host connect/disconnect edge, if any.
+    pub fn take_link_event(&mut self) -> Option<LinkEvent> {
+        self.link_event.take()
+    }
+
+    /// Try to receive a complete COBS-framed command from any channel.
+    /// Returns `Some` when a full frame has arrived, with the command or why
+    /// it could not be decoded; the response to it will be sent on the same
+    /// channel.
+    pub fn try_receive(&mut self) -> Option<Result<Command, ProtocolError>> {
+        let serial = &mut self.serial;
+        if let Some(cmd) = self.serial_rx.receive(|buf| serial.read(buf)) {
+            self.reply_to = Channel::Cdc;
+            return Some(cmd);
+        }
+
+        #[cfg(feature = "webusb")]
+        {
+            let webusb = &mut self.webusb;
+            let cmd = self.webusb_rx.receive(|buf| webusb.read(buf));
+            if cmd.is_some() {
+                self.reply_to = Channel::WebUsb;
+                self.webusb_active = true;
+                return cmd;
             }
-            _ => {}
         }
+
         None
     }
 
     /// Send a response as a COBS-framed postcard message.
+    ///
+    /// Never blocks for longer than `TX_TIMEOUT_US`; a response the host
+    /// doesn't read in time is dropped and the stall is recorded.
     pub fn send(&mut self, resp: &Response) {
-        let mut buf = [0u8; TX_BUF_SIZE];
-        if let Ok(encoded) = postcard::to_slice_cobs(resp, &mut buf) {
-            let mut offset = 0;
-            while offset < encoded.len() {
-                match self.serial.write(&encoded[offset..]) {
-                    Ok(n) => offset += n,
-                    Err(UsbError::WouldBlock) => {
-                        self.poll();
-                    }
-                    Err(_) => break,
+        match resp {
+            Response::Ack(status) | Response::Nack { status, .. } if *status != AckStatus::Ok => {
+                self.last_error = Some(*status);
+            }
+            _ => {}
+        }
+        if !self.tx.push(resp) {
+            defmt::println!("TX queue full, response dropped");
+            self.tx_stalled = true;
+            return;
+        }
+        self.flush();
+    }
+
+    /// Send a frame whose postcard-serialized payload is produced on the fly.
+    ///
+    /// The frame is COBS-encoded a block at a time and fed through the TX
+    /// queue, so payloads larger than the queue (e.g. read straight from
+    /// flash) are never staged whole. If the host stops reading mid-frame
+    /// the rest is dropped and the stall recorded; the host's decoder
+    /// discards the cut-off frame.
+    pub fn send_streamed(&mut self, payload: impl Iterator<Item = u8>) {
+        let mut encoder = CobsStreamEncoder::new(payload);
+        while let Some(chunk) = encoder.next_chunk() {
+            if !self.tx.push_raw(chunk) && !(self.drain() && self.tx.push_raw(chunk)) {
+                defmt::println!("TX stalled, streamed response cut off");
+                self.tx_stalled = true;
+                return;
+            }
+        }
+        self.flush();
+    }
+
+    /// Drain queued responses to the host and flush the reply endpoint.
+    ///
+    /// Call before operations that keep interrupts disabled for a long time
+    /// (bank erase, reset) so queued bytes actually leave the device.
+    pub fn flush(&mut self) {
+        self.drain();
+    }
+
+    /// [`flush`](Self::flush), returning false if it gave up on a stalled host.
+    fn drain(&mut self) -> bool {
+        let start = self.timer.get_counter().ticks();
+
+        loop {
+            if !self.tx.is_empty() {
+                let written = match self.reply_to {
+                    Channel::Cdc => self.serial.write(self.tx.pending()),
+                    #[cfg(feature = "webusb")]
+                    Channel::WebUsb => self.webusb.write(self.tx.pending()),
+                };
+                match written {
+                    Ok(n) => self.tx.consume(n),
+                    Err(UsbError::WouldBlock) => {}
+                    Err(_) => self.tx.clear(),
                 }
             }
+
+            // Bulk writes go straight to the endpoint; only the CDC buffers
+            let flushed = match self.reply_to {
+                Channel::Cdc => self.serial.flush().is_ok(),
+                #[cfg(feature = "webusb")]
+                Channel::WebUsb => true,
+            };
+            if self.tx.is_empty() && flushed {
+                return true;
+            }
+
+            if self.timer.get_counter().ticks() - start > TX_TIMEOUT_US {
+                defmt::println!("TX stalled, dropping {} bytes", self.tx.pending().len());
+                self.tx.clear();
+                self.tx_stalled = true;
+                return false;
+            }
+
+            self.poll();
         }
     }
+
+    /// Whether a response has ever been dropped because the host stopped
+    /// reading. Sticky for the lifetime of the update session.
+    pub fn tx_stalled(&self) -> bool {
+        self.tx_stalled
+    }
+
+    /// The most recent non-`Ok` status sent to the host, sticky like
+    /// [`tx_stalled`](Self::tx_stalled).
+    pub fn last_error(&self) -> Option<AckStatus> {
+        self.last_error
+    }
 }
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Hardware watchdog supervision of update mode.
+//!
+//! Update mode runs under the watchdog so a wedged loop resets the chip
+//! instead of hanging until a power cycle. Before the watchdog is started a
+//! marker is written to a watchdog scratch register, which survives the
+//! reset: on the next boot the marker sends the bootloader straight back into
+//! update mode rather than booting a possibly half-written bank.
+
+use rp2040_hal as hal;
+use rp2040_hal::fugit::ExtU32;
+use rp2040_hal::watchdog::ScratchRegister;
+
+/// Update mode must feed the watchdog at least this often.
+const UPDATE_TIMEOUT_MS: u32 = 5_000;
+
+/// Scratch 4-7 belong to the boot ROM; scratch 0 is free for us.
+const MARKER_REG: ScratchRegister = ScratchRegister::Scratch0;
+const UPDATE_MARKER: u32 = 0x0FDA_7E0D;
+
+static mut WATCHDOG: Option<hal::Watchdog> = None;
+static mut UPDATE_INTERRUPTED: bool = false;
+
+fn watchdog() -> Option<&'static mut hal::Watchdog> {
+    unsafe { (*core::ptr::addr_of_mut!(WATCHDOG)).as_mut() }
+}
+
+/// Take ownership of the watchdog and check for an interrupted update.
+///
+/// A marker left by a previous update-mode session means the chip was reset
+/// (watchdog timeout, fault) before the session ended with a reboot command.
+/// The marker is cleared here; query the result with [`update_interrupted`].
+pub fn init(mut wd: hal::Watchdog) {
+    let interrupted = wd.read_scratch(MARKER_REG) == UPDATE_MARKER;
+    wd.write_scratch(MARKER_REG, 0);
+    unsafe {
+        UPDATE_INTERRUPTED = interrupted;
+        WATCHDOG = Some(wd);
+    }
+}
+
+/// Whether the previous update-mode session ended in a reset instead of a
+/// deliberate reboot.
+pub fn update_interrupted() -> bool {
+    unsafe { UPDATE_INTERRUPTED }
+}
+
+/// Mark update mode as in progress and start the watchdog.
+pub fn start_update_supervision() {
+    if let Some(wd) = watchdog() {
+        wd.write_scratch(MARKER_REG, UPDATE_MARKER);
+        // Halting in a debugger should not reset the chip
+        wd.pause_on_debug(true);
+        wd.start(UPDATE_TIMEOUT_MS.millis());
+    }
+}
+
+/// Reload the watchdog counter. Harmless when the watchdog is not running.
+pub fn feed() {
+    if let Some(wd) = watchdog() {
+        wd.feed();
+    }
+}
+
+/// Stop supervision and clear the marker before a deliberate reboot, so the
+/// next boot takes the normal path and the firmware is not reset by us.
+pub fn stop() {
+    if let Some(wd) = watchdog() {
+        wd.disable();
+        wd.write_scratch(MARKER_REG, 0);
+    }
+}
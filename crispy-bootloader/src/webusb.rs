@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Vendor-class bulk interface for browser-based updaters (WebUSB).
+//!
+//! Carries the same COBS/postcard frames as the protocol CDC. The BOS
+//! descriptor advertises WebUSB and MS OS 2.0 support, and the MS OS 2.0
+//! descriptor set binds WinUSB to this interface so no driver install is
+//! needed on Windows.
+
+use crispy_common::webusb::{
+    ms_os_20_descriptor_set, CAPABILITY_PLATFORM, MS_OS_20_DESCRIPTOR_INDEX,
+    MS_OS_20_PLATFORM_CAPABILITY, MS_OS_20_SET_LEN, MS_OS_20_VENDOR_CODE,
+    WEBUSB_PLATFORM_CAPABILITY,
+};
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+/// Bulk packet size (full speed maximum).
+pub const PACKET_SIZE: usize = 64;
+
+const USB_CLASS_VENDOR: u8 = 0xFF;
+
+/// Interface number of the vendor interface: after the console and protocol
+/// CDCs, or right after the protocol CDC in `single-cdc` builds.
+#[cfg(not(feature = "single-cdc"))]
+pub const VENDOR_INTERFACE: u8 = crispy_common::protocol::PROTOCOL_INTERFACE + 2;
+#[cfg(feature = "single-cdc")]
+pub const VENDOR_INTERFACE: u8 = 2;
+
+static MS_OS_20_SET: [u8; MS_OS_20_SET_LEN] = ms_os_20_descriptor_set(VENDOR_INTERFACE);
+
+pub struct WebUsbClass<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+}
+
+impl<'a, B: UsbBus> WebUsbClass<'a, B> {
+    /// Allocate the interface; must be the last class allocated so its
+    /// number matches the MS OS 2.0 function subset.
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        let interface = alloc.interface();
+        assert_eq!(u8::from(interface), VENDOR_INTERFACE);
+        Self {
+            interface,
+            read_ep: alloc.bulk(PACKET_SIZE as u16),
+            write_ep: alloc.bulk(PACKET_SIZE as u16),
+        }
+    }
+
+    /// Read one packet. `buf` must hold at least [`PACKET_SIZE`] bytes.
+    pub fn read(&mut self, buf: &mut [u8]) -> usb_device::Result<usize> {
+        self.read_ep.read(buf)
+    }
+
+    /// Write up to one packet of `data`, returning how much was accepted.
+    pub fn write(&mut self, data: &[u8]) -> usb_device::Result<usize> {
+        let len = data.len().min(PACKET_SIZE);
+        self.write_ep.write(&data[..len])
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for WebUsbClass<'_, B> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(self.interface, USB_CLASS_VENDOR, 0x00, 0x00)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)?;
+        Ok(())
+    }
+
+    fn get_bos_descriptors(&self, writer: &mut BosWriter) -> usb_device::Result<()> {
+        writer.capability(CAPABILITY_PLATFORM, &WEBUSB_PLATFORM_CAPABILITY)?;
+        writer.capability(CAPABILITY_PLATFORM, &MS_OS_20_PLATFORM_CAPABILITY)?;
+        Ok(())
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = xfer.request();
+        if req.request_type == RequestType::Vendor
+            && req.recipient == Recipient::Device
+            && req.request == MS_OS_20_VENDOR_CODE
+            && req.index == MS_OS_20_DESCRIPTOR_INDEX
+        {
+            xfer.accept_with_static(&MS_OS_20_SET).ok();
+        }
+    }
+}
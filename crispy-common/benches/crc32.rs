@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Host throughput of the shared CRC-32 against the previous byte-wise table.
+//!
+//! Run with `cargo bench -p crispy-common --bench crc32`. Host numbers only
+//! compare the two algorithms; see the `crc32` module docs for timing on
+//! the RP2040.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use crispy_common::crc32;
+
+/// One full bank on the default 2MB layout.
+const IMAGE_SIZE: usize = 768 * 1024;
+const ROUNDS: u32 = 20;
+
+fn byte_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = (0..8).fold(i as u32, |c, _| {
+            if c & 1 != 0 {
+                (c >> 1) ^ 0xEDB8_8320
+            } else {
+                c >> 1
+            }
+        });
+    }
+    table
+}
+
+fn bytewise(table: &[u32; 256], data: &[u8]) -> u32 {
+    !data.iter().fold(0xFFFF_FFFF, |crc, &b| {
+        (crc >> 8) ^ table[((crc ^ b as u32) & 0xFF) as usize]
+    })
+}
+
+/// Best of `ROUNDS` runs, to keep scheduler noise out.
+fn best(mut run: impl FnMut() -> u32) -> (Duration, u32) {
+    let mut best = Duration::MAX;
+    let mut crc = 0;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        crc = black_box(run());
+        best = best.min(start.elapsed());
+    }
+    (best, crc)
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let mb_per_s = IMAGE_SIZE as f64 / elapsed.as_secs_f64() / 1e6;
+    println!("{:<14} {:>10.3?} {:>9.1} MB/s", name, elapsed, mb_per_s);
+}
+
+fn main() {
+    let data: Vec<u8> = (0..IMAGE_SIZE)
+        .map(|i| (i as u32).wrapping_mul(2_654_435_761) as u8)
+        .collect();
+    let table = byte_table();
+
+    let (old, old_crc) = best(|| bytewise(&table, black_box(&data)));
+    let (new, new_crc) = best(|| crc32::checksum(black_box(&data)));
+    assert_eq!(old_crc, new_crc);
+
+    println!("CRC-32 over {} KB, best of {}:", IMAGE_SIZE / 1024, ROUNDS);
+    report("byte table", old);
+    report("slice-by-4", new);
+    println!(
+        "speedup        {:.2}x",
+        old.as_secs_f64() / new.as_secs_f64()
+    );
+}
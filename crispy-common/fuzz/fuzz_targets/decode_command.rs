@@ -0,0 +1,14 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Decode arbitrary COBS frames as commands; must never panic.
+
+#![no_main]
+
+use crispy_common::protocol::Command;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut frame = data.to_vec();
+    let _ = postcard::from_bytes_cobs::<Command>(&mut frame);
+});
@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! The bootloader's receive path: split a byte stream into COBS frames,
+//! decode and validate each one, and dispatch the survivors to the update
+//! state machine over a simulated flash. Every frame must get exactly one
+//! response and nothing may panic.
+
+#![no_main]
+
+use crispy_common::cobs::CobsFrameDecoder;
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::protocol::{Command, ProtocolError, Response};
+use crispy_common::update_fsm::{handle_command, ResponseSink, UpdateState};
+use libfuzzer_sys::fuzz_target;
+
+/// The bootloader's receive buffer size.
+const RX_BUF_SIZE: usize = 2048;
+
+#[derive(Default)]
+struct CountingSink {
+    responses: usize,
+}
+
+impl ResponseSink for CountingSink {
+    fn send(&mut self, _response: &Response) {
+        self.responses += 1;
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut decoder = CobsFrameDecoder::<RX_BUF_SIZE>::new();
+    let mut flash = FlashSim::new();
+    let mut sink = CountingSink::default();
+    let mut state = UpdateState::Idle;
+
+    for &byte in data {
+        let Some(frame) = decoder.feed(byte) else {
+            continue;
+        };
+        let before = sink.responses;
+        match postcard::from_bytes::<Command>(frame).map_err(|_| ProtocolError::Malformed) {
+            Ok(cmd) => state = handle_command(&mut flash, &mut sink, state, cmd),
+            Err(err) => sink.send(&Response::Ack(err.status())),
+        }
+        assert_eq!(sink.responses, before + 1);
+    }
+});
@@ -8,11 +8,9 @@
 //! of hardware by operating on validation results rather than performing
 //! flash reads directly.
 
+use crate::memory_layout::FlashLayout;
 use crate::protocol::BootData;
 
-/// Maximum number of boot attempts before rolling back to the other bank.
-pub const MAX_BOOT_ATTEMPTS: u8 = 3;
-
 /// Information about a firmware bank.
 #[derive(Clone, Copy, Debug)]
 pub struct BankInfo {
@@ -36,16 +34,19 @@ pub struct BankPair {
     pub primary_validation: BankValidation,
     pub fallback: BankInfo,
     pub fallback_validation: BankValidation,
+    /// The primary bank's firmware has confirmed itself. Confirmation
+    /// belongs to the stored active bank, so it never moves to the fallback.
+    pub primary_confirmed: bool,
 }
 
 impl BankPair {
     /// Create a new bank pair from the active bank selection.
-    pub fn new(active_bank: u8, fw_a_addr: u32, fw_b_addr: u32, bd: &BootData) -> Self {
+    pub fn new(active_bank: u8, layout: &FlashLayout, bd: &BootData) -> Self {
         let fallback_bank = toggle_bank(active_bank);
         let (primary_addr, fallback_addr) = if active_bank == 0 {
-            (fw_a_addr, fw_b_addr)
+            (layout.fw_a, layout.fw_b)
         } else {
-            (fw_b_addr, fw_a_addr)
+            (layout.fw_b, layout.fw_a)
         };
         let (primary_crc, primary_size) = bank_metadata(bd, active_bank);
         let (fallback_crc, fallback_size) = bank_metadata(bd, fallback_bank);
@@ -65,6 +66,7 @@ impl BankPair {
                 bank_id: fallback_bank,
             },
             fallback_validation: BankValidation::default(),
+            primary_confirmed: bd.confirmed == 1,
         }
     }
 
@@ -78,6 +80,19 @@ impl BankPair {
         self.fallback_validation = fallback_validation;
         self
     }
+
+    /// Exchange the primary and fallback banks (used on rollback).
+    ///
+    /// The new primary has never confirmed itself as the active bank.
+    pub fn swapped(self) -> Self {
+        Self {
+            primary: self.fallback,
+            primary_validation: self.fallback_validation,
+            fallback: self.primary,
+            fallback_validation: self.primary_validation,
+            primary_confirmed: false,
+        }
+    }
 }
 
 /// Result of boot bank selection (immutable).
@@ -86,7 +101,9 @@ pub struct BootDecision {
     pub flash_addr: u32,
     pub active_bank: u8,
     pub boot_attempts: u8,
-    pub confirmed: u8,
+    /// The stored confirmation still holds: the confirmed primary bank is
+    /// booted with a valid CRC. Any other outcome needs a fresh confirmation.
+    pub keep_confirmed: bool,
 }
 
 impl BootDecision {
@@ -95,7 +112,7 @@ impl BootDecision {
         BootData {
             active_bank: self.active_bank,
             boot_attempts: self.boot_attempts,
-            confirmed: self.confirmed,
+            confirmed: if self.keep_confirmed { bd.confirmed } else { 0 },
             ..*bd
         }
     }
@@ -136,9 +153,10 @@ pub fn bank_metadata(bd: &BootData, bank: u8) -> (u32, u32) {
     }
 }
 
-/// Check if we need to rollback to the other bank.
-pub fn needs_rollback(bd: &BootData) -> bool {
-    bd.boot_attempts >= MAX_BOOT_ATTEMPTS && bd.confirmed == 0
+/// Check if we need to rollback to the other bank after `max_attempts`
+/// unconfirmed boots (normally [`BootData::boot_attempt_limit`]).
+pub fn needs_rollback(bd: &BootData, max_attempts: u8) -> bool {
+    bd.boot_attempts >= max_attempts && bd.confirmed == 0
 }
 
 /// Try a specific boot strategy and return a decision if successful.
@@ -151,29 +169,34 @@ pub fn try_boot_strategy(
         BootStrategy::PrimaryWithCrc if banks.primary_validation.crc_valid => Some(BootDecision {
             flash_addr: banks.primary.addr,
             active_bank: banks.primary.bank_id,
-            boot_attempts: current_attempts + 1,
-            confirmed: 0,
+            // Only unconfirmed boots count towards a rollback
+            boot_attempts: if banks.primary_confirmed {
+                current_attempts
+            } else {
+                current_attempts.saturating_add(1)
+            },
+            keep_confirmed: banks.primary_confirmed,
         }),
         BootStrategy::FallbackWithCrc if banks.fallback_validation.crc_valid => {
             Some(BootDecision {
                 flash_addr: banks.fallback.addr,
                 active_bank: banks.fallback.bank_id,
                 boot_attempts: 1,
-                confirmed: 0,
+                keep_confirmed: false,
             })
         }
         BootStrategy::PrimaryBasic if banks.primary_validation.basic_valid => Some(BootDecision {
             flash_addr: banks.primary.addr,
             active_bank: banks.primary.bank_id,
-            boot_attempts: current_attempts + 1,
-            confirmed: 0,
+            boot_attempts: current_attempts.saturating_add(1),
+            keep_confirmed: false,
         }),
         BootStrategy::FallbackBasic if banks.fallback_validation.basic_valid => {
             Some(BootDecision {
                 flash_addr: banks.fallback.addr,
                 active_bank: banks.fallback.bank_id,
                 boot_attempts: 1,
-                confirmed: 0,
+                keep_confirmed: false,
             })
         }
         _ => None,
@@ -182,13 +205,18 @@ pub fn try_boot_strategy(
 
 /// Select the boot bank using the FSM logic.
 ///
+/// `banks` is built from `bd.active_bank`. On rollback the FSM swaps the
+/// primary and fallback banks itself, so callers never toggle the bank.
+///
 /// Returns the decision containing flash address and updated boot state.
-pub fn select_boot_bank_fsm(bd: &BootData, banks: BankPair) -> BootDecision {
-    // Handle rollback if needed
-    let boot_attempts = if needs_rollback(bd) {
-        0
+pub fn select_boot_bank_fsm(bd: &BootData, banks: BankPair, max_attempts: u8) -> BootDecision {
+    // Handle rollback if needed. Confirmed firmware never rolls back, so
+    // the swapped pair never carries a confirmation.
+    let (banks, boot_attempts) = if needs_rollback(bd, max_attempts) {
+        debug_assert!(!banks.primary_confirmed);
+        (banks.swapped(), 0)
     } else {
-        bd.boot_attempts
+        (banks, bd.boot_attempts)
     };
 
     // Try each strategy in priority order
@@ -198,7 +226,7 @@ pub fn select_boot_bank_fsm(bd: &BootData, banks: BankPair) -> BootDecision {
         .unwrap_or(BootDecision {
             flash_addr: banks.primary.addr,
             active_bank: banks.primary.bank_id,
-            boot_attempts: boot_attempts + 1,
-            confirmed: 0,
+            boot_attempts: boot_attempts.saturating_add(1),
+            keep_confirmed: false,
         })
 }
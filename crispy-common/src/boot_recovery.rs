@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Reconstruction of BootData after corruption - pure logic over a flash reader.
+//!
+//! When the BootData magic is wrong, falling back to an empty record would
+//! throw away both banks and force update mode. Instead each bank's vector
+//! table is checked, and a conservative record is rebuilt for whatever still
+//! looks bootable. Sizes and CRCs cannot be recovered, so they stay 0 and the
+//! boot FSM can only use basic validation for those banks; the record is
+//! tagged with [`BOOT_FLAG_RECONSTRUCTED`] so the zero sizes are not mistaken
+//! for "no firmware uploaded".
+
+use crate::protocol::{BootData, BOOT_FLAG_RECONSTRUCTED};
+use crate::vector_table::VectorTable;
+
+pub use crate::vector_table::RamWindow;
+
+/// Check that the vector table at `bank_addr` passes
+/// [`VectorTable::check`], which is what the bootloader requires to run it.
+pub fn bank_looks_bootable(read_word: impl Fn(u32) -> u32, bank_addr: u32, ram: RamWindow) -> bool {
+    VectorTable::read(read_word, bank_addr).check(ram).is_ok()
+}
+
+/// Which banks passed the vector table check during recovery.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryScan {
+    pub bank_a: bool,
+    pub bank_b: bool,
+}
+
+/// Scan both banks for firmware that still looks bootable.
+pub fn scan_banks(
+    read_word: impl Fn(u32) -> u32,
+    fw_a_addr: u32,
+    fw_b_addr: u32,
+    ram: RamWindow,
+) -> RecoveryScan {
+    RecoveryScan {
+        bank_a: bank_looks_bootable(&read_word, fw_a_addr, ram),
+        bank_b: bank_looks_bootable(&read_word, fw_b_addr, ram),
+    }
+}
+
+/// Build a replacement BootData from a bank scan.
+///
+/// Returns `None` if neither bank looks bootable. Bank A is preferred as the
+/// active bank; the record starts unconfirmed with no boot attempts.
+pub fn reconstruct_boot_data(scan: RecoveryScan) -> Option<BootData> {
+    let active_bank = match (scan.bank_a, scan.bank_b) {
+        (true, _) => 0,
+        (false, true) => 1,
+        (false, false) => return None,
+    };
+
+    Some(BootData {
+        active_bank,
+        flags: BOOT_FLAG_RECONSTRUCTED,
+        ..BootData::default_new()
+    })
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Clock setup shared by the bootloader and firmware.
+//!
+//! The crystal frequency comes from `CRISPY_XOSC_HZ` at build time (12 MHz if
+//! unset). Boards without a crystal can enable the `rosc-only` feature: the
+//! system then runs from the ring oscillator (~6.5 MHz, uncalibrated) and USB
+//! is unavailable, since it needs an exact 48 MHz from the crystal-fed PLL.
+//!
+//! A failed clock setup no longer panics silently: the status LED shows an
+//! error pattern (see [`crate::led::error_halt`]) and the core halts.
+
+use rp2040_hal as hal;
+
+use crate::led::{self, StatusLed};
+
+/// Crystal frequency in Hz, from `CRISPY_XOSC_HZ` at build time.
+pub const XOSC_HZ: u32 = match option_env!("CRISPY_XOSC_HZ") {
+    Some(s) => parse_hz(s),
+    None => 12_000_000,
+};
+
+/// Whether the clock setup provides a 48 MHz USB clock.
+pub const USB_AVAILABLE: bool = !cfg!(feature = "rosc-only");
+
+// PLL_USB runs its VCO at 1200 MHz with refdiv 1; anything that does not
+// divide it exactly would leave USB off-frequency. The watchdog tick also
+// needs a whole number of MHz for a 1 us timer.
+#[cfg(not(feature = "rosc-only"))]
+const _: () = {
+    assert!(
+        XOSC_HZ >= 5_000_000 && XOSC_HZ <= 75_000_000,
+        "CRISPY_XOSC_HZ must be 5..75 MHz"
+    );
+    assert!(
+        1_200_000_000u32.is_multiple_of(XOSC_HZ) && XOSC_HZ.is_multiple_of(1_000_000),
+        "CRISPY_XOSC_HZ must be a whole MHz dividing 1200 MHz"
+    );
+};
+
+const fn parse_hz(s: &str) -> u32 {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "CRISPY_XOSC_HZ is empty");
+    let mut value = 0u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "CRISPY_XOSC_HZ must be a number");
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
+
+/// Nominal ROSC frequency after reset, used in `rosc-only` mode.
+#[cfg(feature = "rosc-only")]
+const ROSC_NOMINAL_HZ: u32 = 6_500_000;
+
+/// Peripherals consumed by [`init_clocks`].
+pub struct ClockPeripherals {
+    pub xosc: hal::pac::XOSC,
+    pub rosc: hal::pac::ROSC,
+    pub clocks: hal::pac::CLOCKS,
+    pub pll_sys: hal::pac::PLL_SYS,
+    pub pll_usb: hal::pac::PLL_USB,
+}
+
+/// Bring up the system clocks, or show an error pattern and halt.
+///
+/// `led` must already be usable (create it right after `Pins::new`).
+#[cfg(not(feature = "rosc-only"))]
+pub fn init_clocks(
+    p: ClockPeripherals,
+    resets: &mut hal::pac::RESETS,
+    watchdog: &mut hal::Watchdog,
+    led: &mut impl StatusLed,
+) -> hal::clocks::ClocksManager {
+    use hal::clocks::InitError;
+
+    match hal::clocks::init_clocks_and_plls(
+        XOSC_HZ, p.xosc, p.clocks, p.pll_sys, p.pll_usb, resets, watchdog,
+    ) {
+        Ok(clocks) => clocks,
+        Err(InitError::XoscErr(_)) => led::error_halt(led, led::ERR_XOSC),
+        Err(InitError::PllError(_)) => led::error_halt(led, led::ERR_PLL),
+        Err(InitError::ClockError(_)) => led::error_halt(led, led::ERR_CLOCK),
+    }
+}
+
+/// Bring up the system clocks from the ROSC, or show an error pattern and halt.
+///
+/// `led` must already be usable (create it right after `Pins::new`).
+#[cfg(feature = "rosc-only")]
+pub fn init_clocks(
+    p: ClockPeripherals,
+    _resets: &mut hal::pac::RESETS,
+    watchdog: &mut hal::Watchdog,
+    led: &mut impl StatusLed,
+) -> hal::clocks::ClocksManager {
+    use hal::clocks::{Clock, ClockSource};
+    use hal::fugit::RateExtU32;
+
+    let rosc = hal::rosc::RingOscillator::new(p.rosc).initialize_with_freq(ROSC_NOMINAL_HZ.Hz());
+
+    // clk_ref stays on the ROSC after reset; approximate 1 us ticks
+    watchdog.enable_tick_generation((ROSC_NOMINAL_HZ / 1_000_000) as u8);
+
+    let mut clocks = hal::clocks::ClocksManager::new(p.clocks);
+    if clocks
+        .system_clock
+        .configure_clock(&rosc, rosc.get_freq())
+        .is_err()
+    {
+        led::error_halt(led, led::ERR_CLOCK);
+    }
+    let sys_freq = clocks.system_clock.freq();
+    if clocks
+        .peripheral_clock
+        .configure_clock(&clocks.system_clock, sys_freq)
+        .is_err()
+    {
+        led::error_halt(led, led::ERR_CLOCK);
+    }
+    clocks
+}
@@ -14,81 +14,132 @@ use alloc::vec::Vec;
 
 use heapless::Vec as HeaplessVec;
 
+/// Why a COBS operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CobsError {
+    /// The input ended (or hit a delimiter) inside a code block.
+    Truncated,
+    /// A zero byte appeared where the encoding cannot contain one.
+    Malformed,
+    /// The output buffer cannot hold the result.
+    BufferTooSmall,
+}
+
+impl core::fmt::Display for CobsError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            CobsError::Truncated => "truncated COBS frame",
+            CobsError::Malformed => "malformed COBS frame",
+            CobsError::BufferTooSmall => "COBS buffer too small",
+        })
+    }
+}
+
+/// Worst-case encoded size of `input_len` bytes, including the delimiter.
+///
+/// One code byte per 254 data bytes, plus the first code byte and the
+/// delimiter.
+pub const fn max_encoded_len(input_len: usize) -> usize {
+    input_len + input_len / 254 + 2
+}
+
 /// COBS encode data into a heapless Vec (for no_std).
 ///
-/// The output includes the trailing 0x00 delimiter.
-pub fn encode_heapless<const N: usize>(data: &[u8]) -> HeaplessVec<u8, N> {
+/// The output includes the trailing 0x00 delimiter. Fails with
+/// `BufferTooSmall` rather than returning a truncated frame.
+pub fn encode_heapless<const N: usize>(data: &[u8]) -> Result<HeaplessVec<u8, N>, CobsError> {
+    let mut output = HeaplessVec::new();
+    // Fill to capacity so the encoder can write into it as a slice; resizing
+    // to exactly N cannot fail
+    output.resize(N, 0).map_err(|_| CobsError::BufferTooSmall)?;
+    let len = encode_into(data, &mut output)?;
+    output.truncate(len);
+    Ok(output)
+}
+
+/// COBS decode data from a heapless Vec (for no_std).
+///
+/// Decoding stops at the first delimiter, if any.
+pub fn decode_heapless<const N: usize>(data: &[u8]) -> Result<HeaplessVec<u8, N>, CobsError> {
     let mut output = HeaplessVec::new();
+    decode_with(data, |byte| push(&mut output, byte))?;
+    Ok(output)
+}
+
+/// COBS encode `data` into `out` without allocating.
+///
+/// Returns the number of bytes written, including the trailing 0x00
+/// delimiter. An `out` of [`max_encoded_len`]`(data.len())` bytes always
+/// suffices; a smaller one may fail with `BufferTooSmall`.
+pub fn encode_into(data: &[u8], out: &mut [u8]) -> Result<usize, CobsError> {
+    let mut len = 0;
     let mut code_idx = 0;
     let mut code: u8 = 1;
 
     // Placeholder for first code byte
-    let _ = output.push(0);
+    put(out, &mut len, 0)?;
 
     for &byte in data {
         if byte == 0 {
-            if code_idx < output.len() {
-                output[code_idx] = code;
-            }
-            code_idx = output.len();
-            let _ = output.push(0); // placeholder
+            out[code_idx] = code;
+            code_idx = len;
+            put(out, &mut len, 0)?; // placeholder
             code = 1;
         } else {
-            let _ = output.push(byte);
+            put(out, &mut len, byte)?;
             code += 1;
             if code == 255 {
-                if code_idx < output.len() {
-                    output[code_idx] = code;
-                }
-                code_idx = output.len();
-                let _ = output.push(0); // placeholder
+                out[code_idx] = code;
+                code_idx = len;
+                put(out, &mut len, 0)?; // placeholder
                 code = 1;
             }
         }
     }
 
-    if code_idx < output.len() {
-        output[code_idx] = code;
-    }
-    let _ = output.push(0); // delimiter
+    out[code_idx] = code;
+    put(out, &mut len, 0)?; // delimiter
 
-    output
+    Ok(len)
 }
 
-/// COBS decode data from a heapless Vec (for no_std).
+/// COBS decode a frame in place, returning the decoded length.
 ///
-/// Returns None if decoding fails.
-pub fn decode_heapless<const N: usize>(data: &[u8]) -> Option<HeaplessVec<u8, N>> {
-    if data.is_empty() {
-        return None;
+/// The decoded bytes are written to the front of `buf`; decoding never
+/// writes ahead of where it reads, so no second buffer is needed. Decoding
+/// stops at the first delimiter, if any.
+pub fn decode_in_place(buf: &mut [u8]) -> Result<usize, CobsError> {
+    if buf.is_empty() {
+        return Err(CobsError::Truncated);
     }
 
-    let mut output = HeaplessVec::new();
-    let mut i = 0;
+    let mut read = 0;
+    let mut write = 0;
 
-    while i < data.len() {
-        let code = data[i] as usize;
+    while read < buf.len() {
+        let code = buf[read] as usize;
         if code == 0 {
             break; // delimiter
         }
-        i += 1;
+        read += 1;
 
         for _ in 1..code {
-            if i >= data.len() {
-                return None; // unexpected end
-            }
-            if output.push(data[i]).is_err() {
-                return None; // buffer overflow
+            match buf.get(read) {
+                None => return Err(CobsError::Truncated),
+                Some(0) => return Err(CobsError::Malformed),
+                Some(&byte) => buf[write] = byte,
             }
-            i += 1;
+            read += 1;
+            write += 1;
         }
 
-        if code < 255 && i < data.len() && data[i] != 0 && output.push(0).is_err() {
-            return None; // buffer overflow
+        if code < 255 && read < buf.len() && buf[read] != 0 {
+            buf[write] = 0;
+            write += 1;
         }
     }
 
-    Some(output)
+    Ok(write)
 }
 
 #[cfg(feature = "std")]
@@ -96,45 +147,34 @@ pub fn decode_heapless<const N: usize>(data: &[u8]) -> Option<HeaplessVec<u8, N>
 ///
 /// The output includes the trailing 0x00 delimiter.
 pub fn encode(data: &[u8]) -> Vec<u8> {
-    let mut output = Vec::with_capacity(data.len() + data.len() / 254 + 2);
-    let mut code_idx = 0;
-    let mut code: u8 = 1;
-
-    output.push(0); // placeholder for first code byte
-
-    for &byte in data {
-        if byte == 0 {
-            output[code_idx] = code;
-            code_idx = output.len();
-            output.push(0); // placeholder
-            code = 1;
-        } else {
-            output.push(byte);
-            code += 1;
-            if code == 255 {
-                output[code_idx] = code;
-                code_idx = output.len();
-                output.push(0); // placeholder
-                code = 1;
-            }
-        }
-    }
-    output[code_idx] = code;
-    output.push(0); // delimiter
-
+    let mut output = alloc::vec![0u8; max_encoded_len(data.len())];
+    let len = encode_into(data, &mut output).expect("sized by max_encoded_len");
+    output.truncate(len);
     output
 }
 
 #[cfg(feature = "std")]
 /// COBS decode data from a slice (for std).
 ///
-/// Returns None if decoding fails.
-pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
+/// Decoding stops at the first delimiter, if any.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>, CobsError> {
+    let mut output = Vec::with_capacity(data.len());
+    decode_with(data, |byte| {
+        output.push(byte);
+        Ok(())
+    })?;
+    Ok(output)
+}
+
+/// Shared decode loop; `emit` receives each decoded byte.
+fn decode_with(
+    data: &[u8],
+    mut emit: impl FnMut(u8) -> Result<(), CobsError>,
+) -> Result<(), CobsError> {
     if data.is_empty() {
-        return None;
+        return Err(CobsError::Truncated);
     }
 
-    let mut output = Vec::with_capacity(data.len());
     let mut i = 0;
 
     while i < data.len() {
@@ -145,19 +185,217 @@ pub fn decode(data: &[u8]) -> Option<Vec<u8>> {
         i += 1;
 
         for _ in 1..code {
-            if i >= data.len() {
-                return None; // unexpected end
+            match data.get(i) {
+                None => return Err(CobsError::Truncated),
+                Some(0) => return Err(CobsError::Malformed),
+                Some(&byte) => emit(byte)?,
             }
-            output.push(data[i]);
             i += 1;
         }
 
         if code < 255 && i < data.len() && data[i] != 0 {
-            output.push(0);
+            emit(0)?;
         }
     }
 
-    Some(output)
+    Ok(())
+}
+
+fn put(out: &mut [u8], len: &mut usize, byte: u8) -> Result<(), CobsError> {
+    *out.get_mut(*len).ok_or(CobsError::BufferTooSmall)? = byte;
+    *len += 1;
+    Ok(())
+}
+
+fn push<const N: usize>(output: &mut HeaplessVec<u8, N>, byte: u8) -> Result<(), CobsError> {
+    output.push(byte).map_err(|_| CobsError::BufferTooSmall)
+}
+
+/// Streaming COBS encoder over a payload iterator.
+///
+/// Produces the frame one code block at a time, so a payload that lives
+/// elsewhere (e.g. in flash) never has to be staged whole in RAM; only the
+/// block in progress is buffered. The output is byte-identical to
+/// [`encode_into`].
+pub struct CobsStreamEncoder<I> {
+    payload: I,
+    /// Code byte, up to 254 data bytes, and room for the delimiter.
+    block: [u8; 256],
+    done: bool,
+}
+
+impl<I: Iterator<Item = u8>> CobsStreamEncoder<I> {
+    pub fn new(payload: I) -> Self {
+        Self {
+            payload,
+            block: [0u8; 256],
+            done: false,
+        }
+    }
+
+    /// The next piece of the encoded frame: one code block, the last one
+    /// followed by the 0x00 delimiter. Returns `None` once the frame is
+    /// complete.
+    ///
+    /// Pieces are at most 256 bytes.
+    pub fn next_chunk(&mut self) -> Option<&[u8]> {
+        if self.done {
+            return None;
+        }
+
+        let mut len = 1;
+        loop {
+            match self.payload.next() {
+                Some(0) => {
+                    self.block[0] = len as u8;
+                    return Some(&self.block[..len]);
+                }
+                Some(byte) => {
+                    self.block[len] = byte;
+                    len += 1;
+                    if len == 255 {
+                        self.block[0] = 0xFF;
+                        return Some(&self.block[..len]);
+                    }
+                }
+                None => {
+                    self.block[0] = len as u8;
+                    self.block[len] = 0; // delimiter
+                    self.done = true;
+                    return Some(&self.block[..len + 1]);
+                }
+            }
+        }
+    }
+}
+
+/// Incremental COBS frame decoder for byte streams.
+///
+/// Bytes are decoded as they arrive, in place, into a fixed `N`-byte buffer;
+/// [`feed`](Self::feed) hands back the decoded payload when a 0x00
+/// delimiter completes a frame. Frames may be split across reads at any byte.
+///
+/// - Delimiters with no frame before them (keepalives) are ignored.
+/// - A frame that decodes to more than `N` bytes is discarded up to its
+///   delimiter.
+/// - A frame cut short by a delimiter is discarded; decoding resynchronizes
+///   on the byte after it.
+///
+/// Discarded frames are counted in [`dropped_frames`](Self::dropped_frames)
+/// and the reason kept in [`last_error`](Self::last_error).
+pub struct CobsFrameDecoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// A code byte has been seen for the current frame.
+    started: bool,
+    /// Current code byte.
+    code: u8,
+    /// Data bytes still to come in the current code block.
+    remaining: u8,
+    /// The current frame does not fit and is being skipped.
+    overflow: bool,
+    dropped: u32,
+    last_error: Option<CobsError>,
+}
+
+impl<const N: usize> CobsFrameDecoder<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            len: 0,
+            started: false,
+            code: 0,
+            remaining: 0,
+            overflow: false,
+            dropped: 0,
+            last_error: None,
+        }
+    }
+
+    /// Feed one byte. Returns the decoded payload when `byte` is the
+    /// delimiter ending a valid frame.
+    ///
+    /// The payload stays valid until the next call.
+    pub fn feed(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == 0 {
+            let complete = self.started && self.remaining == 0 && !self.overflow;
+            if self.started && !complete {
+                self.dropped = self.dropped.wrapping_add(1);
+                self.last_error = Some(if self.overflow {
+                    CobsError::BufferTooSmall
+                } else {
+                    CobsError::Truncated
+                });
+            }
+            let len = self.len;
+            self.reset();
+            return complete.then(|| &self.buf[..len]);
+        }
+
+        if self.remaining == 0 {
+            // Code byte: the previous block ended in an implied zero unless it was full
+            if self.started && self.code != 0xFF {
+                self.push(0);
+            }
+            self.started = true;
+            self.code = byte;
+            self.remaining = byte - 1;
+        } else {
+            self.push(byte);
+            self.remaining -= 1;
+        }
+        None
+    }
+
+    /// Feed a slice, calling `on_frame` with each completed payload.
+    /// Returns the number of frames completed.
+    pub fn feed_slice(&mut self, data: &[u8], mut on_frame: impl FnMut(&[u8])) -> usize {
+        let mut frames = 0;
+        for &byte in data {
+            if let Some(frame) = self.feed(byte) {
+                on_frame(frame);
+                frames += 1;
+            }
+        }
+        frames
+    }
+
+    /// Discard any partially received frame.
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.started = false;
+        self.code = 0;
+        self.remaining = 0;
+        self.overflow = false;
+    }
+
+    /// Number of frames discarded as oversize or truncated so far.
+    pub fn dropped_frames(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Why the most recently discarded frame was dropped.
+    pub fn last_error(&self) -> Option<CobsError> {
+        self.last_error
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.overflow {
+            return;
+        }
+        if self.len == N {
+            self.overflow = true;
+        } else {
+            self.buf[self.len] = byte;
+            self.len += 1;
+        }
+    }
+}
+
+impl<const N: usize> Default for CobsFrameDecoder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Tests that work in both std and no_std modes
@@ -168,7 +406,7 @@ mod tests {
     #[test]
     fn test_heapless_encode_decode_roundtrip() {
         let data = [0x11, 0x22, 0x00, 0x33];
-        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data);
+        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data).unwrap();
         let decoded: HeaplessVec<u8, 64> = decode_heapless(&encoded).unwrap();
         assert_eq!(&decoded[..], &data[..]);
     }
@@ -176,7 +414,7 @@ mod tests {
     #[test]
     fn test_heapless_encode_no_zeros_in_payload() {
         let data = [0x11, 0x22, 0x33];
-        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data);
+        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data).unwrap();
         // Check no zeros except the delimiter at the end
         assert!(encoded[..encoded.len() - 1].iter().all(|&b| b != 0));
         assert_eq!(encoded[encoded.len() - 1], 0);
@@ -185,15 +423,15 @@ mod tests {
     #[test]
     fn test_heapless_empty_data() {
         let data: [u8; 0] = [];
-        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data);
+        let encoded: HeaplessVec<u8, 64> = encode_heapless(&data).unwrap();
         let decoded: HeaplessVec<u8, 64> = decode_heapless(&encoded).unwrap();
         assert!(decoded.is_empty());
     }
 
     #[test]
-    fn test_heapless_decode_invalid_returns_none() {
-        let invalid: Option<HeaplessVec<u8, 64>> = decode_heapless(&[0x05, 0x01]); // Claims 4 more bytes
-        assert!(invalid.is_none());
+    fn test_heapless_decode_invalid_returns_truncated() {
+        let invalid: Result<HeaplessVec<u8, 64>, _> = decode_heapless(&[0x05, 0x01]); // Claims 4 more bytes
+        assert_eq!(invalid, Err(CobsError::Truncated));
     }
 
     #[cfg(feature = "std")]
@@ -231,7 +469,7 @@ mod tests {
         let data = [0x11, 0x22, 0x00, 0x33, 0x44];
 
         let std_encoded = encode(&data);
-        let heapless_encoded: HeaplessVec<u8, 64> = encode_heapless(&data);
+        let heapless_encoded: HeaplessVec<u8, 64> = encode_heapless(&data).unwrap();
 
         assert_eq!(&std_encoded[..], &heapless_encoded[..]);
 
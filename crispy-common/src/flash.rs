@@ -5,57 +5,147 @@
 //!
 //! This module provides flash operations that can be used by firmware to:
 //! - Confirm boot (write confirmed=1 to BootData)
-//! - Write firmware to banks (self-update capability)
+//! - Read the unit's provisioning record (see [`read_provision`])
+//! - Write firmware to banks (self-update capability, see [`BankWriter`])
 //! - Manage boot configuration
+//!
+//! Everything goes through [`FlashOps`]: firmware passes a
+//! [`RomFlash`](crate::flash_ops::RomFlash), host tests a
+//! [`FlashSim`](crate::flash_sim::FlashSim).
+//!
+//! Operations that can fail return a [`FlashError`]. On firmware
+//! (`embedded`), the 0.2.0 signatures of the functions that now take a
+//! [`FlashOps`] (`confirm_boot() -> bool`, `unsafe fn erase_bank(bank)` and
+//! the like) remain, deprecated, in `legacy` for one release.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crc32::{Digest, FLASH_CHUNK_SIZE};
+use crate::flash_ops::FlashOps;
+use crate::memory_layout::FlashLayout;
+use crate::protocol::{BootData, BootDataError, BOOT_DATA_SIZE, FLASH_BASE, FLASH_PAGE_SIZE};
+#[cfg(feature = "embedded")]
+use crate::protocol::{RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC};
+use crate::provision::{Provision, ProvisionError, PROVISION_SIZE};
+use crate::version::FwVersion;
+
+/// Read BootData from flash. v1 records come back upgraded to v2.
+///
+/// Only the encoding is checked; check the fields with
+/// [`BootData::issues_for`] and `flash.layout()`.
+pub fn read_boot_data(flash: &impl FlashOps) -> Result<BootData, BootDataError> {
+    let mut raw = [0u8; BOOT_DATA_SIZE];
+    flash.read(flash.layout().boot_data, &mut raw);
+    BootData::from_bytes(&raw)
+}
 
-use crate::protocol::{
-    BootData, BOOT_DATA_ADDR, FLASH_BASE, FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE, FW_A_ADDR,
-    FW_BANK_SIZE, FW_B_ADDR, RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC,
-};
+/// Why a flash operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    /// Bank number other than 0 or 1.
+    InvalidBank(u8),
+    /// BootData is missing or was rejected.
+    BootData(BootDataError),
+    /// The bank does not hold the image BootData records for it.
+    Bank(BankVerify),
+    /// An image size larger than a bank.
+    SizeTooLarge(u32),
+    /// The data does not fit in the bank; the offset is the first byte past
+    /// its end.
+    OutOfRange(u32),
+    /// Flash read back differently after programming or erasing (and one
+    /// retry), at this offset into the bank, BootData or provisioning record.
+    Verify(u32),
+}
 
-/// Read BootData from flash.
-pub fn read_boot_data() -> BootData {
-    unsafe { BootData::read_from(BOOT_DATA_ADDR) }
+impl FlashError {
+    /// Offset the error refers to, for errors tied to one.
+    pub fn offset(&self) -> Option<u32> {
+        match *self {
+            FlashError::OutOfRange(offset) | FlashError::Verify(offset) => Some(offset),
+            _ => None,
+        }
+    }
 }
 
-/// Write BootData to flash.
+impl From<BootDataError> for FlashError {
+    fn from(err: BootDataError) -> Self {
+        FlashError::BootData(err)
+    }
+}
+
+/// Write BootData to flash as a v2 record (erase its sector, then program
+/// one padded page).
 ///
-/// # Safety
-/// Caller must ensure no code is executing from flash during this operation.
-pub unsafe fn write_boot_data(bd: &BootData) {
-    let offset = BOOT_DATA_ADDR - FLASH_BASE;
+/// The record is read back; on mismatch the sector is rewritten once before
+/// returning [`FlashError::Verify`].
+pub fn write_boot_data<F: FlashOps>(flash: &mut F, bd: &BootData) -> Result<(), FlashError> {
+    let addr = flash.layout().boot_data;
+    write_record(flash, addr, &bd.to_bytes())
+}
+
+/// Read the provisioning record from the last sector of flash.
+pub fn read_provision(flash: &impl FlashOps) -> Result<Provision, ProvisionError> {
+    let mut raw = [0u8; PROVISION_SIZE];
+    flash.read(flash.layout().provision(), &mut raw);
+    Provision::from_bytes(&raw)
+}
+
+/// Write the provisioning record, as [`write_boot_data`] writes BootData.
+pub fn write_provision<F: FlashOps>(flash: &mut F, record: &Provision) -> Result<(), FlashError> {
+    let addr = flash.layout().provision();
+    write_record(flash, addr, &record.to_bytes())
+}
+
+/// Erase the sector at `addr` and program `record` at its start, padded to
+/// a page, rewriting it once if it does not read back.
+fn write_record<F: FlashOps>(flash: &mut F, addr: u32, record: &[u8]) -> Result<(), FlashError> {
+    let offset = addr - FLASH_BASE;
 
     // Pad to page size
     let mut page = [0xFFu8; FLASH_PAGE_SIZE as usize];
-    let src = bd.as_bytes();
-    page[..src.len()].copy_from_slice(src);
+    page[..record.len()].copy_from_slice(record);
+
+    let mut fault = None;
+    for _ in 0..2 {
+        flash.erase(offset, F::SECTOR_SIZE);
+        flash.program(offset, &page);
+        fault = first_mismatch(flash, addr, record);
+        if fault.is_none() {
+            return Ok(());
+        }
+    }
 
-    flash_erase_and_program(offset, &page);
+    fault.map_or(Ok(()), |i| Err(FlashError::Verify(i)))
+}
+
+/// What [`confirm_boot`] did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfirmOutcome {
+    /// The boot was unconfirmed and BootData has been updated.
+    Confirmed,
+    /// The boot was already confirmed; flash was not touched.
+    AlreadyConfirmed,
 }
 
 /// Confirm the current boot to the bootloader.
 /// Sets confirmed=1 and boot_attempts=0 in BootData.
 ///
-/// Returns true if confirmation was successful, false if BootData is invalid.
-pub fn confirm_boot() -> bool {
-    let mut bd = read_boot_data();
-
-    if !bd.is_valid() {
-        return false;
-    }
+/// Fails with [`FlashError::BootData`] if BootData is invalid, or
+/// [`FlashError::Verify`] if the new record did not stick.
+pub fn confirm_boot(flash: &mut impl FlashOps) -> Result<ConfirmOutcome, FlashError> {
+    let mut bd = read_boot_data(flash)?;
 
     if bd.confirmed == 1 {
-        return true; // Already confirmed
+        return Ok(ConfirmOutcome::AlreadyConfirmed);
     }
 
     bd.confirmed = 1;
     bd.boot_attempts = 0;
 
-    unsafe {
-        write_boot_data(&bd);
-    }
+    write_boot_data(flash, &bd)?;
 
-    true
+    Ok(ConfirmOutcome::Confirmed)
 }
 
 /// Set the active bank for next boot.
@@ -63,103 +153,306 @@ pub fn confirm_boot() -> bool {
 /// # Arguments
 /// * `bank` - 0 for bank A, 1 for bank B
 ///
-/// Returns false if bank is invalid or BootData is invalid.
-pub fn set_active_bank(bank: u8) -> bool {
-    if bank > 1 {
-        return false;
-    }
-
-    let mut bd = read_boot_data();
-    if !bd.is_valid() {
-        bd = BootData::default_new();
+/// Like the bootloader's `SetActiveBank`, the bank must pass [`verify_bank`]
+/// first; otherwise BootData is left alone and the failed check is returned
+/// as [`FlashError::Bank`].
+pub fn set_active_bank(flash: &mut impl FlashOps, bank: u8) -> Result<(), FlashError> {
+    check_bank(bank)?;
+    let mut bd = read_boot_data(flash)?;
+    match verify_bank_image(flash, &bd, bank, |_| {}) {
+        BankVerify::Ok { .. } => {}
+        failed => return Err(FlashError::Bank(failed)),
     }
 
     bd.active_bank = bank;
     bd.confirmed = 0;
     bd.boot_attempts = 0;
 
-    unsafe {
-        write_boot_data(&bd);
+    write_boot_data(flash, &bd)
+}
+
+/// Result of checking a bank against the size and CRC stored in BootData.
+///
+/// Sent to the host in [`Response::BootData`](crate::protocol::Response), so
+/// variants are only ever appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BankVerify {
+    /// The bank holds `size` bytes whose CRC32 matches the stored `crc`.
+    Ok { size: u32, crc: u32 },
+    /// Bank number other than 0 or 1.
+    BadBank(u8),
+    /// BootData is invalid or records no image (size 0) for the bank.
+    NoMetadata,
+    /// The stored size does not fit in a bank.
+    SizeOutOfRange(u32),
+    /// The bank content does not match the stored CRC32.
+    CrcMismatch { expected: u32, computed: u32 },
+}
+
+impl BankVerify {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, BankVerify::Ok { .. })
     }
+}
 
-    true
+/// Check a bank against its size and CRC32 in BootData.
+///
+/// Firmware should verify an image it has just written, and treat anything
+/// but [`BankVerify::Ok`] as a failed update before touching the active bank:
+///
+/// ```ignore
+/// writer.finish(Some(FW_VERSION))?;
+/// if !flash::verify_bank(&rom_flash, bank).is_ok() {
+///     flash::erase_bank(&mut rom_flash, bank)?; // and report the failure
+/// }
+/// ```
+///
+/// The whole image is read back, so this takes as long as a CRC over it.
+pub fn verify_bank(flash: &impl FlashOps, bank: u8) -> BankVerify {
+    match read_boot_data(flash) {
+        Ok(bd) => verify_bank_image(flash, &bd, bank, |_| {}),
+        Err(_) => BankVerify::NoMetadata,
+    }
 }
 
-/// Get the flash address for a bank.
+/// Like [`verify_bank`] against an already loaded `bd`, calling `on_chunk`
+/// as [`compute_crc32_with`] does.
+pub fn verify_bank_image(
+    flash: &impl FlashOps,
+    bd: &BootData,
+    bank: u8,
+    on_chunk: impl FnMut(u32),
+) -> BankVerify {
+    let (size, crc) = match bank {
+        0 => (bd.size_a, bd.crc_a),
+        1 => (bd.size_b, bd.crc_b),
+        _ => return BankVerify::BadBank(bank),
+    };
+    if size == 0 {
+        return BankVerify::NoMetadata;
+    }
+    if size > flash.layout().bank_size {
+        return BankVerify::SizeOutOfRange(size);
+    }
+
+    let computed = compute_crc32_with(flash, bank_base(flash.layout(), bank), size, on_chunk);
+    if computed != crc {
+        return BankVerify::CrcMismatch {
+            expected: crc,
+            computed,
+        };
+    }
+    BankVerify::Ok { size, crc }
+}
+
+/// Get the flash address for a bank in the default layout.
 pub fn bank_address(bank: u8) -> u32 {
+    bank_base(&FlashLayout::DEFAULT, bank)
+}
+
+/// Flash address of `bank` in `layout`; anything but 0 is bank B.
+pub fn bank_base(layout: &FlashLayout, bank: u8) -> u32 {
     if bank == 0 {
-        FW_A_ADDR
+        layout.fw_a
     } else {
-        FW_B_ADDR
+        layout.fw_b
     }
 }
 
 /// Get the inactive bank (opposite of current active bank).
-pub fn inactive_bank() -> u8 {
-    let bd = read_boot_data();
-    if bd.is_valid() && bd.active_bank == 0 {
+pub fn inactive_bank(flash: &impl FlashOps) -> u8 {
+    if read_boot_data(flash).is_ok_and(|bd| bd.active_bank == 0) {
         1
     } else {
         0
     }
 }
 
-/// Erase a firmware bank.
+/// Erase a firmware bank and check that it reads back blank.
+///
+/// A bank that is not all 0xFF afterwards is erased once more; if it still
+/// is not, this fails with [`FlashError::Verify`] at the bank offset of the
+/// first byte that did not erase.
 ///
 /// # Arguments
 /// * `bank` - 0 for bank A, 1 for bank B
-///
-/// # Safety
-/// Caller must ensure no code is executing from the target bank.
-pub unsafe fn erase_bank(bank: u8) {
-    let addr = bank_address(bank);
-    let offset = addr - FLASH_BASE;
-
-    // Erase entire bank (768KB = 192 sectors of 4KB)
-    let num_sectors = FW_BANK_SIZE / FLASH_SECTOR_SIZE;
-
-    cortex_m::interrupt::disable();
-    rp2040_hal::rom_data::connect_internal_flash();
-    rp2040_hal::rom_data::flash_exit_xip();
-
-    for i in 0..num_sectors {
-        let sector_offset = offset + i * FLASH_SECTOR_SIZE;
-        rp2040_hal::rom_data::flash_range_erase(
-            sector_offset,
-            FLASH_SECTOR_SIZE as usize,
-            FLASH_SECTOR_SIZE,
-            0x20, // SECTOR_ERASE command
-        );
+pub fn erase_bank(flash: &mut impl FlashOps, bank: u8) -> Result<(), FlashError> {
+    check_bank(bank)?;
+    let layout = *flash.layout();
+    let base = bank_base(&layout, bank);
+    let mut first_dirty = None;
+
+    for _ in 0..2 {
+        flash.erase(base - FLASH_BASE, layout.bank_size);
+        first_dirty = blank_check(flash, base, layout.bank_size, || {}).first_dirty;
+        if first_dirty.is_none() {
+            return Ok(());
+        }
     }
 
-    rp2040_hal::rom_data::flash_flush_cache();
-    rp2040_hal::rom_data::flash_enter_cmd_xip();
-    cortex_m::interrupt::enable();
+    first_dirty.map_or(Ok(()), |offset| Err(FlashError::Verify(offset)))
 }
 
 /// Write data to a firmware bank at the specified offset.
 ///
+/// The programmed range is read back and compared; on mismatch the program
+/// is retried once before giving up.
+///
 /// # Arguments
 /// * `bank` - 0 for bank A, 1 for bank B
 /// * `offset` - Offset within the bank (must be page-aligned, 256 bytes)
 /// * `data` - Data to write (must be page-aligned length)
 ///
-/// # Safety
-/// Caller must ensure:
-/// - No code is executing from the target bank
-/// - The bank has been erased before writing
-/// - Offset + data.len() <= FW_BANK_SIZE
-pub unsafe fn write_to_bank(bank: u8, offset: u32, data: &[u8]) {
-    let bank_addr = bank_address(bank);
+/// Fails with [`FlashError::Verify`] at the bank offset of the first byte
+/// that did not verify. Data that does not fit in the bank fails with
+/// [`FlashError::OutOfRange`] at the first byte past its end.
+///
+/// The bank must have been erased before writing.
+pub fn write_to_bank(
+    flash: &mut impl FlashOps,
+    bank: u8,
+    offset: u32,
+    data: &[u8],
+) -> Result<(), FlashError> {
+    check_bank(bank)?;
+    let layout = *flash.layout();
+    if offset
+        .checked_add(data.len() as u32)
+        .is_none_or(|end| end > layout.bank_size)
+    {
+        return Err(FlashError::OutOfRange(layout.bank_size.max(offset)));
+    }
+
+    let bank_addr = bank_base(&layout, bank);
     let flash_offset = (bank_addr - FLASH_BASE) + offset;
 
-    cortex_m::interrupt::disable();
-    rp2040_hal::rom_data::connect_internal_flash();
-    rp2040_hal::rom_data::flash_exit_xip();
-    rp2040_hal::rom_data::flash_range_program(flash_offset, data.as_ptr(), data.len());
-    rp2040_hal::rom_data::flash_flush_cache();
-    rp2040_hal::rom_data::flash_enter_cmd_xip();
-    cortex_m::interrupt::enable();
+    let mut fault = None;
+    for _ in 0..2 {
+        flash.program(flash_offset, data);
+        fault = first_mismatch(flash, bank_addr + offset, data);
+        if fault.is_none() {
+            return Ok(());
+        }
+    }
+
+    fault.map_or(Ok(()), |i| Err(FlashError::Verify(offset + i)))
+}
+
+/// Streams a firmware image into an erased bank.
+///
+/// [`write_to_bank`] only takes whole pages; `BankWriter` accepts any slice
+/// sizes, buffers them into 256-byte pages, programs each page once as it
+/// fills and keeps the CRC32 of the bytes written. [`finish`](Self::finish)
+/// programs the last page padded with 0xFF.
+///
+/// ```ignore
+/// let bank = flash::inactive_bank(&rom_flash);
+/// flash::erase_bank(&mut rom_flash, bank)?;
+/// let mut writer = BankWriter::new(&mut rom_flash, bank);
+/// while let Some(chunk) = link.next_chunk() {
+///     writer.write(chunk)?;
+/// }
+/// // Record size, CRC and version so the bootloader can validate the bank
+/// let (size, crc) = writer.finish(Some(FW_VERSION))?;
+/// ```
+///
+/// Errors are those of [`write_to_bank`]. After one, the bank content is
+/// unknown: erase it and start over with a new writer.
+pub struct BankWriter<'a, F: FlashOps> {
+    flash: &'a mut F,
+    bank: u8,
+    page: [u8; FLASH_PAGE_SIZE as usize],
+    fill: usize,
+    size: u32,
+    digest: Digest,
+}
+
+impl<'a, F: FlashOps> BankWriter<'a, F> {
+    /// Start writing at the beginning of `bank`, which must be erased.
+    pub fn new(flash: &'a mut F, bank: u8) -> Self {
+        Self {
+            flash,
+            bank,
+            page: [0xFF; FLASH_PAGE_SIZE as usize],
+            fill: 0,
+            size: 0,
+            digest: Digest::new(),
+        }
+    }
+
+    /// Bytes accepted so far.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Append `data` to the image. Full pages are programmed immediately;
+    /// the rest waits in the page buffer.
+    ///
+    /// Data that would not fit in the bank is refused as a whole with
+    /// `OutOfRange(bank_size)`.
+    pub fn write(&mut self, mut data: &[u8]) -> Result<(), FlashError> {
+        let bank_size = self.flash.layout().bank_size;
+        if self
+            .size
+            .checked_add(data.len() as u32)
+            .is_none_or(|end| end > bank_size)
+        {
+            return Err(FlashError::OutOfRange(bank_size));
+        }
+        self.digest.update(data);
+
+        while !data.is_empty() {
+            let page_len = self.page.len();
+
+            // Whole pages straight from the caller when nothing is buffered
+            if self.fill == 0 && data.len() >= page_len {
+                let whole = data.len() - data.len() % page_len;
+                write_to_bank(self.flash, self.bank, self.page_offset(), &data[..whole])?;
+                self.size += whole as u32;
+                data = &data[whole..];
+                continue;
+            }
+
+            let n = (page_len - self.fill).min(data.len());
+            self.page[self.fill..self.fill + n].copy_from_slice(&data[..n]);
+            self.fill += n;
+            self.size += n as u32;
+            data = &data[n..];
+            if self.fill == page_len {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Program the padded tail and return the image size and CRC32.
+    ///
+    /// With `Some(version)`, also records size, CRC and version in BootData
+    /// through [`update_bank_metadata`]. The active bank is not changed.
+    pub fn finish(mut self, version: Option<FwVersion>) -> Result<(u32, u32), FlashError> {
+        if self.fill > 0 {
+            self.flush()?;
+        }
+
+        let crc = self.digest.finalize();
+        if let Some(version) = version {
+            update_bank_metadata(self.flash, self.bank, self.size, crc, version)?;
+        }
+        Ok((self.size, crc))
+    }
+
+    /// Bank offset of the page being filled.
+    fn page_offset(&self) -> u32 {
+        self.size - self.fill as u32
+    }
+
+    fn flush(&mut self) -> Result<(), FlashError> {
+        write_to_bank(self.flash, self.bank, self.page_offset(), &self.page)?;
+        self.page.fill(0xFF);
+        self.fill = 0;
+        Ok(())
+    }
 }
 
 /// Update firmware metadata in BootData after writing firmware to a bank.
@@ -169,12 +462,22 @@ pub unsafe fn write_to_bank(bank: u8, offset: u32, data: &[u8]) {
 /// * `size` - Firmware size in bytes
 /// * `crc` - CRC32 of the firmware
 /// * `version` - Firmware version number
-pub fn update_bank_metadata(bank: u8, size: u32, crc: u32, version: u32) {
-    let mut bd = read_boot_data();
-    if !bd.is_valid() {
-        bd = BootData::default_new();
+///
+/// Invalid BootData is replaced by a fresh record rather than reported.
+pub fn update_bank_metadata(
+    flash: &mut impl FlashOps,
+    bank: u8,
+    size: u32,
+    crc: u32,
+    version: FwVersion,
+) -> Result<(), FlashError> {
+    check_bank(bank)?;
+    if size > flash.layout().bank_size {
+        return Err(FlashError::SizeTooLarge(size));
     }
 
+    let mut bd = read_boot_data(flash).unwrap_or_else(|_| BootData::default_new());
+
     if bank == 0 {
         bd.size_a = size;
         bd.crc_a = crc;
@@ -185,34 +488,90 @@ pub fn update_bank_metadata(bank: u8, size: u32, crc: u32, version: u32) {
         bd.version_b = version;
     }
 
-    unsafe {
-        write_boot_data(&bd);
-    }
+    write_boot_data(flash, &bd)
 }
 
 /// Compute CRC32 of data in flash.
-pub fn compute_crc32(addr: u32, size: u32) -> u32 {
-    let data = unsafe { core::slice::from_raw_parts(addr as *const u8, size as usize) };
-
-    // CRC32 (same polynomial as used by bootloader)
-    let mut crc: u32 = 0xFFFF_FFFF;
-    for &byte in data {
-        crc ^= byte as u32;
-        for _ in 0..8 {
-            if crc & 1 != 0 {
-                crc = (crc >> 1) ^ 0xEDB8_8320;
-            } else {
-                crc >>= 1;
+pub fn compute_crc32(flash: &impl FlashOps, addr: u32, size: u32) -> u32 {
+    compute_crc32_with(flash, addr, size, |_| {})
+}
+
+/// Like [`compute_crc32`], calling `on_chunk` with the number of bytes hashed
+/// so far after every [`FLASH_CHUNK_SIZE`] bytes and at the end.
+pub fn compute_crc32_with(
+    flash: &impl FlashOps,
+    addr: u32,
+    size: u32,
+    mut on_chunk: impl FnMut(u32),
+) -> u32 {
+    let mut digest = Digest::new();
+    let mut chunk = [0u8; 1024];
+    let mut done = 0;
+
+    while done < size {
+        let chunk_end = (done + FLASH_CHUNK_SIZE).min(size);
+        while done < chunk_end {
+            let n = (chunk_end - done).min(chunk.len() as u32);
+            flash.read(addr + done, &mut chunk[..n as usize]);
+            digest.update(&chunk[..n as usize]);
+            done += n;
+        }
+        on_chunk(done);
+    }
+
+    digest.finalize()
+}
+
+/// Outcome of a blank check over a flash range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlankReport {
+    /// Offset of the first non-0xFF byte from the start of the range.
+    pub first_dirty: Option<u32>,
+    /// Number of non-0xFF bytes found.
+    pub dirty_bytes: u32,
+}
+
+/// Scan `size` bytes at an absolute flash address for anything other than 0xFF.
+///
+/// `between_chunks` runs after every [`FLASH_CHUNK_SIZE`] bytes so the caller
+/// can keep USB serviced during a full-bank scan.
+pub fn blank_check(
+    flash: &impl FlashOps,
+    addr: u32,
+    size: u32,
+    mut between_chunks: impl FnMut(),
+) -> BlankReport {
+    let mut report = BlankReport {
+        first_dirty: None,
+        dirty_bytes: 0,
+    };
+
+    let mut buf = [0u8; 256];
+    let mut offset = 0;
+    while offset < size {
+        let chunk_end = (offset + FLASH_CHUNK_SIZE).min(size);
+        while offset < chunk_end {
+            let n = (chunk_end - offset).min(buf.len() as u32);
+            flash.read(addr + offset, &mut buf[..n as usize]);
+            for (i, &byte) in buf[..n as usize].iter().enumerate() {
+                if byte != 0xFF {
+                    report.first_dirty.get_or_insert(offset + i as u32);
+                    report.dirty_bytes += 1;
+                }
             }
+            offset += n;
         }
+        between_chunks();
     }
-    !crc
+
+    report
 }
 
 /// Reboot to bootloader update mode.
 ///
 /// This writes the magic flag to RAM and triggers a system reset.
 /// The bootloader will detect the flag and enter update mode.
+#[cfg(feature = "embedded")]
 pub fn reboot_to_bootloader() -> ! {
     unsafe {
         (RAM_UPDATE_FLAG_ADDR as *mut u32).write_volatile(RAM_UPDATE_MAGIC);
@@ -225,31 +584,131 @@ pub fn reboot_to_bootloader() -> ! {
 }
 
 /// Reboot normally.
+#[cfg(feature = "embedded")]
 pub fn reboot() -> ! {
     cortex_m::peripheral::SCB::sys_reset();
 }
 
 // --- Internal helpers ---
 
-unsafe fn flash_erase_and_program(offset: u32, data: &[u8]) {
-    cortex_m::interrupt::disable();
-
-    rp2040_hal::rom_data::connect_internal_flash();
-    rp2040_hal::rom_data::flash_exit_xip();
-    rp2040_hal::rom_data::flash_range_erase(
-        offset,
-        FLASH_SECTOR_SIZE as usize,
-        FLASH_SECTOR_SIZE,
-        0x20,
-    );
-    rp2040_hal::rom_data::flash_flush_cache();
-    rp2040_hal::rom_data::flash_enter_cmd_xip();
-
-    rp2040_hal::rom_data::connect_internal_flash();
-    rp2040_hal::rom_data::flash_exit_xip();
-    rp2040_hal::rom_data::flash_range_program(offset, data.as_ptr(), data.len());
-    rp2040_hal::rom_data::flash_flush_cache();
-    rp2040_hal::rom_data::flash_enter_cmd_xip();
-
-    cortex_m::interrupt::enable();
+fn check_bank(bank: u8) -> Result<(), FlashError> {
+    if bank > 1 {
+        return Err(FlashError::InvalidBank(bank));
+    }
+    Ok(())
+}
+
+/// Index of the first byte at `addr` that differs from `expected`.
+fn first_mismatch(flash: &impl FlashOps, addr: u32, expected: &[u8]) -> Option<u32> {
+    let mut chunk = [0u8; 256];
+    let mut done = 0;
+
+    for part in expected.chunks(chunk.len()) {
+        let actual = &mut chunk[..part.len()];
+        flash.read(addr + done, actual);
+        if let Some(i) = actual.iter().zip(part).position(|(a, b)| a != b) {
+            return Some(done + i as u32);
+        }
+        done += part.len() as u32;
+    }
+
+    None
+}
+
+/// The 0.2.0 signatures of the functions that now take a
+/// [`FlashOps`](crate::flash_ops::FlashOps), over
+/// the ROM routines as 0.2.0 used them, for firmware that has not migrated
+/// yet. Removed in the next release.
+#[cfg(feature = "embedded")]
+pub mod legacy {
+    use crate::flash_ops::RomFlash;
+    use crate::protocol::BootData;
+    use crate::version::FwVersion;
+
+    fn rom_flash() -> RomFlash {
+        // 0.2.0 made the same assumptions without saying so
+        unsafe { RomFlash::new() }
+    }
+
+    /// Read BootData from flash. A rejected record comes back with a zero
+    /// magic, so `is_valid()` is false as it was for one 0.2.0 could not use.
+    #[deprecated(note = "use flash::read_boot_data with a RomFlash, which returns a Result")]
+    pub fn read_boot_data() -> BootData {
+        super::read_boot_data(&rom_flash()).unwrap_or(BootData {
+            magic: 0,
+            ..BootData::default_new()
+        })
+    }
+
+    /// Write BootData to flash.
+    ///
+    /// # Safety
+    /// Caller must ensure no code is executing from flash during this operation.
+    #[deprecated(note = "use flash::write_boot_data with a RomFlash, which returns a Result")]
+    pub unsafe fn write_boot_data(bd: &BootData) {
+        let _ = super::write_boot_data(&mut rom_flash(), bd);
+    }
+
+    /// Confirm the current boot to the bootloader.
+    ///
+    /// Returns true if the boot is (now or already) confirmed, false if
+    /// BootData is invalid or the write failed.
+    #[deprecated(note = "use flash::confirm_boot with a RomFlash, which returns a Result")]
+    pub fn confirm_boot() -> bool {
+        super::confirm_boot(&mut rom_flash()).is_ok()
+    }
+
+    /// Set the active bank for next boot.
+    ///
+    /// Returns false if the bank is invalid, BootData is invalid, or, unlike
+    /// 0.2.0, the bank fails its check.
+    #[deprecated(note = "use flash::set_active_bank with a RomFlash, which returns a Result")]
+    pub fn set_active_bank(bank: u8) -> bool {
+        super::set_active_bank(&mut rom_flash(), bank).is_ok()
+    }
+
+    /// Get the inactive bank (opposite of current active bank).
+    #[deprecated(note = "use flash::inactive_bank with a RomFlash")]
+    pub fn inactive_bank() -> u8 {
+        super::inactive_bank(&rom_flash())
+    }
+
+    /// Erase a firmware bank.
+    ///
+    /// # Safety
+    /// Caller must ensure no code is executing from the target bank.
+    #[deprecated(note = "use flash::erase_bank with a RomFlash, which returns a Result")]
+    pub unsafe fn erase_bank(bank: u8) {
+        let _ = super::erase_bank(&mut rom_flash(), bank);
+    }
+
+    /// Write data to a firmware bank at the specified offset.
+    ///
+    /// # Safety
+    /// Caller must ensure:
+    /// - No code is executing from the target bank
+    /// - The bank has been erased before writing
+    /// - Offset + data.len() <= FW_BANK_SIZE
+    #[deprecated(note = "use flash::write_to_bank with a RomFlash, which returns a Result")]
+    pub unsafe fn write_to_bank(bank: u8, offset: u32, data: &[u8]) {
+        let _ = super::write_to_bank(&mut rom_flash(), bank, offset, data);
+    }
+
+    /// Update firmware metadata in BootData after writing firmware to a bank.
+    #[deprecated(note = "use flash::update_bank_metadata with a RomFlash, which returns a Result")]
+    pub fn update_bank_metadata(bank: u8, size: u32, crc: u32, version: u32) {
+        let _ = super::update_bank_metadata(
+            &mut rom_flash(),
+            bank,
+            size,
+            crc,
+            FwVersion::from_raw(version),
+        );
+    }
+
+    /// Compute CRC32 of data in flash.
+    #[deprecated(note = "use flash::compute_crc32 with a RomFlash")]
+    pub fn compute_crc32(addr: u32, size: u32) -> u32 {
+        super::compute_crc32(&rom_flash(), addr, size)
+    }
 }
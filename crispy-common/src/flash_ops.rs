@@ -0,0 +1,249 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Flash access behind a trait, so flash-dependent logic runs in host tests.
+//!
+//! - [`RomFlash`] (`embedded`): the RP2040 boot ROM routines
+//! - [`FlashSim`](crate::flash_sim::FlashSim) (`std`): a RAM-backed flash
+//!   image with NOR semantics and fault injection
+//!
+//! Erase and program take flash-relative offsets, as the ROM does; reads take
+//! absolute XIP addresses, as the rest of the code base does.
+
+use crate::memory_layout::FlashLayout;
+use crate::protocol::{FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE};
+
+/// Erase, program and read access to the flash part.
+pub trait FlashOps {
+    /// Programming granularity in bytes.
+    const PAGE_SIZE: u32 = FLASH_PAGE_SIZE;
+    /// Erase granularity in bytes.
+    const SECTOR_SIZE: u32 = FLASH_SECTOR_SIZE;
+
+    /// Erase `len` bytes at flash-relative `offset` (both sector aligned).
+    fn erase(&mut self, offset: u32, len: u32);
+
+    /// Program `data` at flash-relative `offset` (both page aligned) into an
+    /// erased range.
+    fn program(&mut self, offset: u32, data: &[u8]);
+
+    /// Read `buf.len()` bytes from absolute address `addr`.
+    fn read(&self, addr: u32, buf: &mut [u8]);
+
+    /// Where the banks and BootData are on this part.
+    fn layout(&self) -> &FlashLayout {
+        &FlashLayout::DEFAULT
+    }
+}
+
+/// Flash access through the RP2040 boot ROM.
+///
+/// **Nothing may execute from flash while XIP is down.** Erase, program and
+/// [`transfer`](Self::transfer) take XIP down, so the code that runs in
+/// between is kept out of flash:
+///
+/// - the ROM routines are looked up once in [`new`](Self::new), while XIP is
+///   still up, and called through the stored pointers
+/// - the sequence from `flash_exit_xip` to `flash_enter_cmd_xip` is in
+///   functions placed in `.data`, which the cortex-m-rt linker script puts in
+///   RAM; `new` asserts they ended up there
+/// - interrupts are off for the whole sequence, since handlers may be in flash
+/// - data to program that lives in flash is copied through a stack buffer
+///   first, as the ROM reads it with XIP down
+///
+/// This holds for firmware copied to RAM by the bootloader and for firmware
+/// executing in place from flash alike.
+#[cfg(feature = "embedded")]
+#[derive(Clone, Copy)]
+pub struct RomFlash {
+    rom: RomFns,
+}
+
+#[cfg(feature = "embedded")]
+impl RomFlash {
+    /// Resolve the ROM flash routines.
+    ///
+    /// # Safety
+    /// The caller must be the only user of the flash while this instance
+    /// erases or programs, and core 1 must not be executing from flash.
+    pub unsafe fn new() -> Self {
+        let ram = 0x2000_0000..0x2004_2000;
+        assert!(
+            ram.contains(&(erase_in_ram as usize))
+                && ram.contains(&(program_in_ram as usize))
+                && ram.contains(&(transfer_in_ram as usize)),
+            "flash routines not linked into RAM"
+        );
+        Self {
+            rom: RomFns::resolve(),
+        }
+    }
+
+    /// Clock `buf` out to the flash as one raw SPI command and replace it
+    /// with the bytes clocked back in, for commands the ROM has no routine
+    /// for (JEDEC ID, unique ID). `buf` must not be in flash.
+    pub fn transfer(&mut self, buf: &mut [u8]) {
+        assert!(!in_xip(buf.as_ptr()), "transfer buffer in flash");
+        cortex_m::interrupt::free(|_| unsafe { transfer_in_ram(&self.rom, buf) });
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl FlashOps for RomFlash {
+    fn erase(&mut self, offset: u32, len: u32) {
+        cortex_m::interrupt::free(|_| unsafe { erase_in_ram(&self.rom, offset, len) });
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        if !in_xip(data.as_ptr()) {
+            cortex_m::interrupt::free(|_| unsafe {
+                program_in_ram(&self.rom, offset, data.as_ptr(), data.len())
+            });
+            return;
+        }
+
+        let mut page = [0u8; FLASH_PAGE_SIZE as usize];
+        for (i, chunk) in data.chunks(page.len()).enumerate() {
+            page[..chunk.len()].copy_from_slice(chunk);
+            let offset = offset + (i * page.len()) as u32;
+            cortex_m::interrupt::free(|_| unsafe {
+                program_in_ram(&self.rom, offset, page.as_ptr(), chunk.len())
+            });
+        }
+    }
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        unsafe { crate::xip::read_at(addr, buf) }
+    }
+}
+
+// ROM function pointer types
+#[cfg(feature = "embedded")]
+type RomFnVoid = unsafe extern "C" fn();
+#[cfg(feature = "embedded")]
+type RomFnErase = unsafe extern "C" fn(u32, usize, u32, u8);
+#[cfg(feature = "embedded")]
+type RomFnProgram = unsafe extern "C" fn(u32, *const u8, usize);
+
+/// ROM flash routines, resolved while XIP is up.
+#[cfg(feature = "embedded")]
+#[derive(Clone, Copy)]
+struct RomFns {
+    connect_internal_flash: RomFnVoid,
+    flash_exit_xip: RomFnVoid,
+    flash_range_erase: RomFnErase,
+    flash_range_program: RomFnProgram,
+    flash_flush_cache: RomFnVoid,
+    flash_enter_cmd_xip: RomFnVoid,
+}
+
+#[cfg(feature = "embedded")]
+impl RomFns {
+    unsafe fn resolve() -> Self {
+        Self {
+            connect_internal_flash: core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(
+                b"IF",
+            )),
+            flash_exit_xip: core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"EX")),
+            flash_range_erase: core::mem::transmute::<usize, RomFnErase>(rom_func_lookup(b"RE")),
+            flash_range_program: core::mem::transmute::<usize, RomFnProgram>(rom_func_lookup(
+                b"RP",
+            )),
+            flash_flush_cache: core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"FC")),
+            flash_enter_cmd_xip: core::mem::transmute::<usize, RomFnVoid>(rom_func_lookup(b"CX")),
+        }
+    }
+}
+
+/// Look up a ROM function by its two-character tag.
+/// ROM table pointer at 0x14 and lookup function at 0x18 are 16-bit halfword pointers.
+#[cfg(feature = "embedded")]
+unsafe fn rom_func_lookup(tag: &[u8; 2]) -> usize {
+    let fn_table = *(0x14 as *const u16) as *const u16;
+    let lookup: unsafe extern "C" fn(*const u16, u32) -> usize =
+        core::mem::transmute::<usize, unsafe extern "C" fn(*const u16, u32) -> usize>(
+            *(0x18 as *const u16) as usize,
+        );
+    let code = u16::from_le_bytes(*tag) as u32;
+    lookup(fn_table, code)
+}
+
+/// Whether `ptr` points into the XIP window, which is unreadable with XIP down.
+#[cfg(feature = "embedded")]
+fn in_xip(ptr: *const u8) -> bool {
+    (0x1000_0000..0x2000_0000).contains(&(ptr as usize))
+}
+
+/// Erase from RAM. Interrupts must be disabled.
+#[cfg(feature = "embedded")]
+#[link_section = ".data"]
+#[inline(never)]
+unsafe fn erase_in_ram(rom: &RomFns, offset: u32, len: u32) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(offset, len as usize, FLASH_SECTOR_SIZE, 0x20); // SECTOR_ERASE
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
+
+/// Program from RAM. Interrupts must be disabled and `data` must not be in
+/// flash.
+#[cfg(feature = "embedded")]
+#[link_section = ".data"]
+#[inline(never)]
+unsafe fn program_in_ram(rom: &RomFns, offset: u32, data: *const u8, len: usize) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_program)(offset, data, len);
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
+
+// QSPI chip-select override and SSI registers used for raw flash commands
+#[cfg(feature = "embedded")]
+const IO_QSPI_SS_CTRL: *mut u32 = 0x4001_800C as *mut u32;
+#[cfg(feature = "embedded")]
+const SS_OUTOVER_MASK: u32 = 0b11 << 8;
+#[cfg(feature = "embedded")]
+const SS_OUTOVER_LOW: u32 = 0b10 << 8;
+#[cfg(feature = "embedded")]
+const SS_OUTOVER_HIGH: u32 = 0b11 << 8;
+#[cfg(feature = "embedded")]
+const SSI_SR: *const u32 = 0x1800_0028 as *const u32;
+#[cfg(feature = "embedded")]
+const SSI_DR0: *mut u32 = 0x1800_0060 as *mut u32;
+#[cfg(feature = "embedded")]
+const SSI_SR_TFNF: u32 = 1 << 1;
+#[cfg(feature = "embedded")]
+const SSI_SR_RFNE: u32 = 1 << 3;
+
+/// Raw command from RAM: the SSI is driven by hand with chip select forced
+/// low. Interrupts must be disabled and `buf` must not be in flash.
+#[cfg(feature = "embedded")]
+#[link_section = ".data"]
+#[inline(never)]
+unsafe fn transfer_in_ram(rom: &RomFns, buf: &mut [u8]) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+
+    let ctrl = IO_QSPI_SS_CTRL.read_volatile() & !SS_OUTOVER_MASK;
+    IO_QSPI_SS_CTRL.write_volatile(ctrl | SS_OUTOVER_LOW);
+
+    // Byte i comes back only after byte i went out, so it can be overwritten
+    let (mut tx_i, mut rx_i) = (0, 0);
+    while rx_i < buf.len() {
+        let sr = SSI_SR.read_volatile();
+        if sr & SSI_SR_TFNF != 0 && tx_i < buf.len() {
+            SSI_DR0.write_volatile(buf[tx_i] as u32);
+            tx_i += 1;
+        }
+        if sr & SSI_SR_RFNE != 0 {
+            buf[rx_i] = SSI_DR0.read_volatile() as u8;
+            rx_i += 1;
+        }
+    }
+
+    IO_QSPI_SS_CTRL.write_volatile(ctrl | SS_OUTOVER_HIGH);
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+}
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! NOR flash simulator for host tests (`std` only).
+//!
+//! [`FlashSim`] keeps the whole flash image in a `Vec` and implements
+//! [`FlashOps`] with the rules of the real part:
+//!
+//! - erase works on whole 4KB sectors and sets them to 0xFF
+//! - program works on whole 256-byte pages and can only clear bits
+//! - misaligned or out-of-range operations panic
+//!
+//! Programming over bytes that are not erased ANDs by default, as the chip
+//! does. A [`strict`](FlashSim::strict) simulator panics instead, to catch
+//! code that forgets to erase first.
+//!
+//! Faults are injected with [`inject`](FlashSim::inject): an operation can
+//! be made to have no effect, power can be cut part way through one, or a
+//! byte can be made to stop erasing. The first two count erase and program
+//! calls from the moment the fault is injected.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::flash_ops::FlashOps;
+use crate::memory_layout::FlashLayout;
+use crate::protocol::{FLASH_BASE, FLASH_SIZE};
+
+/// A fault to trigger on a future erase or program call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The `n`th operation (1-based) returns without touching the flash.
+    FailOp(u32),
+    /// Power is lost during the `n`th operation after `bytes` bytes of it
+    /// took effect. Later erases and programs do nothing until
+    /// [`power_cycle`](FlashSim::power_cycle); reads still work, so tests
+    /// can inspect what was left behind.
+    PowerLoss { op: u32, bytes: usize },
+    /// The byte at absolute address `addr` no longer erases: every erase
+    /// leaves it at `value`, until another fault is injected.
+    StuckByte { addr: u32, value: u8 },
+}
+
+/// RAM-backed flash image with NOR semantics and fault injection.
+pub struct FlashSim {
+    data: Vec<u8>,
+    strict: bool,
+    sector_erases: Vec<u32>,
+    sector_programs: Vec<u32>,
+    fault: Option<Fault>,
+    ops_since_fault: u32,
+    powered: bool,
+    layout: FlashLayout,
+    /// Number of `erase` calls so far.
+    pub erase_count: u32,
+    /// Number of `program` calls so far.
+    pub program_count: u32,
+}
+
+impl FlashSim {
+    /// An erased flash of the configured `FLASH_SIZE`.
+    pub fn new() -> Self {
+        Self::with_size(FLASH_SIZE)
+    }
+
+    /// An erased flash of `size` bytes starting at `FLASH_BASE`.
+    pub fn with_size(size: u32) -> Self {
+        let sectors = size.div_ceil(Self::SECTOR_SIZE) as usize;
+        Self {
+            data: vec![0xFF; size as usize],
+            strict: false,
+            sector_erases: vec![0; sectors],
+            sector_programs: vec![0; sectors],
+            fault: None,
+            ops_since_fault: 0,
+            powered: true,
+            layout: FlashLayout::DEFAULT,
+            erase_count: 0,
+            program_count: 0,
+        }
+    }
+
+    /// An erased flash sized for `layout`, which the flash helpers and the
+    /// update state machine then use instead of the default one.
+    pub fn with_layout(layout: FlashLayout) -> Self {
+        Self {
+            layout,
+            ..Self::with_size(layout.flash_size)
+        }
+    }
+
+    /// Panic when a program would write over bytes that are not erased,
+    /// instead of ANDing like the real part.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Arm a fault, replacing any pending one.
+    pub fn inject(&mut self, fault: Fault) {
+        self.fault = Some(fault);
+        self.ops_since_fault = 0;
+    }
+
+    /// Restore power after a [`Fault::PowerLoss`]. The flash contents stay
+    /// as they were when power went away.
+    pub fn power_cycle(&mut self) {
+        self.powered = true;
+    }
+
+    /// False between a simulated power loss and the next power cycle.
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Number of times the sector containing absolute address `addr` was
+    /// erased.
+    pub fn sector_erases(&self, addr: u32) -> u32 {
+        self.sector_erases[self.sector(addr)]
+    }
+
+    /// Number of program calls that touched the sector containing absolute
+    /// address `addr`.
+    pub fn sector_programs(&self, addr: u32) -> u32 {
+        self.sector_programs[self.sector(addr)]
+    }
+
+    /// The whole flash image.
+    pub fn contents(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Overwrite bytes at an absolute address directly, bypassing NOR rules
+    /// (test setup, simulated corruption).
+    pub fn poke(&mut self, addr: u32, bytes: &[u8]) {
+        let start = self.index(addr, bytes.len());
+        self.data[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// How many bytes of the next operation take effect, if any.
+    fn next_op(&mut self, len: usize) -> usize {
+        if !self.powered {
+            return 0;
+        }
+        let Some(fault) = self.fault else {
+            return len;
+        };
+
+        self.ops_since_fault += 1;
+        match fault {
+            Fault::FailOp(n) if n == self.ops_since_fault => {
+                self.fault = None;
+                0
+            }
+            Fault::PowerLoss { op, bytes } if op == self.ops_since_fault => {
+                self.fault = None;
+                self.powered = false;
+                bytes.min(len)
+            }
+            _ => len,
+        }
+    }
+
+    fn sector(&self, addr: u32) -> usize {
+        self.index(addr, 0) / Self::SECTOR_SIZE as usize
+    }
+
+    fn sectors(&self, start: usize, len: usize) -> core::ops::Range<usize> {
+        let sector = Self::SECTOR_SIZE as usize;
+        start / sector..(start + len).div_ceil(sector)
+    }
+
+    fn index(&self, addr: u32, len: usize) -> usize {
+        let start = addr
+            .checked_sub(FLASH_BASE)
+            .unwrap_or_else(|| panic!("address 0x{:08x} below flash", addr))
+            as usize;
+        assert!(
+            start + len <= self.data.len(),
+            "0x{:08x}+{} beyond flash end",
+            addr,
+            len
+        );
+        start
+    }
+}
+
+impl Default for FlashSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlashOps for FlashSim {
+    fn erase(&mut self, offset: u32, len: u32) {
+        assert!(
+            offset.is_multiple_of(Self::SECTOR_SIZE) && len.is_multiple_of(Self::SECTOR_SIZE),
+            "unaligned erase 0x{:x}+0x{:x}",
+            offset,
+            len
+        );
+        let start = self.index(FLASH_BASE + offset, len as usize);
+        self.erase_count += 1;
+        for sector in self.sectors(start, len as usize) {
+            self.sector_erases[sector] += 1;
+        }
+
+        let done = self.next_op(len as usize);
+        self.data[start..start + done].fill(0xFF);
+        if let Some(Fault::StuckByte { addr, value }) = self.fault {
+            let stuck = self.index(addr, 1);
+            if (start..start + done).contains(&stuck) {
+                self.data[stuck] = value;
+            }
+        }
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        assert!(
+            offset.is_multiple_of(Self::PAGE_SIZE)
+                && (data.len() as u32).is_multiple_of(Self::PAGE_SIZE),
+            "unaligned program 0x{:x}+0x{:x}",
+            offset,
+            data.len()
+        );
+        let start = self.index(FLASH_BASE + offset, data.len());
+        self.program_count += 1;
+        for sector in self.sectors(start, data.len()) {
+            self.sector_programs[sector] += 1;
+        }
+
+        let done = self.next_op(data.len());
+        let cells = &mut self.data[start..start + done];
+        for (i, (cell, &byte)) in cells.iter_mut().zip(data).enumerate() {
+            assert!(
+                !self.strict || *cell == 0xFF || byte == 0xFF,
+                "program over unerased byte at 0x{:08x}",
+                FLASH_BASE + offset + i as u32
+            );
+            *cell &= byte;
+        }
+    }
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        let start = self.index(addr, buf.len());
+        buf.copy_from_slice(&self.data[start..start + buf.len()]);
+    }
+
+    fn layout(&self) -> &FlashLayout {
+        &self.layout
+    }
+}
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Health-gated boot confirmation: confirm a new image only once it has
+//! shown that it works.
+//!
+//! The bootloader counts every boot that is not confirmed and rolls back to
+//! the other bank when the count reaches the limit. Confirming first thing
+//! at startup defeats that, since an image that crashes a second later is
+//! already confirmed. [`confirm_when_healthy`] instead runs the caller's
+//! checks until they pass, then calls [`confirm_boot`]; if they fail or the
+//! deadline passes, the boot is left unconfirmed and the caller makes the
+//! chip reset, typically by no longer feeding the watchdog:
+//!
+//! ```ignore
+//! let outcome = health::confirm_when_healthy(
+//!     &mut rom_flash,
+//!     10_000_000,
+//!     || timer.get_counter().ticks(),
+//!     || {
+//!         watchdog.feed();
+//!         usb_dev.poll(&mut [&mut serial]);
+//!         usb_health(&usb_dev).and(app_health())
+//!     },
+//! );
+//! if !matches!(outcome, Ok(HealthOutcome::Confirmed(_))) {
+//!     loop {} // the watchdog resets the chip; the bootloader counts the boot
+//! }
+//! ```
+//!
+//! The decision itself is [`HealthGate`], which needs neither flash nor a
+//! clock and can be used from a main loop that does not block.
+
+use crate::flash::{confirm_boot, read_boot_data, ConfirmOutcome, FlashError};
+use crate::flash_ops::FlashOps;
+
+/// What a run of the health checks found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Not known yet: run the checks again.
+    Pending,
+    /// Everything checked works.
+    Healthy,
+    /// Something is broken and waiting will not fix it.
+    Failed,
+}
+
+impl Health {
+    /// Both checks together: failed if either failed, healthy if both are.
+    pub fn and(self, other: Health) -> Health {
+        match (self, other) {
+            (Health::Failed, _) | (_, Health::Failed) => Health::Failed,
+            (Health::Healthy, Health::Healthy) => Health::Healthy,
+            _ => Health::Pending,
+        }
+    }
+}
+
+/// What [`HealthGate::decide`] tells the caller to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Run the checks again.
+    Wait,
+    /// The checks passed in time: confirm the boot.
+    Confirm,
+    /// A check failed.
+    Failed,
+    /// The deadline passed with checks still pending.
+    TimedOut,
+}
+
+/// The deadline the checks must pass by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthGate {
+    deadline_us: u64,
+}
+
+impl HealthGate {
+    /// Give the checks `timeout_us` from `now_us`.
+    pub const fn new(now_us: u64, timeout_us: u64) -> Self {
+        Self {
+            deadline_us: now_us.saturating_add(timeout_us),
+        }
+    }
+
+    pub fn deadline_us(&self) -> u64 {
+        self.deadline_us
+    }
+
+    /// Decide from `health`, found at `now_us`. A failure stops at once;
+    /// a pass counts up to and including the deadline.
+    pub fn decide(&self, now_us: u64, health: Health) -> Verdict {
+        match health {
+            Health::Failed => Verdict::Failed,
+            _ if now_us > self.deadline_us => Verdict::TimedOut,
+            Health::Healthy => Verdict::Confirm,
+            Health::Pending => Verdict::Wait,
+        }
+    }
+}
+
+/// How [`confirm_when_healthy`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthOutcome {
+    /// The checks passed and the boot is confirmed, or was already
+    /// confirmed and the checks were not run.
+    Confirmed(ConfirmOutcome),
+    /// A check failed; the boot is not confirmed.
+    Failed,
+    /// The checks did not pass within the timeout; the boot is not
+    /// confirmed.
+    TimedOut,
+}
+
+/// Run `check` until it passes, fails or `timeout_us` has passed on the
+/// `now_us` clock, and confirm the boot if it passed.
+///
+/// BootData must be readable, or [`FlashError::BootData`] is returned before
+/// any check runs. A boot that is already confirmed needs no checks. `check`
+/// is called in a tight loop, so it is where the caller polls its
+/// peripherals and feeds the watchdog.
+pub fn confirm_when_healthy<F: FlashOps>(
+    flash: &mut F,
+    timeout_us: u64,
+    mut now_us: impl FnMut() -> u64,
+    mut check: impl FnMut() -> Health,
+) -> Result<HealthOutcome, FlashError> {
+    if read_boot_data(flash)?.confirmed == 1 {
+        return Ok(HealthOutcome::Confirmed(ConfirmOutcome::AlreadyConfirmed));
+    }
+
+    let gate = HealthGate::new(now_us(), timeout_us);
+    loop {
+        let health = check();
+        match gate.decide(now_us(), health) {
+            Verdict::Wait => {}
+            Verdict::Confirm => return confirm_boot(flash).map(HealthOutcome::Confirmed),
+            Verdict::Failed => return Ok(HealthOutcome::Failed),
+            Verdict::TimedOut => return Ok(HealthOutcome::TimedOut),
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Intel HEX input for the host tools (`std` only).
+//!
+//! Each line is a record, `:LLAAAATT<data>CC`: byte count, 16-bit address,
+//! type, data and a checksum making the bytes sum to zero. Data records
+//! (`00`) are placed at the address plus the upper 16 bits set by the last
+//! extended linear address record (`04`); `01` ends the file and `05` gives
+//! the entry point. Segment addressing (`02`, `03`) is not used by 32-bit
+//! toolchains and is rejected.
+//!
+//! [`parse`] flattens the data into one image. Gaps of up to `max_gap`
+//! bytes are filled with 0xFF, as erased flash reads; wider gaps and
+//! overlapping data are errors, since they mean the file holds more than
+//! one image.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::uf2::in_flash_or_fw_ram;
+
+/// An Intel HEX file flattened into a raw image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IhexImage {
+    /// Address of the first byte of `data`.
+    pub base: u32,
+    pub data: Vec<u8>,
+    /// Entry point from a start linear address record (`05`).
+    pub entry: Option<u32>,
+}
+
+/// Why an Intel HEX file was rejected. `line` counts from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IhexError {
+    /// Not `:` followed by an even number of hex digits, or the byte count
+    /// disagrees with the length.
+    Syntax { line: usize },
+    /// The record bytes do not sum to zero.
+    BadChecksum { line: usize },
+    /// A record type other than `00`, `01`, `04` or `05`.
+    UnsupportedRecord { line: usize, kind: u8 },
+    /// An `04` or `05` record with the wrong length.
+    BadRecordLength { line: usize },
+    /// No end-of-file record (`01`), or records after it.
+    Eof,
+    /// Two data records write the byte at `addr`.
+    Overlap { addr: u32 },
+    /// `len` bytes are missing before `addr`, more than the allowed gap.
+    Gap { addr: u32, len: u32 },
+    /// The image is outside flash and the firmware RAM window.
+    BadAddress(u32),
+    /// No data records.
+    Empty,
+}
+
+/// Whether `file` looks like Intel HEX: a record at the start.
+pub fn is_ihex(file: &[u8]) -> bool {
+    let text = file.trim_ascii_start();
+    text.first() == Some(&b':')
+        && text
+            .get(1..9)
+            .is_some_and(|h| h.iter().all(u8::is_ascii_hexdigit))
+}
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Decode one record into its bytes, checking syntax and checksum.
+fn record(text: &str, line: usize) -> Result<Vec<u8>, IhexError> {
+    let hex = text
+        .strip_prefix(':')
+        .filter(|h| h.len() >= 10 && h.len() % 2 == 0)
+        .ok_or(IhexError::Syntax { line })?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| IhexError::Syntax { line })?;
+
+    if bytes[0] as usize + 5 != bytes.len() {
+        return Err(IhexError::Syntax { line });
+    }
+    if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+        return Err(IhexError::BadChecksum { line });
+    }
+    Ok(bytes)
+}
+
+/// Flatten an Intel HEX file, filling gaps of up to `max_gap` bytes.
+pub fn parse(text: &str, max_gap: u32) -> Result<IhexImage, IhexError> {
+    let mut chunks: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut upper = 0u32;
+    let mut entry = None;
+    let mut ended = false;
+
+    for (i, text) in text.lines().enumerate() {
+        let (line, text) = (i + 1, text.trim());
+        if text.is_empty() {
+            continue;
+        }
+        if ended {
+            return Err(IhexError::Eof);
+        }
+
+        let bytes = record(text, line)?;
+        let offset = u32::from(u16::from_be_bytes([bytes[1], bytes[2]]));
+        let (kind, data) = (bytes[3], &bytes[4..bytes.len() - 1]);
+        match kind {
+            DATA => {
+                // Addresses run on past a 64KB boundary within a record
+                chunks.push((upper.wrapping_add(offset), data.to_vec()));
+            }
+            END_OF_FILE => ended = true,
+            EXTENDED_LINEAR_ADDRESS => {
+                let value: [u8; 2] = data
+                    .try_into()
+                    .map_err(|_| IhexError::BadRecordLength { line })?;
+                upper = u32::from(u16::from_be_bytes(value)) << 16;
+            }
+            START_LINEAR_ADDRESS => {
+                let value: [u8; 4] = data
+                    .try_into()
+                    .map_err(|_| IhexError::BadRecordLength { line })?;
+                entry = Some(u32::from_be_bytes(value));
+            }
+            kind => return Err(IhexError::UnsupportedRecord { line, kind }),
+        }
+    }
+    if !ended {
+        return Err(IhexError::Eof);
+    }
+
+    chunks.retain(|(_, data)| !data.is_empty());
+    chunks.sort_by_key(|(addr, _)| *addr);
+    let base = chunks.first().ok_or(IhexError::Empty)?.0;
+
+    let mut data = Vec::new();
+    for (addr, bytes) in &chunks {
+        let next = u64::from(base) + data.len() as u64;
+        let addr64 = u64::from(*addr);
+        if addr64 < next {
+            return Err(IhexError::Overlap { addr: *addr });
+        }
+        let gap = addr64 - next;
+        if gap > u64::from(max_gap) {
+            return Err(IhexError::Gap {
+                addr: *addr,
+                len: gap as u32,
+            });
+        }
+        data.resize(data.len() + gap as usize, 0xFF);
+        data.extend_from_slice(bytes);
+    }
+
+    if !in_flash_or_fw_ram(base, data.len() as u64) {
+        return Err(IhexError::BadAddress(base));
+    }
+    Ok(IhexImage { base, data, entry })
+}
+
+/// Encode `data` at `base` as Intel HEX, 16 bytes per record.
+pub fn encode(data: &[u8], base: u32) -> String {
+    use core::fmt::Write;
+
+    fn emit(out: &mut String, offset: u16, kind: u8, data: &[u8]) {
+        let mut bytes = Vec::with_capacity(data.len() + 4);
+        bytes.push(data.len() as u8);
+        bytes.extend_from_slice(&offset.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        out.push(':');
+        for b in bytes.iter().chain(core::iter::once(&sum.wrapping_neg())) {
+            let _ = write!(out, "{:02X}", b);
+        }
+        out.push('\n');
+    }
+
+    let mut out = String::new();
+    let mut upper = None;
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let addr = base + (i * 16) as u32;
+        if upper != Some(addr >> 16) {
+            upper = Some(addr >> 16);
+            emit(
+                &mut out,
+                0,
+                EXTENDED_LINEAR_ADDRESS,
+                &((addr >> 16) as u16).to_be_bytes(),
+            );
+        }
+        emit(&mut out, addr as u16, DATA, chunk);
+    }
+    emit(&mut out, 0, END_OF_FILE, &[]);
+    out
+}
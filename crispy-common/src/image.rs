@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Firmware image header, shared by the host packer and the device parser.
+//!
+//! A headered image is the raw firmware binary followed by a 64-byte
+//! [`ImageHeader`]. The header goes at the end rather than the start: the
+//! bootloader runs a bank from its first byte, where the vector table has to
+//! stay, so a headered image boots like the raw binary it wraps. Images
+//! without a header remain valid; the header is recognised by its magic.
+//!
+//! Layout (little-endian, 64 bytes):
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | magic, `b"CRIM"` |
+//! | 4 | 1 | header version (1) |
+//! | 5 | 1 | flags |
+//! | 6 | 2 | board id, 0 = any board |
+//! | 8 | 4 | image length, bytes before the header |
+//! | 12 | 4 | image CRC32, over those bytes |
+//! | 16 | 4 | firmware version |
+//! | 20 | 40 | reserved, zero |
+//! | 60 | 4 | CRC32 of bytes 0..60 |
+//!
+//! The same compatibility rules as the wire format apply: fields are only
+//! ever added in the reserved bytes, and anything else bumps the header
+//! version.
+
+use crate::crc32;
+use crate::version::FwVersion;
+
+/// Size of the encoded header.
+pub const IMAGE_HEADER_SIZE: usize = 64;
+/// `b"CRIM"` read as a little-endian word.
+pub const IMAGE_MAGIC: u32 = u32::from_le_bytes(*b"CRIM");
+/// Header version written by this build.
+pub const IMAGE_HEADER_VERSION: u8 = 1;
+
+/// Offset of the header CRC, which covers every byte before it.
+const HEADER_CRC_OFFSET: usize = IMAGE_HEADER_SIZE - 4;
+
+/// Metadata carried by a headered image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageHeader {
+    /// `IMAGE_FLAG_*` bits; none are defined yet.
+    pub flags: u8,
+    /// Board the image is built for, 0 for any.
+    pub board_id: u16,
+    /// Length of the firmware before the header.
+    pub image_len: u32,
+    /// CRC32 of the firmware before the header.
+    pub image_crc: u32,
+    /// Firmware version recorded in BootData.
+    pub fw_version: FwVersion,
+}
+
+/// Why an image header was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+    /// The header magic is missing.
+    BadMagic(u32),
+    /// Written with a header version this build does not know.
+    UnknownVersion(u8),
+    /// The header contents do not match its CRC.
+    BadHeaderCrc,
+    /// The header describes a different number of bytes than precede it.
+    LengthMismatch { header: u32, actual: u32 },
+    /// The firmware before the header does not match the header CRC.
+    CrcMismatch { header: u32, actual: u32 },
+}
+
+impl ImageHeader {
+    /// Header for `payload` with no flags, for any board.
+    pub fn describe(payload: &[u8], fw_version: FwVersion) -> Self {
+        Self {
+            flags: 0,
+            board_id: 0,
+            image_len: payload.len() as u32,
+            image_crc: crc32::checksum(payload),
+            fw_version,
+        }
+    }
+
+    /// Encode the header, filling in magic, version and header CRC.
+    pub fn to_bytes(&self) -> [u8; IMAGE_HEADER_SIZE] {
+        let mut raw = [0u8; IMAGE_HEADER_SIZE];
+        raw[0..4].copy_from_slice(&IMAGE_MAGIC.to_le_bytes());
+        raw[4] = IMAGE_HEADER_VERSION;
+        raw[5] = self.flags;
+        raw[6..8].copy_from_slice(&self.board_id.to_le_bytes());
+        raw[8..12].copy_from_slice(&self.image_len.to_le_bytes());
+        raw[12..16].copy_from_slice(&self.image_crc.to_le_bytes());
+        raw[16..20].copy_from_slice(&self.fw_version.raw().to_le_bytes());
+        let crc = crc32::checksum(&raw[..HEADER_CRC_OFFSET]);
+        raw[HEADER_CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// Decode a header, checking magic, version and header CRC.
+    pub fn from_bytes(raw: &[u8; IMAGE_HEADER_SIZE]) -> Result<Self, ImageError> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+
+        let magic = word(0);
+        if magic != IMAGE_MAGIC {
+            return Err(ImageError::BadMagic(magic));
+        }
+        if raw[4] != IMAGE_HEADER_VERSION {
+            return Err(ImageError::UnknownVersion(raw[4]));
+        }
+        if crc32::checksum(&raw[..HEADER_CRC_OFFSET]) != word(HEADER_CRC_OFFSET) {
+            return Err(ImageError::BadHeaderCrc);
+        }
+
+        Ok(Self {
+            flags: raw[5],
+            board_id: u16::from_le_bytes([raw[6], raw[7]]),
+            image_len: word(8),
+            image_crc: word(12),
+            fw_version: FwVersion::from_raw(word(16)),
+        })
+    }
+
+    /// Check the header against the firmware it follows, given as its length
+    /// and CRC32 (the device computes the CRC from flash).
+    pub fn check_payload(&self, len: u32, crc: u32) -> Result<(), ImageError> {
+        if self.image_len != len {
+            return Err(ImageError::LengthMismatch {
+                header: self.image_len,
+                actual: len,
+            });
+        }
+        if self.image_crc != crc {
+            return Err(ImageError::CrcMismatch {
+                header: self.image_crc,
+                actual: crc,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Whether `image_len` bytes could hold firmware followed by a header, and
+/// `tail` (the last [`IMAGE_HEADER_SIZE`] bytes) starts with the magic.
+pub fn has_header(image_len: u32, tail: &[u8; IMAGE_HEADER_SIZE]) -> bool {
+    image_len > IMAGE_HEADER_SIZE as u32 && tail[..4] == IMAGE_MAGIC.to_le_bytes()
+}
+
+/// Split a complete image into firmware and header.
+///
+/// Returns `None` for an image without a header, and the firmware with its
+/// validated header otherwise.
+pub fn split(image: &[u8]) -> Option<Result<(&[u8], ImageHeader), ImageError>> {
+    let at = image.len().checked_sub(IMAGE_HEADER_SIZE)?;
+    let (payload, tail) = image.split_at(at);
+    let tail: &[u8; IMAGE_HEADER_SIZE] = tail.try_into().unwrap();
+    if !has_header(image.len() as u32, tail) {
+        return None;
+    }
+
+    Some(ImageHeader::from_bytes(tail).and_then(|header| {
+        header.check_payload(payload.len() as u32, crc32::checksum(payload))?;
+        Ok((payload, header))
+    }))
+}
+
+/// Builds headered images from raw binaries (`std` only).
+#[cfg(feature = "std")]
+pub struct ImageBuilder {
+    fw_version: FwVersion,
+    board_id: u16,
+    flags: u8,
+}
+
+#[cfg(feature = "std")]
+impl ImageBuilder {
+    pub fn new(fw_version: FwVersion) -> Self {
+        Self {
+            fw_version,
+            board_id: 0,
+            flags: 0,
+        }
+    }
+
+    /// Restrict the image to one board.
+    pub fn board_id(mut self, board_id: u16) -> Self {
+        self.board_id = board_id;
+        self
+    }
+
+    pub fn flags(mut self, flags: u8) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// `payload` followed by its header.
+    pub fn wrap(&self, payload: &[u8]) -> alloc::vec::Vec<u8> {
+        let header = ImageHeader {
+            flags: self.flags,
+            board_id: self.board_id,
+            ..ImageHeader::describe(payload, self.fw_version)
+        };
+        let mut image = payload.to_vec();
+        image.extend_from_slice(&header.to_bytes());
+        image
+    }
+}
@@ -0,0 +1,320 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Status LED abstraction.
+//!
+//! Not every board has a plain LED on GPIO25: the Pico W routes it through
+//! the CYW43, and carrier boards may use another pin or an active-low LED.
+//! Code that signals state goes through [`StatusLed`]; the board LED is
+//! selected at build time:
+//! - default: GPIO output on `CRISPY_LED_PIN` (env var, 25 if unset),
+//!   inverted with the `led-active-low` feature
+//! - `led-none`: no LED at all
+//! - `led-external`: calls `crispy_status_led_set(on: bool)`, which the
+//!   application must define with `#[no_mangle]`
+
+/// An LED used to signal bootloader/firmware state.
+pub trait StatusLed {
+    /// Turn the LED on or off.
+    fn set(&mut self, on: bool);
+    /// Invert the current LED state.
+    fn toggle(&mut self);
+}
+
+/// LED that does nothing, for boards without a usable status LED.
+#[derive(Debug, Default)]
+pub struct NoLed;
+
+impl StatusLed for NoLed {
+    fn set(&mut self, _on: bool) {}
+    fn toggle(&mut self) {}
+}
+
+/// LED on a GPIO output, optionally active-low.
+#[cfg(feature = "embedded")]
+pub struct GpioLed<P: embedded_hal::digital::OutputPin> {
+    pin: P,
+    active_low: bool,
+    on: bool,
+}
+
+#[cfg(feature = "embedded")]
+impl<P: embedded_hal::digital::OutputPin> GpioLed<P> {
+    /// Wrap an output pin; the LED starts off.
+    pub fn new(pin: P, active_low: bool) -> Self {
+        let mut led = Self {
+            pin,
+            active_low,
+            on: false,
+        };
+        led.set(false);
+        led
+    }
+}
+
+#[cfg(feature = "embedded")]
+impl<P: embedded_hal::digital::OutputPin> StatusLed for GpioLed<P> {
+    fn set(&mut self, on: bool) {
+        self.on = on;
+        if on != self.active_low {
+            self.pin.set_high().ok();
+        } else {
+            self.pin.set_low().ok();
+        }
+    }
+
+    fn toggle(&mut self) {
+        self.set(!self.on);
+    }
+}
+
+/// LED driven by an application-provided hook (e.g. a CYW43 driver).
+#[cfg(feature = "led-external")]
+#[derive(Debug, Default)]
+pub struct ExternalLed {
+    on: bool,
+}
+
+#[cfg(feature = "led-external")]
+extern "Rust" {
+    fn crispy_status_led_set(on: bool);
+}
+
+#[cfg(feature = "led-external")]
+impl StatusLed for ExternalLed {
+    fn set(&mut self, on: bool) {
+        self.on = on;
+        unsafe { crispy_status_led_set(on) };
+    }
+
+    fn toggle(&mut self) {
+        self.set(!self.on);
+    }
+}
+
+/// A repeating LED pattern: a table of (on, duration in ms) steps.
+///
+/// A step with duration 0 holds its state for good, which is how
+/// [`LedPattern::SOLID`] stays on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LedPattern(&'static [(bool, u32)]);
+
+impl LedPattern {
+    /// Double pulse once a second.
+    pub const HEARTBEAT: Self = Self::new(&[(true, 100), (false, 150), (true, 100), (false, 650)]);
+    /// 1 s on, 1 s off.
+    pub const SLOW_BLINK: Self = Self::new(&[(true, 1000), (false, 1000)]);
+    /// 100 ms on, 100 ms off.
+    pub const FAST_BLINK: Self = Self::new(&[(true, 100), (false, 100)]);
+    /// Three short pulses every two seconds.
+    pub const TRIPLE_PULSE: Self = Self::new(&[
+        (true, 100),
+        (false, 150),
+        (true, 100),
+        (false, 150),
+        (true, 100),
+        (false, 1400),
+    ]);
+    /// Morse SOS (150 ms unit) followed by a word gap.
+    pub const SOS: Self = Self::new(&[
+        (true, 150),
+        (false, 150),
+        (true, 150),
+        (false, 150),
+        (true, 150),
+        (false, 450),
+        (true, 450),
+        (false, 150),
+        (true, 450),
+        (false, 150),
+        (true, 450),
+        (false, 450),
+        (true, 150),
+        (false, 150),
+        (true, 150),
+        (false, 150),
+        (true, 150),
+        (false, 1050),
+    ]);
+    /// Always on.
+    pub const SOLID: Self = Self::new(&[(true, 0)]);
+    /// Always off.
+    pub const OFF: Self = Self::new(&[(false, 0)]);
+
+    /// Wrap a step table; it must not be empty.
+    pub const fn new(steps: &'static [(bool, u32)]) -> Self {
+        assert!(!steps.is_empty(), "LED pattern has no steps");
+        Self(steps)
+    }
+
+    pub fn steps(&self) -> &'static [(bool, u32)] {
+        self.0
+    }
+
+    /// Length of one repetition in microseconds, 0 if the pattern holds.
+    fn period_us(&self) -> u64 {
+        if self.0.iter().any(|&(_, ms)| ms == 0) {
+            return 0;
+        }
+        self.0.iter().map(|&(_, ms)| ms as u64 * 1000).sum()
+    }
+}
+
+/// Plays an [`LedPattern`] without blocking.
+///
+/// Call [`tick`](Self::tick) from the main loop with a microsecond clock;
+/// the LED is only written when the step changes. A loop that stalls for
+/// longer than a step skips ahead instead of replaying the missed steps.
+#[derive(Debug)]
+pub struct PatternPlayer {
+    pattern: LedPattern,
+    step: usize,
+    /// When the current step started, `None` until the first tick.
+    step_start: Option<u64>,
+}
+
+impl PatternPlayer {
+    pub const fn new(pattern: LedPattern) -> Self {
+        Self {
+            pattern,
+            step: 0,
+            step_start: None,
+        }
+    }
+
+    pub fn pattern(&self) -> LedPattern {
+        self.pattern
+    }
+
+    /// Switch to `pattern`, restarting it on the next tick. Setting the
+    /// pattern already playing does nothing, so callers may set it every
+    /// iteration.
+    pub fn set_pattern(&mut self, pattern: LedPattern) {
+        if pattern != self.pattern {
+            *self = Self::new(pattern);
+        }
+    }
+
+    /// Advance the pattern to `now_us` and update `led` if the step changed.
+    pub fn tick(&mut self, led: &mut impl StatusLed, now_us: u64) {
+        let steps = self.pattern.steps();
+        let Some(mut start) = self.step_start else {
+            self.step_start = Some(now_us);
+            led.set(steps[0].0);
+            return;
+        };
+
+        // Drop whole repetitions missed while the caller was busy
+        let period = self.pattern.period_us();
+        if let Some(missed) = now_us.saturating_sub(start).checked_div(period) {
+            start += missed * period;
+        }
+
+        let previous = self.step;
+        loop {
+            let duration = steps[self.step].1 as u64 * 1000;
+            if duration == 0 || now_us.saturating_sub(start) < duration {
+                break;
+            }
+            start += duration;
+            self.step = (self.step + 1) % steps.len();
+        }
+        self.step_start = Some(start);
+
+        if self.step != previous {
+            led.set(steps[self.step].0);
+        }
+    }
+}
+
+// Error codes shown by `error_halt` (number of pulses per group)
+pub const ERR_XOSC: u32 = 2;
+pub const ERR_PLL: u32 = 3;
+pub const ERR_CLOCK: u32 = 4;
+pub const ERR_NO_USB: u32 = 5;
+pub const ERR_LAYOUT: u32 = 6;
+
+/// Signal a fatal error by repeating `code` short pulses followed by a pause,
+/// forever. Timing uses busy-wait cycles so it works without a clock setup.
+#[cfg(feature = "embedded")]
+pub fn error_halt(led: &mut impl StatusLed, code: u32) -> ! {
+    // ~6.5 MHz ROSC after reset; faster clocks just blink faster
+    const PULSE_CYCLES: u32 = 1_000_000;
+
+    loop {
+        for _ in 0..code {
+            led.set(true);
+            cortex_m::asm::delay(PULSE_CYCLES);
+            led.set(false);
+            cortex_m::asm::delay(PULSE_CYCLES);
+        }
+        cortex_m::asm::delay(PULSE_CYCLES * 6);
+    }
+}
+
+/// GPIO number of the board LED, from `CRISPY_LED_PIN` at build time.
+pub const LED_PIN: u8 = match option_env!("CRISPY_LED_PIN") {
+    Some(s) => parse_pin(s),
+    None => 25,
+};
+
+const fn parse_pin(s: &str) -> u8 {
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "CRISPY_LED_PIN is empty");
+    let mut value = 0u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        assert!(bytes[i].is_ascii_digit(), "CRISPY_LED_PIN must be a number");
+        value = value * 10 + (bytes[i] - b'0') as u32;
+        i += 1;
+    }
+    assert!(value < 30, "CRISPY_LED_PIN must be GPIO0..GPIO29");
+    value as u8
+}
+
+#[cfg(all(
+    feature = "embedded",
+    not(any(feature = "led-none", feature = "led-external"))
+))]
+pub type BoardLed = GpioLed<
+    rp2040_hal::gpio::Pin<
+        rp2040_hal::gpio::DynPinId,
+        rp2040_hal::gpio::FunctionSioOutput,
+        rp2040_hal::gpio::PullNone,
+    >,
+>;
+#[cfg(feature = "led-none")]
+pub type BoardLed = NoLed;
+#[cfg(all(feature = "led-external", not(feature = "led-none")))]
+pub type BoardLed = ExternalLed;
+
+/// Create the board LED selected by the `led-*` features.
+///
+/// Must be called after `hal::gpio::Pins::new` has taken IO_BANK0 out of
+/// reset. The caller must not use the `LED_PIN` GPIO from `Pins` elsewhere.
+#[cfg(all(
+    feature = "embedded",
+    not(any(feature = "led-none", feature = "led-external"))
+))]
+pub fn board_led() -> BoardLed {
+    use rp2040_hal::gpio::{DynBankId, DynPinId, FunctionSioOutput, PullNone};
+
+    let pin = unsafe {
+        rp2040_hal::gpio::new_pin(DynPinId {
+            bank: DynBankId::Bank0,
+            num: LED_PIN,
+        })
+    };
+    let pin = pin
+        .try_into_function::<FunctionSioOutput>()
+        .ok()
+        .expect("SIO is valid on every bank0 pin")
+        .into_pull_type::<PullNone>();
+    GpioLed::new(pin, cfg!(feature = "led-active-low"))
+}
+
+/// Create the board LED selected by the `led-*` features.
+#[cfg(any(feature = "led-none", feature = "led-external"))]
+pub fn board_led() -> BoardLed {
+    BoardLed::default()
+}
@@ -7,6 +7,9 @@
 //! - Default: `no_std` mode for embedded targets
 //! - `std` feature: Enables `std` support for host tools
 //! - `embedded` feature: Enables embedded-specific board support (rp2040-hal)
+//! - `led-*` features: Select the status LED implementation (see [`led`])
+//! - `rosc-only` feature: Run without a crystal (no USB); otherwise the crystal
+//!   frequency is taken from `CRISPY_XOSC_HZ` (see `clocks`)
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
@@ -14,69 +17,101 @@
 extern crate alloc;
 
 pub mod boot_fsm;
+pub mod boot_recovery;
+#[cfg(feature = "embedded")]
+pub mod clocks;
 pub mod cobs;
+pub mod crc32;
+pub mod health;
+#[cfg(feature = "std")]
+pub mod ihex;
+pub mod image;
+pub mod led;
+pub mod memory_layout;
 pub mod protocol;
+pub mod provision;
+pub mod self_update;
+pub mod tx_queue;
+#[cfg(feature = "std")]
+pub mod uf2;
+pub mod update_fsm;
+pub mod vector_table;
+pub mod version;
+pub mod webusb;
+pub mod xip;
 
-// Flash operations for firmware (requires embedded feature)
-#[cfg(feature = "embedded")]
+// Flash operations for firmware, over any `FlashOps` implementation
 pub mod flash;
+pub mod flash_ops;
+#[cfg(feature = "std")]
+pub mod flash_sim;
 
 // Re-export commonly used types
-pub use protocol::{AckStatus, BootData, BootState, Command, Response};
-pub use protocol::{BOOT_DATA_ADDR, BOOT_DATA_MAGIC, FLASH_BASE, FW_A_ADDR, FW_B_ADDR};
+pub use protocol::{
+    AckStatus, BootData, BootDataError, BootDataIssue, BootState, Command, ProtocolError, Response,
+};
+pub use protocol::{BOOT_DATA_ADDR, BOOT_DATA_MAGIC, FLASH_BASE, FLASH_SIZE, FW_A_ADDR, FW_B_ADDR};
 pub use protocol::{FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE, FW_BANK_SIZE, MAX_DATA_BLOCK_SIZE};
 
+pub use led::{LedPattern, PatternPlayer, StatusLed};
+pub use memory_layout::{FlashLayout, LayoutError, MemoryLayout, Partition};
+pub use provision::{Provision, ProvisionError};
+pub use version::FwVersion;
+
 // Embedded-specific exports (only with embedded feature)
 #[cfg(feature = "embedded")]
 use embedded_hal::delay::DelayNs;
 #[cfg(feature = "embedded")]
-use embedded_hal::digital::OutputPin;
-#[cfg(feature = "embedded")]
 use rp2040_hal as hal;
 
 #[cfg(feature = "embedded")]
-pub type LedPin =
-    hal::gpio::Pin<hal::gpio::bank0::Gpio25, hal::gpio::FunctionSioOutput, hal::gpio::PullDown>;
+pub use led::{board_led, BoardLed};
 
 /// Initialize RP2040 board peripherals.
 ///
 /// # Safety
 /// Uses `Peripherals::steal()` — caller must ensure exclusive peripheral access.
 #[cfg(feature = "embedded")]
-pub fn init_board() -> (hal::Timer, LedPin) {
+pub fn init_board() -> (hal::Timer, BoardLed) {
     let mut pac = unsafe { hal::pac::Peripherals::steal() };
 
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
-    let clocks = hal::clocks::init_clocks_and_plls(
-        12_000_000u32,
-        pac.XOSC,
-        pac.CLOCKS,
-        pac.PLL_SYS,
-        pac.PLL_USB,
-        &mut pac.RESETS,
-        &mut watchdog,
-    )
-    .unwrap();
-
-    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
     let sio = hal::Sio::new(pac.SIO);
-    let pins = hal::gpio::Pins::new(
+    let _pins = hal::gpio::Pins::new(
         pac.IO_BANK0,
         pac.PADS_BANK0,
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
+    let mut led = board_led();
+
+    let clocks = clocks::init_clocks(
+        clocks::ClockPeripherals {
+            xosc: pac.XOSC,
+            rosc: pac.ROSC,
+            clocks: pac.CLOCKS,
+            pll_sys: pac.PLL_SYS,
+            pll_usb: pac.PLL_USB,
+        },
+        &mut pac.RESETS,
+        &mut watchdog,
+        &mut led,
+    );
 
-    (timer, pins.gpio25.into_push_pull_output())
+    let timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    (timer, led)
 }
 
 /// Blink an LED a specified number of times.
+///
+/// Busy-waits, so it is only for start-up signals; loops should drive the
+/// LED with a [`PatternPlayer`] instead.
 #[cfg(feature = "embedded")]
-pub fn blink(led: &mut impl OutputPin, timer: &mut impl DelayNs, count: u32, period_ms: u32) {
+pub fn blink(led: &mut impl StatusLed, timer: &mut impl DelayNs, count: u32, period_ms: u32) {
     for _ in 0..count {
-        led.set_high().ok();
+        led.set(true);
         timer.delay_ms(period_ms);
-        led.set_low().ok();
+        led.set(false);
         timer.delay_ms(period_ms);
     }
 }
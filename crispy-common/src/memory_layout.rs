@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Flash and RAM layout shared by the bootloader and host code.
+//!
+//! The bootloader reads its layout from linker symbols
+//! (`linker_scripts/bootloader_rp2040.x`), while the update protocol
+//! addresses banks through the constants in [`crate::protocol`]. If the two
+//! disagree, the bootloader validates one address and flashes another, so
+//! the bootloader builds a [`MemoryLayout`] from the linker and refuses to
+//! run unless [`MemoryLayout::check`] passes. Host code uses
+//! [`MemoryLayout::from_constants`].
+//!
+//! The flash half of the layout is a [`FlashLayout`], which is what the
+//! flash helpers and state machines are given.
+
+use crate::protocol::{
+    BOOT_DATA_ADDR, FLASH_BASE, FLASH_SECTOR_SIZE, FLASH_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR,
+};
+
+/// RAM address the firmware is copied to and run from.
+pub const FW_RAM_BASE: u32 = 0x2000_0000;
+/// Bytes copied from the bank to RAM before the jump (192KB).
+pub const FW_COPY_SIZE: u32 = 192 * 1024;
+
+const _: () = assert!(FW_COPY_SIZE <= FW_BANK_SIZE);
+
+/// A flash region outside the firmware banks and BootData (a config store,
+/// a golden image), carried by [`FlashLayout`] so it is checked with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Partition {
+    pub name: &'static str,
+    pub addr: u32,
+    pub size: u32,
+}
+
+/// Where the banks, BootData and any extra partitions live in flash. The
+/// provisioning record takes the last sector.
+///
+/// The flash helpers, the update state machine and the boot FSM take their
+/// addresses from a `FlashLayout` (see
+/// [`FlashOps::layout`](crate::flash_ops::FlashOps::layout)) rather than
+/// from the constants in [`crate::protocol`], which remain as the fields of
+/// [`FlashLayout::DEFAULT`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FlashLayout {
+    pub fw_a: u32,
+    pub fw_b: u32,
+    pub bank_size: u32,
+    pub boot_data: u32,
+    /// Size of the flash part; regions must end by `FLASH_BASE + flash_size`.
+    pub flash_size: u32,
+    pub partitions: &'static [Partition],
+}
+
+impl FlashLayout {
+    /// The layout the protocol constants describe, without extra partitions.
+    pub const DEFAULT: Self = Self {
+        fw_a: FW_A_ADDR,
+        fw_b: FW_B_ADDR,
+        bank_size: FW_BANK_SIZE,
+        boot_data: BOOT_DATA_ADDR,
+        flash_size: FLASH_SIZE,
+        partitions: &[],
+    };
+
+    /// Start address of `bank` (0 = A, 1 = B).
+    pub fn bank_addr(&self, bank: u8) -> Option<u32> {
+        match bank {
+            0 => Some(self.fw_a),
+            1 => Some(self.fw_b),
+            _ => None,
+        }
+    }
+
+    /// Whether `len` bytes at `addr` lie entirely inside `bank`.
+    pub fn bank_contains(&self, bank: u8, addr: u32, len: u32) -> bool {
+        let Some(start) = self.bank_addr(bank) else {
+            return false;
+        };
+        addr >= start
+            && addr
+                .checked_add(len)
+                .is_some_and(|end| end <= start + self.bank_size)
+    }
+
+    /// The bank holding `addr`, if any.
+    pub fn bank_of(&self, addr: u32) -> Option<u8> {
+        (0..2).find(|&bank| self.bank_contains(bank, addr, 1))
+    }
+
+    /// Address of the provisioning record (see [`crate::provision`]): the
+    /// last sector of flash, which no other region may take.
+    pub fn provision(&self) -> u32 {
+        self.flash_end() - FLASH_SECTOR_SIZE
+    }
+
+    /// The partition called `name`, if any.
+    pub fn partition(&self, name: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.name == name)
+    }
+
+    /// Check that the banks, BootData and partitions are sector aligned,
+    /// disjoint and inside flash, with bank A after the bootloader and the
+    /// banks and BootData in that order, all before the provisioning record.
+    pub fn check_geometry(&self) -> Result<(), LayoutError> {
+        for value in [self.fw_a, self.fw_b, self.bank_size, self.boot_data] {
+            if !value.is_multiple_of(FLASH_SECTOR_SIZE) {
+                return Err(LayoutError::Misaligned(value));
+            }
+        }
+        if self.fw_a <= FLASH_BASE {
+            return Err(LayoutError::OutOfFlash(self.fw_a));
+        }
+
+        let (Some(a_end), Some(b_end)) = (
+            self.fw_a.checked_add(self.bank_size),
+            self.fw_b.checked_add(self.bank_size),
+        ) else {
+            return Err(LayoutError::OutOfFlash(self.fw_b));
+        };
+        if self.fw_b < a_end || self.boot_data < b_end {
+            return Err(LayoutError::Overlap);
+        }
+        if self
+            .boot_data
+            .checked_add(FLASH_SECTOR_SIZE)
+            .is_none_or(|end| end > self.flash_end())
+        {
+            return Err(LayoutError::OutOfFlash(self.boot_data));
+        }
+        if self.boot_data + FLASH_SECTOR_SIZE > self.provision() {
+            return Err(LayoutError::Overlap);
+        }
+
+        for (i, p) in self.partitions.iter().enumerate() {
+            self.check_partition(p)?;
+            if self.partitions[..i]
+                .iter()
+                .any(|q| overlaps(p.addr, p.size, q.addr, q.size))
+            {
+                return Err(LayoutError::PartitionOverlap(p.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check one partition against everything but the other partitions.
+    fn check_partition(&self, p: &Partition) -> Result<(), LayoutError> {
+        if !p.addr.is_multiple_of(FLASH_SECTOR_SIZE) || !p.size.is_multiple_of(FLASH_SECTOR_SIZE) {
+            return Err(LayoutError::Misaligned(p.addr));
+        }
+        if p.size == 0
+            || p.addr < FLASH_BASE
+            || p.addr
+                .checked_add(p.size)
+                .is_none_or(|end| end > self.flash_end())
+        {
+            return Err(LayoutError::OutOfFlash(p.addr));
+        }
+
+        let taken = [
+            (FLASH_BASE, self.fw_a - FLASH_BASE),
+            (self.fw_a, self.bank_size),
+            (self.fw_b, self.bank_size),
+            (self.boot_data, FLASH_SECTOR_SIZE),
+            (self.provision(), FLASH_SECTOR_SIZE),
+        ];
+        if taken
+            .iter()
+            .any(|&(addr, size)| overlaps(p.addr, p.size, addr, size))
+        {
+            return Err(LayoutError::PartitionOverlap(p.name));
+        }
+        Ok(())
+    }
+
+    fn flash_end(&self) -> u32 {
+        FLASH_BASE + self.flash_size
+    }
+}
+
+/// Whether two non-empty ranges that do not wrap share a byte.
+fn overlaps(a: u32, a_len: u32, b: u32, b_len: u32) -> bool {
+    a < b + b_len && b < a + a_len
+}
+
+/// The flash layout plus where the firmware runs from in RAM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub flash: FlashLayout,
+    pub ram_base: u32,
+    pub copy_size: u32,
+}
+
+/// Why a layout was rejected by [`MemoryLayout::check`] or
+/// [`FlashLayout::check_geometry`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// Bank A address differs from `FW_A_ADDR`.
+    FwAMismatch(u32),
+    /// Bank B address differs from `FW_B_ADDR`.
+    FwBMismatch(u32),
+    /// Bank size differs from `FW_BANK_SIZE`.
+    BankSizeMismatch(u32),
+    /// BootData address differs from `BOOT_DATA_ADDR`.
+    BootDataMismatch(u32),
+    /// An address or size is not on a 4KB sector boundary.
+    Misaligned(u32),
+    /// Bank A runs into bank B, bank B into BootData, or BootData into the
+    /// provisioning record.
+    Overlap,
+    /// A region starts below the bootloader or ends past the flash.
+    OutOfFlash(u32),
+    /// A partition overlaps the bootloader, a bank, BootData, the
+    /// provisioning record or an earlier partition.
+    PartitionOverlap(&'static str),
+    /// More bytes would be copied to RAM than a bank holds.
+    CopyTooLarge(u32),
+}
+
+impl MemoryLayout {
+    /// The layout the protocol constants describe.
+    pub const fn from_constants() -> Self {
+        Self {
+            flash: FlashLayout::DEFAULT,
+            ram_base: FW_RAM_BASE,
+            copy_size: FW_COPY_SIZE,
+        }
+    }
+
+    /// Check the flash layout against the protocol constants, then the
+    /// geometry.
+    pub fn check(&self) -> Result<(), LayoutError> {
+        let (flash, default) = (&self.flash, &FlashLayout::DEFAULT);
+        if flash.fw_a != default.fw_a {
+            return Err(LayoutError::FwAMismatch(flash.fw_a));
+        }
+        if flash.fw_b != default.fw_b {
+            return Err(LayoutError::FwBMismatch(flash.fw_b));
+        }
+        if flash.bank_size != default.bank_size {
+            return Err(LayoutError::BankSizeMismatch(flash.bank_size));
+        }
+        if flash.boot_data != default.boot_data {
+            return Err(LayoutError::BootDataMismatch(flash.boot_data));
+        }
+        self.check_geometry()
+    }
+
+    /// Check the flash geometry and that the RAM copy fits in a bank,
+    /// whatever the addresses.
+    pub fn check_geometry(&self) -> Result<(), LayoutError> {
+        self.flash.check_geometry()?;
+        if self.copy_size > self.flash.bank_size {
+            return Err(LayoutError::CopyTooLarge(self.copy_size));
+        }
+        Ok(())
+    }
+}
@@ -11,14 +11,42 @@ extern crate alloc;
 
 use serde::{Deserialize, Serialize};
 
+use crate::crc32;
+use crate::flash::BankVerify;
+use crate::memory_layout::FlashLayout;
+use crate::provision::{Provision, PROVISION_SIZE, SERIAL_LEN};
+use crate::version::FwVersion;
+
 // --- Flash layout constants ---
+//
+// The layout scales with the flash part selected at build time
+// (`flash-4mb`, `flash-8mb`, `flash-16mb`; 2MB by default). Each bank gets
+// half of what remains after the bootloader and a 448KB reserved tail, which
+// reproduces the original 2MB layout exactly. `linker_scripts/bootloader_rp2040.x`
+// applies the same formula to `__flash_size`.
+
+#[cfg(feature = "flash-16mb")]
+pub const FLASH_SIZE: u32 = 16 * 1024 * 1024;
+#[cfg(all(feature = "flash-8mb", not(feature = "flash-16mb")))]
+pub const FLASH_SIZE: u32 = 8 * 1024 * 1024;
+#[cfg(all(
+    feature = "flash-4mb",
+    not(any(feature = "flash-8mb", feature = "flash-16mb"))
+))]
+pub const FLASH_SIZE: u32 = 4 * 1024 * 1024;
+#[cfg(not(any(feature = "flash-4mb", feature = "flash-8mb", feature = "flash-16mb")))]
+pub const FLASH_SIZE: u32 = 2 * 1024 * 1024;
 
 pub const FLASH_BASE: u32 = 0x1000_0000;
-pub const FW_A_ADDR: u32 = 0x1001_0000;
-pub const FW_B_ADDR: u32 = 0x100D_0000;
-pub const BOOT_DATA_ADDR: u32 = 0x1019_0000;
+pub const BOOTLOADER_SIZE: u32 = 64 * 1024;
+pub const FLASH_RESERVED_TAIL: u32 = 448 * 1024;
 
-pub const FW_BANK_SIZE: u32 = 768 * 1024; // 768KB per bank
+pub const FW_BANK_SIZE: u32 = (FLASH_SIZE - BOOTLOADER_SIZE - FLASH_RESERVED_TAIL) / 2; // 768KB on 2MB
+pub const FW_A_ADDR: u32 = FLASH_BASE + BOOTLOADER_SIZE;
+pub const FW_B_ADDR: u32 = FW_A_ADDR + FW_BANK_SIZE;
+pub const BOOT_DATA_ADDR: u32 = FW_B_ADDR + FW_BANK_SIZE;
+/// The provisioning record, in the last sector (see [`crate::provision`]).
+pub const PROVISION_ADDR: u32 = FLASH_BASE + FLASH_SIZE - FLASH_SECTOR_SIZE;
 
 pub const RAM_UPDATE_FLAG_ADDR: u32 = 0x2003_BFF0;
 pub const RAM_UPDATE_MAGIC: u32 = 0x0FDA_7E00;
@@ -28,26 +56,122 @@ pub const FLASH_PAGE_SIZE: u32 = 256;
 
 pub const BOOT_DATA_MAGIC: u32 = 0xB007_DA7A;
 
-// --- BootData (repr(C), 32 bytes) ---
+// Layout must stay sector-aligned and inside the selected part
+const _: () = assert!(FW_BANK_SIZE.is_multiple_of(FLASH_SECTOR_SIZE));
+const _: () = assert!(BOOT_DATA_ADDR + FLASH_SECTOR_SIZE <= PROVISION_ADDR);
+
+// --- USB identification ---
+
+pub const USB_VID: u16 = 0x2E8A;
+pub const BOOTLOADER_PID: u16 = 0x000A;
+/// PID of the sample firmware's serial console.
+pub const FIRMWARE_PID: u16 = 0x000B;
+
+/// Interface number of the protocol CDC in the bootloader's composite device.
+/// Interfaces 0/1 are the text console; the protocol uses 2/3. Builds with
+/// the `single-cdc` feature expose only the protocol, on interface 0.
+pub const PROTOCOL_INTERFACE: u8 = 2;
+
+// --- BootData (repr(C), 64 bytes, layout v2) ---
+//
+// The first 32 bytes are the v1 record, unchanged. A v1 record ends there and
+// was written padded with 0xFF, so its byte 32 reads as erased flash; a v2
+// record stores its layout version in that byte and a CRC-32 of everything
+// before it in the last 4 bytes. v1 records are upgraded in memory when read
+// and stored as v2 by the next write.
+
+/// Size of the stored BootData record.
+pub const BOOT_DATA_SIZE: usize = 64;
+/// Layout version written by this build.
+pub const BOOT_DATA_VERSION: u8 = 2;
+
+/// Size of the original record, and offset of `layout_version`.
+const BOOT_DATA_V1_SIZE: usize = 32;
+/// Offset of `record_crc`, which covers every byte before it.
+const BOOT_DATA_CRC_OFFSET: usize = BOOT_DATA_SIZE - 4;
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 pub struct BootData {
-    pub magic: u32,        // 0xB007DA7A
-    pub active_bank: u8,   // 0 = A, 1 = B
-    pub confirmed: u8,     // 1 = confirmed good
-    pub boot_attempts: u8, // rollback after 3
-    pub _reserved0: u8,
-    pub version_a: u32, // firmware version in bank A
-    pub version_b: u32, // firmware version in bank B
-    pub crc_a: u32,     // CRC32 of bank A firmware
-    pub crc_b: u32,     // CRC32 of bank B firmware
-    pub size_a: u32,    // size of firmware in bank A
-    pub size_b: u32,    // size of firmware in bank B
+    pub magic: u32,            // 0xB007DA7A
+    pub active_bank: u8,       // 0 = A, 1 = B
+    pub confirmed: u8,         // 1 = confirmed good
+    pub boot_attempts: u8,     // rollback at boot_attempt_limit()
+    pub flags: u8,             // BOOT_FLAG_* bits
+    pub version_a: FwVersion,  // firmware version in bank A
+    pub version_b: FwVersion,  // firmware version in bank B
+    pub crc_a: u32,            // CRC32 of bank A firmware
+    pub crc_b: u32,            // CRC32 of bank B firmware
+    pub size_a: u32,           // size of firmware in bank A
+    pub size_b: u32,           // size of firmware in bank B
+    pub layout_version: u8,    // BOOT_DATA_VERSION, set by to_bytes()
+    pub max_boot_attempts: u8, // rollback threshold, 0 = default
+    pub reserved: [u8; 26],    // zero; room for new fields
+    pub record_crc: u32,       // CRC32 of bytes 0..60, set by to_bytes()
 }
 
+/// BootData was reconstructed from the banks after the stored record was corrupt.
+pub const BOOT_FLAG_RECONSTRUCTED: u8 = 0x01;
+/// BootData had out-of-range fields that were reset by [`BootData::repaired`].
+pub const BOOT_FLAG_REPAIRED: u8 = 0x02;
+/// Every `BOOT_FLAG_*` bit this build defines.
+pub const BOOT_FLAGS_KNOWN: u8 = BOOT_FLAG_RECONSTRUCTED | BOOT_FLAG_REPAIRED;
+
+/// Unconfirmed boots before rolling back, when BootData does not set a limit.
+pub const DEFAULT_MAX_BOOT_ATTEMPTS: u8 = 3;
+/// Range a stored `max_boot_attempts` is clamped to.
+pub const MAX_BOOT_ATTEMPTS_RANGE: core::ops::RangeInclusive<u8> = 1..=32;
+
 // Compile-time size check
-const _: () = assert!(core::mem::size_of::<BootData>() == 32);
+const _: () = assert!(core::mem::size_of::<BootData>() == BOOT_DATA_SIZE);
+
+/// Why a stored BootData record was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootDataError {
+    /// The magic is wrong: erased or corrupt sector.
+    BadMagic(u32),
+    /// Written with a layout this build does not know (e.g. by a newer
+    /// bootloader).
+    UnknownVersion(u8),
+    /// A v2 record whose contents do not match its CRC.
+    BadCrc,
+}
+
+/// A field of a decoded BootData record that holds an impossible value.
+///
+/// A record can pass its CRC and still be wrong: a v1 record has no CRC,
+/// and a firmware writing BootData itself can store anything. See
+/// [`BootData::issues`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootDataIssue {
+    /// The magic is wrong.
+    BadMagic(u32),
+    /// `active_bank` is neither 0 nor 1.
+    ActiveBankOutOfRange(u8),
+    /// `confirmed` is neither 0 nor 1.
+    ConfirmedNotBool(u8),
+    /// `boot_attempts` is past the largest limit a record can set.
+    AttemptsTooHigh(u8),
+    /// `max_boot_attempts` is outside [`MAX_BOOT_ATTEMPTS_RANGE`] (0 aside).
+    MaxAttemptsOutOfRange(u8),
+    /// A bank size is larger than the bank.
+    SizeTooLarge { bank: u8, size: u32 },
+    /// `flags` has bits outside [`BOOT_FLAGS_KNOWN`].
+    UnknownFlags(u8),
+}
+
+impl BootDataIssue {
+    /// Whether resetting the field fixes this issue. The others leave no
+    /// way to tell which bank holds what, so the record has to be rebuilt
+    /// from the banks instead.
+    pub fn is_repairable(&self) -> bool {
+        !matches!(
+            self,
+            Self::BadMagic(_) | Self::ActiveBankOutOfRange(_) | Self::SizeTooLarge { .. }
+        )
+    }
+}
 
 impl BootData {
     pub fn default_new() -> Self {
@@ -56,13 +180,17 @@ impl BootData {
             active_bank: 0,
             confirmed: 0,
             boot_attempts: 0,
-            _reserved0: 0,
-            version_a: 0,
-            version_b: 0,
+            flags: 0,
+            version_a: FwVersion::from_raw(0),
+            version_b: FwVersion::from_raw(0),
             crc_a: 0,
             crc_b: 0,
             size_a: 0,
             size_b: 0,
+            layout_version: BOOT_DATA_VERSION,
+            max_boot_attempts: 0,
+            reserved: [0; 26],
+            record_crc: 0,
         }
     }
 
@@ -70,30 +198,201 @@ impl BootData {
         self.magic == BOOT_DATA_MAGIC
     }
 
+    /// Every [`BootDataIssue`] in this record under the default layout.
+    pub fn issues(&self) -> impl Iterator<Item = BootDataIssue> {
+        self.issues_for(&FlashLayout::DEFAULT)
+    }
+
+    /// Every [`BootDataIssue`] in this record, in field order, with bank
+    /// sizes checked against `layout`.
+    pub fn issues_for(&self, layout: &FlashLayout) -> impl Iterator<Item = BootDataIssue> {
+        let bank_size = layout.bank_size;
+        let attempts_max = *MAX_BOOT_ATTEMPTS_RANGE.end();
+        [
+            (self.magic != BOOT_DATA_MAGIC).then_some(BootDataIssue::BadMagic(self.magic)),
+            (self.active_bank > 1).then_some(BootDataIssue::ActiveBankOutOfRange(self.active_bank)),
+            (self.confirmed > 1).then_some(BootDataIssue::ConfirmedNotBool(self.confirmed)),
+            (self.boot_attempts > attempts_max)
+                .then_some(BootDataIssue::AttemptsTooHigh(self.boot_attempts)),
+            (self.max_boot_attempts > attempts_max)
+                .then_some(BootDataIssue::MaxAttemptsOutOfRange(self.max_boot_attempts)),
+            (self.size_a > bank_size).then_some(BootDataIssue::SizeTooLarge {
+                bank: 0,
+                size: self.size_a,
+            }),
+            (self.size_b > bank_size).then_some(BootDataIssue::SizeTooLarge {
+                bank: 1,
+                size: self.size_b,
+            }),
+            (self.flags & !BOOT_FLAGS_KNOWN != 0)
+                .then_some(BootDataIssue::UnknownFlags(self.flags)),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// [`validate_extended_for`](Self::validate_extended_for) the default
+    /// layout.
+    pub fn validate_extended(&self) -> Result<(), BootDataIssue> {
+        self.validate_extended_for(&FlashLayout::DEFAULT)
+    }
+
+    /// Check every field, not just the magic; the error is the first issue
+    /// found. Use [`issues_for`](Self::issues_for) to see them all.
+    pub fn validate_extended_for(&self, layout: &FlashLayout) -> Result<(), BootDataIssue> {
+        self.issues_for(layout).next().map_or(Ok(()), Err)
+    }
+
+    /// A copy with every repairable issue reset to a safe value and
+    /// [`BOOT_FLAG_REPAIRED`] set, or `None` if some issue needs the record
+    /// rebuilt from the banks. A record without issues is returned as is.
+    ///
+    /// An unclear `confirmed` becomes unconfirmed and excess attempts become
+    /// the limit, so a doubtful bank rolls back rather than boots forever.
+    pub fn repaired(&self) -> Option<Self> {
+        self.repaired_for(&FlashLayout::DEFAULT)
+    }
+
+    /// [`repaired`](Self::repaired), with bank sizes checked against `layout`.
+    pub fn repaired_for(&self, layout: &FlashLayout) -> Option<Self> {
+        let mut bd = *self;
+        for issue in self.issues_for(layout) {
+            match issue {
+                BootDataIssue::ConfirmedNotBool(_) => bd.confirmed = 0,
+                BootDataIssue::MaxAttemptsOutOfRange(_) => bd.max_boot_attempts = 0,
+                BootDataIssue::UnknownFlags(_) => bd.flags &= BOOT_FLAGS_KNOWN,
+                BootDataIssue::AttemptsTooHigh(_) => {}
+                _ => return None,
+            }
+            bd.flags |= BOOT_FLAG_REPAIRED;
+        }
+        // After max_boot_attempts is settled, so the limit is the final one
+        if bd.boot_attempts > *MAX_BOOT_ATTEMPTS_RANGE.end() {
+            bd.boot_attempts = bd.boot_attempt_limit();
+        }
+        Some(bd)
+    }
+
+    /// Whether [`repaired`](Self::repaired) reset fields of this record.
+    pub fn is_repaired(&self) -> bool {
+        self.flags & BOOT_FLAG_REPAIRED != 0
+    }
+
+    /// Whether this record was rebuilt after the stored one was corrupt.
+    /// Bank sizes of 0 then mean "unknown" rather than "empty".
+    pub fn is_reconstructed(&self) -> bool {
+        self.flags & BOOT_FLAG_RECONSTRUCTED != 0
+    }
+
+    /// Unconfirmed boots allowed before rolling back: the stored limit
+    /// clamped to [`MAX_BOOT_ATTEMPTS_RANGE`], or the default if unset.
+    pub fn boot_attempt_limit(&self) -> u8 {
+        match self.max_boot_attempts {
+            0 => DEFAULT_MAX_BOOT_ATTEMPTS,
+            n => n.clamp(
+                *MAX_BOOT_ATTEMPTS_RANGE.start(),
+                *MAX_BOOT_ATTEMPTS_RANGE.end(),
+            ),
+        }
+    }
+
     pub fn bank_addr(&self) -> u32 {
+        self.bank_addr_in(&FlashLayout::DEFAULT)
+    }
+
+    /// Start address of the active bank in `layout`.
+    pub fn bank_addr_in(&self, layout: &FlashLayout) -> u32 {
         if self.active_bank == 0 {
-            FW_A_ADDR
+            layout.fw_a
         } else {
-            FW_B_ADDR
+            layout.fw_b
         }
     }
 
-    /// Read BootData from a raw address via volatile reads.
+    /// Read and decode BootData from a raw address via volatile reads.
     ///
     /// # Safety
-    /// `addr` must point to a readable, properly aligned memory region of at least 32 bytes.
-    pub unsafe fn read_from(addr: u32) -> Self {
-        let ptr = addr as *const Self;
-        core::ptr::read_volatile(ptr)
-    }
-
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe {
-            core::slice::from_raw_parts(
-                self as *const Self as *const u8,
-                core::mem::size_of::<Self>(),
-            )
+    /// `addr` must point to at least [`BOOT_DATA_SIZE`] readable bytes.
+    pub unsafe fn read_from(addr: u32) -> Result<Self, BootDataError> {
+        let mut raw = [0u8; BOOT_DATA_SIZE];
+        for (i, byte) in raw.iter_mut().enumerate() {
+            *byte = core::ptr::read_volatile((addr as *const u8).add(i));
+        }
+        Self::from_bytes(&raw)
+    }
+
+    /// Decode BootData from its flash representation.
+    ///
+    /// Accepts v1 records (upgraded in memory) and v2 records with a
+    /// matching CRC; anything else is rejected. Fields are read
+    /// little-endian, as [`to_bytes`](Self::to_bytes) writes them.
+    pub fn from_bytes(bytes: &[u8; BOOT_DATA_SIZE]) -> Result<Self, BootDataError> {
+        let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        if magic != BOOT_DATA_MAGIC {
+            return Err(BootDataError::BadMagic(magic));
         }
+
+        let mut raw = *bytes;
+        match bytes[BOOT_DATA_V1_SIZE] {
+            // v1: the record ended here, the rest is erased flash
+            0xFF => {
+                raw[BOOT_DATA_V1_SIZE..].fill(0);
+                raw[BOOT_DATA_V1_SIZE] = BOOT_DATA_VERSION;
+            }
+            BOOT_DATA_VERSION => {
+                let stored = u32::from_le_bytes(bytes[BOOT_DATA_CRC_OFFSET..].try_into().unwrap());
+                if crc32::checksum(&bytes[..BOOT_DATA_CRC_OFFSET]) != stored {
+                    return Err(BootDataError::BadCrc);
+                }
+            }
+            version => return Err(BootDataError::UnknownVersion(version)),
+        }
+
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        Ok(Self {
+            magic,
+            active_bank: raw[4],
+            confirmed: raw[5],
+            boot_attempts: raw[6],
+            flags: raw[7],
+            version_a: FwVersion::from_raw(word(8)),
+            version_b: FwVersion::from_raw(word(12)),
+            crc_a: word(16),
+            crc_b: word(20),
+            size_a: word(24),
+            size_b: word(28),
+            layout_version: raw[32],
+            max_boot_attempts: raw[33],
+            reserved: raw[34..BOOT_DATA_CRC_OFFSET].try_into().unwrap(),
+            record_crc: word(BOOT_DATA_CRC_OFFSET),
+        })
+    }
+
+    /// Encode as a v2 record, filling in `layout_version` and `record_crc`.
+    ///
+    /// Fields are written little-endian at their `repr(C)` offsets, so the
+    /// bytes are the same whichever machine encodes them.
+    pub fn to_bytes(&self) -> [u8; BOOT_DATA_SIZE] {
+        let mut raw = [0u8; BOOT_DATA_SIZE];
+        raw[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        raw[4..8].copy_from_slice(&[
+            self.active_bank,
+            self.confirmed,
+            self.boot_attempts,
+            self.flags,
+        ]);
+        raw[8..12].copy_from_slice(&self.version_a.raw().to_le_bytes());
+        raw[12..16].copy_from_slice(&self.version_b.raw().to_le_bytes());
+        raw[16..20].copy_from_slice(&self.crc_a.to_le_bytes());
+        raw[20..24].copy_from_slice(&self.crc_b.to_le_bytes());
+        raw[24..28].copy_from_slice(&self.size_a.to_le_bytes());
+        raw[28..32].copy_from_slice(&self.size_b.to_le_bytes());
+        raw[BOOT_DATA_V1_SIZE] = BOOT_DATA_VERSION;
+        raw[33] = self.max_boot_attempts;
+        raw[34..BOOT_DATA_CRC_OFFSET].copy_from_slice(&self.reserved);
+        let crc = crc32::checksum(&raw[..BOOT_DATA_CRC_OFFSET]);
+        raw[BOOT_DATA_CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        raw
     }
 }
 
@@ -101,6 +400,8 @@ impl BootData {
 
 /// Maximum data block size for firmware uploads.
 pub const MAX_DATA_BLOCK_SIZE: usize = 1024;
+/// Maximum `ReadFlash` length, the same as a data block.
+pub const MAX_READ_SIZE: usize = MAX_DATA_BLOCK_SIZE;
 
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(clippy::large_enum_variant)] // no_std, no allocator for Box
@@ -110,7 +411,10 @@ pub enum Command {
         bank: u8,
         size: u32,
         crc32: u32,
-        version: u32,
+        version: FwVersion,
+        /// Blocks may skip ahead to a later page instead of following each
+        /// other; the pages skipped stay erased (0xFF).
+        sparse: bool,
     },
     #[cfg(not(feature = "std"))]
     DataBlock {
@@ -130,17 +434,265 @@ pub enum Command {
     },
     /// Wipe all firmware banks and reset boot data.
     WipeAll,
+    /// Report the flash part and the bank layout the bootloader was built for.
+    GetFlashInfo,
+    /// Scan an absolute flash range and report any byte that is not 0xFF.
+    BlankCheck {
+        addr: u32,
+        length: u32,
+    },
+    /// Set how many unconfirmed boots are allowed before rolling back
+    /// (within `MAX_BOOT_ATTEMPTS_RANGE`, or 0 for the default).
+    SetBootAttempts {
+        max_attempts: u8,
+    },
+    /// Copy the firmware in bank `from`, and its BootData metadata, to bank
+    /// `to`. The active bank is not changed.
+    CopyBank {
+        from: u8,
+        to: u8,
+    },
+    /// Read the stored BootData record as it is in flash, and check both
+    /// banks against it.
+    GetBootData,
+    /// Report the bootloader version, chip and flash identity, and the last
+    /// error this session.
+    GetDeviceInfo,
+    /// Read `length` bytes (1 to `MAX_READ_SIZE`) of flash at the absolute
+    /// address `addr`; answered by `FlashData`.
+    ReadFlash {
+        addr: u32,
+        length: u32,
+    },
+    /// Change the version BootData records for `bank`, which must hold
+    /// firmware. The image, its size and CRC, and the boot state are left
+    /// alone.
+    SetVersion {
+        bank: u8,
+        version: FwVersion,
+    },
+    /// Acknowledge a block of up to `MAX_DATA_BLOCK_SIZE` bytes and drop
+    /// it, in any state, so the host can time the link without flash.
+    #[cfg(not(feature = "std"))]
+    BenchData {
+        data: heapless::Vec<u8, MAX_DATA_BLOCK_SIZE>,
+    },
+    #[cfg(feature = "std")]
+    BenchData {
+        data: alloc::vec::Vec<u8>,
+    },
+    /// Replace the stored BootData record with `raw` (`BOOT_DATA_SIZE`
+    /// bytes, as `BootData` reports it), e.g. to restore a backup. The
+    /// record must decode without issues, and each bank it gives a size
+    /// must hold firmware matching its CRC.
+    SetBootData {
+        raw: heapless::Vec<u8, BOOT_DATA_SIZE>,
+    },
+    /// Read the provisioning record as it is in flash; answered by
+    /// `Provision`.
+    GetProvision,
+    /// Write the provisioning record (see [`crate::provision`]). Writing
+    /// the record already stored leaves flash alone.
+    SetProvision {
+        board_id: u16,
+        hw_revision: u8,
+        /// 1 to `SERIAL_LEN` printable ASCII characters.
+        serial: heapless::Vec<u8, SERIAL_LEN>,
+    },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Command {
+    /// Check the fields a corrupt or hostile frame could set to anything.
+    ///
+    /// Only stateless limits are checked here; whether a command fits the
+    /// current transfer is up to the update state machine.
+    pub fn validate(&self) -> Result<(), ProtocolError> {
+        self.validate_for(&FlashLayout::DEFAULT)
+    }
+
+    /// [`validate`](Self::validate) against the bank size and flash size of
+    /// `layout`.
+    pub fn validate_for(&self, layout: &FlashLayout) -> Result<(), ProtocolError> {
+        match self {
+            Command::StartUpdate { bank, size, .. } => {
+                check_bank(*bank)?;
+                if *size == 0 || *size > layout.bank_size {
+                    return Err(ProtocolError::BadImageSize(*size));
+                }
+            }
+            Command::DataBlock { offset, data } => {
+                if data.len() > MAX_DATA_BLOCK_SIZE {
+                    return Err(ProtocolError::BlockTooLarge(data.len()));
+                }
+                let end = offset.checked_add(data.len() as u32);
+                if end.is_none_or(|end| end > layout.bank_size) {
+                    return Err(ProtocolError::BlockOutOfBank(*offset));
+                }
+            }
+            Command::BenchData { data } => {
+                if data.len() > MAX_DATA_BLOCK_SIZE {
+                    return Err(ProtocolError::BlockTooLarge(data.len()));
+                }
+            }
+            Command::SetActiveBank { bank } | Command::SetVersion { bank, .. } => {
+                check_bank(*bank)?
+            }
+            Command::CopyBank { from, to } => {
+                check_bank(*from)?;
+                check_bank(*to)?;
+                if from == to {
+                    return Err(ProtocolError::SameBank(*from));
+                }
+            }
+            Command::ReadFlash { addr, length } => {
+                if *length == 0 || *length as usize > MAX_READ_SIZE {
+                    return Err(ProtocolError::BadReadLength(*length));
+                }
+                check_range(layout, *addr, *length)?;
+            }
+            Command::BlankCheck { addr, length } => check_range(layout, *addr, *length)?,
+            Command::SetBootData { raw } => {
+                if raw.len() != BOOT_DATA_SIZE {
+                    return Err(ProtocolError::BadRecordLength(raw.len()));
+                }
+            }
+            Command::SetProvision {
+                board_id,
+                hw_revision,
+                serial,
+            } => {
+                if Provision::new(*board_id, *hw_revision, serial).is_err() {
+                    return Err(ProtocolError::BadSerial);
+                }
+            }
+            // 0 restores the default; anything else must be in range, not clamped
+            Command::SetBootAttempts { max_attempts } => {
+                if *max_attempts != 0 && !MAX_BOOT_ATTEMPTS_RANGE.contains(max_attempts) {
+                    return Err(ProtocolError::BadBootAttempts(*max_attempts));
+                }
+            }
+            Command::GetStatus
+            | Command::FinishUpdate
+            | Command::Reboot
+            | Command::WipeAll
+            | Command::GetFlashInfo
+            | Command::GetBootData
+            | Command::GetDeviceInfo
+            | Command::GetProvision => {}
+        }
+        Ok(())
+    }
+}
+
+fn check_bank(bank: u8) -> Result<(), ProtocolError> {
+    if bank > 1 {
+        return Err(ProtocolError::BadBank(bank));
+    }
+    Ok(())
+}
+
+/// Check that `length` bytes at the absolute address `addr` are in flash.
+fn check_range(layout: &FlashLayout, addr: u32, length: u32) -> Result<(), ProtocolError> {
+    let end = addr.checked_add(length);
+    if addr < FLASH_BASE || end.is_none_or(|end| end > FLASH_BASE + layout.flash_size) {
+        return Err(ProtocolError::RangeOutOfFlash(addr));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::large_enum_variant)] // no_std, no allocator for Box
 pub enum Response {
     Ack(AckStatus),
     Status {
         active_bank: u8,
-        version_a: u32,
-        version_b: u32,
+        version_a: FwVersion,
+        version_b: FwVersion,
         state: BootState,
+        /// Host has asserted DTR on the CDC port.
+        host_connected: bool,
+        /// A response has been dropped because the host stopped reading.
+        tx_stalled: bool,
+        /// BootData was found corrupt and rebuilt from the banks.
+        bootdata_reconstructed: bool,
+        /// The previous update-mode session ended in a reset (e.g. watchdog).
+        update_interrupted: bool,
+    },
+    /// Negative acknowledgement with the bank offset where the failure occurred.
+    Nack {
+        status: AckStatus,
+        offset: u32,
+    },
+    FlashInfo {
+        /// JEDEC ID (manufacturer, type, capacity) read from the flash chip.
+        jedec_id: u32,
+        /// Size reported by the chip, or 0 if it could not be determined.
+        detected_size: u32,
+        /// Size the layout was built for.
+        layout_size: u32,
+        bank_size: u32,
+        fw_a_addr: u32,
+        fw_b_addr: u32,
+        boot_data_addr: u32,
+    },
+    BlankCheckResult {
+        /// Offset (from the requested address) of the first non-0xFF byte.
+        first_dirty: Option<u32>,
+        /// Number of non-0xFF bytes in the range.
+        dirty_bytes: u32,
     },
+    /// Progress of a long-running operation (e.g. CRC verification).
+    /// Sent before the command's final response, which still follows.
+    Progress {
+        done: u32,
+        total: u32,
+    },
+    BootData {
+        /// The BootData sector's first `BOOT_DATA_SIZE` bytes, unvalidated.
+        raw: heapless::Vec<u8, BOOT_DATA_SIZE>,
+        /// Bank A and B checked against the record (both `NoMetadata` if it
+        /// is invalid).
+        banks: [BankVerify; 2],
+    },
+    DeviceInfo {
+        /// Bootloader crate version.
+        bootloader_version: FwVersion,
+        /// RP2040 `SYSINFO.CHIP_ID` (revision, part, manufacturer).
+        chip_id: u32,
+        /// Unique ID of the flash chip, or 0 if it could not be read.
+        flash_uid: u64,
+        /// Most recent non-`Ok` status sent since the bootloader started.
+        last_error: Option<AckStatus>,
+    },
+    /// Flash contents for `ReadFlash`. The bootloader streams this from
+    /// flash after [`flash_data_prefix`] rather than building it.
+    #[cfg(not(feature = "std"))]
+    FlashData {
+        addr: u32,
+        data: heapless::Vec<u8, MAX_READ_SIZE>,
+    },
+    #[cfg(feature = "std")]
+    FlashData {
+        addr: u32,
+        data: alloc::vec::Vec<u8>,
+    },
+    Provision {
+        /// The provisioning sector's first `PROVISION_SIZE` bytes,
+        /// unvalidated: all 0xFF on a unit never provisioned.
+        raw: heapless::Vec<u8, PROVISION_SIZE>,
+    },
+}
+
+/// Index of `Response::FlashData`, pinned by the wire-format tests.
+const FLASH_DATA_VARIANT: u32 = 8;
+
+/// The encoding of `Response::FlashData { addr, data }` up to the data
+/// itself, for a platform that sends the `length` data bytes after it.
+pub fn flash_data_prefix(addr: u32, length: u32) -> heapless::Vec<u8, 16> {
+    // Variant index, addr and the data length are all varints
+    let mut buf = [0u8; 16];
+    let prefix = postcard::to_slice(&(FLASH_DATA_VARIANT, addr, length), &mut buf).unwrap();
+    heapless::Vec::from_slice(prefix).unwrap()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -151,6 +703,50 @@ pub enum AckStatus {
     BadCommand,
     BadState,
     BankInvalid,
+    /// The built-in flash layout does not fit the detected flash part.
+    LayoutMismatch,
+    /// The uploaded image carries a header (see [`crate::image`]) that does
+    /// not match the firmware before it.
+    BadImage,
+}
+
+/// Why a frame from the host was refused before it reached a handler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The frame does not decode as a `Command`.
+    Malformed,
+    /// Bank number other than 0 (A) or 1 (B).
+    BadBank(u8),
+    /// Image size of 0 or larger than a bank.
+    BadImageSize(u32),
+    /// Data block longer than `MAX_DATA_BLOCK_SIZE`.
+    BlockTooLarge(usize),
+    /// Data block at this offset reaches past the end of the bank.
+    BlockOutOfBank(u32),
+    /// Range starting at this address is not inside the flash.
+    RangeOutOfFlash(u32),
+    /// Boot attempt limit outside `MAX_BOOT_ATTEMPTS_RANGE` (and not 0).
+    BadBootAttempts(u8),
+    /// Bank copy onto the bank it reads from.
+    SameBank(u8),
+    /// Flash read of 0 or more than `MAX_READ_SIZE` bytes.
+    BadReadLength(u32),
+    /// BootData record that is not `BOOT_DATA_SIZE` bytes long.
+    BadRecordLength(usize),
+    /// Serial number a provisioning record cannot hold.
+    BadSerial,
+}
+
+impl ProtocolError {
+    /// The status reported to the host for this error.
+    pub fn status(&self) -> AckStatus {
+        match self {
+            ProtocolError::BadBank(_)
+            | ProtocolError::BadImageSize(_)
+            | ProtocolError::SameBank(_) => AckStatus::BankInvalid,
+            _ => AckStatus::BadCommand,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Provisioning record: the identity a unit is given at the end of the
+//! production line, shared by the bootloader, the host and firmware.
+//!
+//! The record lives in the last sector of flash (see
+//! [`FlashLayout::provision`](crate::memory_layout::FlashLayout::provision)),
+//! away from the banks and BootData, so `WipeAll`, uploads and bank copies
+//! never touch it. The bootloader writes it for `SetProvision` and reports
+//! it for `GetProvision`; firmware reads it with [`Provision::read_from`].
+//!
+//! Layout (little-endian, 32 bytes):
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | magic, `b"PROV"` |
+//! | 4 | 1 | record version (1) |
+//! | 5 | 1 | hardware revision |
+//! | 6 | 2 | board id |
+//! | 8 | 16 | serial number, ASCII, zero padded |
+//! | 24 | 4 | reserved, zero |
+//! | 28 | 4 | CRC32 of bytes 0..28 |
+//!
+//! As with the image header, fields are only ever added in the reserved
+//! bytes, and anything else bumps the record version.
+
+use crate::crc32;
+
+/// Size of the encoded record.
+pub const PROVISION_SIZE: usize = 32;
+/// `b"PROV"` read as a little-endian word.
+pub const PROVISION_MAGIC: u32 = u32::from_le_bytes(*b"PROV");
+/// Record version written by this build.
+pub const PROVISION_VERSION: u8 = 1;
+/// Longest serial number a record holds.
+pub const SERIAL_LEN: usize = 16;
+
+/// Offset of the record CRC, which covers every byte before it.
+const PROVISION_CRC_OFFSET: usize = PROVISION_SIZE - 4;
+
+/// A unit's identity.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Provision {
+    /// Board the unit is, as image headers name it.
+    pub board_id: u16,
+    /// Hardware revision of that board.
+    pub hw_revision: u8,
+    /// Serial number or batch, 1 to [`SERIAL_LEN`] printable ASCII
+    /// characters, zero padded.
+    serial: [u8; SERIAL_LEN],
+}
+
+/// Why a provisioning record was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProvisionError {
+    /// The sector is erased: the unit was never provisioned.
+    Blank,
+    /// The magic is wrong.
+    BadMagic(u32),
+    /// Written with a record version this build does not know.
+    UnknownVersion(u8),
+    /// The record contents do not match its CRC.
+    BadCrc,
+    /// The serial number is empty, longer than [`SERIAL_LEN`], or has a
+    /// character that is not printable ASCII.
+    BadSerial,
+}
+
+impl Provision {
+    /// A record for `serial`, checked as [`ProvisionError::BadSerial`]
+    /// describes.
+    pub fn new(board_id: u16, hw_revision: u8, serial: &[u8]) -> Result<Self, ProvisionError> {
+        let valid =
+            (1..=SERIAL_LEN).contains(&serial.len()) && serial.iter().all(|b| b.is_ascii_graphic());
+        if !valid {
+            return Err(ProvisionError::BadSerial);
+        }
+        let mut padded = [0u8; SERIAL_LEN];
+        padded[..serial.len()].copy_from_slice(serial);
+        Ok(Self {
+            board_id,
+            hw_revision,
+            serial: padded,
+        })
+    }
+
+    /// The serial number, without its padding.
+    pub fn serial(&self) -> &str {
+        let len = self
+            .serial
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(SERIAL_LEN);
+        // Checked to be ASCII by `new` and `from_bytes`
+        core::str::from_utf8(&self.serial[..len]).unwrap_or_default()
+    }
+
+    /// Encode the record, filling in magic, version and CRC.
+    pub fn to_bytes(&self) -> [u8; PROVISION_SIZE] {
+        let mut raw = [0u8; PROVISION_SIZE];
+        raw[0..4].copy_from_slice(&PROVISION_MAGIC.to_le_bytes());
+        raw[4] = PROVISION_VERSION;
+        raw[5] = self.hw_revision;
+        raw[6..8].copy_from_slice(&self.board_id.to_le_bytes());
+        raw[8..24].copy_from_slice(&self.serial);
+        let crc = crc32::checksum(&raw[..PROVISION_CRC_OFFSET]);
+        raw[PROVISION_CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+
+    /// Decode a record, checking magic, version, CRC and serial number.
+    pub fn from_bytes(raw: &[u8; PROVISION_SIZE]) -> Result<Self, ProvisionError> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+
+        if raw.iter().all(|&b| b == 0xFF) {
+            return Err(ProvisionError::Blank);
+        }
+        let magic = word(0);
+        if magic != PROVISION_MAGIC {
+            return Err(ProvisionError::BadMagic(magic));
+        }
+        if raw[4] != PROVISION_VERSION {
+            return Err(ProvisionError::UnknownVersion(raw[4]));
+        }
+        if crc32::checksum(&raw[..PROVISION_CRC_OFFSET]) != word(PROVISION_CRC_OFFSET) {
+            return Err(ProvisionError::BadCrc);
+        }
+
+        let serial = &raw[8..24];
+        let len = serial.iter().position(|&b| b == 0).unwrap_or(SERIAL_LEN);
+        if serial[len..].iter().any(|&b| b != 0) {
+            return Err(ProvisionError::BadSerial);
+        }
+        Self::new(u16::from_le_bytes([raw[6], raw[7]]), raw[5], &serial[..len])
+    }
+
+    /// Read and decode the record at a raw address via volatile reads.
+    ///
+    /// # Safety
+    /// `addr` must point to at least [`PROVISION_SIZE`] readable bytes.
+    pub unsafe fn read_from(addr: u32) -> Result<Self, ProvisionError> {
+        let mut raw = [0u8; PROVISION_SIZE];
+        for (i, byte) in raw.iter_mut().enumerate() {
+            *byte = core::ptr::read_volatile((addr as *const u8).add(i));
+        }
+        Self::from_bytes(&raw)
+    }
+}
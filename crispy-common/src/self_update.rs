@@ -0,0 +1,139 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Firmware self-update: the application writes the inactive bank itself,
+//! as the sample firmware's `update` and `apply` console commands do.
+//!
+//! The steps, in the order that keeps a failure harmless:
+//!
+//! 1. [`prepare`] picks the inactive bank, records it as empty in BootData
+//!    so a rollback can never pick a half-written image, and erases it
+//! 2. the image goes in through a [`BankWriter`], from any transport in
+//!    slices of any size; `finish(None)` returns size and CRC32 without
+//!    recording anything
+//! 3. [`stage`] compares that CRC with the one the sender announced, and only
+//!    then records size, CRC and version for the bank; it reads the bank
+//!    back with [`verify_bank`] before calling the update staged. The active
+//!    bank is not changed, so a reboot now still runs the current firmware
+//! 4. [`apply`] makes the staged bank active (unconfirmed, like an upload
+//!    through the bootloader) after checking it once more; the caller then
+//!    reboots, and the new firmware confirms itself or is rolled back
+//!
+//! ```ignore
+//! let bank = self_update::prepare(&mut rom_flash, size)?;
+//! let mut writer = BankWriter::new(&mut rom_flash, bank);
+//! while writer.size() < size {
+//!     writer.write(link.next_chunk()?)?;
+//! }
+//! let (written, crc) = writer.finish(None)?;
+//! let staged = self_update::stage(&mut rom_flash, bank, written, crc, expected_crc)?;
+//! // later, on request
+//! self_update::apply(&mut rom_flash, &staged)?;
+//! flash::reboot();
+//! ```
+//!
+//! [`BankWriter`]: crate::flash::BankWriter
+
+use crate::flash::{
+    self, inactive_bank, read_boot_data, update_bank_metadata, verify_bank, BankVerify, FlashError,
+};
+use crate::flash_ops::FlashOps;
+use crate::version::{self, FwVersion, VERSION_BLOCK_OFFSET, VERSION_BLOCK_SIZE};
+
+/// Version recorded for an image without a version block, as `crispy-upload`
+/// records one.
+pub const DEFAULT_VERSION: FwVersion = FwVersion::new(0, 0, 1);
+
+/// An image written to the inactive bank, checked and recorded in BootData
+/// but not active.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Staged {
+    pub bank: u8,
+    pub size: u32,
+    pub crc: u32,
+    /// From the image's version block, else [`DEFAULT_VERSION`].
+    pub version: FwVersion,
+}
+
+/// Why a self-update stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateError {
+    /// An image of 0 bytes.
+    Empty,
+    /// An image larger than a bank.
+    TooLarge(u32),
+    /// Reading, writing or checking flash failed.
+    Flash(FlashError),
+    /// The image received is not the one announced.
+    CrcMismatch { expected: u32, computed: u32 },
+}
+
+impl From<FlashError> for UpdateError {
+    fn from(err: FlashError) -> Self {
+        UpdateError::Flash(err)
+    }
+}
+
+/// Get the inactive bank ready for an image of `size` bytes and return it.
+///
+/// BootData must be valid, or there is no telling which bank is running.
+/// The bank is recorded as empty before it is erased.
+pub fn prepare(flash: &mut impl FlashOps, size: u32) -> Result<u8, UpdateError> {
+    if size == 0 {
+        return Err(UpdateError::Empty);
+    }
+    if size > flash.layout().bank_size {
+        return Err(UpdateError::TooLarge(size));
+    }
+    read_boot_data(flash).map_err(FlashError::from)?;
+
+    let bank = inactive_bank(flash);
+    update_bank_metadata(flash, bank, 0, 0, FwVersion::from_raw(0))?;
+    flash::erase_bank(flash, bank)?;
+    Ok(bank)
+}
+
+/// Record the `size` bytes with CRC32 `crc` just written to `bank`, if
+/// `crc` is the `expected` one, and check the bank against the record.
+pub fn stage(
+    flash: &mut impl FlashOps,
+    bank: u8,
+    size: u32,
+    crc: u32,
+    expected: u32,
+) -> Result<Staged, UpdateError> {
+    if crc != expected {
+        return Err(UpdateError::CrcMismatch {
+            expected,
+            computed: crc,
+        });
+    }
+
+    let version = image_version(flash, bank, size);
+    update_bank_metadata(flash, bank, size, crc, version)?;
+    match verify_bank(flash, bank) {
+        BankVerify::Ok { .. } => Ok(Staged {
+            bank,
+            size,
+            crc,
+            version,
+        }),
+        failed => Err(FlashError::Bank(failed).into()),
+    }
+}
+
+/// Make the staged bank active for the next boot; the caller reboots.
+pub fn apply(flash: &mut impl FlashOps, staged: &Staged) -> Result<(), FlashError> {
+    flash::set_active_bank(flash, staged.bank)
+}
+
+/// The version in the version block of the image in `bank`.
+fn image_version(flash: &impl FlashOps, bank: u8, size: u32) -> FwVersion {
+    const HEAD: usize = VERSION_BLOCK_OFFSET + VERSION_BLOCK_SIZE;
+    if (size as usize) < HEAD {
+        return DEFAULT_VERSION;
+    }
+    let mut head = [0u8; HEAD];
+    flash.read(flash::bank_base(flash.layout(), bank), &mut head);
+    version::embedded_version(&head).unwrap_or(DEFAULT_VERSION)
+}
@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Outgoing frame queue, independent of the USB stack.
+//!
+//! Messages are COBS-framed with postcard into a fixed buffer and drained
+//! by the transport in whatever sized pieces the endpoint accepts. Keeping
+//! this logic free of `usbd` types lets it be tested on the host.
+
+use serde::Serialize;
+
+/// Fixed-capacity queue of encoded frames waiting to be written.
+pub struct TxQueue<const N: usize> {
+    buf: [u8; N],
+    start: usize,
+    end: usize,
+}
+
+impl<const N: usize> TxQueue<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0u8; N],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Encode `msg` as a COBS frame and append it to the queue.
+    ///
+    /// Returns false (leaving the queue untouched) if the frame does not fit.
+    pub fn push<T: Serialize>(&mut self, msg: &T) -> bool {
+        self.compact();
+
+        match postcard::to_slice_cobs(msg, &mut self.buf[self.end..]) {
+            Ok(encoded) => {
+                self.end += encoded.len();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Append already-encoded bytes, e.g. a piece of a streamed frame.
+    ///
+    /// Returns false (leaving the queue untouched) if they do not fit.
+    pub fn push_raw(&mut self, bytes: &[u8]) -> bool {
+        self.compact();
+
+        let Some(dest) = self.buf.get_mut(self.end..self.end + bytes.len()) else {
+            return false;
+        };
+        dest.copy_from_slice(bytes);
+        self.end += bytes.len();
+        true
+    }
+
+    /// Bytes not yet handed to the endpoint.
+    pub fn pending(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    /// Mark `n` pending bytes as written.
+    pub fn consume(&mut self, n: usize) {
+        self.start = (self.start + n).min(self.end);
+        if self.start == self.end {
+            self.clear();
+        }
+    }
+
+    /// Drop everything still queued.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.end = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Reclaim the space already written out.
+    fn compact(&mut self) {
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+    }
+}
+
+impl<const N: usize> Default for TxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
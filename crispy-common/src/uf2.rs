@@ -0,0 +1,200 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! UF2 input for the host tools (`std` only).
+//!
+//! RP2040 builds usually end in a UF2 file: 512-byte blocks, each carrying
+//! up to 476 bytes of payload and the address it belongs at. The bootloader
+//! takes raw images, so [`parse`] turns a UF2 file back into the contiguous
+//! image it describes, checking the family ID on the way. Blocks may come in
+//! any order, but together they must cover one range without gaps.
+//!
+//! Block layout (little-endian):
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | first magic, `0x0A324655` |
+//! | 4 | 4 | second magic, `0x9E5D5157` |
+//! | 8 | 4 | flags |
+//! | 12 | 4 | target address |
+//! | 16 | 4 | payload size |
+//! | 20 | 4 | block number |
+//! | 24 | 4 | number of blocks |
+//! | 28 | 4 | family ID (with [`UF2_FLAG_FAMILY_ID`]) |
+//! | 32 | 476 | payload |
+//! | 508 | 4 | final magic, `0x0AB16F30` |
+
+use alloc::vec::Vec;
+
+use crate::protocol::{FLASH_BASE, FLASH_SIZE};
+use crate::vector_table::FW_RAM_WINDOW;
+
+/// Size of one UF2 block.
+pub const UF2_BLOCK_SIZE: usize = 512;
+/// Largest payload a block can carry.
+pub const UF2_MAX_PAYLOAD: u32 = 476;
+pub const UF2_MAGIC_START0: u32 = 0x0A32_4655;
+pub const UF2_MAGIC_START1: u32 = 0x9E5D_5157;
+pub const UF2_MAGIC_END: u32 = 0x0AB1_6F30;
+/// The block is not meant for main flash (comments, other partitions);
+/// it is skipped.
+pub const UF2_FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// The family ID field is set.
+pub const UF2_FLAG_FAMILY_ID: u32 = 0x0000_2000;
+/// Family ID of RP2040 images.
+pub const RP2040_FAMILY_ID: u32 = 0xE48B_FF56;
+
+const PAYLOAD_OFFSET: usize = 32;
+
+/// One UF2 block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Uf2Block<'a> {
+    pub flags: u32,
+    pub target_addr: u32,
+    pub block_no: u32,
+    pub num_blocks: u32,
+    /// Family ID, when [`UF2_FLAG_FAMILY_ID`] is set.
+    pub family_id: Option<u32>,
+    pub payload: &'a [u8],
+}
+
+/// A UF2 file reassembled into a raw image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Uf2Image {
+    /// Address of the first byte of `data`.
+    pub base: u32,
+    /// Family ID of the blocks, if they carry one.
+    pub family_id: Option<u32>,
+    pub data: Vec<u8>,
+}
+
+/// Why a UF2 file was rejected. `block` is the index of the block in the
+/// file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Uf2Error {
+    /// The file is not a whole number of blocks.
+    Length(usize),
+    /// A block's start or end magic is wrong.
+    BadMagic { block: usize },
+    /// A block claims more than [`UF2_MAX_PAYLOAD`] bytes.
+    PayloadTooLarge { block: usize, size: u32 },
+    /// A block is for another chip family.
+    WrongFamily { block: usize, family_id: u32 },
+    /// Block numbers are out of range or repeated, or a block's count is
+    /// not the number of blocks in the file.
+    BlockNumbers,
+    /// The payload skips or overlaps bytes before `found`, where `expected`
+    /// was next.
+    NotContiguous { expected: u32, found: u32 },
+    /// The image is outside flash and the firmware RAM window.
+    BadAddress(u32),
+    /// No block for main flash.
+    Empty,
+}
+
+/// Whether `file` starts with a UF2 block.
+pub fn is_uf2(file: &[u8]) -> bool {
+    file.len() >= UF2_BLOCK_SIZE
+        && word(file, 0) == UF2_MAGIC_START0
+        && word(file, 4) == UF2_MAGIC_START1
+}
+
+fn word(raw: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(raw[at..at + 4].try_into().unwrap())
+}
+
+impl<'a> Uf2Block<'a> {
+    /// Decode block number `index` of a file, checking magic and size.
+    pub fn parse(raw: &'a [u8; UF2_BLOCK_SIZE], index: usize) -> Result<Self, Uf2Error> {
+        if word(raw, 0) != UF2_MAGIC_START0
+            || word(raw, 4) != UF2_MAGIC_START1
+            || word(raw, UF2_BLOCK_SIZE - 4) != UF2_MAGIC_END
+        {
+            return Err(Uf2Error::BadMagic { block: index });
+        }
+        let size = word(raw, 16);
+        if size > UF2_MAX_PAYLOAD {
+            return Err(Uf2Error::PayloadTooLarge { block: index, size });
+        }
+
+        let flags = word(raw, 8);
+        Ok(Self {
+            flags,
+            target_addr: word(raw, 12),
+            block_no: word(raw, 20),
+            num_blocks: word(raw, 24),
+            family_id: (flags & UF2_FLAG_FAMILY_ID != 0).then(|| word(raw, 28)),
+            payload: &raw[PAYLOAD_OFFSET..PAYLOAD_OFFSET + size as usize],
+        })
+    }
+}
+
+/// Reassemble the image in a UF2 file.
+///
+/// With `family` set, blocks carrying another family ID are rejected;
+/// blocks without one are accepted either way.
+pub fn parse(file: &[u8], family: Option<u32>) -> Result<Uf2Image, Uf2Error> {
+    if file.is_empty() || !file.len().is_multiple_of(UF2_BLOCK_SIZE) {
+        return Err(Uf2Error::Length(file.len()));
+    }
+
+    let count = file.len() / UF2_BLOCK_SIZE;
+    let mut seen = alloc::vec![false; count];
+    let mut blocks = Vec::with_capacity(count);
+    for (index, raw) in file.chunks_exact(UF2_BLOCK_SIZE).enumerate() {
+        let block = Uf2Block::parse(raw.try_into().unwrap(), index)?;
+
+        // Block numbers 0..count, each once, whatever order they come in
+        match seen.get_mut(block.block_no as usize) {
+            Some(slot) if block.num_blocks as usize == count && !*slot => *slot = true,
+            _ => return Err(Uf2Error::BlockNumbers),
+        }
+
+        if block.flags & UF2_FLAG_NOT_MAIN_FLASH != 0 {
+            continue;
+        }
+        if let (Some(expected), Some(found)) = (family, block.family_id) {
+            if found != expected {
+                return Err(Uf2Error::WrongFamily {
+                    block: index,
+                    family_id: found,
+                });
+            }
+        }
+        blocks.push(block);
+    }
+    let family_id = blocks.first().ok_or(Uf2Error::Empty)?.family_id;
+
+    blocks.sort_by_key(|b| b.target_addr);
+    let base = blocks[0].target_addr;
+    let mut data = Vec::new();
+    for block in &blocks {
+        let expected = base + data.len() as u32;
+        if block.target_addr != expected {
+            return Err(Uf2Error::NotContiguous {
+                expected,
+                found: block.target_addr,
+            });
+        }
+        data.extend_from_slice(block.payload);
+    }
+
+    if !in_flash_or_fw_ram(base, data.len() as u64) {
+        return Err(Uf2Error::BadAddress(base));
+    }
+
+    Ok(Uf2Image {
+        base,
+        family_id,
+        data,
+    })
+}
+
+/// Whether `len` bytes at `base` lie in flash or the firmware RAM window,
+/// the places an image read from a file can be for.
+pub(crate) fn in_flash_or_fw_ram(base: u32, len: u64) -> bool {
+    let end = base as u64 + len;
+    let in_flash = base >= FLASH_BASE && end <= FLASH_BASE as u64 + FLASH_SIZE as u64;
+    let in_ram = FW_RAM_WINDOW.contains(base) && end <= FW_RAM_WINDOW.end as u64;
+    in_flash || in_ram
+}
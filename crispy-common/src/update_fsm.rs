@@ -0,0 +1,803 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Firmware update state machine - protocol policy without hardware dependencies.
+//!
+//! Decides how each update command changes the state, which offsets and sizes
+//! are acceptable and when BootData is committed. Flash goes through
+//! [`FlashOps`] and replies through [`ResponseSink`], so whole command
+//! sequences can be driven on the host against a
+//! [`FlashSim`](crate::flash_sim::FlashSim).
+//!
+//! `GetStatus`, `GetFlashInfo`, `GetDeviceInfo` and `Reboot` depend on the
+//! transport and the chip, so the platform answers them itself;
+//! [`handle_command`] rejects them with `BadCommand`.
+
+use crate::crc32::FLASH_CHUNK_SIZE;
+use crate::flash::{
+    bank_base, blank_check, compute_crc32_with, read_boot_data, read_provision, verify_bank_image,
+    write_boot_data, write_provision, write_to_bank, BankVerify, FlashError,
+};
+use crate::flash_ops::FlashOps;
+use crate::image::{self, ImageError, ImageHeader, IMAGE_HEADER_SIZE};
+use crate::protocol::{
+    flash_data_prefix, AckStatus, BootData, BootState, Command, Response, BOOT_DATA_SIZE,
+    FLASH_BASE, FLASH_PAGE_SIZE, MAX_DATA_BLOCK_SIZE, MAX_READ_SIZE,
+};
+use crate::provision::{Provision, PROVISION_SIZE};
+use crate::version::FwVersion;
+
+/// Where the state machine sends its replies.
+pub trait ResponseSink {
+    /// Send a response to the host.
+    fn send(&mut self, response: &Response);
+
+    /// Write a human-readable line to the console, if there is one.
+    fn log(&mut self, _line: &str) {}
+
+    /// Push out pending output before flash operations that keep interrupts
+    /// off.
+    fn flush(&mut self) {}
+
+    /// Called between sectors and chunks of long flash operations, so the
+    /// platform can poll USB and feed its watchdog.
+    fn keep_alive(&mut self) {}
+
+    /// Called during CRC verification and bank erases with the number of
+    /// bytes hashed or erased so far.
+    fn progress(&mut self, _done: u32, _total: u32) {
+        self.keep_alive();
+    }
+
+    /// Send a response given as its postcard encoding, produced while it is
+    /// sent so flash contents need not be staged in RAM.
+    ///
+    /// The default collects the bytes and decodes them for
+    /// [`send`](Self::send); transports override it to stream the frame.
+    fn send_encoded(&mut self, payload: &mut dyn Iterator<Item = u8>) {
+        let mut buf = [0u8; MAX_READ_SIZE + 16];
+        let mut len = 0;
+        for (slot, byte) in buf.iter_mut().zip(payload) {
+            *slot = byte;
+            len += 1;
+        }
+        if let Ok(response) = postcard::from_bytes::<Response>(&buf[..len]) {
+            self.send(&response);
+        }
+    }
+}
+
+/// Update state machine states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateState {
+    /// Waiting for a new update to start.
+    Idle,
+    /// Actively receiving firmware data.
+    Receiving {
+        bank: u8,
+        bank_addr: u32,
+        expected_size: u32,
+        expected_crc: u32,
+        version: FwVersion,
+        /// End of the last block written. Blocks come in order, so
+        /// everything before it is written or, in a sparse upload, erased.
+        bytes_received: u32,
+        /// Blocks may leave gaps (`StartUpdate::sparse`).
+        sparse: bool,
+    },
+}
+
+impl UpdateState {
+    /// The state as reported in `Response::Status`.
+    pub fn boot_state(&self) -> BootState {
+        match self {
+            UpdateState::Idle => BootState::UpdateMode,
+            UpdateState::Receiving { .. } => BootState::Receiving,
+        }
+    }
+
+    pub fn is_receiving(&self) -> bool {
+        matches!(self, UpdateState::Receiving { .. })
+    }
+}
+
+/// Validate a command, dispatch it to its handler and return the next state.
+///
+/// Commands that fail [`Command::validate_for`] the flash layout are answered with the error's
+/// status and leave the state unchanged.
+pub fn handle_command<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    cmd: Command,
+) -> UpdateState {
+    if let Err(err) = cmd.validate_for(flash.layout()) {
+        sink.send(&Response::Ack(err.status()));
+        return state;
+    }
+
+    match cmd {
+        Command::StartUpdate {
+            bank,
+            size,
+            crc32,
+            version,
+            sparse,
+        } => handle_start_update(flash, sink, state, bank, size, crc32, version, sparse),
+        Command::DataBlock { offset, data } => handle_data_block(flash, sink, state, offset, &data),
+        Command::FinishUpdate => handle_finish_update(flash, sink, state),
+        Command::SetActiveBank { bank } => handle_set_active_bank(flash, sink, state, bank),
+        Command::WipeAll => handle_wipe_all(flash, sink, state),
+        Command::BlankCheck { addr, length } => {
+            handle_blank_check(flash, sink, state, addr, length)
+        }
+        Command::SetBootAttempts { max_attempts } => {
+            handle_set_boot_attempts(flash, sink, state, max_attempts)
+        }
+        Command::CopyBank { from, to } => handle_copy_bank(flash, sink, state, from, to),
+        Command::GetBootData => handle_get_boot_data(flash, sink, state),
+        Command::ReadFlash { addr, length } => handle_read_flash(flash, sink, state, addr, length),
+        Command::SetVersion { bank, version } => {
+            handle_set_version(flash, sink, state, bank, version)
+        }
+        // Link timing only: nothing is kept and the transfer is not touched
+        Command::BenchData { .. } => {
+            sink.send(&Response::Ack(AckStatus::Ok));
+            state
+        }
+        Command::SetBootData { raw } => handle_set_boot_data(flash, sink, state, &raw),
+        Command::GetProvision => handle_get_provision(flash, sink, state),
+        Command::SetProvision {
+            board_id,
+            hw_revision,
+            serial,
+        } => handle_set_provision(flash, sink, state, board_id, hw_revision, &serial),
+        Command::GetStatus | Command::GetFlashInfo | Command::GetDeviceInfo | Command::Reboot => {
+            sink.send(&Response::Ack(AckStatus::BadCommand));
+            state
+        }
+    }
+}
+
+/// Handle BlankCheck command: scan a flash range, keeping the platform serviced.
+fn handle_blank_check<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    addr: u32,
+    length: u32,
+) -> UpdateState {
+    // Report how far the scan got after every chunk, as the CRC checks do
+    let mut done = 0;
+    let report = blank_check(flash, addr, length, || {
+        done = (done + FLASH_CHUNK_SIZE).min(length);
+        sink.progress(done, length);
+    });
+    sink.send(&Response::BlankCheckResult {
+        first_dirty: report.first_dirty,
+        dirty_bytes: report.dirty_bytes,
+    });
+    state
+}
+
+/// Handle GetBootData command: send the raw record and verify both banks.
+fn handle_get_boot_data<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+) -> UpdateState {
+    let mut raw = [0u8; BOOT_DATA_SIZE];
+    flash.read(flash.layout().boot_data, &mut raw);
+
+    let bd = read_valid_boot_data(flash);
+    let total = bd.size_a.saturating_add(bd.size_b);
+    let bank_a = verify_bank_image(flash, &bd, 0, |done| sink.progress(done, total));
+    let done_a = match bank_a {
+        BankVerify::Ok { .. } | BankVerify::CrcMismatch { .. } => bd.size_a,
+        _ => 0,
+    };
+    let bank_b = verify_bank_image(flash, &bd, 1, |done| sink.progress(done_a + done, total));
+
+    sink.send(&Response::BootData {
+        raw: heapless::Vec::from_slice(&raw).unwrap(),
+        banks: [bank_a, bank_b],
+    });
+    state
+}
+
+/// Handle ReadFlash command: stream the range back as `FlashData`.
+fn handle_read_flash<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    addr: u32,
+    length: u32,
+) -> UpdateState {
+    const PIECE: u32 = 32;
+
+    let flash = &*flash;
+    let contents = (0..length).step_by(PIECE as usize).flat_map(move |offset| {
+        let mut piece = [0u8; PIECE as usize];
+        let n = (length - offset).min(PIECE) as usize;
+        flash.read(addr + offset, &mut piece[..n]);
+        piece.into_iter().take(n)
+    });
+    sink.send_encoded(&mut flash_data_prefix(addr, length).into_iter().chain(contents));
+    state
+}
+
+/// Handle StartUpdate command: erase bank, begin receiving.
+#[allow(clippy::too_many_arguments)]
+fn handle_start_update<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    bank: u8,
+    size: u32,
+    crc32: u32,
+    version: FwVersion,
+    sparse: bool,
+) -> UpdateState {
+    // Must be in Idle state
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let bank_addr = bank_base(flash.layout(), bank);
+
+    // Erase the bank (rounded up to sector boundary)
+    let erase_size = size.div_ceil(F::SECTOR_SIZE) * F::SECTOR_SIZE;
+    if let Err(fault) = erase_verified(flash, sink, bank_addr, erase_size) {
+        send_flash_error(sink, fault);
+        return UpdateState::Idle;
+    }
+
+    sink.log("Update started, bank erased");
+    sink.send(&Response::Ack(AckStatus::Ok));
+
+    UpdateState::Receiving {
+        bank,
+        bank_addr,
+        expected_size: size,
+        expected_crc: crc32,
+        version,
+        bytes_received: 0,
+        sparse,
+    }
+}
+
+/// Handle DataBlock command: validate offset, program flash.
+fn handle_data_block<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    mut state: UpdateState,
+    offset: u32,
+    data: &[u8],
+) -> UpdateState {
+    let UpdateState::Receiving {
+        bank,
+        ref mut bytes_received,
+        expected_size,
+        sparse,
+        ..
+    } = state
+    else {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    };
+
+    // Validate the offset: the next byte, or in a sparse upload any later
+    // page, so nothing is programmed twice
+    let in_order = if sparse {
+        offset >= *bytes_received && offset.is_multiple_of(FLASH_PAGE_SIZE)
+    } else {
+        offset == *bytes_received
+    };
+    if !in_order {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    }
+
+    // Validate data doesn't exceed the expected size
+    let data_len = data.len() as u32;
+    let end = offset + data_len;
+    if end > expected_size {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    }
+
+    // Blocks are programmed whole pages at a time, so only the last one may
+    // end part way through a page
+    let is_last = end == expected_size;
+    if !is_last && !data_len.is_multiple_of(FLASH_PAGE_SIZE) {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    }
+
+    // Pad data to 256-byte page boundary for flash programming
+    let mut page_buf = [0xFFu8; MAX_DATA_BLOCK_SIZE + FLASH_PAGE_SIZE as usize];
+    page_buf[..data.len()].copy_from_slice(data);
+    let padded_len = data.len().div_ceil(FLASH_PAGE_SIZE as usize) * FLASH_PAGE_SIZE as usize;
+
+    if let Err(err) = write_to_bank(flash, bank, offset, &page_buf[..padded_len]) {
+        send_flash_error(sink, err.offset().unwrap_or(offset));
+        return UpdateState::Idle;
+    }
+
+    *bytes_received = end;
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle FinishUpdate command: verify CRC, update BootData.
+fn handle_finish_update<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+) -> UpdateState {
+    let UpdateState::Receiving {
+        bank,
+        bank_addr,
+        expected_size,
+        expected_crc,
+        version,
+        bytes_received,
+        sparse,
+    } = state
+    else {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    };
+
+    // Verify all data was received; a sparse upload may leave erased pages
+    // at the end, and the CRC below covers them
+    let complete = if sparse {
+        bytes_received <= expected_size
+    } else {
+        bytes_received == expected_size
+    };
+    if !complete {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    }
+
+    // Verify CRC
+    let actual_crc = compute_crc32_with(flash, bank_addr, expected_size, |done| {
+        sink.progress(done, expected_size)
+    });
+    if actual_crc != expected_crc {
+        sink.log("Update failed: CRC mismatch");
+        sink.send(&Response::Ack(AckStatus::CrcError));
+        return UpdateState::Idle;
+    }
+
+    if check_image_header(flash, sink, bank_addr, expected_size).is_err() {
+        sink.log("Update failed: image header does not match the firmware");
+        sink.send(&Response::Ack(AckStatus::BadImage));
+        return UpdateState::Idle;
+    }
+
+    // Update BootData
+    let mut bd = read_valid_boot_data(flash);
+    bd.active_bank = bank;
+    bd.confirmed = 0; // unconfirmed until firmware confirms
+    bd.boot_attempts = 0;
+
+    if bank == 0 {
+        bd.version_a = version;
+        bd.crc_a = expected_crc;
+        bd.size_a = expected_size;
+    } else {
+        bd.version_b = version;
+        bd.crc_b = expected_crc;
+        bd.size_b = expected_size;
+    }
+
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return UpdateState::Idle;
+    }
+
+    sink.log("Update complete");
+    sink.send(&Response::Ack(AckStatus::Ok));
+    UpdateState::Idle
+}
+
+/// Handle SetActiveBank command: change the active bank for next boot.
+fn handle_set_active_bank<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    bank: u8,
+) -> UpdateState {
+    // Must be in Idle state
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let mut bd = read_valid_boot_data(flash);
+
+    // The target bank must hold firmware matching its stored CRC
+    let size = if bank == 0 { bd.size_a } else { bd.size_b };
+    match verify_bank_image(flash, &bd, bank, |done| sink.progress(done, size)) {
+        BankVerify::Ok { .. } => {}
+        BankVerify::CrcMismatch { .. } => {
+            sink.send(&Response::Ack(AckStatus::CrcError));
+            return state;
+        }
+        _ => {
+            sink.send(&Response::Ack(AckStatus::BankInvalid));
+            return state;
+        }
+    }
+
+    // Update BootData
+    bd.active_bank = bank;
+    bd.confirmed = 0; // unconfirmed until firmware confirms
+    bd.boot_attempts = 0;
+
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return state;
+    }
+
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle WipeAll command: reset BootData to defaults.
+fn handle_wipe_all<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    if commit_boot_data(flash, sink, &BootData::default_new()).is_err() {
+        return state;
+    }
+
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle SetBootAttempts command: store the rollback threshold.
+fn handle_set_boot_attempts<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    max_attempts: u8,
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let mut bd = read_valid_boot_data(flash);
+    bd.max_boot_attempts = max_attempts;
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return state;
+    }
+
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle SetVersion command: rewrite the recorded version of a bank that
+/// holds firmware, and nothing else.
+fn handle_set_version<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    bank: u8,
+    version: FwVersion,
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let mut bd = read_valid_boot_data(flash);
+    let (size, stored) = if bank == 0 {
+        (bd.size_a, &mut bd.version_a)
+    } else {
+        (bd.size_b, &mut bd.version_b)
+    };
+    if size == 0 {
+        sink.send(&Response::Ack(AckStatus::BankInvalid));
+        return state;
+    }
+    *stored = version;
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return state;
+    }
+
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle SetBootData command: store a whole BootData record, as a restore
+/// does.
+///
+/// The record must decode and pass [`BootData::validate_extended_for`] the
+/// flash layout, and every bank it gives a size must hold firmware matching
+/// its CRC, so the bootloader is never pointed at an image that is not
+/// there. Progress spans both checks.
+fn handle_set_boot_data<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    raw: &[u8],
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let record = raw
+        .try_into()
+        .ok()
+        .and_then(|raw| BootData::from_bytes(raw).ok());
+    let Some(bd) = record.filter(|bd| bd.validate_extended_for(flash.layout()).is_ok()) else {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    };
+
+    let total = bd.size_a.saturating_add(bd.size_b);
+    for bank in [0, 1] {
+        let (size, done_before) = if bank == 0 {
+            (bd.size_a, 0)
+        } else {
+            (bd.size_b, bd.size_a)
+        };
+        if size == 0 {
+            continue;
+        }
+        let verify = verify_bank_image(flash, &bd, bank, |done| {
+            sink.progress(done_before + done, total)
+        });
+        match verify {
+            BankVerify::Ok { .. } => {}
+            BankVerify::CrcMismatch { .. } => {
+                sink.send(&Response::Ack(AckStatus::CrcError));
+                return state;
+            }
+            _ => {
+                sink.send(&Response::Ack(AckStatus::BankInvalid));
+                return state;
+            }
+        }
+    }
+
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return state;
+    }
+
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle GetProvision command: send the provisioning record as stored.
+fn handle_get_provision<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+) -> UpdateState {
+    let mut raw = [0u8; PROVISION_SIZE];
+    flash.read(flash.layout().provision(), &mut raw);
+    sink.send(&Response::Provision {
+        raw: heapless::Vec::from_slice(&raw).unwrap(),
+    });
+    state
+}
+
+/// Handle SetProvision command: store the unit's identity, unless it is
+/// already the stored one.
+///
+/// Whether an existing record may be replaced is the host's call; the
+/// record itself was checked by [`Command::validate_for`].
+fn handle_set_provision<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    board_id: u16,
+    hw_revision: u8,
+    serial: &[u8],
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+    let Ok(record) = Provision::new(board_id, hw_revision, serial) else {
+        sink.send(&Response::Ack(AckStatus::BadCommand));
+        return state;
+    };
+
+    if read_provision(flash) != Ok(record) {
+        sink.flush();
+        if write_provision(flash, &record).is_err() {
+            sink.log("Provisioning record write failed");
+            sink.send(&Response::Ack(AckStatus::FlashError));
+            return state;
+        }
+        sink.log("Provisioned");
+    }
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+/// Handle CopyBank command: duplicate a verified bank and its metadata.
+///
+/// The source is checked against its stored CRC, then copied a page at a
+/// time through RAM, erasing each destination sector as the copy reaches
+/// it. The copy is read back against the same CRC before its size, CRC and
+/// version are recorded. Progress spans the three passes as `3 * size`.
+fn handle_copy_bank<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    state: UpdateState,
+    from: u8,
+    to: u8,
+) -> UpdateState {
+    if state.is_receiving() {
+        sink.send(&Response::Ack(AckStatus::BadState));
+        return state;
+    }
+
+    let mut bd = read_valid_boot_data(flash);
+    let (size, crc, version) = if from == 0 {
+        (bd.size_a, bd.crc_a, bd.version_a)
+    } else {
+        (bd.size_b, bd.crc_b, bd.version_b)
+    };
+    let total = size.saturating_mul(3);
+
+    match verify_bank_image(flash, &bd, from, |done| sink.progress(done, total)) {
+        BankVerify::Ok { .. } => {}
+        BankVerify::CrcMismatch { .. } => {
+            sink.send(&Response::Ack(AckStatus::CrcError));
+            return state;
+        }
+        _ => {
+            sink.send(&Response::Ack(AckStatus::BankInvalid));
+            return state;
+        }
+    }
+
+    let layout = *flash.layout();
+    let (src, dst) = (bank_base(&layout, from), bank_base(&layout, to));
+    let mut page = [0u8; FLASH_PAGE_SIZE as usize];
+    sink.flush();
+    for offset in (0..size).step_by(page.len()) {
+        if offset.is_multiple_of(F::SECTOR_SIZE) {
+            flash.erase(dst - FLASH_BASE + offset, F::SECTOR_SIZE);
+            sink.progress(size + offset, total);
+        }
+        flash.read(src + offset, &mut page);
+        if let Err(err) = write_to_bank(flash, to, offset, &page) {
+            forget_bank(flash, &mut bd, to);
+            send_flash_error(sink, err.offset().unwrap_or(offset));
+            return state;
+        }
+    }
+
+    let copied = compute_crc32_with(flash, dst, size, |done| {
+        sink.progress(2 * size + done, total)
+    });
+    if copied != crc {
+        forget_bank(flash, &mut bd, to);
+        sink.log("Bank copy failed: CRC mismatch");
+        sink.send(&Response::Ack(AckStatus::CrcError));
+        return state;
+    }
+
+    if to == 0 {
+        (bd.size_a, bd.crc_a, bd.version_a) = (size, crc, version);
+    } else {
+        (bd.size_b, bd.crc_b, bd.version_b) = (size, crc, version);
+    }
+    if commit_boot_data(flash, sink, &bd).is_err() {
+        return state;
+    }
+
+    sink.log("Bank copied");
+    sink.send(&Response::Ack(AckStatus::Ok));
+    state
+}
+
+// --- Internal helpers ---
+
+/// Write BootData, answering `FlashError` if it does not read back.
+fn commit_boot_data(
+    flash: &mut impl FlashOps,
+    sink: &mut impl ResponseSink,
+    bd: &BootData,
+) -> Result<(), FlashError> {
+    write_boot_data(flash, bd).inspect_err(|_| {
+        sink.log("BootData write failed");
+        sink.send(&Response::Ack(AckStatus::FlashError));
+    })
+}
+
+/// Clear the size, CRC and version of a bank that was partly overwritten, so
+/// its old metadata no longer vouches for it. Best effort: the caller is
+/// reporting a failure already.
+fn forget_bank(flash: &mut impl FlashOps, bd: &mut BootData, bank: u8) {
+    let cleared = (0, 0, FwVersion::from_raw(0));
+    if bank == 0 {
+        (bd.size_a, bd.crc_a, bd.version_a) = cleared;
+    } else {
+        (bd.size_b, bd.crc_b, bd.version_b) = cleared;
+    }
+    let _ = write_boot_data(flash, bd);
+}
+
+/// Read BootData, falling back to defaults if the stored record is invalid.
+fn read_valid_boot_data(flash: &impl FlashOps) -> BootData {
+    read_boot_data(flash).unwrap_or_else(|_| BootData::default_new())
+}
+
+/// Check the header of a headered image against the firmware before it.
+/// Images without a header pass.
+fn check_image_header<F: FlashOps, S: ResponseSink>(
+    flash: &F,
+    sink: &mut S,
+    bank_addr: u32,
+    size: u32,
+) -> Result<(), ImageError> {
+    let Some(payload_len) = size.checked_sub(IMAGE_HEADER_SIZE as u32) else {
+        return Ok(());
+    };
+    let mut tail = [0u8; IMAGE_HEADER_SIZE];
+    flash.read(bank_addr + payload_len, &mut tail);
+    if !image::has_header(size, &tail) {
+        return Ok(());
+    }
+
+    let header = ImageHeader::from_bytes(&tail)?;
+    let crc = compute_crc32_with(flash, bank_addr, payload_len, |done| {
+        sink.progress(done, payload_len)
+    });
+    header.check_payload(payload_len, crc)
+}
+
+/// Erase a range sector by sector and confirm every byte reads back as 0xFF,
+/// retrying the erase once.
+/// Returns the offset of the first dirty byte from `addr` on error.
+fn erase_verified<F: FlashOps, S: ResponseSink>(
+    flash: &mut F,
+    sink: &mut S,
+    addr: u32,
+    size: u32,
+) -> Result<(), u32> {
+    let offset = addr - FLASH_BASE;
+    let mut first_dirty = None;
+
+    for _ in 0..2 {
+        sink.flush();
+        for sector in (0..size).step_by(F::SECTOR_SIZE as usize) {
+            flash.erase(offset + sector, F::SECTOR_SIZE);
+            sink.keep_alive();
+            sink.progress(sector + F::SECTOR_SIZE, size);
+        }
+
+        first_dirty = blank_check(flash, addr, size, || sink.keep_alive()).first_dirty;
+        if first_dirty.is_none() {
+            return Ok(());
+        }
+    }
+
+    first_dirty.map_or(Ok(()), Err)
+}
+
+/// Report a flash erase/program verification failure.
+fn send_flash_error(sink: &mut impl ResponseSink, offset: u32) {
+    sink.log("Flash error, update aborted");
+    sink.send(&Response::Nack {
+        status: AckStatus::FlashError,
+        offset,
+    });
+}
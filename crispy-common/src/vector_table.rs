@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! The vector table check the bootloader applies before running a bank.
+//!
+//! Firmware is copied to RAM and entered through the first two words of its
+//! bank: the initial stack pointer and the reset vector. Both must point
+//! into the firmware RAM window, and the reset vector must have the Thumb
+//! bit set, or the jump faults. The bootloader, BootData recovery and
+//! `crispy-upload inspect` all use [`VectorTable::check`].
+
+use core::fmt;
+
+/// Address range a bootable vector table must point into (inclusive).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RamWindow {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl RamWindow {
+    pub fn contains(&self, addr: u32) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+}
+
+/// The firmware RAM window of `linker_scripts/bootloader_rp2040.x`
+/// (`__fw_ram_start`..`__fw_ram_end`), for host tools. The bootloader reads
+/// the linker symbols instead.
+pub const FW_RAM_WINDOW: RamWindow = RamWindow {
+    start: 0x2000_0000,
+    end: 0x2004_2000,
+};
+
+/// The first two words of a firmware image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorTable {
+    pub initial_sp: u32,
+    pub reset_vector: u32,
+}
+
+/// Why a vector table would not be run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VectorTableError {
+    /// The initial stack pointer is outside the RAM window.
+    SpOutsideRam(u32),
+    /// The reset vector is outside the RAM window.
+    ResetOutsideRam(u32),
+    /// The reset vector lacks the Thumb bit, so jumping to it faults.
+    ResetNotThumb(u32),
+}
+
+impl VectorTable {
+    /// Read the table at `addr` through a word reader.
+    pub fn read(read_word: impl Fn(u32) -> u32, addr: u32) -> Self {
+        Self {
+            initial_sp: read_word(addr),
+            reset_vector: read_word(addr + 4),
+        }
+    }
+
+    /// Decode the first 8 bytes of an image (little-endian).
+    pub fn from_bytes(bytes: &[u8; 8]) -> Self {
+        Self {
+            initial_sp: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            reset_vector: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    /// Check the table against the RAM window the firmware runs in.
+    pub fn check(&self, ram: RamWindow) -> Result<(), VectorTableError> {
+        if !ram.contains(self.initial_sp) {
+            return Err(VectorTableError::SpOutsideRam(self.initial_sp));
+        }
+        if !ram.contains(self.reset_vector) {
+            return Err(VectorTableError::ResetOutsideRam(self.reset_vector));
+        }
+        if self.reset_vector & 1 == 0 {
+            return Err(VectorTableError::ResetNotThumb(self.reset_vector));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for VectorTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            VectorTableError::SpOutsideRam(sp) => {
+                write!(f, "initial SP 0x{:08x} is outside firmware RAM", sp)
+            }
+            VectorTableError::ResetOutsideRam(pc) => write!(
+                f,
+                "reset vector 0x{:08x} is outside firmware RAM (linked for XIP?)",
+                pc
+            ),
+            VectorTableError::ResetNotThumb(pc) => {
+                write!(f, "reset vector 0x{:08x} lacks the Thumb bit", pc)
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Firmware version as major.minor.patch packed into a `u32`.
+//!
+//! The packing is `major << 24 | minor << 16 | patch`, so comparing packed
+//! values orders versions correctly, and BootData, the image header and the
+//! wire format keep storing a plain `u32`. Versions written before this type
+//! existed were bare integers; they read back as `0.0.N`.
+//!
+//! Firmware records its own version with [`fw_version!`], which places a
+//! [`VersionBlock`] in the `.crispy_fw_version` link section. The firmware
+//! linker script puts that section at [`VERSION_BLOCK_OFFSET`], so the host
+//! finds the version in a raw binary as well as in the ELF file.
+
+use core::fmt;
+use core::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// A packed major.minor.patch firmware version.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+#[repr(transparent)]
+pub struct FwVersion(u32);
+
+impl FwVersion {
+    pub const fn new(major: u8, minor: u8, patch: u16) -> Self {
+        Self((major as u32) << 24 | (minor as u32) << 16 | patch as u32)
+    }
+
+    /// Reinterpret a stored or transmitted `u32`.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// The packed value as stored and transmitted.
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+
+    pub const fn major(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub const fn minor(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub const fn patch(self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Parse `"MAJOR.MINOR.PATCH"` in a `const`, for [`fw_version!`].
+    ///
+    /// Panics on anything else, which fails the build when evaluated at
+    /// compile time. Pre-release and build suffixes are not accepted.
+    pub const fn from_semver(s: &str) -> Self {
+        let bytes = s.as_bytes();
+        let mut parts = [0u32; 3];
+        let mut part = 0;
+        let mut digits = 0;
+        let mut i = 0;
+        while i < bytes.len() {
+            let b = bytes[i];
+            if b == b'.' && digits > 0 && part < 2 {
+                part += 1;
+                digits = 0;
+            } else if b.is_ascii_digit() && digits < 5 {
+                parts[part] = parts[part] * 10 + (b - b'0') as u32;
+                digits += 1;
+            } else {
+                panic!("firmware version must be MAJOR.MINOR.PATCH");
+            }
+            i += 1;
+        }
+        if part != 2 || digits == 0 || parts[0] > 255 || parts[1] > 255 || parts[2] > 65535 {
+            panic!("firmware version must be MAJOR.MINOR.PATCH (at most 255.255.65535)");
+        }
+        Self::new(parts[0] as u8, parts[1] as u8, parts[2] as u16)
+    }
+}
+
+/// Link section [`fw_version!`] places the [`VersionBlock`] in.
+pub const VERSION_SECTION: &str = ".crispy_fw_version";
+
+/// Offset of the version block in a firmware image: right after the
+/// largest RP2040 vector table (16 system and 32 interrupt entries).
+pub const VERSION_BLOCK_OFFSET: usize = 0xC0;
+
+/// Magic starting a version block, `CRFV`.
+pub const VERSION_BLOCK_MAGIC: u32 = u32::from_le_bytes(*b"CRFV");
+
+/// Size of an encoded version block.
+pub const VERSION_BLOCK_SIZE: usize = 8;
+
+/// The version a firmware image records about itself: a magic word and the
+/// packed version, both little-endian.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct VersionBlock {
+    magic: u32,
+    version: FwVersion,
+}
+
+impl VersionBlock {
+    pub const fn new(version: FwVersion) -> Self {
+        Self {
+            magic: VERSION_BLOCK_MAGIC,
+            version,
+        }
+    }
+
+    pub const fn version(&self) -> FwVersion {
+        self.version
+    }
+
+    pub fn to_bytes(&self) -> [u8; VERSION_BLOCK_SIZE] {
+        let mut raw = [0u8; VERSION_BLOCK_SIZE];
+        raw[0..4].copy_from_slice(&self.magic.to_le_bytes());
+        raw[4..8].copy_from_slice(&self.version.raw().to_le_bytes());
+        raw
+    }
+
+    /// Decode a block, or `None` if the magic is missing.
+    pub fn from_bytes(raw: &[u8; VERSION_BLOCK_SIZE]) -> Option<Self> {
+        let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+        (word(0) == VERSION_BLOCK_MAGIC).then(|| Self::new(FwVersion::from_raw(word(4))))
+    }
+}
+
+/// The version recorded in the version block of a raw image, if it has one.
+pub fn embedded_version(image: &[u8]) -> Option<FwVersion> {
+    let raw = image.get(VERSION_BLOCK_OFFSET..VERSION_BLOCK_OFFSET + VERSION_BLOCK_SIZE)?;
+    VersionBlock::from_bytes(raw.try_into().unwrap()).map(|block| block.version())
+}
+
+/// Record the firmware version in the image, where `crispy-upload` finds it.
+///
+/// Takes a `"MAJOR.MINOR.PATCH"` string, typically the crate version:
+///
+/// ```ignore
+/// crispy_common::fw_version!(env!("CARGO_PKG_VERSION"));
+/// ```
+///
+/// The block goes in the `.crispy_fw_version` section, which the firmware
+/// linker script keeps at [`VERSION_BLOCK_OFFSET`]. Use it once per
+/// firmware; a second use fails to link.
+#[macro_export]
+macro_rules! fw_version {
+    ($version:expr) => {
+        #[used]
+        #[no_mangle]
+        #[link_section = ".crispy_fw_version"]
+        pub static CRISPY_FW_VERSION_BLOCK: $crate::version::VersionBlock =
+            $crate::version::VersionBlock::new($crate::FwVersion::from_semver($version));
+    };
+}
+
+impl From<u32> for FwVersion {
+    fn from(raw: u32) -> Self {
+        Self::from_raw(raw)
+    }
+}
+
+impl From<FwVersion> for u32 {
+    fn from(version: FwVersion) -> Self {
+        version.raw()
+    }
+}
+
+impl fmt::Display for FwVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major(), self.minor(), self.patch())
+    }
+}
+
+/// A string that is neither `major.minor.patch` nor a plain integer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseVersionError;
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("expected MAJOR.MINOR.PATCH (at most 255.255.65535) or an integer")
+    }
+}
+
+impl core::error::Error for ParseVersionError {}
+
+impl FromStr for FwVersion {
+    type Err = ParseVersionError;
+
+    /// Parse `"1.2.3"`, or a plain integer taken as the packed value.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.');
+        let (Some(first), second, third) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(ParseVersionError);
+        };
+        if parts.next().is_some() {
+            return Err(ParseVersionError);
+        }
+
+        match (second, third) {
+            (None, None) => first.parse().map(Self).map_err(|_| ParseVersionError),
+            (Some(minor), Some(patch)) => Ok(Self::new(
+                first.parse().map_err(|_| ParseVersionError)?,
+                minor.parse().map_err(|_| ParseVersionError)?,
+                patch.parse().map_err(|_| ParseVersionError)?,
+            )),
+            _ => Err(ParseVersionError),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! WebUSB and Microsoft OS 2.0 descriptors for the vendor protocol interface.
+//!
+//! The WebUSB platform capability lets Chrome's `navigator.usb` see the
+//! device; the MS OS 2.0 descriptor set makes Windows bind WinUSB to the
+//! vendor interface without an INF. Both are plain byte arrays so they can
+//! be checked on the host and served from flash by the bootloader.
+
+/// `bRequest` used for WebUSB requests (GET_URL).
+pub const WEBUSB_VENDOR_CODE: u8 = 0x01;
+/// `bRequest` used to fetch the MS OS 2.0 descriptor set.
+pub const MS_OS_20_VENDOR_CODE: u8 = 0x02;
+/// `wIndex` of the MS OS 2.0 descriptor set request.
+pub const MS_OS_20_DESCRIPTOR_INDEX: u16 = 0x07;
+
+/// Device capability type for platform descriptors in the BOS.
+pub const CAPABILITY_PLATFORM: u8 = 0x05;
+
+/// Device interface GUID registered for the WinUSB interface.
+pub const DEVICE_INTERFACE_GUID: &str = "{8D3C2E5A-6F1B-4C7E-9A2D-5B4E1F0C7A93}";
+
+/// Windows 8.1 (0x06030000), the minimum for MS OS 2.0 descriptors.
+const WINDOWS_VERSION: u32 = 0x0603_0000;
+
+/// WebUSB platform capability UUID {3408b638-09a9-47a0-8bfd-a0768815b665}.
+const WEBUSB_UUID: [u8; 16] = [
+    0x38, 0xB6, 0x08, 0x34, 0xA9, 0x09, 0xA0, 0x47, 0x8B, 0xFD, 0xA0, 0x76, 0x88, 0x15, 0xB6, 0x65,
+];
+
+/// MS OS 2.0 platform capability UUID {D8DD60DF-4589-4CC7-9CD2-659D9E648A9F}.
+const MS_OS_20_UUID: [u8; 16] = [
+    0xDF, 0x60, 0xDD, 0xD8, 0x89, 0x45, 0xC7, 0x4C, 0x9C, 0xD2, 0x65, 0x9D, 0x9E, 0x64, 0x8A, 0x9F,
+];
+
+const PROPERTY_NAME: &str = "DeviceInterfaceGUIDs";
+// UTF-16LE with terminator; REG_MULTI_SZ data ends with a double terminator
+const PROPERTY_NAME_LEN: usize = (PROPERTY_NAME.len() + 1) * 2;
+const PROPERTY_DATA_LEN: usize = (DEVICE_INTERFACE_GUID.len() + 2) * 2;
+
+const SET_HEADER_LEN: usize = 10;
+const CONFIG_SUBSET_LEN: usize = 8;
+const FUNCTION_SUBSET_LEN: usize = 8;
+const COMPATIBLE_ID_LEN: usize = 20;
+const REGISTRY_PROPERTY_LEN: usize = 10 + PROPERTY_NAME_LEN + PROPERTY_DATA_LEN;
+
+/// Total length of the MS OS 2.0 descriptor set.
+pub const MS_OS_20_SET_LEN: usize = SET_HEADER_LEN
+    + CONFIG_SUBSET_LEN
+    + FUNCTION_SUBSET_LEN
+    + COMPATIBLE_ID_LEN
+    + REGISTRY_PROPERTY_LEN;
+
+/// WebUSB platform capability body (after bLength/bDescriptorType/bDevCapabilityType).
+/// No landing page is advertised (`iLandingPage` = 0).
+pub const WEBUSB_PLATFORM_CAPABILITY: [u8; 21] = {
+    let mut out = [0u8; 21];
+    // bReserved, PlatformCapabilityUUID
+    out = put_bytes(out, 1, &WEBUSB_UUID);
+    // bcdVersion 1.0, bVendorCode, iLandingPage
+    out = put_u16(out, 17, 0x0100);
+    out[19] = WEBUSB_VENDOR_CODE;
+    out[20] = 0;
+    out
+};
+
+/// MS OS 2.0 platform capability body (after bLength/bDescriptorType/bDevCapabilityType).
+pub const MS_OS_20_PLATFORM_CAPABILITY: [u8; 25] = {
+    let mut out = [0u8; 25];
+    out = put_bytes(out, 1, &MS_OS_20_UUID);
+    out = put_u32(out, 17, WINDOWS_VERSION);
+    out = put_u16(out, 21, MS_OS_20_SET_LEN as u16);
+    out[23] = MS_OS_20_VENDOR_CODE;
+    // bAltEnumCode: no alternate enumeration
+    out[24] = 0;
+    out
+};
+
+/// Build the MS OS 2.0 descriptor set binding WinUSB to `interface`.
+///
+/// A function subset is used because the bootloader is a composite device.
+pub const fn ms_os_20_descriptor_set(interface: u8) -> [u8; MS_OS_20_SET_LEN] {
+    let mut out = [0u8; MS_OS_20_SET_LEN];
+    let mut at = 0;
+
+    // Set header
+    out = put_u16(out, at, SET_HEADER_LEN as u16);
+    out = put_u16(out, at + 2, 0x00);
+    out = put_u32(out, at + 4, WINDOWS_VERSION);
+    out = put_u16(out, at + 8, MS_OS_20_SET_LEN as u16);
+    at += SET_HEADER_LEN;
+
+    // Configuration subset header (configuration index 0)
+    out = put_u16(out, at, CONFIG_SUBSET_LEN as u16);
+    out = put_u16(out, at + 2, 0x01);
+    out = put_u16(out, at + 6, (MS_OS_20_SET_LEN - SET_HEADER_LEN) as u16);
+    at += CONFIG_SUBSET_LEN;
+
+    // Function subset header
+    out = put_u16(out, at, FUNCTION_SUBSET_LEN as u16);
+    out = put_u16(out, at + 2, 0x02);
+    out[at + 4] = interface;
+    out = put_u16(
+        out,
+        at + 6,
+        (FUNCTION_SUBSET_LEN + COMPATIBLE_ID_LEN + REGISTRY_PROPERTY_LEN) as u16,
+    );
+    at += FUNCTION_SUBSET_LEN;
+
+    // Compatible ID "WINUSB", empty sub-compatible ID
+    out = put_u16(out, at, COMPATIBLE_ID_LEN as u16);
+    out = put_u16(out, at + 2, 0x03);
+    out = put_bytes(out, at + 4, b"WINUSB");
+    at += COMPATIBLE_ID_LEN;
+
+    // Registry property: DeviceInterfaceGUIDs (REG_MULTI_SZ)
+    out = put_u16(out, at, REGISTRY_PROPERTY_LEN as u16);
+    out = put_u16(out, at + 2, 0x04);
+    out = put_u16(out, at + 4, 0x0007);
+    out = put_u16(out, at + 6, PROPERTY_NAME_LEN as u16);
+    out = put_utf16(out, at + 8, PROPERTY_NAME);
+    at += 8 + PROPERTY_NAME_LEN;
+    out = put_u16(out, at, PROPERTY_DATA_LEN as u16);
+    out = put_utf16(out, at + 2, DEVICE_INTERFACE_GUID);
+
+    out
+}
+
+const fn put_u16<const N: usize>(mut out: [u8; N], at: usize, v: u16) -> [u8; N] {
+    out[at] = v as u8;
+    out[at + 1] = (v >> 8) as u8;
+    out
+}
+
+const fn put_u32<const N: usize>(mut out: [u8; N], at: usize, v: u32) -> [u8; N] {
+    out = put_u16(out, at, v as u16);
+    put_u16(out, at + 2, (v >> 16) as u16)
+}
+
+const fn put_bytes<const N: usize>(mut out: [u8; N], at: usize, bytes: &[u8]) -> [u8; N] {
+    let mut i = 0;
+    while i < bytes.len() {
+        out[at + i] = bytes[i];
+        i += 1;
+    }
+    out
+}
+
+/// Write ASCII `s` as UTF-16LE; the zero terminator(s) come from the zeroed buffer.
+const fn put_utf16<const N: usize>(mut out: [u8; N], at: usize, s: &str) -> [u8; N] {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        out[at + i * 2] = bytes[i];
+        i += 1;
+    }
+    out
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Volatile reads from memory-mapped (XIP) flash.
+//!
+//! XIP serves aligned 32-bit loads as cheaply as byte loads, so copying a
+//! word at a time is roughly four times faster. Only an unaligned head and
+//! tail fall back to byte loads; the result is identical to a byte-wise copy.
+
+/// Copy `buf.len()` bytes starting at `src` into `buf` using volatile loads.
+///
+/// # Safety
+/// `src..src + buf.len()` must be readable memory.
+pub unsafe fn read_volatile(src: *const u8, buf: &mut [u8]) {
+    let head = ((4 - (src as usize & 3)) & 3).min(buf.len());
+    let (head_buf, rest) = buf.split_at_mut(head);
+    for (i, byte) in head_buf.iter_mut().enumerate() {
+        *byte = src.add(i).read_volatile();
+    }
+
+    let src = src.add(head);
+    let mut words = rest.chunks_exact_mut(4);
+    let mut offset = 0;
+    for word in &mut words {
+        let value = (src.add(offset) as *const u32).read_volatile();
+        word.copy_from_slice(&value.to_ne_bytes());
+        offset += 4;
+    }
+
+    for (i, byte) in words.into_remainder().iter_mut().enumerate() {
+        *byte = src.add(offset + i).read_volatile();
+    }
+}
+
+/// Copy `buf.len()` bytes from an absolute address (e.g. in the XIP window).
+///
+/// # Safety
+/// `addr..addr + buf.len()` must be readable memory.
+pub unsafe fn read_at(addr: u32, buf: &mut [u8]) {
+    read_volatile(addr as usize as *const u8, buf)
+}
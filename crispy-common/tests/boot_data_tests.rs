@@ -3,7 +3,25 @@
 
 //! Unit tests for BootData structure and methods.
 
-use crispy_common::protocol::{BootData, BOOT_DATA_MAGIC, FW_A_ADDR, FW_B_ADDR};
+use crispy_common::crc32;
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{
+    BootData, BootDataError, BootDataIssue, BOOT_DATA_MAGIC, BOOT_DATA_SIZE, BOOT_DATA_VERSION,
+    BOOT_FLAG_RECONSTRUCTED, BOOT_FLAG_REPAIRED, DEFAULT_MAX_BOOT_ATTEMPTS, FW_A_ADDR,
+    FW_BANK_SIZE, FW_B_ADDR,
+};
+use crispy_common::version::FwVersion;
+
+/// A record as written by v1 firmware: 32 bytes, then erased flash.
+fn v1_record(active_bank: u8, confirmed: u8, version_b: u32, size_b: u32) -> [u8; BOOT_DATA_SIZE] {
+    let mut raw = [0xFFu8; BOOT_DATA_SIZE];
+    raw[..4].copy_from_slice(&BOOT_DATA_MAGIC.to_le_bytes());
+    raw[4..8].copy_from_slice(&[active_bank, confirmed, 2, 0]);
+    raw[8..32].fill(0);
+    raw[12..16].copy_from_slice(&version_b.to_le_bytes());
+    raw[28..32].copy_from_slice(&size_b.to_le_bytes());
+    raw
+}
 
 #[test]
 fn test_boot_data_default_new() {
@@ -13,8 +31,8 @@ fn test_boot_data_default_new() {
     assert_eq!(bd.active_bank, 0);
     assert_eq!(bd.confirmed, 0);
     assert_eq!(bd.boot_attempts, 0);
-    assert_eq!(bd.version_a, 0);
-    assert_eq!(bd.version_b, 0);
+    assert_eq!(bd.version_a, FwVersion::default());
+    assert_eq!(bd.version_b, FwVersion::default());
     assert_eq!(bd.crc_a, 0);
     assert_eq!(bd.crc_b, 0);
     assert_eq!(bd.size_a, 0);
@@ -50,17 +68,15 @@ fn test_boot_data_bank_addr_bank_b() {
 }
 
 #[test]
-fn test_boot_data_as_bytes_length() {
+fn test_boot_data_to_bytes_length() {
     let bd = BootData::default_new();
-    let bytes = bd.as_bytes();
-
-    assert_eq!(bytes.len(), 32);
+    assert_eq!(bd.to_bytes().len(), 64);
 }
 
 #[test]
-fn test_boot_data_as_bytes_magic() {
+fn test_boot_data_to_bytes_magic() {
     let bd = BootData::default_new();
-    let bytes = bd.as_bytes();
+    let bytes = bd.to_bytes();
 
     // Magic is at the start, little-endian
     let magic = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
@@ -68,6 +84,353 @@ fn test_boot_data_as_bytes_magic() {
 }
 
 #[test]
-fn test_boot_data_size_is_32_bytes() {
-    assert_eq!(std::mem::size_of::<BootData>(), 32);
+fn test_boot_data_size_is_64_bytes() {
+    assert_eq!(std::mem::size_of::<BootData>(), 64);
+}
+
+// --- Layout v2 ---
+
+#[test]
+fn test_boot_data_to_bytes_sets_version_and_crc() {
+    let mut bd = BootData::default_new();
+    bd.layout_version = 0;
+    bd.record_crc = 0;
+    let bytes = bd.to_bytes();
+
+    assert_eq!(bytes[32], BOOT_DATA_VERSION);
+    let crc = u32::from_le_bytes(bytes[60..].try_into().unwrap());
+    assert_eq!(crc, crc32::checksum(&bytes[..60]));
+}
+
+#[test]
+fn test_boot_data_v2_roundtrip() {
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.flags = 0x01;
+    bd.crc_a = 0xDEAD_BEEF;
+    bd.size_b = 12345;
+    bd.max_boot_attempts = 9;
+    bd.reserved[25] = 0x5A;
+
+    let read = BootData::from_bytes(&bd.to_bytes()).unwrap();
+    assert_eq!(read.to_bytes(), bd.to_bytes());
+    assert_eq!(read.max_boot_attempts, 9);
+    assert_eq!(read.reserved[25], 0x5A);
+}
+
+#[test]
+fn test_boot_data_v1_is_upgraded() {
+    let bd = BootData::from_bytes(&v1_record(1, 1, 7, 4096)).unwrap();
+
+    assert_eq!(bd.active_bank, 1);
+    assert_eq!(bd.confirmed, 1);
+    assert_eq!(bd.boot_attempts, 2);
+    assert_eq!(bd.version_b, FwVersion::from_raw(7));
+    assert_eq!(bd.size_b, 4096);
+    assert_eq!(bd.layout_version, BOOT_DATA_VERSION);
+    assert_eq!(bd.max_boot_attempts, 0);
+    assert_eq!(bd.reserved, [0; 26]);
+
+    // Written back as v2, and still readable
+    let bytes = bd.to_bytes();
+    assert_eq!(bytes[32], BOOT_DATA_VERSION);
+    assert_eq!(&bytes[..32], &v1_record(1, 1, 7, 4096)[..32]);
+    assert_eq!(BootData::from_bytes(&bytes).unwrap().to_bytes(), bytes);
+}
+
+#[test]
+fn test_boot_data_bad_magic_rejected() {
+    assert_eq!(
+        BootData::from_bytes(&[0xFF; BOOT_DATA_SIZE]),
+        Err(BootDataError::BadMagic(0xFFFF_FFFF))
+    );
+}
+
+#[test]
+fn test_boot_data_bad_crc_rejected() {
+    let mut bytes = BootData::default_new().to_bytes();
+    bytes[8] ^= 0x01;
+    assert_eq!(BootData::from_bytes(&bytes), Err(BootDataError::BadCrc));
+
+    let mut bytes = BootData::default_new().to_bytes();
+    bytes[63] ^= 0x80;
+    assert_eq!(BootData::from_bytes(&bytes), Err(BootDataError::BadCrc));
+}
+
+#[test]
+fn test_boot_data_unknown_version_rejected() {
+    for version in [0, 3, 0x7F, 0xFE] {
+        let mut bytes = BootData::default_new().to_bytes();
+        bytes[32] = version;
+        // A CRC that matches does not make a future layout readable
+        let crc = crc32::checksum(&bytes[..60]);
+        bytes[60..].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(
+            BootData::from_bytes(&bytes),
+            Err(BootDataError::UnknownVersion(version))
+        );
+    }
+}
+
+// --- Extended validation ---
+
+/// A default record with one change.
+fn with(change: impl FnOnce(&mut BootData)) -> BootData {
+    let mut bd = BootData::default_new();
+    change(&mut bd);
+    bd
+}
+
+#[test]
+fn test_validate_extended_accepts_sane_records() {
+    let mut bd = BootData::default_new();
+    assert_eq!(bd.validate_extended(), Ok(()));
+
+    bd.active_bank = 1;
+    bd.confirmed = 1;
+    bd.boot_attempts = 32;
+    bd.max_boot_attempts = 32;
+    bd.size_a = FW_BANK_SIZE;
+    bd.size_b = FW_BANK_SIZE;
+    bd.flags = BOOT_FLAG_RECONSTRUCTED | BOOT_FLAG_REPAIRED;
+    assert_eq!(bd.validate_extended(), Ok(()));
+    assert_eq!(bd.repaired(), Some(bd));
+}
+
+#[test]
+fn test_validate_extended_reports_each_issue() {
+    let cases = [
+        (
+            with(|bd| bd.magic = 0xFFFF_FFFF),
+            BootDataIssue::BadMagic(0xFFFF_FFFF),
+            false,
+        ),
+        (
+            with(|bd| bd.active_bank = 2),
+            BootDataIssue::ActiveBankOutOfRange(2),
+            false,
+        ),
+        (
+            with(|bd| bd.confirmed = 0xFF),
+            BootDataIssue::ConfirmedNotBool(0xFF),
+            true,
+        ),
+        (
+            with(|bd| bd.boot_attempts = 33),
+            BootDataIssue::AttemptsTooHigh(33),
+            true,
+        ),
+        (
+            with(|bd| bd.max_boot_attempts = 200),
+            BootDataIssue::MaxAttemptsOutOfRange(200),
+            true,
+        ),
+        (
+            with(|bd| bd.size_a = FW_BANK_SIZE + 1),
+            BootDataIssue::SizeTooLarge {
+                bank: 0,
+                size: FW_BANK_SIZE + 1,
+            },
+            false,
+        ),
+        (
+            with(|bd| bd.size_b = u32::MAX),
+            BootDataIssue::SizeTooLarge {
+                bank: 1,
+                size: u32::MAX,
+            },
+            false,
+        ),
+        (
+            with(|bd| bd.flags = 0x81),
+            BootDataIssue::UnknownFlags(0x81),
+            true,
+        ),
+    ];
+
+    for (bd, issue, repairable) in cases {
+        assert_eq!(bd.validate_extended(), Err(issue));
+        assert_eq!(bd.issues().collect::<Vec<_>>(), [issue]);
+        assert_eq!(issue.is_repairable(), repairable, "{issue:?}");
+        assert_eq!(bd.repaired().is_some(), repairable, "{issue:?}");
+    }
+}
+
+#[test]
+fn test_issues_collects_all_in_field_order() {
+    let mut bd = BootData::default_new();
+    bd.flags = 0x40;
+    bd.size_b = FW_BANK_SIZE + 4;
+    bd.confirmed = 7;
+    bd.active_bank = 9;
+
+    assert_eq!(
+        bd.issues().collect::<Vec<_>>(),
+        [
+            BootDataIssue::ActiveBankOutOfRange(9),
+            BootDataIssue::ConfirmedNotBool(7),
+            BootDataIssue::SizeTooLarge {
+                bank: 1,
+                size: FW_BANK_SIZE + 4,
+            },
+            BootDataIssue::UnknownFlags(0x40),
+        ]
+    );
+    assert_eq!(
+        bd.validate_extended(),
+        Err(BootDataIssue::ActiveBankOutOfRange(9))
+    );
+    // One unrepairable issue means the record is rebuilt
+    assert_eq!(bd.repaired(), None);
+}
+
+#[test]
+fn test_repaired_resets_fields_and_flags_record() {
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.confirmed = 2;
+    bd.boot_attempts = 250;
+    bd.max_boot_attempts = 99;
+    bd.flags = 0xF0 | BOOT_FLAG_RECONSTRUCTED;
+
+    let fixed = bd.repaired().unwrap();
+    assert_eq!(fixed.validate_extended(), Ok(()));
+    assert!(fixed.is_repaired());
+    assert!(fixed.is_reconstructed());
+    assert_eq!(fixed.active_bank, 1);
+    // Doubtful boots count as unconfirmed and at the limit, so they roll back
+    assert_eq!(fixed.confirmed, 0);
+    assert_eq!(fixed.max_boot_attempts, 0);
+    assert_eq!(fixed.boot_attempts, DEFAULT_MAX_BOOT_ATTEMPTS);
+}
+
+#[test]
+fn test_checks_follow_the_flash_layout() {
+    // 1MB banks on a 4MB part
+    let large = FlashLayout {
+        fw_a: 0x1001_0000,
+        fw_b: 0x1011_0000,
+        bank_size: 0x10_0000,
+        boot_data: 0x1021_0000,
+        flash_size: 0x40_0000,
+        partitions: &[],
+    };
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.size_b = FW_BANK_SIZE + 4;
+    bd.flags = 0x40;
+
+    assert_eq!(
+        bd.issues_for(&large).collect::<Vec<_>>(),
+        [BootDataIssue::UnknownFlags(0x40)]
+    );
+    let fixed = bd.repaired_for(&large).unwrap();
+    assert_eq!(fixed.validate_extended_for(&large), Ok(()));
+    assert_eq!(fixed.size_b, FW_BANK_SIZE + 4);
+    assert_eq!(bd.bank_addr_in(&large), large.fw_b);
+
+    // The plain methods keep to the default layout
+    assert!(bd
+        .issues()
+        .any(|issue| matches!(issue, BootDataIssue::SizeTooLarge { .. })));
+    assert_eq!(bd.repaired(), None);
+    assert_eq!(bd.bank_addr(), FW_B_ADDR);
+}
+
+#[test]
+fn test_v1_record_is_checked_after_upgrade() {
+    // v1 has no CRC, so a garbled field decodes cleanly
+    let bd = BootData::from_bytes(&v1_record(3, 1, 1, 1024)).unwrap();
+    assert_eq!(
+        bd.validate_extended(),
+        Err(BootDataIssue::ActiveBankOutOfRange(3))
+    );
+}
+
+// --- Byte layout and serde representation ---
+
+/// A record with a distinct value in every field.
+fn distinct_record() -> BootData {
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.confirmed = 1;
+    bd.boot_attempts = 2;
+    bd.flags = BOOT_FLAG_REPAIRED;
+    bd.version_a = FwVersion::new(1, 2, 3);
+    bd.version_b = FwVersion::new(4, 5, 6);
+    bd.crc_a = 0x1111_2222;
+    bd.crc_b = 0x3333_4444;
+    bd.size_a = 0x0001_0000;
+    bd.size_b = 0x0002_0000;
+    bd.max_boot_attempts = 7;
+    bd.reserved[25] = 0x5A;
+    bd
+}
+
+// Every field at its repr(C) offset, little-endian
+const DISTINCT_RECORD_BYTES: [u8; BOOT_DATA_SIZE] = [
+    0x7a, 0xda, 0x07, 0xb0, 0x01, 0x01, 0x02, 0x02, 0x03, 0x00, 0x02, 0x01, 0x06, 0x00, 0x05, 0x04,
+    0x22, 0x22, 0x11, 0x11, 0x44, 0x44, 0x33, 0x33, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00,
+    0x02, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5a, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[test]
+fn test_boot_data_byte_layout() {
+    let bytes = distinct_record().to_bytes();
+    assert_eq!(bytes[..60], DISTINCT_RECORD_BYTES[..60]);
+    assert_eq!(
+        bytes[60..],
+        crc32::checksum(&DISTINCT_RECORD_BYTES[..60]).to_le_bytes()
+    );
+}
+
+// Pinned next to the bytes above: a field added, renamed or retyped on
+// one side only fails here or there
+#[cfg(feature = "std")]
+#[test]
+fn test_boot_data_json_representation() {
+    let bd = BootData::from_bytes(&distinct_record().to_bytes()).unwrap();
+    let expected = serde_json::json!({
+        "magic": 0xB007_DA7Au32,
+        "active_bank": 1,
+        "confirmed": 1,
+        "boot_attempts": 2,
+        "flags": 2,
+        "version_a": 0x0102_0003,
+        "version_b": 0x0405_0006,
+        "crc_a": 0x1111_2222,
+        "crc_b": 0x3333_4444,
+        "size_a": 0x1_0000,
+        "size_b": 0x2_0000,
+        "layout_version": 2,
+        "max_boot_attempts": 7,
+        "reserved": [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x5A],
+        "record_crc": bd.record_crc,
+    });
+    assert_eq!(serde_json::to_value(bd).unwrap(), expected);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_boot_data_json_and_bytes_round_trip() {
+    let bytes = distinct_record().to_bytes();
+    let json = serde_json::to_string(&BootData::from_bytes(&bytes).unwrap()).unwrap();
+    let back: BootData = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.to_bytes(), bytes);
+
+    // A hand-written record need not carry a valid CRC; encoding sets it
+    let mut value = serde_json::to_value(back).unwrap();
+    value["record_crc"] = 0.into();
+    let edited: BootData = serde_json::from_value(value).unwrap();
+    assert_eq!(edited.to_bytes(), bytes);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_boot_data_json_rejects_missing_fields() {
+    let mut value = serde_json::to_value(distinct_record()).unwrap();
+    value.as_object_mut().unwrap().remove("size_b");
+    assert!(serde_json::from_value::<BootData>(value).is_err());
 }
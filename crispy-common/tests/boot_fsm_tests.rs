@@ -5,23 +5,32 @@
 
 use crispy_common::boot_fsm::{
     bank_metadata, needs_rollback, select_boot_bank_fsm, toggle_bank, try_boot_strategy, BankPair,
-    BankValidation, BootDecision, BootStrategy, MAX_BOOT_ATTEMPTS,
+    BankValidation, BootDecision, BootStrategy,
+};
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{BootData, DEFAULT_MAX_BOOT_ATTEMPTS, MAX_BOOT_ATTEMPTS_RANGE};
+use crispy_common::version::FwVersion;
+
+/// The 2MB bank addresses, whatever flash size the crate is built for.
+const LAYOUT: FlashLayout = FlashLayout {
+    fw_a: 0x1001_0000,
+    fw_b: 0x100D_0000,
+    ..FlashLayout::DEFAULT
 };
-use crispy_common::protocol::{BootData, BOOT_DATA_MAGIC};
 
 fn make_boot_data() -> BootData {
     BootData {
-        magic: BOOT_DATA_MAGIC,
         active_bank: 0,
         confirmed: 0,
         boot_attempts: 0,
-        _reserved0: 0,
-        version_a: 1,
-        version_b: 2,
+        flags: 0,
+        version_a: FwVersion::from_raw(1),
+        version_b: FwVersion::from_raw(2),
         crc_a: 0xAAAA_AAAA,
         crc_b: 0xBBBB_BBBB,
         size_a: 1024,
         size_b: 2048,
+        ..BootData::default_new()
     }
 }
 
@@ -83,47 +92,47 @@ fn test_needs_rollback_zero_attempts() {
     let mut bd = make_boot_data();
     bd.boot_attempts = 0;
     bd.confirmed = 0;
-    assert!(!needs_rollback(&bd));
+    assert!(!needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 #[test]
 fn test_needs_rollback_below_max_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS - 1;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS - 1;
     bd.confirmed = 0;
-    assert!(!needs_rollback(&bd));
+    assert!(!needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 #[test]
 fn test_needs_rollback_at_max_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
     bd.confirmed = 0;
-    assert!(needs_rollback(&bd));
+    assert!(needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 #[test]
 fn test_needs_rollback_above_max_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS + 2;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS + 2;
     bd.confirmed = 0;
-    assert!(needs_rollback(&bd));
+    assert!(needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 #[test]
 fn test_needs_rollback_confirmed_firmware_at_max_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
     bd.confirmed = 1;
-    assert!(!needs_rollback(&bd));
+    assert!(!needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 #[test]
 fn test_needs_rollback_confirmed_firmware_above_max_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS + 5;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS + 5;
     bd.confirmed = 1;
-    assert!(!needs_rollback(&bd));
+    assert!(!needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
 }
 
 // =============================================================================
@@ -137,7 +146,7 @@ fn test_boot_decision_apply_to_updates_active_bank() {
         flash_addr: 0x1000_0000,
         active_bank: 1,
         boot_attempts: 0,
-        confirmed: 0,
+        keep_confirmed: false,
     };
 
     let new_bd = decision.apply_to(&bd);
@@ -151,7 +160,7 @@ fn test_boot_decision_apply_to_updates_boot_attempts() {
         flash_addr: 0x1000_0000,
         active_bank: 0,
         boot_attempts: 5,
-        confirmed: 0,
+        keep_confirmed: false,
     };
 
     let new_bd = decision.apply_to(&bd);
@@ -159,19 +168,49 @@ fn test_boot_decision_apply_to_updates_boot_attempts() {
 }
 
 #[test]
-fn test_boot_decision_apply_to_updates_confirmed() {
-    let bd = make_boot_data();
+fn test_boot_decision_apply_to_keeps_confirmed() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
     let decision = BootDecision {
         flash_addr: 0x1000_0000,
         active_bank: 0,
         boot_attempts: 0,
-        confirmed: 1,
+        keep_confirmed: true,
     };
 
     let new_bd = decision.apply_to(&bd);
     assert_eq!(new_bd.confirmed, 1);
 }
 
+#[test]
+fn test_boot_decision_apply_to_clears_confirmed() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+    let decision = BootDecision {
+        flash_addr: 0x1000_0000,
+        active_bank: 0,
+        boot_attempts: 0,
+        keep_confirmed: false,
+    };
+
+    let new_bd = decision.apply_to(&bd);
+    assert_eq!(new_bd.confirmed, 0);
+}
+
+#[test]
+fn test_boot_decision_keep_confirmed_does_not_confirm() {
+    let bd = make_boot_data();
+    let decision = BootDecision {
+        flash_addr: 0x1000_0000,
+        active_bank: 0,
+        boot_attempts: 0,
+        keep_confirmed: true,
+    };
+
+    // Keeping a confirmation never creates one
+    assert_eq!(decision.apply_to(&bd).confirmed, 0);
+}
+
 #[test]
 fn test_boot_decision_apply_to_preserves_other_fields() {
     let bd = make_boot_data();
@@ -179,7 +218,7 @@ fn test_boot_decision_apply_to_preserves_other_fields() {
         flash_addr: 0x1000_0000,
         active_bank: 1,
         boot_attempts: 2,
-        confirmed: 1,
+        keep_confirmed: true,
     };
 
     let new_bd = decision.apply_to(&bd);
@@ -201,7 +240,7 @@ fn test_boot_decision_apply_to_preserves_other_fields() {
 #[test]
 fn test_bank_pair_new_bank_a_active() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd);
+    let pair = BankPair::new(0, &LAYOUT, &bd);
 
     assert_eq!(pair.primary.bank_id, 0);
     assert_eq!(pair.primary.addr, 0x1001_0000);
@@ -217,7 +256,7 @@ fn test_bank_pair_new_bank_a_active() {
 #[test]
 fn test_bank_pair_new_bank_b_active() {
     let bd = make_boot_data();
-    let pair = BankPair::new(1, 0x1001_0000, 0x100D_0000, &bd);
+    let pair = BankPair::new(1, &LAYOUT, &bd);
 
     assert_eq!(pair.primary.bank_id, 1);
     assert_eq!(pair.primary.addr, 0x100D_0000);
@@ -233,7 +272,7 @@ fn test_bank_pair_new_bank_b_active() {
 #[test]
 fn test_bank_pair_default_validation_is_invalid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd);
+    let pair = BankPair::new(0, &LAYOUT, &bd);
 
     assert!(!pair.primary_validation.crc_valid);
     assert!(!pair.primary_validation.basic_valid);
@@ -244,7 +283,7 @@ fn test_bank_pair_default_validation_is_invalid() {
 #[test]
 fn test_bank_pair_with_validation() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -268,7 +307,7 @@ fn test_bank_pair_with_validation() {
 #[test]
 fn test_try_boot_strategy_primary_with_crc_valid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -283,13 +322,13 @@ fn test_try_boot_strategy_primary_with_crc_valid() {
     assert_eq!(decision.active_bank, 0);
     assert_eq!(decision.flash_addr, 0x1001_0000);
     assert_eq!(decision.boot_attempts, 1);
-    assert_eq!(decision.confirmed, 0);
+    assert!(!decision.keep_confirmed);
 }
 
 #[test]
 fn test_try_boot_strategy_primary_with_crc_invalid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: false,
             basic_valid: true,
@@ -304,7 +343,7 @@ fn test_try_boot_strategy_primary_with_crc_invalid() {
 #[test]
 fn test_try_boot_strategy_fallback_with_crc_valid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation::default(),
         BankValidation {
             crc_valid: true,
@@ -319,13 +358,13 @@ fn test_try_boot_strategy_fallback_with_crc_valid() {
     assert_eq!(decision.active_bank, 1);
     assert_eq!(decision.flash_addr, 0x100D_0000);
     assert_eq!(decision.boot_attempts, 1); // Reset to 1 for fallback
-    assert_eq!(decision.confirmed, 0);
+    assert!(!decision.keep_confirmed);
 }
 
 #[test]
 fn test_try_boot_strategy_primary_basic_valid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: false,
             basic_valid: true,
@@ -344,7 +383,7 @@ fn test_try_boot_strategy_primary_basic_valid() {
 #[test]
 fn test_try_boot_strategy_fallback_basic_valid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation::default(),
         BankValidation {
             crc_valid: false,
@@ -367,7 +406,7 @@ fn test_try_boot_strategy_fallback_basic_valid() {
 #[test]
 fn test_select_boot_bank_fsm_primary_crc_valid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -378,7 +417,7 @@ fn test_select_boot_bank_fsm_primary_crc_valid() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     assert_eq!(decision.active_bank, 0);
     assert_eq!(decision.flash_addr, 0x1001_0000);
     assert_eq!(decision.boot_attempts, 1);
@@ -387,7 +426,7 @@ fn test_select_boot_bank_fsm_primary_crc_valid() {
 #[test]
 fn test_select_boot_bank_fsm_falls_back_to_fallback_crc() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: false,
             basic_valid: true,
@@ -398,7 +437,7 @@ fn test_select_boot_bank_fsm_falls_back_to_fallback_crc() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     assert_eq!(decision.active_bank, 1);
     assert_eq!(decision.flash_addr, 0x100D_0000);
 }
@@ -406,7 +445,7 @@ fn test_select_boot_bank_fsm_falls_back_to_fallback_crc() {
 #[test]
 fn test_select_boot_bank_fsm_falls_back_to_primary_basic() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: false,
             basic_valid: true,
@@ -417,7 +456,7 @@ fn test_select_boot_bank_fsm_falls_back_to_primary_basic() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     assert_eq!(decision.active_bank, 0);
     assert_eq!(decision.flash_addr, 0x1001_0000);
 }
@@ -425,7 +464,7 @@ fn test_select_boot_bank_fsm_falls_back_to_primary_basic() {
 #[test]
 fn test_select_boot_bank_fsm_falls_back_to_fallback_basic() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: false,
             basic_valid: false,
@@ -436,7 +475,7 @@ fn test_select_boot_bank_fsm_falls_back_to_fallback_basic() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     assert_eq!(decision.active_bank, 1);
     assert_eq!(decision.flash_addr, 0x100D_0000);
 }
@@ -444,10 +483,10 @@ fn test_select_boot_bank_fsm_falls_back_to_fallback_basic() {
 #[test]
 fn test_select_boot_bank_fsm_default_when_all_invalid() {
     let bd = make_boot_data();
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd)
+    let pair = BankPair::new(0, &LAYOUT, &bd)
         .with_validation(BankValidation::default(), BankValidation::default());
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     // Falls back to primary with incremented attempts
     assert_eq!(decision.active_bank, 0);
     assert_eq!(decision.flash_addr, 0x1001_0000);
@@ -457,11 +496,10 @@ fn test_select_boot_bank_fsm_default_when_all_invalid() {
 #[test]
 fn test_select_boot_bank_fsm_rollback_resets_attempts() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
     bd.confirmed = 0;
 
-    // After rollback, bank 1 becomes primary
-    let pair = BankPair::new(1, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(bd.active_bank, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -472,17 +510,17 @@ fn test_select_boot_bank_fsm_rollback_resets_attempts() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
-    assert_eq!(decision.boot_attempts, 1); // Reset from MAX_BOOT_ATTEMPTS to 0, then +1
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.boot_attempts, 1); // Reset from DEFAULT_MAX_BOOT_ATTEMPTS to 0, then +1
 }
 
 #[test]
 fn test_select_boot_bank_fsm_no_rollback_when_confirmed() {
     let mut bd = make_boot_data();
-    bd.boot_attempts = MAX_BOOT_ATTEMPTS;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
     bd.confirmed = 1;
 
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -493,9 +531,30 @@ fn test_select_boot_bank_fsm_no_rollback_when_confirmed() {
         },
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
-    // No rollback, so attempts is MAX_BOOT_ATTEMPTS + 1
-    assert_eq!(decision.boot_attempts, MAX_BOOT_ATTEMPTS + 1);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    // No rollback, and a confirmed boot is not counted
+    assert_eq!(decision.active_bank, 0);
+    assert_eq!(decision.boot_attempts, DEFAULT_MAX_BOOT_ATTEMPTS);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_confirmed_boots_are_not_counted() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+    let valid = BankValidation {
+        crc_valid: true,
+        basic_valid: true,
+    };
+
+    // Far more boots than attempts_max: the record must stay clean
+    for _ in 0..40 {
+        let pair = BankPair::new(bd.active_bank, &LAYOUT, &bd).with_validation(valid, valid);
+        bd = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS).apply_to(&bd);
+        assert_eq!((bd.active_bank, bd.confirmed, bd.boot_attempts), (0, 1, 0));
+        assert_eq!(bd.issues().next(), None);
+        assert_eq!(bd.repaired(), Some(bd));
+        assert!(!bd.is_repaired());
+    }
 }
 
 #[test]
@@ -503,7 +562,7 @@ fn test_select_boot_bank_fsm_increments_attempts() {
     let mut bd = make_boot_data();
     bd.boot_attempts = 1;
 
-    let pair = BankPair::new(0, 0x1001_0000, 0x100D_0000, &bd).with_validation(
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
         BankValidation {
             crc_valid: true,
             basic_valid: true,
@@ -511,6 +570,287 @@ fn test_select_boot_bank_fsm_increments_attempts() {
         BankValidation::default(),
     );
 
-    let decision = select_boot_bank_fsm(&bd, pair);
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
     assert_eq!(decision.boot_attempts, 2); // 1 + 1
 }
+
+// =============================================================================
+// Rollback and confirmation cases (bootloader/FSM unification)
+// =============================================================================
+
+#[test]
+fn test_bank_pair_swapped_exchanges_banks_and_validation() {
+    let bd = make_boot_data();
+    let pair = BankPair::new(0, &LAYOUT, &bd)
+        .with_validation(
+            BankValidation {
+                crc_valid: true,
+                basic_valid: true,
+            },
+            BankValidation::default(),
+        )
+        .swapped();
+
+    assert_eq!(pair.primary.bank_id, 1);
+    assert_eq!(pair.primary.addr, 0x100D_0000);
+    assert!(!pair.primary_validation.crc_valid);
+    assert_eq!(pair.fallback.bank_id, 0);
+    assert!(pair.fallback_validation.crc_valid);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_rollback_switches_to_other_bank() {
+    let mut bd = make_boot_data();
+    bd.active_bank = 0;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
+    bd.confirmed = 0;
+
+    // Pair is built from the stored active bank; the FSM performs the toggle
+    let pair = BankPair::new(bd.active_bank, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 1);
+    assert_eq!(decision.flash_addr, 0x100D_0000);
+    assert_eq!(decision.boot_attempts, 1);
+    assert!(!decision.keep_confirmed);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_rollback_returns_when_other_bank_invalid() {
+    let mut bd = make_boot_data();
+    bd.active_bank = 0;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
+
+    // Bank B is empty, so after the swap the original bank is the fallback
+    let pair = BankPair::new(bd.active_bank, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation::default(),
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 0);
+    assert_eq!(decision.flash_addr, 0x1001_0000);
+    assert_eq!(decision.boot_attempts, 1);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_fallback_basic_clears_confirmed() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+
+    // The old bootloader kept `confirmed` when switching to a basic-valid
+    // fallback; a bank switch must always require a fresh confirmation.
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation::default(),
+        BankValidation {
+            crc_valid: false,
+            basic_valid: true,
+        },
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 1);
+    assert!(!decision.keep_confirmed);
+    assert_eq!(decision.apply_to(&bd).confirmed, 0);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_confirmed_primary_crc_valid_keeps_confirmed() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 0);
+    assert!(decision.keep_confirmed);
+    assert_eq!(decision.apply_to(&bd).confirmed, 1);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_confirmed_primary_crc_invalid_fallback_clears() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: false,
+            basic_valid: true,
+        },
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 1);
+    assert!(!decision.keep_confirmed);
+    assert_eq!(decision.apply_to(&bd).confirmed, 0);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_confirmed_primary_basic_only_clears() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+
+    // Same bank, but its image no longer matches the confirmed CRC
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: false,
+            basic_valid: true,
+        },
+        BankValidation::default(),
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 0);
+    assert!(!decision.keep_confirmed);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_unconfirmed_primary_stays_unconfirmed() {
+    let bd = make_boot_data();
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation::default(),
+    );
+
+    assert!(!select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS).keep_confirmed);
+}
+
+#[test]
+fn test_select_boot_bank_fsm_confirmed_exhausted_attempts_never_rolls_back() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+    bd.boot_attempts = u8::MAX;
+
+    // Confirmed firmware cannot trigger a rollback, however many boots it saw
+    assert!(!needs_rollback(&bd, DEFAULT_MAX_BOOT_ATTEMPTS));
+
+    let pair = BankPair::new(0, &LAYOUT, &bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+    );
+
+    let decision = select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+    assert_eq!(decision.active_bank, 0);
+    assert!(decision.keep_confirmed);
+    assert_eq!(decision.boot_attempts, u8::MAX);
+}
+
+#[test]
+fn test_bank_pair_swapped_drops_confirmation() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+    let pair = BankPair::new(0, &LAYOUT, &bd);
+    assert!(pair.primary_confirmed);
+    assert!(!pair.swapped().primary_confirmed);
+}
+
+#[test]
+#[should_panic]
+fn test_select_boot_bank_fsm_rollback_of_confirmed_pair_is_a_bug() {
+    let mut bd = make_boot_data();
+    bd.confirmed = 1;
+    let pair = BankPair::new(0, &LAYOUT, &bd);
+
+    // A record that is both confirmed and due for rollback cannot exist
+    bd.confirmed = 0;
+    bd.boot_attempts = DEFAULT_MAX_BOOT_ATTEMPTS;
+    select_boot_bank_fsm(&bd, pair, DEFAULT_MAX_BOOT_ATTEMPTS);
+}
+
+// =============================================================================
+// Configurable rollback threshold
+// =============================================================================
+
+fn both_crc_valid(bd: &BootData) -> BankPair {
+    BankPair::new(bd.active_bank, &LAYOUT, bd).with_validation(
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+        BankValidation {
+            crc_valid: true,
+            basic_valid: true,
+        },
+    )
+}
+
+#[test]
+fn test_threshold_one_rolls_back_on_first_unconfirmed_reboot() {
+    let mut bd = make_boot_data();
+
+    // First boot of a fresh image
+    assert!(!needs_rollback(&bd, 1));
+    let decision = select_boot_bank_fsm(&bd, both_crc_valid(&bd), 1);
+    assert_eq!(decision.active_bank, 0);
+    assert_eq!(decision.boot_attempts, 1);
+
+    // It reset without confirming: the next boot rolls back
+    bd = decision.apply_to(&bd);
+    assert!(needs_rollback(&bd, 1));
+    let decision = select_boot_bank_fsm(&bd, both_crc_valid(&bd), 1);
+    assert_eq!(decision.active_bank, 1);
+}
+
+#[test]
+fn test_high_threshold_tolerates_many_unconfirmed_boots() {
+    let mut bd = make_boot_data();
+    let max_attempts = 20;
+
+    for _ in 0..max_attempts {
+        assert!(!needs_rollback(&bd, max_attempts));
+        let decision = select_boot_bank_fsm(&bd, both_crc_valid(&bd), max_attempts);
+        assert_eq!(decision.active_bank, 0);
+        bd = decision.apply_to(&bd);
+    }
+
+    assert_eq!(bd.boot_attempts, max_attempts);
+    let decision = select_boot_bank_fsm(&bd, both_crc_valid(&bd), max_attempts);
+    assert_eq!(decision.active_bank, 1);
+}
+
+#[test]
+fn test_boot_attempt_limit_defaults_and_clamps() {
+    let mut bd = make_boot_data();
+    assert_eq!(bd.boot_attempt_limit(), DEFAULT_MAX_BOOT_ATTEMPTS);
+
+    bd.max_boot_attempts = 1;
+    assert_eq!(bd.boot_attempt_limit(), 1);
+    bd.max_boot_attempts = 10;
+    assert_eq!(bd.boot_attempt_limit(), 10);
+    bd.max_boot_attempts = 0xFF;
+    assert_eq!(bd.boot_attempt_limit(), *MAX_BOOT_ATTEMPTS_RANGE.end());
+}
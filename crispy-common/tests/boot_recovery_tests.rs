@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for BootData reconstruction over a simulated flash.
+
+use std::collections::HashMap;
+
+use crispy_common::boot_fsm::{select_boot_bank_fsm, BankPair, BankValidation};
+use crispy_common::boot_recovery::{
+    bank_looks_bootable, reconstruct_boot_data, scan_banks, RamWindow, RecoveryScan,
+};
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{BOOT_FLAG_RECONSTRUCTED, FW_A_ADDR, FW_B_ADDR};
+
+const RAM: RamWindow = RamWindow {
+    start: 0x2000_0000,
+    end: 0x2003_BFFF,
+};
+
+/// Sparse word-addressed flash; unwritten words read as erased.
+#[derive(Default)]
+struct SimFlash {
+    words: HashMap<u32, u32>,
+}
+
+impl SimFlash {
+    fn with_vector_table(mut self, bank_addr: u32, sp: u32, reset: u32) -> Self {
+        self.words.insert(bank_addr, sp);
+        self.words.insert(bank_addr + 4, reset);
+        self
+    }
+
+    fn read_word(&self, addr: u32) -> u32 {
+        self.words.get(&addr).copied().unwrap_or(0xFFFF_FFFF)
+    }
+}
+
+fn bootable(flash: SimFlash, bank_addr: u32) -> SimFlash {
+    flash.with_vector_table(bank_addr, 0x2003_B000, 0x2000_00C1)
+}
+
+fn scan(flash: &SimFlash) -> RecoveryScan {
+    scan_banks(|addr| flash.read_word(addr), FW_A_ADDR, FW_B_ADDR, RAM)
+}
+
+// --- Vector table checks ---
+
+#[test]
+fn test_erased_bank_not_bootable() {
+    let flash = SimFlash::default();
+    assert!(!bank_looks_bootable(|a| flash.read_word(a), FW_A_ADDR, RAM));
+}
+
+#[test]
+fn test_vector_table_outside_ram_not_bootable() {
+    // XIP-linked image: reset vector in flash
+    let flash = SimFlash::default().with_vector_table(FW_A_ADDR, 0x2003_B000, 0x1001_00C1);
+    assert!(!bank_looks_bootable(|a| flash.read_word(a), FW_A_ADDR, RAM));
+}
+
+#[test]
+fn test_ram_window_bounds_inclusive() {
+    assert!(RAM.contains(RAM.start));
+    assert!(RAM.contains(RAM.end));
+    assert!(!RAM.contains(RAM.end + 1));
+}
+
+// --- Reconstruction ---
+
+#[test]
+fn test_reconstruct_both_banks_prefers_a() {
+    let flash = bootable(bootable(SimFlash::default(), FW_A_ADDR), FW_B_ADDR);
+    let result = scan(&flash);
+    assert_eq!(
+        result,
+        RecoveryScan {
+            bank_a: true,
+            bank_b: true
+        }
+    );
+
+    let bd = reconstruct_boot_data(result).unwrap();
+    assert!(bd.is_valid());
+    assert!(bd.is_reconstructed());
+    assert_eq!(bd.flags, BOOT_FLAG_RECONSTRUCTED);
+    assert_eq!(bd.active_bank, 0);
+    assert_eq!(bd.confirmed, 0);
+    assert_eq!(bd.boot_attempts, 0);
+    assert_eq!((bd.size_a, bd.size_b), (0, 0));
+}
+
+#[test]
+fn test_reconstruct_only_b() {
+    let flash = bootable(SimFlash::default(), FW_B_ADDR);
+    let bd = reconstruct_boot_data(scan(&flash)).unwrap();
+    assert_eq!(bd.active_bank, 1);
+    assert!(bd.is_reconstructed());
+}
+
+#[test]
+fn test_reconstruct_nothing_bootable() {
+    let flash = SimFlash::default();
+    assert!(reconstruct_boot_data(scan(&flash)).is_none());
+}
+
+#[test]
+fn test_reconstructed_record_boots_via_basic_validation() {
+    let flash = bootable(SimFlash::default(), FW_B_ADDR);
+    let result = scan(&flash);
+    let bd = reconstruct_boot_data(result).unwrap();
+
+    // Sizes are unknown, so CRC validation cannot pass for either bank
+    let validation = |ok: bool| BankValidation {
+        crc_valid: false,
+        basic_valid: ok,
+    };
+    let banks = BankPair::new(bd.active_bank, &FlashLayout::DEFAULT, &bd)
+        .with_validation(validation(result.bank_b), validation(result.bank_a));
+    let decision = select_boot_bank_fsm(&bd, banks, bd.boot_attempt_limit());
+
+    assert_eq!(decision.flash_addr, FW_B_ADDR);
+    assert!(decision.apply_to(&bd).is_reconstructed());
+}
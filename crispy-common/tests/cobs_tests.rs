@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for COBS error reporting, the slice-based codec and the
+//! streaming frame encoder and decoder.
+
+use crispy_common::cobs::{
+    decode, decode_heapless, decode_in_place, encode, encode_heapless, encode_into,
+    max_encoded_len, CobsError, CobsFrameDecoder, CobsStreamEncoder,
+};
+use heapless::Vec as HeaplessVec;
+
+// --- Error paths ---
+
+#[test]
+fn test_decode_empty_is_truncated() {
+    assert_eq!(decode(&[]), Err(CobsError::Truncated));
+    assert_eq!(
+        decode_heapless::<16>(&[]).map(|v| v.len()),
+        Err(CobsError::Truncated)
+    );
+}
+
+#[test]
+fn test_decode_truncated_block() {
+    // Code byte promises four data bytes, only one follows
+    assert_eq!(decode(&[0x05, 0x01]), Err(CobsError::Truncated));
+}
+
+#[test]
+fn test_decode_zero_inside_block_is_malformed() {
+    assert_eq!(decode(&[0x05, 0x01, 0x02, 0x00]), Err(CobsError::Malformed));
+    assert_eq!(
+        decode_heapless::<16>(&[0x03, 0x11, 0x00, 0x00]).map(|v| v.len()),
+        Err(CobsError::Malformed)
+    );
+}
+
+#[test]
+fn test_decode_heapless_buffer_too_small() {
+    let encoded = encode(&[1, 2, 3, 4, 5]);
+    assert_eq!(
+        decode_heapless::<4>(&encoded).map(|v| v.len()),
+        Err(CobsError::BufferTooSmall)
+    );
+    assert_eq!(decode_heapless::<5>(&encoded).unwrap(), [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_encode_heapless_buffer_too_small() {
+    // Five data bytes encode to code + 5 + delimiter = 7 bytes
+    let data = [1, 2, 3, 4, 5];
+    let fits: HeaplessVec<u8, 7> = encode_heapless(&data).unwrap();
+    assert_eq!(&fits[..], &encode(&data)[..]);
+
+    let overflow: Result<HeaplessVec<u8, 6>, _> = encode_heapless(&data);
+    assert_eq!(overflow, Err(CobsError::BufferTooSmall));
+}
+
+#[test]
+fn test_encode_heapless_overflow_at_block_boundary() {
+    // 254 non-zero bytes fill a code block; the next code byte must fit too
+    let data = [0x7F; 254];
+    let encoded = encode(&data);
+    assert!(encode_heapless::<258>(&data).is_ok());
+    assert_eq!(encoded.len(), 257);
+
+    let overflow: Result<HeaplessVec<u8, 256>, _> = encode_heapless(&data);
+    assert_eq!(overflow, Err(CobsError::BufferTooSmall));
+}
+
+#[test]
+fn test_decoder_reports_drop_reason() {
+    let mut decoder = CobsFrameDecoder::<4>::new();
+    assert_eq!(decoder.last_error(), None);
+
+    decoder.feed_slice(&encode(&[1; 8]), |_| panic!("oversize"));
+    assert_eq!(decoder.last_error(), Some(CobsError::BufferTooSmall));
+
+    decoder.feed_slice(&[0x04, 0x01, 0x00], |_| panic!("truncated"));
+    assert_eq!(decoder.last_error(), Some(CobsError::Truncated));
+    assert_eq!(decoder.dropped_frames(), 2);
+}
+
+/// Deterministic xorshift32 generator for the round-trip tests.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Random bytes, zero-heavy one time in four to exercise short blocks.
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        let zero_heavy = self.next().is_multiple_of(4);
+        (0..len)
+            .map(|_| {
+                let r = self.next();
+                if zero_heavy && r.is_multiple_of(3) {
+                    0
+                } else {
+                    (r >> 8) as u8
+                }
+            })
+            .collect()
+    }
+}
+
+/// Inputs from empty up to several KB, plus block-boundary lengths.
+fn random_inputs() -> Vec<Vec<u8>> {
+    let mut rng = Rng(0x1234_5678);
+    let mut inputs: Vec<Vec<u8>> = [0, 1, 253, 254, 255, 508, 509]
+        .iter()
+        .map(|&len| vec![0xA5; len])
+        .collect();
+    for _ in 0..200 {
+        let len = (rng.next() % 6000) as usize;
+        inputs.push(rng.bytes(len));
+    }
+    inputs
+}
+
+// --- Slice-based codec ---
+
+#[test]
+fn test_encode_into_matches_encode() {
+    for input in random_inputs() {
+        let mut out = vec![0u8; input.len() + input.len() / 254 + 2];
+        let n = encode_into(&input, &mut out).unwrap();
+        assert_eq!(&out[..n], &encode(&input)[..], "len {}", input.len());
+    }
+}
+
+#[test]
+fn test_decode_in_place_matches_decode() {
+    for input in random_inputs() {
+        let mut buf = encode(&input);
+        let n = decode_in_place(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &input[..], "len {}", input.len());
+        assert_eq!(decode(&encode(&input)).unwrap(), input);
+    }
+}
+
+#[test]
+fn test_decode_in_place_without_delimiter() {
+    let mut buf = encode(&[0x11, 0x00, 0x22]);
+    buf.pop();
+    let n = decode_in_place(&mut buf).unwrap();
+    assert_eq!(&buf[..n], &[0x11, 0x00, 0x22]);
+}
+
+#[test]
+fn test_decode_in_place_errors() {
+    assert_eq!(decode_in_place(&mut []), Err(CobsError::Truncated));
+    assert_eq!(
+        decode_in_place(&mut [0x05, 0x01]),
+        Err(CobsError::Truncated)
+    );
+    assert_eq!(
+        decode_in_place(&mut [0x03, 0x11, 0x00, 0x00]),
+        Err(CobsError::Malformed)
+    );
+}
+
+#[test]
+fn test_encode_into_buffer_too_small() {
+    let data = [1, 2, 3];
+    let mut out = [0u8; 4];
+    assert_eq!(encode_into(&data, &mut out), Err(CobsError::BufferTooSmall));
+    assert_eq!(encode_into(&data, &mut []), Err(CobsError::BufferTooSmall));
+
+    let mut out = [0u8; 5];
+    assert_eq!(encode_into(&data, &mut out), Ok(5));
+}
+
+// --- Streaming encoder ---
+
+fn stream_chunks(input: &[u8]) -> Vec<Vec<u8>> {
+    let mut encoder = CobsStreamEncoder::new(input.iter().copied());
+    let mut chunks = Vec::new();
+    while let Some(chunk) = encoder.next_chunk() {
+        chunks.push(chunk.to_vec());
+    }
+    chunks
+}
+
+#[test]
+fn test_stream_encoder_matches_encode() {
+    for input in random_inputs() {
+        let streamed: Vec<u8> = stream_chunks(&input).concat();
+        assert_eq!(streamed, encode(&input), "len {}", input.len());
+    }
+}
+
+#[test]
+fn test_stream_encoder_chunks_fit_a_block() {
+    for input in random_inputs() {
+        let chunks = stream_chunks(&input);
+        assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= 256));
+        // Only the final chunk carries the delimiter
+        let (last, rest) = chunks.split_last().unwrap();
+        assert_eq!(*last.last().unwrap(), 0x00);
+        assert!(rest.iter().all(|c| !c.contains(&0x00)));
+    }
+}
+
+#[test]
+fn test_stream_encoder_empty_payload() {
+    assert_eq!(stream_chunks(&[]), vec![vec![0x01, 0x00]]);
+}
+
+#[test]
+fn test_stream_encoder_round_trips_through_decoder() {
+    let input = Rng(0xC0FF_EE00).bytes(5000);
+    let mut decoder: CobsFrameDecoder<6000> = CobsFrameDecoder::new();
+    let mut frames = Vec::new();
+    for chunk in stream_chunks(&input) {
+        decoder.feed_slice(&chunk, |frame| frames.push(frame.to_vec()));
+    }
+    assert_eq!(frames, vec![input]);
+}
+
+// --- Capacity boundaries ---
+
+#[test]
+fn test_max_encoded_len_is_worst_case() {
+    for len in [0, 1, 253, 254, 255, 508, 1024, 4096] {
+        // Non-zero data never gets a zero's code byte for free
+        let data = vec![0x5A; len];
+        assert_eq!(encode(&data).len(), max_encoded_len(len), "len {}", len);
+        // Zeros only ever shorten or match it
+        assert!(encode(&vec![0; len]).len() <= max_encoded_len(len));
+    }
+}
+
+/// Encode `data` into `N` bytes of heapless storage.
+fn encode_cap<const N: usize>(data: &[u8]) -> Result<Vec<u8>, CobsError> {
+    encode_heapless::<N>(data).map(|v| v.to_vec())
+}
+
+#[test]
+fn test_encode_heapless_at_capacity() {
+    // max_encoded_len(300) == 303
+    let data: Vec<u8> = (0..300).map(|i| (i % 255) as u8 + 1).collect();
+    assert_eq!(max_encoded_len(data.len()), 303);
+
+    assert_eq!(encode_cap::<303>(&data), Ok(encode(&data)));
+    assert_eq!(encode_cap::<302>(&data), Err(CobsError::BufferTooSmall));
+}
+
+#[test]
+fn test_encode_heapless_one_byte_over_with_zeros() {
+    // [1, 0, 2] encodes to [02 01 02 02 00]: exactly five bytes
+    let data = [1, 0, 2];
+    assert_eq!(
+        encode_cap::<5>(&data),
+        Ok(vec![0x02, 0x01, 0x02, 0x02, 0x00])
+    );
+    assert_eq!(encode_cap::<4>(&data), Err(CobsError::BufferTooSmall));
+}
+
+#[test]
+fn test_encode_into_at_capacity() {
+    let data = vec![0x33; 254];
+    let needed = max_encoded_len(data.len());
+
+    let mut out = vec![0u8; needed];
+    assert_eq!(encode_into(&data, &mut out), Ok(needed));
+
+    let mut out = vec![0u8; needed - 1];
+    assert_eq!(encode_into(&data, &mut out), Err(CobsError::BufferTooSmall));
+}
+
+/// Feed `stream` split into the given pieces and collect every frame.
+fn decode_pieces<const N: usize>(
+    decoder: &mut CobsFrameDecoder<N>,
+    pieces: &[&[u8]],
+) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    for piece in pieces {
+        decoder.feed_slice(piece, |frame| frames.push(frame.to_vec()));
+    }
+    frames
+}
+
+fn payloads() -> Vec<Vec<u8>> {
+    vec![
+        vec![0x11, 0x22, 0x00, 0x33],
+        vec![0x00],
+        vec![0x00, 0x00, 0x00],
+        vec![0x42],
+        (0..254).map(|i| i as u8 + 1).collect(),
+        (0..255).map(|i| i as u8 + 1).collect(),
+        (0..600).map(|i| i as u8).collect(),
+    ]
+}
+
+// --- Whole frames ---
+
+#[test]
+fn test_decoder_matches_one_shot_decode() {
+    for payload in payloads() {
+        let mut decoder = CobsFrameDecoder::<1024>::new();
+        let frames = decode_pieces(&mut decoder, &[&encode(&payload)]);
+        assert_eq!(frames, vec![payload]);
+    }
+}
+
+#[test]
+fn test_feed_returns_frame_on_delimiter_only() {
+    let encoded = encode(&[1, 2, 3]);
+    let mut decoder = CobsFrameDecoder::<64>::new();
+
+    for &byte in &encoded[..encoded.len() - 1] {
+        assert!(decoder.feed(byte).is_none());
+    }
+    assert_eq!(decoder.feed(0), Some(&[1u8, 2, 3][..]));
+}
+
+#[test]
+fn test_empty_payload_frame() {
+    let mut decoder = CobsFrameDecoder::<64>::new();
+    assert_eq!(decoder.feed(0x01), None);
+    assert_eq!(decoder.feed(0x00), Some(&[][..]));
+}
+
+#[test]
+fn test_back_to_back_frames() {
+    let mut stream = Vec::new();
+    for payload in payloads() {
+        stream.extend(encode(&payload));
+    }
+
+    let mut decoder = CobsFrameDecoder::<1024>::new();
+    let mut count = 0;
+    let frames = decoder.feed_slice(&stream, |_| count += 1);
+    assert_eq!(frames, payloads().len());
+    assert_eq!(count, frames);
+}
+
+// --- Split reads ---
+
+#[test]
+fn test_frame_split_at_every_boundary() {
+    for payload in payloads() {
+        let encoded = encode(&payload);
+        for split in 0..=encoded.len() {
+            let (head, tail) = encoded.split_at(split);
+            let mut decoder = CobsFrameDecoder::<1024>::new();
+            let frames = decode_pieces(&mut decoder, &[head, tail]);
+            assert_eq!(frames, vec![payload.clone()], "split at {}", split);
+        }
+    }
+}
+
+#[test]
+fn test_two_frames_split_at_every_boundary() {
+    let first = vec![0x01, 0x00, 0x02];
+    let second = vec![0x00, 0xAA, 0xBB];
+    let mut stream = encode(&first);
+    stream.extend(encode(&second));
+
+    for split in 0..=stream.len() {
+        let (head, tail) = stream.split_at(split);
+        let mut decoder = CobsFrameDecoder::<64>::new();
+        let frames = decode_pieces(&mut decoder, &[head, tail]);
+        assert_eq!(
+            frames,
+            vec![first.clone(), second.clone()],
+            "split at {}",
+            split
+        );
+    }
+}
+
+#[test]
+fn test_byte_at_a_time() {
+    let payload: Vec<u8> = (0..300).map(|i| (i % 7) as u8).collect();
+    let encoded = encode(&payload);
+    let pieces: Vec<&[u8]> = encoded.chunks(1).collect();
+
+    let mut decoder = CobsFrameDecoder::<512>::new();
+    assert_eq!(decode_pieces(&mut decoder, &pieces), vec![payload]);
+}
+
+// --- Keepalives, oversize and resync ---
+
+#[test]
+fn test_delimiter_keepalives_ignored() {
+    let mut stream = vec![0x00, 0x00];
+    stream.extend(encode(&[7, 8]));
+    stream.extend([0x00, 0x00, 0x00]);
+
+    let mut decoder = CobsFrameDecoder::<64>::new();
+    assert_eq!(decode_pieces(&mut decoder, &[&stream]), vec![vec![7, 8]]);
+    assert_eq!(decoder.dropped_frames(), 0);
+}
+
+#[test]
+fn test_oversize_frame_dropped() {
+    let mut stream = encode(&[0x55; 40]);
+    stream.extend(encode(&[1, 2, 3]));
+
+    let mut decoder = CobsFrameDecoder::<32>::new();
+    assert_eq!(decode_pieces(&mut decoder, &[&stream]), vec![vec![1, 2, 3]]);
+    assert_eq!(decoder.dropped_frames(), 1);
+}
+
+#[test]
+fn test_frame_exactly_at_capacity() {
+    let payload = [0x00, 0x10, 0x20, 0x00];
+    let mut decoder = CobsFrameDecoder::<4>::new();
+    assert_eq!(
+        decode_pieces(&mut decoder, &[&encode(&payload)]),
+        vec![payload.to_vec()]
+    );
+
+    let mut decoder = CobsFrameDecoder::<3>::new();
+    assert!(decode_pieces(&mut decoder, &[&encode(&payload)]).is_empty());
+    assert_eq!(decoder.dropped_frames(), 1);
+}
+
+#[test]
+fn test_truncated_frame_resyncs() {
+    // Code byte promises four data bytes but the delimiter comes after two
+    let mut stream = vec![0x05, 0x11, 0x22, 0x00];
+    stream.extend(encode(&[9, 9]));
+
+    let mut decoder = CobsFrameDecoder::<64>::new();
+    assert_eq!(decode_pieces(&mut decoder, &[&stream]), vec![vec![9, 9]]);
+    assert_eq!(decoder.dropped_frames(), 1);
+}
+
+#[test]
+fn test_resync_after_joining_mid_frame() {
+    // Start listening halfway through a frame
+    let first = encode(&[1, 2, 3, 4, 5, 6]);
+    let mut stream = first[3..].to_vec();
+    stream.extend(encode(&[0xAB]));
+
+    let mut decoder = CobsFrameDecoder::<64>::new();
+    let frames = decode_pieces(&mut decoder, &[&stream]);
+    assert_eq!(frames.last(), Some(&vec![0xAB]));
+}
+
+#[test]
+fn test_reset_discards_partial_frame() {
+    let encoded = encode(&[1, 2, 3]);
+    let mut decoder = CobsFrameDecoder::<64>::new();
+    decoder.feed_slice(&encoded[..2], |_| panic!("no frame yet"));
+
+    decoder.reset();
+
+    let frames = decode_pieces(&mut decoder, &[&encode(&[4])]);
+    assert_eq!(frames, vec![vec![4]]);
+}
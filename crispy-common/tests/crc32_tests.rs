@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Cross-checks of the shared CRC-32 against the `crc` crate reference.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use crispy_common::crc32::{self, Digest};
+
+const REFERENCE: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Deterministic pseudo-random bytes (LCG) so failures are reproducible.
+fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+#[test]
+fn test_crc32_check_value() {
+    // Standard check value for CRC-32/ISO-HDLC
+    assert_eq!(crc32::checksum(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_crc32_empty() {
+    assert_eq!(crc32::checksum(&[]), 0);
+    assert_eq!(crc32::checksum(&[]), REFERENCE.checksum(&[]));
+}
+
+#[test]
+fn test_crc32_matches_reference_corpus() {
+    let corpus: Vec<Vec<u8>> = vec![
+        vec![0x00],
+        vec![0xFF],
+        vec![0x00; 256],
+        vec![0xFF; 4096],
+        (0..=255).collect(),
+        pseudo_random(1, 1),
+        pseudo_random(3, 2),
+        pseudo_random(1023, 3),
+        pseudo_random(1024, 4),
+        pseudo_random(65_537, 5),
+    ];
+
+    for data in &corpus {
+        assert_eq!(
+            crc32::checksum(data),
+            REFERENCE.checksum(data),
+            "mismatch for {} byte input",
+            data.len()
+        );
+    }
+}
+
+#[test]
+fn test_crc32_matches_reference_multi_megabyte() {
+    let data = pseudo_random(4 * 1024 * 1024 + 7, 42);
+    assert_eq!(crc32::checksum(&data), REFERENCE.checksum(&data));
+}
+
+#[test]
+fn test_crc32_streaming_matches_one_shot() {
+    let data = pseudo_random(10_000, 7);
+
+    for chunk_size in [1, 3, 64, 256, 1000, 9_999] {
+        let mut digest = Digest::new();
+        for chunk in data.chunks(chunk_size) {
+            digest.update(chunk);
+        }
+        assert_eq!(digest.finalize(), crc32::checksum(&data));
+    }
+}
+
+// --- Slice-by-4 against the byte-wise table ---
+
+/// The previous implementation: one 256-entry table lookup per byte.
+fn table_crc(data: &[u8]) -> u32 {
+    let table: Vec<u32> = (0..256u32)
+        .map(|i| {
+            (0..8).fold(i, |c, _| {
+                if c & 1 != 0 {
+                    (c >> 1) ^ 0xEDB8_8320
+                } else {
+                    c >> 1
+                }
+            })
+        })
+        .collect();
+    !data.iter().fold(0xFFFF_FFFFu32, |crc, &b| {
+        (crc >> 8) ^ table[((crc ^ b as u32) & 0xFF) as usize]
+    })
+}
+
+#[test]
+fn test_slice_by_4_matches_table_every_length_and_alignment() {
+    let data = pseudo_random(4096 + 3, 11);
+
+    for start in 0..4 {
+        for len in 0..=64 {
+            let slice = &data[start..start + len];
+            assert_eq!(
+                crc32::checksum(slice),
+                table_crc(slice),
+                "start {} len {}",
+                start,
+                len
+            );
+        }
+        let slice = &data[start..];
+        assert_eq!(crc32::checksum(slice), table_crc(slice), "start {}", start);
+    }
+}
+
+#[test]
+fn test_slice_by_4_matches_table_random_splits() {
+    for seed in 0..32 {
+        let data = pseudo_random(2000 + seed as usize * 37, seed);
+        let cuts = pseudo_random(16, seed + 100);
+
+        // Feed in pieces of 0..=255 bytes so word steps straddle the splits
+        let mut digest = Digest::new();
+        let mut rest = &data[..];
+        for &cut in cuts.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (head, tail) = rest.split_at((cut as usize).min(rest.len()));
+            digest.update(head);
+            rest = tail;
+        }
+        assert_eq!(digest.finalize(), table_crc(&data), "seed {}", seed);
+    }
+}
+
+// --- Memory-mapped (word-wise) path ---
+
+/// The pre-word-wise implementation: one volatile byte load at a time.
+fn bytewise_crc(data: &[u8]) -> u32 {
+    let mut digest = Digest::new();
+    for i in 0..data.len() {
+        let byte = unsafe { data.as_ptr().add(i).read_volatile() };
+        digest.update(&[byte]);
+    }
+    digest.finalize()
+}
+
+#[test]
+fn test_crc32_over_memory_matches_bytewise() {
+    let data = pseudo_random(64 * 1024 + 11, 3);
+
+    for start in 0..4 {
+        for len in [0, 1, 3, 4, 5, 1023, 1024, 4097, 40_000] {
+            let slice = &data[start..start + len];
+            let crc = unsafe { crc32::compute_over_memory(slice.as_ptr(), len as u32, |_| {}) };
+            assert_eq!(crc, bytewise_crc(slice), "start {} len {}", start, len);
+        }
+    }
+}
+
+#[test]
+fn test_crc32_over_memory_reports_chunks() {
+    let data = pseudo_random(3 * crc32::FLASH_CHUNK_SIZE as usize + 100, 9);
+    let mut progress = Vec::new();
+    let crc = unsafe {
+        crc32::compute_over_memory(data.as_ptr(), data.len() as u32, |done| progress.push(done))
+    };
+
+    assert_eq!(crc, crc32::checksum(&data));
+    let chunk = crc32::FLASH_CHUNK_SIZE;
+    assert_eq!(
+        progress,
+        vec![chunk, 2 * chunk, 3 * chunk, data.len() as u32]
+    );
+}
@@ -0,0 +1,165 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Tests for the flash simulator: NOR rules, counters and fault injection.
+
+use crispy_common::flash::{read_boot_data, write_boot_data, write_to_bank, FlashError};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::{Fault, FlashSim};
+use crispy_common::protocol::{
+    BootData, BootDataError, BOOT_DATA_ADDR, FLASH_BASE, FLASH_PAGE_SIZE, FW_A_ADDR,
+};
+
+const PAGE: usize = FLASH_PAGE_SIZE as usize;
+
+// --- NOR rules ---
+
+#[test]
+fn test_program_over_programmed_bytes_ands() {
+    let mut flash = FlashSim::new();
+    flash.program(0, &[0xF0; PAGE]);
+    flash.program(0, &[0x3C; PAGE]);
+
+    assert_eq!(flash.contents()[0], 0x30);
+}
+
+#[test]
+#[should_panic(expected = "program over unerased byte")]
+fn test_strict_panics_on_program_without_erase() {
+    let mut flash = FlashSim::new().strict();
+    flash.program(0, &[0xF0; PAGE]);
+    flash.program(0, &[0x3C; PAGE]);
+}
+
+#[test]
+fn test_strict_allows_program_after_erase() {
+    let mut flash = FlashSim::new().strict();
+    flash.program(0, &[0xF0; PAGE]);
+    flash.erase(0, FlashSim::SECTOR_SIZE);
+    flash.program(0, &[0x3C; PAGE]);
+
+    assert_eq!(flash.contents()[0], 0x3C);
+}
+
+#[test]
+fn test_strict_write_boot_data_twice() {
+    let mut flash = FlashSim::new().strict();
+    write_boot_data(&mut flash, &BootData::default_new()).unwrap();
+    write_boot_data(&mut flash, &BootData::default_new()).unwrap();
+
+    assert!(read_boot_data(&flash).is_ok());
+}
+
+// --- Counters ---
+
+#[test]
+fn test_per_sector_counters() {
+    let mut flash = FlashSim::new();
+    write_boot_data(&mut flash, &BootData::default_new()).unwrap();
+    write_boot_data(&mut flash, &BootData::default_new()).unwrap();
+
+    assert_eq!(flash.sector_erases(BOOT_DATA_ADDR), 2);
+    assert_eq!(flash.sector_programs(BOOT_DATA_ADDR), 2);
+    assert_eq!(flash.sector_erases(FW_A_ADDR), 0);
+    assert_eq!(flash.sector_programs(FLASH_BASE), 0);
+}
+
+#[test]
+fn test_multi_sector_erase_counts_each_sector() {
+    let mut flash = FlashSim::new();
+    flash.erase(0, 2 * FlashSim::SECTOR_SIZE);
+
+    assert_eq!(flash.erase_count, 1);
+    assert_eq!(flash.sector_erases(FLASH_BASE), 1);
+    assert_eq!(flash.sector_erases(FLASH_BASE + FlashSim::SECTOR_SIZE), 1);
+    assert_eq!(
+        flash.sector_erases(FLASH_BASE + 2 * FlashSim::SECTOR_SIZE),
+        0
+    );
+}
+
+// --- Failed operations ---
+
+#[test]
+fn test_write_to_bank_retries_failed_program() {
+    let mut flash = FlashSim::new();
+    flash.inject(Fault::FailOp(1));
+
+    assert_eq!(write_to_bank(&mut flash, 0, 0, &[0x5A; PAGE]), Ok(()));
+    assert_eq!(flash.program_count, 2);
+    assert_eq!(flash.contents()[(FW_A_ADDR - FLASH_BASE) as usize], 0x5A);
+}
+
+#[test]
+fn test_write_boot_data_retries_failed_program() {
+    let mut flash = FlashSim::new();
+    // Erase, then a program that does nothing
+    flash.inject(Fault::FailOp(2));
+
+    assert_eq!(
+        write_boot_data(&mut flash, &BootData::default_new()),
+        Ok(())
+    );
+    assert_eq!((flash.erase_count, flash.program_count), (2, 2));
+    assert!(read_boot_data(&flash).is_ok());
+}
+
+#[test]
+fn test_failed_op_counts_from_injection() {
+    let mut flash = FlashSim::new();
+    flash.program(0, &[0x00; PAGE]);
+    flash.inject(Fault::FailOp(2));
+    flash.erase(0, FlashSim::SECTOR_SIZE);
+    flash.program(0, &[0x11; PAGE]);
+    flash.program(PAGE as u32, &[0x22; PAGE]);
+
+    assert_eq!(flash.contents()[0], 0xFF);
+    assert_eq!(flash.contents()[PAGE], 0x22);
+}
+
+// --- Power loss ---
+
+#[test]
+fn test_power_loss_during_boot_data_write() {
+    let mut flash = FlashSim::new();
+    write_boot_data(&mut flash, &BootData::default_new()).unwrap();
+
+    let bd = BootData {
+        active_bank: 1,
+        ..BootData::default_new()
+    };
+    flash.inject(Fault::PowerLoss { op: 2, bytes: 40 });
+    assert_eq!(
+        write_boot_data(&mut flash, &bd),
+        Err(FlashError::Verify(40))
+    );
+
+    assert!(!flash.is_powered());
+    assert_eq!(read_boot_data(&flash), Err(BootDataError::BadCrc));
+}
+
+#[test]
+fn test_power_loss_during_erase_is_partial() {
+    let mut flash = FlashSim::new();
+    flash.program(0, &[0x00; PAGE]);
+    flash.program(PAGE as u32, &[0x00; PAGE]);
+    flash.inject(Fault::PowerLoss { op: 1, bytes: PAGE });
+    flash.erase(0, FlashSim::SECTOR_SIZE);
+
+    assert!(flash.contents()[..PAGE].iter().all(|&b| b == 0xFF));
+    assert!(flash.contents()[PAGE..2 * PAGE].iter().all(|&b| b == 0x00));
+}
+
+#[test]
+fn test_no_writes_until_power_cycle() {
+    let mut flash = FlashSim::new();
+    flash.inject(Fault::PowerLoss { op: 1, bytes: 0 });
+    flash.erase(0, FlashSim::SECTOR_SIZE);
+    flash.program(0, &[0x00; PAGE]);
+    assert_eq!(flash.contents()[0], 0xFF);
+
+    flash.power_cycle();
+    assert!(flash.is_powered());
+    flash.program(0, &[0x00; PAGE]);
+    assert_eq!(flash.contents()[0], 0x00);
+}
@@ -0,0 +1,545 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for BootData persistence and bank writes over the flash simulator.
+
+use crispy_common::crc32;
+use crispy_common::flash::{
+    compute_crc32, confirm_boot, erase_bank, inactive_bank, read_boot_data, set_active_bank,
+    update_bank_metadata, verify_bank, write_boot_data, write_to_bank, BankVerify, BankWriter,
+    ConfirmOutcome, FlashError,
+};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::{Fault, FlashSim};
+use crispy_common::protocol::{
+    BootData, BootDataError, BOOT_DATA_ADDR, BOOT_DATA_MAGIC, BOOT_DATA_SIZE, BOOT_DATA_VERSION,
+    FLASH_BASE, FLASH_PAGE_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR,
+};
+use crispy_common::version::FwVersion;
+
+fn flash_with(bd: &BootData) -> FlashSim {
+    let mut flash = FlashSim::new();
+    write_boot_data(&mut flash, bd).unwrap();
+    flash
+}
+
+// --- Flash simulator behaviour ---
+
+#[test]
+fn test_flash_sim_starts_erased() {
+    let flash = FlashSim::new();
+    assert!(flash.contents().iter().all(|&b| b == 0xFF));
+    assert!(read_boot_data(&flash).is_err());
+}
+
+#[test]
+fn test_flash_sim_program_only_clears_bits() {
+    let mut flash = FlashSim::new();
+    let offset = FW_A_ADDR - FLASH_BASE;
+    flash.program(offset, &[0x0F; FLASH_PAGE_SIZE as usize]);
+    flash.program(offset, &[0xF0; FLASH_PAGE_SIZE as usize]);
+
+    let mut buf = [0xAA; 4];
+    flash.read(FW_A_ADDR, &mut buf);
+    assert_eq!(buf, [0x00; 4]);
+
+    flash.erase(offset, FlashSim::SECTOR_SIZE);
+    flash.read(FW_A_ADDR, &mut buf);
+    assert_eq!(buf, [0xFF; 4]);
+}
+
+#[test]
+#[should_panic(expected = "unaligned erase")]
+fn test_flash_sim_rejects_unaligned_erase() {
+    FlashSim::new().erase(0x100, FlashSim::SECTOR_SIZE);
+}
+
+// --- BootData persistence ---
+
+#[test]
+fn test_boot_data_roundtrip() {
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.version_b = FwVersion::from_raw(7);
+    bd.crc_b = 0x1234_5678;
+    bd.size_b = 4096;
+
+    let flash = flash_with(&bd);
+    let read = read_boot_data(&flash).unwrap();
+    assert_eq!(read.to_bytes(), bd.to_bytes());
+    assert_eq!(flash.erase_count, 1);
+    assert_eq!(flash.program_count, 1);
+}
+
+#[test]
+fn test_boot_data_rewrite_erases_first() {
+    let mut flash = flash_with(&BootData::default_new());
+    let mut bd = BootData::default_new();
+    bd.size_a = 0xFFFF_0000;
+    write_boot_data(&mut flash, &bd).unwrap();
+    bd.size_a = 0x0000_FFFF;
+    write_boot_data(&mut flash, &bd).unwrap();
+
+    assert_eq!(read_boot_data(&flash).unwrap().size_a, 0x0000_FFFF);
+}
+
+#[test]
+fn test_boot_data_page_padding() {
+    let flash = flash_with(&BootData::default_new());
+    let start = (BOOT_DATA_ADDR - FLASH_BASE) as usize;
+    let page = &flash.contents()[start..start + FLASH_PAGE_SIZE as usize];
+    assert!(page[BOOT_DATA_SIZE..].iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn test_v1_boot_data_upgraded_on_next_write() {
+    let mut flash = FlashSim::new();
+    let mut v1 = [0u8; 32];
+    v1[..4].copy_from_slice(&BOOT_DATA_MAGIC.to_le_bytes());
+    v1[6] = 2; // boot_attempts
+    flash.poke(BOOT_DATA_ADDR, &v1);
+
+    assert_eq!(read_boot_data(&flash).unwrap().boot_attempts, 2);
+
+    assert_eq!(confirm_boot(&mut flash), Ok(ConfirmOutcome::Confirmed));
+    let start = (BOOT_DATA_ADDR - FLASH_BASE) as usize;
+    let stored = &flash.contents()[start..start + BOOT_DATA_SIZE];
+    assert_eq!(stored[32], BOOT_DATA_VERSION);
+    let read = read_boot_data(&flash).unwrap();
+    assert_eq!((read.confirmed, read.boot_attempts), (1, 0));
+}
+
+#[test]
+fn test_future_boot_data_is_not_confirmed() {
+    let mut flash = flash_with(&BootData::default_new());
+    flash.poke(BOOT_DATA_ADDR + 32, &[BOOT_DATA_VERSION + 1]);
+
+    assert_eq!(
+        read_boot_data(&flash),
+        Err(BootDataError::UnknownVersion(BOOT_DATA_VERSION + 1))
+    );
+    assert_eq!(
+        confirm_boot(&mut flash),
+        Err(FlashError::BootData(BootDataError::UnknownVersion(
+            BOOT_DATA_VERSION + 1
+        )))
+    );
+    assert_eq!(flash.erase_count, 1);
+}
+
+// --- Confirmation ---
+
+#[test]
+fn test_confirm_boot_sets_confirmed() {
+    let mut bd = BootData::default_new();
+    bd.boot_attempts = 2;
+    let mut flash = flash_with(&bd);
+
+    assert_eq!(confirm_boot(&mut flash), Ok(ConfirmOutcome::Confirmed));
+    let read = read_boot_data(&flash).unwrap();
+    assert_eq!(read.confirmed, 1);
+    assert_eq!(read.boot_attempts, 0);
+}
+
+#[test]
+fn test_confirm_boot_invalid_data() {
+    let mut flash = FlashSim::new();
+    assert_eq!(
+        confirm_boot(&mut flash),
+        Err(FlashError::BootData(BootDataError::BadMagic(0xFFFF_FFFF)))
+    );
+    assert_eq!(flash.erase_count, 0);
+}
+
+#[test]
+fn test_confirm_boot_already_confirmed_does_not_write() {
+    let mut bd = BootData::default_new();
+    bd.confirmed = 1;
+    let mut flash = flash_with(&bd);
+
+    assert_eq!(
+        confirm_boot(&mut flash),
+        Ok(ConfirmOutcome::AlreadyConfirmed)
+    );
+    assert_eq!(flash.erase_count, 1);
+}
+
+#[test]
+fn test_confirm_boot_reports_failed_write() {
+    let mut flash = flash_with(&BootData::default_new());
+    // Every erase and program from here on has no effect
+    flash.inject(Fault::PowerLoss { op: 1, bytes: 0 });
+
+    assert!(matches!(
+        confirm_boot(&mut flash),
+        Err(FlashError::Verify(_))
+    ));
+    assert_eq!(read_boot_data(&flash).unwrap().confirmed, 0);
+}
+
+// --- Bank selection and metadata ---
+
+/// Flash whose BootData records `data` in bank B, unconfirmed after one boot.
+fn flash_with_image_b(data: &[u8]) -> FlashSim {
+    let mut bd = BootData::default_new();
+    bd.confirmed = 1;
+    bd.boot_attempts = 1;
+    bd.size_b = data.len() as u32;
+    bd.crc_b = crc32::checksum(data);
+    let mut flash = flash_with(&bd);
+
+    let mut writer = BankWriter::new(&mut flash, 1);
+    writer.write(data).unwrap();
+    writer.finish(None).unwrap();
+    flash
+}
+
+#[test]
+fn test_set_active_bank() {
+    let mut flash = flash_with_image_b(&image(1000));
+
+    assert_eq!(set_active_bank(&mut flash, 1), Ok(()));
+    let read = read_boot_data(&flash).unwrap();
+    assert_eq!(read.active_bank, 1);
+    assert_eq!(read.confirmed, 0);
+    assert_eq!(read.boot_attempts, 0);
+    assert_eq!(inactive_bank(&flash), 0);
+
+    assert_eq!(
+        set_active_bank(&mut flash, 2),
+        Err(FlashError::InvalidBank(2))
+    );
+}
+
+#[test]
+fn test_set_active_bank_refuses_unverified_bank() {
+    let mut flash = flash_with_image_b(&image(1000));
+    flash.poke(FW_B_ADDR + 10, &[0x00]);
+    let before = flash.erase_count;
+
+    assert!(matches!(
+        set_active_bank(&mut flash, 1),
+        Err(FlashError::Bank(BankVerify::CrcMismatch { .. }))
+    ));
+    assert_eq!(
+        set_active_bank(&mut flash, 0),
+        Err(FlashError::Bank(BankVerify::NoMetadata))
+    );
+    assert_eq!(flash.erase_count, before);
+    assert_eq!(read_boot_data(&flash).unwrap().active_bank, 0);
+}
+
+#[test]
+fn test_set_active_bank_needs_valid_boot_data() {
+    let mut flash = FlashSim::new();
+    assert_eq!(
+        set_active_bank(&mut flash, 1),
+        Err(FlashError::BootData(BootDataError::BadMagic(0xFFFF_FFFF)))
+    );
+    assert!(read_boot_data(&flash).is_err());
+}
+
+// --- Bank verification ---
+
+#[test]
+fn test_verify_bank_ok() {
+    let data = image(3000);
+    let flash = flash_with_image_b(&data);
+    assert_eq!(
+        verify_bank(&flash, 1),
+        BankVerify::Ok {
+            size: 3000,
+            crc: crc32::checksum(&data)
+        }
+    );
+    assert!(verify_bank(&flash, 1).is_ok());
+}
+
+#[test]
+fn test_verify_bank_without_metadata() {
+    assert_eq!(verify_bank(&FlashSim::new(), 0), BankVerify::NoMetadata);
+
+    let flash = flash_with_image_b(&image(100));
+    assert_eq!(verify_bank(&flash, 0), BankVerify::NoMetadata);
+    assert_eq!(verify_bank(&flash, 7), BankVerify::BadBank(7));
+}
+
+#[test]
+fn test_verify_bank_size_out_of_range() {
+    let mut bd = BootData::default_new();
+    bd.size_a = FW_BANK_SIZE + 1;
+    let flash = flash_with(&bd);
+    assert_eq!(
+        verify_bank(&flash, 0),
+        BankVerify::SizeOutOfRange(FW_BANK_SIZE + 1)
+    );
+}
+
+#[test]
+fn test_verify_bank_crc_mismatch_reports_computed() {
+    let mut data = image(512);
+    let mut flash = flash_with_image_b(&data);
+    let expected = crc32::checksum(&data);
+    data[511] = 0;
+    flash.poke(FW_B_ADDR + 511, &[0]);
+
+    assert_eq!(
+        verify_bank(&flash, 1),
+        BankVerify::CrcMismatch {
+            expected,
+            computed: crc32::checksum(&data)
+        }
+    );
+}
+
+#[test]
+fn test_update_bank_metadata_keeps_other_bank() {
+    let mut bd = BootData::default_new();
+    bd.version_a = FwVersion::from_raw(3);
+    bd.size_a = 100;
+    let mut flash = flash_with(&bd);
+
+    update_bank_metadata(&mut flash, 1, 2048, 0xCAFE_F00D, FwVersion::from_raw(9)).unwrap();
+    let read = read_boot_data(&flash).unwrap();
+    assert_eq!((read.version_a, read.size_a), (FwVersion::from_raw(3), 100));
+    assert_eq!(
+        (read.version_b, read.size_b, read.crc_b),
+        (FwVersion::from_raw(9), 2048, 0xCAFE_F00D)
+    );
+}
+
+#[test]
+fn test_update_bank_metadata_rejects_bad_arguments() {
+    let mut flash = flash_with(&BootData::default_new());
+    let version = FwVersion::from_raw(1);
+
+    assert_eq!(
+        update_bank_metadata(&mut flash, 2, 2048, 0, version),
+        Err(FlashError::InvalidBank(2))
+    );
+    assert_eq!(
+        update_bank_metadata(&mut flash, 0, FW_BANK_SIZE + 1, 0, version),
+        Err(FlashError::SizeTooLarge(FW_BANK_SIZE + 1))
+    );
+    assert_eq!(flash.erase_count, 1);
+}
+
+// --- Bank writes ---
+
+#[test]
+fn test_write_to_bank_and_crc() {
+    let mut flash = FlashSim::new();
+    let data: Vec<u8> = (0..2048u32).map(|i| (i * 7) as u8).collect();
+
+    assert_eq!(write_to_bank(&mut flash, 1, 0x400, &data), Ok(()));
+    assert_eq!(
+        compute_crc32(&flash, FW_B_ADDR + 0x400, data.len() as u32),
+        crc32::checksum(&data)
+    );
+}
+
+#[test]
+fn test_write_to_bank_reports_unerased_byte() {
+    let mut flash = FlashSim::new();
+    flash.poke(FW_A_ADDR + 0x10, &[0x00]);
+    let data = [0x5A; FLASH_PAGE_SIZE as usize];
+
+    assert_eq!(
+        write_to_bank(&mut flash, 0, 0, &data),
+        Err(FlashError::Verify(0x10))
+    );
+    // One retry after the first mismatch
+    assert_eq!(flash.program_count, 2);
+}
+
+#[test]
+fn test_write_to_bank_rejects_out_of_range() {
+    let mut flash = FlashSim::new();
+    let data = [0u8; FLASH_PAGE_SIZE as usize];
+    assert_eq!(
+        write_to_bank(&mut flash, 0, FW_BANK_SIZE, &data),
+        Err(FlashError::OutOfRange(FW_BANK_SIZE))
+    );
+    assert_eq!(
+        write_to_bank(&mut flash, 2, 0, &data),
+        Err(FlashError::InvalidBank(2))
+    );
+    assert_eq!(flash.program_count, 0);
+}
+
+#[test]
+fn test_erase_bank_only_touches_bank() {
+    let mut flash = FlashSim::new();
+    let data = [0u8; FLASH_PAGE_SIZE as usize];
+    write_to_bank(&mut flash, 0, 0, &data).unwrap();
+    write_to_bank(&mut flash, 1, 0, &data).unwrap();
+
+    erase_bank(&mut flash, 0).unwrap();
+    assert_eq!(erase_bank(&mut flash, 2), Err(FlashError::InvalidBank(2)));
+
+    let mut buf = [0u8; 4];
+    flash.read(FW_A_ADDR, &mut buf);
+    assert_eq!(buf, [0xFF; 4]);
+    flash.read(FW_B_ADDR, &mut buf);
+    assert_eq!(buf, [0x00; 4]);
+}
+
+#[test]
+fn test_erase_bank_retries_an_erase_that_did_not_take() {
+    let mut flash = FlashSim::new();
+    write_to_bank(&mut flash, 0, 0, &[0u8; FLASH_PAGE_SIZE as usize]).unwrap();
+
+    flash.inject(Fault::FailOp(1));
+    let erases = flash.erase_count;
+    assert_eq!(erase_bank(&mut flash, 0), Ok(()));
+    assert_eq!(flash.erase_count - erases, 2);
+
+    let mut buf = [0u8; 4];
+    flash.read(FW_A_ADDR, &mut buf);
+    assert_eq!(buf, [0xFF; 4]);
+}
+
+#[test]
+fn test_erase_bank_reports_stuck_byte() {
+    let mut flash = FlashSim::new();
+    flash.inject(Fault::StuckByte {
+        addr: FW_A_ADDR + 0x1234,
+        value: 0xA5,
+    });
+
+    let erases = flash.erase_count;
+    assert_eq!(erase_bank(&mut flash, 0), Err(FlashError::Verify(0x1234)));
+    assert_eq!(flash.erase_count - erases, 2);
+
+    // Bank B is unaffected
+    assert_eq!(erase_bank(&mut flash, 1), Ok(()));
+    assert_eq!(flash.erase_count - erases, 3);
+}
+
+// --- BankWriter ---
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + i / 256) as u8).collect()
+}
+
+/// Feed `data` to a fresh writer for bank B in slices of `sizes`, cycling.
+fn write_image(flash: &mut FlashSim, data: &[u8], sizes: &[usize]) -> (u32, u32) {
+    let mut writer = BankWriter::new(flash, 1);
+    let mut rest = data;
+    for &n in sizes.iter().cycle() {
+        if rest.is_empty() {
+            break;
+        }
+        let (head, tail) = rest.split_at(n.min(rest.len()));
+        writer.write(head).unwrap();
+        rest = tail;
+    }
+    assert_eq!(writer.size(), data.len() as u32);
+    writer.finish(None).unwrap()
+}
+
+#[test]
+fn test_bank_writer_arbitrary_write_sizes() {
+    let data = image(5000);
+    let slicings: [&[usize]; 7] = [
+        &[1],
+        &[7, 300],
+        &[255],
+        &[256],
+        &[257, 3],
+        &[1000, 24],
+        &[5000],
+    ];
+
+    for sizes in slicings {
+        let mut flash = FlashSim::new().strict();
+        let (size, crc) = write_image(&mut flash, &data, sizes);
+        assert_eq!(size, 5000);
+        assert_eq!(crc, crc32::checksum(&data), "{:?}", sizes);
+
+        let mut written = vec![0u8; 5120];
+        flash.read(FW_B_ADDR, &mut written);
+        assert_eq!(&written[..5000], &data[..], "{:?}", sizes);
+        assert!(written[5000..].iter().all(|&b| b == 0xFF));
+        assert_eq!(compute_crc32(&flash, FW_B_ADDR, size), crc);
+    }
+}
+
+#[test]
+fn test_bank_writer_programs_each_page_once() {
+    let mut flash = FlashSim::new().strict();
+    write_image(&mut flash, &image(1000), &[100]);
+    assert_eq!(flash.program_count, 4);
+
+    // Whole pages handed over at once go out in a single program
+    let mut flash = FlashSim::new().strict();
+    write_image(&mut flash, &image(4096), &[4096]);
+    assert_eq!(flash.program_count, 1);
+}
+
+#[test]
+fn test_bank_writer_empty_image() {
+    let mut flash = FlashSim::new();
+    let writer = BankWriter::new(&mut flash, 0);
+    assert_eq!(writer.finish(None), Ok((0, crc32::checksum(&[]))));
+    assert_eq!(flash.program_count, 0);
+}
+
+#[test]
+fn test_bank_writer_finish_records_metadata() {
+    let mut flash = flash_with(&BootData::default_new());
+    let data = image(700);
+
+    let mut writer = BankWriter::new(&mut flash, 1);
+    writer.write(&data).unwrap();
+    let (size, crc) = writer.finish(Some(FwVersion::from_raw(42))).unwrap();
+
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!(
+        (bd.size_b, bd.crc_b, bd.version_b),
+        (size, crc, FwVersion::from_raw(42))
+    );
+    assert_eq!(bd.size_a, 0);
+    // Writing an image does not switch to it
+    assert_eq!(bd.active_bank, 0);
+}
+
+#[test]
+fn test_bank_writer_finish_without_metadata() {
+    let mut flash = flash_with(&BootData::default_new());
+    let mut writer = BankWriter::new(&mut flash, 1);
+    writer.write(&image(300)).unwrap();
+    writer.finish(None).unwrap();
+
+    assert_eq!(read_boot_data(&flash).unwrap().size_b, 0);
+}
+
+#[test]
+fn test_bank_writer_refuses_data_past_bank_end() {
+    let mut flash = FlashSim::new();
+    let mut writer = BankWriter::new(&mut flash, 0);
+    writer
+        .write(&vec![0u8; FW_BANK_SIZE as usize - 10])
+        .unwrap();
+
+    assert_eq!(
+        writer.write(&[0u8; 11]),
+        Err(FlashError::OutOfRange(FW_BANK_SIZE))
+    );
+    // The refused slice was not taken, the rest still fits
+    assert_eq!(writer.size(), FW_BANK_SIZE - 10);
+    writer.write(&[0u8; 10]).unwrap();
+    assert_eq!(writer.finish(None).unwrap().0, FW_BANK_SIZE);
+}
+
+#[test]
+fn test_bank_writer_reports_failed_page() {
+    let mut flash = FlashSim::new();
+    // The second page program and everything after it has no effect
+    flash.inject(Fault::PowerLoss { op: 2, bytes: 0 });
+
+    let mut writer = BankWriter::new(&mut flash, 0);
+    writer.write(&image(200)).unwrap();
+    assert_eq!(writer.write(&image(200)), Ok(()));
+    assert_eq!(writer.write(&image(200)), Err(FlashError::Verify(0x100)));
+}
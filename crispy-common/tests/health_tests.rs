@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Tests for health-gated boot confirmation.
+
+use std::cell::Cell;
+
+use crispy_common::flash::{read_boot_data, write_boot_data, ConfirmOutcome, FlashError};
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::health::{confirm_when_healthy, Health, HealthGate, HealthOutcome, Verdict};
+use crispy_common::protocol::BootData;
+
+const TIMEOUT: u64 = 10_000_000;
+
+/// Flash where the bootloader has just started a new image, unconfirmed.
+fn first_boot() -> FlashSim {
+    let mut bd = BootData::default_new();
+    bd.active_bank = 1;
+    bd.boot_attempts = 1;
+    let mut flash = FlashSim::new();
+    write_boot_data(&mut flash, &bd).unwrap();
+    flash
+}
+
+/// Run [`confirm_when_healthy`] with a clock that advances 100 ms per check
+/// and checks answered by `health(check number)`; returns the outcome and
+/// how many checks ran.
+fn run(
+    flash: &mut FlashSim,
+    mut health: impl FnMut(u32) -> Health,
+) -> (Result<HealthOutcome, FlashError>, u32) {
+    let clock = Cell::new(5_000u64);
+    let mut checks = 0;
+    let outcome = confirm_when_healthy(
+        flash,
+        TIMEOUT,
+        || clock.get(),
+        || {
+            clock.set(clock.get() + 100_000);
+            checks += 1;
+            health(checks)
+        },
+    );
+    (outcome, checks)
+}
+
+// --- Decision ---
+
+#[test]
+fn test_and_combines_checks() {
+    use Health::*;
+    assert_eq!(Healthy.and(Healthy), Healthy);
+    assert_eq!(Healthy.and(Pending), Pending);
+    assert_eq!(Pending.and(Healthy), Pending);
+    assert_eq!(Pending.and(Failed), Failed);
+    assert_eq!(Failed.and(Healthy), Failed);
+}
+
+#[test]
+fn test_gate_decides() {
+    let gate = HealthGate::new(1_000, 500);
+    assert_eq!(gate.deadline_us(), 1_500);
+    assert_eq!(gate.decide(1_200, Health::Pending), Verdict::Wait);
+    assert_eq!(gate.decide(1_200, Health::Healthy), Verdict::Confirm);
+    assert_eq!(gate.decide(1_200, Health::Failed), Verdict::Failed);
+    // Up to and including the deadline
+    assert_eq!(gate.decide(1_500, Health::Healthy), Verdict::Confirm);
+    assert_eq!(gate.decide(1_501, Health::Healthy), Verdict::TimedOut);
+    assert_eq!(gate.decide(1_501, Health::Pending), Verdict::TimedOut);
+    assert_eq!(gate.decide(1_501, Health::Failed), Verdict::Failed);
+}
+
+#[test]
+fn test_gate_deadline_saturates() {
+    let gate = HealthGate::new(u64::MAX - 10, 100);
+    assert_eq!(gate.deadline_us(), u64::MAX);
+    assert_eq!(gate.decide(u64::MAX, Health::Pending), Verdict::Wait);
+}
+
+// --- Confirmation ---
+
+#[test]
+fn test_confirms_once_healthy() {
+    let mut flash = first_boot();
+    let (outcome, checks) = run(&mut flash, |n| {
+        if n < 20 {
+            Health::Pending
+        } else {
+            Health::Healthy
+        }
+    });
+    assert_eq!(
+        outcome,
+        Ok(HealthOutcome::Confirmed(ConfirmOutcome::Confirmed))
+    );
+    assert_eq!(checks, 20);
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!((bd.confirmed, bd.boot_attempts), (1, 0));
+}
+
+#[test]
+fn test_times_out_unconfirmed() {
+    let mut flash = first_boot();
+    let (outcome, checks) = run(&mut flash, |_| Health::Pending);
+    assert_eq!(outcome, Ok(HealthOutcome::TimedOut));
+    // 10 s at 100 ms a check, and the one that found the deadline passed
+    assert_eq!(checks, 101);
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!((bd.confirmed, bd.boot_attempts), (0, 1));
+}
+
+#[test]
+fn test_failure_stops_at_once() {
+    let mut flash = first_boot();
+    let (outcome, checks) = run(&mut flash, |n| {
+        if n == 3 {
+            Health::Failed
+        } else {
+            Health::Pending
+        }
+    });
+    assert_eq!(outcome, Ok(HealthOutcome::Failed));
+    assert_eq!(checks, 3);
+    assert_eq!(read_boot_data(&flash).unwrap().confirmed, 0);
+}
+
+#[test]
+fn test_confirmed_boot_skips_checks() {
+    let mut flash = first_boot();
+    let mut bd = read_boot_data(&flash).unwrap();
+    bd.confirmed = 1;
+    write_boot_data(&mut flash, &bd).unwrap();
+
+    let (outcome, checks) = run(&mut flash, |_| Health::Failed);
+    assert_eq!(
+        outcome,
+        Ok(HealthOutcome::Confirmed(ConfirmOutcome::AlreadyConfirmed))
+    );
+    assert_eq!(checks, 0);
+}
+
+#[test]
+fn test_unreadable_boot_data() {
+    let mut flash = FlashSim::new();
+    let (outcome, checks) = run(&mut flash, |_| Health::Healthy);
+    assert!(matches!(outcome, Err(FlashError::BootData(_))));
+    assert_eq!(checks, 0);
+    assert!(flash.contents().iter().all(|&b| b == 0xFF));
+}
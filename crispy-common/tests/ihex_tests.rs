@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Tests for Intel HEX parsing.
+
+use crispy_common::ihex::{self, IhexError, IhexImage};
+use crispy_common::protocol::FW_A_ADDR;
+
+/// Build a record with a correct checksum.
+fn record(offset: u16, kind: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&offset.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    bytes.push(sum.wrapping_neg());
+    let hex: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", hex)
+}
+
+fn upper(addr: u32) -> String {
+    record(0, 0x04, &((addr >> 16) as u16).to_be_bytes())
+}
+
+const EOF: &str = ":00000001FF\n";
+
+fn parse(text: &str) -> Result<IhexImage, IhexError> {
+    ihex::parse(text, 16)
+}
+
+#[test]
+fn test_parse_known_file() {
+    let text = ":020000041000EA\n:04000000004200209A\n:00000001FF\n";
+    let image = parse(text).unwrap();
+    assert_eq!(image.base, 0x1000_0000);
+    assert_eq!(image.data, [0x00, 0x42, 0x00, 0x20]);
+    assert_eq!(image.entry, None);
+}
+
+#[test]
+fn test_round_trip() {
+    let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+    let image = parse(&ihex::encode(&data, FW_A_ADDR)).unwrap();
+    assert_eq!(image.base, FW_A_ADDR);
+    assert_eq!(image.data, data);
+}
+
+#[test]
+fn test_segment_crossing() {
+    // Data just below and just above a 64KB boundary, in separate segments
+    let base = FW_A_ADDR + 0x1_0000 - 8;
+    let text = upper(base)
+        + &record(base as u16, 0x00, &[1; 8])
+        + &upper(base + 8)
+        + &record(0, 0x00, &[2; 8])
+        + EOF;
+    let image = parse(&text).unwrap();
+    assert_eq!(image.base, base);
+    assert_eq!(image.data, [[1u8; 8], [2u8; 8]].concat());
+
+    // The encoder emits a new segment at each boundary
+    let data = vec![0x5A; 0x2_0010];
+    let text = ihex::encode(&data, FW_A_ADDR + 0x8000);
+    assert_eq!(text.matches(":02000004").count(), 3);
+    assert_eq!(parse(&text).unwrap().data, data);
+}
+
+#[test]
+fn test_segments_in_any_order() {
+    let text = upper(FW_A_ADDR + 0x1_0000)
+        + &record(0, 0x00, &[2; 4])
+        + &upper(FW_A_ADDR)
+        + &record(0xFFFC, 0x00, &[1; 4])
+        + EOF;
+    let image = parse(&text).unwrap();
+    assert_eq!(image.base, FW_A_ADDR + 0xFFFC);
+    assert_eq!(image.data, [1, 1, 1, 1, 2, 2, 2, 2]);
+}
+
+#[test]
+fn test_bad_checksum() {
+    let good = upper(FW_A_ADDR) + &record(0, 0x00, &[1, 2, 3, 4]) + EOF;
+    assert!(parse(&good).is_ok());
+
+    // Corrupt a data byte, then the checksum byte itself
+    let mut lines: Vec<String> = good.lines().map(String::from).collect();
+    lines[1].replace_range(9..11, "FF");
+    assert_eq!(
+        parse(&lines.join("\n")),
+        Err(IhexError::BadChecksum { line: 2 })
+    );
+
+    let mut lines: Vec<String> = good.lines().map(String::from).collect();
+    let len = lines[0].len();
+    lines[0].replace_range(len - 2.., "00");
+    assert_eq!(
+        parse(&lines.join("\n")),
+        Err(IhexError::BadChecksum { line: 1 })
+    );
+}
+
+#[test]
+fn test_gap_fill_and_limit() {
+    let text = upper(FW_A_ADDR) + &record(0, 0x00, &[1; 4]) + &record(20, 0x00, &[2; 4]) + EOF;
+    let image = parse(&text).unwrap();
+    assert_eq!(image.data.len(), 24);
+    assert!(image.data[4..20].iter().all(|&b| b == 0xFF));
+
+    assert_eq!(
+        ihex::parse(&text, 15),
+        Err(IhexError::Gap {
+            addr: FW_A_ADDR + 20,
+            len: 16
+        })
+    );
+}
+
+#[test]
+fn test_overlap_rejected() {
+    let text = upper(FW_A_ADDR) + &record(0, 0x00, &[1; 8]) + &record(4, 0x00, &[2; 8]) + EOF;
+    assert_eq!(
+        parse(&text),
+        Err(IhexError::Overlap {
+            addr: FW_A_ADDR + 4
+        })
+    );
+}
+
+#[test]
+fn test_start_address() {
+    let text = upper(0x2000_0000)
+        + &record(0, 0x00, &[0; 8])
+        + &record(0, 0x05, &0x2000_00C1u32.to_be_bytes())
+        + EOF;
+    assert_eq!(parse(&text).unwrap().entry, Some(0x2000_00C1));
+}
+
+#[test]
+fn test_record_errors() {
+    assert_eq!(
+        parse(&(record(0, 0x02, &[0x10, 0x00]) + EOF)),
+        Err(IhexError::UnsupportedRecord {
+            line: 1,
+            kind: 0x02
+        })
+    );
+    assert_eq!(
+        parse(&(record(0, 0x04, &[0x10]) + EOF)),
+        Err(IhexError::BadRecordLength { line: 1 })
+    );
+    // Byte count disagrees with the record length
+    assert_eq!(
+        parse(":0500000001020304F1\n"),
+        Err(IhexError::Syntax { line: 1 })
+    );
+    assert_eq!(parse("10000000\n"), Err(IhexError::Syntax { line: 1 }));
+}
+
+#[test]
+fn test_eof_and_empty() {
+    let data = upper(FW_A_ADDR) + &record(0, 0x00, &[1; 4]);
+    assert_eq!(parse(&data), Err(IhexError::Eof));
+    assert_eq!(parse(&(data.clone() + EOF + &data)), Err(IhexError::Eof));
+    assert_eq!(parse(EOF), Err(IhexError::Empty));
+}
+
+#[test]
+fn test_bad_address() {
+    let text = record(0x1000, 0x00, &[0; 16]) + EOF;
+    assert_eq!(parse(&text), Err(IhexError::BadAddress(0x1000)));
+}
+
+#[test]
+fn test_is_ihex() {
+    assert!(ihex::is_ihex(b":020000041000EA\n"));
+    assert!(ihex::is_ihex(b"\r\n:020000041000EA\n"));
+    assert!(!ihex::is_ihex(b"\x00\x42\x00\x20"));
+    assert!(!ihex::is_ihex(b":hello"));
+}
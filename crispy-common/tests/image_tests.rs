@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests and golden vectors for the firmware image header.
+
+use crispy_common::crc32;
+use crispy_common::image::{
+    self, ImageError, ImageHeader, IMAGE_HEADER_SIZE, IMAGE_HEADER_VERSION, IMAGE_MAGIC,
+};
+use crispy_common::version::FwVersion;
+
+fn header() -> ImageHeader {
+    ImageHeader {
+        flags: 0,
+        board_id: 2,
+        image_len: 0x1234,
+        image_crc: 0xDEAD_BEEF,
+        fw_version: FwVersion::from_raw(7),
+    }
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 13 + 5) as u8).collect()
+}
+
+/// `payload` followed by the header describing it.
+fn headered(payload: &[u8], fw_version: u32) -> Vec<u8> {
+    let mut image = payload.to_vec();
+    image.extend_from_slice(&ImageHeader::describe(payload, fw_version.into()).to_bytes());
+    image
+}
+
+// --- Byte layout ---
+
+#[test]
+fn test_header_constants() {
+    assert_eq!(IMAGE_HEADER_SIZE, 64);
+    assert_eq!(IMAGE_MAGIC.to_le_bytes(), *b"CRIM");
+    assert_eq!(IMAGE_HEADER_VERSION, 1);
+}
+
+#[test]
+fn test_header_golden_vector() {
+    let mut expected = vec![
+        0x43, 0x52, 0x49, 0x4d, // magic "CRIM"
+        0x01, // header version
+        0x00, // flags
+        0x02, 0x00, // board id
+        0x34, 0x12, 0x00, 0x00, // image length
+        0xef, 0xbe, 0xad, 0xde, // image CRC
+        0x07, 0x00, 0x00, 0x00, // firmware version
+    ];
+    expected.extend_from_slice(&[0; 40]);
+    expected.extend_from_slice(&[0x1c, 0x9b, 0xc3, 0x4a]); // header CRC
+
+    assert_eq!(header().to_bytes().to_vec(), expected);
+    assert_eq!(
+        ImageHeader::from_bytes(&expected.try_into().unwrap()),
+        Ok(header())
+    );
+}
+
+#[test]
+fn test_headered_image_golden_vector() {
+    let payload: Vec<u8> = (0..16).collect();
+    let image = headered(&payload, 3);
+
+    assert_eq!(image.len(), 16 + IMAGE_HEADER_SIZE);
+    assert_eq!(&image[..16], &payload[..]);
+    assert_eq!(
+        &image[16..36],
+        &[
+            0x43, 0x52, 0x49, 0x4d, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x88, 0xe2,
+            0xce, 0xce, 0x03, 0x00, 0x00, 0x00,
+        ]
+    );
+    assert_eq!(&image[76..], &[0x1d, 0x35, 0xbc, 0xda]);
+}
+
+// --- Parsing ---
+
+#[test]
+fn test_from_bytes_rejects_bad_magic() {
+    let mut raw = header().to_bytes();
+    raw[0] = b'X';
+    assert!(matches!(
+        ImageHeader::from_bytes(&raw),
+        Err(ImageError::BadMagic(_))
+    ));
+}
+
+#[test]
+fn test_from_bytes_rejects_unknown_version() {
+    let mut raw = header().to_bytes();
+    raw[4] = 2;
+    assert_eq!(
+        ImageHeader::from_bytes(&raw),
+        Err(ImageError::UnknownVersion(2))
+    );
+}
+
+#[test]
+fn test_from_bytes_rejects_bad_header_crc() {
+    let mut raw = header().to_bytes();
+    raw[16] ^= 1;
+    assert_eq!(ImageHeader::from_bytes(&raw), Err(ImageError::BadHeaderCrc));
+
+    // Reserved bytes are covered too
+    let mut raw = header().to_bytes();
+    raw[40] = 1;
+    assert_eq!(ImageHeader::from_bytes(&raw), Err(ImageError::BadHeaderCrc));
+}
+
+#[test]
+fn test_check_payload() {
+    let h = header();
+    assert_eq!(h.check_payload(0x1234, 0xDEAD_BEEF), Ok(()));
+    assert_eq!(
+        h.check_payload(0x1235, 0xDEAD_BEEF),
+        Err(ImageError::LengthMismatch {
+            header: 0x1234,
+            actual: 0x1235
+        })
+    );
+    assert_eq!(
+        h.check_payload(0x1234, 0),
+        Err(ImageError::CrcMismatch {
+            header: 0xDEAD_BEEF,
+            actual: 0
+        })
+    );
+}
+
+// --- Splitting images ---
+
+#[test]
+fn test_split_raw_image_has_no_header() {
+    assert!(image::split(&payload(4096)).is_none());
+    assert!(image::split(&payload(10)).is_none());
+    assert!(image::split(&[]).is_none());
+}
+
+#[test]
+fn test_split_headered_image() {
+    let data = payload(1000);
+    let image = headered(&data, 9);
+
+    let (firmware, header) = image::split(&image).unwrap().unwrap();
+    assert_eq!(firmware, &data[..]);
+    assert_eq!(header.fw_version, FwVersion::from_raw(9));
+    assert_eq!(header.image_len, 1000);
+    assert_eq!(header.image_crc, crc32::checksum(&data));
+}
+
+#[test]
+fn test_split_rejects_tampered_firmware() {
+    let mut image = headered(&payload(1000), 9);
+    image[100] ^= 0xFF;
+    assert!(matches!(
+        image::split(&image),
+        Some(Err(ImageError::CrcMismatch { .. }))
+    ));
+}
+
+#[test]
+fn test_split_rejects_header_for_other_length() {
+    let mut image = payload(1000);
+    image.extend_from_slice(
+        &ImageHeader::describe(&payload(999), FwVersion::from_raw(1)).to_bytes(),
+    );
+    assert!(matches!(
+        image::split(&image),
+        Some(Err(ImageError::LengthMismatch {
+            header: 999,
+            actual: 1000
+        }))
+    ));
+}
+
+#[test]
+fn test_header_alone_is_not_an_image() {
+    assert!(image::split(&header().to_bytes()).is_none());
+}
+
+// --- Builder ---
+
+#[cfg(feature = "std")]
+#[test]
+fn test_builder_wraps_payload() {
+    use crispy_common::image::ImageBuilder;
+
+    let data = payload(300);
+    let image = ImageBuilder::new(FwVersion::from_raw(5))
+        .board_id(3)
+        .flags(0x80)
+        .wrap(&data);
+
+    let (firmware, header) = image::split(&image).unwrap().unwrap();
+    assert_eq!(firmware, &data[..]);
+    assert_eq!(
+        header,
+        ImageHeader {
+            flags: 0x80,
+            board_id: 3,
+            image_len: 300,
+            image_crc: crc32::checksum(&data),
+            fw_version: FwVersion::from_raw(5),
+        }
+    );
+    assert_eq!(
+        ImageBuilder::new(FwVersion::from_raw(5)).wrap(&data),
+        headered(&data, 5)
+    );
+}
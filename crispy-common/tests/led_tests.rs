@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for the status LED abstraction.
+
+use crispy_common::led::{LedPattern, NoLed, PatternPlayer, LED_PIN};
+use crispy_common::StatusLed;
+
+/// Records LED state so trait users can be checked on the host.
+#[derive(Default)]
+struct RecordingLed {
+    on: bool,
+    changes: u32,
+}
+
+impl StatusLed for RecordingLed {
+    fn set(&mut self, on: bool) {
+        self.on = on;
+        self.changes += 1;
+    }
+
+    fn toggle(&mut self) {
+        self.set(!self.on);
+    }
+}
+
+// --- Configuration tests ---
+
+#[test]
+fn test_led_pin_default() {
+    if option_env!("CRISPY_LED_PIN").is_none() {
+        assert_eq!(LED_PIN, 25);
+    }
+}
+
+// --- Implementation tests ---
+
+#[test]
+fn test_no_led_accepts_calls() {
+    let mut led = NoLed;
+    led.set(true);
+    led.toggle();
+}
+
+#[test]
+fn test_toggle_through_trait_object() {
+    let mut led = RecordingLed::default();
+    {
+        let dyn_led: &mut dyn StatusLed = &mut led;
+        dyn_led.toggle();
+        dyn_led.toggle();
+        dyn_led.toggle();
+    }
+    assert!(led.on);
+    assert_eq!(led.changes, 3);
+}
+
+// --- Pattern player tests ---
+
+const MS: u64 = 1000;
+
+/// Tick `player` every millisecond from `from` to `to` (exclusive) and
+/// return the LED state sampled at each tick.
+fn run(player: &mut PatternPlayer, led: &mut RecordingLed, from: u64, to: u64) -> Vec<bool> {
+    (from..to)
+        .map(|ms| {
+            player.tick(led, ms * MS);
+            led.on
+        })
+        .collect()
+}
+
+#[test]
+fn test_first_tick_sets_first_step() {
+    let mut led = RecordingLed {
+        on: true,
+        changes: 0,
+    };
+    let mut player = PatternPlayer::new(LedPattern::OFF);
+    player.tick(&mut led, 12_345);
+    assert!(!led.on);
+    assert_eq!(led.changes, 1);
+}
+
+#[test]
+fn test_fast_blink_timing() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::FAST_BLINK);
+    let states = run(&mut player, &mut led, 0, 400);
+
+    assert!(states[..100].iter().all(|&on| on));
+    assert!(states[100..200].iter().all(|&on| !on));
+    assert!(states[200..300].iter().all(|&on| on));
+    assert!(states[300..].iter().all(|&on| !on));
+    // One write to start, then one per step change
+    assert_eq!(led.changes, 4);
+}
+
+#[test]
+fn test_heartbeat_repeats_each_second() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::HEARTBEAT);
+    let first = run(&mut player, &mut led, 0, 1000);
+    let second = run(&mut player, &mut led, 1000, 2000);
+    assert_eq!(first, second);
+    assert_eq!(first.iter().filter(|&&on| on).count(), 200);
+}
+
+#[test]
+fn test_sos_pulse_lengths() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::SOS);
+    let period: u32 = LedPattern::SOS.steps().iter().map(|&(_, ms)| ms).sum();
+    let states = run(&mut player, &mut led, 0, period as u64);
+
+    // Lengths of the on-runs: three short, three long, three short
+    let mut pulses = Vec::new();
+    let mut run_len = 0;
+    for &on in states.iter().chain([false].iter()) {
+        if on {
+            run_len += 1;
+        } else if run_len > 0 {
+            pulses.push(run_len);
+            run_len = 0;
+        }
+    }
+    assert_eq!(pulses, [150, 150, 150, 450, 450, 450, 150, 150, 150]);
+}
+
+#[test]
+fn test_triple_pulse_every_two_seconds() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::TRIPLE_PULSE);
+    let first = run(&mut player, &mut led, 0, 2000);
+    let second = run(&mut player, &mut led, 2000, 4000);
+    assert_eq!(first, second);
+
+    let rising = first.windows(2).filter(|w| !w[0] && w[1]).count();
+    assert!(first[0]);
+    assert_eq!(rising, 2);
+    assert_eq!(first.iter().filter(|&&on| on).count(), 300);
+}
+
+#[test]
+fn test_solid_never_changes() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::SOLID);
+    let states = run(&mut player, &mut led, 0, 5000);
+    assert!(states.iter().all(|&on| on));
+    assert_eq!(led.changes, 1);
+}
+
+#[test]
+fn test_stall_skips_missed_steps() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::FAST_BLINK);
+    player.tick(&mut led, 0);
+
+    // 10.15 s later we are 150 ms into a period: the off step
+    player.tick(&mut led, 10_150 * MS);
+    assert!(!led.on);
+    assert_eq!(led.changes, 2);
+
+    // And the on step starts on schedule afterwards
+    player.tick(&mut led, 10_199 * MS);
+    assert!(!led.on);
+    player.tick(&mut led, 10_200 * MS);
+    assert!(led.on);
+}
+
+#[test]
+fn test_set_pattern_restarts_only_on_change() {
+    let mut led = RecordingLed::default();
+    let mut player = PatternPlayer::new(LedPattern::SLOW_BLINK);
+    run(&mut player, &mut led, 0, 1500);
+    assert!(!led.on);
+
+    // Same pattern: keeps its place
+    player.set_pattern(LedPattern::SLOW_BLINK);
+    player.tick(&mut led, 1600 * MS);
+    assert!(!led.on);
+
+    // New pattern: starts from its first step
+    player.set_pattern(LedPattern::FAST_BLINK);
+    assert_eq!(player.pattern(), LedPattern::FAST_BLINK);
+    player.tick(&mut led, 1601 * MS);
+    assert!(led.on);
+    player.tick(&mut led, 1701 * MS);
+    assert!(!led.on);
+}
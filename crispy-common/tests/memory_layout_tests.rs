@@ -0,0 +1,355 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for FlashLayout and MemoryLayout arithmetic and their
+//! consistency checks.
+
+use crispy_common::memory_layout::{
+    FlashLayout, LayoutError, MemoryLayout, Partition, FW_COPY_SIZE, FW_RAM_BASE,
+};
+use crispy_common::protocol::{
+    BOOT_DATA_ADDR, FLASH_BASE, FLASH_SECTOR_SIZE, FLASH_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR,
+};
+
+const LAYOUT: MemoryLayout = MemoryLayout::from_constants();
+const FLASH: FlashLayout = FlashLayout::DEFAULT;
+
+/// The default memory layout with a different flash half.
+fn with_flash(flash: FlashLayout) -> MemoryLayout {
+    MemoryLayout { flash, ..LAYOUT }
+}
+
+// --- Constants ---
+
+#[test]
+fn test_from_constants() {
+    assert_eq!(FLASH.fw_a, FW_A_ADDR);
+    assert_eq!(FLASH.fw_b, FW_B_ADDR);
+    assert_eq!(FLASH.bank_size, FW_BANK_SIZE);
+    assert_eq!(FLASH.boot_data, BOOT_DATA_ADDR);
+    assert_eq!(FLASH.flash_size, FLASH_SIZE);
+    assert!(FLASH.partitions.is_empty());
+    assert_eq!(LAYOUT.flash, FLASH);
+    assert_eq!(LAYOUT.ram_base, FW_RAM_BASE);
+    assert_eq!(LAYOUT.copy_size, FW_COPY_SIZE);
+}
+
+#[test]
+fn test_constants_layout_passes_check() {
+    assert_eq!(LAYOUT.check(), Ok(()));
+}
+
+#[test]
+fn test_linker_script_values() {
+    // Mirrors __fw_ram_base and __fw_copy_size in bootloader_rp2040.x
+    assert_eq!(FW_RAM_BASE, 0x2000_0000);
+    assert_eq!(FW_COPY_SIZE, 0x30000);
+}
+
+// --- Bank arithmetic ---
+
+#[test]
+fn test_bank_addr() {
+    assert_eq!(FLASH.bank_addr(0), Some(FW_A_ADDR));
+    assert_eq!(FLASH.bank_addr(1), Some(FW_B_ADDR));
+    assert_eq!(FLASH.bank_addr(2), None);
+}
+
+#[test]
+fn test_bank_contains_edges() {
+    assert!(FLASH.bank_contains(0, FW_A_ADDR, FW_BANK_SIZE));
+    assert!(FLASH.bank_contains(0, FW_A_ADDR + FW_BANK_SIZE - 1, 1));
+    assert!(!FLASH.bank_contains(0, FW_A_ADDR + FW_BANK_SIZE - 1, 2));
+    assert!(!FLASH.bank_contains(0, FW_A_ADDR - 1, 1));
+    assert!(FLASH.bank_contains(1, FW_B_ADDR, 0));
+    assert!(!FLASH.bank_contains(1, BOOT_DATA_ADDR, 1));
+    assert!(!FLASH.bank_contains(2, FW_A_ADDR, 1));
+}
+
+#[test]
+fn test_bank_contains_does_not_overflow() {
+    assert!(!FLASH.bank_contains(1, FW_B_ADDR, u32::MAX));
+}
+
+#[test]
+fn test_bank_of() {
+    assert_eq!(FLASH.bank_of(FLASH_BASE), None);
+    assert_eq!(FLASH.bank_of(FW_A_ADDR), Some(0));
+    assert_eq!(FLASH.bank_of(FW_B_ADDR - 1), Some(0));
+    assert_eq!(FLASH.bank_of(FW_B_ADDR), Some(1));
+    assert_eq!(FLASH.bank_of(BOOT_DATA_ADDR), None);
+}
+
+#[test]
+fn test_banks_do_not_overlap() {
+    for addr in [FW_A_ADDR, FW_B_ADDR - 1, FW_B_ADDR, BOOT_DATA_ADDR - 1] {
+        let in_a = FLASH.bank_contains(0, addr, 1);
+        let in_b = FLASH.bank_contains(1, addr, 1);
+        assert!(in_a != in_b, "0x{:08x}", addr);
+    }
+}
+
+// --- Consistency with the protocol constants ---
+
+#[test]
+fn test_check_reports_each_mismatch() {
+    let cases = [
+        (
+            with_flash(FlashLayout {
+                fw_a: FW_A_ADDR + FLASH_SECTOR_SIZE,
+                ..FLASH
+            }),
+            LayoutError::FwAMismatch(FW_A_ADDR + FLASH_SECTOR_SIZE),
+        ),
+        (
+            with_flash(FlashLayout {
+                fw_b: FW_B_ADDR + FLASH_SECTOR_SIZE,
+                ..FLASH
+            }),
+            LayoutError::FwBMismatch(FW_B_ADDR + FLASH_SECTOR_SIZE),
+        ),
+        (
+            with_flash(FlashLayout {
+                bank_size: FW_BANK_SIZE / 2,
+                ..FLASH
+            }),
+            LayoutError::BankSizeMismatch(FW_BANK_SIZE / 2),
+        ),
+        (
+            with_flash(FlashLayout {
+                boot_data: BOOT_DATA_ADDR + FLASH_SECTOR_SIZE,
+                ..FLASH
+            }),
+            LayoutError::BootDataMismatch(BOOT_DATA_ADDR + FLASH_SECTOR_SIZE),
+        ),
+    ];
+
+    for (layout, err) in cases {
+        assert_eq!(layout.check(), Err(err));
+    }
+}
+
+#[test]
+fn test_shifted_layout_is_consistent_but_mismatched() {
+    // A self-consistent layout from a different linker script still fails
+    let shifted = with_flash(FlashLayout {
+        fw_a: FW_A_ADDR + FLASH_SECTOR_SIZE,
+        fw_b: FW_B_ADDR + FLASH_SECTOR_SIZE,
+        boot_data: BOOT_DATA_ADDR + FLASH_SECTOR_SIZE,
+        ..FLASH
+    });
+    assert_eq!(shifted.check_geometry(), Ok(()));
+    assert!(shifted.check().is_err());
+}
+
+// --- Geometry ---
+
+#[test]
+fn test_geometry_misaligned() {
+    let layout = with_flash(FlashLayout {
+        fw_b: FW_B_ADDR + 0x100,
+        ..FLASH
+    });
+    assert_eq!(
+        layout.check_geometry(),
+        Err(LayoutError::Misaligned(FW_B_ADDR + 0x100))
+    );
+}
+
+#[test]
+fn test_geometry_overlapping_banks() {
+    let layout = with_flash(FlashLayout {
+        fw_b: FW_B_ADDR - FLASH_SECTOR_SIZE,
+        ..FLASH
+    });
+    assert_eq!(layout.check_geometry(), Err(LayoutError::Overlap));
+
+    let layout = with_flash(FlashLayout {
+        boot_data: BOOT_DATA_ADDR - FLASH_SECTOR_SIZE,
+        ..FLASH
+    });
+    assert_eq!(layout.check_geometry(), Err(LayoutError::Overlap));
+}
+
+#[test]
+fn test_geometry_bank_a_over_bootloader() {
+    let layout = with_flash(FlashLayout {
+        fw_a: FLASH_BASE,
+        ..FLASH
+    });
+    assert_eq!(
+        layout.check_geometry(),
+        Err(LayoutError::OutOfFlash(FLASH_BASE))
+    );
+}
+
+#[test]
+fn test_geometry_boot_data_past_flash_end() {
+    let end = FLASH_BASE + FLASH_SIZE;
+    let layout = with_flash(FlashLayout {
+        boot_data: end,
+        ..FLASH
+    });
+    assert_eq!(layout.check_geometry(), Err(LayoutError::OutOfFlash(end)));
+}
+
+#[test]
+fn test_geometry_boot_data_over_provision_record() {
+    let last = FLASH_BASE + FLASH_SIZE - FLASH_SECTOR_SIZE;
+    assert_eq!(FLASH.provision(), last);
+    let layout = with_flash(FlashLayout {
+        boot_data: last,
+        ..FLASH
+    });
+    assert_eq!(layout.check_geometry(), Err(LayoutError::Overlap));
+}
+
+#[test]
+fn test_geometry_copy_larger_than_bank() {
+    let layout = MemoryLayout {
+        copy_size: FW_BANK_SIZE + 4,
+        ..LAYOUT
+    };
+    assert_eq!(
+        layout.check_geometry(),
+        Err(LayoutError::CopyTooLarge(FW_BANK_SIZE + 4))
+    );
+}
+
+#[test]
+fn test_geometry_follows_flash_size() {
+    // Today's BootData address does not fit a 1MB part
+    let small = FlashLayout {
+        flash_size: BOOT_DATA_ADDR - FLASH_BASE,
+        ..FLASH
+    };
+    assert_eq!(
+        small.check_geometry(),
+        Err(LayoutError::OutOfFlash(BOOT_DATA_ADDR))
+    );
+}
+
+// --- Partitions ---
+
+/// The sector after BootData, free in the default layout.
+const CONFIG: Partition = Partition {
+    name: "config",
+    addr: BOOT_DATA_ADDR + FLASH_SECTOR_SIZE,
+    size: FLASH_SECTOR_SIZE,
+};
+
+fn with_partitions(partitions: &'static [Partition]) -> FlashLayout {
+    FlashLayout {
+        partitions,
+        ..FLASH
+    }
+}
+
+#[test]
+fn test_partition_lookup() {
+    let layout = with_partitions(&[CONFIG]);
+    assert_eq!(layout.check_geometry(), Ok(()));
+    assert_eq!(layout.partition("config"), Some(&CONFIG));
+    assert_eq!(layout.partition("golden"), None);
+}
+
+#[test]
+fn test_partition_misaligned() {
+    const ODD_ADDR: [Partition; 1] = [Partition {
+        addr: CONFIG.addr + 0x100,
+        ..CONFIG
+    }];
+    const ODD_SIZE: [Partition; 1] = [Partition {
+        size: 0x100,
+        ..CONFIG
+    }];
+    assert_eq!(
+        with_partitions(&ODD_ADDR).check_geometry(),
+        Err(LayoutError::Misaligned(CONFIG.addr + 0x100))
+    );
+    assert_eq!(
+        with_partitions(&ODD_SIZE).check_geometry(),
+        Err(LayoutError::Misaligned(CONFIG.addr))
+    );
+}
+
+#[test]
+fn test_partition_out_of_flash() {
+    let end = FLASH_BASE + FLASH_SIZE;
+    const EMPTY: [Partition; 1] = [Partition { size: 0, ..CONFIG }];
+    const PAST_END: [Partition; 1] = [Partition {
+        addr: FLASH_BASE + FLASH_SIZE,
+        ..CONFIG
+    }];
+    const BELOW_FLASH: [Partition; 1] = [Partition { addr: 0, ..CONFIG }];
+
+    assert_eq!(
+        with_partitions(&EMPTY).check_geometry(),
+        Err(LayoutError::OutOfFlash(CONFIG.addr))
+    );
+    assert_eq!(
+        with_partitions(&PAST_END).check_geometry(),
+        Err(LayoutError::OutOfFlash(end))
+    );
+    assert_eq!(
+        with_partitions(&BELOW_FLASH).check_geometry(),
+        Err(LayoutError::OutOfFlash(0))
+    );
+}
+
+#[test]
+fn test_partition_overlaps_reserved_regions() {
+    // Over the bootloader, bank A, the end of bank A into bank B, BootData,
+    // the provisioning record
+    const OVERLAPPING: [[Partition; 1]; 5] = [
+        [Partition {
+            addr: FLASH_BASE,
+            ..CONFIG
+        }],
+        [Partition {
+            addr: FW_A_ADDR,
+            ..CONFIG
+        }],
+        [Partition {
+            addr: FW_B_ADDR - FLASH_SECTOR_SIZE,
+            size: 2 * FLASH_SECTOR_SIZE,
+            ..CONFIG
+        }],
+        [Partition {
+            addr: BOOT_DATA_ADDR,
+            ..CONFIG
+        }],
+        [Partition {
+            addr: FLASH_BASE + FLASH_SIZE - FLASH_SECTOR_SIZE,
+            ..CONFIG
+        }],
+    ];
+    for partitions in &OVERLAPPING {
+        assert_eq!(
+            with_partitions(partitions).check_geometry(),
+            Err(LayoutError::PartitionOverlap("config")),
+            "0x{:08x}",
+            partitions[0].addr
+        );
+    }
+}
+
+#[test]
+fn test_partitions_overlap_each_other() {
+    const GOLDEN: Partition = Partition {
+        name: "golden",
+        addr: CONFIG.addr,
+        size: 2 * FLASH_SECTOR_SIZE,
+    };
+    assert_eq!(
+        with_partitions(&[CONFIG, GOLDEN]).check_geometry(),
+        Err(LayoutError::PartitionOverlap("golden"))
+    );
+
+    const NEXT: Partition = Partition {
+        name: "next",
+        addr: CONFIG.addr + FLASH_SECTOR_SIZE,
+        ..CONFIG
+    };
+    assert_eq!(with_partitions(&[CONFIG, NEXT]).check_geometry(), Ok(()));
+}
@@ -4,10 +4,12 @@
 //! Unit tests for protocol types and constants.
 
 use crispy_common::protocol::{
-    AckStatus, BootState, Command, Response, BOOT_DATA_ADDR, FLASH_BASE, FLASH_PAGE_SIZE,
-    FLASH_SECTOR_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR, MAX_DATA_BLOCK_SIZE,
-    RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC,
+    AckStatus, BootState, Command, ProtocolError, Response, BOOTLOADER_SIZE, BOOT_DATA_ADDR,
+    BOOT_DATA_SIZE, FLASH_BASE, FLASH_PAGE_SIZE, FLASH_RESERVED_TAIL, FLASH_SECTOR_SIZE,
+    FLASH_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR, MAX_BOOT_ATTEMPTS_RANGE, MAX_DATA_BLOCK_SIZE,
+    MAX_READ_SIZE, RAM_UPDATE_FLAG_ADDR, RAM_UPDATE_MAGIC,
 };
+use crispy_common::version::FwVersion;
 
 // --- Flash layout constants tests ---
 
@@ -49,26 +51,17 @@ fn test_max_data_block_size() {
     assert_eq!(MAX_DATA_BLOCK_SIZE, 1024);
 }
 
-// --- Memory layout validation ---
+// --- Default layout ---
 
 #[test]
-fn test_bank_a_does_not_overlap_bootloader() {
-    // Bootloader is at FLASH_BASE, bank A should be after it
-    assert!(FW_A_ADDR > FLASH_BASE);
-}
-
-#[test]
-fn test_banks_do_not_overlap() {
-    // Bank B should start after bank A ends
-    let bank_a_end = FW_A_ADDR + FW_BANK_SIZE;
-    assert!(FW_B_ADDR >= bank_a_end);
-}
-
-#[test]
-fn test_boot_data_after_banks() {
-    // Boot data should be after both firmware banks
-    let bank_b_end = FW_B_ADDR + FW_BANK_SIZE;
-    assert!(BOOT_DATA_ADDR >= bank_b_end);
+fn test_default_layout_is_2mb() {
+    // Without a flash-* feature the original 2MB layout must be unchanged
+    assert_eq!(FLASH_SIZE, 2 * 1024 * 1024);
+    assert_eq!(
+        BOOT_DATA_ADDR + FLASH_RESERVED_TAIL,
+        FLASH_BASE + FLASH_SIZE
+    );
+    assert_eq!(FW_A_ADDR, FLASH_BASE + BOOTLOADER_SIZE);
 }
 
 // --- AckStatus tests ---
@@ -120,7 +113,8 @@ fn test_command_start_update_debug() {
         bank: 0,
         size: 1024,
         crc32: 0xDEADBEEF,
-        version: 1,
+        version: FwVersion::from_raw(1),
+        sparse: false,
     };
     let debug = format!("{:?}", cmd);
     assert!(debug.contains("StartUpdate"));
@@ -162,6 +156,218 @@ fn test_command_wipe_all_debug() {
     assert!(format!("{:?}", cmd).contains("WipeAll"));
 }
 
+#[test]
+fn test_command_get_flash_info_debug() {
+    let cmd = Command::GetFlashInfo;
+    assert!(format!("{:?}", cmd).contains("GetFlashInfo"));
+}
+
+#[test]
+fn test_command_blank_check_debug() {
+    let cmd = Command::BlankCheck {
+        addr: FW_B_ADDR,
+        length: FW_BANK_SIZE,
+    };
+    let debug = format!("{:?}", cmd);
+    assert!(debug.contains("BlankCheck"));
+    assert!(debug.contains("length"));
+}
+
+// --- Command validation ---
+
+fn start_update(bank: u8, size: u32) -> Command {
+    Command::StartUpdate {
+        bank,
+        size,
+        crc32: 0,
+        version: FwVersion::from_raw(1),
+        sparse: false,
+    }
+}
+
+fn data_block(offset: u32, len: usize) -> Command {
+    Command::DataBlock {
+        offset,
+        data: vec![0xA5; len],
+    }
+}
+
+#[test]
+fn test_validate_accepts_limits() {
+    assert_eq!(start_update(1, FW_BANK_SIZE).validate(), Ok(()));
+    assert_eq!(
+        data_block(
+            FW_BANK_SIZE - MAX_DATA_BLOCK_SIZE as u32,
+            MAX_DATA_BLOCK_SIZE
+        )
+        .validate(),
+        Ok(())
+    );
+    assert_eq!(Command::SetActiveBank { bank: 0 }.validate(), Ok(()));
+    assert_eq!(
+        Command::BlankCheck {
+            addr: FLASH_BASE,
+            length: FLASH_SIZE,
+        }
+        .validate(),
+        Ok(())
+    );
+    for max_attempts in [0, *MAX_BOOT_ATTEMPTS_RANGE.end()] {
+        assert_eq!(Command::SetBootAttempts { max_attempts }.validate(), Ok(()));
+    }
+    assert_eq!(Command::GetStatus.validate(), Ok(()));
+}
+
+#[test]
+fn test_validate_start_update() {
+    assert_eq!(
+        start_update(2, 1024).validate(),
+        Err(ProtocolError::BadBank(2))
+    );
+    assert_eq!(
+        start_update(0, 0).validate(),
+        Err(ProtocolError::BadImageSize(0))
+    );
+    assert_eq!(
+        start_update(0, u32::MAX).validate(),
+        Err(ProtocolError::BadImageSize(u32::MAX))
+    );
+}
+
+#[test]
+fn test_validate_data_block() {
+    assert_eq!(
+        data_block(0, MAX_DATA_BLOCK_SIZE + 1).validate(),
+        Err(ProtocolError::BlockTooLarge(MAX_DATA_BLOCK_SIZE + 1))
+    );
+    assert_eq!(
+        data_block(FW_BANK_SIZE - 16, 32).validate(),
+        Err(ProtocolError::BlockOutOfBank(FW_BANK_SIZE - 16))
+    );
+    // An offset that would wrap the end address
+    assert_eq!(
+        data_block(u32::MAX - 8, 16).validate(),
+        Err(ProtocolError::BlockOutOfBank(u32::MAX - 8))
+    );
+}
+
+#[test]
+fn test_validate_other_commands() {
+    assert_eq!(
+        Command::SetActiveBank { bank: 0xFF }.validate(),
+        Err(ProtocolError::BadBank(0xFF))
+    );
+    assert_eq!(
+        Command::SetVersion {
+            bank: 2,
+            version: FwVersion::new(1, 0, 0),
+        }
+        .validate(),
+        Err(ProtocolError::BadBank(2))
+    );
+    assert_eq!(
+        Command::BlankCheck {
+            addr: FLASH_BASE - 4,
+            length: 8,
+        }
+        .validate(),
+        Err(ProtocolError::RangeOutOfFlash(FLASH_BASE - 4))
+    );
+    assert_eq!(
+        Command::BlankCheck {
+            addr: FW_B_ADDR,
+            length: u32::MAX,
+        }
+        .validate(),
+        Err(ProtocolError::RangeOutOfFlash(FW_B_ADDR))
+    );
+    let max_attempts = MAX_BOOT_ATTEMPTS_RANGE.end() + 1;
+    assert_eq!(
+        Command::SetBootAttempts { max_attempts }.validate(),
+        Err(ProtocolError::BadBootAttempts(max_attempts))
+    );
+}
+
+#[test]
+fn test_validate_read_flash() {
+    let read = |addr, length| Command::ReadFlash { addr, length }.validate();
+    assert_eq!(read(FW_A_ADDR, MAX_READ_SIZE as u32), Ok(()));
+    assert_eq!(read(FLASH_BASE, 1), Ok(()));
+    assert_eq!(read(FW_A_ADDR, 0), Err(ProtocolError::BadReadLength(0)));
+    assert_eq!(
+        read(FW_A_ADDR, MAX_READ_SIZE as u32 + 1),
+        Err(ProtocolError::BadReadLength(MAX_READ_SIZE as u32 + 1))
+    );
+    assert_eq!(
+        read(FLASH_BASE - 4, 8),
+        Err(ProtocolError::RangeOutOfFlash(FLASH_BASE - 4))
+    );
+    assert_eq!(
+        read(FLASH_BASE + FLASH_SIZE - 4, 8),
+        Err(ProtocolError::RangeOutOfFlash(FLASH_BASE + FLASH_SIZE - 4))
+    );
+    assert_eq!(
+        ProtocolError::BadReadLength(0).status(),
+        AckStatus::BadCommand
+    );
+}
+
+#[test]
+fn test_validate_set_boot_data() {
+    let set = |len: usize| {
+        let raw = heapless::Vec::from_slice(&[0u8; BOOT_DATA_SIZE][..len]).unwrap();
+        Command::SetBootData { raw }.validate()
+    };
+    assert_eq!(set(BOOT_DATA_SIZE), Ok(()));
+    assert_eq!(set(0), Err(ProtocolError::BadRecordLength(0)));
+    assert_eq!(
+        set(BOOT_DATA_SIZE - 4),
+        Err(ProtocolError::BadRecordLength(BOOT_DATA_SIZE - 4))
+    );
+}
+
+#[test]
+fn test_validate_copy_bank() {
+    assert_eq!(Command::CopyBank { from: 0, to: 1 }.validate(), Ok(()));
+    assert_eq!(Command::CopyBank { from: 1, to: 0 }.validate(), Ok(()));
+    assert_eq!(
+        Command::CopyBank { from: 1, to: 1 }.validate(),
+        Err(ProtocolError::SameBank(1))
+    );
+    assert_eq!(
+        Command::CopyBank { from: 2, to: 0 }.validate(),
+        Err(ProtocolError::BadBank(2))
+    );
+    assert_eq!(
+        Command::CopyBank { from: 0, to: 7 }.validate(),
+        Err(ProtocolError::BadBank(7))
+    );
+    assert_eq!(ProtocolError::SameBank(0).status(), AckStatus::BankInvalid);
+}
+
+#[test]
+fn test_protocol_error_status() {
+    assert_eq!(ProtocolError::BadBank(2).status(), AckStatus::BankInvalid);
+    assert_eq!(
+        ProtocolError::BadImageSize(0).status(),
+        AckStatus::BankInvalid
+    );
+    assert_eq!(ProtocolError::Malformed.status(), AckStatus::BadCommand);
+    assert_eq!(
+        ProtocolError::BlockOutOfBank(0).status(),
+        AckStatus::BadCommand
+    );
+}
+
+#[test]
+fn test_garbage_frames_do_not_decode() {
+    // Unknown variant, truncated StartUpdate, DataBlock promising more bytes than sent
+    let frames: [&[u8]; 3] = [&[0x7F], &[0x01, 0x00, 0x80], &[0x02, 0x00, 0x10, 0xAA]];
+    for frame in frames {
+        assert!(postcard::from_bytes::<Command>(frame).is_err());
+    }
+}
+
 // --- Response tests ---
 
 #[test]
@@ -176,11 +382,80 @@ fn test_response_ack_debug() {
 fn test_response_status_debug() {
     let resp = Response::Status {
         active_bank: 0,
-        version_a: 1,
-        version_b: 2,
+        version_a: FwVersion::from_raw(1),
+        version_b: FwVersion::from_raw(2),
         state: BootState::Idle,
+        host_connected: true,
+        tx_stalled: false,
+        bootdata_reconstructed: false,
+        update_interrupted: false,
     };
     let debug = format!("{:?}", resp);
     assert!(debug.contains("Status"));
     assert!(debug.contains("Idle"));
 }
+
+#[test]
+fn test_response_nack_debug() {
+    let resp = Response::Nack {
+        status: AckStatus::FlashError,
+        offset: 0x400,
+    };
+    let debug = format!("{:?}", resp);
+    assert!(debug.contains("Nack"));
+    assert!(debug.contains("FlashError"));
+    assert!(debug.contains("1024"));
+}
+
+#[test]
+fn test_response_flash_info_debug() {
+    let resp = Response::FlashInfo {
+        jedec_id: 0xEF4015,
+        detected_size: FLASH_SIZE,
+        layout_size: FLASH_SIZE,
+        bank_size: FW_BANK_SIZE,
+        fw_a_addr: FW_A_ADDR,
+        fw_b_addr: FW_B_ADDR,
+        boot_data_addr: BOOT_DATA_ADDR,
+    };
+    let debug = format!("{:?}", resp);
+    assert!(debug.contains("FlashInfo"));
+    assert!(debug.contains("bank_size"));
+}
+
+#[test]
+fn test_response_blank_check_result_roundtrip() {
+    let resp = Response::BlankCheckResult {
+        first_dirty: Some(0x1234),
+        dirty_bytes: 7,
+    };
+    let mut buf = [0u8; 32];
+    let encoded = postcard::to_slice_cobs(&resp, &mut buf).unwrap();
+    match postcard::from_bytes_cobs::<Response>(encoded).unwrap() {
+        Response::BlankCheckResult {
+            first_dirty,
+            dirty_bytes,
+        } => {
+            assert_eq!(first_dirty, Some(0x1234));
+            assert_eq!(dirty_bytes, 7);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_progress_roundtrip() {
+    let resp = Response::Progress {
+        done: 0x4000,
+        total: 0xC0000,
+    };
+    let mut buf = [0u8; 32];
+    let encoded = postcard::to_slice_cobs(&resp, &mut buf).unwrap();
+    match postcard::from_bytes_cobs::<Response>(encoded).unwrap() {
+        Response::Progress { done, total } => {
+            assert_eq!(done, 0x4000);
+            assert_eq!(total, 0xC0000);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
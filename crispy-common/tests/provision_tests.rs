@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests and golden vectors for the provisioning record.
+
+use crispy_common::flash::{read_provision, write_provision};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{FLASH_BASE, FLASH_SECTOR_SIZE, FLASH_SIZE, PROVISION_ADDR};
+use crispy_common::provision::{
+    Provision, ProvisionError, PROVISION_MAGIC, PROVISION_SIZE, PROVISION_VERSION, SERIAL_LEN,
+};
+
+fn record() -> Provision {
+    Provision::new(2, 1, b"ABC123").unwrap()
+}
+
+// --- Byte layout ---
+
+#[test]
+fn test_constants() {
+    assert_eq!(PROVISION_SIZE, 32);
+    assert_eq!(PROVISION_MAGIC.to_le_bytes(), *b"PROV");
+    assert_eq!(PROVISION_VERSION, 1);
+    assert_eq!(SERIAL_LEN, 16);
+    assert_eq!(PROVISION_ADDR, FLASH_BASE + FLASH_SIZE - FLASH_SECTOR_SIZE);
+    assert_eq!(FlashLayout::DEFAULT.provision(), PROVISION_ADDR);
+}
+
+#[test]
+fn test_golden_vector() {
+    let mut expected = vec![
+        0x50, 0x52, 0x4f, 0x56, // magic "PROV"
+        0x01, // record version
+        0x01, // hardware revision
+        0x02, 0x00, // board id
+    ];
+    expected.extend_from_slice(b"ABC123");
+    expected.extend_from_slice(&[0; 10]); // serial padding
+    expected.extend_from_slice(&[0; 4]); // reserved
+    expected.extend_from_slice(&[0x27, 0x67, 0x56, 0x0e]); // record CRC
+
+    assert_eq!(record().to_bytes().to_vec(), expected);
+    assert_eq!(
+        Provision::from_bytes(&expected.try_into().unwrap()),
+        Ok(record())
+    );
+}
+
+#[test]
+fn test_serial() {
+    assert_eq!(record().serial(), "ABC123");
+    let full = Provision::new(0, 0, b"0123456789ABCDEF").unwrap();
+    assert_eq!(full.serial(), "0123456789ABCDEF");
+    assert_eq!(Provision::from_bytes(&full.to_bytes()), Ok(full));
+
+    for bad in [
+        &b""[..],
+        b"0123456789ABCDEFG",
+        b"AB C",
+        b"AB\0C",
+        "é".as_bytes(),
+    ] {
+        assert_eq!(
+            Provision::new(2, 1, bad),
+            Err(ProvisionError::BadSerial),
+            "{:?}",
+            bad
+        );
+    }
+}
+
+// --- Rejected records ---
+
+#[test]
+fn test_blank_sector() {
+    assert_eq!(
+        Provision::from_bytes(&[0xFF; PROVISION_SIZE]),
+        Err(ProvisionError::Blank)
+    );
+}
+
+#[test]
+fn test_bad_magic() {
+    let mut raw = record().to_bytes();
+    raw[0] = 0x00;
+    assert!(matches!(
+        Provision::from_bytes(&raw),
+        Err(ProvisionError::BadMagic(_))
+    ));
+}
+
+#[test]
+fn test_unknown_version() {
+    let mut raw = record().to_bytes();
+    raw[4] = 2;
+    assert_eq!(
+        Provision::from_bytes(&raw),
+        Err(ProvisionError::UnknownVersion(2))
+    );
+}
+
+#[test]
+fn test_bad_crc() {
+    for at in [5, 6, 8, 24] {
+        let mut raw = record().to_bytes();
+        raw[at] ^= 0x01;
+        assert_eq!(
+            Provision::from_bytes(&raw),
+            Err(ProvisionError::BadCrc),
+            "byte {}",
+            at
+        );
+    }
+}
+
+// --- In flash ---
+
+#[test]
+fn test_write_and_read_back() {
+    let mut flash = FlashSim::new();
+    assert_eq!(read_provision(&flash), Err(ProvisionError::Blank));
+
+    write_provision(&mut flash, &record()).unwrap();
+    assert_eq!(read_provision(&flash), Ok(record()));
+    let mut raw = [0u8; PROVISION_SIZE];
+    flash.read(PROVISION_ADDR, &mut raw);
+    assert_eq!(raw, record().to_bytes());
+
+    let other = Provision::new(3, 2, b"XYZ").unwrap();
+    write_provision(&mut flash, &other).unwrap();
+    assert_eq!(read_provision(&flash), Ok(other));
+}
@@ -0,0 +1,157 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Tests for the firmware self-update flow, on the flash simulator.
+
+use crispy_common::crc32;
+use crispy_common::flash::{
+    self, read_boot_data, write_boot_data, BankVerify, BankWriter, FlashError,
+};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::protocol::{BootData, FW_BANK_SIZE};
+use crispy_common::self_update::{self, Staged, UpdateError, DEFAULT_VERSION};
+use crispy_common::version::{FwVersion, VersionBlock, VERSION_BLOCK_OFFSET};
+
+/// Flash running confirmed firmware from bank A, with an older image
+/// recorded in bank B.
+fn running_a() -> FlashSim {
+    let mut bd = BootData::default_new();
+    bd.confirmed = 1;
+    bd.size_a = 4;
+    bd.crc_a = crc32::checksum(&[1, 2, 3, 4]);
+    bd.size_b = 4;
+    bd.crc_b = crc32::checksum(&[5, 6, 7, 8]);
+    let mut flash = FlashSim::new();
+    write_boot_data(&mut flash, &bd).unwrap();
+    flash
+}
+
+fn image(len: usize, version: Option<FwVersion>) -> Vec<u8> {
+    let mut data: Vec<u8> = (0..len).map(|i| (i * 7 + 3) as u8).collect();
+    if let Some(version) = version {
+        let block = VersionBlock::new(version).to_bytes();
+        data[VERSION_BLOCK_OFFSET..VERSION_BLOCK_OFFSET + block.len()].copy_from_slice(&block);
+    }
+    data
+}
+
+/// Run the whole update as the firmware does, in 100-byte slices.
+fn update(flash: &mut FlashSim, data: &[u8], expected: u32) -> Result<Staged, UpdateError> {
+    let bank = self_update::prepare(flash, data.len() as u32)?;
+    let mut writer = BankWriter::new(flash, bank);
+    for chunk in data.chunks(100) {
+        writer.write(chunk)?;
+    }
+    let (size, crc) = writer.finish(None)?;
+    self_update::stage(flash, bank, size, crc, expected)
+}
+
+#[test]
+fn test_update_stages_without_activating() {
+    let mut flash = running_a();
+    let data = image(3000, Some(FwVersion::new(1, 4, 2)));
+    let crc = crc32::checksum(&data);
+
+    let staged = update(&mut flash, &data, crc).unwrap();
+    assert_eq!(
+        staged,
+        Staged {
+            bank: 1,
+            size: 3000,
+            crc,
+            version: FwVersion::new(1, 4, 2),
+        }
+    );
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!((bd.active_bank, bd.confirmed), (0, 1));
+    assert_eq!(
+        (bd.size_b, bd.crc_b, bd.version_b),
+        (3000, crc, staged.version)
+    );
+
+    self_update::apply(&mut flash, &staged).unwrap();
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!((bd.active_bank, bd.confirmed, bd.boot_attempts), (1, 0, 0));
+}
+
+#[test]
+fn test_image_without_version_block() {
+    let mut flash = running_a();
+    let data = image(3000, None);
+    let staged = update(&mut flash, &data, crc32::checksum(&data)).unwrap();
+    assert_eq!(staged.version, DEFAULT_VERSION);
+
+    let short = image(16, None);
+    let staged = update(&mut flash, &short, crc32::checksum(&short)).unwrap();
+    assert_eq!(staged.version, DEFAULT_VERSION);
+}
+
+#[test]
+fn test_wrong_crc_leaves_the_bank_unrecorded() {
+    let mut flash = running_a();
+    let data = image(3000, None);
+
+    let err = update(&mut flash, &data, 0x1234_5678).unwrap_err();
+    assert_eq!(
+        err,
+        UpdateError::CrcMismatch {
+            expected: 0x1234_5678,
+            computed: crc32::checksum(&data),
+        }
+    );
+    // Neither the old image nor the new one: a rollback finds nothing
+    let bd = read_boot_data(&flash).unwrap();
+    assert_eq!((bd.active_bank, bd.size_b, bd.crc_b), (0, 0, 0));
+}
+
+#[test]
+fn test_bank_that_does_not_read_back_is_not_staged() {
+    let mut flash = running_a();
+    let data = image(3000, None);
+    let crc = crc32::checksum(&data);
+    let bank = self_update::prepare(&mut flash, 3000).unwrap();
+    let mut writer = BankWriter::new(&mut flash, bank);
+    writer.write(&data).unwrap();
+    let (size, _) = writer.finish(None).unwrap();
+
+    // A bit lost after programming
+    let addr = flash::bank_base(flash.layout(), bank) + 10;
+    flash.poke(addr, &[data[10] ^ 1]);
+    let err = self_update::stage(&mut flash, bank, size, crc, crc).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            UpdateError::Flash(FlashError::Bank(BankVerify::CrcMismatch { .. }))
+        ),
+        "{:?}",
+        err
+    );
+    let staged = Staged {
+        bank,
+        size,
+        crc,
+        version: DEFAULT_VERSION,
+    };
+    assert!(self_update::apply(&mut flash, &staged).is_err());
+    assert_eq!(read_boot_data(&flash).unwrap().active_bank, 0);
+}
+
+#[test]
+fn test_prepare_refuses() {
+    let mut flash = running_a();
+    assert_eq!(self_update::prepare(&mut flash, 0), Err(UpdateError::Empty));
+    assert_eq!(
+        self_update::prepare(&mut flash, FW_BANK_SIZE + 1),
+        Err(UpdateError::TooLarge(FW_BANK_SIZE + 1))
+    );
+    // Nothing was touched
+    assert_eq!(read_boot_data(&flash).unwrap().size_b, 4);
+
+    let mut blank = FlashSim::new();
+    assert!(matches!(
+        self_update::prepare(&mut blank, 100),
+        Err(UpdateError::Flash(FlashError::BootData(_)))
+    ));
+    assert!(blank.contents().iter().all(|&b| b == 0xFF));
+}
@@ -0,0 +1,131 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for the outgoing frame queue.
+
+use crispy_common::protocol::{AckStatus, BootState, Response};
+use crispy_common::tx_queue::TxQueue;
+use crispy_common::version::FwVersion;
+
+fn encoded(resp: &Response) -> Vec<u8> {
+    let mut buf = [0u8; 64];
+    postcard::to_slice_cobs(resp, &mut buf).unwrap().to_vec()
+}
+
+#[test]
+fn test_tx_queue_starts_empty() {
+    let queue: TxQueue<64> = TxQueue::new();
+    assert!(queue.is_empty());
+    assert!(queue.pending().is_empty());
+}
+
+#[test]
+fn test_tx_queue_push_matches_postcard_cobs() {
+    let resp = Response::Ack(AckStatus::Ok);
+    let mut queue: TxQueue<64> = TxQueue::new();
+
+    assert!(queue.push(&resp));
+    assert_eq!(queue.pending(), &encoded(&resp)[..]);
+    assert_eq!(*queue.pending().last().unwrap(), 0x00);
+}
+
+#[test]
+fn test_tx_queue_partial_consume() {
+    let resp = Response::Status {
+        active_bank: 1,
+        version_a: FwVersion::from_raw(7),
+        version_b: FwVersion::from_raw(8),
+        state: BootState::Idle,
+        host_connected: true,
+        tx_stalled: false,
+        bootdata_reconstructed: false,
+        update_interrupted: false,
+    };
+    let expected = encoded(&resp);
+    let mut queue: TxQueue<64> = TxQueue::new();
+    queue.push(&resp);
+
+    queue.consume(2);
+    assert_eq!(queue.pending(), &expected[2..]);
+
+    queue.consume(expected.len() - 2);
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_tx_queue_appends_frames_in_order() {
+    let first = Response::Ack(AckStatus::Ok);
+    let second = Response::Ack(AckStatus::CrcError);
+    let mut queue: TxQueue<64> = TxQueue::new();
+
+    queue.push(&first);
+    queue.consume(1);
+    queue.push(&second);
+
+    let mut expected = encoded(&first)[1..].to_vec();
+    expected.extend(encoded(&second));
+    assert_eq!(queue.pending(), &expected[..]);
+}
+
+#[test]
+fn test_tx_queue_rejects_frame_that_does_not_fit() {
+    let resp = Response::Ack(AckStatus::Ok);
+    let mut queue: TxQueue<4> = TxQueue::new();
+
+    assert!(queue.push(&resp));
+    let before = queue.pending().to_vec();
+
+    assert!(!queue.push(&resp));
+    assert_eq!(queue.pending(), &before[..]);
+}
+
+#[test]
+fn test_tx_queue_push_raw_appends_bytes() {
+    let resp = Response::Ack(AckStatus::Ok);
+    let mut queue: TxQueue<64> = TxQueue::new();
+
+    assert!(queue.push(&resp));
+    assert!(queue.push_raw(&[0x02, 0xAA]));
+    assert!(queue.push_raw(&[0x00]));
+
+    let mut expected = encoded(&resp);
+    expected.extend([0x02, 0xAA, 0x00]);
+    assert_eq!(queue.pending(), &expected[..]);
+}
+
+#[test]
+fn test_tx_queue_push_raw_reclaims_consumed_space() {
+    let mut queue: TxQueue<8> = TxQueue::new();
+    assert!(queue.push_raw(&[1; 6]));
+    queue.consume(4);
+
+    assert!(queue.push_raw(&[2; 6]));
+    assert_eq!(queue.pending(), &[1, 1, 2, 2, 2, 2, 2, 2]);
+}
+
+#[test]
+fn test_tx_queue_push_raw_rejects_bytes_that_do_not_fit() {
+    let mut queue: TxQueue<8> = TxQueue::new();
+    assert!(queue.push_raw(&[1; 5]));
+
+    assert!(!queue.push_raw(&[2; 4]));
+    assert_eq!(queue.pending(), &[1; 5]);
+}
+
+#[test]
+fn test_tx_queue_clear_drops_pending() {
+    let mut queue: TxQueue<64> = TxQueue::new();
+    queue.push(&Response::Ack(AckStatus::Ok));
+
+    queue.clear();
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_tx_queue_consume_past_end_clamps() {
+    let mut queue: TxQueue<64> = TxQueue::new();
+    queue.push(&Response::Ack(AckStatus::Ok));
+
+    queue.consume(1000);
+    assert!(queue.is_empty());
+}
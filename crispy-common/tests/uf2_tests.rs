@@ -0,0 +1,225 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Tests for UF2 parsing, on hand-built files.
+
+use crispy_common::protocol::FW_A_ADDR;
+use crispy_common::uf2::{
+    self, Uf2Error, RP2040_FAMILY_ID, UF2_BLOCK_SIZE, UF2_FLAG_FAMILY_ID, UF2_FLAG_NOT_MAIN_FLASH,
+    UF2_MAGIC_END, UF2_MAGIC_START0, UF2_MAGIC_START1,
+};
+
+const RP2350_ARM_FAMILY_ID: u32 = 0xE48B_FF59;
+
+struct Block {
+    flags: u32,
+    addr: u32,
+    block_no: u32,
+    num_blocks: u32,
+    family_id: u32,
+    payload: Vec<u8>,
+}
+
+impl Block {
+    fn new(block_no: u32, num_blocks: u32, addr: u32, payload: Vec<u8>) -> Self {
+        Self {
+            flags: UF2_FLAG_FAMILY_ID,
+            addr,
+            block_no,
+            num_blocks,
+            family_id: RP2040_FAMILY_ID,
+            payload,
+        }
+    }
+
+    fn encode(&self) -> [u8; UF2_BLOCK_SIZE] {
+        let mut raw = [0u8; UF2_BLOCK_SIZE];
+        let fields = [
+            UF2_MAGIC_START0,
+            UF2_MAGIC_START1,
+            self.flags,
+            self.addr,
+            self.payload.len() as u32,
+            self.block_no,
+            self.num_blocks,
+            self.family_id,
+        ];
+        for (i, value) in fields.iter().enumerate() {
+            raw[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        raw[32..32 + self.payload.len()].copy_from_slice(&self.payload);
+        raw[UF2_BLOCK_SIZE - 4..].copy_from_slice(&UF2_MAGIC_END.to_le_bytes());
+        raw
+    }
+}
+
+fn file(blocks: &[Block]) -> Vec<u8> {
+    blocks.iter().flat_map(|b| b.encode()).collect()
+}
+
+/// `n` blocks of 256 bytes at `base`, as RP2040 tools write them.
+fn blocks(n: u32, base: u32) -> Vec<Block> {
+    (0..n)
+        .map(|i| Block::new(i, n, base + i * 256, vec![i as u8 + 1; 256]))
+        .collect()
+}
+
+fn image_bytes(n: u32) -> Vec<u8> {
+    (0..n).flat_map(|i| vec![i as u8 + 1; 256]).collect()
+}
+
+#[test]
+fn test_parse_contiguous() {
+    let image = uf2::parse(&file(&blocks(3, FW_A_ADDR)), Some(RP2040_FAMILY_ID)).unwrap();
+    assert_eq!(image.base, FW_A_ADDR);
+    assert_eq!(image.family_id, Some(RP2040_FAMILY_ID));
+    assert_eq!(image.data, image_bytes(3));
+}
+
+#[test]
+fn test_parse_ram_image() {
+    let image = uf2::parse(&file(&blocks(2, 0x2000_0000)), Some(RP2040_FAMILY_ID)).unwrap();
+    assert_eq!(image.base, 0x2000_0000);
+    assert_eq!(image.data.len(), 512);
+}
+
+#[test]
+fn test_out_of_order_blocks() {
+    let mut shuffled = blocks(4, FW_A_ADDR);
+    shuffled.swap(0, 3);
+    shuffled.swap(1, 2);
+    let image = uf2::parse(&file(&shuffled), Some(RP2040_FAMILY_ID)).unwrap();
+    assert_eq!(image.base, FW_A_ADDR);
+    assert_eq!(image.data, image_bytes(4));
+}
+
+#[test]
+fn test_gap_rejected() {
+    let mut gapped = blocks(3, FW_A_ADDR);
+    gapped[2].addr += 256;
+    assert_eq!(
+        uf2::parse(&file(&gapped), None),
+        Err(Uf2Error::NotContiguous {
+            expected: FW_A_ADDR + 512,
+            found: FW_A_ADDR + 768
+        })
+    );
+}
+
+#[test]
+fn test_overlap_rejected() {
+    let mut overlapping = blocks(2, FW_A_ADDR);
+    overlapping[1].addr -= 16;
+    assert_eq!(
+        uf2::parse(&file(&overlapping), None),
+        Err(Uf2Error::NotContiguous {
+            expected: FW_A_ADDR + 256,
+            found: FW_A_ADDR + 240
+        })
+    );
+}
+
+#[test]
+fn test_wrong_family() {
+    let mut foreign = blocks(2, FW_A_ADDR);
+    foreign[1].family_id = RP2350_ARM_FAMILY_ID;
+    let uf2 = file(&foreign);
+    assert_eq!(
+        uf2::parse(&uf2, Some(RP2040_FAMILY_ID)),
+        Err(Uf2Error::WrongFamily {
+            block: 1,
+            family_id: RP2350_ARM_FAMILY_ID
+        })
+    );
+    // Without a family to check, any is accepted
+    assert!(uf2::parse(&uf2, None).is_ok());
+
+    // Blocks without a family ID are accepted either way
+    let mut untagged = blocks(2, FW_A_ADDR);
+    for block in &mut untagged {
+        block.flags = 0;
+    }
+    let image = uf2::parse(&file(&untagged), Some(RP2040_FAMILY_ID)).unwrap();
+    assert_eq!(image.family_id, None);
+}
+
+#[test]
+fn test_not_main_flash_blocks_skipped() {
+    let mut with_extra = blocks(3, FW_A_ADDR);
+    with_extra[2].flags |= UF2_FLAG_NOT_MAIN_FLASH;
+    with_extra[2].addr = 0;
+    let image = uf2::parse(&file(&with_extra), Some(RP2040_FAMILY_ID)).unwrap();
+    assert_eq!(image.data, image_bytes(2));
+}
+
+#[test]
+fn test_block_numbers() {
+    let mut repeated = blocks(3, FW_A_ADDR);
+    repeated[2].block_no = 1;
+    assert_eq!(
+        uf2::parse(&file(&repeated), None),
+        Err(Uf2Error::BlockNumbers)
+    );
+
+    // A block missing from the file: the count says 3, there are 2
+    let truncated = &blocks(3, FW_A_ADDR)[..2];
+    assert_eq!(
+        uf2::parse(&file(truncated), None),
+        Err(Uf2Error::BlockNumbers)
+    );
+}
+
+#[test]
+fn test_malformed_blocks() {
+    let good = file(&blocks(2, FW_A_ADDR));
+    assert_eq!(
+        uf2::parse(&good[..UF2_BLOCK_SIZE + 10], None),
+        Err(Uf2Error::Length(UF2_BLOCK_SIZE + 10))
+    );
+    assert_eq!(uf2::parse(&[], None), Err(Uf2Error::Length(0)));
+
+    let mut bad_end = good.clone();
+    bad_end[2 * UF2_BLOCK_SIZE - 1] ^= 0xFF;
+    assert_eq!(
+        uf2::parse(&bad_end, None),
+        Err(Uf2Error::BadMagic { block: 1 })
+    );
+
+    let mut oversized = good;
+    oversized[16..20].copy_from_slice(&477u32.to_le_bytes());
+    assert_eq!(
+        uf2::parse(&oversized, None),
+        Err(Uf2Error::PayloadTooLarge {
+            block: 0,
+            size: 477
+        })
+    );
+}
+
+#[test]
+fn test_bad_address() {
+    assert_eq!(
+        uf2::parse(&file(&blocks(2, 0x0000_1000)), None),
+        Err(Uf2Error::BadAddress(0x0000_1000))
+    );
+    // Runs off the end of the RAM window
+    assert_eq!(
+        uf2::parse(&file(&blocks(2, 0x2004_1F00)), None),
+        Err(Uf2Error::BadAddress(0x2004_1F00))
+    );
+}
+
+#[test]
+fn test_only_extra_blocks_is_empty() {
+    let mut extra = blocks(1, FW_A_ADDR);
+    extra[0].flags |= UF2_FLAG_NOT_MAIN_FLASH;
+    assert_eq!(uf2::parse(&file(&extra), None), Err(Uf2Error::Empty));
+}
+
+#[test]
+fn test_is_uf2() {
+    let uf2 = file(&blocks(1, FW_A_ADDR));
+    assert!(uf2::is_uf2(&uf2));
+    assert!(!uf2::is_uf2(&uf2[..64]));
+    assert!(!uf2::is_uf2(&[0u8; UF2_BLOCK_SIZE]));
+}
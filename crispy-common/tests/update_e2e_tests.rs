@@ -0,0 +1,393 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! End-to-end update scenarios: a command stream goes in, and the full
+//! response transcript, the bank bytes, the stored BootData and the next boot
+//! decision come out.
+
+use crispy_common::boot_fsm::{select_boot_bank_fsm, BankPair, BankValidation, BootDecision};
+use crispy_common::boot_recovery::{
+    bank_looks_bootable, reconstruct_boot_data, scan_banks, RamWindow,
+};
+use crispy_common::crc32;
+use crispy_common::flash::{compute_crc32, confirm_boot, read_boot_data, write_boot_data};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::{Fault, FlashSim};
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{
+    AckStatus, BootData, BootDataError, Command, Response, FLASH_BASE, FW_A_ADDR, FW_B_ADDR,
+    MAX_DATA_BLOCK_SIZE,
+};
+use crispy_common::update_fsm::{handle_command, ResponseSink, UpdateState};
+use crispy_common::version::FwVersion;
+
+const RAM: RamWindow = RamWindow {
+    start: 0x2000_0000,
+    end: 0x2003_BFFF,
+};
+
+const OK: Response = Response::Ack(AckStatus::Ok);
+
+#[derive(Default)]
+struct Transcript(Vec<Response>);
+
+impl ResponseSink for Transcript {
+    fn send(&mut self, response: &Response) {
+        self.0.push(response.clone());
+    }
+}
+
+/// A device in update mode: flash that survives resets, plus the volatile
+/// update state.
+struct Device {
+    flash: FlashSim,
+    state: UpdateState,
+}
+
+impl Device {
+    fn new() -> Self {
+        Self {
+            flash: FlashSim::new().strict(),
+            state: UpdateState::Idle,
+        }
+    }
+
+    /// Feed a command stream and return every response it produced.
+    fn feed(&mut self, cmds: impl IntoIterator<Item = Command>) -> Vec<Response> {
+        let mut transcript = Transcript::default();
+        for cmd in cmds {
+            self.state = handle_command(&mut self.flash, &mut transcript, self.state, cmd);
+        }
+        transcript.0
+    }
+
+    /// Power back on and come up in update mode again.
+    fn reset(&mut self) {
+        self.flash.power_cycle();
+        self.state = UpdateState::Idle;
+    }
+
+    fn bank(&self, addr: u32, len: usize) -> &[u8] {
+        let start = (addr - FLASH_BASE) as usize;
+        &self.flash.contents()[start..start + len]
+    }
+
+    fn read_word(&self, addr: u32) -> u32 {
+        let mut word = [0u8; 4];
+        self.flash.read(addr, &mut word);
+        u32::from_le_bytes(word)
+    }
+
+    fn validate(&self, addr: u32, crc: u32, size: u32) -> BankValidation {
+        BankValidation {
+            crc_valid: size != 0 && compute_crc32(&self.flash, addr, size) == crc,
+            basic_valid: bank_looks_bootable(|a| self.read_word(a), addr, RAM),
+        }
+    }
+
+    /// What the bootloader would decide on the next reset, recovering a
+    /// rejected BootData the way `boot.rs` does.
+    fn next_boot(&self) -> BootDecision {
+        let bd = read_boot_data(&self.flash).unwrap_or_else(|_| {
+            let scan = scan_banks(|a| self.read_word(a), FW_A_ADDR, FW_B_ADDR, RAM);
+            reconstruct_boot_data(scan).expect("no bootable bank")
+        });
+        let banks = BankPair::new(bd.active_bank, &FlashLayout::DEFAULT, &bd);
+        let primary = self.validate(banks.primary.addr, banks.primary.crc, banks.primary.size);
+        let fallback = self.validate(banks.fallback.addr, banks.fallback.crc, banks.fallback.size);
+        let banks = banks.with_validation(primary, fallback);
+        select_boot_bank_fsm(&bd, banks, bd.boot_attempt_limit())
+    }
+}
+
+/// A firmware image with a bootable vector table and `seed`-dependent body.
+fn firmware(len: usize, seed: u8) -> Vec<u8> {
+    let mut fw: Vec<u8> = (0..len)
+        .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+        .collect();
+    fw[..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+    fw[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+    fw
+}
+
+fn start(bank: u8, fw: &[u8], version: u32) -> Command {
+    Command::StartUpdate {
+        bank,
+        size: fw.len() as u32,
+        crc32: crc32::checksum(fw),
+        version: version.into(),
+        sparse: false,
+    }
+}
+
+fn blocks(fw: &[u8], chunk: usize) -> Vec<Command> {
+    fw.chunks(chunk)
+        .enumerate()
+        .map(|(i, data)| Command::DataBlock {
+            offset: (i * chunk) as u32,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+/// The stream `crispy-upload` sends for one image.
+fn update_stream(bank: u8, fw: &[u8], version: u32, chunk: usize) -> Vec<Command> {
+    let mut cmds = vec![start(bank, fw, version)];
+    cmds.extend(blocks(fw, chunk));
+    cmds.push(Command::FinishUpdate);
+    cmds
+}
+
+fn oks(n: usize) -> Vec<Response> {
+    vec![OK; n]
+}
+
+/// Install `fw` in bank A and confirm it, as a device in the field would be.
+fn device_running_a(fw: &[u8]) -> Device {
+    let mut dev = Device::new();
+    let responses = dev.feed(update_stream(0, fw, 1, MAX_DATA_BLOCK_SIZE));
+    assert_eq!(responses.last(), Some(&OK));
+    confirm_boot(&mut dev.flash).unwrap();
+    dev
+}
+
+// --- Happy path ---
+
+#[test]
+fn test_update_then_boot_new_bank() {
+    let mut dev = Device::new();
+    let fw = firmware(5000, 0x11);
+
+    // Start, five blocks, finish
+    let responses = dev.feed(update_stream(1, &fw, 7, MAX_DATA_BLOCK_SIZE));
+    assert_eq!(responses, oks(7));
+    assert_eq!(dev.state, UpdateState::Idle);
+
+    assert_eq!(dev.bank(FW_B_ADDR, fw.len()), &fw[..]);
+    // The padding after the image stays erased
+    assert!(dev.bank(FW_B_ADDR + 5000, 120).iter().all(|&b| b == 0xFF));
+
+    let bd = read_boot_data(&dev.flash).unwrap();
+    assert_eq!(
+        bd,
+        BootData {
+            active_bank: 1,
+            version_b: FwVersion::from_raw(7),
+            crc_b: crc32::checksum(&fw),
+            size_b: 5000,
+            record_crc: bd.record_crc,
+            ..BootData::default_new()
+        }
+    );
+
+    let boot = dev.next_boot();
+    assert_eq!((boot.flash_addr, boot.boot_attempts), (FW_B_ADDR, 1));
+    assert!(!boot.keep_confirmed);
+}
+
+#[test]
+fn test_second_update_switches_banks() {
+    let fw_a = firmware(3000, 0x22);
+    let mut dev = device_running_a(&fw_a);
+    let fw_b = firmware(4096, 0x33);
+
+    assert_eq!(
+        dev.feed(update_stream(1, &fw_b, 2, MAX_DATA_BLOCK_SIZE)),
+        oks(6)
+    );
+
+    let bd = read_boot_data(&dev.flash).unwrap();
+    assert_eq!((bd.active_bank, bd.confirmed), (1, 0));
+    assert_eq!((bd.version_a, bd.size_a), (FwVersion::from_raw(1), 3000));
+    assert_eq!((bd.version_b, bd.size_b), (FwVersion::from_raw(2), 4096));
+    assert_eq!(dev.bank(FW_A_ADDR, fw_a.len()), &fw_a[..]);
+    assert_eq!(dev.next_boot().flash_addr, FW_B_ADDR);
+}
+
+// --- Chunk sizes ---
+
+#[test]
+fn test_page_sized_chunks() {
+    let mut dev = Device::new();
+    let fw = firmware(1000, 0x44);
+
+    // 256, 256, 256, 232
+    assert_eq!(dev.feed(update_stream(0, &fw, 1, 256)), oks(6));
+    assert_eq!(dev.bank(FW_A_ADDR, fw.len()), &fw[..]);
+}
+
+#[test]
+fn test_chunks_not_page_multiples_rejected() {
+    let mut dev = Device::new();
+    let fw = firmware(2500, 0x55);
+
+    let responses = dev.feed(update_stream(0, &fw, 1, 1000));
+    assert_eq!(
+        responses,
+        vec![
+            OK,
+            Response::Ack(AckStatus::BadCommand), // 1000 bytes, not the last block
+            Response::Ack(AckStatus::BadCommand), // offset 1000, nothing received
+            Response::Ack(AckStatus::BadCommand), // offset 2000
+            Response::Ack(AckStatus::BadCommand), // FinishUpdate with 0 bytes
+        ]
+    );
+    assert!(dev.state.is_receiving());
+
+    // The transfer is still open; the same image in page multiples completes it
+    let mut retry = blocks(&fw, 512);
+    retry.push(Command::FinishUpdate);
+    assert_eq!(dev.feed(retry), oks(6));
+    assert_eq!(dev.bank(FW_A_ADDR, fw.len()), &fw[..]);
+}
+
+// --- Retransmission and abort ---
+
+#[test]
+fn test_retransmitted_block_is_rejected_and_harmless() {
+    let mut dev = Device::new();
+    let fw = firmware(3072, 0x66);
+    // The host missed the Ack for block 1 and sends it again
+    let mut stream = vec![start(0, &fw, 1)];
+    stream.extend(blocks(&fw, 1024).into_iter().take(2));
+    stream.push(blocks(&fw, 1024).swap_remove(1));
+    stream.extend(blocks(&fw, 1024).into_iter().skip(2));
+    stream.push(Command::FinishUpdate);
+    assert_eq!(
+        dev.feed(stream),
+        vec![OK, OK, OK, Response::Ack(AckStatus::BadCommand), OK, OK]
+    );
+    assert_eq!(dev.bank(FW_A_ADDR, fw.len()), &fw[..]);
+    assert_eq!(dev.next_boot().flash_addr, FW_A_ADDR);
+}
+
+#[test]
+fn test_abort_and_restart_with_other_image() {
+    let fw_a = firmware(2048, 0x77);
+    let mut dev = device_running_a(&fw_a);
+    let stored = read_boot_data(&dev.flash).unwrap();
+    let abandoned = firmware(8192, 0x88);
+    let fw_b = firmware(3000, 0x99);
+
+    let mut stream = vec![start(1, &abandoned, 5)];
+    stream.extend(blocks(&abandoned, 1024).into_iter().take(3));
+    stream.push(start(1, &fw_b, 6));
+    assert_eq!(
+        dev.feed(stream),
+        vec![OK, OK, OK, OK, Response::Ack(AckStatus::BadState)]
+    );
+
+    // The host gives up; the platform drops the transfer
+    dev.state = UpdateState::Idle;
+    assert_eq!(read_boot_data(&dev.flash).unwrap(), stored);
+
+    assert_eq!(dev.feed(update_stream(1, &fw_b, 6, 1024)), oks(5));
+    assert_eq!(dev.bank(FW_B_ADDR, fw_b.len()), &fw_b[..]);
+    // Nothing of the abandoned image is left in the erased range
+    assert!(dev
+        .bank(FW_B_ADDR + 3000, 4096 - 3000)
+        .iter()
+        .all(|&b| b == 0xFF));
+    assert_eq!(
+        read_boot_data(&dev.flash).unwrap().version_b,
+        FwVersion::from_raw(6)
+    );
+}
+
+// --- CRC mismatch ---
+
+#[test]
+fn test_crc_mismatch_keeps_running_firmware() {
+    let fw_a = firmware(2048, 0xAA);
+    let mut dev = device_running_a(&fw_a);
+    let stored = read_boot_data(&dev.flash).unwrap();
+    let fw_b = firmware(2048, 0xBB);
+
+    let mut stream = update_stream(1, &fw_b, 2, 1024);
+    stream[0] = Command::StartUpdate {
+        bank: 1,
+        size: 2048,
+        crc32: crc32::checksum(&fw_b) ^ 1,
+        version: FwVersion::from_raw(2),
+        sparse: false,
+    };
+    assert_eq!(
+        dev.feed(stream),
+        vec![OK, OK, OK, Response::Ack(AckStatus::CrcError)]
+    );
+    assert_eq!(dev.state, UpdateState::Idle);
+    assert_eq!(read_boot_data(&dev.flash).unwrap(), stored);
+
+    let boot = dev.next_boot();
+    assert_eq!(boot.flash_addr, FW_A_ADDR);
+    assert!(boot.keep_confirmed);
+}
+
+// --- Power loss ---
+
+#[test]
+fn test_power_loss_during_data_then_restart() {
+    let fw_a = firmware(2048, 0xCC);
+    let mut dev = device_running_a(&fw_a);
+    let stored = read_boot_data(&dev.flash).unwrap();
+    let fw_b = firmware(4096, 0xDD);
+
+    let mut stream = update_stream(1, &fw_b, 2, 1024).into_iter();
+    dev.feed(stream.by_ref().take(2));
+    // Power goes during the second block
+    dev.flash.inject(Fault::PowerLoss { op: 1, bytes: 300 });
+    dev.feed(stream.by_ref().take(1));
+    dev.reset();
+
+    assert_eq!(read_boot_data(&dev.flash).unwrap(), stored);
+    assert_eq!(dev.next_boot().flash_addr, FW_A_ADDR);
+
+    // The host starts over
+    assert_eq!(dev.feed(update_stream(1, &fw_b, 2, 1024)), oks(6));
+    assert_eq!(dev.bank(FW_B_ADDR, fw_b.len()), &fw_b[..]);
+}
+
+#[test]
+fn test_power_loss_between_boot_data_erase_and_program() {
+    let fw_a = firmware(2048, 0xEE);
+    let mut dev = device_running_a(&fw_a);
+    let fw_b = firmware(2048, 0xF0);
+
+    let mut stream = update_stream(1, &fw_b, 2, 1024);
+    let finish = stream.split_off(3);
+    assert_eq!(dev.feed(stream), oks(3));
+    // FinishUpdate erases the BootData sector (op 1), then programs it (op 2)
+    dev.flash.inject(Fault::PowerLoss { op: 2, bytes: 0 });
+    dev.feed(finish);
+    dev.reset();
+
+    assert_eq!(
+        read_boot_data(&dev.flash),
+        Err(BootDataError::BadMagic(0xFFFF_FFFF))
+    );
+    // Recovery finds both banks bootable and prefers A
+    let boot = dev.next_boot();
+    assert_eq!((boot.flash_addr, boot.active_bank), (FW_A_ADDR, 0));
+}
+
+#[test]
+fn test_power_loss_mid_boot_data_program() {
+    let fw_a = firmware(2048, 0x12);
+    let mut dev = device_running_a(&fw_a);
+    let fw_b = firmware(2048, 0x34);
+
+    let mut stream = update_stream(1, &fw_b, 2, 1024);
+    let finish = stream.split_off(3);
+    assert_eq!(dev.feed(stream), oks(3));
+    dev.flash.inject(Fault::PowerLoss { op: 2, bytes: 40 });
+    dev.feed(finish);
+    dev.reset();
+
+    assert_eq!(read_boot_data(&dev.flash), Err(BootDataError::BadCrc));
+    assert_eq!(dev.next_boot().flash_addr, FW_A_ADDR);
+
+    // Once the record is rewritten the device is back to normal
+    write_boot_data(&mut dev.flash, &BootData::default_new()).unwrap();
+    assert_eq!(dev.feed(update_stream(1, &fw_b, 2, 1024)), oks(4));
+    assert_eq!(dev.next_boot().flash_addr, FW_B_ADDR);
+}
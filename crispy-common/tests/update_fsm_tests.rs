@@ -0,0 +1,1207 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Command-sequence tests for the update state machine over the flash simulator.
+
+use crispy_common::crc32;
+use crispy_common::flash::{read_boot_data, read_provision, write_boot_data, BankVerify};
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::{Fault, FlashSim};
+use crispy_common::image::{ImageBuilder, ImageHeader};
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{
+    AckStatus, BootData, BootState, Command, Response, DEFAULT_MAX_BOOT_ATTEMPTS, FLASH_BASE,
+    FLASH_SECTOR_SIZE, FW_A_ADDR, FW_BANK_SIZE, FW_B_ADDR, MAX_BOOT_ATTEMPTS_RANGE,
+    MAX_DATA_BLOCK_SIZE, PROVISION_ADDR,
+};
+use crispy_common::provision::{Provision, ProvisionError, PROVISION_SIZE};
+use crispy_common::update_fsm::{handle_command, ResponseSink, UpdateState};
+use crispy_common::version::FwVersion;
+
+/// Records everything the state machine sends.
+#[derive(Default)]
+struct RecordingSink {
+    responses: Vec<Response>,
+    logs: Vec<String>,
+    keep_alives: u32,
+    progress: Vec<(u32, u32)>,
+}
+
+impl ResponseSink for RecordingSink {
+    fn send(&mut self, response: &Response) {
+        self.responses.push(response.clone());
+    }
+
+    fn log(&mut self, line: &str) {
+        self.logs.push(line.to_string());
+    }
+
+    fn keep_alive(&mut self) {
+        self.keep_alives += 1;
+    }
+
+    fn progress(&mut self, done: u32, total: u32) {
+        self.progress.push((done, total));
+    }
+}
+
+/// Mock flash, sink and state driven together like the update loop does.
+struct Session {
+    flash: FlashSim,
+    sink: RecordingSink,
+    state: UpdateState,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self::with_flash(FlashSim::new())
+    }
+
+    fn with_flash(flash: FlashSim) -> Self {
+        Self {
+            flash,
+            sink: RecordingSink::default(),
+            state: UpdateState::Idle,
+        }
+    }
+
+    /// Run one command and return the single response it produced.
+    fn run(&mut self, cmd: Command) -> Response {
+        let before = self.sink.responses.len();
+        self.state = handle_command(&mut self.flash, &mut self.sink, self.state, cmd);
+        assert_eq!(
+            self.sink.responses.len(),
+            before + 1,
+            "one response per command"
+        );
+        self.sink.responses.last().unwrap().clone()
+    }
+
+    fn start(&mut self, bank: u8, image: &[u8], version: u32) -> Response {
+        self.run(Command::StartUpdate {
+            bank,
+            size: image.len() as u32,
+            crc32: crc32::checksum(image),
+            version: version.into(),
+            sparse: false,
+        })
+    }
+
+    /// Send `image` in `MAX_DATA_BLOCK_SIZE` blocks, stopping at the first non-Ok reply.
+    fn send_image(&mut self, image: &[u8]) -> Response {
+        let mut last = Response::Ack(AckStatus::Ok);
+        for (i, chunk) in image.chunks(MAX_DATA_BLOCK_SIZE).enumerate() {
+            last = self.run(data_block((i * MAX_DATA_BLOCK_SIZE) as u32, chunk));
+            if last != Response::Ack(AckStatus::Ok) {
+                break;
+            }
+        }
+        last
+    }
+
+    fn upload(&mut self, bank: u8, image: &[u8], version: u32) -> Response {
+        assert_eq!(self.start(bank, image, version), ack(AckStatus::Ok));
+        assert_eq!(self.send_image(image), ack(AckStatus::Ok));
+        self.run(Command::FinishUpdate)
+    }
+
+    fn boot_data(&self) -> BootData {
+        read_boot_data(&self.flash).unwrap()
+    }
+
+    fn bank(&self, addr: u32, len: usize) -> Vec<u8> {
+        let start = (addr - FLASH_BASE) as usize;
+        self.flash.contents()[start..start + len].to_vec()
+    }
+}
+
+fn ack(status: AckStatus) -> Response {
+    Response::Ack(status)
+}
+
+fn data_block(offset: u32, data: &[u8]) -> Command {
+    Command::DataBlock {
+        offset,
+        data: data.to_vec(),
+    }
+}
+
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 + 7) as u8).collect()
+}
+
+// --- Happy path ---
+
+#[test]
+fn test_full_update_to_bank_b() {
+    let mut s = Session::new();
+    let fw = image(5000);
+
+    assert_eq!(s.upload(1, &fw, 42), ack(AckStatus::Ok));
+    assert_eq!(s.state, UpdateState::Idle);
+    assert_eq!(s.bank(FW_B_ADDR, fw.len()), fw);
+
+    let bd = s.boot_data();
+    assert!(bd.is_valid());
+    assert_eq!(bd.active_bank, 1);
+    assert_eq!(bd.confirmed, 0);
+    assert_eq!(bd.boot_attempts, 0);
+    assert_eq!(
+        (bd.version_b, bd.size_b, bd.crc_b),
+        (FwVersion::from_raw(42), 5000, crc32::checksum(&fw))
+    );
+    assert_eq!(s.sink.logs.last().unwrap(), "Update complete");
+}
+
+#[test]
+fn test_update_keeps_other_bank_metadata() {
+    let mut s = Session::new();
+    let fw_a = image(2048);
+    let fw_b = image(3000);
+
+    assert_eq!(s.upload(0, &fw_a, 1), ack(AckStatus::Ok));
+    assert_eq!(s.upload(1, &fw_b, 2), ack(AckStatus::Ok));
+
+    let bd = s.boot_data();
+    assert_eq!(bd.active_bank, 1);
+    assert_eq!((bd.version_a, bd.size_a), (FwVersion::from_raw(1), 2048));
+    assert_eq!((bd.version_b, bd.size_b), (FwVersion::from_raw(2), 3000));
+    assert_eq!(s.bank(FW_A_ADDR, fw_a.len()), fw_a);
+}
+
+#[test]
+fn test_start_update_erases_previous_contents() {
+    let mut s = Session::new();
+    s.flash.poke(FW_A_ADDR + 100, &[0x00; 16]);
+
+    assert_eq!(s.start(0, &image(256), 1), ack(AckStatus::Ok));
+    assert!(s.bank(FW_A_ADDR, 256).iter().all(|&b| b == 0xFF));
+    assert!(s.state.is_receiving());
+    assert_eq!(s.state.boot_state(), BootState::Receiving);
+}
+
+#[test]
+fn test_start_update_reports_erase_progress() {
+    let mut s = Session::new();
+    let fw = image(2 * FLASH_SECTOR_SIZE as usize + 1);
+    assert_eq!(s.start(0, &fw, 1), ack(AckStatus::Ok));
+
+    let erased = 3 * FLASH_SECTOR_SIZE;
+    let sectors: Vec<_> = (1..=3).map(|n| (n * FLASH_SECTOR_SIZE, erased)).collect();
+    assert_eq!(s.sink.progress, sectors);
+}
+
+#[test]
+fn test_long_operations_keep_platform_alive() {
+    let mut s = Session::new();
+    let fw = image(3 * FLASH_SECTOR_SIZE as usize);
+
+    s.upload(0, &fw, 1);
+
+    // One per erased sector plus one per blank-checked chunk
+    assert!(s.sink.keep_alives >= 6);
+    assert_eq!(
+        s.sink.progress.last(),
+        Some(&(fw.len() as u32, fw.len() as u32))
+    );
+}
+
+// --- State and sequencing errors ---
+
+#[test]
+fn test_finish_before_all_data() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.start(0, &fw, 1);
+    s.run(data_block(0, &fw[..1024]));
+
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::BadCommand));
+    // Still receiving; the transfer can be completed
+    assert!(s.state.is_receiving());
+    assert_eq!(s.run(data_block(1024, &fw[1024..])), ack(AckStatus::Ok));
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::Ok));
+}
+
+#[test]
+fn test_data_block_wrong_offset() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.start(0, &fw, 1);
+
+    assert_eq!(
+        s.run(data_block(1024, &fw[..1024])),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(s.run(data_block(0, &fw[..1024])), ack(AckStatus::Ok));
+    assert_eq!(
+        s.run(data_block(0, &fw[..1024])),
+        ack(AckStatus::BadCommand)
+    );
+}
+
+#[test]
+fn test_data_block_beyond_declared_size() {
+    let mut s = Session::new();
+    s.start(0, &image(1000), 1);
+
+    assert_eq!(
+        s.run(data_block(0, &image(1024))),
+        ack(AckStatus::BadCommand)
+    );
+}
+
+#[test]
+fn test_data_block_over_block_limit() {
+    let mut s = Session::new();
+    s.start(0, &image(4096), 1);
+
+    assert_eq!(
+        s.run(data_block(0, &image(MAX_DATA_BLOCK_SIZE + 1))),
+        ack(AckStatus::BadCommand)
+    );
+}
+
+#[test]
+fn test_invalid_command_rejected_before_dispatch() {
+    let mut s = Session::new();
+    s.start(0, &image(4096), 1);
+
+    // Stateless checks come first, even when the state would also refuse it
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 7 }),
+        ack(AckStatus::BankInvalid)
+    );
+    assert_eq!(
+        s.run(data_block(FW_BANK_SIZE - 256, &image(512))),
+        ack(AckStatus::BadCommand)
+    );
+    assert!(s.state.is_receiving());
+    assert_eq!(s.flash.program_count, 0);
+}
+
+#[test]
+fn test_commands_outside_transfer() {
+    let mut s = Session::new();
+
+    assert_eq!(s.run(data_block(0, &image(16))), ack(AckStatus::BadState));
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::BadState));
+    assert_eq!(s.flash.program_count, 0);
+}
+
+#[test]
+fn test_commands_blocked_during_transfer() {
+    let mut s = Session::new();
+    s.start(0, &image(1024), 1);
+
+    assert_eq!(s.start(1, &image(1024), 2), ack(AckStatus::BadState));
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 1 }),
+        ack(AckStatus::BadState)
+    );
+    assert_eq!(s.run(Command::WipeAll), ack(AckStatus::BadState));
+    assert!(s.state.is_receiving());
+}
+
+#[test]
+fn test_abort_discards_transfer() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.start(0, &fw, 1);
+    s.run(data_block(0, &fw[..1024]));
+
+    // What the platform does when the host goes away
+    s.state = UpdateState::Idle;
+
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::BadState));
+    assert!(read_boot_data(&s.flash).is_err());
+    // A fresh transfer starts from offset 0 again
+    assert_eq!(s.upload(0, &fw, 1), ack(AckStatus::Ok));
+}
+
+#[test]
+fn test_platform_commands_rejected() {
+    let mut s = Session::new();
+    for cmd in [
+        Command::GetStatus,
+        Command::GetFlashInfo,
+        Command::GetDeviceInfo,
+        Command::Reboot,
+    ] {
+        assert_eq!(s.run(cmd), ack(AckStatus::BadCommand));
+    }
+}
+
+// --- Parameter validation ---
+
+#[test]
+fn test_start_update_invalid_bank() {
+    let mut s = Session::new();
+    assert_eq!(s.start(2, &image(16), 1), ack(AckStatus::BankInvalid));
+    assert_eq!(s.flash.erase_count, 0);
+}
+
+#[test]
+fn test_start_update_oversize() {
+    let mut s = Session::new();
+    let resp = s.run(Command::StartUpdate {
+        bank: 0,
+        size: FW_BANK_SIZE + 1,
+        crc32: 0,
+        version: FwVersion::from_raw(1),
+        sparse: false,
+    });
+    assert_eq!(resp, ack(AckStatus::BankInvalid));
+    assert_eq!(s.state, UpdateState::Idle);
+    assert_eq!(s.flash.erase_count, 0);
+}
+
+#[test]
+fn test_start_update_zero_size() {
+    let mut s = Session::new();
+    assert_eq!(s.start(0, &[], 1), ack(AckStatus::BankInvalid));
+}
+
+// --- Sparse uploads ---
+
+/// Start a sparse upload of `fw` to bank A.
+fn start_sparse(s: &mut Session, fw: &[u8]) {
+    let resp = s.run(Command::StartUpdate {
+        bank: 0,
+        size: fw.len() as u32,
+        crc32: crc32::checksum(fw),
+        version: FwVersion::from_raw(1),
+        sparse: true,
+    });
+    assert_eq!(resp, ack(AckStatus::Ok));
+}
+
+#[test]
+fn test_sparse_upload_skips_erased_pages() {
+    let mut s = Session::new();
+    // Data, a page of padding, data, then padding to the end
+    let mut fw = vec![0xFF; 2048];
+    fw[..256].copy_from_slice(&image(256));
+    fw[512..768].copy_from_slice(&image(256));
+    start_sparse(&mut s, &fw);
+
+    assert_eq!(s.run(data_block(0, &fw[..256])), ack(AckStatus::Ok));
+    assert_eq!(s.run(data_block(512, &fw[512..768])), ack(AckStatus::Ok));
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::Ok));
+
+    let bd = s.boot_data();
+    assert_eq!((bd.size_a, bd.crc_a), (2048, crc32::checksum(&fw)));
+}
+
+#[test]
+fn test_sparse_upload_refuses_rewind_and_unaligned_blocks() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    start_sparse(&mut s, &fw);
+
+    assert_eq!(s.run(data_block(512, &fw[512..768])), ack(AckStatus::Ok));
+    // Behind the last block, or not on a page
+    assert_eq!(
+        s.run(data_block(256, &fw[256..512])),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(
+        s.run(data_block(800, &fw[800..1056])),
+        ack(AckStatus::BadCommand)
+    );
+    // Past the declared size
+    assert_eq!(
+        s.run(data_block(1792, &image(512))),
+        ack(AckStatus::BadCommand)
+    );
+
+    // The pages never sent read as erased, so the CRC catches them
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::CrcError));
+}
+
+#[test]
+fn test_sequential_upload_refuses_gaps() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    assert_eq!(s.start(0, &fw, 1), ack(AckStatus::Ok));
+
+    assert_eq!(s.run(data_block(0, &fw[..1024])), ack(AckStatus::Ok));
+    assert_eq!(
+        s.run(data_block(1280, &fw[1280..])),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::BadCommand));
+}
+
+// --- CRC and flash failures ---
+
+#[test]
+fn test_crc_mismatch_leaves_boot_data() {
+    let mut s = Session::new();
+    let mut original = BootData::default_new();
+    original.version_a = FwVersion::from_raw(9);
+    write_boot_data(&mut s.flash, &original).unwrap();
+
+    let fw = image(1500);
+    let resp = s.run(Command::StartUpdate {
+        bank: 1,
+        size: fw.len() as u32,
+        crc32: crc32::checksum(&fw) ^ 1,
+        version: FwVersion::from_raw(2),
+        sparse: false,
+    });
+    assert_eq!(resp, ack(AckStatus::Ok));
+    s.send_image(&fw);
+
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::CrcError));
+    assert_eq!(s.state, UpdateState::Idle);
+    assert_eq!(s.boot_data().to_bytes(), original.to_bytes());
+    assert_eq!(s.sink.logs.last().unwrap(), "Update failed: CRC mismatch");
+}
+
+#[test]
+fn test_program_failure_reports_bank_offset() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.start(1, &fw, 1);
+    s.run(data_block(0, &fw[..1024]));
+
+    // A stuck-at-zero byte that programming cannot raise
+    let bad = fw.iter().skip(1024).position(|&b| b != 0).unwrap() as u32 + 1024;
+    s.flash.poke(FW_B_ADDR + bad, &[0x00]);
+
+    assert_eq!(
+        s.run(data_block(1024, &fw[1024..])),
+        Response::Nack {
+            status: AckStatus::FlashError,
+            offset: bad,
+        }
+    );
+    assert_eq!(s.state, UpdateState::Idle);
+}
+
+// --- SetActiveBank and WipeAll ---
+
+#[test]
+fn test_set_active_bank_after_update() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.upload(1, &image(2048), 2);
+
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 0 }),
+        ack(AckStatus::Ok)
+    );
+    assert_eq!(s.boot_data().active_bank, 0);
+}
+
+#[test]
+fn test_set_active_bank_empty_bank() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 1 }),
+        ack(AckStatus::BankInvalid)
+    );
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 2 }),
+        ack(AckStatus::BankInvalid)
+    );
+    assert_eq!(s.boot_data().active_bank, 0);
+}
+
+#[test]
+fn test_set_active_bank_corrupt_image() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.upload(1, &image(1024), 2);
+    s.flash.poke(FW_A_ADDR + 10, &[0x00]);
+
+    assert_eq!(
+        s.run(Command::SetActiveBank { bank: 0 }),
+        ack(AckStatus::CrcError)
+    );
+    assert_eq!(s.boot_data().active_bank, 1);
+}
+
+#[test]
+fn test_wipe_all_resets_boot_data() {
+    let mut s = Session::new();
+    s.upload(1, &image(1024), 3);
+
+    assert_eq!(s.run(Command::WipeAll), ack(AckStatus::Ok));
+    assert_eq!(s.boot_data().to_bytes(), BootData::default_new().to_bytes());
+}
+
+#[test]
+fn test_boot_data_write_failure_reported() {
+    let mut s = Session::new();
+    s.upload(1, &image(1024), 3);
+    // Every erase and program from here on has no effect
+    s.flash.inject(Fault::PowerLoss { op: 1, bytes: 0 });
+
+    assert_eq!(s.run(Command::WipeAll), ack(AckStatus::FlashError));
+    assert_eq!(s.sink.logs.last().unwrap(), "BootData write failed");
+    assert_eq!(s.boot_data().size_b, 1024);
+}
+
+// --- CopyBank ---
+
+#[test]
+fn test_copy_bank_clones_image_and_metadata() {
+    let mut s = Session::new();
+    let fw = image(3 * FLASH_SECTOR_SIZE as usize + 100);
+    s.upload(0, &fw, 7);
+    s.upload(1, &image(512), 2);
+    s.sink.progress.clear();
+
+    assert_eq!(
+        s.run(Command::CopyBank { from: 0, to: 1 }),
+        ack(AckStatus::Ok)
+    );
+    assert_eq!(s.bank(FW_B_ADDR, fw.len()), fw);
+
+    let bd = s.boot_data();
+    assert_eq!((bd.size_b, bd.crc_b), (bd.size_a, bd.crc_a));
+    assert_eq!(bd.version_b, FwVersion::from_raw(7));
+    assert_eq!(bd.active_bank, 1, "active bank is left alone");
+    let total = 3 * fw.len() as u32;
+    assert!(s.sink.progress.iter().all(|&(_, t)| t == total));
+    assert!(s.sink.progress.windows(2).all(|w| w[0].0 <= w[1].0));
+}
+
+#[test]
+fn test_copy_bank_corrupt_source() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.flash.poke(FW_A_ADDR + 10, &[0x00]);
+    let before = s.boot_data();
+
+    assert_eq!(
+        s.run(Command::CopyBank { from: 0, to: 1 }),
+        ack(AckStatus::CrcError)
+    );
+    assert_eq!(s.boot_data(), before);
+    assert!(s.bank(FW_B_ADDR, 16).iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn test_copy_bank_write_failure_clears_destination_metadata() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.upload(0, &fw, 7);
+    s.upload(1, &image(512), 2);
+
+    // A byte in bank B that programming cannot bring to the source's value
+    s.flash.inject(Fault::StuckByte {
+        addr: FW_B_ADDR + 300,
+        value: 0,
+    });
+    assert_ne!(fw[300], 0);
+
+    assert_eq!(
+        s.run(Command::CopyBank { from: 0, to: 1 }),
+        Response::Nack {
+            status: AckStatus::FlashError,
+            offset: 300,
+        }
+    );
+    let bd = s.boot_data();
+    assert_eq!((bd.size_b, bd.crc_b, bd.version_b.raw()), (0, 0, 0));
+    assert_eq!(bd.size_a, fw.len() as u32, "source is left alone");
+}
+
+#[test]
+fn test_copy_bank_failed_erase_clears_destination_metadata() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 7);
+    s.upload(1, &[0u8; 512], 2);
+
+    // The first sector erase of bank B has no effect
+    s.flash.inject(Fault::FailOp(1));
+
+    assert!(matches!(
+        s.run(Command::CopyBank { from: 0, to: 1 }),
+        Response::Nack {
+            status: AckStatus::FlashError,
+            ..
+        }
+    ));
+    let bd = s.boot_data();
+    assert_eq!((bd.size_b, bd.crc_b, bd.version_b.raw()), (0, 0, 0));
+    assert_eq!(
+        s.run(Command::CopyBank { from: 1, to: 0 }),
+        ack(AckStatus::BankInvalid)
+    );
+}
+
+#[test]
+fn test_copy_bank_rejected() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+
+    // Empty source
+    assert_eq!(
+        s.run(Command::CopyBank { from: 1, to: 0 }),
+        ack(AckStatus::BankInvalid)
+    );
+    assert_eq!(
+        s.run(Command::CopyBank { from: 0, to: 0 }),
+        ack(AckStatus::BankInvalid)
+    );
+
+    s.start(1, &image(64), 2);
+    assert_eq!(
+        s.run(Command::CopyBank { from: 0, to: 1 }),
+        ack(AckStatus::BadState)
+    );
+    assert_eq!(s.boot_data().size_a, 1024);
+}
+
+// --- GetBootData ---
+
+#[test]
+fn test_get_boot_data_reports_record_and_banks() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.upload(1, &fw, 4);
+    s.sink.progress.clear();
+
+    let Response::BootData { raw, banks } = s.run(Command::GetBootData) else {
+        panic!("expected BootData");
+    };
+    assert_eq!(
+        BootData::from_bytes(raw[..].try_into().unwrap()).unwrap(),
+        s.boot_data()
+    );
+    assert_eq!(banks[0], BankVerify::NoMetadata);
+    assert_eq!(
+        banks[1],
+        BankVerify::Ok {
+            size: 2048,
+            crc: crc32::checksum(&fw),
+        }
+    );
+    assert_eq!(s.sink.progress.last(), Some(&(2048, 2048)));
+}
+
+#[test]
+fn test_get_boot_data_sends_corrupt_record_as_is() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    let bd_addr = FlashLayout::DEFAULT.boot_data;
+    s.flash.poke(bd_addr + 40, &[0x5A]);
+
+    let Response::BootData { raw, banks } = s.run(Command::GetBootData) else {
+        panic!("expected BootData");
+    };
+    assert_eq!(raw[..], s.bank(bd_addr, raw.len())[..]);
+    assert!(BootData::from_bytes(raw[..].try_into().unwrap()).is_err());
+    assert_eq!(banks, [BankVerify::NoMetadata; 2]);
+}
+
+// --- ReadFlash ---
+
+#[test]
+fn test_read_flash_returns_bank_contents() {
+    let mut s = Session::new();
+    let fw = image(3000);
+    s.upload(0, &fw, 1);
+
+    for (offset, len) in [(0, 1024), (1000, 33), (2990, 10)] {
+        let resp = s.run(Command::ReadFlash {
+            addr: FW_A_ADDR + offset,
+            length: len,
+        });
+        let expected = Response::FlashData {
+            addr: FW_A_ADDR + offset,
+            data: fw[offset as usize..(offset + len) as usize].to_vec(),
+        };
+        assert_eq!(resp, expected);
+    }
+
+    // Past the image the bank reads back erased
+    let Response::FlashData { data, .. } = s.run(Command::ReadFlash {
+        addr: FW_A_ADDR + 4096,
+        length: 16,
+    }) else {
+        panic!("expected FlashData");
+    };
+    assert_eq!(data, [0xFF; 16]);
+}
+
+#[test]
+fn test_read_flash_rejected_range() {
+    let mut s = Session::new();
+    assert_eq!(
+        s.run(Command::ReadFlash {
+            addr: FW_B_ADDR,
+            length: MAX_DATA_BLOCK_SIZE as u32 + 1,
+        }),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(
+        s.run(Command::ReadFlash {
+            addr: FLASH_BASE - 16,
+            length: 16,
+        }),
+        ack(AckStatus::BadCommand)
+    );
+}
+
+// --- SetBootAttempts ---
+
+#[test]
+fn test_set_boot_attempts_keeps_banks() {
+    let mut s = Session::new();
+    s.upload(1, &image(1024), 3);
+
+    assert_eq!(
+        s.run(Command::SetBootAttempts { max_attempts: 10 }),
+        ack(AckStatus::Ok)
+    );
+    let bd = s.boot_data();
+    assert_eq!(bd.boot_attempt_limit(), 10);
+    assert_eq!(
+        (bd.active_bank, bd.version_b, bd.size_b),
+        (1, FwVersion::from_raw(3), 1024)
+    );
+
+    // WipeAll goes back to the default
+    s.run(Command::WipeAll);
+    assert_eq!(
+        s.boot_data().boot_attempt_limit(),
+        DEFAULT_MAX_BOOT_ATTEMPTS
+    );
+}
+
+#[test]
+fn test_set_boot_attempts_zero_restores_default() {
+    let mut s = Session::new();
+    s.run(Command::SetBootAttempts { max_attempts: 1 });
+
+    assert_eq!(
+        s.run(Command::SetBootAttempts { max_attempts: 0 }),
+        ack(AckStatus::Ok)
+    );
+    assert_eq!(
+        s.boot_data().boot_attempt_limit(),
+        DEFAULT_MAX_BOOT_ATTEMPTS
+    );
+}
+
+#[test]
+fn test_set_boot_attempts_out_of_range_rejected() {
+    let mut s = Session::new();
+    let max_attempts = MAX_BOOT_ATTEMPTS_RANGE.end() + 1;
+
+    assert_eq!(
+        s.run(Command::SetBootAttempts { max_attempts }),
+        ack(AckStatus::BadCommand)
+    );
+    assert!(read_boot_data(&s.flash).is_err());
+}
+
+#[test]
+fn test_set_boot_attempts_rejected_while_receiving() {
+    let mut s = Session::new();
+    let fw = image(2048);
+    s.start(0, &fw, 1);
+
+    assert_eq!(
+        s.run(Command::SetBootAttempts { max_attempts: 5 }),
+        ack(AckStatus::BadState)
+    );
+    assert!(s.state.is_receiving());
+}
+
+// --- SetVersion ---
+
+#[test]
+fn test_set_version_changes_only_the_version() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.upload(1, &image(2048), 2);
+    s.run(Command::SetActiveBank { bank: 1 });
+    let before = s.boot_data();
+
+    assert_eq!(
+        s.run(Command::SetVersion {
+            bank: 0,
+            version: FwVersion::new(1, 2, 3),
+        }),
+        ack(AckStatus::Ok)
+    );
+    let after = s.boot_data();
+    assert_eq!(after.version_a, FwVersion::new(1, 2, 3));
+    assert_eq!(
+        BootData {
+            version_a: before.version_a,
+            record_crc: before.record_crc,
+            ..after
+        },
+        before
+    );
+}
+
+#[test]
+fn test_set_version_refuses_empty_bank() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    let before = s.boot_data();
+
+    assert_eq!(
+        s.run(Command::SetVersion {
+            bank: 1,
+            version: FwVersion::new(2, 0, 0),
+        }),
+        ack(AckStatus::BankInvalid)
+    );
+    assert_eq!(s.boot_data(), before);
+}
+
+#[test]
+fn test_set_version_rejected_while_receiving() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.start(1, &image(2048), 2);
+
+    assert_eq!(
+        s.run(Command::SetVersion {
+            bank: 0,
+            version: FwVersion::new(2, 0, 0),
+        }),
+        ack(AckStatus::BadState)
+    );
+    assert!(s.state.is_receiving());
+}
+
+// --- SetBootData ---
+
+fn set_boot_data(bd: &BootData) -> Command {
+    Command::SetBootData {
+        raw: heapless::Vec::from_slice(&bd.to_bytes()).unwrap(),
+    }
+}
+
+#[test]
+fn test_set_boot_data_restores_a_record() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    s.upload(1, &image(2048), 2);
+    s.run(Command::SetActiveBank { bank: 1 });
+    s.run(Command::SetBootAttempts { max_attempts: 7 });
+    let saved = s.boot_data();
+
+    // WipeAll resets BootData but leaves the images in flash
+    s.run(Command::WipeAll);
+    assert_eq!(s.run(set_boot_data(&saved)), ack(AckStatus::Ok));
+    assert_eq!(s.boot_data(), saved);
+    let (done, total) = *s.sink.progress.last().unwrap();
+    assert_eq!((done, total), (1024 + 2048, 1024 + 2048));
+}
+
+#[test]
+fn test_set_boot_data_checks_the_banks() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    let before = s.boot_data();
+
+    let wrong_crc = BootData {
+        crc_a: before.crc_a ^ 1,
+        ..before
+    };
+    assert_eq!(s.run(set_boot_data(&wrong_crc)), ack(AckStatus::CrcError));
+    let no_image = BootData {
+        size_b: 1024,
+        crc_b: before.crc_a,
+        ..before
+    };
+    assert_eq!(s.run(set_boot_data(&no_image)), ack(AckStatus::CrcError));
+    let too_large = BootData {
+        size_a: FW_BANK_SIZE + 1,
+        ..before
+    };
+    assert_eq!(s.run(set_boot_data(&too_large)), ack(AckStatus::BadCommand));
+    assert_eq!(s.boot_data(), before);
+}
+
+#[test]
+fn test_set_boot_data_refuses_bad_records() {
+    let mut s = Session::new();
+    s.upload(0, &image(1024), 1);
+    let before = s.boot_data();
+
+    let mut corrupt = before.to_bytes();
+    corrupt[4] ^= 1;
+    let cmd = Command::SetBootData {
+        raw: heapless::Vec::from_slice(&corrupt).unwrap(),
+    };
+    assert_eq!(s.run(cmd), ack(AckStatus::BadCommand));
+    let short = Command::SetBootData {
+        raw: heapless::Vec::from_slice(&before.to_bytes()[..60]).unwrap(),
+    };
+    assert_eq!(s.run(short), ack(AckStatus::BadCommand));
+    assert_eq!(s.boot_data(), before);
+
+    s.start(1, &image(2048), 2);
+    assert_eq!(s.run(set_boot_data(&before)), ack(AckStatus::BadState));
+    assert!(s.state.is_receiving());
+}
+
+// --- BlankCheck ---
+
+#[test]
+fn test_blank_check_reports_dirty_bytes() {
+    let mut s = Session::new();
+    s.flash.poke(FW_B_ADDR + 0x20, &[0x12, 0x34]);
+
+    assert_eq!(
+        s.run(Command::BlankCheck {
+            addr: FW_B_ADDR,
+            length: FLASH_SECTOR_SIZE,
+        }),
+        Response::BlankCheckResult {
+            first_dirty: Some(0x20),
+            dirty_bytes: 2,
+        }
+    );
+}
+
+#[test]
+fn test_blank_check_reports_progress() {
+    let mut s = Session::new();
+    let length = 3 * FLASH_SECTOR_SIZE;
+
+    s.run(Command::BlankCheck {
+        addr: FW_B_ADDR,
+        length,
+    });
+    assert!(s.sink.progress.len() > 1);
+    assert!(s.sink.progress.iter().all(|&(_, total)| total == length));
+    assert!(s.sink.progress.windows(2).all(|w| w[0].0 < w[1].0));
+    assert_eq!(s.sink.progress.last(), Some(&(length, length)));
+}
+
+#[test]
+fn test_blank_check_out_of_range() {
+    let mut s = Session::new();
+    let len = s.flash.contents().len() as u32;
+
+    assert_eq!(
+        s.run(Command::BlankCheck {
+            addr: FLASH_BASE + len - 4,
+            length: 8,
+        }),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(s.flash.erase_count, 0);
+}
+
+// --- Headered images ---
+
+#[test]
+fn test_headered_image_accepted() {
+    let mut s = Session::new();
+    let firmware = ImageBuilder::new(FwVersion::from_raw(4)).wrap(&image(3000));
+
+    assert_eq!(s.upload(1, &firmware, 4), ack(AckStatus::Ok));
+    let bd = s.boot_data();
+    assert_eq!(bd.size_b, firmware.len() as u32);
+    assert_eq!(bd.crc_b, crc32::checksum(&firmware));
+}
+
+#[test]
+fn test_headered_image_with_tampered_firmware_rejected() {
+    let mut s = Session::new();
+    let mut firmware = ImageBuilder::new(FwVersion::from_raw(4)).wrap(&image(3000));
+    // The host CRC covers the tampered bytes, only the header catches them
+    firmware[10] ^= 0xFF;
+
+    assert_eq!(s.upload(0, &firmware, 4), ack(AckStatus::BadImage));
+    assert_eq!(s.state, UpdateState::Idle);
+    assert!(read_boot_data(&s.flash).is_err());
+}
+
+#[test]
+fn test_headered_image_with_corrupt_header_rejected() {
+    let mut s = Session::new();
+    let mut firmware = image(2000);
+    let mut header = ImageHeader::describe(&firmware, FwVersion::from_raw(4)).to_bytes();
+    header[20] = 0xAA;
+    firmware.extend_from_slice(&header);
+
+    assert_eq!(s.upload(0, &firmware, 4), ack(AckStatus::BadImage));
+    assert!(read_boot_data(&s.flash).is_err());
+}
+
+// --- Provisioning ---
+
+fn set_provision(board_id: u16, hw_revision: u8, serial: &[u8]) -> Command {
+    Command::SetProvision {
+        board_id,
+        hw_revision,
+        serial: heapless::Vec::from_slice(serial).unwrap(),
+    }
+}
+
+fn stored_provision(s: &mut Session) -> [u8; PROVISION_SIZE] {
+    match s.run(Command::GetProvision) {
+        Response::Provision { raw } => raw[..].try_into().unwrap(),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_provision_written_and_reported() {
+    let mut s = Session::new();
+    assert_eq!(stored_provision(&mut s), [0xFF; PROVISION_SIZE]);
+
+    assert_eq!(s.run(set_provision(2, 1, b"ABC123")), ack(AckStatus::Ok));
+    let record = Provision::new(2, 1, b"ABC123").unwrap();
+    assert_eq!(stored_provision(&mut s), record.to_bytes());
+    assert_eq!(read_provision(&s.flash), Ok(record));
+
+    // The same record again leaves flash alone; another replaces it
+    assert_eq!(s.run(set_provision(2, 1, b"ABC123")), ack(AckStatus::Ok));
+    assert_eq!(s.flash.sector_erases(PROVISION_ADDR), 1);
+    assert_eq!(s.run(set_provision(3, 1, b"ABC124")), ack(AckStatus::Ok));
+    assert_eq!(read_provision(&s.flash).unwrap().board_id, 3);
+}
+
+#[test]
+fn test_provision_survives_wipe_and_uploads() {
+    let mut s = Session::new();
+    s.run(set_provision(2, 1, b"ABC123"));
+    s.upload(0, &image(4096), 1);
+    s.upload(1, &image(4096), 2);
+    assert_eq!(s.run(Command::WipeAll), ack(AckStatus::Ok));
+
+    assert_eq!(
+        read_provision(&s.flash),
+        Ok(Provision::new(2, 1, b"ABC123").unwrap())
+    );
+}
+
+#[test]
+fn test_provision_rejects_bad_serial_and_transfers() {
+    let mut s = Session::new();
+    assert_eq!(s.run(set_provision(2, 1, b"")), ack(AckStatus::BadCommand));
+    assert_eq!(
+        s.run(set_provision(2, 1, b"A B")),
+        ack(AckStatus::BadCommand)
+    );
+
+    let firmware = image(2048);
+    s.start(0, &firmware, 1);
+    assert_eq!(
+        s.run(set_provision(2, 1, b"ABC123")),
+        ack(AckStatus::BadState)
+    );
+    assert_eq!(read_provision(&s.flash), Err(ProvisionError::Blank));
+}
+
+#[test]
+fn test_provision_write_failure_reported() {
+    let mut s = Session::new();
+    s.flash.inject(Fault::PowerLoss { op: 1, bytes: 0 });
+
+    assert_eq!(
+        s.run(set_provision(2, 1, b"ABC123")),
+        ack(AckStatus::FlashError)
+    );
+    assert_eq!(
+        s.sink.logs.last().unwrap(),
+        "Provisioning record write failed"
+    );
+}
+
+// --- Layout from the flash ---
+
+/// A 1MB part with 256KB banks.
+const SMALL: FlashLayout = FlashLayout {
+    fw_a: 0x1001_0000,
+    fw_b: 0x1005_0000,
+    bank_size: 0x4_0000,
+    boot_data: 0x1009_0000,
+    flash_size: 0x10_0000,
+    partitions: &[],
+};
+
+#[test]
+fn test_update_uses_flash_layout() {
+    assert_eq!(SMALL.check_geometry(), Ok(()));
+    let mut s = Session::with_flash(FlashSim::with_layout(SMALL));
+    let fw = image(5000);
+
+    assert_eq!(s.upload(1, &fw, 3), ack(AckStatus::Ok));
+    assert_eq!(s.bank(SMALL.fw_b, fw.len()), fw);
+
+    let mut raw = [0u8; 64];
+    s.flash.read(SMALL.boot_data, &mut raw);
+    let bd = BootData::from_bytes(&raw).unwrap();
+    assert_eq!((bd.active_bank, bd.size_b), (1, 5000));
+    assert_eq!(s.boot_data(), bd);
+}
+
+#[test]
+fn test_limits_follow_flash_layout() {
+    let mut s = Session::with_flash(FlashSim::with_layout(SMALL));
+
+    let resp = s.run(Command::StartUpdate {
+        bank: 0,
+        size: SMALL.bank_size + 1,
+        crc32: 0,
+        version: FwVersion::from_raw(1),
+        sparse: false,
+    });
+    assert_eq!(resp, ack(AckStatus::BankInvalid));
+
+    let resp = s.run(Command::BlankCheck {
+        addr: FLASH_BASE + SMALL.flash_size,
+        length: 4,
+    });
+    assert_eq!(resp, ack(AckStatus::BadCommand));
+    assert_eq!(s.flash.erase_count, 0);
+}
+
+#[test]
+fn test_set_boot_data_follows_flash_layout() {
+    let mut s = Session::with_flash(FlashSim::with_layout(SMALL));
+    s.upload(0, &image(1024), 1);
+    let before = s.boot_data();
+
+    // Fits a default bank, but not a SMALL one
+    let too_large = BootData {
+        size_b: SMALL.bank_size + 1,
+        ..before
+    };
+    assert_eq!(s.run(set_boot_data(&too_large)), ack(AckStatus::BadCommand));
+    assert_eq!(s.run(set_boot_data(&before)), ack(AckStatus::Ok));
+    assert_eq!(s.boot_data(), before);
+}
+
+#[test]
+fn test_bench_data_is_dropped() {
+    let mut s = Session::new();
+    let before = s.flash.contents().to_vec();
+    let bench = |len: usize| Command::BenchData {
+        data: vec![0x5A; len],
+    };
+    assert_eq!(s.run(bench(MAX_DATA_BLOCK_SIZE)), ack(AckStatus::Ok));
+    assert_eq!(s.run(bench(0)), ack(AckStatus::Ok));
+    assert_eq!(
+        s.run(bench(MAX_DATA_BLOCK_SIZE + 1)),
+        ack(AckStatus::BadCommand)
+    );
+    assert_eq!(s.flash.contents(), &before[..]);
+
+    // In the middle of a transfer too, which carries on
+    let fw = image(2048);
+    assert_eq!(s.start(0, &fw, 1), ack(AckStatus::Ok));
+    assert_eq!(s.run(data_block(0, &fw[..1024])), ack(AckStatus::Ok));
+    assert_eq!(s.run(bench(16)), ack(AckStatus::Ok));
+    assert_eq!(s.run(data_block(1024, &fw[1024..])), ack(AckStatus::Ok));
+    assert_eq!(s.run(Command::FinishUpdate), ack(AckStatus::Ok));
+    assert_eq!(s.boot_data().crc_a, crc32::checksum(&fw));
+}
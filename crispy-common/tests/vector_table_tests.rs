@@ -0,0 +1,75 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for the vector table check shared by the bootloader and host.
+
+use crispy_common::vector_table::{RamWindow, VectorTable, VectorTableError, FW_RAM_WINDOW};
+
+const RAM: RamWindow = RamWindow {
+    start: 0x2000_0000,
+    end: 0x2003_BFFF,
+};
+
+fn table(initial_sp: u32, reset_vector: u32) -> VectorTable {
+    VectorTable {
+        initial_sp,
+        reset_vector,
+    }
+}
+
+// --- Decoding ---
+
+#[test]
+fn test_from_bytes_is_little_endian() {
+    let bytes = [0x00, 0xB0, 0x03, 0x20, 0xC1, 0x00, 0x00, 0x20];
+    assert_eq!(
+        VectorTable::from_bytes(&bytes),
+        table(0x2003_B000, 0x2000_00C1)
+    );
+}
+
+#[test]
+fn test_read_uses_first_two_words() {
+    let vt = VectorTable::read(|addr| addr * 2, 0x1001_0000);
+    assert_eq!(vt, table(0x2002_0000, 0x2002_0008));
+}
+
+// --- Checks ---
+
+#[test]
+fn test_ram_image_passes() {
+    assert_eq!(table(0x2003_B000, 0x2000_00C1).check(RAM), Ok(()));
+    // Window bounds are inclusive
+    assert_eq!(table(RAM.end, RAM.start + 1).check(RAM), Ok(()));
+}
+
+#[test]
+fn test_sp_outside_ram() {
+    assert_eq!(
+        table(0x2004_0000, 0x2000_00C1).check(RAM),
+        Err(VectorTableError::SpOutsideRam(0x2004_0000))
+    );
+}
+
+#[test]
+fn test_xip_linked_reset_vector() {
+    assert_eq!(
+        table(0x2003_B000, 0x1001_00C1).check(RAM),
+        Err(VectorTableError::ResetOutsideRam(0x1001_00C1))
+    );
+}
+
+#[test]
+fn test_reset_vector_needs_thumb_bit() {
+    assert_eq!(
+        table(0x2003_B000, 0x2000_00C0).check(RAM),
+        Err(VectorTableError::ResetNotThumb(0x2000_00C0))
+    );
+}
+
+#[test]
+fn test_erased_flash_fails() {
+    assert!(table(0xFFFF_FFFF, 0xFFFF_FFFF)
+        .check(FW_RAM_WINDOW)
+        .is_err());
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+use crispy_common::version::{
+    embedded_version, FwVersion, ParseVersionError, VersionBlock, VERSION_BLOCK_OFFSET,
+};
+
+// --- Packing ---
+
+#[test]
+fn test_packing() {
+    let v = FwVersion::new(1, 2, 3);
+    assert_eq!(v.raw(), 0x0102_0003);
+    assert_eq!((v.major(), v.minor(), v.patch()), (1, 2, 3));
+    assert_eq!(FwVersion::from_raw(0x0102_0003), v);
+    assert_eq!(u32::from(v), 0x0102_0003);
+}
+
+#[test]
+fn test_max_components_round_trip() {
+    let v = FwVersion::new(255, 255, 65535);
+    assert_eq!(v.raw(), u32::MAX);
+    assert_eq!(v.to_string(), "255.255.65535");
+    assert_eq!("255.255.65535".parse(), Ok(v));
+}
+
+#[test]
+fn test_legacy_integer_reads_as_patch() {
+    assert_eq!(FwVersion::from_raw(7).to_string(), "0.0.7");
+    assert_eq!(FwVersion::default().to_string(), "0.0.0");
+}
+
+// --- Parsing ---
+
+#[test]
+fn test_parse_dotted() {
+    assert_eq!("1.2.3".parse(), Ok(FwVersion::new(1, 2, 3)));
+    assert_eq!("0.0.0".parse(), Ok(FwVersion::default()));
+    assert_eq!("10.20.300".parse(), Ok(FwVersion::new(10, 20, 300)));
+}
+
+#[test]
+fn test_parse_plain_integer_is_raw() {
+    assert_eq!("1".parse(), Ok(FwVersion::from_raw(1)));
+    assert_eq!("10203".parse(), Ok(FwVersion::from_raw(10203)));
+    assert_eq!("4294967295".parse(), Ok(FwVersion::from_raw(u32::MAX)));
+}
+
+#[test]
+fn test_parse_rejects_out_of_range_components() {
+    for s in ["256.0.0", "0.256.0", "0.0.65536", "4294967296"] {
+        assert_eq!(s.parse::<FwVersion>(), Err(ParseVersionError), "{s}");
+    }
+}
+
+#[test]
+fn test_parse_rejects_malformed() {
+    for s in [
+        "", "1.2", "1.2.3.4", "1..3", "a.b.c", "-1.0.0", "1.2.3 ", "v1.2.3",
+    ] {
+        assert_eq!(s.parse::<FwVersion>(), Err(ParseVersionError), "{s:?}");
+    }
+}
+
+#[test]
+fn test_from_semver() {
+    const V: FwVersion = FwVersion::from_semver("1.2.3");
+    assert_eq!(V, FwVersion::new(1, 2, 3));
+    assert_eq!(
+        FwVersion::from_semver("255.255.65535"),
+        FwVersion::new(255, 255, 65535)
+    );
+}
+
+#[test]
+fn test_from_semver_rejects_what_parse_rejects() {
+    // A plain integer is a raw value to `parse`, never a crate version
+    for s in [
+        "",
+        "1",
+        "1.2",
+        "1.2.3.4",
+        "1..3",
+        "256.0.0",
+        "0.0.65536",
+        "1.0.0-rc.1",
+    ] {
+        assert!(
+            std::panic::catch_unwind(|| FwVersion::from_semver(s)).is_err(),
+            "{s:?}"
+        );
+    }
+}
+
+// --- Version block ---
+
+#[test]
+fn test_version_block_bytes() {
+    let block = VersionBlock::new(FwVersion::new(1, 2, 3));
+    let raw = block.to_bytes();
+    assert_eq!(raw, [b'C', b'R', b'F', b'V', 0x03, 0x00, 0x02, 0x01]);
+    assert_eq!(VersionBlock::from_bytes(&raw), Some(block));
+    assert_eq!(VersionBlock::from_bytes(&[0xFF; 8]), None);
+    assert_eq!(
+        core::mem::size_of::<VersionBlock>(),
+        crispy_common::version::VERSION_BLOCK_SIZE
+    );
+}
+
+#[test]
+fn test_embedded_version() {
+    let mut image = vec![0u8; 1024];
+    assert_eq!(embedded_version(&image), None);
+
+    let raw = VersionBlock::new(FwVersion::new(0, 2, 0)).to_bytes();
+    image[VERSION_BLOCK_OFFSET..VERSION_BLOCK_OFFSET + 8].copy_from_slice(&raw);
+    assert_eq!(embedded_version(&image), Some(FwVersion::new(0, 2, 0)));
+
+    // Too short to hold one
+    assert_eq!(embedded_version(&image[..VERSION_BLOCK_OFFSET + 4]), None);
+}
+
+// --- Ordering ---
+
+#[test]
+fn test_ordering_by_component() {
+    let ordered = [
+        FwVersion::new(0, 0, 1),
+        FwVersion::new(0, 0, 65535),
+        FwVersion::new(0, 1, 0),
+        FwVersion::new(0, 255, 65535),
+        FwVersion::new(1, 0, 0),
+        FwVersion::new(1, 2, 3),
+        FwVersion::new(1, 10, 0),
+        FwVersion::new(255, 255, 65535),
+    ];
+    for pair in ordered.windows(2) {
+        assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+        assert!(pair[0].raw() < pair[1].raw());
+    }
+}
+
+#[test]
+fn test_wire_encoding_is_packed_u32() {
+    let mut buf = [0u8; 8];
+    let v = FwVersion::new(1, 2, 3);
+    assert_eq!(
+        postcard::to_slice(&v, &mut buf).unwrap(),
+        postcard::to_slice(&v.raw(), &mut [0u8; 8]).unwrap()
+    );
+    assert_eq!(
+        postcard::from_bytes::<FwVersion>(&[0x83, 0x80, 0x88, 0x08]),
+        Ok(v)
+    );
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests for the WebUSB / MS OS 2.0 descriptors.
+
+use crispy_common::webusb::{
+    ms_os_20_descriptor_set, DEVICE_INTERFACE_GUID, MS_OS_20_PLATFORM_CAPABILITY, MS_OS_20_SET_LEN,
+    MS_OS_20_VENDOR_CODE, WEBUSB_PLATFORM_CAPABILITY, WEBUSB_VENDOR_CODE,
+};
+
+fn u16_at(buf: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([buf[at], buf[at + 1]])
+}
+
+// --- Platform capability tests ---
+
+#[test]
+fn test_webusb_capability() {
+    let cap = WEBUSB_PLATFORM_CAPABILITY;
+    assert_eq!(cap[0], 0);
+    assert_eq!(&cap[1..5], &[0x38, 0xB6, 0x08, 0x34]);
+    assert_eq!(u16_at(&cap, 17), 0x0100);
+    assert_eq!(cap[19], WEBUSB_VENDOR_CODE);
+    assert_eq!(cap[20], 0);
+}
+
+#[test]
+fn test_ms_os_20_capability_matches_set() {
+    let cap = MS_OS_20_PLATFORM_CAPABILITY;
+    assert_eq!(&cap[1..5], &[0xDF, 0x60, 0xDD, 0xD8]);
+    assert_eq!(&cap[17..21], &[0x00, 0x00, 0x03, 0x06]);
+    assert_eq!(u16_at(&cap, 21) as usize, MS_OS_20_SET_LEN);
+    assert_eq!(cap[23], MS_OS_20_VENDOR_CODE);
+}
+
+// --- Descriptor set tests ---
+
+#[test]
+fn test_descriptor_set_headers() {
+    let set = ms_os_20_descriptor_set(4);
+    assert_eq!(MS_OS_20_SET_LEN, 178);
+
+    // Set header: length, type, total length
+    assert_eq!(u16_at(&set, 0), 10);
+    assert_eq!(u16_at(&set, 2), 0x00);
+    assert_eq!(u16_at(&set, 8) as usize, MS_OS_20_SET_LEN);
+
+    // Configuration subset covers everything after the set header
+    assert_eq!(u16_at(&set, 12), 0x01);
+    assert_eq!(u16_at(&set, 16) as usize, MS_OS_20_SET_LEN - 10);
+
+    // Function subset points at the vendor interface
+    assert_eq!(u16_at(&set, 20), 0x02);
+    assert_eq!(set[22], 4);
+    assert_eq!(u16_at(&set, 24) as usize, MS_OS_20_SET_LEN - 18);
+}
+
+#[test]
+fn test_descriptor_set_winusb_and_guid() {
+    let set = ms_os_20_descriptor_set(2);
+    assert_eq!(set[22], 2);
+
+    // Compatible ID
+    assert_eq!(u16_at(&set, 26), 20);
+    assert_eq!(u16_at(&set, 28), 0x03);
+    assert_eq!(&set[30..38], b"WINUSB\0\0");
+
+    // Registry property, REG_MULTI_SZ
+    let prop = 46;
+    assert_eq!(u16_at(&set, prop) as usize, MS_OS_20_SET_LEN - prop);
+    assert_eq!(u16_at(&set, prop + 2), 0x04);
+    assert_eq!(u16_at(&set, prop + 4), 0x07);
+    let name_len = u16_at(&set, prop + 6) as usize;
+    let name: Vec<u16> = set[prop + 8..prop + 8 + name_len]
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    assert_eq!(String::from_utf16(&name).unwrap(), "DeviceInterfaceGUIDs\0");
+
+    let data = prop + 8 + name_len;
+    let data_len = u16_at(&set, data) as usize;
+    assert_eq!(data + 2 + data_len, MS_OS_20_SET_LEN);
+    let guid: Vec<u16> = set[data + 2..]
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    assert_eq!(
+        String::from_utf16(&guid).unwrap(),
+        format!("{}\0\0", DEVICE_INTERFACE_GUID)
+    );
+}
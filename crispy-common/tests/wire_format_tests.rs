@@ -0,0 +1,430 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Golden wire-format vectors for `Command` and `Response`.
+//!
+//! Every variant is pinned to its postcard bytes and its COBS frame. The
+//! suite builds with and without the `std` feature, so the bootloader's
+//! heapless `DataBlock` and the host's `Vec` one are held to the same bytes:
+//!
+//! ```text
+//! cargo test -p crispy-common --features std --test wire_format_tests
+//! cargo test -p crispy-common --test wire_format_tests
+//! ```
+//!
+//! # Compatibility policy
+//!
+//! The wire format carries no version number; a bootloader and a host tool
+//! interoperate only if they agree on these bytes. So:
+//!
+//! - Compatible: adding a variant at the end of `Command`, `Response` or
+//!   `AckStatus`. Old peers drop the frame they cannot decode, and the host
+//!   reports a timeout rather than misreading it.
+//! - Breaking: reordering, removing or renaming-with-serde-attributes a
+//!   variant, adding, removing, reordering or retyping a field of an existing
+//!   variant, or changing `MAX_DATA_BLOCK_SIZE`.
+//!
+//! A breaking change bumps the crispy-common minor version, ships the
+//! bootloader and `crispy-upload` together, and is the only reason to edit
+//! a vector below. A failing vector on any other change is a bug.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crispy_common::flash::BankVerify;
+use crispy_common::protocol::{
+    flash_data_prefix, AckStatus, BootState, Command, Response, MAX_DATA_BLOCK_SIZE, MAX_READ_SIZE,
+};
+use crispy_common::version::FwVersion;
+
+/// Check `value` against its golden bytes in both directions.
+fn check<T: Serialize + DeserializeOwned>(value: &T, bytes: &[u8], frame: &[u8]) {
+    let mut buf = [0u8; MAX_DATA_BLOCK_SIZE + 64];
+    assert_eq!(postcard::to_slice(value, &mut buf).unwrap(), bytes);
+    assert_eq!(postcard::to_slice_cobs(value, &mut buf).unwrap(), frame);
+
+    // Decoding the golden bytes and encoding again must be lossless
+    let decoded: T = postcard::from_bytes(bytes).unwrap();
+    assert_eq!(postcard::to_slice(&decoded, &mut buf).unwrap(), bytes);
+    let decoded: T = postcard::from_bytes_cobs(&mut frame.to_vec()).unwrap();
+    assert_eq!(postcard::to_slice(&decoded, &mut buf).unwrap(), bytes);
+}
+
+#[cfg(feature = "std")]
+fn data_block(offset: u32, data: &[u8]) -> Command {
+    Command::DataBlock {
+        offset,
+        data: data.to_vec(),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn data_block(offset: u32, data: &[u8]) -> Command {
+    Command::DataBlock {
+        offset,
+        data: heapless::Vec::from_slice(data).unwrap(),
+    }
+}
+
+#[cfg(feature = "std")]
+fn bench_data(data: &[u8]) -> Command {
+    Command::BenchData {
+        data: data.to_vec(),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn bench_data(data: &[u8]) -> Command {
+    Command::BenchData {
+        data: heapless::Vec::from_slice(data).unwrap(),
+    }
+}
+
+#[cfg(feature = "std")]
+fn flash_data(addr: u32, data: &[u8]) -> Response {
+    Response::FlashData {
+        addr,
+        data: data.to_vec(),
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn flash_data(addr: u32, data: &[u8]) -> Response {
+    Response::FlashData {
+        addr,
+        data: heapless::Vec::from_slice(data).unwrap(),
+    }
+}
+
+// --- Commands ---
+
+#[test]
+fn test_command_vectors() {
+    let vectors: [(Command, &[u8], &[u8]); 19] = [
+        (Command::GetStatus, &[0x00], &[0x01, 0x01, 0x00]),
+        (
+            Command::StartUpdate {
+                bank: 1,
+                size: 0x0001_2345,
+                crc32: 0xDEAD_BEEF,
+                version: FwVersion::from_raw(7),
+                sparse: true,
+            },
+            &[
+                0x01, 0x01, 0xc5, 0xc6, 0x04, 0xef, 0xfd, 0xb6, 0xf5, 0x0d, 0x07, 0x01,
+            ],
+            &[
+                0x0d, 0x01, 0x01, 0xc5, 0xc6, 0x04, 0xef, 0xfd, 0xb6, 0xf5, 0x0d, 0x07, 0x01, 0x00,
+            ],
+        ),
+        (
+            data_block(0x400, &[0xDE, 0xAD, 0x00, 0x01]),
+            &[0x02, 0x80, 0x08, 0x04, 0xde, 0xad, 0x00, 0x01],
+            &[0x07, 0x02, 0x80, 0x08, 0x04, 0xde, 0xad, 0x02, 0x01, 0x00],
+        ),
+        (Command::FinishUpdate, &[0x03], &[0x02, 0x03, 0x00]),
+        (Command::Reboot, &[0x04], &[0x02, 0x04, 0x00]),
+        (
+            Command::SetActiveBank { bank: 1 },
+            &[0x05, 0x01],
+            &[0x03, 0x05, 0x01, 0x00],
+        ),
+        (Command::WipeAll, &[0x06], &[0x02, 0x06, 0x00]),
+        (Command::GetFlashInfo, &[0x07], &[0x02, 0x07, 0x00]),
+        (
+            Command::BlankCheck {
+                addr: 0x100D_0000,
+                length: 0x1000,
+            },
+            &[0x08, 0x80, 0x80, 0xb4, 0x80, 0x01, 0x80, 0x20],
+            &[0x09, 0x08, 0x80, 0x80, 0xb4, 0x80, 0x01, 0x80, 0x20, 0x00],
+        ),
+        (
+            Command::SetBootAttempts { max_attempts: 5 },
+            &[0x09, 0x05],
+            &[0x03, 0x09, 0x05, 0x00],
+        ),
+        (
+            Command::CopyBank { from: 0, to: 1 },
+            &[0x0a, 0x00, 0x01],
+            &[0x02, 0x0a, 0x02, 0x01, 0x00],
+        ),
+        (Command::GetBootData, &[0x0b], &[0x02, 0x0b, 0x00]),
+        (Command::GetDeviceInfo, &[0x0c], &[0x02, 0x0c, 0x00]),
+        (
+            Command::ReadFlash {
+                addr: 0x1001_0000,
+                length: 256,
+            },
+            &[0x0d, 0x80, 0x80, 0x84, 0x80, 0x01, 0x80, 0x02],
+            &[0x09, 0x0d, 0x80, 0x80, 0x84, 0x80, 0x01, 0x80, 0x02, 0x00],
+        ),
+        (
+            Command::SetVersion {
+                bank: 1,
+                version: FwVersion::from_raw(7),
+            },
+            &[0x0e, 0x01, 0x07],
+            &[0x04, 0x0e, 0x01, 0x07, 0x00],
+        ),
+        (
+            bench_data(&[0xAA, 0x00]),
+            &[0x0f, 0x02, 0xaa, 0x00],
+            &[0x04, 0x0f, 0x02, 0xaa, 0x01, 0x00],
+        ),
+        (
+            Command::SetBootData {
+                raw: heapless::Vec::from_slice(&[0x7a, 0xda, 0x07, 0xb0]).unwrap(),
+            },
+            &[0x10, 0x04, 0x7a, 0xda, 0x07, 0xb0],
+            &[0x07, 0x10, 0x04, 0x7a, 0xda, 0x07, 0xb0, 0x00],
+        ),
+        (Command::GetProvision, &[0x11], &[0x02, 0x11, 0x00]),
+        (
+            Command::SetProvision {
+                board_id: 2,
+                hw_revision: 1,
+                serial: heapless::Vec::from_slice(b"AB").unwrap(),
+            },
+            &[0x12, 0x02, 0x01, 0x02, 0x41, 0x42],
+            &[0x07, 0x12, 0x02, 0x01, 0x02, 0x41, 0x42, 0x00],
+        ),
+    ];
+
+    for (cmd, bytes, frame) in &vectors {
+        check(cmd, bytes, frame);
+    }
+}
+
+#[test]
+fn test_full_data_block_layout() {
+    let data: Vec<u8> = (0..MAX_DATA_BLOCK_SIZE).map(|i| i as u8).collect();
+
+    // Tag 2, offset 0x1_0000, length 1024, then the bytes as they are
+    let mut bytes = vec![0x02, 0x80, 0x80, 0x04, 0x80, 0x08];
+    bytes.extend_from_slice(&data);
+
+    let mut buf = [0u8; MAX_DATA_BLOCK_SIZE + 16];
+    let cmd = data_block(0x1_0000, &data);
+    assert_eq!(postcard::to_slice(&cmd, &mut buf).unwrap(), &bytes[..]);
+
+    match postcard::from_bytes::<Command>(&bytes).unwrap() {
+        Command::DataBlock { offset, data: got } => {
+            assert_eq!(offset, 0x1_0000);
+            assert_eq!(&got[..], &data[..]);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+// The bootloader's heapless block refuses to grow past its capacity
+#[cfg(not(feature = "std"))]
+#[test]
+fn test_oversize_data_block_does_not_decode() {
+    let mut bytes = vec![0x02, 0x00, 0x81, 0x08];
+    bytes.extend(core::iter::repeat_n(0xAA, MAX_DATA_BLOCK_SIZE + 1));
+    assert!(postcard::from_bytes::<Command>(&bytes).is_err());
+}
+
+// serde only implements `Vec` support with `std`; without it the golden
+// vectors above are what ties the heapless encoding to the alloc one
+#[cfg(feature = "std")]
+#[test]
+fn test_data_block_heapless_and_alloc_encode_identically() {
+    let data: Vec<u8> = (0..MAX_DATA_BLOCK_SIZE).map(|i| (i * 7) as u8).collect();
+    let fixed: heapless::Vec<u8, MAX_DATA_BLOCK_SIZE> = heapless::Vec::from_slice(&data).unwrap();
+
+    let mut alloc_buf = [0u8; MAX_DATA_BLOCK_SIZE + 16];
+    let mut heapless_buf = [0u8; MAX_DATA_BLOCK_SIZE + 16];
+    assert_eq!(
+        postcard::to_slice(&(0x400u32, &data), &mut alloc_buf).unwrap(),
+        postcard::to_slice(&(0x400u32, &fixed), &mut heapless_buf).unwrap()
+    );
+
+    // And either side decodes what the other wrote
+    let bytes = postcard::to_slice(&(0x400u32, &data), &mut alloc_buf).unwrap();
+    let (_, back): (u32, heapless::Vec<u8, MAX_DATA_BLOCK_SIZE>) =
+        postcard::from_bytes(bytes).unwrap();
+    assert_eq!(&back[..], &data[..]);
+    let bytes = postcard::to_slice(&(0x400u32, &fixed), &mut heapless_buf).unwrap();
+    let (_, back): (u32, Vec<u8>) = postcard::from_bytes(bytes).unwrap();
+    assert_eq!(back, data);
+}
+
+// --- Responses ---
+
+#[test]
+fn test_ack_vectors() {
+    let statuses = [
+        AckStatus::Ok,
+        AckStatus::CrcError,
+        AckStatus::FlashError,
+        AckStatus::BadCommand,
+        AckStatus::BadState,
+        AckStatus::BankInvalid,
+        AckStatus::LayoutMismatch,
+        AckStatus::BadImage,
+    ];
+
+    for (tag, status) in statuses.into_iter().enumerate() {
+        let tag = tag as u8;
+        let frame: &[u8] = if tag == 0 {
+            &[0x01, 0x01, 0x01, 0x00]
+        } else {
+            &[0x01, 0x02, tag, 0x00]
+        };
+        check(&Response::Ack(status), &[0x00, tag], frame);
+    }
+}
+
+#[test]
+fn test_response_vectors() {
+    let vectors: [(Response, &[u8], &[u8]); 10] = [
+        (
+            Response::Status {
+                active_bank: 1,
+                version_a: FwVersion::from_raw(3),
+                version_b: FwVersion::from_raw(300),
+                state: BootState::Receiving,
+                host_connected: true,
+                tx_stalled: false,
+                bootdata_reconstructed: true,
+                update_interrupted: false,
+            },
+            &[0x01, 0x01, 0x03, 0xac, 0x02, 0x02, 0x01, 0x00, 0x01, 0x00],
+            &[
+                0x08, 0x01, 0x01, 0x03, 0xac, 0x02, 0x02, 0x01, 0x02, 0x01, 0x01, 0x00,
+            ],
+        ),
+        (
+            Response::Nack {
+                status: AckStatus::FlashError,
+                offset: 0x1_0100,
+            },
+            &[0x02, 0x02, 0x80, 0x82, 0x04],
+            &[0x06, 0x02, 0x02, 0x80, 0x82, 0x04, 0x00],
+        ),
+        (
+            Response::FlashInfo {
+                jedec_id: 0x00EF_4015,
+                detected_size: 0x20_0000,
+                layout_size: 0x20_0000,
+                bank_size: 0xC_0000,
+                fw_a_addr: 0x1001_0000,
+                fw_b_addr: 0x100D_0000,
+                boot_data_addr: 0x1019_0000,
+            },
+            &[
+                0x03, 0x95, 0x80, 0xbd, 0x07, 0x80, 0x80, 0x80, 0x01, 0x80, 0x80, 0x80, 0x01, 0x80,
+                0x80, 0x30, 0x80, 0x80, 0x84, 0x80, 0x01, 0x80, 0x80, 0xb4, 0x80, 0x01, 0x80, 0x80,
+                0xe4, 0x80, 0x01,
+            ],
+            &[
+                0x20, 0x03, 0x95, 0x80, 0xbd, 0x07, 0x80, 0x80, 0x80, 0x01, 0x80, 0x80, 0x80, 0x01,
+                0x80, 0x80, 0x30, 0x80, 0x80, 0x84, 0x80, 0x01, 0x80, 0x80, 0xb4, 0x80, 0x01, 0x80,
+                0x80, 0xe4, 0x80, 0x01, 0x00,
+            ],
+        ),
+        (
+            Response::BlankCheckResult {
+                first_dirty: Some(0x80),
+                dirty_bytes: 2,
+            },
+            &[0x04, 0x01, 0x80, 0x01, 0x02],
+            &[0x06, 0x04, 0x01, 0x80, 0x01, 0x02, 0x00],
+        ),
+        (
+            Response::BlankCheckResult {
+                first_dirty: None,
+                dirty_bytes: 0,
+            },
+            &[0x04, 0x00, 0x00],
+            &[0x02, 0x04, 0x01, 0x01, 0x00],
+        ),
+        (
+            Response::Progress {
+                done: 0x8000,
+                total: 0x1_0000,
+            },
+            &[0x05, 0x80, 0x80, 0x02, 0x80, 0x80, 0x04],
+            &[0x08, 0x05, 0x80, 0x80, 0x02, 0x80, 0x80, 0x04, 0x00],
+        ),
+        (
+            Response::BootData {
+                raw: heapless::Vec::from_slice(&[0x7a, 0xda, 0x07, 0xb0]).unwrap(),
+                banks: [
+                    BankVerify::Ok {
+                        size: 0x100,
+                        crc: 1,
+                    },
+                    BankVerify::CrcMismatch {
+                        expected: 2,
+                        computed: 3,
+                    },
+                ],
+            },
+            &[
+                0x06, 0x04, 0x7a, 0xda, 0x07, 0xb0, 0x00, 0x80, 0x02, 0x01, 0x04, 0x02, 0x03,
+            ],
+            &[
+                0x07, 0x06, 0x04, 0x7a, 0xda, 0x07, 0xb0, 0x07, 0x80, 0x02, 0x01, 0x04, 0x02, 0x03,
+                0x00,
+            ],
+        ),
+        (
+            Response::DeviceInfo {
+                bootloader_version: FwVersion::new(0, 2, 0),
+                chip_id: 0x2000_2927,
+                flash_uid: 0xE661_4103_E745_2D2F,
+                last_error: Some(AckStatus::CrcError),
+            },
+            &[
+                0x07, 0x80, 0x80, 0x08, 0xa7, 0xd2, 0x80, 0x80, 0x02, 0xaf, 0xda, 0x94, 0xba, 0xbe,
+                0xa0, 0xd0, 0xb0, 0xe6, 0x01, 0x01, 0x01,
+            ],
+            &[
+                0x16, 0x07, 0x80, 0x80, 0x08, 0xa7, 0xd2, 0x80, 0x80, 0x02, 0xaf, 0xda, 0x94, 0xba,
+                0xbe, 0xa0, 0xd0, 0xb0, 0xe6, 0x01, 0x01, 0x01, 0x00,
+            ],
+        ),
+        (
+            flash_data(0x1001_0000, &[0x7a, 0x00, 0xff]),
+            &[0x08, 0x80, 0x80, 0x84, 0x80, 0x01, 0x03, 0x7a, 0x00, 0xff],
+            &[
+                0x09, 0x08, 0x80, 0x80, 0x84, 0x80, 0x01, 0x03, 0x7a, 0x02, 0xff, 0x00,
+            ],
+        ),
+        (
+            Response::Provision {
+                raw: heapless::Vec::from_slice(&[0x50, 0x52, 0x00, 0xff]).unwrap(),
+            },
+            &[0x09, 0x04, 0x50, 0x52, 0x00, 0xff],
+            &[0x05, 0x09, 0x04, 0x50, 0x52, 0x02, 0xff, 0x00],
+        ),
+    ];
+
+    for (resp, bytes, frame) in &vectors {
+        check(resp, bytes, frame);
+        assert_eq!(&postcard::from_bytes::<Response>(bytes).unwrap(), resp);
+    }
+}
+
+#[test]
+fn test_flash_data_prefix_matches_encoding() {
+    // The bootloader streams the prefix and then the flash bytes
+    let data: Vec<u8> = (0..MAX_READ_SIZE).map(|i| i as u8).collect();
+    for len in [1, 127, 128, MAX_READ_SIZE] {
+        let mut buf = [0u8; MAX_READ_SIZE + 16];
+        let encoded = postcard::to_slice(&flash_data(0x1019_0000, &data[..len]), &mut buf).unwrap();
+        let mut streamed = flash_data_prefix(0x1019_0000, len as u32).to_vec();
+        streamed.extend_from_slice(&data[..len]);
+        assert_eq!(encoded, &streamed[..]);
+    }
+}
+
+#[test]
+fn test_boot_state_vectors() {
+    check(&BootState::Idle, &[0x00], &[0x01, 0x01, 0x00]);
+    check(&BootState::UpdateMode, &[0x01], &[0x02, 0x01, 0x00]);
+    check(&BootState::Receiving, &[0x02], &[0x02, 0x02, 0x00]);
+}
@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Unit tests comparing word-wise XIP reads against byte-wise reads.
+
+use crispy_common::xip;
+
+/// Deterministic pseudo-random bytes (xorshift) so failures are reproducible.
+fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// The previous implementation: one volatile byte load per byte.
+fn read_bytewise(src: &[u8]) -> Vec<u8> {
+    (0..src.len())
+        .map(|i| unsafe { src.as_ptr().add(i).read_volatile() })
+        .collect()
+}
+
+fn read_wordwise(src: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; src.len()];
+    unsafe { xip::read_volatile(src.as_ptr(), &mut out) };
+    out
+}
+
+// --- Alignment tests ---
+
+#[test]
+fn test_read_every_alignment_and_short_length() {
+    let data = pseudo_random(64, 1);
+    for start in 0..8 {
+        for len in 0..=(64 - start).min(13) {
+            let src = &data[start..start + len];
+            assert_eq!(
+                read_wordwise(src),
+                read_bytewise(src),
+                "start {} len {}",
+                start,
+                len
+            );
+        }
+    }
+}
+
+#[test]
+fn test_read_into_unaligned_destination() {
+    let data = pseudo_random(300, 2);
+    let mut out = vec![0u8; 301];
+    unsafe { xip::read_volatile(data.as_ptr().wrapping_add(1), &mut out[1..300]) };
+    assert_eq!(&out[1..300], &data[1..300]);
+    assert_eq!(out[0], 0);
+    assert_eq!(out[300], 0);
+}
+
+// --- Randomized comparison ---
+
+#[test]
+fn test_read_matches_bytewise_random_buffers() {
+    for seed in 1..=50u32 {
+        let data = pseudo_random(8192, seed);
+        let start = (seed as usize * 7) % 64;
+        let len = (seed as usize * 131) % (8192 - start);
+        let src = &data[start..start + len];
+        assert_eq!(read_wordwise(src), read_bytewise(src), "seed {}", seed);
+    }
+}
@@ -1,14 +1,27 @@
 // Copyright (c) 2026 ADNT Sarl <info@adnt.io>
 // SPDX-License-Identifier: MIT
 
+//! Sample application firmware: a USB serial console for boot status,
+//! confirmation and self-update, and a boot confirmed once health checks
+//! pass.
+//!
+//! The checks always include a readable BootData and [`app_health`]. Build
+//! with the `usb-health` feature to also require USB to stay configured by a
+//! host for [`HEALTH_USB_UP_US`]; it is off by default so units that boot
+//! without a host attached still confirm.
+
 #![no_std]
 #![no_main]
 
-use crispy_common::flash;
-use crispy_common::protocol::BootData;
+use core::fmt::Write;
+use crispy_common::flash::{BankWriter, ConfirmOutcome, FlashError};
+use crispy_common::flash_ops::RomFlash;
+use crispy_common::health::{self, Health, HealthOutcome};
+use crispy_common::protocol::{BootData, FIRMWARE_PID, USB_VID};
+use crispy_common::self_update::{self, Staged, UpdateError};
+use crispy_common::{clocks, flash};
+use crispy_common::{LedPattern, PatternPlayer};
 use defmt_rtt as _;
-use embedded_hal::digital::OutputPin;
-use embedded_hal::digital::StatefulOutputPin;
 use panic_probe as _;
 use rp2040_hal as hal;
 use rp2040_hal::usb::UsbBus;
@@ -20,6 +33,8 @@ defmt::timestamp!("{=u64:us}", { 0 });
 
 use cortex_m_rt::entry;
 
+mod watchdog;
+
 /// Static storage for UsbBusAllocator (required by usb-device for 'static lifetime).
 static mut USB_BUS: Option<UsbBusAllocator<UsbBus>> = None;
 
@@ -29,6 +44,72 @@ fn usb_bus_ref() -> &'static UsbBusAllocator<UsbBus> {
 
 const FW_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+// Lets crispy-upload record this version without --version
+crispy_common::fw_version!(FW_VERSION);
+
+/// Confirm the boot at startup once the health checks pass, unless built
+/// with `manual-confirm`, where the `confirm` command does it. Either way
+/// the bootloader rolls back after enough boots without one.
+const AUTO_CONFIRM: bool = !cfg!(feature = "manual-confirm");
+
+/// Time a new image has to pass its health checks before it stops feeding
+/// the watchdog.
+const HEALTH_TIMEOUT_US: u64 = 10_000_000;
+
+/// How long USB must stay configured by a host to count as healthy, with
+/// the `usb-health` feature; `None` drops the check.
+const HEALTH_USB_UP_US: Option<u64> = if cfg!(feature = "usb-health") {
+    Some(2_000_000)
+} else {
+    None
+};
+
+/// Silence after which an `update` transfer is abandoned.
+const UPDATE_IDLE_TIMEOUT_US: u64 = 3_000_000;
+
+/// What the main loop does after a command.
+enum Action {
+    None,
+    /// Reboot to bootloader update mode.
+    Bootload,
+    /// The boot is confirmed: back to the heartbeat.
+    Confirmed,
+    /// Receive `size` bytes with CRC32 `crc` into the inactive bank.
+    Update {
+        size: u32,
+        crc: u32,
+    },
+}
+
+/// Formats into a fixed buffer, dropping what does not fit.
+struct BufWriter<'b> {
+    buf: &'b mut [u8],
+    pos: usize,
+}
+
+impl<'b> Write for BufWriter<'b> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let bytes = s.as_bytes();
+        let remaining = self.buf.len() - self.pos;
+        let to_write = bytes.len().min(remaining);
+        self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
+        self.pos += to_write;
+        Ok(())
+    }
+}
+
+/// Write formatted text of up to 128 bytes.
+fn print(serial: &mut SerialPort<UsbBus>, args: core::fmt::Arguments) {
+    let mut buf = [0u8; 128];
+    let mut writer = BufWriter {
+        buf: &mut buf,
+        pos: 0,
+    };
+    let _ = writer.write_fmt(args);
+    let len = writer.pos;
+    let _ = serial.write(&buf[..len]);
+}
+
 fn print_welcome(serial: &mut SerialPort<UsbBus>) {
     let _ = serial.write(b"\r\n");
     let _ = serial.write(b"+======================================+\r\n");
@@ -40,22 +121,50 @@ fn print_welcome(serial: &mut SerialPort<UsbBus>) {
     let _ = serial.write(b"Type 'help' for available commands.\r\n> ");
 }
 
-/// Process a received command line and return a response.
-/// Returns true if we should reboot to bootloader.
-fn process_command(line: &str, serial: &mut SerialPort<UsbBus>) -> bool {
+/// Process a received command line and return what the main loop does
+/// next. `staged` is the update received since boot, if any.
+fn process_command(
+    line: &str,
+    serial: &mut SerialPort<UsbBus>,
+    rom_flash: &mut RomFlash,
+    staged: Option<&Staged>,
+) -> Action {
     let line = line.trim();
 
+    if let Some(args) = line.strip_prefix("update ") {
+        return match parse_update(args) {
+            Some((size, crc)) => Action::Update { size, crc },
+            None => {
+                let _ = serial.write(b"Usage: update <size> <crc32>\r\n");
+                Action::None
+            }
+        };
+    }
+
     match line {
         "help" | "?" => {
             let _ = serial.write(b"Available commands:\r\n");
             let _ = serial.write(b"  help     - Show this help\r\n");
             let _ = serial.write(b"  status   - Show boot status\r\n");
+            let _ = serial.write(b"  confirm  - Confirm this boot to the bootloader\r\n");
+            let _ = serial.write(b"  update   - Receive firmware for the other bank\r\n");
+            let _ = serial.write(b"             (update <size> <crc32>, then send it raw)\r\n");
+            let _ = serial.write(b"  apply    - Reboot into the received firmware\r\n");
             let _ = serial.write(b"  bootload - Reboot to bootloader update mode\r\n");
             let _ = serial.write(b"  reboot   - Reboot normally\r\n");
+            if AUTO_CONFIRM {
+                let _ = serial.write(
+                    b"Boots are confirmed at startup once health checks pass (default).\r\n",
+                );
+            } else {
+                let _ =
+                    serial.write(b"Boots are confirmed by 'confirm' (manual-confirm build);\r\n");
+                let _ = serial
+                    .write(b"without it the bootloader rolls back after the last attempt.\r\n");
+            }
         }
         "status" => {
-            let bd = flash::read_boot_data();
-            if bd.is_valid() {
+            if let Ok(bd) = flash::read_boot_data(rom_flash) {
                 let mut buf = [0u8; 256];
                 let len = format_status(&bd, &mut buf);
                 let _ = serial.write(&buf[..len]);
@@ -63,13 +172,47 @@ fn process_command(line: &str, serial: &mut SerialPort<UsbBus>) -> bool {
                 let _ = serial.write(b"BootData: invalid\r\n");
             }
         }
+        "confirm" => match flash::confirm_boot(rom_flash) {
+            Ok(ConfirmOutcome::Confirmed) => {
+                let _ = serial.write(b"Boot confirmed.\r\n");
+                return Action::Confirmed;
+            }
+            Ok(ConfirmOutcome::AlreadyConfirmed) => {
+                let _ = serial.write(b"Boot already confirmed.\r\n");
+            }
+            Err(FlashError::BootData(_)) => {
+                let _ = serial.write(b"BootData invalid, nothing to confirm.\r\n");
+            }
+            Err(err) => print(serial, format_args!("Confirm failed: {:?}\r\n", err)),
+        },
+        "update" => {
+            let _ = serial.write(b"Usage: update <size> <crc32>\r\n");
+        }
+        "apply" => match staged {
+            Some(staged) => match self_update::apply(rom_flash, staged) {
+                Ok(()) => {
+                    print(
+                        serial,
+                        format_args!("Rebooting into bank {}...\r\n", staged.bank),
+                    );
+                    cortex_m::asm::delay(1_000_000);
+                    watchdog::stop();
+                    flash::reboot();
+                }
+                Err(err) => print(serial, format_args!("Apply failed: {:?}\r\n", err)),
+            },
+            None => {
+                let _ = serial.write(b"Nothing to apply: run 'update' first.\r\n");
+            }
+        },
         "bootload" => {
             let _ = serial.write(b"Rebooting to bootloader...\r\n");
-            return true;
+            return Action::Bootload;
         }
         "reboot" => {
             let _ = serial.write(b"Rebooting...\r\n");
             cortex_m::asm::delay(1_000_000);
+            watchdog::stop();
             flash::reboot();
         }
         "" => {}
@@ -78,39 +221,189 @@ fn process_command(line: &str, serial: &mut SerialPort<UsbBus>) -> bool {
         }
     }
 
-    false
+    Action::None
 }
 
-fn format_status(bd: &BootData, buf: &mut [u8]) -> usize {
-    use core::fmt::Write;
+/// Parse `<size> <crc32>`: size in decimal, CRC32 in hex with or without
+/// `0x`, as `crispy-upload checksum` prints them.
+fn parse_update(args: &str) -> Option<(u32, u32)> {
+    let mut words = args.split_whitespace();
+    let size = words.next()?.parse().ok()?;
+    let crc = words.next()?;
+    let crc = u32::from_str_radix(crc.strip_prefix("0x").unwrap_or(crc), 16).ok()?;
+    words.next().is_none().then_some((size, crc))
+}
 
-    struct BufWriter<'b> {
-        buf: &'b mut [u8],
-        pos: usize,
+/// Receive `size` bytes of firmware into the inactive bank and stage them
+/// with [`self_update`], reporting each step on `serial`.
+///
+/// The bank is erased before `READY <bank> <size>` is printed; the sender
+/// then writes the raw image. Nothing is activated: that is `apply`.
+fn receive_update(
+    usb_dev: &mut UsbDevice<UsbBus>,
+    serial: &mut SerialPort<UsbBus>,
+    rom_flash: &mut RomFlash,
+    timer: &hal::Timer,
+    size: u32,
+    crc: u32,
+) -> Option<Staged> {
+    let _ = serial.write(b"Erasing...\r\n");
+    let bank = match self_update::prepare(rom_flash, size) {
+        Ok(bank) => bank,
+        Err(err) => {
+            report_update_error(serial, err);
+            return None;
+        }
+    };
+    // A line ending sent apart from the command is not image data
+    discard_input(usb_dev, serial, timer, 100_000);
+    print(serial, format_args!("READY {} {}\r\n", bank, size));
+
+    let mut writer = BankWriter::new(rom_flash, bank);
+    let mut buf = [0u8; 64];
+    let mut last_data = timer.get_counter().ticks();
+    while writer.size() < size {
+        usb_dev.poll(&mut [&mut *serial]);
+        let now = timer.get_counter().ticks();
+        match serial.read(&mut buf) {
+            Ok(count) if count > 0 => {
+                let count = count.min((size - writer.size()) as usize);
+                if let Err(err) = writer.write(&buf[..count]) {
+                    report_update_error(serial, err.into());
+                    discard_input(usb_dev, serial, timer, 500_000);
+                    return None;
+                }
+                last_data = now;
+            }
+            _ if now - last_data > UPDATE_IDLE_TIMEOUT_US => {
+                print(
+                    serial,
+                    format_args!(
+                        "Update timed out after {} of {} bytes\r\n",
+                        writer.size(),
+                        size
+                    ),
+                );
+                return None;
+            }
+            _ => {}
+        }
     }
 
-    impl<'b> Write for BufWriter<'b> {
-        fn write_str(&mut self, s: &str) -> core::fmt::Result {
-            let bytes = s.as_bytes();
-            let remaining = self.buf.len() - self.pos;
-            let to_write = bytes.len().min(remaining);
-            self.buf[self.pos..self.pos + to_write].copy_from_slice(&bytes[..to_write]);
-            self.pos += to_write;
-            Ok(())
+    let staged = writer
+        .finish(None)
+        .map_err(UpdateError::from)
+        .and_then(|(written, computed)| {
+            self_update::stage(rom_flash, bank, written, computed, crc)
+        });
+    match staged {
+        Ok(staged) => {
+            print(
+                serial,
+                format_args!(
+                    "Staged {} bytes in bank {}, CRC32 0x{:08x}, version {}\r\n",
+                    staged.size, staged.bank, staged.crc, staged.version
+                ),
+            );
+            let _ = serial.write(b"Type 'apply' to boot it.\r\n");
+            Some(staged)
+        }
+        Err(err) => {
+            report_update_error(serial, err);
+            None
+        }
+    }
+}
+
+/// Poll USB and drop whatever arrives until `quiet_us` pass without data.
+fn discard_input(
+    usb_dev: &mut UsbDevice<UsbBus>,
+    serial: &mut SerialPort<UsbBus>,
+    timer: &hal::Timer,
+    quiet_us: u64,
+) {
+    let mut buf = [0u8; 64];
+    let mut last_data = timer.get_counter().ticks();
+    loop {
+        usb_dev.poll(&mut [&mut *serial]);
+        let now = timer.get_counter().ticks();
+        if matches!(serial.read(&mut buf), Ok(count) if count > 0) {
+            last_data = now;
+        } else if now - last_data > quiet_us {
+            return;
+        }
+    }
+}
+
+fn report_update_error(serial: &mut SerialPort<UsbBus>, err: UpdateError) {
+    match err {
+        UpdateError::Empty => {
+            let _ = serial.write(b"Update failed: size is 0\r\n");
         }
+        UpdateError::TooLarge(size) => print(
+            serial,
+            format_args!("Update failed: {} bytes do not fit in a bank\r\n", size),
+        ),
+        UpdateError::Flash(FlashError::BootData(_)) => {
+            let _ = serial.write(b"Update failed: BootData invalid, running bank unknown\r\n");
+        }
+        UpdateError::Flash(err) => print(serial, format_args!("Update failed: {:?}\r\n", err)),
+        UpdateError::CrcMismatch { expected, computed } => print(
+            serial,
+            format_args!(
+                "Update failed: CRC32 0x{:08x} received, 0x{:08x} expected\r\n",
+                computed, expected
+            ),
+        ),
+    }
+}
+
+/// Healthy once USB has been configured by a host for
+/// [`HEALTH_USB_UP_US`] without a break.
+fn usb_health(state: UsbDeviceState, up_since: &mut Option<u64>, now_us: u64) -> Health {
+    let Some(required) = HEALTH_USB_UP_US else {
+        return Health::Healthy;
+    };
+    if state != UsbDeviceState::Configured {
+        *up_since = None;
+        return Health::Pending;
+    }
+    let since = *up_since.get_or_insert(now_us);
+    if now_us - since >= required {
+        Health::Healthy
+    } else {
+        Health::Pending
     }
+}
 
+/// The application's own checks, run with the others until they all pass.
+/// Replace with whatever proves this firmware works (sensors answer, a
+/// server replies); return `Failed` to give up on the boot at once.
+fn app_health() -> Health {
+    Health::Healthy
+}
+
+fn format_status(bd: &BootData, buf: &mut [u8]) -> usize {
     let mut writer = BufWriter { buf, pos: 0 };
     let _ = write!(
         writer,
-        "Boot status:\r\n  Bank: {} ({})\r\n  Confirmed: {}\r\n  Attempts: {}\r\n  Version A: {}\r\n  Version B: {}\r\n",
+        "Boot status:\r\n  Bank: {} ({})\r\n  Confirmed: {}\r\n  Attempts: {}\r\n  Version A: {}\r\n  Version B: {}\r\n  Repaired: {}\r\n",
         bd.active_bank,
         if bd.active_bank == 0 { "A" } else { "B" },
         bd.confirmed,
         bd.boot_attempts,
         bd.version_a,
-        bd.version_b
+        bd.version_b,
+        bd.is_repaired()
     );
+    if bd.confirmed == 0 {
+        let _ = write!(
+            writer,
+            "  PENDING CONFIRMATION (attempt {}/{})\r\n",
+            bd.boot_attempts,
+            bd.boot_attempt_limit()
+        );
+    }
 
     writer.pos
 }
@@ -123,37 +416,39 @@ fn main() -> ! {
     let mut pac = unsafe { hal::pac::Peripherals::steal() };
 
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
-    let clocks = hal::clocks::init_clocks_and_plls(
-        12_000_000u32,
-        pac.XOSC,
-        pac.CLOCKS,
-        pac.PLL_SYS,
-        pac.PLL_USB,
-        &mut pac.RESETS,
-        &mut watchdog,
-    )
-    .unwrap();
-
-    let mut timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
     let sio = hal::Sio::new(pac.SIO);
-    let pins = hal::gpio::Pins::new(
+    let _pins = hal::gpio::Pins::new(
         pac.IO_BANK0,
         pac.PADS_BANK0,
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
 
-    let mut led_pin = pins.gpio25.into_push_pull_output();
+    let mut led = crispy_common::board_led();
+
+    let clocks = clocks::init_clocks(
+        clocks::ClockPeripherals {
+            xosc: pac.XOSC,
+            rosc: pac.ROSC,
+            clocks: pac.CLOCKS,
+            pll_sys: pac.PLL_SYS,
+            pll_usb: pac.PLL_USB,
+        },
+        &mut pac.RESETS,
+        &mut watchdog,
+        &mut led,
+    );
+
+    // Early, so a hang anywhere from here on resets the chip
+    watchdog::init(watchdog);
+
+    let mut timer = hal::Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
 
     // Blink to signal firmware alive
-    crispy_common::blink(&mut led_pin, &mut timer, 5, 100);
+    crispy_common::blink(&mut led, &mut timer, 5, 100);
 
-    // Confirm boot using library
-    if flash::confirm_boot() {
-        defmt::println!("Boot confirmed");
-    } else {
-        defmt::println!("BootData invalid, skipping confirmation");
-    }
+    // Erase/program run from RAM with XIP down, see RomFlash
+    let mut rom_flash = unsafe { RomFlash::new() };
 
     // Initialize USB
     let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
@@ -168,7 +463,7 @@ fn main() -> ! {
     }
 
     let mut serial = SerialPort::new(usb_bus_ref());
-    let mut usb_dev = UsbDeviceBuilder::new(usb_bus_ref(), UsbVidPid(0x2E8A, 0x000B))
+    let mut usb_dev = UsbDeviceBuilder::new(usb_bus_ref(), UsbVidPid(USB_VID, FIRMWARE_PID))
         .strings(&[StringDescriptors::default()
             .manufacturer("ADNT")
             .product("Crispy Firmware")
@@ -177,13 +472,63 @@ fn main() -> ! {
         .device_class(usbd_serial::USB_CLASS_CDC)
         .build();
 
-    defmt::println!("USB CDC initialized, entering main loop");
+    defmt::println!("USB CDC initialized");
+
+    // A distinct pattern while the bootloader may still roll back
+    let unconfirmed = flash::read_boot_data(&rom_flash).is_ok_and(|bd| bd.confirmed == 0);
+    let mut heartbeat = PatternPlayer::new(if unconfirmed {
+        LedPattern::TRIPLE_PULSE
+    } else {
+        LedPattern::HEARTBEAT
+    });
+
+    // Confirm boot once this image has shown it works
+    if AUTO_CONFIRM {
+        let mut usb_up_since = None;
+        let outcome = health::confirm_when_healthy(
+            &mut rom_flash,
+            HEALTH_TIMEOUT_US,
+            || timer.get_counter().ticks(),
+            || {
+                watchdog::feed();
+                usb_dev.poll(&mut [&mut serial]);
+                let now = timer.get_counter().ticks();
+                heartbeat.tick(&mut led, now);
+                usb_health(usb_dev.state(), &mut usb_up_since, now).and(app_health())
+            },
+        );
+        match outcome {
+            Ok(HealthOutcome::Confirmed(ConfirmOutcome::Confirmed)) => {
+                defmt::println!("Health checks passed, boot confirmed");
+                heartbeat.set_pattern(LedPattern::HEARTBEAT);
+            }
+            Ok(HealthOutcome::Confirmed(ConfirmOutcome::AlreadyConfirmed)) => {
+                defmt::println!("Boot already confirmed")
+            }
+            Ok(HealthOutcome::Failed | HealthOutcome::TimedOut) => {
+                // Let the watchdog reset us: the bootloader counts this boot
+                // and rolls back once the attempts run out
+                defmt::println!("Health checks did not pass, waiting for the watchdog");
+                loop {
+                    core::hint::spin_loop();
+                }
+            }
+            Err(FlashError::BootData(_)) => {
+                defmt::println!("BootData invalid, skipping confirmation")
+            }
+            Err(_) => defmt::println!("BootData write failed, boot not confirmed"),
+        }
+    } else {
+        defmt::println!("Manual confirmation: waiting for 'confirm'");
+    }
+
+    defmt::println!("Entering main loop");
     defmt::println!("Connect via serial terminal and type 'help' for commands");
 
     let mut cmd_buf = [0u8; 64];
     let mut cmd_pos = 0usize;
-    let mut blink_counter = 0u32;
     let mut welcome_printed = false;
+    let mut staged: Option<Staged> = None;
 
     loop {
         // Poll USB
@@ -199,6 +544,7 @@ fn main() -> ! {
 
         // Read incoming data
         let mut buf = [0u8; 64];
+        let mut update = None;
         if let Ok(count) = serial.read(&mut buf) {
             for &byte in &buf[..count] {
                 // Echo character
@@ -209,18 +555,31 @@ fn main() -> ! {
 
                     if cmd_pos > 0 {
                         if let Ok(line) = core::str::from_utf8(&cmd_buf[..cmd_pos]) {
-                            if process_command(line, &mut serial) {
-                                // Flush USB before rebooting
-                                for _ in 0..100 {
-                                    usb_dev.poll(&mut [&mut serial]);
-                                    cortex_m::asm::delay(10_000);
+                            match process_command(
+                                line,
+                                &mut serial,
+                                &mut rom_flash,
+                                staged.as_ref(),
+                            ) {
+                                Action::None => {}
+                                Action::Confirmed => heartbeat.set_pattern(LedPattern::HEARTBEAT),
+                                Action::Bootload => {
+                                    // Flush USB before rebooting
+                                    for _ in 0..100 {
+                                        usb_dev.poll(&mut [&mut serial]);
+                                        cortex_m::asm::delay(10_000);
+                                    }
+                                    watchdog::stop();
+                                    flash::reboot_to_bootloader();
                                 }
-                                flash::reboot_to_bootloader();
+                                Action::Update { size, crc } => update = Some((size, crc)),
                             }
                         }
                         cmd_pos = 0;
                     }
-                    let _ = serial.write(b"> ");
+                    if update.is_none() {
+                        let _ = serial.write(b"> ");
+                    }
                 } else if byte == 0x7F || byte == 0x08 {
                     // Backspace
                     if cmd_pos > 0 {
@@ -234,15 +593,18 @@ fn main() -> ! {
             }
         }
 
-        // Slow blink LED to show activity
-        blink_counter += 1;
-        if blink_counter >= 500_000 {
-            blink_counter = 0;
-            if led_pin.is_set_high().unwrap_or(false) {
-                led_pin.set_low().ok();
-            } else {
-                led_pin.set_high().ok();
-            }
+        // After the rest of the command's packet, so a trailing line ending
+        // is not taken for image data
+        if let Some((size, crc)) = update {
+            // Erasing a bank takes longer than the watchdog timeout
+            watchdog::stop();
+            staged = receive_update(&mut usb_dev, &mut serial, &mut rom_flash, &timer, size, crc);
+            watchdog::restart();
+            let _ = serial.write(b"> ");
         }
+
+        // Heartbeat LED to show activity
+        heartbeat.tick(&mut led, timer.get_counter().ticks());
+        watchdog::feed();
     }
 }
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Hardware watchdog supervision of the firmware.
+//!
+//! Started right after the clocks and fed from the main loop, so a firmware
+//! that hangs is reset instead of hanging until a power cycle, and the
+//! bootloader counts the boot. A boot that fails its health checks simply
+//! stops feeding it. The watchdog keeps running through a software reset,
+//! so it is stopped before every deliberate reboot, as the bootloader does.
+
+use rp2040_hal as hal;
+use rp2040_hal::fugit::ExtU32;
+
+/// The firmware must feed the watchdog at least this often.
+const TIMEOUT_MS: u32 = 3_000;
+
+static mut WATCHDOG: Option<hal::Watchdog> = None;
+
+fn watchdog() -> Option<&'static mut hal::Watchdog> {
+    unsafe { (*core::ptr::addr_of_mut!(WATCHDOG)).as_mut() }
+}
+
+/// Take ownership of the watchdog and start it.
+pub fn init(wd: hal::Watchdog) {
+    unsafe {
+        WATCHDOG = Some(wd);
+    }
+    restart();
+}
+
+/// Start the watchdog again after [`stop`].
+pub fn restart() {
+    if let Some(wd) = watchdog() {
+        // Halting in a debugger should not reset the chip
+        wd.pause_on_debug(true);
+        wd.start(TIMEOUT_MS.millis());
+    }
+}
+
+/// Reload the watchdog counter.
+pub fn feed() {
+    if let Some(wd) = watchdog() {
+        wd.feed();
+    }
+}
+
+/// Stop supervision, before a deliberate reboot or an operation too long to
+/// feed through, such as erasing a bank.
+pub fn stop() {
+    if let Some(wd) = watchdog() {
+        wd.disable();
+    }
+}
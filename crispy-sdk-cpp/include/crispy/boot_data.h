@@ -7,26 +7,32 @@
 
 namespace crispy {
 
-// BootData structure (must match crispy-common, 32 bytes)
+// BootData structure (must match crispy-common, 64 bytes, layout v2).
+// The first 32 bytes are the v1 record; v1 records have 0xFF at byte 32.
 struct __attribute__((packed)) BootData {
     uint32_t magic;
     uint8_t  active_bank;
     uint8_t  confirmed;
     uint8_t  boot_attempts;
-    uint8_t  _reserved0;
+    uint8_t  flags;          // BOOT_FLAG_* bits
     uint32_t version_a;
     uint32_t version_b;
     uint32_t crc_a;
     uint32_t crc_b;
     uint32_t size_a;
     uint32_t size_b;
+    uint8_t  layout_version; // BOOT_DATA_VERSION
+    uint8_t  max_boot_attempts; // rollback threshold, 0 = default (3)
+    uint8_t  reserved[26];
+    uint32_t record_crc;     // CRC32 of the 60 bytes before it
 
     bool is_valid() const { return magic == BOOT_DATA_MAGIC; }
     const char* bank_name() const { return active_bank == 0 ? "A" : "B"; }
 };
-static_assert(sizeof(BootData) == 32, "BootData must be 32 bytes");
+static_assert(sizeof(BootData) == 64, "BootData must be 64 bytes");
 
-// Read BootData from flash
+// Read BootData from flash. v1 records are upgraded in memory; a record that
+// fails its CRC or has an unknown layout version comes back with magic 0.
 BootData read_boot_data();
 
 // Confirm boot to bootloader (write confirmed=1, boot_attempts=0)
@@ -15,6 +15,8 @@ constexpr uint32_t BOOT_DATA_ADDR       = 0x10190000;
 
 constexpr uint32_t FW_BANK_SIZE         = 768 * 1024;  // 768KB per bank
 constexpr uint32_t BOOT_DATA_MAGIC      = 0xB007DA7A;
+constexpr uint8_t  BOOT_DATA_VERSION    = 2;           // BootData layout written by this SDK
+constexpr uint8_t  BOOT_FLAG_RECONSTRUCTED = 0x01;  // BootData rebuilt after corruption
 
 // RAM flags for bootloader communication
 constexpr uint32_t RAM_UPDATE_FLAG_ADDR = 0x2003BFF0;
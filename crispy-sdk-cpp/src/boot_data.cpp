@@ -6,14 +6,41 @@
 #include "hardware/flash.h"
 #include "hardware/sync.h"
 #include "hardware/watchdog.h"
+#include <cstddef>
 #include <cstring>
 #include <cstdio>
 
 namespace crispy {
 
+// CRC-32 (ISO HDLC), same as crispy-common's crc32 module
+static uint32_t crc32(const uint8_t* data, size_t len) {
+    uint32_t crc = 0xFFFFFFFF;
+    for (size_t i = 0; i < len; i++) {
+        crc ^= data[i];
+        for (int bit = 0; bit < 8; bit++) {
+            crc = (crc & 1) ? (crc >> 1) ^ 0xEDB88320 : crc >> 1;
+        }
+    }
+    return ~crc;
+}
+
+static constexpr size_t BOOT_DATA_CRC_LEN = offsetof(BootData, record_crc);
+
 BootData read_boot_data() {
-    const auto* bd = reinterpret_cast<const BootData*>(BOOT_DATA_ADDR);
-    return *bd;
+    BootData bd;
+    memcpy(&bd, reinterpret_cast<const void*>(BOOT_DATA_ADDR), sizeof(bd));
+
+    if (bd.layout_version == 0xFF) {
+        // v1 record: the rest is erased flash
+        bd.max_boot_attempts = 0;
+        memset(bd.reserved, 0, sizeof(bd.reserved));
+        bd.layout_version = BOOT_DATA_VERSION;
+        bd.record_crc = 0;
+    } else if (bd.layout_version != BOOT_DATA_VERSION ||
+               crc32(reinterpret_cast<const uint8_t*>(&bd), BOOT_DATA_CRC_LEN) != bd.record_crc) {
+        bd.magic = 0;
+    }
+    return bd;
 }
 
 void confirm_boot() {
@@ -32,6 +59,8 @@ void confirm_boot() {
 
     bd.confirmed = 1;
     bd.boot_attempts = 0;
+    bd.layout_version = BOOT_DATA_VERSION;
+    bd.record_crc = crc32(reinterpret_cast<const uint8_t*>(&bd), BOOT_DATA_CRC_LEN);
 
     uint32_t offset = BOOT_DATA_ADDR - FLASH_BASE_ADDR;
 
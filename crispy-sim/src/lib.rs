@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! A simulated crispy-bootloader in update mode, behind a TCP bridge.
+//!
+//! `crispy-upload --port tcp://ADDR:PORT` talks to it as to a device on a
+//! serial-to-TCP bridge. The banks and BootData live on a
+//! [`FlashSim`], and every update command goes through the same
+//! [`update_fsm`] the bootloader runs, so the simulator accepts and refuses
+//! exactly what the device does. The platform commands are answered the way
+//! `crispy-bootloader/src/update.rs` answers them:
+//!
+//! - `GetStatus` from BootData and the update state
+//! - `GetFlashInfo` from the flash layout, with [`JEDEC_ID`]
+//! - `GetDeviceInfo` with [`CHIP_ID`], [`FLASH_UID`] and the last error
+//! - `Reboot` is acknowledged, then the connection is closed; the device
+//!   comes back as the bootloader on the next connection
+//!
+//! Connections are served one after another, against the same flash. A
+//! connection that closes mid-upload aborts it, as a USB disconnect does.
+//! `Progress` frames go out once an operation has run a second, every
+//! 250 ms.
+//!
+//! [`Faults`] make the link or the device misbehave on purpose: lose every
+//! Nth command, corrupt every Nth data block, or erase slowly.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crispy_common::cobs::CobsFrameDecoder;
+use crispy_common::flash::read_boot_data;
+use crispy_common::flash_ops::FlashOps;
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::memory_layout::FlashLayout;
+use crispy_common::protocol::{AckStatus, BootData, Command, Response};
+use crispy_common::update_fsm::{self, ResponseSink, UpdateState};
+
+/// JEDEC ID reported by `GetFlashInfo`: a Winbond W25Q16JV, as on the Pico.
+pub const JEDEC_ID: u32 = 0xEF_40_15;
+/// `SYSINFO.CHIP_ID` reported by `GetDeviceInfo`: an RP2040-B2.
+pub const CHIP_ID: u32 = 0x2000_2927;
+/// Flash unique ID reported by `GetDeviceInfo`.
+pub const FLASH_UID: u64 = 0xE661_4103_E745_2D2F;
+
+/// Largest command frame accepted, a full `DataBlock` and then some.
+const FRAME_SIZE: usize = 4096;
+/// Time an operation runs before it reports progress.
+const PROGRESS_DELAY: Duration = Duration::from_secs(1);
+/// Spacing of progress frames once they start.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Misbehaviour to inject. Counts run over the whole life of the simulator,
+/// across connections; 0 turns a fault off.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Faults {
+    /// Lose every Nth command frame: it is neither handled nor answered.
+    pub drop_every: u32,
+    /// Flip the first byte of every Nth `DataBlock` before it is written.
+    pub corrupt_every: u32,
+    /// Time each flash sector takes to erase.
+    pub erase_delay: Duration,
+}
+
+/// A simulated device listening for connections.
+pub struct Sim {
+    listener: TcpListener,
+    device: Device,
+}
+
+impl Sim {
+    /// Listen on `addr` (`127.0.0.1:0` picks a free port), with blank flash.
+    pub fn bind(addr: &str, faults: Faults) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            device: Device {
+                flash: FlashSim::new(),
+                state: UpdateState::Idle,
+                faults,
+                commands: 0,
+                blocks: 0,
+                last_error: None,
+            },
+        })
+    }
+
+    /// The `--port` that reaches this device.
+    pub fn port(&self) -> String {
+        let addr = self.listener.local_addr().expect("bound listener");
+        format!("tcp://{}", addr)
+    }
+
+    /// Serve connections one after another, for as long as the process runs.
+    pub fn run(mut self) -> io::Result<()> {
+        loop {
+            let (stream, peer) = self.listener.accept()?;
+            log(&format!("Host connected from {}", peer));
+            self.device.serve(stream);
+        }
+    }
+
+    /// [`run`](Self::run) on a thread of its own, and return the `--port`.
+    pub fn spawn(self) -> String {
+        let port = self.port();
+        thread::spawn(move || self.run());
+        port
+    }
+}
+
+fn log(line: &str) {
+    eprintln!("[crispy-sim] {}", line);
+}
+
+struct Device {
+    flash: FlashSim,
+    state: UpdateState,
+    faults: Faults,
+    /// Command frames received so far.
+    commands: u32,
+    /// `DataBlock` commands received so far.
+    blocks: u32,
+    /// Most recent non-`Ok` status sent.
+    last_error: Option<AckStatus>,
+}
+
+/// What ended a connection.
+enum Closed {
+    Host,
+    Reboot,
+}
+
+impl Device {
+    fn serve(&mut self, mut stream: TcpStream) {
+        let closed = self.serve_frames(&mut stream);
+        if self.state.is_receiving() {
+            log("Update aborted: host disconnected");
+        }
+        self.state = UpdateState::Idle;
+        match closed {
+            Closed::Host => log("Host disconnected"),
+            Closed::Reboot => log("Rebooted"),
+        }
+    }
+
+    fn serve_frames(&mut self, stream: &mut TcpStream) -> Closed {
+        let mut rx = CobsFrameDecoder::<FRAME_SIZE>::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return Closed::Host,
+                Ok(n) => n,
+            };
+            for &byte in &buf[..n] {
+                let Some(frame) = rx.feed(byte) else {
+                    continue;
+                };
+                let command = postcard::from_bytes::<Command>(frame);
+                if let Some(Closed::Reboot) = self.handle(stream, command) {
+                    return Closed::Reboot;
+                }
+            }
+        }
+    }
+
+    fn handle(
+        &mut self,
+        stream: &mut TcpStream,
+        command: Result<Command, postcard::Error>,
+    ) -> Option<Closed> {
+        self.commands += 1;
+        if every(self.faults.drop_every, self.commands) {
+            log(&format!("Dropped command frame {}", self.commands));
+            return None;
+        }
+
+        let mut sink = SimSink::new(stream, self.last_error);
+        let closed = match command {
+            Ok(command) => self.answer(&mut sink, command),
+            Err(_) => {
+                sink.send(&Response::Ack(AckStatus::BadCommand));
+                None
+            }
+        };
+        self.last_error = sink.last_error;
+        closed
+    }
+
+    fn answer(&mut self, sink: &mut SimSink, mut command: Command) -> Option<Closed> {
+        match &mut command {
+            Command::GetStatus => {
+                let bd = read_boot_data(&self.flash).unwrap_or_else(|_| BootData::default_new());
+                sink.send(&Response::Status {
+                    active_bank: bd.active_bank,
+                    version_a: bd.version_a,
+                    version_b: bd.version_b,
+                    state: self.state.boot_state(),
+                    host_connected: true,
+                    tx_stalled: false,
+                    bootdata_reconstructed: bd.is_reconstructed(),
+                    update_interrupted: false,
+                });
+            }
+            Command::GetFlashInfo => {
+                let layout = *self.flash.layout();
+                sink.send(&Response::FlashInfo {
+                    jedec_id: JEDEC_ID,
+                    detected_size: layout.flash_size,
+                    layout_size: layout.flash_size,
+                    bank_size: layout.bank_size,
+                    fw_a_addr: layout.fw_a,
+                    fw_b_addr: layout.fw_b,
+                    boot_data_addr: layout.boot_data,
+                });
+            }
+            Command::GetDeviceInfo => {
+                let last_error = sink.last_error;
+                sink.send(&Response::DeviceInfo {
+                    bootloader_version: env!("CARGO_PKG_VERSION").parse().unwrap_or_default(),
+                    chip_id: CHIP_ID,
+                    flash_uid: FLASH_UID,
+                    last_error,
+                });
+            }
+            Command::Reboot => {
+                sink.send(&Response::Ack(AckStatus::Ok));
+                return Some(Closed::Reboot);
+            }
+            Command::DataBlock { data, .. } => {
+                self.blocks += 1;
+                if every(self.faults.corrupt_every, self.blocks) && !data.is_empty() {
+                    log(&format!("Corrupted data block {}", self.blocks));
+                    data[0] ^= 0xFF;
+                }
+                self.update(sink, command);
+            }
+            _ => self.update(sink, command),
+        }
+        None
+    }
+
+    fn update(&mut self, sink: &mut SimSink, command: Command) {
+        let mut flash = SlowFlash {
+            flash: &mut self.flash,
+            erase_delay: self.faults.erase_delay,
+        };
+        self.state = update_fsm::handle_command(&mut flash, sink, self.state, command);
+    }
+}
+
+/// Whether the `n`th event is one of every `every`th.
+fn every(every: u32, n: u32) -> bool {
+    every > 0 && n.is_multiple_of(every)
+}
+
+/// Sends each response as a COBS frame, keeps the last error for
+/// `GetDeviceInfo`, and reports progress as the bootloader does.
+struct SimSink<'a> {
+    stream: &'a mut TcpStream,
+    last_error: Option<AckStatus>,
+    started: Instant,
+    last_report: Option<Instant>,
+}
+
+impl<'a> SimSink<'a> {
+    fn new(stream: &'a mut TcpStream, last_error: Option<AckStatus>) -> Self {
+        Self {
+            stream,
+            last_error,
+            started: Instant::now(),
+            last_report: None,
+        }
+    }
+}
+
+impl ResponseSink for SimSink<'_> {
+    fn send(&mut self, response: &Response) {
+        match response {
+            Response::Ack(status) | Response::Nack { status, .. } if *status != AckStatus::Ok => {
+                self.last_error = Some(*status);
+            }
+            _ => {}
+        }
+        let encoded = postcard::to_stdvec_cobs(response).expect("response encodes");
+        // A host that went away is noticed on the next read
+        let _ = self.stream.write_all(&encoded);
+    }
+
+    fn log(&mut self, line: &str) {
+        log(line);
+    }
+
+    fn progress(&mut self, done: u32, total: u32) {
+        let now = Instant::now();
+        let due = self
+            .last_report
+            .is_none_or(|last| now - last >= PROGRESS_INTERVAL);
+        if now - self.started >= PROGRESS_DELAY && due {
+            self.last_report = Some(now);
+            self.send(&Response::Progress { done, total });
+        }
+    }
+}
+
+/// The flash, taking [`Faults::erase_delay`] per sector to erase.
+struct SlowFlash<'a> {
+    flash: &'a mut FlashSim,
+    erase_delay: Duration,
+}
+
+impl FlashOps for SlowFlash<'_> {
+    fn erase(&mut self, offset: u32, len: u32) {
+        thread::sleep(self.erase_delay * (len / FlashSim::SECTOR_SIZE));
+        self.flash.erase(offset, len);
+    }
+
+    fn program(&mut self, offset: u32, data: &[u8]) {
+        self.flash.program(offset, data);
+    }
+
+    fn read(&self, addr: u32, buf: &mut [u8]) {
+        self.flash.read(addr, buf);
+    }
+
+    fn layout(&self) -> &FlashLayout {
+        self.flash.layout()
+    }
+}
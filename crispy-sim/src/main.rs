@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Simulated crispy-bootloader for testing crispy-upload without hardware.
+//!
+//! Usage:
+//!   crispy-sim                                  (listens on 127.0.0.1:4001)
+//!   crispy-sim --listen 127.0.0.1:0             (any free port)
+//!   crispy-sim --drop-every 50 --corrupt-every 100 --erase-delay 20
+//!
+//! Prints `tcp://ADDR:PORT` on stdout once listening, for
+//! `crispy-upload --port`; what the device does goes to stderr.
+
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+
+use crispy_sim::{Faults, Sim};
+
+#[derive(Parser)]
+#[command(name = "crispy-sim")]
+#[command(about = "Simulated crispy-bootloader behind a TCP bridge")]
+#[command(version)]
+struct Cli {
+    /// Address and port to listen on (port 0 picks a free one)
+    #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:4001")]
+    listen: String,
+
+    /// Lose every Nth command frame, unanswered
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    drop_every: u32,
+
+    /// Flip a byte in every Nth data block before writing it
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    corrupt_every: u32,
+
+    /// Milliseconds each flash sector takes to erase
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    erase_delay: u64,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let faults = Faults {
+        drop_every: cli.drop_every,
+        corrupt_every: cli.corrupt_every,
+        erase_delay: Duration::from_millis(cli.erase_delay),
+    };
+    let sim = match Sim::bind(&cli.listen, faults) {
+        Ok(sim) => sim,
+        Err(err) => {
+            eprintln!("Error: failed to listen on {}: {}", cli.listen, err);
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{}", sim.port());
+    match sim.run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,770 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `backup` and `restore`: both banks and BootData of a device in one
+//! archive, to put the device back as it was after experimenting on it.
+//!
+//! The archive is a plain ustar file, so `tar tf` lists it and `tar xf`
+//! unpacks it:
+//!
+//! | Entry | Contents |
+//! |-------|----------|
+//! | `manifest.json` | What was saved, from which device (a [`Manifest`]) |
+//! | `boot-data.bin` | The BootData record as `GetBootData` reports it |
+//! | `bank-a.bin`, `bank-b.bin` | A bank from its start: the image BootData records, or the whole bank with `--full` |
+//!
+//! Without `--full` a bank that records no image is left out, and so are
+//! both banks when the record is invalid and the image sizes are unknown;
+//! `--full` saves them anyway.
+//!
+//! `restore` checks every entry against the CRC32 the manifest gives it
+//! before touching the device, and refuses a device whose chip ID, flash
+//! unique ID or bank layout is not the manifest's (or cannot be read)
+//! unless `--allow-other-device` or `--force`. It uploads each saved bank,
+//! stores the record with `SetBootData`, then reads BootData back and has
+//! the device check both banks against it.
+
+use std::fs;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crispy_common::crc32;
+use crispy_common::flash::BankVerify;
+use crispy_common::protocol::{AckStatus, BootData, Command, Response, BOOT_DATA_SIZE};
+
+use crate::commands::{self, InputOptions, UploadOptions, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_GAP};
+use crate::history;
+use crate::output::{self, outln, Failure, Progress};
+use crate::overrides::{Overrides, Protection};
+use crate::transport::Transport;
+
+/// [`Manifest::format`] of every backup.
+const FORMAT: &str = "crispy-backup";
+/// [`Manifest::version`] this build writes, and the newest it restores.
+const FORMAT_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+const BOOT_DATA_FILE: &str = "boot-data.bin";
+const BANK_FILES: [&str; 2] = ["bank-a.bin", "bank-b.bin"];
+
+/// `manifest.json`: where the backup came from and what it holds.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Always `crispy-backup`.
+    pub format: String,
+    /// Format version; a newer one than this build knows is refused.
+    pub version: u32,
+    /// When the backup was taken, `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created: String,
+    /// The `crispy-upload` version that took it.
+    pub tool: String,
+    pub port: String,
+    pub device: Identity,
+    pub layout: Layout,
+    /// Whole banks were saved (`--full`).
+    pub full: bool,
+    pub boot_data: Entry,
+    pub banks: Vec<BankEntry>,
+}
+
+/// Who the device is, as far as the bootloader can tell; `None` where an
+/// older bootloader cannot.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    /// RP2040 `SYSINFO.CHIP_ID`.
+    pub chip_id: Option<u32>,
+    /// Unique ID of the flash chip, in hex.
+    pub flash_uid: Option<String>,
+    pub bootloader_version: Option<String>,
+    /// JEDEC ID of the flash chip.
+    pub jedec_id: Option<u32>,
+}
+
+/// Where the banks are.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub fw_a_addr: u32,
+    pub fw_b_addr: u32,
+    pub bank_size: u32,
+}
+
+/// An archive entry and the CRC32 of its contents.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub file: String,
+    pub length: u32,
+    pub crc32: u32,
+}
+
+/// A saved bank.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BankEntry {
+    pub bank: u8,
+    pub addr: u32,
+    #[serde(flatten)]
+    pub entry: Entry,
+    /// Version BootData records for the bank, when the record is valid.
+    pub version: Option<String>,
+}
+
+/// Save both banks and BootData of the device to `output`.
+pub fn backup(transport: &mut Transport, output: &Path, full: bool, chunk_size: u32) -> Result<()> {
+    commands::check_chunk_size(chunk_size)?;
+    let (device, layout) = identify(transport)?;
+    if device.chip_id.is_none() {
+        outln!("Warning:  the bootloader cannot report its chip ID; restore will need --allow-other-device");
+    }
+    let (raw, _) = boot_data(transport)?;
+    let record = commands::decode_boot_data(&raw);
+    if let Err(err) = &record {
+        outln!("Warning:  the stored BootData is invalid ({:#})", err);
+        if !full {
+            outln!("          pass --full to save the banks without it");
+        }
+    }
+
+    let mut files = vec![(BOOT_DATA_FILE, raw.clone())];
+    let mut banks = Vec::new();
+    for bank in [0, 1] {
+        let addr = [layout.fw_a_addr, layout.fw_b_addr][bank as usize];
+        let recorded = record.as_ref().ok().map(|bd| recorded(bd, bank));
+        let length = match (full, recorded) {
+            (true, _) => layout.bank_size,
+            (false, Some((size, _))) => size,
+            (false, None) => 0,
+        };
+        if length == 0 {
+            outln!("Bank {}: no image recorded, not saved", bank);
+            continue;
+        }
+        output::phase("read");
+        outln!(
+            "Reading bank {} ({} bytes at 0x{:08x})...",
+            bank,
+            length,
+            addr
+        );
+        let data = read_bank(transport, addr, length, chunk_size)?;
+        banks.push(BankEntry {
+            bank,
+            addr,
+            entry: entry(BANK_FILES[bank as usize], &data),
+            version: recorded.map(|(_, version)| version.to_string()),
+        });
+        files.push((BANK_FILES[bank as usize], data));
+    }
+
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: FORMAT_VERSION,
+        created: history::now(),
+        tool: env!("CARGO_PKG_VERSION").to_string(),
+        port: transport.port_name().to_string(),
+        device,
+        layout,
+        full,
+        boot_data: entry(BOOT_DATA_FILE, &raw),
+        banks,
+    };
+    let mut entries = vec![(MANIFEST, serde_json::to_vec_pretty(&manifest)?)];
+    entries.extend(files);
+    let archive = tar::write(&entries, unix_now());
+    fs::write(output, &archive).with_context(|| format!("Failed to write {}", output.display()))?;
+
+    outln!(
+        "Saved {} ({} bytes): BootData{}",
+        output.display(),
+        archive.len(),
+        manifest
+            .banks
+            .iter()
+            .map(|b| format!(", bank {} ({} bytes)", b.bank, b.entry.length))
+            .collect::<String>()
+    );
+    output::report(json!({ "file": output, "manifest": manifest }));
+    Ok(())
+}
+
+/// Put the device back as the backup in `archive` has it. `overrides` may
+/// go ahead on a device the backup did not come from.
+pub fn restore(transport: &mut Transport, archive: &Path, overrides: Overrides) -> Result<()> {
+    let bytes =
+        fs::read(archive).with_context(|| format!("Failed to read {}", archive.display()))?;
+    let backup = Backup::parse(&bytes).map_err(|problem| {
+        Failure::new("archive", format!("{}: {}", archive.display(), problem))
+    })?;
+    let manifest = &backup.manifest;
+    outln!(
+        "Backup:   {} from {}, taken {}",
+        archive.display(),
+        manifest.port,
+        manifest.created
+    );
+
+    let (device, layout) = identify(transport)?;
+    let problems = mismatches(manifest, &device, &layout);
+    overrides.check(Protection::OtherDevice, || {
+        if problems.is_empty() {
+            return Ok(());
+        }
+        bail!(Failure::new(
+            "wrong_device",
+            format!(
+                "The backup is not from this device ({}); --allow-other-device restores it anyway",
+                problems.join("; ")
+            )
+        )
+        .with("problems", problems))
+    })?;
+
+    // The record is checked before any bank is erased for it
+    let record =
+        commands::decode_boot_data(&backup.boot_data).and_then(|bd| match bd.validate_extended() {
+            Ok(()) => Ok(bd),
+            Err(issue) => bail!("{:?}", issue),
+        });
+    if let Err(err) = &record {
+        outln!(
+            "Warning:  the saved BootData is invalid ({:#}); restoring the banks only",
+            err
+        );
+    }
+
+    for (bank, data) in &backup.banks {
+        let version = record.as_ref().ok().map(|bd| recorded(bd, bank.bank).1);
+        let file = temp_file(&transport.port_name(), BANK_FILES[bank.bank as usize]);
+        fs::write(&file, data).with_context(|| format!("Failed to write {}", file.display()))?;
+        outln!("Restoring bank {}...", bank.bank);
+        let uploaded =
+            commands::upload(transport, &file, Some(bank.bank), &upload_options(version));
+        let _ = fs::remove_file(&file);
+        uploaded?;
+        outln!();
+    }
+
+    let Ok(expected) = record else {
+        output::report(json!({ "file": archive, "boot_data": false }));
+        outln!("Restored the banks; BootData is as the uploads left it");
+        return Ok(());
+    };
+    output::phase("boot_data");
+    outln!("Restoring BootData...");
+    let raw = backup
+        .boot_data
+        .as_slice()
+        .try_into()
+        .expect("checked length");
+    let pb = Progress::percent("boot_data")?;
+    let response =
+        transport.send_recv_with_progress(&Command::SetBootData { raw }, |done, total| {
+            pb.set_length(total as u64);
+            pb.set_position(done as u64);
+        });
+    pb.finish_and_clear();
+    match response? {
+        Response::Ack(AckStatus::Ok) => {}
+        Response::Ack(AckStatus::BadCommand) => bail!(Failure::new(
+            "device",
+            "The bootloader cannot store a BootData record (no SetBootData); \
+             the banks are restored, update the bootloader to restore the rest"
+        )
+        .with("status", "BadCommand")),
+        Response::Ack(status) => bail!(commands::refused("SetBootData", status)),
+        other => bail!(commands::unexpected(&other)),
+    }
+
+    output::phase("verify");
+    let (raw, banks) = boot_data(transport)?;
+    let stored = commands::decode_boot_data(&raw)?;
+    if stored.to_bytes() != expected.to_bytes() {
+        bail!(Failure::new(
+            "verify",
+            "BootData read back differs from the backup"
+        ));
+    }
+    for (bank, verdict) in banks.iter().enumerate() {
+        let saved = recorded(&expected, bank as u8).0 > 0;
+        if saved && !matches!(verdict, BankVerify::Ok { .. }) {
+            bail!(Failure::new(
+                "verify",
+                format!("Bank {} does not match BootData: {:?}", bank, verdict)
+            )
+            .with("bank", bank));
+        }
+    }
+    outln!(
+        "Restored {}: BootData and {} bank(s), verified",
+        archive.display(),
+        backup.banks.len()
+    );
+    output::report(json!({
+        "file": archive,
+        "banks": backup.banks.iter().map(|(b, _)| b.bank).collect::<Vec<_>>(),
+        "boot_data": true,
+        "verified": true,
+    }));
+    Ok(())
+}
+
+/// A backup read from its archive, every entry checked.
+struct Backup {
+    manifest: Manifest,
+    boot_data: Vec<u8>,
+    banks: Vec<(BankEntry, Vec<u8>)>,
+}
+
+impl Backup {
+    fn parse(bytes: &[u8]) -> std::result::Result<Self, String> {
+        let mut entries = tar::read(bytes)?;
+        let mut take = |name: &str| {
+            let at = entries.iter().position(|(n, _)| n == name);
+            at.map(|at| entries.swap_remove(at).1)
+                .ok_or_else(|| format!("no {} in the archive", name))
+        };
+        let manifest: Manifest =
+            serde_json::from_slice(&take(MANIFEST)?).map_err(|e| format!("{}: {}", MANIFEST, e))?;
+        if manifest.format != FORMAT {
+            return Err(format!("not a backup (format {:?})", manifest.format));
+        }
+        if manifest.version > FORMAT_VERSION {
+            return Err(format!(
+                "format version {} is newer than this crispy-upload knows ({})",
+                manifest.version, FORMAT_VERSION
+            ));
+        }
+
+        let checked = |entry: &Entry, data: Vec<u8>| {
+            if data.len() != entry.length as usize || crc32::checksum(&data) != entry.crc32 {
+                return Err(format!("{} does not match the manifest", entry.file));
+            }
+            Ok(data)
+        };
+        let boot_data = checked(&manifest.boot_data, take(&manifest.boot_data.file)?)?;
+        if boot_data.len() != BOOT_DATA_SIZE {
+            return Err(format!(
+                "{} is {} bytes, not {}",
+                manifest.boot_data.file,
+                boot_data.len(),
+                BOOT_DATA_SIZE
+            ));
+        }
+        let mut banks = Vec::new();
+        for bank in &manifest.banks {
+            if bank.bank > 1 || bank.entry.length > manifest.layout.bank_size {
+                return Err(format!("{} is not a bank", bank.entry.file));
+            }
+            let data = checked(&bank.entry, take(&bank.entry.file)?)?;
+            banks.push((bank.clone(), data));
+        }
+        Ok(Self {
+            manifest,
+            boot_data,
+            banks,
+        })
+    }
+}
+
+/// The device's identity and bank layout.
+fn identify(transport: &mut Transport) -> Result<(Identity, Layout)> {
+    let mut device = Identity {
+        chip_id: None,
+        flash_uid: None,
+        bootloader_version: None,
+        jedec_id: None,
+    };
+    if let Some(Response::DeviceInfo {
+        bootloader_version,
+        chip_id,
+        flash_uid,
+        ..
+    }) = commands::optional_query(transport, &Command::GetDeviceInfo)?
+    {
+        device.chip_id = Some(chip_id);
+        // 0 when the flash chip did not answer
+        device.flash_uid = (flash_uid != 0).then(|| format!("{:016x}", flash_uid));
+        device.bootloader_version = Some(bootloader_version.to_string());
+    }
+    if let Some(Response::FlashInfo { jedec_id, .. }) =
+        commands::optional_query(transport, &Command::GetFlashInfo)?
+    {
+        device.jedec_id = Some(jedec_id);
+    }
+    let layout = Layout {
+        fw_a_addr: commands::bank_addr(transport, 0)?,
+        fw_b_addr: commands::bank_addr(transport, 1)?,
+        bank_size: commands::bank_size(transport)?,
+    };
+    Ok((device, layout))
+}
+
+/// Why the device is not the one `manifest` was taken from, if it is not.
+/// A device that cannot say who it is, or a backup that does not, counts.
+fn mismatches(manifest: &Manifest, device: &Identity, layout: &Layout) -> Vec<String> {
+    let saved = &manifest.device;
+    let mut problems = Vec::new();
+    match (saved.chip_id, device.chip_id) {
+        (Some(saved), Some(found)) if saved != found => problems.push(format!(
+            "chip ID 0x{:08x}, the backup has 0x{:08x}",
+            found, saved
+        )),
+        (Some(_), Some(_)) => {}
+        _ => problems.push("the chip ID cannot be compared".to_string()),
+    }
+    match (&saved.flash_uid, &device.flash_uid) {
+        (Some(saved), Some(found)) if saved != found => problems.push(format!(
+            "flash unique ID {}, the backup has {}",
+            found, saved
+        )),
+        (Some(_), Some(_)) => {}
+        _ => problems.push("the flash unique ID cannot be compared".to_string()),
+    }
+    if *layout != manifest.layout {
+        problems.push(format!(
+            "banks at 0x{:08x}/0x{:08x} of {} bytes, the backup has 0x{:08x}/0x{:08x} of {}",
+            layout.fw_a_addr,
+            layout.fw_b_addr,
+            layout.bank_size,
+            manifest.layout.fw_a_addr,
+            manifest.layout.fw_b_addr,
+            manifest.layout.bank_size
+        ));
+    }
+    problems
+}
+
+/// The raw BootData record and the device's check of both banks against it.
+fn boot_data(transport: &mut Transport) -> Result<(Vec<u8>, [BankVerify; 2])> {
+    match transport.send_recv(&Command::GetBootData)? {
+        Response::BootData { raw, banks } => Ok((raw.to_vec(), banks)),
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to report its BootData")
+        }
+        other => bail!(commands::unexpected(&other)),
+    }
+}
+
+/// Image size and version BootData records for `bank`.
+fn recorded(bd: &BootData, bank: u8) -> (u32, crispy_common::FwVersion) {
+    match bank {
+        0 => (bd.size_a, bd.version_a),
+        _ => (bd.size_b, bd.version_b),
+    }
+}
+
+fn read_bank(
+    transport: &mut Transport,
+    addr: u32,
+    length: u32,
+    chunk_size: u32,
+) -> Result<Vec<u8>> {
+    let pb = Progress::bytes("read", length)?;
+    let mut data = Vec::with_capacity(length as usize);
+    let read = transport.read_flash(addr, length, chunk_size, |offset, chunk| {
+        data.extend_from_slice(chunk);
+        pb.set_position((offset as usize + chunk.len()) as u64);
+        ControlFlow::Continue(())
+    });
+    if let Err(err) = read {
+        pb.abandon();
+        return Err(err);
+    }
+    pb.finish_and_clear();
+    Ok(data)
+}
+
+fn entry(file: &str, data: &[u8]) -> Entry {
+    Entry {
+        file: file.to_string(),
+        length: data.len() as u32,
+        crc32: crc32::checksum(data),
+    }
+}
+
+fn upload_options(version: Option<crispy_common::FwVersion>) -> UploadOptions {
+    UploadOptions {
+        version,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        verify: true,
+        input: InputOptions {
+            any_family: false,
+            max_gap: DEFAULT_MAX_GAP,
+            sha256: None,
+            bank_size: crispy_common::FW_BANK_SIZE,
+        },
+        // The images came from the device; a whole bank is no image at all
+        overrides: Overrides::unchecked(),
+        always: true,
+    }
+}
+
+/// A bank image on its way back to the device on `port`, apart from those
+/// of a restore on another device at the same time.
+fn temp_file(port: &str, name: &str) -> PathBuf {
+    let port: String = port
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let file = format!("crispy-restore-{}-{}-{}", std::process::id(), port, name);
+    std::env::temp_dir().join(file)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// The part of ustar (POSIX.1-1988) a backup needs: regular files with
+/// short names, nothing else.
+mod tar {
+    const BLOCK: usize = 512;
+    const NAME_LEN: usize = 100;
+
+    /// An archive of `entries`, each stamped `mtime` (seconds since the
+    /// Unix epoch).
+    pub fn write(entries: &[(&str, Vec<u8>)], mtime: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        for (name, data) in entries {
+            assert!(name.len() < NAME_LEN, "tar name too long: {}", name);
+            let mut header = [0u8; BLOCK];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            octal(&mut header[100..108], 0o644);
+            octal(&mut header[108..116], 0);
+            octal(&mut header[116..124], 0);
+            octal(&mut header[124..136], data.len() as u64);
+            octal(&mut header[136..148], mtime);
+            header[156] = b'0';
+            header[257..263].copy_from_slice(b"ustar\0");
+            header[263..265].copy_from_slice(b"00");
+            let sum = checksum(&header);
+            octal(&mut header[148..155], sum);
+            header[155] = b' ';
+
+            out.extend_from_slice(&header);
+            out.extend_from_slice(data);
+            out.resize(out.len().next_multiple_of(BLOCK), 0);
+        }
+        // Two zero blocks end the archive
+        out.resize(out.len() + 2 * BLOCK, 0);
+        out
+    }
+
+    /// The regular files in an archive, by name.
+    pub fn read(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+        let mut entries = Vec::new();
+        let mut at = 0;
+        loop {
+            let Some(header) = bytes.get(at..at + BLOCK) else {
+                return Err("truncated tar archive".to_string());
+            };
+            if header.iter().all(|&b| b == 0) {
+                return Ok(entries);
+            }
+            let field = |range: std::ops::Range<usize>| parse_octal(&header[range]);
+            if field(148..156) != Some(checksum(header)) {
+                return Err(format!("bad tar header checksum at offset {}", at));
+            }
+            let size = field(124..136).ok_or("bad tar entry size")? as usize;
+            let name_end = header[..NAME_LEN].iter().position(|&b| b == 0);
+            let name = String::from_utf8_lossy(&header[..name_end.unwrap_or(NAME_LEN)]);
+            let data_at = at + BLOCK;
+            let Some(data) = bytes.get(data_at..data_at + size) else {
+                return Err(format!("truncated tar entry {}", name));
+            };
+            // Directories, links and extended headers are not a backup's
+            if matches!(header[156], b'0' | 0) {
+                entries.push((name.into_owned(), data.to_vec()));
+            }
+            at = data_at + size.next_multiple_of(BLOCK);
+        }
+    }
+
+    /// Sum of the header bytes, the checksum field counted as spaces.
+    fn checksum(header: &[u8]) -> u64 {
+        header
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum()
+    }
+
+    /// `value` in octal, zero-padded, NUL-terminated.
+    fn octal(field: &mut [u8], value: u64) {
+        let digits = format!("{:0width$o}", value, width = field.len() - 1);
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+        field[digits.len()] = 0;
+    }
+
+    fn parse_octal(field: &[u8]) -> Option<u64> {
+        let text = std::str::from_utf8(field).ok()?;
+        let text = text.trim_matches(|c| c == '\0' || c == ' ');
+        u64::from_str_radix(text, 8).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkOptions;
+    use crate::test_device::TestDevice;
+    use crispy_common::flash::read_boot_data;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "crispy-backup-test-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    fn image(len: usize, seed: u8) -> Vec<u8> {
+        let mut data: Vec<u8> = (0..len)
+            .map(|i| (i as u8).wrapping_mul(31) ^ seed)
+            .collect();
+        // A vector table the bootloader would not mind
+        data[..4].fill(0);
+        data
+    }
+
+    #[test]
+    fn test_tar_round_trip() {
+        let entries = vec![
+            ("manifest.json", b"{}".to_vec()),
+            ("empty.bin", Vec::new()),
+            ("bank-a.bin", vec![0xA5; 1000]),
+        ];
+        let archive = tar::write(&entries, 1_700_000_000);
+        assert_eq!(archive.len() % 512, 0);
+        let read = tar::read(&archive).unwrap();
+        let names: Vec<_> = read.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["manifest.json", "empty.bin", "bank-a.bin"]);
+        assert_eq!(read[2].1, vec![0xA5; 1000]);
+
+        let mut corrupt = archive.clone();
+        corrupt[0] ^= 1;
+        assert!(tar::read(&corrupt).unwrap_err().contains("checksum"));
+        assert!(tar::read(&archive[..700]).is_err());
+    }
+
+    #[test]
+    fn test_backup_and_restore_round_trip() {
+        let (archive, fw) = (temp_path("round.tar"), temp_path("fw.bin"));
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let opts = upload_options(Some(crispy_common::FwVersion::new(1, 2, 3)));
+        fs::write(&fw, image(3000, 1)).unwrap();
+        commands::upload(&mut transport, &fw, Some(0), &opts).unwrap();
+        fs::write(&fw, image(5000, 2)).unwrap();
+        commands::upload(&mut transport, &fw, Some(1), &opts).unwrap();
+        commands::set_bank(&mut transport, 0).unwrap();
+        commands::set_boot_attempts(&mut transport, 9).unwrap();
+        let (before, _) = boot_data(&mut transport).unwrap();
+
+        backup(&mut transport, &archive, false, DEFAULT_CHUNK_SIZE).unwrap();
+        let saved = Backup::parse(&fs::read(&archive).unwrap()).unwrap();
+        assert_eq!(saved.boot_data, before);
+        let lengths: Vec<_> = saved.banks.iter().map(|(b, d)| (b.bank, d.len())).collect();
+        assert_eq!(lengths, [(0, 3000), (1, 5000)]);
+
+        // Experiment: overwrite a bank, change the settings
+        fs::write(&fw, image(1024, 3)).unwrap();
+        commands::upload(&mut transport, &fw, Some(1), &opts).unwrap();
+        commands::set_bank(&mut transport, 1).unwrap();
+
+        // The test device has no GetDeviceInfo, so nothing identifies it
+        let err = restore(&mut transport, &archive, Overrides::NONE).unwrap_err();
+        assert_eq!(err.downcast_ref::<Failure>().unwrap().code, "wrong_device");
+        let allowed = Overrides::NONE.with(Protection::OtherDevice, true);
+        restore(&mut transport, &archive, allowed).unwrap();
+
+        drop(transport);
+        let flash = device.finish();
+        let restored = read_boot_data(&flash).unwrap();
+        assert_eq!(restored, commands::decode_boot_data(&before).unwrap());
+        fs::remove_file(archive).unwrap();
+        fs::remove_file(fw).unwrap();
+    }
+
+    #[test]
+    fn test_backup_of_an_empty_device() {
+        let archive = temp_path("full.tar");
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+
+        backup(&mut transport, &archive, false, DEFAULT_CHUNK_SIZE).unwrap();
+        // Nothing recorded, nothing but the (erased) record saved
+        let saved = Backup::parse(&fs::read(&archive).unwrap()).unwrap();
+        assert!(saved.banks.is_empty());
+        restore(&mut transport, &archive, Overrides::force(true)).unwrap();
+
+        drop(transport);
+        device.finish();
+        fs::remove_file(archive).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_archive_is_refused() {
+        let manifest = Manifest {
+            format: FORMAT.to_string(),
+            version: FORMAT_VERSION,
+            created: "2026-01-02T03:04:05Z".to_string(),
+            tool: "0.0.0".to_string(),
+            port: "tcp:localhost:1".to_string(),
+            device: Identity {
+                chip_id: Some(0x1000_2927),
+                flash_uid: Some("e6614103e7452d2f".to_string()),
+                bootloader_version: None,
+                jedec_id: None,
+            },
+            layout: Layout {
+                fw_a_addr: crispy_common::FW_A_ADDR,
+                fw_b_addr: crispy_common::FW_B_ADDR,
+                bank_size: crispy_common::FW_BANK_SIZE,
+            },
+            full: false,
+            boot_data: entry(BOOT_DATA_FILE, &BootData::default_new().to_bytes()),
+            banks: vec![BankEntry {
+                bank: 0,
+                addr: crispy_common::FW_A_ADDR,
+                entry: entry(BANK_FILES[0], &image(256, 0)),
+                version: Some("1.0.0".to_string()),
+            }],
+        };
+        let archive = |bank: Vec<u8>| {
+            let entries = vec![
+                (MANIFEST, serde_json::to_vec(&manifest).unwrap()),
+                (BOOT_DATA_FILE, BootData::default_new().to_bytes().to_vec()),
+                (BANK_FILES[0], bank),
+            ];
+            tar::write(&entries, 0)
+        };
+        assert!(Backup::parse(&archive(image(256, 0))).is_ok());
+        let err = Backup::parse(&archive(image(256, 1))).err().unwrap();
+        assert_eq!(err, "bank-a.bin does not match the manifest");
+
+        // Identity checks
+        let same = manifest.device.clone();
+        assert!(mismatches(&manifest, &same, &manifest.layout).is_empty());
+        let other = Identity {
+            flash_uid: Some("0000000000000001".to_string()),
+            ..same.clone()
+        };
+        assert_eq!(
+            mismatches(&manifest, &other, &manifest.layout),
+            ["flash unique ID 0000000000000001, the backup has e6614103e7452d2f"]
+        );
+        let unknown = Identity {
+            chip_id: None,
+            ..same
+        };
+        assert_eq!(
+            mismatches(&manifest, &unknown, &manifest.layout),
+            ["the chip ID cannot be compared"]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `bench`: how fast the link to a device is, apart from flash.
+//!
+//! Latency is timed over `GetStatus` round trips, the cheapest command
+//! every bootloader answers, and given as min, median, p99 and max.
+//! Throughput is timed with `BenchData`, which the device acknowledges and
+//! drops without touching flash: the same bytes are sent in blocks of each
+//! size, one block per round trip, as an upload sends them.
+//!
+//! A bootloader too old to know `BenchData` answers it `BadCommand`; then
+//! only the latency is measured, and the `throughput` data is null.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use serde_json::json;
+
+use crispy_common::protocol::{AckStatus, Command, Response, MAX_DATA_BLOCK_SIZE};
+
+use crate::commands;
+use crate::output::{self, outln, Failure};
+use crate::transport::Transport;
+
+/// Round trips timed unless `--rounds` says otherwise.
+pub const DEFAULT_ROUNDS: u32 = 100;
+
+/// Block sizes timed unless `--block-sizes` says otherwise.
+pub const DEFAULT_BLOCK_SIZES: [u32; 4] = [64, 256, 512, MAX_DATA_BLOCK_SIZE as u32];
+
+/// Bytes sent per block size unless `--bytes` says otherwise.
+pub const DEFAULT_BYTES: u32 = 64 * 1024;
+
+/// What to measure.
+pub struct BenchOptions {
+    pub rounds: u32,
+    pub block_sizes: Vec<u32>,
+    /// Bytes sent for each block size.
+    pub bytes: u32,
+}
+
+/// Round-trip times, in microseconds.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Latency {
+    rounds: usize,
+    min_us: u64,
+    median_us: u64,
+    p99_us: u64,
+    max_us: u64,
+}
+
+impl Latency {
+    /// The statistics of `samples`, which must not be empty.
+    fn of(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let us = |d: Duration| d.as_micros() as u64;
+        Self {
+            rounds: samples.len(),
+            min_us: us(samples[0]),
+            median_us: us(percentile(&samples, 50)),
+            p99_us: us(percentile(&samples, 99)),
+            max_us: us(samples[samples.len() - 1]),
+        }
+    }
+}
+
+/// The `p`th percentile of `sorted` by nearest rank: the smallest sample
+/// with at least `p`% of them at or below it.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let rank = (sorted.len() * p).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// One row of the throughput table.
+#[derive(Debug, Serialize)]
+struct Throughput {
+    block_size: u32,
+    blocks: u32,
+    bytes: u32,
+    ms: u64,
+    bytes_per_sec: u64,
+}
+
+/// Refuse block sizes the device would not take, before sending anything.
+fn check_block_sizes(block_sizes: &[u32]) -> Result<()> {
+    if let Some(&size) = block_sizes
+        .iter()
+        .find(|&&size| size == 0 || size > MAX_DATA_BLOCK_SIZE as u32)
+    {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "--block-sizes {}: use sizes from 1 up to the {} bytes the device takes per block",
+                size, MAX_DATA_BLOCK_SIZE
+            )
+        )
+        .with("block_size", size));
+    }
+    Ok(())
+}
+
+/// Measure the link to the device open on `transport`.
+pub fn bench(transport: &mut Transport, opts: &BenchOptions) -> Result<()> {
+    check_block_sizes(&opts.block_sizes)?;
+
+    output::phase("latency");
+    let latency = latency(transport, opts.rounds)?;
+    outln!(
+        "Latency:     {} round trips: min {}, median {}, p99 {}, max {}",
+        latency.rounds,
+        ms(latency.min_us),
+        ms(latency.median_us),
+        ms(latency.p99_us),
+        ms(latency.max_us)
+    );
+
+    let probe = Command::BenchData { data: Vec::new() };
+    if commands::optional_query(transport, &probe)?.is_none() {
+        outln!("Throughput:  not measured; the bootloader does not support BenchData");
+        output::report(json!({ "latency": latency, "throughput": null }));
+        return Ok(());
+    }
+
+    output::phase("throughput");
+    outln!(
+        "Throughput:  {} bytes per block size, one block per round trip",
+        opts.bytes
+    );
+    outln!(
+        "  {:>6}  {:>6}  {:>9}  {:>11}",
+        "Block",
+        "Blocks",
+        "Time",
+        "Rate"
+    );
+    let mut rows = Vec::new();
+    for &block_size in &opts.block_sizes {
+        let row = throughput(transport, block_size, opts.bytes)?;
+        outln!(
+            "  {:>6}  {:>6}  {:>7.2} s  {:>6.1} KB/s",
+            row.block_size,
+            row.blocks,
+            row.ms as f64 / 1000.0,
+            row.bytes_per_sec as f64 / 1024.0
+        );
+        rows.push(row);
+    }
+    output::report(json!({ "latency": latency, "throughput": rows }));
+    Ok(())
+}
+
+/// Time `rounds` `GetStatus` round trips.
+fn latency(transport: &mut Transport, rounds: u32) -> Result<Latency> {
+    let mut samples = Vec::with_capacity(rounds as usize);
+    for _ in 0..rounds {
+        let started = Instant::now();
+        let response = transport.send_recv(&Command::GetStatus)?;
+        samples.push(started.elapsed());
+        if !matches!(response, Response::Status { .. }) {
+            bail!(commands::unexpected(&response));
+        }
+    }
+    Ok(Latency::of(samples))
+}
+
+/// Time `bytes` sent in `BenchData` blocks of `block_size`, the last one
+/// short when it does not divide them.
+fn throughput(transport: &mut Transport, block_size: u32, bytes: u32) -> Result<Throughput> {
+    let block: Vec<u8> = (0..block_size).map(|i| i as u8).collect();
+    let mut blocks = 0;
+    let mut remaining = bytes;
+    let started = Instant::now();
+    while remaining > 0 {
+        let len = remaining.min(block_size) as usize;
+        let command = Command::BenchData {
+            data: block[..len].to_vec(),
+        };
+        match transport.send_recv(&command)? {
+            Response::Ack(AckStatus::Ok) => {}
+            response => bail!(commands::unexpected(&response)),
+        }
+        remaining -= len as u32;
+        blocks += 1;
+    }
+    let elapsed = started.elapsed();
+    Ok(Throughput {
+        block_size,
+        blocks,
+        bytes,
+        ms: elapsed.as_millis() as u64,
+        bytes_per_sec: (bytes as f64 / elapsed.as_secs_f64().max(1e-6)) as u64,
+    })
+}
+
+/// Microseconds as "N.NN ms".
+fn ms(us: u64) -> String {
+    format!("{:.2} ms", us as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkOptions;
+    use crate::test_device::TestDevice;
+
+    fn us(list: &[u64]) -> Vec<Duration> {
+        list.iter().map(|&us| Duration::from_micros(us)).collect()
+    }
+
+    #[test]
+    fn test_latency_statistics() {
+        let samples: Vec<u64> = (1..=100).rev().collect();
+        assert_eq!(
+            Latency::of(us(&samples)),
+            Latency {
+                rounds: 100,
+                min_us: 1,
+                median_us: 50,
+                p99_us: 99,
+                max_us: 100,
+            }
+        );
+        // Few samples: p99 is the slowest
+        let latency = Latency::of(us(&[300, 100, 200]));
+        assert_eq!((latency.median_us, latency.p99_us), (200, 300));
+        let latency = Latency::of(us(&[7]));
+        assert_eq!(
+            (latency.min_us, latency.median_us, latency.max_us),
+            (7, 7, 7)
+        );
+    }
+
+    #[test]
+    fn test_bench() {
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let opts = BenchOptions {
+            rounds: 5,
+            block_sizes: vec![100, 1024],
+            bytes: 3000,
+        };
+        let (result, data) = output::collect(|| bench(&mut transport, &opts));
+        drop(transport);
+        let flash = device.finish();
+        result.unwrap();
+
+        assert_eq!(data["latency"]["rounds"], 5);
+        let rows = data["throughput"].as_array().unwrap();
+        let blocks: Vec<_> = rows.iter().map(|r| r["blocks"].clone()).collect();
+        assert_eq!(blocks, [json!(30), json!(3)]);
+        assert!(rows.iter().all(|r| r["bytes"] == 3000));
+        // Nothing reached flash
+        assert!(flash.contents().iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn test_block_sizes_checked_first() {
+        for size in [0, MAX_DATA_BLOCK_SIZE as u32 + 1] {
+            let err = check_block_sizes(&[64, size]).unwrap_err();
+            let failure = err.downcast_ref::<Failure>().unwrap();
+            assert_eq!(failure.code, "invalid_argument");
+        }
+        check_block_sizes(&DEFAULT_BLOCK_SIZES).unwrap();
+    }
+}
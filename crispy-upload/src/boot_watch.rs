@@ -0,0 +1,490 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Watching a device reboot, for `upload --reboot` and `update`.
+//!
+//! A reboot drops the bootloader's USB port, and what enumerates next tells
+//! how the new image did: the firmware's CDC means it booted, the bootloader
+//! again means it did not. With `--expect-confirm`, the firmware console's
+//! `status` command is then polled until it reports the boot confirmed from
+//! the bank that was just written. `update` goes the other way first, typing
+//! `bootload` into the firmware console and waiting for the bootloader.
+//!
+//! Both builds use fixed USB serial numbers, so the device is recognised as
+//! a matching port that was not there before the reboot.
+//!
+//! Behind a TCP bridge there is no port list to watch, only one address
+//! that drops or refuses connections while the device re-enumerates. There
+//! the device is told apart by what answers: the bootloader takes a
+//! protocol command, the firmware console prints its boot status. Each
+//! probe opens a fresh connection, so a bridge that hung up is simply
+//! reconnected.
+
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crispy_common::protocol::{Command, Response, BOOTLOADER_PID, FIRMWARE_PID, USB_VID};
+
+use crate::link::{self, Link, LinkOptions};
+use crate::transport::{self, DeviceKind, PortListing, Transport};
+
+/// How often the port list and the firmware console are polled.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long the firmware gets to drop off the bus before `bootload` is sent
+/// again.
+const BOOTLOAD_RETRY: Duration = Duration::from_secs(2);
+/// How long to wait for the protocol port once one bootloader port is up;
+/// a composite device's ports do not always show up together.
+const PORT_SETTLE: Duration = Duration::from_secs(1);
+/// How long a probe through a TCP bridge waits for the bootloader.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long after a reboot command before probing a TCP bridge, so the
+/// probe does not reach the old image before it resets.
+const RESET_SETTLE: Duration = Duration::from_secs(1);
+
+/// A USB vendor and product ID, written `2e8a:000b`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UsbId {
+    pub vid: u16,
+    pub pid: u16,
+}
+
+impl UsbId {
+    pub const FIRMWARE: Self = Self {
+        vid: USB_VID,
+        pid: FIRMWARE_PID,
+    };
+    pub const BOOTLOADER: Self = Self {
+        vid: USB_VID,
+        pid: BOOTLOADER_PID,
+    };
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (vid, pid) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected VID:PID in hex, got {:?}", s))?;
+        let hex = |v: &str| u16::from_str_radix(v, 16).map_err(|e| format!("{}: {}", v, e));
+        Ok(Self {
+            vid: hex(vid)?,
+            pid: hex(pid)?,
+        })
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)
+    }
+}
+
+/// What to wait for after the reboot.
+pub struct WatchOptions {
+    pub firmware: UsbId,
+    pub bootloader: UsbId,
+    /// For the old port to go and a new one to appear.
+    pub timeout: Duration,
+    /// Poll the firmware console for a confirmed boot.
+    pub expect_confirm: bool,
+    pub confirm_timeout: Duration,
+}
+
+/// The port the device re-enumerated on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reappeared {
+    Firmware(String),
+    Bootloader(String),
+}
+
+fn usb_id(p: &PortListing) -> Option<UsbId> {
+    p.usb.as_ref().map(|u| UsbId {
+        vid: u.vid,
+        pid: u.pid,
+    })
+}
+
+/// Every port name and its USB ID, if it has one.
+fn snapshot() -> Result<Vec<(String, Option<UsbId>)>> {
+    Ok(transport::list_ports()?
+        .iter()
+        .map(|p| (p.name.clone(), usb_id(p)))
+        .collect())
+}
+
+/// The USB ID of `port`, if it is a USB port.
+pub fn port_id(port: &str) -> Result<Option<UsbId>> {
+    Ok(snapshot()?
+        .into_iter()
+        .find(|(name, _)| name == port)
+        .and_then(|(_, id)| id))
+}
+
+fn is_present(port: &str) -> Result<bool> {
+    Ok(snapshot()?.iter().any(|(name, _)| name == port))
+}
+
+/// Wait until `port` is gone, or `deadline`.
+fn wait_gone(port: &str, deadline: Instant) -> Result<bool> {
+    while is_present(port)? {
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    Ok(true)
+}
+
+/// Port names other than `port`, taken before the reboot.
+pub fn ports_besides(port: &str) -> Result<Vec<String>> {
+    Ok(snapshot()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name != port)
+        .collect())
+}
+
+/// The first port matching `opts` that is not in `before`; the firmware
+/// wins if both are there.
+fn find_new(
+    ports: &[(String, Option<UsbId>)],
+    before: &[String],
+    opts: &WatchOptions,
+) -> Option<Reappeared> {
+    let new = |id: UsbId| {
+        ports
+            .iter()
+            .find(|(name, port_id)| *port_id == Some(id) && !before.contains(name))
+            .map(|(name, _)| name.clone())
+    };
+    new(opts.firmware)
+        .map(Reappeared::Firmware)
+        .or_else(|| new(opts.bootloader).map(Reappeared::Bootloader))
+}
+
+/// Wait for `port` to disappear, then for the device to come back.
+pub fn wait_for_reboot(port: &str, before: &[String], opts: &WatchOptions) -> Result<Reappeared> {
+    let deadline = Instant::now() + opts.timeout;
+
+    if !wait_gone(port, deadline)? {
+        bail!(
+            "{} did not go away within {:?} of the reboot",
+            port,
+            opts.timeout
+        );
+    }
+
+    loop {
+        if let Some(found) = find_new(&snapshot()?, before, opts) {
+            return Ok(found);
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Neither firmware ({}) nor bootloader ({}) enumerated within {:?}",
+                opts.firmware,
+                opts.bootloader,
+                opts.timeout
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// What answers on `port`, a TCP bridge: the bootloader if it takes a
+/// protocol command, the firmware if its console reports a boot status, or
+/// nothing yet (the bridge refused or dropped the connection, or no answer).
+pub fn identify(port: &str, link: &LinkOptions) -> Option<DeviceKind> {
+    let probe = LinkOptions {
+        read_timeout: PROBE_TIMEOUT,
+        wait: Duration::ZERO,
+        ..*link
+    };
+    // The firmware console echoes the command back, which is no Status
+    let answer = Transport::open(port, &probe).and_then(|mut t| t.send_recv(&Command::GetStatus));
+    if let Ok(Response::Status { .. }) = answer {
+        return Some(DeviceKind::Bootloader);
+    }
+
+    console_answers(port, &probe).then_some(DeviceKind::Firmware)
+}
+
+/// Whether a firmware console on `port` reports its boot status.
+pub fn console_answers(port: &str, link: &LinkOptions) -> bool {
+    open_console(port, link)
+        .ok()
+        .and_then(|mut console| query_status(console.as_mut()).ok().flatten())
+        .is_some()
+}
+
+/// Wait for the device behind the TCP bridge `port` to come back after a
+/// reboot, reconnecting until something answers.
+pub fn wait_for_reboot_tcp(
+    port: &str,
+    link: &LinkOptions,
+    opts: &WatchOptions,
+) -> Result<Reappeared> {
+    let deadline = Instant::now() + opts.timeout;
+    thread::sleep(RESET_SETTLE);
+    loop {
+        match identify(port, link) {
+            Some(DeviceKind::Firmware) => return Ok(Reappeared::Firmware(port.to_string())),
+            Some(DeviceKind::Bootloader) => return Ok(Reappeared::Bootloader(port.to_string())),
+            None if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            None => bail!(
+                "Neither the firmware nor the bootloader answered on {} within {:?}",
+                port,
+                opts.timeout
+            ),
+        }
+    }
+}
+
+/// Type `bootload` into the firmware console behind the TCP bridge `port`
+/// until the bootloader answers there, for at most `timeout`.
+pub fn request_bootloader_tcp(port: &str, link: &LinkOptions, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut sent: Option<Instant> = None;
+    loop {
+        match identify(port, link) {
+            Some(DeviceKind::Bootloader) => return Ok(()),
+            Some(DeviceKind::Firmware) if sent.is_none_or(|at| at.elapsed() >= BOOTLOAD_RETRY) => {
+                let mut console = open_console(port, link)?;
+                console.write_all(b"\rbootload\r")?;
+                console.flush()?;
+                sent = Some(Instant::now());
+            }
+            _ => {}
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "The firmware behind {} did not reboot into the bootloader within {:?}",
+                port,
+                timeout
+            );
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Type `bootload` into the firmware console on `port` until the firmware
+/// drops off the bus, for at most `timeout`.
+pub fn request_bootloader(port: &str, link: &LinkOptions, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        // The port closes under us once the firmware reboots
+        let mut console = open_console(port, link)?;
+        console.write_all(b"bootload\r")?;
+        console.flush()?;
+        drop(console);
+
+        if wait_gone(port, deadline.min(Instant::now() + BOOTLOAD_RETRY))? {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "The firmware on {} did not reboot into the bootloader within {:?}",
+                port,
+                timeout
+            );
+        }
+    }
+}
+
+/// Wait for the bootloader to enumerate on a port that is not in `before`,
+/// returning its protocol port.
+pub fn wait_for_bootloader(before: &[String], opts: &WatchOptions) -> Result<String> {
+    let deadline = Instant::now() + opts.timeout;
+    let mut first_seen = None;
+    loop {
+        let ports: Vec<_> = transport::list_ports()?
+            .into_iter()
+            .filter(|p| usb_id(p) == Some(opts.bootloader) && !before.contains(&p.name))
+            .collect();
+        if let Some(p) = ports.iter().find(|p| transport::is_protocol_port(p)) {
+            return Ok(p.name.clone());
+        }
+
+        let settled = !ports.is_empty()
+            && first_seen.get_or_insert_with(Instant::now).elapsed() >= PORT_SETTLE;
+        if settled || Instant::now() >= deadline {
+            return match ports.as_slice() {
+                [p] => Ok(p.name.clone()),
+                [] => bail!(
+                    "The bootloader ({}) did not enumerate within {:?}",
+                    opts.bootloader,
+                    opts.timeout
+                ),
+                _ => bail!(
+                    "Several new bootloader ports ({}); pass --port with the bootloader's",
+                    ports
+                        .iter()
+                        .map(|p| p.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Open the firmware console on `port`; it only talks to a terminal that
+/// asserts DTR, which opening a serial link does.
+fn open_console(port: &str, link: &LinkOptions) -> Result<Box<dyn Link>> {
+    let opts = LinkOptions {
+        read_timeout: POLL_INTERVAL,
+        ..*link
+    };
+    link::open(port, &opts).with_context(|| format!("Failed to open firmware console {}", port))
+}
+
+/// Boot state as printed by the firmware console's `status` command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsoleStatus {
+    pub bank: u8,
+    pub confirmed: bool,
+}
+
+/// Find the `Bank:` and `Confirmed:` lines in console output.
+fn parse_console_status(text: &str) -> Option<ConsoleStatus> {
+    let field = |name: &str| {
+        text.lines()
+            .find_map(|line| line.trim().strip_prefix(name))
+            .and_then(|rest| rest.split_whitespace().next())
+            .and_then(|value| value.parse::<u8>().ok())
+    };
+    Some(ConsoleStatus {
+        bank: field("Bank:")?,
+        confirmed: field("Confirmed:")? != 0,
+    })
+}
+
+/// Ask the firmware console for its boot status, giving it a few polls to
+/// answer.
+fn query_status(console: &mut dyn Link) -> Result<Option<ConsoleStatus>> {
+    // The leading return ends whatever else is on the console's line
+    console.write_all(b"\rstatus\r")?;
+
+    let mut text = String::new();
+    let reply_by = Instant::now() + 4 * POLL_INTERVAL;
+    let mut buf = [0u8; 256];
+    loop {
+        match console.read(&mut buf) {
+            Ok(n) => text.push_str(&String::from_utf8_lossy(&buf[..n])),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e).context("Failed to read the firmware console"),
+        }
+        if let Some(status) = parse_console_status(&text) {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= reply_by {
+            return Ok(None);
+        }
+    }
+}
+
+/// Poll the firmware console on `port` until it reports a confirmed boot,
+/// failing if it runs from a bank other than `bank`.
+pub fn wait_for_confirm(port: &str, link: &LinkOptions, bank: u8, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    let mut console = open_console(port, link)?;
+
+    let mut last = None;
+    while Instant::now() < deadline {
+        let status = query_status(console.as_mut())?;
+        match status {
+            Some(s) if s.bank != bank => bail!(
+                "Firmware is running from bank {}, not the bank {} just written (rolled back?)",
+                s.bank,
+                bank
+            ),
+            Some(s) if s.confirmed => return Ok(()),
+            _ => last = status,
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    Err(match last {
+        Some(_) => anyhow!("Firmware did not confirm its boot within {:?}", timeout),
+        None => anyhow!("No boot status from the firmware console on {}", port),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> WatchOptions {
+        WatchOptions {
+            firmware: UsbId::FIRMWARE,
+            bootloader: UsbId::BOOTLOADER,
+            timeout: Duration::from_secs(1),
+            expect_confirm: false,
+            confirm_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_usb_id_round_trip() {
+        let id: UsbId = "2e8a:000B".parse().unwrap();
+        assert_eq!(id, UsbId::FIRMWARE);
+        assert_eq!(id.to_string(), "2e8a:000b");
+        assert!("2e8a".parse::<UsbId>().is_err());
+        assert!("2e8a:xyz".parse::<UsbId>().is_err());
+    }
+
+    #[test]
+    fn test_find_new_ignores_existing_ports() {
+        let before = ["/dev/ttyACM1".to_string()];
+        let ports = [
+            ("/dev/ttyACM1".to_string(), Some(UsbId::FIRMWARE)),
+            ("/dev/ttyS0".to_string(), None),
+        ];
+        assert_eq!(find_new(&ports, &before, &opts()), None);
+
+        let ports = [
+            ("/dev/ttyACM1".to_string(), Some(UsbId::FIRMWARE)),
+            ("/dev/ttyACM0".to_string(), Some(UsbId::BOOTLOADER)),
+        ];
+        assert_eq!(
+            find_new(&ports, &before, &opts()),
+            Some(Reappeared::Bootloader("/dev/ttyACM0".to_string()))
+        );
+
+        let ports = [
+            ("/dev/ttyACM0".to_string(), Some(UsbId::BOOTLOADER)),
+            ("/dev/ttyACM2".to_string(), Some(UsbId::FIRMWARE)),
+        ];
+        assert_eq!(
+            find_new(&ports, &before, &opts()),
+            Some(Reappeared::Firmware("/dev/ttyACM2".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_console_status() {
+        let text = "status\r\n\r\nBoot status:\r\n  Bank: 1 (B)\r\n  Confirmed: 1\r\n  \
+                    Attempts: 0\r\n  Version A: 0.0.1\r\n";
+        assert_eq!(
+            parse_console_status(text),
+            Some(ConsoleStatus {
+                bank: 1,
+                confirmed: true
+            })
+        );
+        let partial = "Boot status:\r\n  Bank: 0 (A)\r\n  Confirm";
+        assert_eq!(parse_console_status(partial), None);
+        let unconfirmed = "  Bank: 0 (A)\r\n  Confirmed: 0\r\n";
+        assert_eq!(
+            parse_console_status(unconfirmed).map(|s| s.confirmed),
+            Some(false)
+        );
+    }
+}
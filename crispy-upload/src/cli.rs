@@ -3,48 +3,374 @@
 
 //! Command-line interface definitions.
 
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
 
-use crate::commands;
-use crate::transport::Transport;
+use crispy_common::{crc32, FwVersion, FW_BANK_SIZE};
+
+use crate::backup;
+use crate::bench::{self, BenchOptions};
+use crate::boot_watch::{UsbId, WatchOptions};
+use crate::commands::{self, InputOptions, UploadOptions};
+use crate::config;
+use crate::factory::{self, FileRecord, Plan, Stage, StageRecord};
+use crate::health;
+use crate::history::{self, Record};
+use crate::link::LinkOptions;
+use crate::live::{self, LiveOptions};
+use crate::manifest::{self, Manifest};
+use crate::monitor;
+use crate::output::{self, outln, Failure, ProgressMode};
+use crate::overrides::{Overrides, Protection};
+use crate::parallel;
+use crate::probe;
+use crate::script::{self, Line};
+use crate::selftest::{self, SelftestOptions};
+use crate::source::{self, Sha256Digest};
+use crate::trace;
+use crate::transport::{self, RetryPolicy, Transport};
 
 /// Command-line arguments.
 #[derive(Parser)]
 #[command(name = "crispy-upload")]
 #[command(about = "Firmware upload tool for crispy-bootloader")]
 pub struct Cli {
-    /// Serial port (e.g., /dev/ttyACM0)
+    /// Serial port (e.g., /dev/ttyACM0) or TCP bridge (tcp://host:port);
+    /// found by USB IDs if omitted. Repeat to run on several devices at once
     #[arg(short, long)]
-    pub port: String,
+    pub port: Vec<String>,
+
+    /// Pick the device with this USB serial number when several are attached
+    #[arg(short, long, conflicts_with = "port")]
+    pub serial: Option<String>,
+
+    /// Run on every attached bootloader at once (with `update`, every
+    /// crispy device)
+    #[arg(long, conflicts_with_all = ["port", "serial"])]
+    pub all: bool,
+
+    /// History file for operations that change a device
+    /// [default: $CRISPY_HISTORY, else ~/.local/share/crispy/history.jsonl]
+    #[arg(long, value_name = "PATH")]
+    pub history_file: Option<PathBuf>,
+
+    /// Do not record this operation in the history
+    #[arg(long, conflicts_with = "history_file")]
+    pub no_history: bool,
+
+    /// Print newline-delimited JSON events on stdout instead of text
+    /// (see the `output` module for the schema)
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Times to re-send a command that timed out or failed in transit
+    #[arg(long, value_name = "N", default_value_t = RetryPolicy::DEFAULT.retries)]
+    pub retries: u32,
+
+    /// Milliseconds before the first retry, doubling after each
+    #[arg(long, value_name = "MS", default_value_t = RetryPolicy::DEFAULT.backoff.as_millis() as u64)]
+    pub retry_backoff: u64,
+
+    /// Milliseconds to wait for a TCP bridge to accept the connection
+    #[arg(long, value_name = "MS", default_value_t = LinkOptions::DEFAULT.connect_timeout.as_millis() as u64)]
+    pub connect_timeout: u64,
+
+    /// Milliseconds to wait for the answer to most commands, and between
+    /// progress reports during a long operation
+    #[arg(long, value_name = "MS", default_value_t = LinkOptions::DEFAULT.read_timeout.as_millis() as u64)]
+    pub read_timeout: u64,
+
+    /// Milliseconds to wait for every answer, instead of --read-timeout and
+    /// the longer defaults for operations that erase flash (60000: starting
+    /// an upload, wipe, clone) or go through whole banks (30000: finishing
+    /// an upload, BootData, blank-check)
+    #[arg(long, value_name = "MS", conflicts_with = "read_timeout")]
+    pub timeout: Option<u64>,
+
+    /// Log every protocol frame (time, direction, name, hex) to stderr
+    #[arg(long, global = true)]
+    pub trace: bool,
+
+    /// Write the --trace log to this file instead of stderr
+    #[arg(long, value_name = "PATH", global = true)]
+    pub trace_file: Option<PathBuf>,
+
+    /// Wait up to SECS (30 without a value) for the device to show up
+    /// before failing, e.g. right after resetting the board
+    #[arg(long, value_name = "SECS", global = true, num_args = 0..=1, require_equals = true, default_missing_value = "30")]
+    pub wait: Option<u64>,
+
+    /// Go ahead where any check says not to, as every --allow-* flag and
+    /// --skip-checks do together; each override is reported
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Change the device on a --port that does not answer as the bootloader
+    #[arg(long, global = true)]
+    pub allow_wrong_port: bool,
+
+    /// How to show progress on stderr
+    #[arg(long, value_name = "MODE", global = true, default_value = "auto")]
+    pub progress: ProgressMode,
+
+    /// Where the defaults came from, filled in from the configuration files
+    #[arg(skip)]
+    pub config: config::Effective,
 
     #[command(subcommand)]
     pub command: Commands,
 }
 
 /// Available subcommands.
-#[derive(Subcommand)]
+#[derive(Clone, Subcommand)]
 pub enum Commands {
     /// Get bootloader status
-    Status,
+    Status {
+        #[command(flatten)]
+        live: LiveArgs,
+    },
+
+    /// Report BootData, bank checks, bootloader version and chip identity
+    Info {
+        #[command(flatten)]
+        live: LiveArgs,
+    },
 
     /// Upload firmware to a bank
     Upload {
-        /// Firmware binary file
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
         #[arg(value_name = "FILE")]
         file: PathBuf,
 
         /// Target bank (0 = A, 1 = B)
+        /// [default: the bank that is not active]
+        #[arg(short, long)]
+        bank: Option<u8>,
+
+        /// Firmware version, MAJOR.MINOR.PATCH or a plain integer
+        /// [default: recorded in the file, else 0.0.1]
+        #[arg(short, long)]
+        version: Option<FwVersion>,
+
+        /// Read the bank back afterwards and compare it byte for byte
+        #[arg(long)]
+        verify: bool,
+
+        /// Bytes per data block and per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Leave out the pre-flight checks of the image (vector table, RAM
+        /// copy size), for unusual images
+        #[arg(long)]
+        skip_checks: bool,
+
+        /// Overwrite the active bank even if its firmware is confirmed
+        #[arg(long)]
+        allow_active_bank: bool,
+
+        /// Upload even when the active bank already holds this image; by
+        /// default the upload is skipped, with nothing erased
+        #[arg(long)]
+        always: bool,
+
+        /// Reboot after uploading and fail unless the firmware comes back
+        #[arg(long)]
+        reboot: bool,
+
+        #[command(flatten)]
+        watch: WatchArgs,
+    },
+
+    /// Upload to both banks over one connection, the inactive bank first,
+    /// then make one of them active
+    UploadBoth {
+        /// Firmware for bank A: raw binary, UF2, ELF or Intel HEX; `-` reads
+        /// stdin, an http(s) URL downloads it
+        #[arg(value_name = "FILE_A")]
+        file_a: PathBuf,
+
+        /// Firmware for bank B [default: FILE_A]
+        #[arg(value_name = "FILE_B")]
+        file_b: Option<PathBuf>,
+
+        /// Firmware version of both, MAJOR.MINOR.PATCH or a plain integer
+        /// [default: recorded in each file, else 0.0.1]
+        #[arg(short, long)]
+        version: Option<FwVersion>,
+
+        /// Read each bank back afterwards and compare it byte for byte
+        #[arg(long)]
+        verify: bool,
+
+        /// Bytes per data block and per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Leave out the pre-flight checks of the images (vector table, RAM
+        /// copy size), for unusual images
+        #[arg(long)]
+        skip_checks: bool,
+
+        /// Bank to leave active at the end (0 = A, 1 = B)
+        #[arg(long, default_value = "0", conflicts_with = "no_activate")]
+        active: u8,
+
+        /// Leave the bank that was active before active
+        #[arg(long)]
+        no_activate: bool,
+    },
+
+    /// Enter the bootloader, upload to the inactive bank and reboot into the
+    /// new firmware, starting from either the firmware or the bootloader
+    Update {
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Firmware version, MAJOR.MINOR.PATCH or a plain integer
+        /// [default: recorded in the file, else 0.0.1]
+        #[arg(short, long)]
+        version: Option<FwVersion>,
+
+        /// Read the bank back before rebooting and compare it byte for byte
+        #[arg(long)]
+        verify: bool,
+
+        /// Bytes per data block and per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        #[command(flatten)]
+        input: InputArgs,
+
+        /// Leave out the pre-flight checks of the image (vector table, RAM
+        /// copy size), for unusual images
+        #[arg(long)]
+        skip_checks: bool,
+
+        /// Upload even when the active bank already holds this image
+        #[arg(long)]
+        always: bool,
+
+        #[command(flatten)]
+        watch: WatchArgs,
+    },
+
+    /// Save a bank's firmware to a file
+    Download {
+        /// Output file
+        #[arg(value_name = "FILE")]
+        output: PathBuf,
+
+        /// Bank to read (0 = A, 1 = B)
         #[arg(short, long, default_value = "0")]
         bank: u8,
 
-        /// Firmware version number
-        #[arg(short, long, default_value = "1")]
-        version: u32,
+        /// Bytes to read [default: the image size recorded in BootData]
+        #[arg(short, long)]
+        length: Option<u32>,
+
+        /// Bytes per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+    },
+
+    /// Save both banks and BootData to an archive, to put the device back
+    /// with `restore`
+    Backup {
+        /// Archive to write (ustar)
+        #[arg(value_name = "FILE")]
+        output: PathBuf,
+
+        /// Save whole banks, not just the images BootData records
+        #[arg(long)]
+        full: bool,
+
+        /// Bytes per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+    },
+
+    /// Put back the banks and BootData saved by `backup`, and verify them;
+    /// refuses another device unless --allow-other-device
+    Restore {
+        /// Archive written by `backup`
+        #[arg(value_name = "FILE")]
+        archive: PathBuf,
+
+        /// Restore a backup taken from another device
+        #[arg(long)]
+        allow_other_device: bool,
     },
 
+    /// Compare a firmware file with a bank on the device; exits 0 if they
+    /// match, 1 if they differ, 2 on error
+    Diff {
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Bank to compare with (0 = A, 1 = B)
+        #[arg(short, long, default_value = "0")]
+        bank: u8,
+
+        /// Stop at the first difference
+        #[arg(long)]
+        first_only: bool,
+
+        /// Bytes per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Check that a bank holds a firmware file; exits 0 if it does, 1 if it
+    /// differs, 2 on error, 3 if the bank holds no firmware
+    Verify {
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Bank to check (0 = A, 1 = B)
+        #[arg(short, long, default_value = "0")]
+        bank: u8,
+
+        /// Compare size and CRC32 with what the device computes, instead of
+        /// reading the bank back
+        #[arg(long)]
+        quick: bool,
+
+        /// Bytes per read (a multiple of 256, up to 1024)
+        #[arg(long, default_value_t = commands::DEFAULT_CHUNK_SIZE)]
+        chunk_size: u32,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Check that the device is consistent: each bank against its CRC32,
+    /// recomputed on the device, the active bank, the boot attempts and
+    /// BootData. Exits 1 for a warning, 2 for a failed check, 3 when the
+    /// checks could not be made
+    VerifyAll,
+
     /// Set the active bank for the next boot (without uploading new firmware)
     SetBank {
         /// Target bank (0 = A, 1 = B)
@@ -52,26 +378,1779 @@ pub enum Commands {
         bank: u8,
     },
 
+    /// Copy one bank's firmware and metadata to the other bank on the device
+    Clone {
+        /// Source bank (0 = A, 1 = B)
+        #[arg(long)]
+        from: u8,
+
+        /// Destination bank, erased first (0 = A, 1 = B)
+        #[arg(long)]
+        to: u8,
+    },
+
     /// Wipe all firmware banks and reset boot data
     Wipe,
 
+    /// Show the detected flash part and the bootloader's bank layout
+    FlashInfo,
+
+    /// Measure the link: round-trip latency over GetStatus, and throughput
+    /// for several block sizes with data the device drops unwritten
+    Bench {
+        /// Round trips to time for the latency
+        #[arg(long, value_name = "N", default_value_t = bench::DEFAULT_ROUNDS, value_parser = clap::value_parser!(u32).range(1..))]
+        rounds: u32,
+
+        /// Block sizes to time the throughput with, comma-separated
+        #[arg(long, value_name = "BYTES", value_delimiter = ',', default_values_t = bench::DEFAULT_BLOCK_SIZES)]
+        block_sizes: Vec<u32>,
+
+        /// Bytes to send for each block size
+        #[arg(long, value_name = "BYTES", default_value_t = bench::DEFAULT_BYTES, value_parser = clap::value_parser!(u32).range(1..))]
+        bytes: u32,
+    },
+
+    /// Check that a bank or flash range reads back fully erased (all 0xFF)
+    ///
+    /// Exits non-zero when it is not blank.
+    BlankCheck {
+        /// Bank to check (0 = A, 1 = B)
+        #[arg(
+            short,
+            long,
+            required_unless_present = "range",
+            conflicts_with = "range"
+        )]
+        bank: Option<u8>,
+        /// Flash range to check instead: absolute address and length,
+        /// decimal or 0x hex
+        #[arg(long, num_args = 2, value_names = ["ADDR", "LEN"], value_parser = parse_number)]
+        range: Option<Vec<u32>>,
+    },
+
+    /// Set how many unconfirmed boots are allowed before rolling back
+    SetBootAttempts {
+        /// Attempt limit (1-32), or 0 to restore the default of 3
+        #[arg(value_name = "N")]
+        max_attempts: u8,
+    },
+
+    /// Change the version recorded for a bank, without reflashing it
+    SetVersion {
+        /// Bank to change (0 = A, 1 = B)
+        #[arg(short, long)]
+        bank: u8,
+
+        /// New version, MAJOR.MINOR.PATCH or a plain integer
+        #[arg(value_name = "VERSION")]
+        version: FwVersion,
+
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Store the unit's board ID, hardware revision and serial number in
+    /// its provisioning record, and read it back; refuses to replace
+    /// another record unless --reprovision
+    Provision {
+        /// Board ID
+        #[arg(long, value_name = "ID")]
+        board: u16,
+
+        /// Hardware revision of the board
+        #[arg(long, value_name = "REV", default_value_t = 0)]
+        revision: u8,
+
+        /// Serial number or batch, up to 16 printable ASCII characters
+        #[arg(long, value_name = "SN")]
+        serial: String,
+
+        /// Replace the record of a unit provisioned before
+        #[arg(long)]
+        reprovision: bool,
+    },
+
     /// Reboot the device
-    Reboot,
+    Reboot {
+        /// Reconnect once the bootloader is back, for a device that stays in
+        /// update mode across a reset (no bootable firmware, update pin held)
+        #[arg(long)]
+        stay: bool,
+    },
+
+    /// Open a terminal on the firmware console (Ctrl-] to quit, ~? for
+    /// escapes)
+    Monitor {
+        /// When the port goes away, wait for the firmware to come back and
+        /// carry on there
+        #[arg(long)]
+        reattach: bool,
+    },
+
+    /// Check a firmware file before uploading it (no device needed)
+    Inspect {
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Print the size and CRC32 of firmware files exactly as the bootloader
+    /// computes them (no device needed)
+    Checksum {
+        /// Firmware files: raw binary, UF2, ELF or Intel HEX, as uploaded;
+        /// `-` reads stdin, an http(s) URL downloads it
+        #[arg(value_name = "FILE", required = true)]
+        files: Vec<PathBuf>,
+
+        /// Start this many bytes into the image
+        #[arg(long, value_name = "N", default_value_t = 0, value_parser = parse_number)]
+        offset: u32,
+
+        /// Only this many bytes [default: to the end]
+        #[arg(long, value_name = "M", value_parser = parse_number)]
+        length: Option<u32>,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Wrap a firmware file in a .crispy container with an image header
+    /// (no device needed)
+    Pack {
+        /// Firmware file: raw binary, UF2, ELF or Intel HEX; `-` reads stdin,
+        /// an http(s) URL downloads it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Container to write [default: FILE with the .crispy extension]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Firmware version, MAJOR.MINOR.PATCH or a plain integer
+        /// [default: from the file]
+        #[arg(short, long)]
+        version: Option<FwVersion>,
+
+        /// Board ID to record in the header
+        #[arg(long, value_name = "ID", default_value_t = 0)]
+        board: u16,
+
+        #[command(flatten)]
+        input: InputArgs,
+    },
+
+    /// Show the operations recorded in the history (no device needed)
+    History {
+        /// Only operations on the device with this USB serial number
+        #[arg(long, value_name = "SN")]
+        device: Option<String>,
+    },
+
+    /// List serial ports of crispy devices (bootloader and sample firmware)
+    ListPorts {
+        /// List every serial port, not only crispy devices
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Tell whether a port is the bootloader, the sample firmware or
+    /// something else [default: every USB serial port]
+    Probe,
+
+    /// Convert BootData records between flash bytes and JSON (no device needed)
+    BootData {
+        #[command(subcommand)]
+        action: BootDataAction,
+    },
+
+    /// Provision a blank board in update mode in one go: wipe, upload,
+    /// set the bank, verify and reboot, with a pass/fail record at the end
+    Factory(FactoryArgs),
+
+    /// Exercise the whole stack on a dev board: upload a random image to the
+    /// inactive bank, reboot into it, check the rollback, then restore the
+    /// banks, with a pass/fail summary of each phase
+    Selftest {
+        /// Bytes of random image
+        #[arg(long, value_name = "BYTES", default_value_t = selftest::DEFAULT_SIZE, value_parser = clap::value_parser!(u32).range(256..))]
+        size: u32,
+
+        /// Seed of the random image, to repeat a run [default: from the clock]
+        #[arg(long, value_name = "N")]
+        seed: Option<u64>,
+
+        /// Seconds to wait for the device to come back after the reboot
+        #[arg(long, value_name = "SECS", default_value_t = 10)]
+        reboot_timeout: u64,
+
+        /// USB VID:PID of the firmware's CDC port
+        #[arg(long, value_name = "VID:PID", default_value_t = UsbId::FIRMWARE)]
+        firmware_id: UsbId,
+
+        /// USB VID:PID of the bootloader's CDC port
+        #[arg(long, value_name = "VID:PID", default_value_t = UsbId::BOOTLOADER)]
+        bootloader_id: UsbId,
+    },
+
+    /// Flash the bootloader ELF onto a blank board over SWD with probe-rs,
+    /// verify it and reset the target (see the `swd` module)
+    #[cfg(feature = "probe-rs")]
+    FlashBootloader {
+        /// Bootloader ELF, as `make bootloader` builds it
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Debug probe, VID:PID[:SERIAL] as `probe-rs list` shows it
+        /// [default: the only one attached]
+        #[arg(long, value_name = "SELECTOR")]
+        probe: Option<String>,
+
+        /// Write this BootData record as well (as `boot-data encode` writes
+        /// it), where the bootloader keeps it
+        #[arg(long, value_name = "FILE")]
+        boot_data: Option<PathBuf>,
+
+        /// Leave the target halted instead of resetting it
+        #[arg(long)]
+        no_reset: bool,
+    },
+
+    /// Serve status, upload, reboot and progress over HTTP until stopped
+    /// (see the `serve` module)
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Address and port to listen on
+        #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Bearer token every request must carry
+        #[arg(long, value_name = "TOKEN", conflicts_with = "token_file")]
+        token: Option<String>,
+
+        /// Read the bearer token from a file
+        #[arg(long, value_name = "FILE")]
+        token_file: Option<PathBuf>,
+    },
+
+    /// Defaults from crispy.toml and ~/.config/crispy/config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Upload what a manifest says, checked before the device is touched
+    /// (see the `manifest` module)
+    Apply {
+        /// Manifest file
+        #[arg(value_name = "MANIFEST")]
+        manifest: PathBuf,
+
+        /// Check the manifest and its files and print the steps, without a
+        /// device
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Leave out the pre-flight checks of the images (vector table, RAM
+        /// copy size), for unusual images
+        #[arg(long)]
+        skip_checks: bool,
+
+        #[command(flatten)]
+        watch: WatchArgs,
+    },
+
+    /// Run the subcommands in a script, one per line, on one device,
+    /// stopping at the first that fails (see the `script` module)
+    Run {
+        /// Script file
+        #[arg(value_name = "SCRIPT")]
+        script: PathBuf,
+
+        /// Replace ${NAME} in the script with VALUE (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
+
+        /// Check the script and the files it names, without a device
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// A script line parsed as a subcommand.
+#[derive(Parser)]
+#[command(name = "step", no_binary_name = true)]
+struct Step {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+/// How to read a firmware file.
+#[derive(Clone, Args)]
+pub struct InputArgs {
+    /// Accept UF2 files for chip families other than RP2040
+    #[arg(long)]
+    pub any_family: bool,
+
+    /// Largest gap between Intel HEX records to fill with 0xFF
+    #[arg(long, value_name = "BYTES", default_value_t = commands::DEFAULT_MAX_GAP)]
+    pub max_gap: u32,
+
+    /// Fail unless the file's SHA-256 is HEX, e.g. as the artifact server
+    /// publishes it
+    #[arg(long, value_name = "HEX", value_parser = source::parse_sha256)]
+    pub sha256: Option<Sha256Digest>,
+
+    /// Bank size to check the image against before a device reports one,
+    /// for targets built with another flash layout
+    #[arg(long, value_name = "BYTES", default_value_t = FW_BANK_SIZE, value_parser = parse_number)]
+    pub bank_size: u32,
+}
+
+impl InputArgs {
+    fn input_options(&self) -> InputOptions {
+        InputOptions {
+            any_family: self.any_family,
+            max_gap: self.max_gap,
+            sha256: self.sha256,
+            bank_size: self.bank_size,
+        }
+    }
+}
+
+/// `--watch` for `status` and `info`.
+#[derive(Clone, Args)]
+pub struct LiveArgs {
+    /// Sample again every SECS seconds [default: 1], highlighting what
+    /// changed, until q or Ctrl-C
+    #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "1", value_parser = clap::value_parser!(u64).range(1..))]
+    pub watch: Option<u64>,
+
+    /// Stop watching after N samples
+    #[arg(long, value_name = "N", requires = "watch", value_parser = clap::value_parser!(u64).range(1..))]
+    pub count: Option<u64>,
+}
+
+impl LiveArgs {
+    fn live_options(&self, info: bool) -> Option<LiveOptions> {
+        Some(LiveOptions {
+            info,
+            interval: Duration::from_secs(self.watch?),
+            count: self.count,
+        })
+    }
+}
+
+/// How to recognise the device across reboots, and how long to wait.
+#[derive(Clone, Args)]
+pub struct WatchArgs {
+    /// After rebooting, wait for the firmware console to report the boot
+    /// confirmed from the uploaded bank (upload needs --reboot)
+    #[arg(long)]
+    pub expect_confirm: bool,
+
+    /// Seconds to wait for the device to re-enumerate after a reboot
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub reboot_timeout: u64,
+
+    /// Seconds to wait for --expect-confirm
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub confirm_timeout: u64,
+
+    /// USB VID:PID of the firmware's CDC port
+    #[arg(long, value_name = "VID:PID", default_value_t = UsbId::FIRMWARE)]
+    pub firmware_id: UsbId,
+
+    /// USB VID:PID of the bootloader's CDC port
+    #[arg(long, value_name = "VID:PID", default_value_t = UsbId::BOOTLOADER)]
+    pub bootloader_id: UsbId,
+}
+
+impl WatchArgs {
+    fn watch_options(&self) -> WatchOptions {
+        WatchOptions {
+            firmware: self.firmware_id,
+            bootloader: self.bootloader_id,
+            timeout: Duration::from_secs(self.reboot_timeout),
+            expect_confirm: self.expect_confirm,
+            confirm_timeout: Duration::from_secs(self.confirm_timeout),
+        }
+    }
+}
+
+/// What `factory` puts on a board, and how.
+#[derive(Clone, Args)]
+pub struct FactoryArgs {
+    /// Firmware for bank A, which the board boots
+    #[arg(long, value_name = "FILE")]
+    pub fw_a: PathBuf,
+
+    /// Firmware for bank B as well
+    #[arg(long, value_name = "FILE")]
+    pub fw_b: Option<PathBuf>,
+
+    /// Board ID to provision
+    #[arg(long, value_name = "ID", requires = "unit_serial")]
+    pub board: Option<u16>,
+
+    /// Hardware revision to provision
+    #[arg(long, value_name = "REV", default_value_t = 0, requires = "board")]
+    pub revision: u8,
+
+    /// Serial number to provision; AUTO takes the device's USB serial number
+    #[arg(long, value_name = "SN", requires = "board")]
+    pub unit_serial: Option<String>,
+
+    /// Resume at this stage, skipping the ones before it
+    #[arg(long, value_name = "STAGE")]
+    pub from: Option<Stage>,
+
+    /// Times to try each stage before giving up
+    #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..))]
+    pub attempts: u32,
+
+    /// Append the unit's pass/fail record to this file, one JSON line per
+    /// unit
+    #[arg(long, value_name = "PATH")]
+    pub log: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub watch: WatchArgs,
+}
+
+/// `boot-data` conversions.
+#[derive(Clone, Subcommand)]
+pub enum BootDataAction {
+    /// Print a BootData record (or a dump of its sector) as JSON
+    Decode {
+        /// Raw record, at least 64 bytes
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+
+    /// Write a JSON BootData record as the 64 bytes stored in flash
+    Encode {
+        /// JSON record, as printed by `boot-data decode`
+        #[arg(value_name = "JSON")]
+        file: PathBuf,
+
+        /// Output file
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+/// `config` actions.
+#[derive(Clone, Subcommand)]
+pub enum ConfigAction {
+    /// Print the options in effect and where each came from: the command
+    /// line, a configuration file and line, or the default
+    Show,
 }
 
 /// Execute the parsed CLI command.
 pub fn run(cli: Cli) -> Result<()> {
-    let mut transport = Transport::new(&cli.port)?;
+    output::set_progress_mode(cli.progress);
+    if cli.trace || cli.trace_file.is_some() {
+        trace::start(cli.trace_file.as_deref())?;
+    }
+    let overrides = overrides(&cli);
+    let history_path = history::path(cli.history_file, cli.no_history);
 
-    match cli.command {
-        Commands::Status => commands::status(&mut transport),
+    // Listing ports and file commands never open a device
+    match &cli.command {
+        Commands::History { device } => {
+            return commands::history(history_path.as_deref(), device.as_deref())
+        }
+        Commands::ListPorts { all } => return commands::list_ports(*all),
+        Commands::Inspect { file, input } => {
+            return commands::inspect(file, &input.input_options())
+        }
+        Commands::Checksum {
+            files,
+            offset,
+            length,
+            input,
+        } => return commands::checksum(files, *offset, *length, &input.input_options()),
+        Commands::Pack {
+            file,
+            output,
+            version,
+            board,
+            input,
+        } => {
+            let output = match output {
+                Some(output) => output.clone(),
+                None if source::is_stream(file) => bail!(Failure::new(
+                    "invalid_argument",
+                    "pack reading stdin or a URL needs --output"
+                )),
+                None => file.with_extension(commands::CONTAINER_EXTENSION),
+            };
+            return commands::pack(file, &output, *version, *board, &input.input_options());
+        }
+        Commands::BootData { action } => {
+            return match action {
+                BootDataAction::Decode { file } => commands::boot_data_decode(file),
+                BootDataAction::Encode { file, output } => commands::boot_data_encode(file, output),
+            };
+        }
+        Commands::Config {
+            action: ConfigAction::Show,
+        } => return config::show(&cli.config),
+        #[cfg(feature = "probe-rs")]
+        Commands::FlashBootloader {
+            file,
+            probe,
+            boot_data,
+            no_reset,
+        } => {
+            let opts = crate::swd::FlashOptions {
+                probe: probe.as_deref(),
+                boot_data: boot_data.as_deref(),
+                no_reset: *no_reset,
+            };
+            return crate::swd::flash_bootloader(file, &opts);
+        }
+        _ => {}
+    }
+
+    // Check the whole script before anything touches the device
+    let script = match &cli.command {
+        Commands::Run {
+            script,
+            vars,
+            dry_run,
+        } => {
+            let steps = load_script(script, vars, overrides)?;
+            if *dry_run {
+                for (line, _) in &steps {
+                    outln!("  line {:>3}: {}", line.number, line.text());
+                }
+                outln!("Script OK: {} steps", steps.len());
+                output::report(serde_json::json!({ "steps": steps.len() }));
+                return Ok(());
+            }
+            Some(steps)
+        }
+        _ => None,
+    };
+
+    // And the whole manifest, with the files it names
+    let manifest = match &cli.command {
+        Commands::Apply {
+            manifest, dry_run, ..
+        } => {
+            let manifest = Manifest::load(manifest)?;
+            manifest.check(command_overrides(overrides, &cli.command))?;
+            if *dry_run {
+                let steps = manifest.plan();
+                for (index, step) in steps.iter().enumerate() {
+                    outln!("  [{}/{}] {}", index + 1, steps.len(), step);
+                }
+                outln!("Manifest OK: {} steps", steps.len());
+                let names: Vec<_> = steps.iter().map(manifest::Step::name).collect();
+                output::report(serde_json::json!({ "steps": names }));
+                return Ok(());
+            }
+            Some(manifest)
+        }
+        _ => None,
+    };
+
+    if let Commands::Upload {
+        reboot: false,
+        watch,
+        ..
+    } = &cli.command
+    {
+        if watch.expect_confirm {
+            bail!("--expect-confirm needs --reboot");
+        }
+    }
+
+    // Refuse a file the device would refuse before the port is opened; an
+    // override is reported by the upload, when it checks the image again
+    if !overrides.allows(Protection::ImageChecks) {
+        for (file, input) in firmware_files(&cli.command) {
+            commands::preflight(file, &input.input_options())?;
+        }
+    }
+
+    let mut link = LinkOptions {
+        connect_timeout: Duration::from_millis(cli.connect_timeout),
+        read_timeout: Duration::from_millis(cli.read_timeout),
+        wait: Duration::from_secs(cli.wait.unwrap_or(0)),
+        ..LinkOptions::DEFAULT
+    };
+    if let Some(timeout) = cli.timeout {
+        link = link.with_timeout(Duration::from_millis(timeout));
+    }
+
+    if let Commands::Probe = cli.command {
+        let ports = if cli.port.is_empty() {
+            transport::list_ports()?
+                .into_iter()
+                .filter(|p| p.usb.is_some())
+                .map(|p| p.name)
+                .collect()
+        } else {
+            cli.port
+        };
+        return commands::probe(&ports, &link);
+    }
+
+    // The console belongs to the firmware, not the bootloader
+    if let Commands::Monitor { reattach } = cli.command {
+        let port = match cli.port.as_slice() {
+            [port] => port.clone(),
+            [] if !cli.all => {
+                let find = || monitor::find_console_port(cli.serial.as_deref());
+                transport::wait_for(link.wait, find, transport::is_nothing_found)?
+            }
+            _ => bail!(Failure::new(
+                "invalid_argument",
+                "monitor works on one device; pass a single --port"
+            )),
+        };
+        return monitor::monitor(&port, &link, reattach);
+    }
+
+    // `update` may start from the firmware; everything else needs the bootloader
+    let update = matches!(cli.command, Commands::Update { .. });
+    let session = Session {
+        command: &cli.command,
+        script: script.as_deref(),
+        manifest: manifest.as_ref(),
+        link,
+        retry: RetryPolicy {
+            retries: cli.retries,
+            backoff: Duration::from_millis(cli.retry_backoff),
+        },
+        history_path: history_path.as_deref(),
+        overrides: Overrides::force(cli.force).with(Protection::WrongPort, cli.allow_wrong_port),
+    };
+    let ports = if cli.all {
+        transport::find_all_device_ports(update)?
+    } else {
+        cli.port.clone()
+    };
+    if ports.len() > 1 {
+        // Ports found by `--all` are crispy devices by their USB IDs
+        return parallel::run_all(&ports, |port| session.run(port, !cli.all));
+    }
+
+    let explicit = !cli.port.is_empty();
+    let port = match ports.into_iter().next() {
+        Some(port) => port,
+        None => {
+            let find = || {
+                if update {
+                    transport::find_device_port(cli.serial.as_deref())
+                } else {
+                    transport::find_bootloader_port(cli.serial.as_deref())
+                }
+            };
+            let port = transport::wait_for(link.wait, find, transport::is_nothing_found)?;
+            eprintln!("Using {}", port);
+            port
+        }
+    };
+    session.run(&port, explicit)
+}
+
+/// What a command needs to run on a device, one or several at once.
+struct Session<'a> {
+    command: &'a Commands,
+    script: Option<&'a [(Line, Commands)]>,
+    /// The checked manifest of `apply`.
+    manifest: Option<&'a Manifest>,
+    link: LinkOptions,
+    retry: RetryPolicy,
+    history_path: Option<&'a Path>,
+    /// What the global flags override; each command adds its own.
+    overrides: Overrides,
+}
+
+impl Session<'_> {
+    /// Run the command, or the script, on `port`. A port that was not
+    /// `explicit`ly given was found by its USB IDs.
+    fn run(&self, port: &str, explicit: bool) -> Result<()> {
+        let (port, link, retry) = (port, &self.link, self.retry);
+        // Auto-detection only picks the bootloader; a --port may be anything
+        let steps = self
+            .script
+            .iter()
+            .copied()
+            .flatten()
+            .map(|(_, command)| command);
+        if explicit && (changes_device(self.command) || steps.clone().any(changes_device)) {
+            probe::check_bootloader(port, link, self.overrides)?;
+        }
+        let audit = audited(self.command);
+
+        #[cfg(feature = "serve")]
+        if let Commands::Serve {
+            listen,
+            token,
+            token_file,
+        } = self.command
+        {
+            let token = match token_file {
+                Some(path) => Some(
+                    fs::read_to_string(path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?
+                        .trim()
+                        .to_string(),
+                ),
+                None => token.clone(),
+            };
+            let opts = crate::serve::ServeOptions {
+                listen: listen.clone(),
+                token,
+            };
+            return crate::serve::run(port, link, retry, &opts);
+        }
+
+        let overrides = command_overrides(self.overrides, self.command);
+        let result = if let Commands::Update {
+            file,
+            version,
+            verify,
+            chunk_size,
+            input,
+            always,
+            watch,
+            ..
+        } = self.command
+        {
+            let upload_opts = UploadOptions {
+                version: *version,
+                chunk_size: *chunk_size,
+                verify: *verify,
+                input: input.input_options(),
+                overrides,
+                always: *always,
+            };
+            commands::update(
+                port,
+                link,
+                file,
+                &upload_opts,
+                retry,
+                &watch.watch_options(),
+            )
+        } else if let Some(steps) = self.script {
+            return run_script(
+                steps.to_vec(),
+                port,
+                link,
+                retry,
+                self.overrides,
+                self.history_path,
+            );
+        } else if let Commands::Factory(args) = self.command {
+            // Every stage is recorded on its own
+            return run_factory(args, port, link, retry, self.overrides, self.history_path);
+        } else if let Commands::Selftest {
+            size,
+            seed,
+            reboot_timeout,
+            firmware_id,
+            bootloader_id,
+        } = self.command
+        {
+            let opts = SelftestOptions {
+                size: *size,
+                seed: seed.unwrap_or_else(selftest::seed_from_clock),
+                watch: WatchOptions {
+                    firmware: *firmware_id,
+                    bootloader: *bootloader_id,
+                    timeout: Duration::from_secs(*reboot_timeout),
+                    expect_confirm: false,
+                    confirm_timeout: Duration::ZERO,
+                },
+            };
+            let mut transport = Transport::open(port, link)?;
+            transport.set_retry_policy(retry);
+            selftest::run(transport, port, &opts)
+        } else if let Commands::Restore { archive, .. } = self.command {
+            let mut transport = Transport::open(port, link)?;
+            transport.set_retry_policy(retry);
+            backup::restore(&mut transport, archive, overrides)
+        } else if let (Some(manifest), Commands::Apply { watch, .. }) =
+            (self.manifest, self.command)
+        {
+            let mut transport = Transport::open(port, link)?;
+            transport.set_retry_policy(retry);
+            manifest::apply(transport, port, manifest, overrides, &watch.watch_options())
+        } else {
+            dispatch(self.command.clone(), port, link, retry, self.overrides)?
+        };
+
+        record(self.history_path, port, audit, &result);
+        result
+    }
+}
+
+/// Append an operation that changed the device to the history.
+fn record(
+    path: Option<&Path>,
+    port: &str,
+    audit: Option<(&'static str, serde_json::Value, Option<u32>)>,
+    result: &Result<()>,
+) {
+    if let (Some(path), Some((command, params, fw_crc))) = (path, audit) {
+        let device = transport::port_serial(port);
+        let record = Record::new(device, port, command, params, fw_crc, result);
+        history::append(path, &record);
+    }
+}
+
+/// The protections `cli` overrides: all of them with --force, else those
+/// whose own flag is given.
+pub fn overrides(cli: &Cli) -> Overrides {
+    let global = Overrides::force(cli.force).with(Protection::WrongPort, cli.allow_wrong_port);
+    command_overrides(global, &cli.command)
+}
+
+/// The firmware files `command` uploads, each with how to read it.
+fn firmware_files(command: &Commands) -> Vec<(&Path, &InputArgs)> {
+    match command {
+        Commands::Upload { file, input, .. } | Commands::Update { file, input, .. } => {
+            vec![(file, input)]
+        }
+        Commands::UploadBoth {
+            file_a,
+            file_b,
+            input,
+            ..
+        } => [Some(file_a), file_b.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|file| (file.as_path(), input))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// `overrides` and those of `command`'s own flags.
+fn command_overrides(overrides: Overrides, command: &Commands) -> Overrides {
+    match command {
+        Commands::Upload {
+            skip_checks,
+            allow_active_bank,
+            ..
+        } => overrides
+            .with(Protection::ImageChecks, *skip_checks)
+            .with(Protection::ActiveBank, *allow_active_bank),
+        Commands::Update { skip_checks, .. }
+        | Commands::UploadBoth { skip_checks, .. }
+        | Commands::Apply { skip_checks, .. } => {
+            overrides.with(Protection::ImageChecks, *skip_checks)
+        }
+        Commands::Restore {
+            allow_other_device, ..
+        } => overrides.with(Protection::OtherDevice, *allow_other_device),
+        Commands::Provision { reprovision, .. } => {
+            overrides.with(Protection::Provisioned, *reprovision)
+        }
+        _ => overrides,
+    }
+}
+
+/// Read a `run` script and parse and check every step, with the global
+/// `overrides`.
+fn load_script(
+    path: &Path,
+    vars: &[String],
+    overrides: Overrides,
+) -> Result<Vec<(Line, Commands)>> {
+    let vars = script::parse_vars(vars)?;
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut steps = Vec::new();
+    for line in script::parse(&text, &vars)? {
+        let command = match parse_step(&line.words) {
+            Ok(command) => command,
+            Err(message) => bail!(script::error(line.number, message)),
+        };
+        if let Err(err) = check_step(&command, overrides) {
+            bail!(script::error(line.number, format!("{:#}", err)));
+        }
+        steps.push((line, command));
+    }
+
+    if steps.is_empty() {
+        bail!(Failure::new(
+            "script",
+            format!("{} has no steps", path.display())
+        ));
+    }
+    // Nothing can follow a reboot on the same connection
+    if let Some((line, _)) = steps[..steps.len() - 1]
+        .iter()
+        .find(|(_, command)| reboots(command))
+    {
+        bail!(script::error(
+            line.number,
+            "a step that reboots the device must be the last"
+        ));
+    }
+    Ok(steps)
+}
+
+/// A subcommand and its arguments as words, with clap's first line as the
+/// error.
+/// A number in decimal or, with a `0x` prefix, hex.
+fn parse_number(text: &str) -> std::result::Result<u32, String> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => text.replace('_', "").parse(),
+    };
+    parsed.map_err(|e| format!("{}: {}", text, e))
+}
+
+fn parse_step(words: &[String]) -> std::result::Result<Commands, String> {
+    Step::try_parse_from(words)
+        .map(|step| step.command)
+        .map_err(|err| {
+            let message = err.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            first.trim_start_matches("error: ").to_string()
+        })
+}
+
+/// Whether `command` changes the device through the bootloader; `update`
+/// starts from the firmware on purpose.
+fn changes_device(command: &Commands) -> bool {
+    match command {
+        Commands::Update { .. } => false,
+        Commands::Factory(_) => true,
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => true,
+        command => audited(command).is_some(),
+    }
+}
+
+/// Whether `command` leaves the device out of the bootloader.
+fn reboots(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Reboot { stay: false } | Commands::Upload { reboot: true, .. }
+    )
+}
+
+/// Check a script step's arguments and files without a device.
+fn check_step(command: &Commands, overrides: Overrides) -> Result<()> {
+    let check_bank = |bank: u8| {
+        if bank > 1 {
+            bail!("invalid bank {}: must be 0 (A) or 1 (B)", bank);
+        }
+        Ok(())
+    };
+    match command {
         Commands::Upload {
             file,
             bank,
+            input,
+            reboot,
+            watch,
+            ..
+        } => {
+            if watch.expect_confirm && !reboot {
+                bail!("--expect-confirm needs --reboot");
+            }
+            bank.map_or(Ok(()), check_bank)?;
+            if command_overrides(overrides, command).allows(Protection::ImageChecks) {
+                commands::check_firmware(file, &input.input_options())?;
+            } else {
+                commands::preflight(file, &input.input_options())?;
+            }
+        }
+        Commands::UploadBoth { active, .. } => {
+            check_bank(*active)?;
+            let skip = command_overrides(overrides, command).allows(Protection::ImageChecks);
+            for (file, input) in firmware_files(command) {
+                if skip {
+                    commands::check_firmware(file, &input.input_options())?;
+                } else {
+                    commands::preflight(file, &input.input_options())?;
+                }
+            }
+        }
+        Commands::Diff {
+            file, bank, input, ..
+        }
+        | Commands::Verify {
+            file, bank, input, ..
+        } => {
+            check_bank(*bank)?;
+            commands::check_firmware(file, &input.input_options())?;
+        }
+        Commands::Inspect { file, input } => {
+            commands::check_firmware(file, &input.input_options())?;
+        }
+        Commands::Download { bank, .. }
+        | Commands::SetBank { bank }
+        | Commands::BlankCheck {
+            bank: Some(bank), ..
+        }
+        | Commands::SetVersion { bank, .. } => check_bank(*bank)?,
+        Commands::Clone { from, to } => {
+            check_bank(*from)?;
+            check_bank(*to)?;
+        }
+        Commands::Update { .. } => {
+            bail!("`update` reconnects on its own; use `upload` and `reboot` in a script")
+        }
+        Commands::Factory(_) => {
+            bail!("`factory` runs its own stages; use `wipe`, `upload` and `verify` in a script")
+        }
+        Commands::Selftest { .. } => {
+            bail!("`selftest` reboots and restores the device on its own; run it by itself")
+        }
+        Commands::Restore { .. } => {
+            bail!("`restore` checks which device it writes to; run it by itself")
+        }
+        Commands::Apply { .. } => {
+            bail!("`apply` runs the steps of its manifest; run it by itself")
+        }
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => bail!("`serve` runs until stopped; run it by itself"),
+        #[cfg(feature = "probe-rs")]
+        Commands::FlashBootloader { .. } => {
+            bail!("`flash-bootloader` goes through a debug probe; run it by itself")
+        }
+        Commands::History { .. }
+        | Commands::ListPorts { .. }
+        | Commands::Checksum { .. }
+        | Commands::Pack { .. }
+        | Commands::BootData { .. }
+        | Commands::Config { .. }
+        | Commands::Monitor { .. }
+        | Commands::Probe
+        | Commands::Run { .. } => bail!("only device commands and `inspect` can run in a script"),
+        Commands::Status { live } | Commands::Info { live } if live.watch.is_some() => {
+            bail!("`--watch` runs until stopped; use it on its own, not in a script")
+        }
+        Commands::Status { .. }
+        | Commands::Info { .. }
+        | Commands::VerifyAll
+        | Commands::Wipe
+        | Commands::FlashInfo
+        | Commands::Bench { .. }
+        | Commands::BlankCheck { .. }
+        | Commands::Backup { .. }
+        | Commands::SetBootAttempts { .. }
+        | Commands::Provision { .. }
+        | Commands::Reboot { .. } => {}
+    }
+    Ok(())
+}
+
+/// Run checked script steps in order on one connection, recording each
+/// in the history, until one fails.
+fn run_script(
+    steps: Vec<(Line, Commands)>,
+    port: &str,
+    link: &LinkOptions,
+    retry: RetryPolicy,
+    overrides: Overrides,
+    history_path: Option<&Path>,
+) -> Result<()> {
+    let mut transport = Transport::open(port, link)?;
+    transport.set_retry_policy(retry);
+
+    let total = steps.len();
+    for (index, (line, command)) in steps.into_iter().enumerate() {
+        outln!("[{}/{}] {}", index + 1, total, line.text());
+        let failed = || {
+            format!(
+                "Step {} of {} failed (script line {}: {})",
+                index + 1,
+                total,
+                line.number,
+                line.text()
+            )
+        };
+        let audit = audited(&command);
+        let result = match execute(command, &mut transport, port, overrides) {
+            // Only ever the last step
+            Ok(Next::Watch(bank, opts)) => {
+                let result = commands::reboot_and_watch(transport, port, bank, &opts);
+                record(history_path, port, audit, &result);
+                return result.with_context(failed);
+            }
+            result => result.map(|_| ()),
+        };
+        record(history_path, port, audit, &result);
+        result.with_context(failed)?;
+    }
+    output::report(serde_json::json!({ "steps": total }));
+    Ok(())
+}
+
+/// Run the `factory` stages on `port` as script steps, trying each up to
+/// `--attempts` times, and leave the unit's record.
+fn run_factory(
+    args: &FactoryArgs,
+    port: &str,
+    link: &LinkOptions,
+    retry: RetryPolicy,
+    overrides: Overrides,
+    history_path: Option<&Path>,
+) -> Result<()> {
+    let device = transport::port_serial(port);
+    let unit_serial = match args.unit_serial.as_deref() {
+        Some(sn) if sn.eq_ignore_ascii_case("auto") => match &device {
+            Some(device) => Some(device.clone()),
+            None => bail!(Failure::new(
+                "invalid_argument",
+                format!("{} has no USB serial number for --unit-serial AUTO", port)
+            )),
+        },
+        sn => sn.map(str::to_string),
+    };
+    let plan = Plan {
+        fw_a: &args.fw_a,
+        fw_b: args.fw_b.as_deref(),
+        provision: args
+            .board
+            .zip(unit_serial.as_deref())
+            .map(|(board, serial)| (board, args.revision, serial)),
+    };
+    // Every file is checked before the board is touched
+    let mut stages = Vec::new();
+    for (stage, words) in plan.stages() {
+        let mut steps = Vec::new();
+        for words in words {
+            let line = Line { number: 0, words };
+            let command = parse_step(&line.words).map_err(anyhow::Error::msg)?;
+            check_step(&command, overrides).with_context(|| format!("Stage {}", stage.name()))?;
+            steps.push((line, command));
+        }
+        stages.push((stage, steps));
+    }
+
+    let mut transport = Some(Transport::open(port, link)?);
+    if let Some(transport) = &mut transport {
+        transport.set_retry_policy(retry);
+    }
+
+    let total = stages.len();
+    let mut records = Vec::new();
+    for (index, (stage, steps)) in stages.into_iter().enumerate() {
+        let skipped = |records: &mut Vec<StageRecord>| {
+            records.push(StageRecord {
+                stage,
+                outcome: "skipped",
+                attempts: 0,
+                ms: 0,
+                error: None,
+            })
+        };
+        if args.from.is_some_and(|from| stage < from) {
+            outln!(
+                "[{}/{}] {}: skipped (--from)",
+                index + 1,
+                total,
+                stage.name()
+            );
+            skipped(&mut records);
+            continue;
+        }
+        outln!("[{}/{}] {}", index + 1, total, stage.name());
+
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = loop {
+            attempts += 1;
+            let result = match (stage, &mut transport) {
+                // The port is let go of for the reboot, so this is the end
+                (Stage::Reboot, transport) => {
+                    let transport = transport.take().expect("the reboot is the last stage");
+                    let opts = args.watch.watch_options();
+                    break commands::reboot_and_watch(transport, port, 0, &opts);
+                }
+                (_, Some(transport)) => steps.iter().try_for_each(|(line, command)| {
+                    outln!("  {}", line.text());
+                    let audit = audited(command);
+                    let result = execute(command.clone(), transport, port, overrides).map(|_| ());
+                    record(history_path, port, audit, &result);
+                    result
+                }),
+                (_, None) => unreachable!("the port is open until the reboot"),
+            };
+            match result {
+                Err(err) if attempts < args.attempts => {
+                    outln!("  Attempt {} failed: {:#}; trying again", attempts, err);
+                }
+                result => break result,
+            }
+        };
+        records.push(StageRecord {
+            stage,
+            outcome: if result.is_ok() { "ok" } else { "failed" },
+            attempts,
+            ms: started.elapsed().as_millis() as u64,
+            error: result.as_ref().err().map(|err| format!("{:#}", err)),
+        });
+        if let Err(err) = result {
+            return factory_result(args, port, device, unit_serial, records, Some((stage, err)));
+        }
+    }
+    factory_result(args, port, device, unit_serial, records, None)
+}
+
+/// Log and report the `factory` record, failing with `failed`.
+fn factory_result(
+    args: &FactoryArgs,
+    port: &str,
+    device: Option<String>,
+    unit_serial: Option<String>,
+    stages: Vec<StageRecord>,
+    failed: Option<(Stage, anyhow::Error)>,
+) -> Result<()> {
+    let record = factory::Record {
+        timestamp: history::now(),
+        port: port.to_string(),
+        device,
+        board: args.board,
+        unit_serial,
+        fw_a: FileRecord::new(&args.fw_a),
+        fw_b: args.fw_b.as_deref().map(FileRecord::new),
+        result: if failed.is_none() { "pass" } else { "fail" },
+        failed_stage: failed.as_ref().map(|(stage, _)| *stage),
+        stages,
+    };
+    if let Some(log) = &args.log {
+        factory::append_log(log, &record)?;
+    }
+    let value = serde_json::to_value(&record).expect("records serialize");
+    let Some((stage, err)) = failed else {
+        outln!("PASS");
+        output::report(value);
+        return Ok(());
+    };
+    outln!("FAIL at {}", stage.name());
+    bail!(Failure::new(
+        "factory",
+        format!(
+            "Stage {} failed: {:#}; fix the cause and rerun with --from {}",
+            stage.name(),
+            err,
+            stage.name()
+        )
+    )
+    .with("stage", stage.name())
+    .with("record", value))
+}
+
+/// What is left to do after [`execute`].
+enum Next {
+    Done,
+    /// `upload --reboot`: reboot into the firmware in this bank and watch it
+    /// come back, which needs the port closed.
+    Watch(u8, WatchOptions),
+}
+
+/// Run a command that talks to the bootloader on `port`.
+///
+/// Failing to open the port is returned as the outer error, before there is
+/// an operation to record.
+fn dispatch(
+    command: Commands,
+    port: &str,
+    link: &LinkOptions,
+    retry: RetryPolicy,
+    overrides: Overrides,
+) -> Result<Result<()>> {
+    let mut transport = Transport::open(port, link)?;
+    transport.set_retry_policy(retry);
+
+    Ok(match execute(command, &mut transport, port, overrides) {
+        Ok(Next::Watch(bank, opts)) => commands::reboot_and_watch(transport, port, bank, &opts),
+        result => result.map(|_| ()),
+    })
+}
+
+/// Run a command on an open connection to the bootloader, with the global
+/// `overrides`.
+fn execute(
+    command: Commands,
+    transport: &mut Transport,
+    port: &str,
+    overrides: Overrides,
+) -> Result<Next> {
+    let overrides = command_overrides(overrides, &command);
+    match command {
+        Commands::Status { live } => match live.live_options(false) {
+            Some(opts) => live::watch(transport, &opts),
+            None => commands::status(transport),
+        },
+        Commands::Info { live } => match live.live_options(true) {
+            Some(opts) => live::watch(transport, &opts),
+            None => commands::info(transport),
+        },
+        Commands::Upload {
+            file,
+            bank,
+            version,
+            verify,
+            chunk_size,
+            input,
+            always,
+            reboot,
+            watch,
+            ..
+        } => {
+            let opts = UploadOptions {
+                version,
+                chunk_size,
+                verify,
+                input: input.input_options(),
+                overrides,
+                always,
+            };
+            let bank = commands::upload(transport, &file, bank, &opts)?;
+            if reboot {
+                return Ok(Next::Watch(bank, watch.watch_options()));
+            }
+            outln!(
+                "Use 'crispy-upload --port {} reboot' to restart the device.",
+                port
+            );
+            Ok(())
+        }
+        Commands::UploadBoth {
+            file_a,
+            file_b,
             version,
-        } => commands::upload(&mut transport, &file, bank, version),
-        Commands::SetBank { bank } => commands::set_bank(&mut transport, bank),
-        Commands::Wipe => commands::wipe(&mut transport),
-        Commands::Reboot => commands::reboot(&mut transport),
+            verify,
+            chunk_size,
+            input,
+            active,
+            no_activate,
+            ..
+        } => {
+            let opts = UploadOptions {
+                version,
+                chunk_size,
+                verify,
+                input: input.input_options(),
+                overrides,
+                // Neither upload is to the active bank, so none is skipped
+                always: true,
+            };
+            let activate = (!no_activate).then_some(active);
+            commands::upload_both(transport, &file_a, file_b.as_deref(), activate, &opts)
+        }
+        Commands::Download {
+            output,
+            bank,
+            length,
+            chunk_size,
+        } => commands::download(transport, bank, &output, length, chunk_size),
+        Commands::Backup {
+            output,
+            full,
+            chunk_size,
+        } => backup::backup(transport, &output, full, chunk_size),
+        Commands::Diff {
+            file,
+            bank,
+            first_only,
+            chunk_size,
+            input,
+        } => commands::diff(
+            transport,
+            &file,
+            bank,
+            chunk_size,
+            first_only,
+            &input.input_options(),
+        ),
+        Commands::Verify {
+            file,
+            bank,
+            quick: true,
+            input,
+            ..
+        } => commands::verify_quick(transport, &file, bank, &input.input_options()),
+        Commands::Verify {
+            file,
+            bank,
+            chunk_size,
+            input,
+            ..
+        } => commands::diff(
+            transport,
+            &file,
+            bank,
+            chunk_size,
+            false,
+            &input.input_options(),
+        ),
+        Commands::VerifyAll => health::verify_all(transport),
+        Commands::SetBank { bank } => commands::set_bank(transport, bank),
+        Commands::Clone { from, to } => commands::clone_bank(transport, from, to),
+        Commands::Wipe => commands::wipe(transport),
+        Commands::FlashInfo => commands::flash_info(transport),
+        Commands::Bench {
+            rounds,
+            block_sizes,
+            bytes,
+        } => bench::bench(
+            transport,
+            &BenchOptions {
+                rounds,
+                block_sizes,
+                bytes,
+            },
+        ),
+        Commands::BlankCheck { bank, range } => {
+            let region = match (bank, range.as_deref()) {
+                (Some(bank), _) => commands::BlankRegion::Bank(bank),
+                (None, Some(&[addr, length])) => commands::BlankRegion::Range { addr, length },
+                _ => unreachable!("clap requires --bank or a two-value --range"),
+            };
+            commands::blank_check(transport, region)
+        }
+        Commands::SetBootAttempts { max_attempts } => {
+            commands::set_boot_attempts(transport, max_attempts)
+        }
+        Commands::SetVersion { bank, version, yes } => {
+            commands::set_version(transport, bank, version, yes)
+        }
+        Commands::Provision {
+            board,
+            revision,
+            serial,
+            ..
+        } => commands::provision(transport, board, revision, &serial, overrides),
+        Commands::Reboot { stay: false } => commands::reboot(transport),
+        Commands::Reboot { stay: true } => commands::reboot_and_stay(transport),
+        // Only from a script; on the command line it never opens a device
+        Commands::Inspect { file, input } => commands::inspect(&file, &input.input_options()),
+        Commands::Update { .. }
+        | Commands::Factory(_)
+        | Commands::Selftest { .. }
+        | Commands::Restore { .. }
+        | Commands::Apply { .. }
+        | Commands::History { .. }
+        | Commands::ListPorts { .. }
+        | Commands::Checksum { .. }
+        | Commands::Pack { .. }
+        | Commands::BootData { .. }
+        | Commands::Config { .. }
+        | Commands::Monitor { .. }
+        | Commands::Probe
+        | Commands::Run { .. } => {
+            unreachable!("handled in run")
+        }
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => unreachable!("handled in run"),
+        #[cfg(feature = "probe-rs")]
+        Commands::FlashBootloader { .. } => unreachable!("handled in run"),
+    }?;
+    Ok(Next::Done)
+}
+
+/// History name, parameters and firmware CRC of a command that changes the
+/// device, or `None` for one that only reads it.
+fn audited(command: &Commands) -> Option<(&'static str, serde_json::Value, Option<u32>)> {
+    use serde_json::json;
+
+    Some(match command {
+        Commands::Upload {
+            file,
+            bank,
+            version,
+            verify,
+            reboot,
+            ..
+        } => {
+            let crc = source::read(file, None).ok().map(|fw| crc32::checksum(&fw));
+            let version = version.map(|v| v.to_string());
+            let params = json!({
+                "file": file.display().to_string(),
+                "bank": bank,
+                "version": version,
+                "verify": verify,
+                "reboot": reboot,
+            });
+            ("upload", params, crc)
+        }
+        Commands::UploadBoth {
+            file_a,
+            file_b,
+            version,
+            verify,
+            active,
+            no_activate,
+            ..
+        } => {
+            let crc = source::read(file_a, None)
+                .ok()
+                .map(|fw| crc32::checksum(&fw));
+            let version = version.map(|v| v.to_string());
+            let params = json!({
+                "file_a": file_a.display().to_string(),
+                "file_b": file_b.as_ref().unwrap_or(file_a).display().to_string(),
+                "version": version,
+                "verify": verify,
+                "active": (!no_activate).then_some(active),
+            });
+            ("upload-both", params, crc)
+        }
+        Commands::Update {
+            file,
+            version,
+            verify,
+            ..
+        } => {
+            let crc = source::read(file, None).ok().map(|fw| crc32::checksum(&fw));
+            let version = version.map(|v| v.to_string());
+            let params = json!({
+                "file": file.display().to_string(),
+                "version": version,
+                "verify": verify,
+            });
+            ("update", params, crc)
+        }
+        Commands::SetBank { bank } => ("set-bank", json!({ "bank": bank }), None),
+        Commands::Clone { from, to } => ("clone", json!({ "from": from, "to": to }), None),
+        Commands::Wipe => ("wipe", json!({}), None),
+        Commands::SetBootAttempts { max_attempts } => (
+            "set-boot-attempts",
+            json!({ "max_attempts": max_attempts }),
+            None,
+        ),
+        Commands::SetVersion { bank, version, .. } => (
+            "set-version",
+            json!({ "bank": bank, "version": version.to_string() }),
+            None,
+        ),
+        Commands::Provision {
+            board,
+            revision,
+            serial,
+            ..
+        } => (
+            "provision",
+            json!({ "board": board, "revision": revision, "serial": serial }),
+            None,
+        ),
+        Commands::Reboot { stay } => ("reboot", json!({ "stay": stay }), None),
+        Commands::Selftest { size, seed, .. } => {
+            ("selftest", json!({ "size": size, "seed": seed }), None)
+        }
+        Commands::Restore { archive, .. } => (
+            "restore",
+            json!({ "file": archive.display().to_string() }),
+            None,
+        ),
+        Commands::Apply { manifest, .. } => (
+            "apply",
+            json!({ "manifest": manifest.display().to_string() }),
+            None,
+        ),
+        Commands::Status { .. }
+        | Commands::Info { .. }
+        | Commands::FlashInfo
+        | Commands::Bench { .. }
+        | Commands::BlankCheck { .. }
+        | Commands::Download { .. }
+        | Commands::Backup { .. }
+        | Commands::Diff { .. }
+        | Commands::Verify { .. }
+        | Commands::VerifyAll
+        | Commands::Factory(_)
+        | Commands::History { .. }
+        | Commands::ListPorts { .. }
+        | Commands::Inspect { .. }
+        | Commands::Checksum { .. }
+        | Commands::Pack { .. }
+        | Commands::BootData { .. }
+        | Commands::Config { .. }
+        | Commands::Monitor { .. }
+        | Commands::Probe
+        | Commands::Run { .. } => return None,
+        #[cfg(feature = "serve")]
+        Commands::Serve { .. } => return None,
+        #[cfg(feature = "probe-rs")]
+        Commands::FlashBootloader { .. } => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::test_device::TestDevice;
+
+    fn script(text: &str) -> Result<Vec<(Line, Commands)>> {
+        // Tests run in parallel; give each script its own file
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "crispy-script-{}-{}.txt",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, text).unwrap();
+        let steps = load_script(&path, &[], Overrides::NONE);
+        fs::remove_file(&path).unwrap();
+        steps
+    }
+
+    fn failure(err: anyhow::Error) -> (usize, String) {
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "script");
+        (
+            failure.context["line"].as_u64().unwrap() as usize,
+            failure.message.clone(),
+        )
+    }
+
+    #[test]
+    fn test_load_script() {
+        let steps =
+            script("wipe\n\nset-bank 1\nset-version --bank 1 1.2.3 --yes\nreboot\n").unwrap();
+        let lines: Vec<_> = steps.iter().map(|(line, _)| line.number).collect();
+        assert_eq!(lines, [1, 3, 4, 5]);
+        assert!(matches!(steps[1].1, Commands::SetBank { bank: 1 }));
+        assert!(reboots(&steps[3].1));
+    }
+
+    #[test]
+    fn test_load_script_rejects_bad_steps() {
+        let (line, message) = failure(script("status\nset-bank 2\n").err().unwrap());
+        assert_eq!(line, 2);
+        assert!(message.contains("invalid bank 2"), "{}", message);
+
+        let (line, message) = failure(script("reboot\nstatus\n").err().unwrap());
+        assert_eq!(line, 1);
+        assert!(message.contains("must be the last"), "{}", message);
+
+        let (line, message) = failure(script("status\nupdate fw.bin\n").err().unwrap());
+        assert_eq!(line, 2);
+        assert!(message.contains("`update`"), "{}", message);
+
+        let (line, message) = failure(script("set-bank\n").err().unwrap());
+        assert_eq!(line, 1);
+        assert!(message.contains("required"), "{}", message);
+
+        assert!(script("# nothing\n").is_err());
+    }
+
+    #[test]
+    fn test_factory_stages_and_record() {
+        let mut fw = vec![0u8; 3000];
+        fw[0..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+        fw[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("crispy-factory-{}.bin", std::process::id()));
+        let log = dir.join(format!("crispy-factory-{}.jsonl", std::process::id()));
+        fs::write(&file, &fw).unwrap();
+
+        let device = TestDevice::start();
+        let step = Step::try_parse_from([
+            "factory",
+            "--fw-a",
+            file.to_str().unwrap(),
+            "--board",
+            "2",
+            "--unit-serial",
+            "SN1",
+            "--log",
+            log.to_str().unwrap(),
+        ])
+        .unwrap();
+        let Commands::Factory(args) = step.command else {
+            panic!("not factory");
+        };
+        // The test device refuses to reboot
+        let err = run_factory(
+            &args,
+            &device.port,
+            &LinkOptions::DEFAULT,
+            RetryPolicy::DEFAULT,
+            Overrides::NONE,
+            None,
+        )
+        .unwrap_err();
+        let flash = device.finish();
+
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "factory");
+        assert_eq!(failure.context["stage"], "reboot");
+        let line = fs::read_to_string(&log).unwrap();
+        fs::remove_file(&file).unwrap();
+        fs::remove_file(&log).unwrap();
+        let record: serde_json::Value = serde_json::from_str(line.trim()).unwrap();
+        assert_eq!(record, failure.context["record"]);
+        assert_eq!(record["result"], "fail");
+        assert_eq!(record["board"], 2);
+        let outcomes: Vec<_> = record["stages"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| (s["stage"].as_str().unwrap(), s["outcome"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("wipe", "ok"),
+                ("upload-a", "ok"),
+                ("provision", "ok"),
+                ("set-bank", "ok"),
+                ("verify", "ok"),
+                ("reboot", "failed"),
+            ]
+        );
+        let bd = crispy_common::flash::read_boot_data(&flash).unwrap();
+        assert_eq!((bd.active_bank, bd.size_a), (0, fw.len() as u32));
+        assert_eq!(bd.crc_a, crc32::checksum(&fw));
+        let identity = crispy_common::flash::read_provision(&flash).unwrap();
+        assert_eq!((identity.board_id, identity.serial()), (2, "SN1"));
+    }
+
+    #[test]
+    fn test_several_ports_at_once() {
+        let devices = [TestDevice::start(), TestDevice::start()];
+        // Nothing listens here any more
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead = format!("tcp://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let mut args = vec!["crispy-upload", "--no-history"];
+        for port in [&devices[0].port, &devices[1].port, &dead] {
+            args.extend(["--port", port]);
+        }
+        args.push("status");
+        let err = run(Cli::try_parse_from(args).unwrap()).unwrap_err();
+
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "devices");
+        let ok: Vec<_> = failure.context["devices"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|device| device["ok"].as_bool().unwrap())
+            .collect();
+        assert_eq!(ok, [true, true, false]);
+        for device in devices {
+            device.finish();
+        }
     }
 }
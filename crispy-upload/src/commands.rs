@@ -4,24 +4,83 @@
 //! Command implementations for bootloader operations.
 
 use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
-use crc::{Crc, CRC_32_ISO_HDLC};
-use indicatif::{ProgressBar, ProgressStyle};
+use serde_json::{json, Map};
 
-use crispy_common::protocol::{AckStatus, Command, Response};
-use crispy_common::MAX_DATA_BLOCK_SIZE;
+use crispy_common::flash::BankVerify;
+use crispy_common::ihex::{self, IhexError};
+use crispy_common::image::ImageBuilder;
+use crispy_common::memory_layout::{FW_COPY_SIZE, FW_RAM_BASE};
+use crispy_common::protocol::{
+    AckStatus, BootData, Command, Response, BOOT_DATA_SIZE, DEFAULT_MAX_BOOT_ATTEMPTS,
+    MAX_BOOT_ATTEMPTS_RANGE,
+};
+use crispy_common::provision::{PROVISION_SIZE, SERIAL_LEN};
+use crispy_common::uf2::{self, Uf2Error, RP2040_FAMILY_ID, UF2_BLOCK_SIZE};
+use crispy_common::vector_table::{VectorTable, FW_RAM_WINDOW};
+use crispy_common::version::{self, VERSION_BLOCK_OFFSET};
+use crispy_common::{crc32, image};
+use crispy_common::{FlashLayout, FwVersion, FLASH_PAGE_SIZE, FLASH_SECTOR_SIZE};
+use crispy_common::{Provision, ProvisionError};
+use crispy_common::{FW_A_ADDR, FW_B_ADDR};
+use crispy_common::{FW_BANK_SIZE, MAX_DATA_BLOCK_SIZE};
 
-use crate::transport::Transport;
+use crate::boot_watch::{self, Reappeared, WatchOptions};
+use crate::elf;
+use crate::history;
+use crate::link::{self, LinkOptions};
+use crate::output::{self, out, outln, Failure, Progress};
+use crate::overrides::{Overrides, Protection};
+use crate::probe::{self, Verdict};
+use crate::source::{self, Sha256Digest};
+use crate::transport::{self, DeviceKind, RetryPolicy, Stats, Transport};
 
-const CRC32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-const CHUNK_SIZE: usize = MAX_DATA_BLOCK_SIZE;
+/// Bytes per `DataBlock` and per `ReadFlash` unless `--chunk-size` says otherwise.
+pub const DEFAULT_CHUNK_SIZE: u32 = MAX_DATA_BLOCK_SIZE as u32;
+
+/// Largest gap between Intel HEX records filled with 0xFF unless
+/// `--max-gap` says otherwise: a flash sector.
+pub const DEFAULT_MAX_GAP: u32 = FLASH_SECTOR_SIZE;
+
+/// How to read a firmware file.
+pub struct InputOptions {
+    /// Accept UF2 files for any chip family.
+    pub any_family: bool,
+    /// Largest gap between Intel HEX records to fill with 0xFF.
+    pub max_gap: u32,
+    /// SHA-256 the file must have, as read (see [`source`]).
+    pub sha256: Option<Sha256Digest>,
+    /// Bank size to check the image against where no device reports one,
+    /// [`FW_BANK_SIZE`] unless the target was built with another layout.
+    pub bank_size: u32,
+}
+
+/// How to upload a firmware file.
+pub struct UploadOptions {
+    /// Version to record [default: from the file, else 0.0.1].
+    pub version: Option<FwVersion>,
+    /// Bytes per `DataBlock` and per `ReadFlash`.
+    pub chunk_size: u32,
+    /// Read the bank back afterwards and compare.
+    pub verify: bool,
+    pub input: InputOptions,
+    /// Checks to go past: the pre-flight checks of the image (see
+    /// [`preflight`]) and the one of a confirmed active bank.
+    pub overrides: Overrides,
+    /// Upload even when the active bank already holds the image (see
+    /// [`up_to_date`]).
+    pub always: bool,
+}
 
 /// Get and display bootloader status.
 pub fn status(transport: &mut Transport) -> Result<()> {
     let response = transport.send_recv(&Command::GetStatus)?;
+    let legacy = transport.legacy_status();
+    output::report(json!({ "status": status_json(&response, legacy) }));
 
     match response {
         Response::Status {
@@ -29,92 +88,661 @@ pub fn status(transport: &mut Transport) -> Result<()> {
             version_a,
             version_b,
             state,
+            host_connected,
+            tx_stalled,
+            bootdata_reconstructed,
+            update_interrupted,
         } => {
-            println!("Bootloader Status:");
-            println!(
+            outln!("Bootloader Status:");
+            outln!(
                 "  Active bank: {} ({})",
                 active_bank,
                 if active_bank == 0 { "A" } else { "B" }
             );
-            println!("  Version A:   {}", version_a);
-            println!("  Version B:   {}", version_b);
-            println!("  State:       {:?}", state);
+            outln!("  Version A:   {}", version_a);
+            outln!("  Version B:   {}", version_b);
+            outln!("  State:       {:?}", state);
+            if legacy {
+                outln!("  Host (DTR):  unsupported by this bootloader");
+                return Ok(());
+            }
+            outln!("  Host (DTR):  {}", host_connected);
+            if tx_stalled {
+                outln!("  Warning:     device dropped responses (TX stalled)");
+            }
+            if bootdata_reconstructed {
+                outln!("  Warning:     boot data was corrupt and rebuilt from the banks");
+            }
+            if update_interrupted {
+                outln!("  Warning:     last update was interrupted by a reset; retry the upload");
+            }
         }
         Response::Ack(status) => {
-            println!("Unexpected ACK response: {:?}", status);
+            outln!("Unexpected ACK response: {:?}", status);
+        }
+        Response::Nack { status, offset } => {
+            outln!("Unexpected NACK response: {:?} at 0x{:08x}", status, offset);
         }
+        other => outln!("Unexpected response: {:?}", other),
+    }
+
+    Ok(())
+}
+
+/// How long `info` waits for a query an older bootloader may drop.
+const OPTIONAL_QUERY_TIMEOUT_MS: u64 = 1_000;
+
+/// Print everything the device reports about itself, for support tickets.
+///
+/// Queries an older bootloader does not know are reported as unsupported
+/// (`null` in JSON) rather than failing the report. A bootloader whose
+/// `Status` predates the status flags knows none of them, so they are not
+/// sent at all.
+pub fn info(transport: &mut Transport) -> Result<()> {
+    let status = transport.send_recv(&Command::GetStatus)?;
+    if !matches!(status, Response::Status { .. }) {
+        bail!(unexpected(&status));
     }
+    let legacy = transport.legacy_status();
+    let mut query = |cmd: Command| match legacy {
+        true => Ok(None),
+        false => optional_query(transport, &cmd),
+    };
+    let boot_data = query(Command::GetBootData)?;
+    let flash = query(Command::GetFlashInfo)?;
+    let device = query(Command::GetDeviceInfo)?;
+    let provision = query(Command::GetProvision)?;
+    let provision = provision.as_ref().map(|response| match response {
+        Response::Provision { raw } => decode_provision(raw),
+        _ => unreachable!("checked by optional_query"),
+    });
 
+    output::report(json!({
+        "port": transport.port_name(),
+        "status": status_json(&status, legacy),
+        "boot_data": boot_data.as_ref().map(boot_data_json),
+        "flash": flash.as_ref().map(flash_info_json),
+        "device": device.as_ref().map(device_info_json),
+        "provision": provision.map(|record| match record {
+            Ok(record) => provision_json(&record),
+            Err(err) => json!({ "error": format!("{:?}", err) }),
+        }),
+    }));
+    if output::is_json() {
+        return Ok(());
+    }
+
+    outln!("Port:          {}", transport.port_name());
+    print_status(&status, legacy);
+    outln!();
+    outln!("Device:");
+    match &device {
+        Some(Response::DeviceInfo {
+            bootloader_version,
+            chip_id,
+            flash_uid,
+            last_error,
+        }) => {
+            outln!("  Bootloader:  {}", bootloader_version);
+            outln!(
+                "  Chip ID:     0x{:08x} (part 0x{:04x}, revision {})",
+                chip_id,
+                (chip_id >> 12) & 0xFFFF,
+                chip_id >> 28
+            );
+            outln!("  Flash UID:   {:016x}", flash_uid);
+            match last_error {
+                Some(status) => outln!("  Last error:  {:?}", status),
+                None => outln!("  Last error:  none"),
+            }
+        }
+        _ => outln!("  unsupported by this bootloader"),
+    }
+    match &flash {
+        Some(Response::FlashInfo {
+            jedec_id,
+            detected_size,
+            layout_size,
+            ..
+        }) => {
+            outln!("  Flash JEDEC: 0x{:06x}", jedec_id);
+            outln!(
+                "  Flash size:  {} KB detected, layout for {} KB",
+                detected_size / 1024,
+                layout_size / 1024
+            );
+        }
+        _ => outln!("  Flash info:  unsupported"),
+    }
+    match provision {
+        Some(Ok(record)) => outln!("  Identity:    {}", describe_provision(&record)),
+        Some(Err(ProvisionError::Blank)) => outln!("  Identity:    not provisioned"),
+        Some(Err(err)) => outln!("  Identity:    invalid record ({:?})", err),
+        None => outln!("  Identity:    unsupported"),
+    }
+    outln!();
+    outln!("BootData:");
+    match &boot_data {
+        Some(Response::BootData { raw, banks }) => print_boot_data(raw, banks),
+        _ => outln!("  unsupported by this bootloader"),
+    }
     Ok(())
 }
 
-/// Upload firmware to the specified bank.
-pub fn upload(transport: &mut Transport, file: &Path, bank: u8, version: u32) -> Result<()> {
-    // Read firmware file
-    let firmware = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+/// Queries the device turned out not to know, so `--watch` stops asking.
+#[derive(Default)]
+pub struct Unsupported {
+    pub boot_data: bool,
+    pub device_info: bool,
+}
+
+/// One sample of what `status --watch` shows, field by field in display
+/// order: the status, BootData's confirmed flag and boot attempts, and with
+/// `info` the bank checks and the last error as well.
+pub fn status_sample(
+    transport: &mut Transport,
+    info: bool,
+    unsupported: &mut Unsupported,
+) -> Result<Vec<(&'static str, serde_json::Value)>> {
+    let status = transport.send_recv(&Command::GetStatus)?;
+    let Response::Status {
+        active_bank,
+        version_a,
+        version_b,
+        state,
+        host_connected,
+        tx_stalled,
+        bootdata_reconstructed,
+        update_interrupted,
+    } = status
+    else {
+        bail!(unexpected(&status));
+    };
+    // Such a bootloader has no flags and none of the queries either
+    let legacy = transport.legacy_status();
+    if legacy {
+        unsupported.boot_data = true;
+        unsupported.device_info = true;
+    }
+    let flag = |value: bool| if legacy { json!(null) } else { json!(value) };
+    let mut fields = vec![
+        ("active_bank", json!(active_bank)),
+        ("state", json!(format!("{:?}", state))),
+        ("version_a", json!(version_a.to_string())),
+        ("version_b", json!(version_b.to_string())),
+        ("host_connected", flag(host_connected)),
+        ("tx_stalled", flag(tx_stalled)),
+        ("bootdata_reconstructed", flag(bootdata_reconstructed)),
+        ("update_interrupted", flag(update_interrupted)),
+    ];
+
+    if !unsupported.boot_data {
+        match optional_query(transport, &Command::GetBootData)? {
+            Some(Response::BootData { raw, banks }) => {
+                if let Ok(bd) = decode_boot_data(&raw) {
+                    fields.push(("confirmed", json!(bd.confirmed)));
+                    fields.push(("boot_attempts", json!(bd.boot_attempts)));
+                    fields.push(("attempt_limit", json!(bd.boot_attempt_limit())));
+                }
+                if info {
+                    fields.push(("check_a", json!(bank_verdict(&banks[0]))));
+                    fields.push(("check_b", json!(bank_verdict(&banks[1]))));
+                }
+            }
+            _ => unsupported.boot_data = true,
+        }
+    }
+    if info && !unsupported.device_info {
+        match optional_query(transport, &Command::GetDeviceInfo)? {
+            Some(Response::DeviceInfo { last_error, .. }) => {
+                let last_error = last_error.map(|status| format!("{:?}", status));
+                fields.push(("last_error", json!(last_error)));
+            }
+            _ => unsupported.device_info = true,
+        }
+    }
+    Ok(fields)
+}
+
+/// Send a query older bootloaders may not know: current ones answer
+/// `BadCommand`, older ones drop the frame. Either is `None`.
+///
+/// Other answers, such as a `BootData` that came after its own query gave
+/// up, are discarded. `GetBootData` checks both banks before it answers,
+/// without a word for the first second, so it gets the full check timeout.
+pub fn optional_query(transport: &mut Transport, cmd: &Command) -> Result<Option<Response>> {
+    let timeout = match cmd {
+        Command::GetBootData => transport.timeout_for(cmd),
+        _ => Duration::from_millis(OPTIONAL_QUERY_TIMEOUT_MS),
+    };
+    let expected = |response: &Response| match cmd {
+        _ if *response == Response::Ack(AckStatus::BadCommand) => true,
+        Command::GetBootData => matches!(response, Response::BootData { .. }),
+        Command::GetFlashInfo => matches!(response, Response::FlashInfo { .. }),
+        Command::GetDeviceInfo => matches!(response, Response::DeviceInfo { .. }),
+        Command::GetProvision => matches!(response, Response::Provision { .. }),
+        Command::BenchData { .. } => matches!(response, Response::Ack(AckStatus::Ok)),
+        _ => true,
+    };
+    match transport.send_recv_expecting(cmd, timeout, expected) {
+        Ok(Response::Ack(AckStatus::BadCommand)) | Err(_) => Ok(None),
+        Ok(response) => Ok(Some(response)),
+    }
+}
+
+fn print_status(status: &Response, legacy: bool) {
+    let Response::Status {
+        active_bank,
+        state,
+        host_connected,
+        tx_stalled,
+        bootdata_reconstructed,
+        update_interrupted,
+        ..
+    } = status
+    else {
+        return;
+    };
+    outln!(
+        "Active bank:   {} ({})",
+        active_bank,
+        if *active_bank == 0 { "A" } else { "B" }
+    );
+    outln!("State:         {:?}", state);
+    if legacy {
+        outln!("Status flags:  unsupported by this bootloader");
+        return;
+    }
+    outln!("Host (DTR):    {}", host_connected);
+    outln!("TX stalled:    {}", tx_stalled);
+    outln!("Reconstructed: {}", bootdata_reconstructed);
+    outln!("Interrupted:   {}", update_interrupted);
+}
+
+fn print_boot_data(raw: &[u8], banks: &[BankVerify; 2]) {
+    for (i, line) in raw.chunks(16).enumerate() {
+        let label = if i == 0 { "Raw:" } else { "" };
+        outln!("  {:<12} {:02x?}", label, line);
+    }
+
+    match decode_boot_data(raw) {
+        Ok(bd) => {
+            outln!("  Record:      valid (layout v{})", bd.layout_version);
+            outln!("  Magic:       0x{:08x}", bd.magic);
+            outln!(
+                "  Active bank: {} ({})",
+                bd.active_bank,
+                if bd.active_bank == 0 { "A" } else { "B" }
+            );
+            outln!("  Confirmed:   {}", bd.confirmed);
+            outln!(
+                "  Attempts:    {} of {}",
+                bd.boot_attempts,
+                bd.boot_attempt_limit()
+            );
+            outln!("  Flags:       0x{:02x}", bd.flags);
+            for (name, version, size, crc) in [
+                ("A", bd.version_a, bd.size_a, bd.crc_a),
+                ("B", bd.version_b, bd.size_b, bd.crc_b),
+            ] {
+                outln!(
+                    "  Bank {}:      version {}, {} bytes, CRC32 0x{:08x}",
+                    name,
+                    version,
+                    size,
+                    crc
+                );
+            }
+            outln!("  Record CRC:  0x{:08x}", bd.record_crc);
+            for issue in bd.issues() {
+                outln!("  Issue:       {:?}", issue);
+            }
+        }
+        Err(err) => outln!("  Record:      invalid ({})", err),
+    }
+
+    for (name, verify) in ["A", "B"].iter().zip(banks) {
+        outln!("  Check {}:     {}", name, bank_verdict(verify));
+    }
+}
+
+fn bank_verdict(verify: &BankVerify) -> String {
+    match verify {
+        BankVerify::Ok { .. } => "valid".to_string(),
+        BankVerify::NoMetadata => "empty".to_string(),
+        BankVerify::SizeOutOfRange(size) => format!("size {} does not fit a bank", size),
+        BankVerify::CrcMismatch { expected, computed } => format!(
+            "CRC mismatch (expected 0x{:08x}, computed 0x{:08x})",
+            expected, computed
+        ),
+        BankVerify::BadBank(bank) => format!("bad bank {}", bank),
+    }
+}
+
+/// Decode a BootData record as `GetBootData` reports it.
+pub fn decode_boot_data(raw: &[u8]) -> Result<BootData> {
+    let raw: &[u8; BOOT_DATA_SIZE] = raw
+        .try_into()
+        .with_context(|| format!("{} bytes, expected {}", raw.len(), BOOT_DATA_SIZE))?;
+    BootData::from_bytes(raw).map_err(|e| anyhow::anyhow!("{:?}", e))
+}
+
+/// `status` as JSON; the flags of a `legacy` status are `null`.
+fn status_json(status: &Response, legacy: bool) -> serde_json::Value {
+    let Response::Status {
+        active_bank,
+        version_a,
+        version_b,
+        state,
+        host_connected,
+        tx_stalled,
+        bootdata_reconstructed,
+        update_interrupted,
+    } = status
+    else {
+        return serde_json::Value::Null;
+    };
+    let flag = |value: &bool| if legacy { json!(null) } else { json!(value) };
+    serde_json::json!({
+        "active_bank": active_bank,
+        "version_a": version_a.to_string(),
+        "version_b": version_b.to_string(),
+        "state": format!("{:?}", state),
+        "host_connected": flag(host_connected),
+        "tx_stalled": flag(tx_stalled),
+        "bootdata_reconstructed": flag(bootdata_reconstructed),
+        "update_interrupted": flag(update_interrupted),
+    })
+}
+
+fn boot_data_json(response: &Response) -> serde_json::Value {
+    let Response::BootData { raw, banks } = response else {
+        return serde_json::Value::Null;
+    };
+    let hex: String = raw.iter().map(|b| format!("{:02x}", b)).collect();
+    let (record, error) = match decode_boot_data(raw) {
+        Ok(bd) => (serde_json::to_value(bd).ok(), None),
+        Err(err) => (None, Some(err.to_string())),
+    };
+    serde_json::json!({
+        "raw": hex,
+        "record": record,
+        "error": error,
+        "banks": banks,
+    })
+}
+
+fn flash_info_json(response: &Response) -> serde_json::Value {
+    let Response::FlashInfo {
+        jedec_id,
+        detected_size,
+        layout_size,
+        bank_size,
+        fw_a_addr,
+        fw_b_addr,
+        boot_data_addr,
+    } = response
+    else {
+        return serde_json::Value::Null;
+    };
+    serde_json::json!({
+        "jedec_id": jedec_id,
+        "detected_size": detected_size,
+        "layout_size": layout_size,
+        "bank_size": bank_size,
+        "fw_a_addr": fw_a_addr,
+        "fw_b_addr": fw_b_addr,
+        "boot_data_addr": boot_data_addr,
+    })
+}
+
+fn device_info_json(response: &Response) -> serde_json::Value {
+    let Response::DeviceInfo {
+        bootloader_version,
+        chip_id,
+        flash_uid,
+        last_error,
+    } = response
+    else {
+        return serde_json::Value::Null;
+    };
+    serde_json::json!({
+        "bootloader_version": bootloader_version.to_string(),
+        "chip_id": chip_id,
+        "flash_uid": format!("{:016x}", flash_uid),
+        "last_error": last_error.map(|s| format!("{:?}", s)),
+    })
+}
+
+/// Upload firmware, returning the bank it went to.
+pub fn upload(
+    transport: &mut Transport,
+    file: &Path,
+    bank: Option<u8>,
+    opts: &UploadOptions,
+) -> Result<u8> {
+    let (version, chunk_size) = (opts.version, opts.chunk_size);
+    check_chunk_size(chunk_size)?;
+    let (begun, link) = (Instant::now(), transport.stats());
+
+    let active = active_bank(&transport.send_recv(&Command::GetStatus)?)?;
+    let requested = bank;
+    let bank = upload_bank(requested, active);
+
+    let Firmware {
+        data: firmware,
+        load_addr,
+        version: file_version,
+    } = read_firmware(file, &opts.input)?;
+    if let Some(base) = load_addr {
+        let start = bank_addr(transport, bank)?;
+        if base != start && base != FW_RAM_BASE {
+            outln!(
+                "Warning:  the image is for 0x{:08x}, not bank {} (0x{:08x}) or RAM (0x{:08x})",
+                base,
+                bank,
+                start,
+                FW_RAM_BASE
+            );
+        }
+    }
+    let bank_size = bank_size(transport)?;
+    opts.overrides.check(Protection::ImageChecks, || {
+        check_image(file, &firmware, bank_size)
+    })?;
     let size = firmware.len() as u32;
-    let crc32 = CRC32.checksum(&firmware);
+    let crc32 = crc32::checksum(&firmware);
 
-    println!(
+    outln!(
         "Firmware: {} ({} bytes, CRC32: 0x{:08x})",
         file.display(),
         size,
         crc32
     );
-    println!(
-        "Target:   Bank {} ({})",
+
+    // A headered image is uploaded whole; the device checks the header too
+    let recorded = match image::split(&firmware) {
+        None => file_version,
+        Some(Err(err)) => bail!(Failure::new(
+            "input",
+            format!("{}: invalid image header: {:?}", file.display(), err)
+        )),
+        Some(Ok((payload, header))) => {
+            outln!(
+                "Header:   version {}, board {}, {} bytes of firmware",
+                header.fw_version,
+                header.board_id,
+                payload.len()
+            );
+            Some(header.fw_version)
+        }
+    };
+    let version = pick_version(version, recorded).unwrap_or(FwVersion::from_raw(1));
+
+    if !opts.always && requested.is_none_or(|bank| bank == active) {
+        if let Some(bank) = up_to_date(transport, active, size, crc32, version) {
+            outln!(
+                "Already up to date: bank {} ({}) holds this image, version {}; nothing erased",
+                bank,
+                if bank == 0 { "A" } else { "B" },
+                version
+            );
+            output::report(json!({
+                "file": file,
+                "bank": bank,
+                "active_bank": active,
+                "size": size,
+                "crc32": crc32,
+                "version": version.to_string(),
+                "up_to_date": true,
+                "verified": false,
+            }));
+            if opts.verify {
+                outln!("Reading back bank {}...", bank);
+                verify_readback(transport, bank, &firmware, chunk_size)?;
+                outln!("Read-back matches {} ({} bytes)", file.display(), size);
+                output::report(json!({ "verified": true }));
+            }
+            return Ok(bank);
+        }
+    }
+    if bank == active {
+        opts.overrides.check(Protection::ActiveBank, || {
+            check_active_bank(transport, bank)
+        })?;
+    }
+    outln!(
+        "Target:   Bank {} ({}){}",
         bank,
-        if bank == 0 { "A" } else { "B" }
+        if bank == 0 { "A" } else { "B" },
+        if bank == active {
+            ", the active bank"
+        } else {
+            ", inactive"
+        }
     );
-    println!("Version:  {}", version);
-    println!();
+    outln!("Version:  {}", version);
+    if bank == active {
+        outln!("Warning:  overwriting the active bank leaves no firmware to fall back to");
+    }
+    outln!();
+
+    output::report(json!({
+        "file": file,
+        "bank": bank,
+        "active_bank": active,
+        "size": size,
+        "crc32": crc32,
+        "version": version.to_string(),
+        "up_to_date": false,
+        "verified": false,
+    }));
+
+    // The erase leaves the bank at 0xFF, so blocks of nothing else (build
+    // padding) need not be sent
+    let erased = |chunk: &[u8]| chunk.iter().all(|&b| b == 0xFF);
+    let sparse = firmware.chunks(chunk_size as usize).any(erased);
 
-    // Start update (includes erasing the target bank - can take 30+ seconds)
-    print!("Starting update (erasing bank)... ");
-    std::io::stdout().flush()?;
+    // Start update (includes erasing the target bank; see `erase_timeout`)
+    output::phase("erase");
+    outln!("Starting update (erasing bank)...");
+    let started = Instant::now();
 
-    let response = transport.send_recv_timeout(
+    let pb = Progress::percent("erase")?;
+    let response = transport.send_recv_with_progress(
         &Command::StartUpdate {
             bank,
             size,
             crc32,
             version,
+            sparse,
         },
-        60_000, // 60 second timeout for bank erase
-    )?;
+        |done, total| {
+            pb.set_length(total as u64);
+            pb.set_position(done as u64);
+        },
+    );
+    pb.finish_and_clear();
 
-    match response {
-        Response::Ack(AckStatus::Ok) => println!("OK"),
-        Response::Ack(status) => bail!("StartUpdate failed: {:?}", status),
-        _ => bail!("Unexpected response: {:?}", response),
+    match response? {
+        Response::Ack(AckStatus::Ok) => outln!("Bank erased"),
+        Response::Nack {
+            status: AckStatus::FlashError,
+            offset,
+        } => bail!(Failure::new(
+            "flash",
+            format!(
+                "Flash hardware error at offset 0x{:08x} (erase failed)",
+                offset
+            )
+        )
+        .with("offset", offset)),
+        Response::Ack(AckStatus::LayoutMismatch) => bail!(layout_mismatch()),
+        Response::Ack(status) => bail!(refused("StartUpdate", status)),
+        response => bail!(unexpected(&response)),
     }
 
+    let erase_time = started.elapsed();
+
     // Send data blocks
-    let pb = ProgressBar::new(size as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
-            )?
-            .progress_chars("#>-"),
-    );
-
-    for (i, chunk) in firmware.chunks(CHUNK_SIZE).enumerate() {
-        let offset = (i * CHUNK_SIZE) as u32;
-        let response = transport.send_recv(&Command::DataBlock {
+    let pb = Progress::bytes("write", size)?;
+    let started = Instant::now();
+    let (mut blocks, mut skipped) = (0, 0);
+
+    for (i, chunk) in firmware.chunks(chunk_size as usize).enumerate() {
+        let offset = i as u32 * chunk_size;
+        if erased(chunk) {
+            skipped += chunk.len() as u32;
+            pb.set_position(offset as u64 + chunk.len() as u64);
+            continue;
+        }
+        blocks += 1;
+        let retried = transport.retries();
+        let block = Command::DataBlock {
             offset,
             data: chunk.to_vec(),
-        })?;
+        };
+        let response =
+            transport.send_recv_retry(&block, |r| matches!(r, Response::Ack(AckStatus::CrcError)));
+        let response = match response {
+            Ok(response) => response,
+            Err(err) => {
+                pb.abandon();
+                return Err(err);
+            }
+        };
 
         match response {
             Response::Ack(AckStatus::Ok) => {}
+            // A block re-sent after a timeout is refused when the first copy
+            // got through and only its answer was lost. The next block's
+            // offset settles it, and FinishUpdate checks the whole image.
+            Response::Ack(AckStatus::BadCommand) if transport.retries() > retried => {
+                outln!("Block at offset 0x{:x} was already written", offset);
+            }
+            Response::Nack {
+                status: AckStatus::FlashError,
+                offset: fault,
+            } => {
+                pb.abandon();
+                bail!(flash_error(fault));
+            }
             Response::Ack(status) => {
                 pb.abandon();
-                bail!("DataBlock failed at offset {}: {:?}", offset, status);
+                bail!(Failure::new(
+                    "device",
+                    format!("DataBlock failed at offset {}: {:?}", offset, status)
+                )
+                .with("status", format!("{:?}", status))
+                .with("offset", offset));
             }
             _ => {
                 pb.abandon();
-                bail!("Unexpected response at offset {}: {:?}", offset, response);
+                bail!(Failure::new(
+                    "protocol",
+                    format!("Unexpected response at offset {}: {:?}", offset, response)
+                )
+                .with("offset", offset));
             }
         }
 
@@ -122,93 +750,2867 @@ pub fn upload(transport: &mut Transport, file: &Path, bank: u8, version: u32) ->
     }
 
     pb.finish_with_message("Upload complete");
-    println!();
+    let write_time = started.elapsed();
+    outln!();
 
     // Finish update
-    print!("Finalizing... ");
-    std::io::stdout().flush()?;
+    output::phase("finalize");
+    outln!("Finalizing (checking the image CRC)...");
+    let started = Instant::now();
 
-    let response = transport.send_recv(&Command::FinishUpdate)?;
+    let pb = Progress::percent("finalize")?;
+    let response = transport.send_recv_with_progress(&Command::FinishUpdate, |done, total| {
+        pb.set_length(total as u64);
+        pb.set_position(done as u64);
+    });
+    pb.finish_and_clear();
 
-    match response {
-        Response::Ack(AckStatus::Ok) => println!("OK"),
-        Response::Ack(AckStatus::CrcError) => bail!("CRC verification failed!"),
-        Response::Ack(status) => bail!("FinishUpdate failed: {:?}", status),
-        _ => bail!("Unexpected response: {:?}", response),
+    match response? {
+        Response::Ack(AckStatus::Ok) => outln!("Image CRC OK"),
+        Response::Ack(AckStatus::CrcError) => {
+            bail!(Failure::new("crc", "CRC verification failed!").with("crc32", crc32))
+        }
+        Response::Ack(AckStatus::BadImage) => bail!(Failure::new(
+            "input",
+            "Device rejected the image header (firmware does not match it)"
+        )
+        .with("status", "BadImage")),
+        Response::Ack(status) => bail!(refused("FinishUpdate", status)),
+        response => bail!(unexpected(&response)),
     }
+    let finalize_time = started.elapsed();
 
-    println!();
-    println!("Firmware uploaded successfully!");
-    println!(
-        "Use 'crispy-upload --port {} reboot' to restart the device.",
-        transport.port_name()
+    let mut verify_time = None;
+    if opts.verify {
+        outln!("Reading back bank {}...", bank);
+        let started = Instant::now();
+        verify_readback(transport, bank, &firmware, chunk_size)?;
+        verify_time = Some(started.elapsed());
+        outln!("Read-back matches {} ({} bytes)", file.display(), size);
+        output::report(json!({ "verified": true }));
+    }
+
+    outln!();
+    outln!("Firmware uploaded successfully!");
+    outln!(
+        "Write:    {} blocks of up to {} bytes, {}",
+        blocks,
+        chunk_size,
+        rate(size, write_time)
     );
+    if skipped > 0 {
+        outln!("Skipped:  {} bytes of 0xFF left erased", skipped);
+    }
+    output::report(json!({
+        "chunk_size": chunk_size,
+        "blocks": blocks,
+        "skipped_bytes": skipped,
+        "erase_ms": erase_time.as_millis() as u64,
+        "write_ms": write_time.as_millis() as u64,
+        "write_bytes_per_sec": bytes_per_sec(size, write_time),
+        "verify_ms": verify_time.map(|t| t.as_millis() as u64),
+    }));
+    let mut phases = vec![
+        ("erase", erase_time),
+        ("write", write_time),
+        ("finalize", finalize_time),
+    ];
+    phases.extend(verify_time.map(|t| ("verify", t)));
+    report_summary(transport, &link, begun, size, &phases);
 
-    Ok(())
+    Ok(bank)
 }
 
-/// Set the active bank for the next boot.
-pub fn set_bank(transport: &mut Transport, bank: u8) -> Result<()> {
-    println!(
-        "Setting active bank to {} ({})...",
-        bank,
-        if bank == 0 { "A" } else { "B" }
+/// Upload `file_a` to bank A and `file_b` (or `file_a` again) to bank B over
+/// one connection, then leave bank `activate` active, or with `None` the
+/// bank that was active before.
+///
+/// The inactive bank is written first, so the device keeps firmware to fall
+/// back on until it is done; once that upload has made it active, the other
+/// bank is no longer the active one either. A failed upload stops there.
+pub fn upload_both(
+    transport: &mut Transport,
+    file_a: &Path,
+    file_b: Option<&Path>,
+    activate: Option<u8>,
+    opts: &UploadOptions,
+) -> Result<()> {
+    if activate.is_some_and(|bank| bank > 1) {
+        bail!(invalid_bank());
+    }
+    let before = active_bank(&transport.send_recv(&Command::GetStatus)?)?;
+    let files = [file_a, file_b.unwrap_or(file_a)];
+    let order = [1 - before, before];
+
+    let mut banks = Vec::new();
+    for (step, bank) in order.into_iter().enumerate() {
+        let file = files[bank as usize];
+        let name = if bank == 0 { "A" } else { "B" };
+        output::phase(if bank == 0 { "bank_a" } else { "bank_b" });
+        outln!(
+            "=== Bank {} ({} of 2): {} ===",
+            name,
+            step + 1,
+            file.display()
+        );
+        let (uploaded, data) = output::collect(|| upload(transport, file, Some(bank), opts));
+        if let Err(err) = uploaded {
+            let other = if bank == 0 { "B" } else { "A" };
+            let done = match banks.first() {
+                Some(_) => format!("bank {} is uploaded and active", other),
+                None => format!("bank {} is untouched", other),
+            };
+            return Err(err.context(format!("Bank {} failed; {}", name, done)));
+        }
+        banks.push((bank, file, data));
+        outln!();
+    }
+
+    let active = activate.unwrap_or(before);
+    if active != order[1] {
+        set_bank(transport, active)?;
+        outln!();
+    }
+
+    outln!("Uploaded both banks:");
+    banks.sort_by_key(|(bank, ..)| *bank);
+    for (bank, file, data) in &banks {
+        outln!(
+            "  Bank {}:   {}, {} bytes, CRC32 0x{:08x}, version {}{}",
+            if *bank == 0 { "A" } else { "B" },
+            file.display(),
+            data["size"],
+            data["crc32"].as_u64().unwrap_or_default(),
+            data["version"].as_str().unwrap_or_default(),
+            if data["verified"] == true {
+                ", read back"
+            } else {
+                ""
+            }
+        );
+    }
+    outln!(
+        "  Active:   bank {} ({})",
+        active,
+        if active == 0 { "A" } else { "B" }
     );
+    output::report(json!({
+        "banks": banks.into_iter().map(|(_, _, data)| data).collect::<Vec<_>>(),
+        "active_bank": active,
+    }));
+    Ok(())
+}
 
-    let response = transport.send_recv(&Command::SetActiveBank { bank })?;
+/// Update the device on `port`, starting from the firmware or the
+/// bootloader: enter the bootloader, upload to the inactive bank, then
+/// reboot into the new firmware and check that it comes back.
+pub fn update(
+    port: &str,
+    link: &LinkOptions,
+    file: &Path,
+    upload_opts: &UploadOptions,
+    retry: RetryPolicy,
+    opts: &WatchOptions,
+) -> Result<()> {
+    if link::tcp_address(port).is_some() {
+        match boot_watch::identify(port, link) {
+            Some(DeviceKind::Firmware) => {
+                outln!("Device:   firmware behind {}", port);
+                output::phase("enter_bootloader");
+                out!("Entering the bootloader... ");
+                boot_watch::request_bootloader_tcp(port, link, opts.timeout)?;
+                outln!("OK");
+            }
+            Some(DeviceKind::Bootloader) => outln!("Device:   bootloader behind {}", port),
+            None => bail!(Failure::new(
+                "no_device",
+                format!(
+                    "Neither the firmware nor the bootloader answers on {}",
+                    port
+                )
+            )),
+        }
+        outln!();
+        return update_in_bootloader(port, link, file, upload_opts, retry, opts);
+    }
 
-    match response {
-        Response::Ack(AckStatus::Ok) => {
-            println!("Active bank set successfully.");
-            println!(
-                "Use 'crispy-upload --port {} reboot' to restart the device.",
-                transport.port_name()
-            );
+    let port = match boot_watch::port_id(port)? {
+        Some(id) if id == opts.firmware => {
+            outln!("Device:   firmware on {}", port);
+            output::phase("enter_bootloader");
+            out!("Entering the bootloader... ");
+            let before = boot_watch::ports_besides(port)?;
+            boot_watch::request_bootloader(port, link, opts.timeout)?;
+            let bootloader = boot_watch::wait_for_bootloader(&before, opts)?;
+            outln!("bootloader on {}", bootloader);
+            bootloader
         }
-        Response::Ack(AckStatus::BankInvalid) => bail!("Invalid bank: must be 0 (A) or 1 (B)"),
-        Response::Ack(AckStatus::CrcError) => {
-            bail!("Bank {} has no valid firmware (CRC check failed)", bank)
+        Some(id) if id == opts.bootloader => {
+            outln!("Device:   bootloader on {}", port);
+            port.to_string()
         }
-        Response::Ack(status) => bail!("SetActiveBank failed: {:?}", status),
-        _ => bail!("Unexpected response: {:?}", response),
-    }
+        _ => bail!(Failure::new(
+            "no_device",
+            format!(
+                "{} is neither firmware ({}) nor bootloader ({}); see --firmware-id and --bootloader-id",
+                port, opts.firmware, opts.bootloader
+            )
+        )),
+    };
+    outln!();
+    update_in_bootloader(&port, link, file, upload_opts, retry, opts)
+}
+
+/// The rest of [`update`], once the bootloader is up on `port`.
+fn update_in_bootloader(
+    port: &str,
+    link: &LinkOptions,
+    file: &Path,
+    upload_opts: &UploadOptions,
+    retry: RetryPolicy,
+    opts: &WatchOptions,
+) -> Result<()> {
+    let mut transport = open_with_retry(port, link, opts.timeout)?;
+    transport.set_retry_policy(retry);
+    let bank = upload(&mut transport, file, None, upload_opts)?;
+    outln!();
+    reboot_and_watch(transport, port, bank, opts)
+}
+
+/// Open `port`, retrying for up to `timeout` (or `--wait`, if longer)
+/// while a device that has just enumerated is still being set up (drivers,
+/// permissions), or a bridge still refuses connections.
+fn open_with_retry(port: &str, link: &LinkOptions, timeout: Duration) -> Result<Transport> {
+    let link = LinkOptions {
+        wait: link.wait.max(timeout),
+        ..*link
+    };
+    Transport::open(port, &link)
+}
 
+/// Reboot the device on `port` after an upload to `bank`, and check that
+/// the firmware comes back rather than the bootloader.
+pub fn reboot_and_watch(
+    mut transport: Transport,
+    port: &str,
+    bank: u8,
+    opts: &WatchOptions,
+) -> Result<()> {
+    let tcp = link::tcp_address(port).is_some();
+    let link = transport.link_options();
+    let before = if tcp {
+        Vec::new()
+    } else {
+        boot_watch::ports_besides(port)?
+    };
+    output::phase("reboot");
+    reboot(&mut transport)?;
+    // Let go of the port so the device can re-enumerate under the same name
+    drop(transport);
+
+    out!("Waiting for the device to come back... ");
+    let reappeared = if tcp {
+        boot_watch::wait_for_reboot_tcp(port, &link, opts)?
+    } else {
+        boot_watch::wait_for_reboot(port, &before, opts)?
+    };
+    let firmware_port = match reappeared {
+        Reappeared::Firmware(name) => {
+            outln!("firmware on {}", name);
+            name
+        }
+        Reappeared::Bootloader(name) => {
+            outln!("bootloader on {}", name);
+            bail!(Failure::new(
+                "boot_failed",
+                "The new firmware did not boot; the device is back in the bootloader (see `status`)"
+            )
+            .with("port", name));
+        }
+    };
+
+    if opts.expect_confirm {
+        output::phase("wait_confirm");
+        out!("Waiting for the firmware to confirm its boot... ");
+        boot_watch::wait_for_confirm(&firmware_port, &link, bank, opts.confirm_timeout)?;
+        outln!("OK");
+    }
+    output::report(json!({
+        "firmware_port": firmware_port,
+        "confirmed": opts.expect_confirm,
+    }));
     Ok(())
 }
 
-/// Wipe all firmware banks and reset boot data.
-pub fn wipe(transport: &mut Transport) -> Result<()> {
-    println!("Resetting boot data (invalidates all firmware)...");
+/// Extension of the firmware containers `pack` writes.
+pub const CONTAINER_EXTENSION: &str = "crispy";
+
+/// A firmware file as the raw image the device takes.
+struct Firmware {
+    data: Vec<u8>,
+    /// Address a UF2, ELF or Intel HEX file was built for.
+    load_addr: Option<u32>,
+    /// Version recorded in the file: an ELF version section or symbol, a
+    /// container header or a version block in the image.
+    version: Option<FwVersion>,
+}
 
-    let response = transport.send_recv(&Command::WipeAll)?;
+/// Read a firmware file, flattening UF2, ELF and Intel HEX files into the
+/// raw image they hold, and find the version it records.
+fn read_firmware(file: &Path, input: &InputOptions) -> Result<Firmware> {
+    let mut firmware = load_firmware(file, input)?;
+    if firmware.version.is_some() {
+        return Ok(firmware);
+    }
+    if let Some(Ok((_, header))) = image::split(&firmware.data) {
+        firmware.version = Some(header.fw_version);
+    } else if let Some(v) = version::embedded_version(&firmware.data) {
+        outln!(
+            "Embedded: version {} at offset 0x{:x}",
+            v,
+            VERSION_BLOCK_OFFSET
+        );
+        firmware.version = Some(v);
+    }
+    Ok(firmware)
+}
 
-    match response {
-        Response::Ack(AckStatus::Ok) => {
-            println!("Boot data reset. Firmware banks marked as invalid.");
-            println!("Device is now in update mode, ready for firmware upload.");
+/// `--version` if given, else the version the file records; a warning when
+/// both are there and disagree.
+fn pick_version(given: Option<FwVersion>, recorded: Option<FwVersion>) -> Option<FwVersion> {
+    match (given, recorded) {
+        (Some(given), Some(recorded)) if given != recorded => {
+            outln!(
+                "Warning:  --version {} overrides version {} recorded in the file",
+                given,
+                recorded
+            );
+            Some(given)
         }
-        Response::Ack(AckStatus::BadState) => {
-            bail!("Cannot wipe: device is not in idle state (upload in progress?)")
+        (given, recorded) => given.or(recorded),
+    }
+}
+
+fn load_firmware(file: &Path, input: &InputOptions) -> Result<Firmware> {
+    let bytes = source::read(file, input.sha256.as_ref())?;
+    let container = file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(CONTAINER_EXTENSION));
+    if container {
+        return read_container(file, bytes);
+    }
+    if elf::is_elf(&bytes) {
+        let image = elf::load(&bytes, input.bank_size)
+            .map_err(|err| Failure::new("input", format!("{}: {:#}", file.display(), err)))?;
+        outln!(
+            "ELF:      {} segments, {} bytes for 0x{:08x}",
+            image.segments,
+            image.data.len(),
+            image.base
+        );
+        if let Some((version, source)) = image.version {
+            outln!("          version {} from {}", version, source);
         }
-        Response::Ack(status) => bail!("Wipe failed: {:?}", status),
-        _ => bail!("Unexpected response: {:?}", response),
+        return Ok(Firmware {
+            data: image.data,
+            load_addr: Some(image.base),
+            version: image.version.map(|(v, _)| v),
+        });
+    }
+    if ihex::is_ihex(&bytes) {
+        return read_ihex(file, &bytes, input.max_gap);
+    }
+    if !uf2::is_uf2(&bytes) {
+        return Ok(Firmware {
+            data: bytes,
+            load_addr: None,
+            version: None,
+        });
+    }
+
+    let family = (!input.any_family).then_some(RP2040_FAMILY_ID);
+    let image = uf2::parse(&bytes, family).map_err(|err| match err {
+        Uf2Error::WrongFamily { family_id, .. } => Failure::new(
+            "input",
+            format!(
+                "{}: UF2 family 0x{:08x} is not RP2040 (--any-family to accept it)",
+                file.display(),
+                family_id
+            ),
+        )
+        .with("family_id", family_id),
+        err => Failure::new(
+            "input",
+            format!("{}: invalid UF2: {:?}", file.display(), err),
+        ),
+    })?;
+    outln!(
+        "UF2:      {} blocks, {} bytes for 0x{:08x}",
+        bytes.len() / UF2_BLOCK_SIZE,
+        image.data.len(),
+        image.base
+    );
+    Ok(Firmware {
+        data: image.data,
+        load_addr: Some(image.base),
+        version: None,
+    })
+}
+
+/// A container is uploaded as it is, header included, so it must carry a
+/// valid one.
+fn read_container(file: &Path, bytes: Vec<u8>) -> Result<Firmware> {
+    let version = match image::split(&bytes) {
+        Some(Ok((_, header))) => header.fw_version,
+        Some(Err(err)) => bail!(Failure::new(
+            "input",
+            format!("{}: invalid image header: {:?}", file.display(), err)
+        )),
+        None => bail!(Failure::new(
+            "input",
+            format!(
+                "{}: not a firmware container (no image header)",
+                file.display()
+            )
+        )),
+    };
+    Ok(Firmware {
+        data: bytes,
+        load_addr: None,
+        version: Some(version),
+    })
+}
+
+/// Wrap a firmware file in a container for `upload`: the raw image followed
+/// by an image header with its length, CRC32, version and board.
+pub fn pack(
+    file: &Path,
+    output: &Path,
+    version: Option<FwVersion>,
+    board_id: u16,
+    input: &InputOptions,
+) -> Result<()> {
+    let Firmware {
+        data,
+        version: file_version,
+        ..
+    } = read_firmware(file, input)?;
+    if image::split(&data).is_some() {
+        bail!(Failure::new(
+            "input",
+            format!("{} already ends with an image header", file.display())
+        ));
+    }
+    let Some(version) = pick_version(version, file_version) else {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!("{} records no version; pass --version", file.display())
+        ));
+    };
+
+    let packed = ImageBuilder::new(version).board_id(board_id).wrap(&data);
+    if packed.len() > input.bank_size as usize {
+        bail!(Failure::new(
+            "input",
+            format!(
+                "{} bytes with the header do not fit a {}-byte bank",
+                packed.len(),
+                input.bank_size
+            )
+        ));
     }
+    fs::write(output, &packed).with_context(|| format!("Failed to write {}", output.display()))?;
 
+    let crc = crc32::checksum(&data);
+    outln!("Packed:   {} -> {}", file.display(), output.display());
+    outln!("Firmware: {} bytes, CRC32 0x{:08x}", data.len(), crc);
+    outln!("Header:   version {}, board {}", version, board_id);
+    output::report(json!({
+        "output": output,
+        "size": packed.len(),
+        "payload_size": data.len(),
+        "image_crc": crc,
+        "version": version.to_string(),
+        "board_id": board_id,
+    }));
     Ok(())
 }
 
-/// Reboot the device.
-pub fn reboot(transport: &mut Transport) -> Result<()> {
-    print!("Rebooting device... ");
-    std::io::stdout().flush()?;
+/// Read a firmware file as `upload` would and check that it fits a bank,
+/// without a device.
+pub fn check_firmware(file: &Path, input: &InputOptions) -> Result<()> {
+    let size = read_firmware(file, input)?.data.len() as u32;
+    if size == 0 || size > input.bank_size {
+        bail!(Failure::new(
+            "input",
+            format!(
+                "{}: {} bytes does not fit a {}-byte bank",
+                file.display(),
+                size,
+                input.bank_size
+            )
+        ));
+    }
+    Ok(())
+}
 
-    let response = transport.send_recv(&Command::Reboot)?;
+/// A pre-flight check that an upload failed, before anything was erased.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Check {
+    /// The file does not exist.
+    Missing,
+    /// The file holds no firmware.
+    Empty,
+    /// The firmware does not fit a bank.
+    TooLarge,
+    /// The first 8 bytes are not a vector table the bootloader runs.
+    VectorTable,
+    /// The firmware is longer than the part copied to RAM at boot.
+    RamCopy,
+    /// The target is the active bank and its firmware is confirmed.
+    ActiveBank,
+}
 
-    match response {
-        Response::Ack(AckStatus::Ok) => println!("OK"),
-        Response::Ack(status) => bail!("Reboot failed: {:?}", status),
-        _ => bail!("Unexpected response: {:?}", response),
+impl Check {
+    /// The `context.check` of the `preflight` error.
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::Missing => "missing",
+            Check::Empty => "empty",
+            Check::TooLarge => "too_large",
+            Check::VectorTable => "vector_table",
+            Check::RamCopy => "ram_copy",
+            Check::ActiveBank => "active_bank",
+        }
+    }
+
+    /// Exit code for a failed check, 10 and up, so scripts can tell them
+    /// apart without parsing messages.
+    pub fn exit_code(self) -> i32 {
+        10 + self as i32
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Check::Missing,
+            Check::Empty,
+            Check::TooLarge,
+            Check::VectorTable,
+            Check::RamCopy,
+            Check::ActiveBank,
+        ]
+        .into_iter()
+        .find(|check| check.name() == name)
+    }
+
+    fn fail(self, message: String) -> Failure {
+        Failure::new("preflight", message).with("check", self.name())
+    }
+}
+
+/// The exit code for `err` if it is a failed pre-flight check.
+pub fn preflight_exit_code(err: &anyhow::Error) -> Option<i32> {
+    let failure = err.downcast_ref::<Failure>()?;
+    if failure.code != "preflight" {
+        return None;
+    }
+    Check::from_name(failure.context["check"].as_str()?).map(Check::exit_code)
+}
+
+/// The image header `file` carries, if it is a headered image.
+pub fn image_header(file: &Path, input: &InputOptions) -> Result<Option<image::ImageHeader>> {
+    let firmware = read_firmware(file, input)?.data;
+    match image::split(&firmware) {
+        None => Ok(None),
+        Some(Ok((_, header))) => Ok(Some(header)),
+        Some(Err(err)) => bail!(Failure::new(
+            "input",
+            format!("{}: invalid image header: {:?}", file.display(), err)
+        )),
+    }
+}
+
+/// Check a firmware file before the port is opened: it exists, and the
+/// image passes [`check_image`] against the given bank size.
+pub fn preflight(file: &Path, input: &InputOptions) -> Result<()> {
+    if !source::is_stream(file) && !file.exists() {
+        bail!(Check::Missing.fail(format!("{} does not exist", file.display())));
     }
+    let firmware = read_firmware(file, input)?.data;
+    check_image(file, &firmware, input.bank_size)
+}
 
+/// Check an image the way the device and the bootloader will, so a file
+/// they would refuse fails before the bank is erased: it is not empty,
+/// fits `bank_size`, starts with a vector table into RAM and fits the part
+/// of a bank copied to RAM at boot.
+fn check_image(file: &Path, firmware: &[u8], bank_size: u32) -> Result<()> {
+    let name = file.display();
+    let size = firmware.len() as u32;
+    if size == 0 {
+        bail!(Check::Empty.fail(format!(
+            "{} holds no firmware; check the build output or the --max-gap of a HEX file",
+            name
+        )));
+    }
+    if size > bank_size {
+        bail!(Check::TooLarge.fail(format!(
+            "{} is {} bytes, {} more than the {}-byte bank; shrink the build (opt-level, \
+             features) or check it is the right file",
+            name,
+            size,
+            size - bank_size,
+            bank_size
+        )));
+    }
+    let Some(bytes) = firmware.first_chunk::<8>() else {
+        bail!(Check::VectorTable.fail(format!(
+            "{} is {} bytes, too short for a vector table",
+            name, size
+        )));
+    };
+    let vt = VectorTable::from_bytes(bytes);
+    if let Err(err) = vt.check(FW_RAM_WINDOW) {
+        bail!(Check::VectorTable.fail(format!(
+            "{} does not start with a vector table the bootloader runs ({}); \
+             link it with the firmware memory.x, or pass --skip-checks",
+            name, err
+        )));
+    }
+    // The header at the end is not copied, or needed, at boot
+    let payload = match image::split(firmware) {
+        Some(Ok((payload, _))) => payload.len() as u32,
+        _ => size,
+    };
+    if payload > FW_COPY_SIZE {
+        bail!(Check::RamCopy.fail(format!(
+            "{} is {} bytes, but only the first {} are copied to RAM at boot; \
+             the rest would be missing when it runs",
+            name, payload, FW_COPY_SIZE
+        )));
+    }
     Ok(())
 }
+
+/// Refuse to overwrite `bank`, the active one, if BootData says its
+/// firmware is confirmed: nothing would be left to fall back to.
+fn check_active_bank(transport: &mut Transport, bank: u8) -> Result<()> {
+    // A bootloader without GetBootData cannot tell; the upload warns
+    let Response::BootData { raw, .. } = transport.send_recv(&Command::GetBootData)? else {
+        return Ok(());
+    };
+    match decode_boot_data(&raw) {
+        Ok(bd) if bd.active_bank == bank && bd.confirmed == 1 => bail!(Check::ActiveBank
+            .fail(format!(
+                "Bank {} is active and its firmware confirmed; upload to bank {} and \
+                 `set-bank`, or pass --allow-active-bank",
+                bank,
+                1 - bank
+            ))
+            .with("bank", bank)),
+        _ => Ok(()),
+    }
+}
+
+/// Flatten an Intel HEX file, filling gaps of up to `max_gap` bytes.
+fn read_ihex(file: &Path, bytes: &[u8], max_gap: u32) -> Result<Firmware> {
+    let text = std::str::from_utf8(bytes).map_err(|_| {
+        Failure::new(
+            "input",
+            format!("{}: Intel HEX file is not text", file.display()),
+        )
+    })?;
+    let image = ihex::parse(text, max_gap).map_err(|err| match err {
+        IhexError::Gap { addr, len } => Failure::new(
+            "input",
+            format!(
+                "{}: {} bytes missing before 0x{:08x}, more than --max-gap ({})",
+                file.display(),
+                len,
+                addr,
+                max_gap
+            ),
+        )
+        .with("addr", addr),
+        err => Failure::new(
+            "input",
+            format!("{}: invalid Intel HEX: {:?}", file.display(), err),
+        ),
+    })?;
+    outln!(
+        "HEX:      {} bytes for 0x{:08x}",
+        image.data.len(),
+        image.base
+    );
+    if let Some(entry) = image.entry {
+        outln!("          entry point 0x{:08x}", entry);
+    }
+    Ok(Firmware {
+        data: image.data,
+        load_addr: Some(image.base),
+        version: None,
+    })
+}
+
+/// The active bank reported by a `GetStatus` response.
+pub fn active_bank(status: &Response) -> Result<u8> {
+    match status {
+        Response::Status { active_bank, .. } if *active_bank <= 1 => Ok(*active_bank),
+        Response::Status { active_bank, .. } => bail!("Device reports active bank {}", active_bank),
+        _ => bail!(Failure::new(
+            "protocol",
+            format!("Unexpected response to GetStatus: {:?}", status)
+        )),
+    }
+}
+
+/// The active bank, if the device vouches that it already holds an image of
+/// `size` bytes with CRC `crc32` recorded as `version`, so uploading it
+/// again would change nothing.
+///
+/// `GetBootData` has the device compute the CRC of the bank, not just report
+/// what BootData records, so a bank changed since it was written does not
+/// pass. Anything short of certain, such as a bootloader too old to answer,
+/// a record that does not decode or a bank that fails its check, is `None`.
+fn up_to_date(
+    transport: &mut Transport,
+    active: u8,
+    size: u32,
+    crc32: u32,
+    version: FwVersion,
+) -> Option<u8> {
+    let Ok(Some(Response::BootData { raw, banks })) =
+        optional_query(transport, &Command::GetBootData)
+    else {
+        return None;
+    };
+    let bd = decode_boot_data(&raw).ok()?;
+    let recorded = if active == 0 {
+        bd.version_a
+    } else {
+        bd.version_b
+    };
+    let same = banks[active as usize] == BankVerify::Ok { size, crc: crc32 };
+    (bd.active_bank == active && recorded == version && same).then_some(active)
+}
+
+/// The bank an upload goes to: `requested` if given, else the bank that is
+/// not `active`, so the running firmware stays as a fallback.
+fn upload_bank(requested: Option<u8>, active: u8) -> u8 {
+    requested.unwrap_or(1 - active)
+}
+
+/// Most mismatching regions kept for a report; the counts go on.
+const MAX_REGIONS: usize = 64;
+
+/// Where flash differs from the expected bytes.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Mismatches {
+    /// Bytes that differ.
+    count: u32,
+    /// Runs of differing bytes as `(start, end)` offsets, end exclusive;
+    /// only the first `MAX_REGIONS`.
+    regions: Vec<(u32, u32)>,
+    /// Runs after those.
+    more_regions: u32,
+    /// Offset of the last differing byte.
+    last: Option<u32>,
+}
+
+impl Mismatches {
+    /// Compare `actual` with `expected`, both starting at `offset`.
+    fn compare(&mut self, offset: u32, actual: &[u8], expected: &[u8]) {
+        for (i, (a, b)) in actual.iter().zip(expected).enumerate() {
+            if a != b {
+                self.add(offset + i as u32);
+            }
+        }
+    }
+
+    fn add(&mut self, at: u32) {
+        self.count += 1;
+        let extends = at > 0 && self.last == Some(at - 1);
+        self.last = Some(at);
+        if !extends {
+            if self.regions.len() < MAX_REGIONS {
+                self.regions.push((at, at + 1));
+            } else {
+                self.more_regions += 1;
+            }
+        } else if self.more_regions == 0 {
+            if let Some(region) = self.regions.last_mut() {
+                region.1 = at + 1;
+            }
+        }
+    }
+
+    fn first(&self) -> Option<u32> {
+        self.regions.first().map(|&(start, _)| start)
+    }
+
+    fn region_count(&self) -> u32 {
+        self.regions.len() as u32 + self.more_regions
+    }
+}
+
+/// Read the flash at `addr` and compare it with `expected`, stopping at the
+/// first difference with `first_only`.
+fn compare_flash(
+    transport: &mut Transport,
+    addr: u32,
+    expected: &[u8],
+    chunk: u32,
+    first_only: bool,
+    pb: &Progress,
+) -> Result<Mismatches> {
+    let mut mismatches = Mismatches::default();
+    let read = transport.read_flash(addr, expected.len() as u32, chunk, |offset, data| {
+        mismatches.compare(offset, data, &expected[offset as usize..][..data.len()]);
+        pb.set_position((offset as usize + data.len()) as u64);
+        if first_only && mismatches.count > 0 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    if let Err(err) = read {
+        pb.abandon();
+        return Err(err);
+    }
+    Ok(mismatches)
+}
+
+/// Compare `bank` byte for byte with `firmware`, reporting where it differs.
+fn verify_readback(transport: &mut Transport, bank: u8, firmware: &[u8], chunk: u32) -> Result<()> {
+    let addr = bank_addr(transport, bank)?;
+    let pb = Progress::bytes("verify", firmware.len() as u32)?;
+    let mismatches =
+        compare_flash(transport, addr, firmware, chunk, false, &pb).context("Read-back failed")?;
+    let mismatched = mismatches.count;
+
+    match mismatches.first() {
+        None => {
+            pb.finish_and_clear();
+            Ok(())
+        }
+        Some(first) => {
+            pb.abandon();
+            let message = format!(
+                "Verify failed: {} byte(s) differ, first at offset 0x{:x} (0x{:08x}); \
+                 retry the upload with a smaller --chunk-size (e.g. 256)",
+                mismatched,
+                first,
+                addr + first
+            );
+            bail!(Failure::new("verify", message)
+                .with("offset", first)
+                .with("addr", addr + first)
+                .with("mismatched", mismatched))
+        }
+    }
+}
+
+/// Save a bank, up to the image size recorded in BootData or `length`
+/// bytes, to `output`.
+pub fn download(
+    transport: &mut Transport,
+    bank: u8,
+    output: &Path,
+    length: Option<u32>,
+    chunk_size: u32,
+) -> Result<()> {
+    check_chunk_size(chunk_size)?;
+    if bank > 1 {
+        bail!(invalid_bank());
+    }
+    let (begun, link) = (Instant::now(), transport.stats());
+    let length = match length {
+        Some(length) => length,
+        None => recorded_size(transport, bank)?,
+    };
+    let addr = bank_addr(transport, bank)?;
+
+    outln!(
+        "Reading {} bytes from bank {} (0x{:08x})...",
+        length,
+        bank,
+        addr
+    );
+    let pb = Progress::bytes("read", length)?;
+    let started = Instant::now();
+    let mut image = Vec::with_capacity(length as usize);
+    let read = transport.read_flash(addr, length, chunk_size, |offset, data| {
+        image.extend_from_slice(data);
+        pb.set_position((offset as usize + data.len()) as u64);
+        ControlFlow::Continue(())
+    });
+    if let Err(err) = read {
+        pb.abandon();
+        return Err(err);
+    }
+    pb.finish_and_clear();
+    let read_time = started.elapsed();
+
+    fs::write(output, &image).with_context(|| format!("Failed to write {}", output.display()))?;
+    let crc32 = crc32::checksum(&image);
+    outln!(
+        "Saved {} ({} bytes, CRC32: 0x{:08x})",
+        output.display(),
+        image.len(),
+        crc32
+    );
+    output::report(json!({
+        "file": output,
+        "bank": bank,
+        "addr": addr,
+        "size": image.len(),
+        "crc32": crc32,
+    }));
+    report_summary(transport, &link, begun, length, &[("read", read_time)]);
+    Ok(())
+}
+
+/// Compare a firmware file with what `bank` holds, reading the bank in
+/// `chunk_size` pieces. Differences fail with the `verify` code, listing
+/// the runs of differing bytes; `first_only` stops at the first one.
+pub fn diff(
+    transport: &mut Transport,
+    file: &Path,
+    bank: u8,
+    chunk_size: u32,
+    first_only: bool,
+    input: &InputOptions,
+) -> Result<()> {
+    check_chunk_size(chunk_size)?;
+    if bank > 1 {
+        bail!(invalid_bank());
+    }
+    let (begun, link) = (Instant::now(), transport.stats());
+    let firmware = read_firmware(file, input)?.data;
+    if firmware.is_empty() || firmware.len() > input.bank_size as usize {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "{} is {} bytes; a bank holds 1 to {}",
+                file.display(),
+                firmware.len(),
+                input.bank_size
+            )
+        ));
+    }
+    let addr = bank_addr(transport, bank)?;
+    let size = firmware.len() as u32;
+
+    outln!(
+        "Comparing {} ({} bytes) with bank {} (0x{:08x})...",
+        file.display(),
+        size,
+        bank,
+        addr
+    );
+    let pb = Progress::bytes("read", size)?;
+    let started = Instant::now();
+    let mismatches = compare_flash(transport, addr, &firmware, chunk_size, first_only, &pb)?;
+    pb.finish_and_clear();
+    report_summary(
+        transport,
+        &link,
+        begun,
+        size,
+        &[("read", started.elapsed())],
+    );
+
+    let (Some(first), Some(last)) = (mismatches.first(), mismatches.last) else {
+        outln!("Identical: bank {} matches all {} bytes", bank, size);
+        output::report(json!({
+            "file": file,
+            "bank": bank,
+            "addr": addr,
+            "size": size,
+            "identical": true,
+        }));
+        return Ok(());
+    };
+
+    if first_only {
+        outln!(
+            "Differs:  first at offset 0x{:x} (0x{:08x}); stopped there (--first-only)",
+            first,
+            addr + first
+        );
+    } else {
+        outln!(
+            "Differs:  {} byte(s) in {} region(s), first at offset 0x{:x}, last at 0x{:x}",
+            mismatches.count,
+            mismatches.region_count(),
+            first,
+            last
+        );
+        for &(start, end) in &mismatches.regions {
+            outln!(
+                "  0x{:08x}..0x{:08x}  {} byte(s)",
+                addr + start,
+                addr + end,
+                end - start
+            );
+        }
+        if mismatches.more_regions > 0 {
+            outln!("  ... and {} more region(s)", mismatches.more_regions);
+        }
+    }
+
+    let regions: Vec<_> = mismatches
+        .regions
+        .iter()
+        .map(|&(start, end)| json!({ "offset": start, "length": end - start }))
+        .collect();
+    let mut failure = Failure::new(
+        "verify",
+        format!("{} differs from bank {}", file.display(), bank),
+    )
+    .with("offset", first)
+    .with("addr", addr + first);
+    if !first_only {
+        failure = failure
+            .with("last", last)
+            .with("mismatched", mismatches.count)
+            .with("region_count", mismatches.region_count())
+            .with("regions", regions);
+    }
+    bail!(failure)
+}
+
+/// Compare a firmware file's size and CRC32 with what the device computes
+/// for a bank, without reading the bank back.
+pub fn verify_quick(
+    transport: &mut Transport,
+    file: &Path,
+    bank: u8,
+    input: &InputOptions,
+) -> Result<()> {
+    if bank > 1 {
+        bail!(invalid_bank());
+    }
+    let firmware = read_firmware(file, input)?.data;
+    let (size, crc) = (firmware.len() as u32, crc32::checksum(&firmware));
+    outln!(
+        "File:     {} ({} bytes, CRC32 0x{:08x})",
+        file.display(),
+        size,
+        crc
+    );
+
+    let (raw, banks) = match transport.send_recv(&Command::GetBootData)? {
+        Response::BootData { raw, banks } => (raw, banks),
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to report bank checks; leave out --quick")
+        }
+        other => bail!(unexpected(&other)),
+    };
+    // The device computes the CRC32 over the size BootData records
+    let (bank_size, bank_crc) = match banks[bank as usize] {
+        BankVerify::Ok { size, crc } => (size, crc),
+        BankVerify::CrcMismatch { computed, .. } => {
+            let bd = decode_boot_data(&raw).context("Stored BootData is invalid")?;
+            outln!("Warning:  bank {} does not match its stored CRC32", bank);
+            (if bank == 0 { bd.size_a } else { bd.size_b }, computed)
+        }
+        BankVerify::NoMetadata | BankVerify::SizeOutOfRange(_) | BankVerify::BadBank(_) => {
+            bail!(
+                Failure::new("empty_bank", format!("Bank {} holds no firmware", bank))
+                    .with("bank", bank)
+            )
+        }
+    };
+    outln!(
+        "Bank {}:   {} bytes, CRC32 0x{:08x}",
+        bank,
+        bank_size,
+        bank_crc
+    );
+
+    let fields = [("size", size, bank_size), ("crc32", crc, bank_crc)];
+    for (name, local, device) in fields {
+        let verdict = if local == device { "match" } else { "MISMATCH" };
+        outln!("  {:<8}{}", name, verdict);
+    }
+    let differing: Vec<_> = fields
+        .iter()
+        .filter(|(_, local, device)| local != device)
+        .map(|(name, ..)| *name)
+        .collect();
+    let data = json!({
+        "file": file,
+        "bank": bank,
+        "size": { "file": size, "bank": bank_size },
+        "crc32": { "file": crc, "bank": bank_crc },
+    });
+    if differing.is_empty() {
+        outln!("Match:    bank {} holds {}", bank, file.display());
+        output::report(data);
+        return Ok(());
+    }
+    bail!(Failure::new(
+        "verify",
+        format!("{} differs from bank {}", file.display(), bank)
+    )
+    .with("differing", differing)
+    .with("size", data["size"].clone())
+    .with("crc32", data["crc32"].clone()))
+}
+
+/// Exit status for a failed `diff` or `verify`: 1 when the bank differs, 2
+/// when the comparison could not be made, as `cmp` does, and 3 when the
+/// bank holds no firmware to compare with.
+pub fn compare_exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Failure>() {
+        Some(failure) if failure.code == "verify" => 1,
+        Some(failure) if failure.code == "empty_bank" => 3,
+        _ => 2,
+    }
+}
+
+/// The BootData record the device holds, decoded.
+pub fn stored_boot_data(transport: &mut Transport) -> Result<BootData> {
+    let raw = match transport.send_recv(&Command::GetBootData)? {
+        Response::BootData { raw, .. } => raw,
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to report its BootData")
+        }
+        other => bail!(unexpected(&other)),
+    };
+    decode_boot_data(&raw).context("Stored BootData is invalid")
+}
+
+/// Image size BootData records for `bank`.
+fn recorded_size(transport: &mut Transport, bank: u8) -> Result<u32> {
+    let raw = match transport.send_recv(&Command::GetBootData)? {
+        Response::BootData { raw, .. } => raw,
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader cannot report the image size; pass --length")
+        }
+        other => bail!(unexpected(&other)),
+    };
+    let bd = decode_boot_data(&raw).context("Stored BootData is invalid; pass --length")?;
+    match if bank == 0 { bd.size_a } else { bd.size_b } {
+        0 => bail!(
+            "Bank {} records no image; pass --length to read it anyway",
+            bank
+        ),
+        size => Ok(size),
+    }
+}
+
+/// Start address of `bank` in the layout the bootloader reports, or the
+/// default layout if it cannot.
+pub fn bank_addr(transport: &mut Transport, bank: u8) -> Result<u32> {
+    let addr = match transport.send_recv(&Command::GetFlashInfo)? {
+        Response::FlashInfo {
+            fw_a_addr,
+            fw_b_addr,
+            ..
+        } => [fw_a_addr, fw_b_addr].get(bank as usize).copied(),
+        _ => FlashLayout::DEFAULT.bank_addr(bank),
+    };
+    addr.with_context(|| format!("Invalid bank {}", bank))
+}
+
+/// Bank size the device reports, or the compiled-in one for a bootloader
+/// that does not say.
+pub fn bank_size(transport: &mut Transport) -> Result<u32> {
+    Ok(match optional_query(transport, &Command::GetFlashInfo)? {
+        Some(Response::FlashInfo { bank_size, .. }) => bank_size,
+        _ => FW_BANK_SIZE,
+    })
+}
+
+/// A `--chunk-size` the device accepts for both `DataBlock` and `ReadFlash`,
+/// checked before anything is erased.
+///
+/// The device programs whole pages, so every block but the last must be a
+/// multiple of a page, and it refuses blocks over `MAX_DATA_BLOCK_SIZE`.
+/// The bootloader does not report a larger limit, so that is the ceiling.
+pub fn check_chunk_size(chunk_size: u32) -> Result<()> {
+    let problem = if chunk_size == 0 || !chunk_size.is_multiple_of(FLASH_PAGE_SIZE) {
+        format!(
+            "{} is not a multiple of the {}-byte flash page the device programs",
+            chunk_size, FLASH_PAGE_SIZE
+        )
+    } else if chunk_size > MAX_DATA_BLOCK_SIZE as u32 {
+        format!(
+            "{} is more than the {} bytes the device takes per block",
+            chunk_size, MAX_DATA_BLOCK_SIZE
+        )
+    } else {
+        return Ok(());
+    };
+    bail!(Failure::new(
+        "invalid_argument",
+        format!(
+            "--chunk-size {}: use a multiple of {} up to {}",
+            problem, FLASH_PAGE_SIZE, MAX_DATA_BLOCK_SIZE
+        )
+    )
+    .with("chunk_size", chunk_size))
+}
+
+/// Bytes per second for `bytes` moved in `elapsed`.
+fn bytes_per_sec(bytes: u32, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64().max(1e-3)) as u64
+}
+
+/// `bytes` moved in `elapsed`, as "N.NN s (R.R KB/s)".
+fn rate(bytes: u32, elapsed: Duration) -> String {
+    format!(
+        "{:.2} s ({:.1} KB/s)",
+        elapsed.as_secs_f64(),
+        bytes_per_sec(bytes, elapsed) as f64 / 1024.0
+    )
+}
+
+/// Print and report what a transfer of `bytes` begun at `begun` took: the
+/// wall time of each phase, the throughput over the whole operation and
+/// what the link did since `link` was taken.
+///
+/// Retries are called out on a line of their own, so a poor link shows up
+/// even when the operation succeeds.
+fn report_summary(
+    transport: &Transport,
+    link: &Stats,
+    begun: Instant,
+    bytes: u32,
+    phases: &[(&str, Duration)],
+) {
+    let total = begun.elapsed();
+    let stats = transport.stats().since(link);
+    let round_trip = stats.average_round_trip();
+
+    if stats.retries > 0 {
+        outln!(
+            "Retries:  {} (a flaky cable, hub or port; check the link if this recurs)",
+            stats.retries
+        );
+    }
+    if stats.discarded_frames > 0 {
+        outln!(
+            "Garbage:  {} frames discarded (noise on the link, or another program on the port)",
+            stats.discarded_frames
+        );
+    }
+    outln!("Summary:  {}", summary_line(bytes, total, phases, &stats));
+    let phases_ms: Map<_, _> = phases
+        .iter()
+        .map(|&(name, time)| (name.to_string(), json!(time.as_millis() as u64)))
+        .collect();
+    output::report(json!({
+        "retries": stats.retries,
+        "stats": {
+            "bytes": bytes,
+            "total_ms": total.as_millis() as u64,
+            "phases_ms": phases_ms,
+            "bytes_per_sec": bytes_per_sec(bytes, total),
+            "retries": stats.retries,
+            "bytes_sent": stats.bytes_sent,
+            "bytes_received": stats.bytes_received,
+            "discarded_frames": stats.discarded_frames,
+            "round_trips": stats.round_trips,
+            "avg_round_trip_us": round_trip.map(|t| t.as_micros() as u64),
+        },
+    }));
+}
+
+/// The one-line summary of [`report_summary`].
+fn summary_line(bytes: u32, total: Duration, phases: &[(&str, Duration)], stats: &Stats) -> String {
+    let mut line = format!(
+        "{} bytes in {:.2} s at {:.1} KB/s",
+        bytes,
+        total.as_secs_f64(),
+        bytes_per_sec(bytes, total) as f64 / 1024.0
+    );
+    for (i, (name, time)) in phases.iter().enumerate() {
+        let sep = if i == 0 { ": " } else { ", " };
+        line += &format!("{}{} {:.2} s", sep, name, time.as_secs_f64());
+    }
+    line += &format!(
+        "; {} {}",
+        stats.retries,
+        if stats.retries == 1 {
+            "retry"
+        } else {
+            "retries"
+        }
+    );
+    if let Some(round_trip) = stats.average_round_trip() {
+        line += &format!(
+            ", {:.1} ms per round trip",
+            round_trip.as_secs_f64() * 1000.0
+        );
+    }
+    line
+}
+
+/// `Invalid bank`, for a bank other than 0 or 1.
+fn invalid_bank() -> Failure {
+    Failure::new("invalid_argument", "Invalid bank: must be 0 (A) or 1 (B)")
+}
+
+/// The device refused `command` with `status`.
+pub fn refused(command: &str, status: AckStatus) -> Failure {
+    Failure::new("device", format!("{} failed: {:?}", command, status))
+        .with("status", format!("{:?}", status))
+}
+
+/// A response the command cannot get.
+pub fn unexpected(response: &Response) -> Failure {
+    Failure::new("protocol", format!("Unexpected response: {:?}", response))
+}
+
+/// A flash hardware error the device reported at `offset`.
+fn flash_error(offset: u32) -> Failure {
+    Failure::new(
+        "flash",
+        format!("Flash hardware error at offset 0x{:08x}", offset),
+    )
+    .with("offset", offset)
+}
+
+/// The bootloader's layout does not fit the flash part.
+fn layout_mismatch() -> Failure {
+    Failure::new(
+        "device",
+        "Bootloader layout does not fit this flash chip (see `flash-info`)",
+    )
+    .with("status", "LayoutMismatch")
+}
+
+/// The CRC32 of a firmware file, as the bootloader computes it.
+#[derive(Debug, PartialEq, Eq)]
+struct Checksum {
+    size: u32,
+    crc32: u32,
+    /// Size and CRC32 of the firmware before an image header.
+    payload: Option<(u32, u32)>,
+}
+
+/// Print the size and CRC32 of each of `files`, or of `length` bytes from
+/// `offset` in each: the image as `upload` sends it (UF2, ELF and Intel HEX
+/// flattened), with [`crc32::checksum`], the CRC the bootloader computes
+/// and `status` shows.
+pub fn checksum(
+    files: &[PathBuf],
+    offset: u32,
+    length: Option<u32>,
+    input: &InputOptions,
+) -> Result<()> {
+    let mut results = Vec::new();
+    for file in files {
+        let sum = file_checksum(file, offset, length, input)?;
+        outln!(
+            "{}: {} bytes, CRC32 0x{:08x}",
+            file.display(),
+            sum.size,
+            sum.crc32
+        );
+        if let Some((size, crc32)) = sum.payload {
+            outln!(
+                "  without the image header: {} bytes, CRC32 0x{:08x}",
+                size,
+                crc32
+            );
+        }
+        results.push(json!({
+            "file": file,
+            "offset": offset,
+            "size": sum.size,
+            "crc32": sum.crc32,
+            "payload": sum.payload.map(|(size, crc32)| json!({ "size": size, "crc32": crc32 })),
+        }));
+    }
+    output::report(json!({ "files": results }));
+    Ok(())
+}
+
+fn file_checksum(
+    file: &Path,
+    offset: u32,
+    length: Option<u32>,
+    input: &InputOptions,
+) -> Result<Checksum> {
+    let data = load_firmware(file, input)?.data;
+    let len = data.len() as u32;
+    let end = match length {
+        Some(length) => offset.checked_add(length),
+        None => Some(len),
+    };
+    let Some(range) = end
+        .filter(|&end| offset <= end && end <= len)
+        .map(|end| offset..end)
+    else {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "{}: {} bytes from offset {} go past its {} bytes",
+                file.display(),
+                length.unwrap_or(0),
+                offset,
+                len
+            )
+        ));
+    };
+    let whole = range.len() == data.len();
+    let data = &data[range.start as usize..range.end as usize];
+    // Only a whole image has its header at the end
+    let payload = match image::split(data) {
+        Some(Ok((payload, _))) if whole => Some((payload.len() as u32, crc32::checksum(payload))),
+        _ => None,
+    };
+    Ok(Checksum {
+        size: data.len() as u32,
+        crc32: crc32::checksum(data),
+        payload,
+    })
+}
+
+/// Check a firmware file the way the device will, without a device.
+///
+/// Fails if the device would refuse the upload (size, image header) or the
+/// bootloader would refuse to run it (vector table).
+pub fn inspect(file: &Path, input: &InputOptions) -> Result<()> {
+    outln!("File:     {}", file.display());
+    let Firmware {
+        data: firmware,
+        load_addr,
+        version,
+    } = read_firmware(file, input)?;
+    let size = firmware.len() as u32;
+    let crc32 = crc32::checksum(&firmware);
+    let mut problems = Vec::new();
+    output::report(json!({
+        "file": file,
+        "size": size,
+        "crc32": crc32,
+        "load_addr": load_addr,
+        "version": version.map(|v| v.to_string()),
+    }));
+
+    if let Some(base) = load_addr {
+        if ![FW_A_ADDR, FW_B_ADDR, FW_RAM_BASE].contains(&base) {
+            outln!(
+                "Warning:  the image is for 0x{:08x}, neither a bank nor RAM (0x{:08x})",
+                base,
+                FW_RAM_BASE
+            );
+        }
+    }
+    outln!("Size:     {} bytes (bank size {})", size, input.bank_size);
+    outln!("CRC32:    0x{:08x}", crc32);
+    match version {
+        Some(v) => outln!("Version:  {}", v),
+        None => outln!("Version:  none recorded (pass --version when uploading)"),
+    }
+
+    // Same check as the device applies to StartUpdate
+    let start = Command::StartUpdate {
+        bank: 0,
+        size,
+        crc32,
+        version: FwVersion::default(),
+        sparse: false,
+    };
+    if let Err(err) = start.validate() {
+        problems.push(format!("the device refuses the upload: {:?}", err));
+    }
+    if size > FW_COPY_SIZE {
+        outln!(
+            "Warning:  only the first {} bytes are copied to RAM at boot",
+            FW_COPY_SIZE
+        );
+    }
+
+    match image::split(&firmware) {
+        None => {
+            outln!("Header:   none");
+            output::report(json!({ "header": null }));
+        }
+        Some(Ok((payload, header))) => {
+            outln!(
+                "Header:   version {}, board {}, flags 0x{:02x}, {} bytes of firmware (CRC32 0x{:08x})",
+                header.fw_version,
+                header.board_id,
+                header.flags,
+                payload.len(),
+                header.image_crc
+            );
+            output::report(json!({
+                "header": {
+                    "version": header.fw_version.to_string(),
+                    "board_id": header.board_id,
+                    "flags": header.flags,
+                    "payload_size": payload.len(),
+                    "image_crc": header.image_crc,
+                },
+            }));
+        }
+        Some(Err(err)) => {
+            outln!("Header:   invalid");
+            problems.push(format!("invalid image header: {:?}", err));
+        }
+    }
+
+    match firmware.first_chunk::<8>() {
+        Some(bytes) => {
+            let vt = VectorTable::from_bytes(bytes);
+            outln!(
+                "Vectors:  SP 0x{:08x}, reset 0x{:08x}",
+                vt.initial_sp,
+                vt.reset_vector
+            );
+            output::report(json!({
+                "vectors": { "initial_sp": vt.initial_sp, "reset_vector": vt.reset_vector },
+            }));
+            if let Err(err) = vt.check(FW_RAM_WINDOW) {
+                problems.push(format!("the bootloader will not run it: {}", err));
+            }
+        }
+        None => problems.push("too short for a vector table".to_string()),
+    }
+
+    outln!();
+    if problems.is_empty() {
+        outln!("OK: the device accepts and boots this image");
+        return Ok(());
+    }
+    for problem in &problems {
+        outln!("Problem:  {}", problem);
+    }
+    bail!(
+        Failure::new("rejected", format!("{} would be rejected", file.display()))
+            .with("problems", problems)
+    )
+}
+
+/// Set the active bank for the next boot.
+pub fn set_bank(transport: &mut Transport, bank: u8) -> Result<()> {
+    outln!(
+        "Setting active bank to {} ({})...",
+        bank,
+        if bank == 0 { "A" } else { "B" }
+    );
+
+    let response = transport.send_recv(&Command::SetActiveBank { bank })?;
+
+    match response {
+        Response::Ack(AckStatus::Ok) => {
+            outln!("Active bank set successfully.");
+            output::report(json!({ "active_bank": bank }));
+            outln!(
+                "Use 'crispy-upload --port {} reboot' to restart the device.",
+                transport.port_name()
+            );
+        }
+        Response::Ack(AckStatus::BankInvalid) => bail!(invalid_bank()),
+        Response::Ack(AckStatus::CrcError) => {
+            bail!(Failure::new(
+                "crc",
+                format!("Bank {} has no valid firmware (CRC check failed)", bank)
+            ))
+        }
+        Response::Ack(status) => bail!(refused("SetActiveBank", status)),
+        _ => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Copy one bank's firmware and metadata to the other bank on the device.
+pub fn clone_bank(transport: &mut Transport, from: u8, to: u8) -> Result<()> {
+    outln!("Copying bank {} to bank {}...", from, to);
+
+    let pb = Progress::percent("copy")?;
+    let response =
+        transport.send_recv_with_progress(&Command::CopyBank { from, to }, |done, total| {
+            pb.set_length(total as u64);
+            pb.set_position(done as u64);
+        });
+    pb.finish_and_clear();
+
+    match response? {
+        Response::Ack(AckStatus::Ok) => {
+            outln!("Bank {} now holds a copy of bank {}.", to, from);
+            output::report(json!({ "from": from, "to": to }));
+            outln!("The active bank is unchanged; use `set-bank {}` to boot the copy.", to);
+        }
+        Response::Ack(AckStatus::BankInvalid) => bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "Cannot copy bank {} to bank {}: banks must be 0 (A) or 1 (B) and differ, and the source must hold firmware",
+                from, to
+            )
+        )),
+        Response::Ack(AckStatus::CrcError) => bail!(Failure::new(
+            "crc",
+            format!(
+                "Copy failed: CRC mismatch (bank {} is corrupt or the copy did not verify)",
+                from
+            )
+        )),
+        Response::Ack(AckStatus::BadState) => {
+            bail!("Cannot copy: device is not in idle state (upload in progress?)")
+        }
+        Response::Nack {
+            status: AckStatus::FlashError,
+            offset,
+        } => bail!(flash_error(offset)),
+        Response::Ack(AckStatus::FlashError) => bail!(Failure::new(
+            "flash",
+            "Copied, but BootData could not be written"
+        )),
+        Response::Ack(AckStatus::LayoutMismatch) => bail!(layout_mismatch()),
+        Response::Ack(status) => bail!(refused("CopyBank", status)),
+        response => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Wipe all firmware banks and reset boot data.
+pub fn wipe(transport: &mut Transport) -> Result<()> {
+    outln!("Resetting boot data (invalidates all firmware)...");
+
+    let pb = Progress::percent("wipe")?;
+    let response = transport.send_recv_with_progress(&Command::WipeAll, |done, total| {
+        pb.set_length(total as u64);
+        pb.set_position(done as u64);
+    });
+    pb.finish_and_clear();
+
+    match response? {
+        Response::Ack(AckStatus::Ok) => {
+            outln!("Boot data reset. Firmware banks marked as invalid.");
+            outln!("Device is now in update mode, ready for firmware upload.");
+        }
+        Response::Ack(AckStatus::BadState) => {
+            bail!("Cannot wipe: device is not in idle state (upload in progress?)")
+        }
+        Response::Ack(status) => bail!(refused("Wipe", status)),
+        response => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Query and display the flash part and bank layout.
+pub fn flash_info(transport: &mut Transport) -> Result<()> {
+    let response = transport.send_recv(&Command::GetFlashInfo)?;
+    output::report(json!({ "flash": flash_info_json(&response) }));
+
+    match response {
+        Response::FlashInfo {
+            jedec_id,
+            detected_size,
+            layout_size,
+            bank_size,
+            fw_a_addr,
+            fw_b_addr,
+            boot_data_addr,
+        } => {
+            outln!("Flash Info:");
+            outln!("  JEDEC ID:    0x{:06x}", jedec_id);
+            if detected_size == 0 {
+                outln!("  Detected:    unknown");
+            } else {
+                outln!("  Detected:    {} KB", detected_size / 1024);
+            }
+            outln!("  Layout for:  {} KB", layout_size / 1024);
+            outln!("  Bank size:   {} KB", bank_size / 1024);
+            outln!("  Bank A:      0x{:08x}", fw_a_addr);
+            outln!("  Bank B:      0x{:08x}", fw_b_addr);
+            outln!("  Boot data:   0x{:08x}", boot_data_addr);
+            if detected_size != 0 && layout_size > detected_size {
+                outln!("  Warning:     layout does not fit, flash writes are disabled");
+            }
+        }
+        _ => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// What `blank-check` scans.
+#[derive(Clone, Copy, Debug)]
+pub enum BlankRegion {
+    Bank(u8),
+    /// An absolute flash range.
+    Range {
+        addr: u32,
+        length: u32,
+    },
+}
+
+/// Check that `region` reads back erased (all 0xFF), failing with
+/// `not_blank` when it does not.
+///
+/// The device scans the range itself and reports its progress. A
+/// bootloader without `BlankCheck` answers `BadCommand`; the range is then
+/// read back and scanned here.
+pub fn blank_check(transport: &mut Transport, region: BlankRegion) -> Result<()> {
+    let (name, bank, addr, length) = match region {
+        BlankRegion::Bank(bank) => {
+            if bank > 1 {
+                bail!(invalid_bank());
+            }
+            // Ask the device for its layout rather than assuming the host's defaults
+            let addr = bank_addr(transport, bank)?;
+            let length = bank_size(transport)?;
+            (format!("Bank {}", bank), Some(bank), addr, length)
+        }
+        BlankRegion::Range { addr, length } => {
+            if length == 0 || (Command::BlankCheck { addr, length }).validate().is_err() {
+                bail!(Failure::new(
+                    "invalid_argument",
+                    format!("0x{:08x} + {} bytes is not a flash range", addr, length)
+                ));
+            }
+            (format!("0x{:08x}+0x{:x}", addr, length), None, addr, length)
+        }
+    };
+
+    output::phase("blank_check");
+    outln!(
+        "Blank-checking {} (0x{:08x}, {} bytes)...",
+        name.to_lowercase(),
+        addr,
+        length
+    );
+    let pb = Progress::bytes("blank_check", length)?;
+    let response = transport
+        .send_recv_with_progress(&Command::BlankCheck { addr, length }, |done, _| {
+            pb.set_position(done as u64)
+        });
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            pb.abandon();
+            return Err(err);
+        }
+    };
+    pb.finish_and_clear();
+    let (first_dirty, dirty_bytes, by) = match response {
+        Response::BlankCheckResult {
+            first_dirty,
+            dirty_bytes,
+        } => (first_dirty, dirty_bytes, "device"),
+        Response::Ack(AckStatus::BadCommand) => {
+            outln!("The bootloader cannot blank-check; reading the range back instead");
+            let (first_dirty, dirty_bytes) = scan_blank(transport, addr, length)?;
+            (first_dirty, dirty_bytes, "host")
+        }
+        Response::Ack(status) => bail!(refused("BlankCheck", status)),
+        other => bail!(unexpected(&other)),
+    };
+
+    output::report(json!({
+        "bank": bank,
+        "addr": addr,
+        "length": length,
+        "blank": first_dirty.is_none(),
+        "first_dirty": first_dirty,
+        "dirty_bytes": dirty_bytes,
+        "checked_by": by,
+    }));
+    let Some(offset) = first_dirty else {
+        outln!("blank");
+        return Ok(());
+    };
+    outln!("NOT blank");
+    outln!(
+        "  First dirty offset: 0x{:08x} (address 0x{:08x})",
+        offset,
+        addr + offset
+    );
+    outln!("  Non-blank bytes:    {}", dirty_bytes);
+    bail!(Failure::new(
+        "not_blank",
+        format!(
+            "{} is not blank: {} bytes are not 0xFF, the first at offset 0x{:x}",
+            name, dirty_bytes, offset
+        )
+    )
+    .with("addr", addr)
+    .with("first_dirty", offset)
+    .with("dirty_bytes", dirty_bytes))
+}
+
+/// Read `length` bytes at `addr` back and find the bytes that are not
+/// 0xFF: the offset of the first, and how many.
+fn scan_blank(transport: &mut Transport, addr: u32, length: u32) -> Result<(Option<u32>, u32)> {
+    let pb = Progress::bytes("read", length)?;
+    let (mut first_dirty, mut dirty_bytes) = (None, 0);
+    let read = transport.read_flash(addr, length, DEFAULT_CHUNK_SIZE, |offset, data| {
+        for (i, _) in data.iter().enumerate().filter(|(_, &b)| b != 0xFF) {
+            first_dirty.get_or_insert(offset + i as u32);
+            dirty_bytes += 1;
+        }
+        pb.set_position((offset as usize + data.len()) as u64);
+        ControlFlow::Continue(())
+    });
+    if let Err(err) = read {
+        pb.abandon();
+        return Err(err);
+    }
+    pb.finish_and_clear();
+    Ok((first_dirty, dirty_bytes))
+}
+
+/// Set the number of unconfirmed boots allowed before rollback.
+pub fn set_boot_attempts(transport: &mut Transport, max_attempts: u8) -> Result<()> {
+    if max_attempts != 0 && !MAX_BOOT_ATTEMPTS_RANGE.contains(&max_attempts) {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "Invalid attempt limit: must be {}-{}, or 0 for the default",
+                MAX_BOOT_ATTEMPTS_RANGE.start(),
+                MAX_BOOT_ATTEMPTS_RANGE.end()
+            )
+        ));
+    }
+
+    let response = transport.send_recv(&Command::SetBootAttempts { max_attempts })?;
+    let limit = match max_attempts {
+        0 => DEFAULT_MAX_BOOT_ATTEMPTS,
+        n => n,
+    };
+    if response == Response::Ack(AckStatus::Ok) {
+        output::report(json!({ "max_attempts": limit }));
+    }
+
+    match response {
+        Response::Ack(AckStatus::Ok) if max_attempts == 0 => {
+            outln!(
+                "Boot attempt limit reset to the default ({}).",
+                DEFAULT_MAX_BOOT_ATTEMPTS
+            )
+        }
+        Response::Ack(AckStatus::Ok) => outln!("Boot attempt limit set to {}.", max_attempts),
+        Response::Ack(AckStatus::BadState) => {
+            bail!("Cannot change the limit: upload in progress")
+        }
+        Response::Ack(AckStatus::LayoutMismatch) => bail!(layout_mismatch()),
+        Response::Ack(status) => bail!(refused("SetBootAttempts", status)),
+        _ => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Change the version BootData records for `bank` without touching the
+/// image. Asks first unless `yes`.
+pub fn set_version(
+    transport: &mut Transport,
+    bank: u8,
+    version: FwVersion,
+    yes: bool,
+) -> Result<()> {
+    if bank > 1 {
+        bail!(invalid_bank());
+    }
+    let name = if bank == 0 { "A" } else { "B" };
+
+    let raw = match transport.send_recv(&Command::GetBootData)? {
+        Response::BootData { raw, .. } => raw,
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to change a stored version")
+        }
+        other => bail!(unexpected(&other)),
+    };
+    let bd = decode_boot_data(&raw).context("Stored BootData is invalid")?;
+    let (size, before) = if bank == 0 {
+        (bd.size_a, bd.version_a)
+    } else {
+        (bd.size_b, bd.version_b)
+    };
+    if size == 0 {
+        bail!(Failure::new(
+            "invalid_argument",
+            format!(
+                "Bank {} holds no firmware; nothing to set a version on",
+                name
+            )
+        )
+        .with("bank", bank));
+    }
+
+    output::report(json!({
+        "bank": bank,
+        "before": before.to_string(),
+        "after": version.to_string(),
+    }));
+    outln!("Bank {} version: {} -> {}", name, before, version);
+    if before == version {
+        outln!("Already set; nothing to change.");
+        return Ok(());
+    }
+    if !yes {
+        output::confirm("Rewrite the stored version?")?;
+    }
+
+    let response = transport.send_recv(&Command::SetVersion { bank, version })?;
+    match response {
+        Response::Ack(AckStatus::Ok) => outln!("Bank {} version set to {}.", name, version),
+        Response::Ack(AckStatus::BankInvalid) => bail!(Failure::new(
+            "device",
+            format!("Bank {} holds no firmware", name)
+        )
+        .with("status", "BankInvalid")),
+        Response::Ack(AckStatus::BadState) => {
+            bail!("Cannot change the version: upload in progress")
+        }
+        Response::Ack(AckStatus::FlashError) => {
+            bail!(Failure::new("flash", "BootData could not be written"))
+        }
+        Response::Ack(AckStatus::LayoutMismatch) => bail!(layout_mismatch()),
+        Response::Ack(status) => bail!(refused("SetVersion", status)),
+        _ => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Store a unit's board ID, hardware revision and serial number, then read
+/// the record back to check it. A unit that already has another record
+/// keeps it unless `overrides` allow [`Protection::Provisioned`].
+pub fn provision(
+    transport: &mut Transport,
+    board_id: u16,
+    hw_revision: u8,
+    serial: &str,
+    overrides: Overrides,
+) -> Result<()> {
+    let record = Provision::new(board_id, hw_revision, serial.as_bytes()).map_err(|_| {
+        Failure::new(
+            "invalid_argument",
+            format!(
+                "Invalid serial number {:?}: must be 1 to {} printable ASCII characters",
+                serial, SERIAL_LEN
+            ),
+        )
+    })?;
+
+    let replaced = match stored_provision(transport)? {
+        Ok(stored) if stored == record => {
+            output::report(json!({ "provision": provision_json(&record), "replaced": false }));
+            outln!("Already provisioned: {}", describe_provision(&record));
+            return Ok(());
+        }
+        Err(ProvisionError::Blank) => false,
+        stored => {
+            let held = match stored {
+                Ok(stored) => describe_provision(&stored),
+                Err(err) => format!("an invalid record ({:?})", err),
+            };
+            overrides.check(Protection::Provisioned, || {
+                bail!(Failure::new(
+                    "provisioned",
+                    format!(
+                        "The unit is already provisioned: {}; --reprovision replaces it",
+                        held
+                    )
+                )
+                .with("stored", held))
+            })?;
+            true
+        }
+    };
+
+    let command = Command::SetProvision {
+        board_id,
+        hw_revision,
+        serial: serial.as_bytes().try_into().expect("checked length"),
+    };
+    let response = transport.send_recv(&command)?;
+    match response {
+        Response::Ack(AckStatus::Ok) => {}
+        Response::Ack(AckStatus::BadState) => {
+            bail!("Cannot provision the unit: upload in progress")
+        }
+        Response::Ack(AckStatus::FlashError) => bail!(Failure::new(
+            "flash",
+            "The provisioning record could not be written"
+        )),
+        Response::Ack(status) => bail!(refused("SetProvision", status)),
+        _ => bail!(unexpected(&response)),
+    }
+
+    match stored_provision(transport)? {
+        Ok(stored) if stored == record => {}
+        stored => bail!(Failure::new(
+            "verify",
+            format!("Provisioning record read back wrong: {:?}", stored)
+        )),
+    }
+    output::report(json!({ "provision": provision_json(&record), "replaced": replaced }));
+    outln!("Provisioned: {}", describe_provision(&record));
+    Ok(())
+}
+
+/// The provisioning record as the device stores it.
+fn stored_provision(transport: &mut Transport) -> Result<Result<Provision, ProvisionError>> {
+    match transport.send_recv(&Command::GetProvision)? {
+        Response::Provision { raw } => Ok(decode_provision(&raw)),
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to store a provisioning record")
+        }
+        other => bail!(unexpected(&other)),
+    }
+}
+
+fn decode_provision(raw: &[u8]) -> Result<Provision, ProvisionError> {
+    // A short record cannot match its CRC
+    let raw: &[u8; PROVISION_SIZE] = raw.try_into().map_err(|_| ProvisionError::BadCrc)?;
+    Provision::from_bytes(raw)
+}
+
+fn describe_provision(record: &Provision) -> String {
+    format!(
+        "board {}, revision {}, serial {}",
+        record.board_id,
+        record.hw_revision,
+        record.serial()
+    )
+}
+
+fn provision_json(record: &Provision) -> serde_json::Value {
+    json!({
+        "board": record.board_id,
+        "revision": record.hw_revision,
+        "serial": record.serial(),
+    })
+}
+
+/// Reboot the device.
+pub fn reboot(transport: &mut Transport) -> Result<()> {
+    out!("Rebooting device... ");
+
+    let response = transport.send_recv(&Command::Reboot)?;
+
+    match response {
+        Response::Ack(AckStatus::Ok) => outln!("OK"),
+        Response::Ack(status) => bail!(refused("Reboot", status)),
+        _ => bail!(unexpected(&response)),
+    }
+
+    Ok(())
+}
+
+/// Reboot the device and reconnect once the bootloader is back.
+pub fn reboot_and_stay(transport: &mut Transport) -> Result<()> {
+    reboot(transport)?;
+    output::phase("reboot");
+    outln!("Waiting for the bootloader to come back...");
+    let within = transport.link_options().wait.max(transport::RECONNECT_WAIT);
+    transport
+        .reconnect(within)
+        .context("The bootloader did not come back (did the firmware boot?)")?;
+    match transport.send_recv(&Command::GetStatus)? {
+        Response::Status { .. } => outln!("Bootloader back on {}", transport.port_name()),
+        other => bail!(unexpected(&other)),
+    }
+    output::report(json!({ "port": transport.port_name() }));
+    Ok(())
+}
+
+/// Tell what is on each of `ports`.
+pub fn probe(ports: &[String], link: &LinkOptions) -> Result<()> {
+    if ports.is_empty() {
+        outln!("No USB serial ports found");
+    }
+    let mut entries = Vec::new();
+    for port in ports {
+        let found = probe::probe(port, link);
+        let usb = found.usb.as_ref();
+        if let Some(usb) = usb {
+            outln!(
+                "{:<16} {:04x}:{:04x} {}",
+                port,
+                usb.vid,
+                usb.pid,
+                usb.product.as_deref().unwrap_or("-")
+            );
+        } else {
+            outln!("{}", port);
+        }
+        outln!("  {} (by {})", found.verdict, found.by);
+        let (kind, version) = match &found.verdict {
+            Verdict::Bootloader(version) => ("bootloader", version.map(|v| v.to_string())),
+            Verdict::Firmware => ("firmware", None),
+            Verdict::Unknown => ("unknown", None),
+        };
+        entries.push(json!({
+            "port": port,
+            "verdict": kind,
+            "version": version,
+            "by": found.by,
+            "vid": usb.map(|u| u.vid),
+            "pid": usb.map(|u| u.pid),
+            "product": usb.and_then(|u| u.product.as_deref()),
+        }));
+    }
+    output::report(json!({ "ports": entries }));
+    Ok(())
+}
+
+/// Print the operations recorded in the history, oldest first.
+pub fn history(path: Option<&Path>, device: Option<&str>) -> Result<()> {
+    let Some(path) = path else {
+        bail!(
+            "History is turned off (--no-history or {}=off)",
+            history::HISTORY_ENV
+        );
+    };
+    let records = history::read(path)?;
+    let shown: Vec<_> = history::filter(&records, device).collect();
+    output::report(json!({ "records": shown }));
+    for r in &shown {
+        let params = match &r.params {
+            serde_json::Value::Object(map) => map
+                .iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| match v {
+                    serde_json::Value::String(s) => format!("{}={}", k, s),
+                    v => format!("{}={}", k, v),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            other => other.to_string(),
+        };
+        let crc = r
+            .fw_crc
+            .map_or(String::new(), |c| format!(" crc=0x{:08x}", c));
+        outln!(
+            "{}  {:<16}  {:<17}  {}{}  {}",
+            r.timestamp,
+            r.device.as_deref().unwrap_or(&r.port),
+            r.command,
+            params,
+            crc,
+            r.error.as_deref().unwrap_or(&r.outcome),
+        );
+    }
+
+    if shown.is_empty() {
+        match device {
+            Some(device) => outln!(
+                "No operations recorded for {} in {}",
+                device,
+                path.display()
+            ),
+            None => outln!("No operations recorded in {}", path.display()),
+        }
+    }
+    Ok(())
+}
+
+/// List serial ports, by default only those of crispy devices.
+pub fn list_ports(all: bool) -> Result<()> {
+    let ports: Vec<_> = transport::list_ports()?
+        .into_iter()
+        .filter(|p| all || p.kind.is_some())
+        .collect();
+
+    let entries: Vec<_> = ports
+        .iter()
+        .map(|p| {
+            let usb = p.usb.as_ref();
+            json!({
+                "port": p.name,
+                "device": p.kind.map(|k| k.name()),
+                "vid": usb.map(|u| u.vid),
+                "pid": usb.map(|u| u.pid),
+                "product": usb.and_then(|u| u.product.as_deref()),
+                "serial_number": usb.and_then(|u| u.serial_number.as_deref()),
+                "interface": usb.and_then(|u| u.interface),
+            })
+        })
+        .collect();
+    output::report(json!({ "ports": entries }));
+    if output::is_json() {
+        return Ok(());
+    }
+
+    if ports.is_empty() {
+        if all {
+            outln!("No serial ports found");
+        } else {
+            outln!("No crispy devices found (--all lists every serial port)");
+        }
+        return Ok(());
+    }
+
+    outln!(
+        "{:<16} {:<9} {:<10} {:<24} SERIAL",
+        "PORT",
+        "VID:PID",
+        "DEVICE",
+        "PRODUCT"
+    );
+    for p in &ports {
+        let usb = p.usb.as_ref();
+        let ids = usb.map_or("-".to_string(), |u| format!("{:04x}:{:04x}", u.vid, u.pid));
+        outln!(
+            "{:<16} {:<9} {:<10} {:<24} {}",
+            p.name,
+            ids,
+            p.kind.map_or("-", |k| k.name()),
+            usb.and_then(|u| u.product.as_deref()).unwrap_or("-"),
+            usb.and_then(|u| u.serial_number.as_deref()).unwrap_or("-"),
+        );
+    }
+    Ok(())
+}
+
+/// Print a raw BootData record as JSON.
+pub fn boot_data_decode(file: &Path) -> Result<()> {
+    let raw = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let Some(record) = raw.first_chunk::<BOOT_DATA_SIZE>() else {
+        bail!(
+            "{} is {} bytes, a BootData record is {}",
+            file.display(),
+            raw.len(),
+            BOOT_DATA_SIZE
+        );
+    };
+    let bd = BootData::from_bytes(record)
+        .map_err(|err| anyhow::anyhow!("Not a valid BootData record: {:?}", err))?;
+
+    warn_issues(&bd);
+    output::report(json!({ "record": bd }));
+    if !output::is_json() {
+        println!("{}", serde_json::to_string_pretty(&bd)?);
+    }
+    Ok(())
+}
+
+/// Write a JSON BootData record as its flash bytes.
+pub fn boot_data_encode(file: &Path, output: &Path) -> Result<()> {
+    let json =
+        fs::read_to_string(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let bd: BootData = serde_json::from_str(&json)
+        .with_context(|| format!("{} is not a BootData record", file.display()))?;
+
+    warn_issues(&bd);
+    fs::write(output, bd.to_bytes())
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    outln!("Wrote {} bytes to {}", BOOT_DATA_SIZE, output.display());
+    output::report(json!({ "file": output, "size": BOOT_DATA_SIZE }));
+    Ok(())
+}
+
+/// The bootloader repairs or rebuilds a record with issues, so say so.
+fn warn_issues(bd: &BootData) {
+    for issue in bd.issues() {
+        let action = if issue.is_repairable() {
+            "resets the field"
+        } else {
+            "rebuilds the record from the banks"
+        };
+        eprintln!("Warning: {:?} (the bootloader {})", issue, action);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crispy_common::protocol::BootState;
+
+    fn status(active_bank: u8) -> Response {
+        Response::Status {
+            active_bank,
+            version_a: FwVersion::from_raw(1),
+            version_b: FwVersion::from_raw(2),
+            state: BootState::Idle,
+            host_connected: true,
+            tx_stalled: false,
+            bootdata_reconstructed: false,
+            update_interrupted: false,
+        }
+    }
+
+    #[test]
+    fn test_active_bank_from_status() {
+        assert_eq!(active_bank(&status(0)).unwrap(), 0);
+        assert_eq!(active_bank(&status(1)).unwrap(), 1);
+        assert!(active_bank(&status(2)).is_err());
+        assert!(active_bank(&Response::Ack(AckStatus::Ok)).is_err());
+    }
+
+    #[test]
+    fn test_upload_bank_defaults_to_inactive() {
+        assert_eq!(upload_bank(None, 0), 1);
+        assert_eq!(upload_bank(None, 1), 0);
+        // An explicit bank wins, even the active one
+        assert_eq!(upload_bank(Some(0), 0), 0);
+        assert_eq!(upload_bank(Some(1), 0), 1);
+    }
+
+    #[test]
+    fn test_check_chunk_size() {
+        for ok in [256, 512, 768, 1024] {
+            assert!(check_chunk_size(ok).is_ok(), "{}", ok);
+        }
+        for bad in [0, 4, 255, 300, 1280] {
+            assert!(check_chunk_size(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(
+            check_chunk_size(2048).unwrap_err().to_string(),
+            "--chunk-size 2048 is more than the 1024 bytes the device takes per block: \
+             use a multiple of 256 up to 1024"
+        );
+    }
+
+    #[test]
+    fn test_mismatch_regions_coalesce() {
+        let mut m = Mismatches::default();
+        let expected = [0u8; 16];
+        let mut actual = [0u8; 16];
+        actual[2..5].fill(1);
+        actual[9] = 1;
+        actual[15] = 1;
+        m.compare(0x100, &actual[..8], &expected[..8]);
+        m.compare(0x108, &actual[8..], &expected[8..]);
+
+        assert_eq!(m.count, 5);
+        assert_eq!(m.regions, [(0x102, 0x105), (0x109, 0x10a), (0x10f, 0x110)]);
+        assert_eq!((m.first(), m.last), (Some(0x102), Some(0x10f)));
+
+        // A run across two reads is one region
+        let mut m = Mismatches::default();
+        m.compare(0, &[0, 1], &[0, 0]);
+        m.compare(2, &[1, 0], &[0, 0]);
+        assert_eq!(m.regions, [(1, 3)]);
+    }
+
+    #[test]
+    fn test_mismatch_regions_are_capped() {
+        let mut m = Mismatches::default();
+        for at in (0..2 * MAX_REGIONS as u32).map(|i| i * 2) {
+            m.add(at);
+            m.add(at + 1);
+        }
+        // A run of consecutive bytes is one region, however long
+        assert_eq!(m.count, 4 * MAX_REGIONS as u32);
+        assert_eq!(m.regions.len(), 1);
+
+        let mut m = Mismatches::default();
+        for i in 0..MAX_REGIONS as u32 + 10 {
+            m.add(i * 4);
+            m.add(i * 4 + 1);
+        }
+        assert_eq!(m.regions.len(), MAX_REGIONS);
+        let last_kept = 4 * (MAX_REGIONS as u32 - 1);
+        assert_eq!(m.regions[MAX_REGIONS - 1], (last_kept, last_kept + 2));
+        assert_eq!(
+            (m.more_regions, m.region_count()),
+            (10, MAX_REGIONS as u32 + 10)
+        );
+        assert_eq!(m.last, Some(4 * (MAX_REGIONS as u32 + 9) + 1));
+    }
+
+    #[test]
+    fn test_compare_exit_code() {
+        let differs = anyhow::Error::new(Failure::new("verify", "a.bin differs from bank 0"));
+        assert_eq!(compare_exit_code(&differs), 1);
+        let timeout = anyhow::Error::new(Failure::new("timeout", "Timeout waiting for response"));
+        assert_eq!(compare_exit_code(&timeout), 2);
+        assert_eq!(
+            compare_exit_code(&anyhow::anyhow!("Failed to read a.bin")),
+            2
+        );
+        let empty = anyhow::Error::new(Failure::new("empty_bank", "Bank 1 holds no firmware"));
+        assert_eq!(compare_exit_code(&empty), 3);
+    }
+
+    #[test]
+    fn test_rate() {
+        assert_eq!(bytes_per_sec(65536, Duration::from_secs(2)), 32768);
+        assert_eq!(rate(65536, Duration::from_secs(2)), "2.00 s (32.0 KB/s)");
+    }
+
+    #[test]
+    fn test_summary_line() {
+        let phases = [
+            ("erase", Duration::from_millis(500)),
+            ("write", Duration::from_millis(1500)),
+        ];
+        let stats = Stats {
+            retries: 1,
+            round_trips: 4,
+            round_trip_time: Duration::from_millis(10),
+            ..Stats::default()
+        };
+        assert_eq!(
+            summary_line(65536, Duration::from_secs(2), &phases, &stats),
+            "65536 bytes in 2.00 s at 32.0 KB/s: erase 0.50 s, write 1.50 s; 1 retry, \
+             2.5 ms per round trip"
+        );
+        assert_eq!(
+            summary_line(1024, Duration::from_secs(1), &[], &Stats::default()),
+            "1024 bytes in 1.00 s at 1.0 KB/s; 0 retries"
+        );
+    }
+
+    // --- Containers ---
+
+    use crate::test_device::TestDevice;
+    use crispy_common::flash::read_boot_data;
+
+    fn input() -> InputOptions {
+        InputOptions {
+            any_family: false,
+            max_gap: DEFAULT_MAX_GAP,
+            sha256: None,
+            bank_size: FW_BANK_SIZE,
+        }
+    }
+
+    /// A file of its own in the temp directory; tests run in parallel.
+    fn temp_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("crispy-{}-{}", std::process::id(), name))
+    }
+
+    /// A raw image the bootloader would boot.
+    fn raw_image() -> Vec<u8> {
+        let mut fw = vec![0u8; 3000];
+        fw[0..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+        fw[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+        for (i, byte) in fw.iter_mut().enumerate().skip(8) {
+            *byte = i as u8;
+        }
+        fw
+    }
+
+    fn pack_image(name: &str) -> (std::path::PathBuf, Vec<u8>) {
+        let raw = temp_file(&format!("{}.bin", name));
+        fs::write(&raw, raw_image()).unwrap();
+        let packed = raw.with_extension(CONTAINER_EXTENSION);
+        pack(&raw, &packed, Some(FwVersion::new(1, 2, 3)), 7, &input()).unwrap();
+        fs::remove_file(raw).unwrap();
+        let bytes = fs::read(&packed).unwrap();
+        (packed, bytes)
+    }
+
+    fn upload_opts() -> UploadOptions {
+        UploadOptions {
+            version: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            verify: true,
+            input: input(),
+            overrides: Overrides::NONE,
+            always: false,
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let raw = temp_file("checksum.bin");
+        // The check value of CRC-32/ISO-HDLC, as zlib's crc32() computes it
+        fs::write(&raw, b"123456789").unwrap();
+        let sum = file_checksum(&raw, 0, None, &input()).unwrap();
+        assert_eq!(sum.crc32, 0xCBF4_3926);
+        assert_eq!((sum.size, sum.payload), (9, None));
+
+        fs::write(&raw, raw_image()).unwrap();
+
+        let part = file_checksum(&raw, 8, Some(16), &input()).unwrap();
+        assert_eq!(part.crc32, crc32::checksum(&raw_image()[8..24]));
+        assert_eq!(part.size, 16);
+        assert!(file_checksum(&raw, 2990, Some(16), &input()).is_err());
+        assert!(file_checksum(&raw, 3001, None, &input()).is_err());
+        fs::remove_file(&raw).unwrap();
+
+        let (packed, bytes) = pack_image("checksum");
+        let sum = file_checksum(&packed, 0, None, &input()).unwrap();
+        assert_eq!(sum.crc32, crc32::checksum(&bytes));
+        let (_, header) = image::split(&bytes).unwrap().unwrap();
+        assert_eq!(sum.payload, Some((header.image_len, header.image_crc)));
+        assert_eq!(header.image_crc, crc32::checksum(&raw_image()));
+        checksum(std::slice::from_ref(&packed), 0, None, &input()).unwrap();
+        fs::remove_file(&packed).unwrap();
+    }
+
+    #[test]
+    fn test_pack_inspect_upload() {
+        let (packed, bytes) = pack_image("round-trip");
+        let (payload, header) = image::split(&bytes).unwrap().unwrap();
+        assert_eq!(payload, raw_image());
+        assert_eq!(header.fw_version, FwVersion::new(1, 2, 3));
+        assert_eq!(header.board_id, 7);
+
+        inspect(&packed, &input()).unwrap();
+        // Packing twice would bury the header
+        let again = temp_file("round-trip-again.crispy");
+        assert!(pack(&packed, &again, None, 0, &input()).is_err());
+
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        assert_eq!(
+            upload(&mut transport, &packed, Some(0), &upload_opts()).unwrap(),
+            0
+        );
+        drop(transport);
+        fs::remove_file(packed).unwrap();
+
+        let flash = device.finish();
+        let bd = read_boot_data(&flash).unwrap();
+        assert_eq!(bd.version_a, FwVersion::new(1, 2, 3));
+        assert_eq!(bd.size_a, bytes.len() as u32);
+        assert_eq!(bd.crc_a, crc32::checksum(&bytes));
+    }
+
+    #[test]
+    fn test_info_against_baseline_bootloader() {
+        let device = TestDevice::baseline();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let started = Instant::now();
+
+        info(&mut transport).unwrap();
+        assert!(transport.legacy_status());
+
+        let mut unsupported = Unsupported::default();
+        let fields = status_sample(&mut transport, true, &mut unsupported).unwrap();
+        let field = |name| fields.iter().find(|(n, _)| *n == name).map(|(_, v)| v);
+        assert_eq!(field("active_bank"), Some(&json!(0)));
+        assert_eq!(field("host_connected"), Some(&json!(null)));
+        assert_eq!(field("update_interrupted"), Some(&json!(null)));
+        assert_eq!(field("confirmed"), None);
+        assert!(unsupported.boot_data && unsupported.device_info);
+        // Nothing was sent that it would have dropped
+        assert!(started.elapsed() < Duration::from_millis(OPTIONAL_QUERY_TIMEOUT_MS));
+
+        drop(transport);
+        device.finish();
+    }
+
+    #[test]
+    fn test_bank_size_option() {
+        let raw = temp_file("bank-size.bin");
+        let packed = raw.with_extension(CONTAINER_EXTENSION);
+        fs::write(&raw, raw_image()).unwrap();
+        let small = InputOptions {
+            bank_size: 2048,
+            ..input()
+        };
+
+        assert!(check_firmware(&raw, &input()).is_ok());
+        let err = check_firmware(&raw, &small).unwrap_err().to_string();
+        assert!(err.contains("does not fit a 2048-byte bank"), "{}", err);
+        assert!(pack(&raw, &packed, Some(FwVersion::new(1, 0, 0)), 0, &small).is_err());
+        assert!(!packed.exists());
+        let err = preflight(&raw, &small).unwrap_err();
+        assert_eq!(preflight_exit_code(&err), Some(Check::TooLarge.exit_code()));
+
+        // A bigger bank than the built-in one takes a bigger image
+        let mut large = raw_image();
+        large.resize(FW_BANK_SIZE as usize + 1, 0);
+        fs::write(&raw, &large).unwrap();
+        let big = InputOptions {
+            bank_size: 2 * FW_BANK_SIZE,
+            ..input()
+        };
+        assert!(check_firmware(&raw, &input()).is_err());
+        assert!(check_firmware(&raw, &big).is_ok());
+        fs::remove_file(&raw).unwrap();
+    }
+
+    #[test]
+    fn test_tampered_container_is_refused() {
+        let (packed, bytes) = pack_image("tampered");
+        let header_at = bytes.len() - image::IMAGE_HEADER_SIZE;
+        for (what, at) in [("header", header_at + 10), ("payload", 100)] {
+            let mut tampered = bytes.clone();
+            tampered[at] ^= 0x01;
+            fs::write(&packed, &tampered).unwrap();
+
+            let err = inspect(&packed, &input()).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Failure>().unwrap().code,
+                "input",
+                "{}",
+                what
+            );
+
+            let device = TestDevice::start();
+            let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+            let err = upload(&mut transport, &packed, Some(0), &upload_opts()).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<Failure>().unwrap().code,
+                "input",
+                "{}",
+                what
+            );
+            drop(transport);
+            assert_eq!(device.finish().erase_count, 0, "{}", what);
+        }
+        fs::remove_file(packed).unwrap();
+
+        // A .crispy without a header at all
+        let plain = temp_file("plain.crispy");
+        fs::write(&plain, raw_image()).unwrap();
+        assert!(inspect(&plain, &input()).is_err());
+        fs::remove_file(plain).unwrap();
+    }
+
+    #[test]
+    fn test_version_from_the_image() {
+        let mut fw = raw_image();
+        let block = version::VersionBlock::new(FwVersion::new(0, 2, 0)).to_bytes();
+        fw[VERSION_BLOCK_OFFSET..VERSION_BLOCK_OFFSET + block.len()].copy_from_slice(&block);
+        let raw = temp_file("embedded.bin");
+        fs::write(&raw, &fw).unwrap();
+        assert_eq!(
+            read_firmware(&raw, &input()).unwrap().version,
+            Some(FwVersion::new(0, 2, 0))
+        );
+
+        // The header of a container wins over the block it wraps
+        let packed = raw.with_extension(CONTAINER_EXTENSION);
+        pack(&raw, &packed, Some(FwVersion::new(0, 3, 0)), 0, &input()).unwrap();
+        assert_eq!(
+            read_firmware(&packed, &input()).unwrap().version,
+            Some(FwVersion::new(0, 3, 0))
+        );
+        fs::remove_file(raw).unwrap();
+        fs::remove_file(packed).unwrap();
+    }
+
+    #[test]
+    fn test_pick_version() {
+        let (a, b) = (FwVersion::new(1, 0, 0), FwVersion::new(2, 0, 0));
+        assert_eq!(pick_version(Some(a), Some(b)), Some(a));
+        assert_eq!(pick_version(None, Some(b)), Some(b));
+        assert_eq!(pick_version(Some(a), None), Some(a));
+        assert_eq!(pick_version(None, None), None);
+    }
+
+    #[test]
+    fn test_verify_quick() {
+        let file = temp_file("quick.bin");
+        fs::write(&file, raw_image()).unwrap();
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        upload(&mut transport, &file, Some(0), &upload_opts()).unwrap();
+
+        verify_quick(&mut transport, &file, 0, &input()).unwrap();
+
+        let err = verify_quick(&mut transport, &file, 1, &input()).unwrap_err();
+        assert_eq!(compare_exit_code(&err), 3);
+
+        let mut other = raw_image();
+        other[100] ^= 0x01;
+        fs::write(&file, &other).unwrap();
+        let err = verify_quick(&mut transport, &file, 0, &input()).unwrap_err();
+        assert_eq!(compare_exit_code(&err), 1);
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.context["differing"], json!(["crc32"]));
+
+        drop(transport);
+        device.finish();
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_blank_check() {
+        let file = temp_file("blank.bin");
+        fs::write(&file, raw_image()).unwrap();
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        upload(&mut transport, &file, Some(0), &upload_opts()).unwrap();
+
+        blank_check(&mut transport, BlankRegion::Bank(1)).unwrap();
+
+        let region = BlankRegion::Range {
+            addr: FW_A_ADDR,
+            length: 0x1000,
+        };
+        let err = blank_check(&mut transport, region).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "not_blank");
+        assert_eq!(failure.context["first_dirty"], json!(0));
+        let dirty = failure.context["dirty_bytes"].clone();
+
+        // The host-side scan of an older bootloader finds the same
+        let (first_dirty, dirty_bytes) = scan_blank(&mut transport, FW_A_ADDR, 0x1000).unwrap();
+        assert_eq!(first_dirty, Some(0));
+        assert_eq!(json!(dirty_bytes), dirty);
+        assert_eq!(
+            scan_blank(&mut transport, FW_B_ADDR, 0x1000).unwrap(),
+            (None, 0)
+        );
+
+        let region = BlankRegion::Range {
+            addr: 0,
+            length: 0x1000,
+        };
+        let err = blank_check(&mut transport, region).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<Failure>().unwrap().code,
+            "invalid_argument"
+        );
+
+        drop(transport);
+        device.finish();
+        fs::remove_file(file).unwrap();
+    }
+
+    // --- Pre-flight checks ---
+
+    /// The check that `file` fails, with its exit code.
+    fn failed_check(file: &Path, bytes: Option<&[u8]>) -> Option<(String, i32)> {
+        if let Some(bytes) = bytes {
+            fs::write(file, bytes).unwrap();
+        }
+        let result = preflight(file, &input());
+        let _ = fs::remove_file(file);
+        let err = result.err()?;
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "preflight");
+        let check = failure.context["check"].as_str().unwrap().to_string();
+        Some((check, preflight_exit_code(&err).unwrap()))
+    }
+
+    #[test]
+    fn test_preflight() {
+        let file = temp_file("preflight.bin");
+        assert_eq!(failed_check(&file, Some(&raw_image())), None);
+        assert_eq!(failed_check(&file, None), Some(("missing".to_string(), 10)));
+        assert_eq!(
+            failed_check(&file, Some(&[])),
+            Some(("empty".to_string(), 11))
+        );
+
+        let mut large = raw_image();
+        large.resize(FW_BANK_SIZE as usize + 1, 0);
+        assert_eq!(
+            failed_check(&file, Some(&large)),
+            Some(("too_large".to_string(), 12))
+        );
+
+        // Zeros, a flash address and a vector table cut short
+        let mut in_flash = raw_image();
+        in_flash[4..8].copy_from_slice(&(FW_A_ADDR | 1).to_le_bytes());
+        for bytes in [&vec![0u8; 3000][..], &in_flash, &raw_image()[..6]] {
+            assert_eq!(
+                failed_check(&file, Some(bytes)),
+                Some(("vector_table".to_string(), 13))
+            );
+        }
+
+        let mut long = raw_image();
+        long.resize(FW_COPY_SIZE as usize + 4, 0);
+        assert_eq!(
+            failed_check(&file, Some(&long)),
+            Some(("ram_copy".to_string(), 14))
+        );
+        // A header past the RAM copy size is fine
+        long.truncate(FW_COPY_SIZE as usize);
+        let packed = ImageBuilder::new(FwVersion::new(1, 0, 0)).wrap(&long);
+        assert_eq!(failed_check(&file, Some(&packed)), None);
+    }
+
+    #[test]
+    fn test_preflight_exit_codes() {
+        assert_eq!(Check::ActiveBank.exit_code(), 15);
+        let err = anyhow::Error::new(Check::ActiveBank.fail("active".to_string()));
+        assert_eq!(preflight_exit_code(&err), Some(15));
+        let err = anyhow::Error::new(Failure::new("verify", "differs"));
+        assert_eq!(preflight_exit_code(&err), None);
+    }
+
+    #[test]
+    fn test_upload_checks_before_erasing() {
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let file = temp_file("preflight-upload.bin");
+        fs::write(&file, vec![0u8; 3000]).unwrap();
+
+        let err = upload(&mut transport, &file, Some(1), &upload_opts()).unwrap_err();
+        assert_eq!(preflight_exit_code(&err), Some(13));
+        let opts = UploadOptions {
+            overrides: Overrides::NONE.with(Protection::ImageChecks, true),
+            verify: false,
+            ..upload_opts()
+        };
+        upload(&mut transport, &file, Some(1), &opts).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        drop(transport);
+        let flash = device.finish();
+        assert_eq!(read_boot_data(&flash).unwrap().size_b, 3000);
+    }
+
+    #[test]
+    fn test_upload_skips_an_image_already_there() {
+        let file = temp_file("up-to-date.bin");
+        fs::write(&file, raw_image()).unwrap();
+        let bank_b = FlashLayout::DEFAULT.bank_addr(1).unwrap();
+        let up_to_date = |transport: &mut Transport, opts: &UploadOptions| {
+            let (bank, data) = output::collect(|| upload(transport, &file, None, opts));
+            (bank.unwrap(), data["up_to_date"] == true)
+        };
+
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        assert_eq!(up_to_date(&mut transport, &upload_opts()), (1, false));
+        // Bank B is active now and holds the image
+        assert_eq!(up_to_date(&mut transport, &upload_opts()), (1, true));
+        let versioned = UploadOptions {
+            version: Some(FwVersion::new(2, 0, 0)),
+            ..upload_opts()
+        };
+        assert_eq!(up_to_date(&mut transport, &versioned), (0, false));
+        drop(transport);
+        let mut flash = device.finish();
+        assert_eq!(flash.sector_erases(bank_b), 1);
+
+        // Bank A is active, recorded as 2.0.0, but its flash has changed
+        flash.poke(FlashLayout::DEFAULT.bank_addr(0).unwrap() + 100, &[0]);
+        let device = TestDevice::with_flash(flash);
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        assert_eq!(up_to_date(&mut transport, &versioned), (1, false));
+        let always = UploadOptions {
+            always: true,
+            version: versioned.version,
+            ..upload_opts()
+        };
+        assert_eq!(up_to_date(&mut transport, &versioned), (1, true));
+        assert_eq!(up_to_date(&mut transport, &always), (0, false));
+        drop(transport);
+        assert_eq!(device.finish().sector_erases(bank_b), 2);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn test_provision() {
+        let replaced = |transport: &mut Transport, serial, overrides| {
+            let (result, data) = output::collect(|| provision(transport, 2, 1, serial, overrides));
+            result.map(|()| data["replaced"] == true)
+        };
+        let code = |err: anyhow::Error| err.downcast_ref::<Failure>().unwrap().code;
+
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        assert!(!replaced(&mut transport, "ABC123", Overrides::NONE).unwrap());
+        // The same record again is not a replacement
+        assert!(!replaced(&mut transport, "ABC123", Overrides::NONE).unwrap());
+        let err = replaced(&mut transport, "XYZ", Overrides::NONE).unwrap_err();
+        assert_eq!(code(err), "provisioned");
+        let reprovision = Overrides::NONE.with(Protection::Provisioned, true);
+        assert!(replaced(&mut transport, "XYZ", reprovision).unwrap());
+        let err = replaced(&mut transport, "two words", reprovision).unwrap_err();
+        assert_eq!(code(err), "invalid_argument");
+        drop(transport);
+
+        let flash = device.finish();
+        let stored = crispy_common::flash::read_provision(&flash).unwrap();
+        assert_eq!(stored, Provision::new(2, 1, b"XYZ").unwrap());
+    }
+}
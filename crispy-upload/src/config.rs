@@ -0,0 +1,675 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Default options from configuration files.
+//!
+//! Two files are read if they exist: `crispy.toml` in the current
+//! directory, for a project's defaults checked in next to its firmware,
+//! and the user's `crispy/config.toml` under `$XDG_CONFIG_HOME`,
+//! `~/.config` or `%APPDATA%`. A key in the project's file wins over the
+//! same key in the user's, and a flag on the command line wins over both.
+//! `config show` prints what is in effect and where each value came from.
+//!
+//! The files are a small subset of TOML: `key = value` lines, strings in
+//! double quotes, integers, `true` and `false`, and `#` comments.
+//!
+//! ```toml
+//! port = "/dev/ttyACM0"   # "auto" finds the device by its USB IDs
+//! read_timeout = 2000
+//! chunk_size = 512
+//! progress = "plain"
+//! ```
+//!
+//! | Key | Flag |
+//! |-----|------|
+//! | `port` | `--port` |
+//! | `serial` | `--serial` (when `port` is not set) |
+//! | `connect_timeout` | `--connect-timeout` |
+//! | `read_timeout` | `--read-timeout` |
+//! | `timeout` | `--timeout` |
+//! | `retries` | `--retries` |
+//! | `retry_backoff` | `--retry-backoff` |
+//! | `chunk_size` | `--chunk-size` of the command (not of `run` script steps) |
+//! | `progress` | `--progress` |
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use serde_json::json;
+
+use crate::cli::{Cli, Commands};
+use crate::commands;
+use crate::output::{self, outln, Failure, ProgressMode};
+
+/// The project's file, in the current directory.
+pub const PROJECT_FILE: &str = "crispy.toml";
+
+/// What a key holds.
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    /// An integer up to this.
+    Number(u64),
+    Progress,
+}
+
+/// Every key, in the order `config show` lists them.
+const KEYS: &[(&str, Kind)] = &[
+    ("port", Kind::Text),
+    ("serial", Kind::Text),
+    ("connect_timeout", Kind::Number(u64::MAX)),
+    ("read_timeout", Kind::Number(u64::MAX)),
+    ("timeout", Kind::Number(u64::MAX)),
+    ("retries", Kind::Number(u32::MAX as u64)),
+    ("retry_backoff", Kind::Number(u64::MAX)),
+    ("chunk_size", Kind::Number(u32::MAX as u64)),
+    ("progress", Kind::Progress),
+];
+
+/// A value from a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Text(String),
+    Number(u64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Text(text) => f.write_str(text),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// One `key = value` line.
+#[derive(Clone, Debug)]
+pub struct Setting {
+    pub key: &'static str,
+    pub value: Value,
+    pub file: PathBuf,
+    pub line: usize,
+}
+
+/// The files read, merged.
+#[derive(Default)]
+pub struct Config {
+    settings: Vec<Setting>,
+    /// Every file looked for, and whether it was there.
+    files: Vec<(PathBuf, bool)>,
+}
+
+impl Config {
+    /// Read the project's and the user's files.
+    pub fn load() -> Result<Self> {
+        let mut paths = vec![PathBuf::from(PROJECT_FILE)];
+        paths.extend(user_file());
+        Self::load_files(&paths)
+    }
+
+    /// Read `paths`, the first winning over the later ones; missing files
+    /// are skipped.
+    fn load_files(paths: &[PathBuf]) -> Result<Self> {
+        let mut config = Config::default();
+        for path in paths {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    config.files.push((path.clone(), false));
+                    continue;
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| format!("Failed to read {}", path.display()))
+                }
+            };
+            config.add(parse(&text, path)?);
+            config.files.push((path.clone(), true));
+        }
+        Ok(config)
+    }
+
+    /// Add the settings of a file that gives way to those already read.
+    fn add(&mut self, settings: Vec<Setting>) {
+        for setting in settings {
+            if self.get(setting.key).is_none() {
+                self.settings.push(setting);
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Setting> {
+        self.settings.iter().find(|s| s.key == key)
+    }
+
+    fn number(&self, key: &str) -> Option<u64> {
+        match self.get(key)?.value {
+            Value::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn text(&self, key: &str) -> Option<&str> {
+        match &self.get(key)?.value {
+            Value::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// Fill in `cli` where the command line left a default, and say where
+    /// each value in effect came from.
+    fn apply_to(&self, cli: &mut Cli, matches: &ArgMatches) -> Vec<Entry> {
+        let given = |matches: &ArgMatches, id: &str| {
+            matches.try_get_raw(id).is_ok()
+                && matches.value_source(id) == Some(ValueSource::CommandLine)
+        };
+        let source = |key: &str, on_command_line: bool| {
+            if on_command_line {
+                Source::CommandLine
+            } else if let Some(setting) = self.get(key) {
+                Source::File(setting.file.clone(), setting.line)
+            } else {
+                Source::Default
+            }
+        };
+
+        // Any way of naming the device on the command line outranks them all
+        let device_given =
+            given(matches, "port") || given(matches, "serial") || given(matches, "all");
+        let port = self.text("port").filter(|port| *port != "auto");
+        if !device_given {
+            match port {
+                Some(port) => cli.port = vec![port.to_string()],
+                None => cli.serial = self.text("serial").map(str::to_string),
+            }
+        }
+
+        if !given(matches, "connect_timeout") {
+            cli.connect_timeout = self
+                .number("connect_timeout")
+                .unwrap_or(cli.connect_timeout);
+        }
+        // --read-timeout and --timeout exclude each other, so either replaces both
+        let timeouts_given = given(matches, "read_timeout") || given(matches, "timeout");
+        if !timeouts_given {
+            cli.read_timeout = self.number("read_timeout").unwrap_or(cli.read_timeout);
+            cli.timeout = self.number("timeout").or(cli.timeout);
+        }
+        if !given(matches, "retries") {
+            cli.retries = self.number("retries").map_or(cli.retries, |n| n as u32);
+        }
+        if !given(matches, "retry_backoff") {
+            cli.retry_backoff = self.number("retry_backoff").unwrap_or(cli.retry_backoff);
+        }
+        if !given(matches, "progress") {
+            let mode = self
+                .text("progress")
+                .and_then(|mode| ProgressMode::from_str(mode, true).ok());
+            cli.progress = mode.unwrap_or(cli.progress);
+        }
+        let sub = matches.subcommand().map(|(_, sub)| sub);
+        let chunk_given = sub.is_some_and(|sub| given(sub, "chunk_size"));
+        let chunk_size = chunk_size_mut(&mut cli.command);
+        if let (Some(chunk_size), false) = (chunk_size, chunk_given) {
+            *chunk_size = self.number("chunk_size").map_or(*chunk_size, |n| n as u32);
+        }
+
+        let port_value = if !cli.port.is_empty() {
+            cli.port.join(", ")
+        } else if cli.all {
+            "every device (--all)".to_string()
+        } else {
+            "auto".to_string()
+        };
+        let chunk_size = match chunk_size_mut(&mut cli.command) {
+            Some(chunk_size) => *chunk_size,
+            None => self
+                .number("chunk_size")
+                .map_or(commands::DEFAULT_CHUNK_SIZE, |n| n as u32),
+        };
+        let progress = cli.progress.to_possible_value().expect("modes have names");
+        let entry = |key, value: Option<String>, on_command_line| Entry {
+            key,
+            value,
+            source: source(key, on_command_line),
+        };
+        vec![
+            entry("port", Some(port_value), device_given),
+            entry("serial", cli.serial.clone(), device_given),
+            entry(
+                "connect_timeout",
+                Some(cli.connect_timeout.to_string()),
+                given(matches, "connect_timeout"),
+            ),
+            entry(
+                "read_timeout",
+                Some(cli.read_timeout.to_string()),
+                timeouts_given,
+            ),
+            entry(
+                "timeout",
+                cli.timeout.map(|t| t.to_string()),
+                timeouts_given,
+            ),
+            entry(
+                "retries",
+                Some(cli.retries.to_string()),
+                given(matches, "retries"),
+            ),
+            entry(
+                "retry_backoff",
+                Some(cli.retry_backoff.to_string()),
+                given(matches, "retry_backoff"),
+            ),
+            entry("chunk_size", Some(chunk_size.to_string()), chunk_given),
+            entry(
+                "progress",
+                Some(progress.get_name().to_string()),
+                given(matches, "progress"),
+            ),
+        ]
+    }
+}
+
+/// The `--chunk-size` of `command`, if it has one.
+fn chunk_size_mut(command: &mut Commands) -> Option<&mut u32> {
+    match command {
+        Commands::Upload { chunk_size, .. }
+        | Commands::Update { chunk_size, .. }
+        | Commands::Download { chunk_size, .. }
+        | Commands::Diff { chunk_size, .. }
+        | Commands::Verify { chunk_size, .. } => Some(chunk_size),
+        _ => None,
+    }
+}
+
+/// The user's file: `crispy/config.toml` under `$XDG_CONFIG_HOME`,
+/// `~/.config`, or `%APPDATA%` on Windows.
+fn user_file() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| var("APPDATA").map(PathBuf::from))
+        .map(|dir| dir.join("crispy").join("config.toml"))
+}
+
+/// Parse the text of the file at `file`.
+pub fn parse(text: &str, file: &Path) -> Result<Vec<Setting>> {
+    let mut settings: Vec<Setting> = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let line = index + 1;
+        let fail = |message: String| {
+            Failure::new(
+                "config",
+                format!("{}:{}: {}", file.display(), line, message),
+            )
+            .with("path", file.display().to_string())
+            .with("line", line)
+        };
+
+        let content = strip_comment(raw).trim();
+        if content.is_empty() {
+            continue;
+        }
+        if content.starts_with('[') {
+            bail!(fail(
+                "tables are not supported; keys go at the top level".to_string()
+            ));
+        }
+        let Some((name, value)) = content.split_once('=') else {
+            bail!(fail(format!("expected `key = value`, found `{}`", content)));
+        };
+        let name = name.trim();
+        let Some(&(key, kind)) = KEYS.iter().find(|(key, _)| *key == name) else {
+            let known: Vec<_> = KEYS.iter().map(|(key, _)| *key).collect();
+            bail!(fail(format!(
+                "unknown key `{}`; known keys: {}",
+                name,
+                known.join(", ")
+            )));
+        };
+        if let Some(earlier) = settings.iter().find(|s| s.key == key) {
+            bail!(fail(format!(
+                "`{}` is already set on line {}",
+                key, earlier.line
+            )));
+        }
+        let value = parse_value(value.trim()).map_err(&fail)?;
+        check(key, kind, &value).map_err(&fail)?;
+        settings.push(Setting {
+            key,
+            value,
+            file: file.to_path_buf(),
+            line,
+        });
+    }
+    Ok(settings)
+}
+
+/// `line` without a `#` comment outside quotes.
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// A quoted string, an integer (`_` may separate digits), `true` or
+/// `false`.
+pub fn parse_value(text: &str) -> Result<Value, String> {
+    if let Some(inner) = text.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    let rest = chars.as_str().trim();
+                    if !rest.is_empty() {
+                        return Err(format!("unexpected `{}` after the string", rest));
+                    }
+                    return Ok(Value::Text(value));
+                }
+                '\\' => match chars.next() {
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    _ => return Err("unknown escape in string".to_string()),
+                },
+                c => value.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    match text {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => {}
+    }
+    let digits = text.replace('_', "");
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        return digits
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| format!("{} is too large", text));
+    }
+    Err(format!(
+        "`{}` is not a string (in double quotes), an integer, true or false",
+        text
+    ))
+}
+
+/// Whether `value` suits `key`.
+fn check(key: &str, kind: Kind, value: &Value) -> Result<(), String> {
+    match (kind, value) {
+        (Kind::Text, Value::Text(_)) => Ok(()),
+        (Kind::Number(max), Value::Number(n)) if *n <= max => Ok(()),
+        (Kind::Number(max), Value::Number(_)) => Err(format!("`{}` must be at most {}", key, max)),
+        (Kind::Progress, Value::Text(mode)) if ProgressMode::from_str(mode, true).is_ok() => Ok(()),
+        (Kind::Progress, _) => Err(format!(
+            "`{}` must be one of \"auto\", \"bar\", \"plain\", \"none\"",
+            key
+        )),
+        (Kind::Text, _) => Err(format!("`{}` must be a string", key)),
+        (Kind::Number(_), _) => Err(format!("`{}` must be an integer", key)),
+    }
+}
+
+/// Where a value in effect came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    CommandLine,
+    File(PathBuf, usize),
+    Default,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::CommandLine => f.write_str("command line"),
+            Source::File(path, line) => write!(f, "{}:{}", path.display(), line),
+            Source::Default => f.write_str("default"),
+        }
+    }
+}
+
+/// One value in effect, for `config show`.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub key: &'static str,
+    /// `None` for an option that is not set.
+    pub value: Option<String>,
+    pub source: Source,
+}
+
+/// The configuration a run uses, for `config show`.
+#[derive(Clone, Debug, Default)]
+pub struct Effective {
+    pub files: Vec<(PathBuf, bool)>,
+    pub entries: Vec<Entry>,
+}
+
+/// Read the configuration files and fill in `cli` where the command line
+/// left a default.
+pub fn apply(cli: &mut Cli, matches: &ArgMatches) -> Result<()> {
+    let config = Config::load()?;
+    cli.config = Effective {
+        entries: config.apply_to(cli, matches),
+        files: config.files,
+    };
+    Ok(())
+}
+
+/// Print the configuration in effect and where each value came from.
+pub fn show(effective: &Effective) -> Result<()> {
+    outln!("Files:");
+    for (path, found) in &effective.files {
+        let state = if *found { "" } else { " (not found)" };
+        outln!("  {}{}", path.display(), state);
+    }
+    outln!("Settings:");
+    for entry in &effective.entries {
+        outln!(
+            "  {:<16} {:<20} {}",
+            entry.key,
+            entry.value.as_deref().unwrap_or("-"),
+            entry.source
+        );
+    }
+
+    let files: Vec<_> = effective
+        .files
+        .iter()
+        .map(|(path, found)| json!({ "path": path.display().to_string(), "found": found }))
+        .collect();
+    let settings: serde_json::Map<_, _> = effective
+        .entries
+        .iter()
+        .map(|entry| {
+            let value = json!({ "value": entry.value, "source": entry.source.to_string() });
+            (entry.key.to_string(), value)
+        })
+        .collect();
+    output::report(json!({ "files": files, "settings": settings }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, FromArgMatches};
+
+    fn settings(text: &str) -> Result<Vec<Setting>> {
+        parse(text, Path::new("crispy.toml"))
+    }
+
+    fn error(text: &str) -> String {
+        let err = settings(text).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "config");
+        failure.message.clone()
+    }
+
+    fn config(layers: &[&str]) -> Config {
+        let mut config = Config::default();
+        for (i, text) in layers.iter().enumerate() {
+            config.add(parse(text, Path::new(&format!("layer{}.toml", i))).unwrap());
+        }
+        config
+    }
+
+    fn cli(config: &Config, args: &[&str]) -> (Cli, Vec<Entry>) {
+        let matches = Cli::command().get_matches_from(args);
+        let mut cli = Cli::from_arg_matches(&matches).unwrap();
+        let entries = config.apply_to(&mut cli, &matches);
+        (cli, entries)
+    }
+
+    #[test]
+    fn test_parse() {
+        let parsed = settings(
+            "# defaults\n\nport = \"/dev/ttyACM0\" # the bench board\nread_timeout = 2_000\nserial = \"a#b\\\"c\"\nprogress = \"plain\"\n",
+        )
+        .unwrap();
+        let values: Vec<_> = parsed
+            .iter()
+            .map(|s| (s.key, s.value.clone(), s.line))
+            .collect();
+        assert_eq!(
+            values,
+            [
+                ("port", Value::Text("/dev/ttyACM0".into()), 3),
+                ("read_timeout", Value::Number(2000), 4),
+                ("serial", Value::Text("a#b\"c".into()), 5),
+                ("progress", Value::Text("plain".into()), 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let message = error("retries = 3\nprot = \"x\"\n");
+        assert!(
+            message.starts_with("crispy.toml:2: unknown key `prot`"),
+            "{}",
+            message
+        );
+        assert!(message.contains("known keys: port, serial"), "{}", message);
+
+        assert!(error("retries = \"3\"").contains("`retries` must be an integer"));
+        assert!(error("port = 3").contains("`port` must be a string"));
+        assert!(error("retries = true").contains("`retries` must be an integer"));
+        assert!(error("retries = 5000000000").contains("at most 4294967295"));
+        assert!(error("progress = \"fancy\"").contains("must be one of"));
+        assert!(error("port = \"x").contains("unterminated string"));
+        assert!(error("port = x").contains("not a string"));
+        assert!(error("retries").contains("expected `key = value`"));
+        assert!(error("[link]").contains("tables are not supported"));
+        assert!(error("retries = 1\nretries = 2").contains("already set on line 1"));
+    }
+
+    #[test]
+    fn test_layers() {
+        // The project's file first, the user's second
+        let config = config(&["retries = 5\n", "retries = 1\nread_timeout = 800\n"]);
+        assert_eq!(config.number("retries"), Some(5));
+        assert_eq!(config.number("read_timeout"), Some(800));
+        assert_eq!(
+            config.get("read_timeout").unwrap().file,
+            Path::new("layer1.toml")
+        );
+    }
+
+    #[test]
+    fn test_command_line_wins() {
+        let config = config(&[
+            "port = \"/dev/ttyACM3\"\nretries = 5\nchunk_size = 512\nprogress = \"none\"\n",
+            "read_timeout = 800\nconnect_timeout = 100\n",
+        ]);
+        let (cli, entries) = cli(
+            &config,
+            &["crispy-upload", "--retries", "1", "upload", "fw.bin"],
+        );
+        assert_eq!(cli.port, ["/dev/ttyACM3"]);
+        assert_eq!(cli.retries, 1);
+        assert_eq!(cli.read_timeout, 800);
+        assert_eq!(cli.progress, ProgressMode::None);
+        assert!(matches!(
+            cli.command,
+            Commands::Upload {
+                chunk_size: 512,
+                ..
+            }
+        ));
+
+        let source = |key| {
+            entries
+                .iter()
+                .find(|e| e.key == key)
+                .unwrap()
+                .source
+                .clone()
+        };
+        assert_eq!(source("retries"), Source::CommandLine);
+        assert_eq!(source("port"), Source::File("layer0.toml".into(), 1));
+        assert_eq!(
+            source("connect_timeout"),
+            Source::File("layer1.toml".into(), 2)
+        );
+        assert_eq!(source("retry_backoff"), Source::Default);
+
+        // A port, a serial number or --all on the command line replaces the
+        // configured port; --read-timeout replaces a configured --timeout
+        let config = self::config(&["port = \"/dev/ttyACM3\"\ntimeout = 9000\n"]);
+        let (cli, _) = self::cli(
+            &config,
+            &[
+                "crispy-upload",
+                "--serial",
+                "E661",
+                "--read-timeout",
+                "10",
+                "status",
+            ],
+        );
+        assert!(cli.port.is_empty());
+        assert_eq!(cli.serial.as_deref(), Some("E661"));
+        assert_eq!(cli.timeout, None);
+
+        let config = self::config(&["port = \"auto\"\nserial = \"E661\"\n"]);
+        let (cli, _) = self::cli(&config, &["crispy-upload", "status"]);
+        assert!(cli.port.is_empty());
+        assert_eq!(cli.serial.as_deref(), Some("E661"));
+    }
+
+    #[test]
+    fn test_missing_files_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("crispy-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let present = dir.join("config.toml");
+        fs::write(&present, "retries = 7\n").unwrap();
+        let config = Config::load_files(&[dir.join("missing.toml"), present.clone()]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(config.number("retries"), Some(7));
+        assert_eq!(
+            config.files,
+            [(dir.join("missing.toml"), false), (present, true)]
+        );
+    }
+}
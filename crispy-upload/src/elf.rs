@@ -0,0 +1,275 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! ELF input for `upload`, `update` and `inspect`.
+//!
+//! `cargo build` leaves an ELF, and flattening it here saves the objcopy
+//! step. The image is the file contents of the `PT_LOAD` segments placed at
+//! their load addresses, which for the RAM-linked firmware puts `.data`
+//! right after `.rodata`, as `objcopy -O binary` does. Gaps between
+//! segments are zero-filled like objcopy fills them, but segments must not
+//! overlap, must lie in the firmware RAM window or a bank, and must span no
+//! more than a bank.
+//!
+//! The firmware version comes from a `.crispy_header` section holding an
+//! [`ImageHeader`], else from the `.crispy_fw_version` section
+//! `crispy_common::fw_version!` fills, else from a `CRISPY_FW_VERSION`
+//! symbol holding the raw `u32` version.
+
+use anyhow::{anyhow, bail, Context, Result};
+use object::elf::{EM_ARM, PT_LOAD};
+use object::read::elf::{ElfFile32, FileHeader, ProgramHeader};
+use object::{Endianness, Object, ObjectSection, ObjectSymbol};
+
+use crispy_common::image::{ImageHeader, IMAGE_HEADER_SIZE};
+use crispy_common::protocol::FLASH_SECTOR_SIZE;
+use crispy_common::vector_table::FW_RAM_WINDOW;
+use crispy_common::version::{VersionBlock, VERSION_BLOCK_SIZE, VERSION_SECTION};
+use crispy_common::{FlashLayout, FwVersion, BOOT_DATA_ADDR, FLASH_BASE, FW_A_ADDR};
+
+/// Section holding an image header, for its firmware version.
+pub const HEADER_SECTION: &str = ".crispy_header";
+/// Symbol holding the raw firmware version.
+pub const VERSION_SYMBOL: &str = "CRISPY_FW_VERSION";
+
+/// An ELF file flattened to a raw image.
+pub struct ElfImage {
+    /// Load address of the first byte of `data`.
+    pub base: u32,
+    pub data: Vec<u8>,
+    /// Number of loadable segments with file contents.
+    pub segments: usize,
+    /// Firmware version and where it was found.
+    pub version: Option<(FwVersion, &'static str)>,
+}
+
+/// Whether `file` starts with the ELF magic.
+pub fn is_elf(file: &[u8]) -> bool {
+    file.starts_with(b"\x7fELF")
+}
+
+/// A loadable segment: its load address and its bytes in the file.
+#[derive(Debug)]
+struct Segment<'a> {
+    addr: u32,
+    data: &'a [u8],
+}
+
+/// Flatten an ARM ELF file into the image the bootloader takes, for banks
+/// of `bank_size` bytes.
+pub fn load(file: &[u8], bank_size: u32) -> Result<ElfImage> {
+    let elf = ElfFile32::<Endianness>::parse(file)
+        .map_err(|err| anyhow!("not a 32-bit ELF file: {}", err))?;
+    let endian = elf.endian();
+    let machine = elf.elf_header().e_machine(endian);
+    if machine != EM_ARM {
+        bail!("ELF is for machine {}, not ARM ({})", machine, EM_ARM);
+    }
+
+    let mut segments = Vec::new();
+    for ph in elf.elf_program_headers() {
+        if ph.p_type(endian) != PT_LOAD || ph.p_filesz(endian) == 0 {
+            continue;
+        }
+        let addr = ph.p_paddr(endian);
+        let data = ph
+            .data(endian, file)
+            .map_err(|()| anyhow!("segment at 0x{:08x} runs past the end of the file", addr))?;
+        segments.push(Segment { addr, data });
+    }
+    let (base, data) = flatten(&mut segments, bank_size)?;
+
+    Ok(ElfImage {
+        base,
+        data,
+        segments: segments.len(),
+        version: version(&elf)?,
+    })
+}
+
+/// Lay the segments out by address, zero-filling the gaps.
+fn flatten(segments: &mut [Segment], bank_size: u32) -> Result<(u32, Vec<u8>)> {
+    segments.sort_by_key(|s| s.addr);
+    let base = segments
+        .first()
+        .context("ELF has no loadable segments")?
+        .addr;
+
+    let mut data: Vec<u8> = Vec::new();
+    let mut prev: Option<&Segment> = None;
+    for s in segments.iter() {
+        check_region(s)?;
+        if let Some(prev) = prev {
+            if u64::from(s.addr) < u64::from(base) + data.len() as u64 {
+                bail!(
+                    "segment at 0x{:08x} overlaps the segment at 0x{:08x}",
+                    s.addr,
+                    prev.addr
+                );
+            }
+        }
+        let offset = (s.addr - base) as usize;
+        if offset + s.data.len() > bank_size as usize {
+            bail!(
+                "segments from 0x{:08x} to 0x{:08x} span more than a bank ({} bytes)",
+                base,
+                u64::from(s.addr) + s.data.len() as u64,
+                bank_size
+            );
+        }
+        data.resize(offset, 0);
+        data.extend_from_slice(s.data);
+        prev = Some(s);
+    }
+    Ok((base, data))
+}
+
+/// Check that a segment lies in the firmware RAM window or a bank, naming
+/// what it runs into otherwise.
+fn check_region(s: &Segment) -> Result<()> {
+    let start = u64::from(s.addr);
+    let end = start + s.data.len() as u64;
+    let overlaps =
+        |addr: u32, len: u32| start < u64::from(addr) + u64::from(len) && end > addr.into();
+
+    if overlaps(FLASH_BASE, FW_A_ADDR - FLASH_BASE) {
+        bail!(
+            "segment at 0x{:08x} overlaps the bootloader region (0x{:08x}..0x{:08x})",
+            s.addr,
+            FLASH_BASE,
+            FW_A_ADDR
+        );
+    }
+    if overlaps(BOOT_DATA_ADDR, FLASH_SECTOR_SIZE) {
+        bail!(
+            "segment at 0x{:08x} overlaps BootData at 0x{:08x}",
+            s.addr,
+            BOOT_DATA_ADDR
+        );
+    }
+    let len = s.data.len() as u32;
+    let in_ram = FW_RAM_WINDOW.contains(s.addr) && end <= FW_RAM_WINDOW.end.into();
+    let in_bank = (0..2).any(|bank| FlashLayout::DEFAULT.bank_contains(bank, s.addr, len));
+    if !in_ram && !in_bank {
+        bail!(
+            "segment at 0x{:08x} ({} bytes) is in neither the firmware RAM window nor a bank",
+            s.addr,
+            len
+        );
+    }
+    Ok(())
+}
+
+/// The firmware version recorded in the ELF, if any.
+fn version(elf: &ElfFile32<Endianness>) -> Result<Option<(FwVersion, &'static str)>> {
+    if let Some(section) = elf.section_by_name(HEADER_SECTION) {
+        let raw = section.data().ok().and_then(|d| d.get(..IMAGE_HEADER_SIZE));
+        let raw =
+            raw.with_context(|| format!("{} is shorter than an image header", HEADER_SECTION))?;
+        let header = ImageHeader::from_bytes(raw.try_into().unwrap())
+            .map_err(|err| anyhow!("{}: invalid image header: {:?}", HEADER_SECTION, err))?;
+        return Ok(Some((header.fw_version, HEADER_SECTION)));
+    }
+
+    if let Some(section) = elf.section_by_name(VERSION_SECTION) {
+        let raw = section
+            .data()
+            .ok()
+            .and_then(|d| d.get(..VERSION_BLOCK_SIZE));
+        let block = raw
+            .and_then(|raw| VersionBlock::from_bytes(raw.try_into().unwrap()))
+            .with_context(|| format!("{} holds no version block", VERSION_SECTION))?;
+        return Ok(Some((block.version(), VERSION_SECTION)));
+    }
+
+    if let Some(symbol) = elf.symbol_by_name(VERSION_SYMBOL) {
+        let bytes = elf
+            .sections()
+            .find_map(|s| s.data_range(symbol.address(), 4).ok().flatten())
+            .with_context(|| format!("{} has no contents", VERSION_SYMBOL))?;
+        let raw = u32::from_le_bytes(bytes.try_into().unwrap());
+        return Ok(Some((FwVersion::from_raw(raw), VERSION_SYMBOL)));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crispy_common::memory_layout::FW_RAM_BASE;
+    use crispy_common::{FW_BANK_SIZE, FW_B_ADDR};
+
+    fn error(segments: &mut [Segment]) -> String {
+        flatten(segments, FW_BANK_SIZE).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_flatten_ram_image() {
+        // .text at the RAM base, .data loaded right after it
+        let (text, data) = ([1u8; 16], [2u8; 8]);
+        let mut segments = [
+            Segment {
+                addr: FW_RAM_BASE + 16,
+                data: &data,
+            },
+            Segment {
+                addr: FW_RAM_BASE,
+                data: &text,
+            },
+        ];
+        let (base, image) = flatten(&mut segments, FW_BANK_SIZE).unwrap();
+        assert_eq!(base, FW_RAM_BASE);
+        assert_eq!(image, [&text[..], &data[..]].concat());
+    }
+
+    #[test]
+    fn test_flatten_fills_gaps() {
+        let (a, b) = ([1u8; 4], [2u8; 4]);
+        let mut segments = [
+            Segment {
+                addr: FW_RAM_BASE,
+                data: &a,
+            },
+            Segment {
+                addr: FW_RAM_BASE + 8,
+                data: &b,
+            },
+        ];
+        let (_, image) = flatten(&mut segments, FW_BANK_SIZE).unwrap();
+        assert_eq!(image, [1, 1, 1, 1, 0, 0, 0, 0, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_flatten_errors() {
+        let bytes = [0u8; 32];
+        let at = |addr| Segment { addr, data: &bytes };
+
+        assert_eq!(
+            error(&mut [at(FLASH_BASE)]),
+            format!(
+                "segment at 0x10000000 overlaps the bootloader region (0x10000000..0x{:08x})",
+                FW_A_ADDR
+            )
+        );
+        assert_eq!(
+            error(&mut [at(FW_RAM_BASE), at(FW_RAM_BASE + 16)]),
+            "segment at 0x20000010 overlaps the segment at 0x20000000"
+        );
+        assert!(error(&mut [at(FW_A_ADDR), at(FW_B_ADDR)]).contains("span more than a bank"));
+        assert_eq!(
+            flatten(&mut [at(FW_RAM_BASE), at(FW_RAM_BASE + 32)], 48)
+                .unwrap_err()
+                .to_string(),
+            "segments from 0x20000000 to 0x20000040 span more than a bank (48 bytes)"
+        );
+        assert!(error(&mut [at(0x3000_0000)]).contains("in neither the firmware RAM window"));
+        assert_eq!(error(&mut []), "ELF has no loadable segments");
+    }
+
+    #[test]
+    fn test_is_elf() {
+        assert!(is_elf(b"\x7fELF\x01\x01\x01"));
+        assert!(!is_elf(b"\x00\x00\x04\x20"));
+    }
+}
@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! The stages of `factory` and the record it leaves for the production log.
+//!
+//! A blank board in update mode goes through the same subcommands a `run`
+//! script would use, one stage each:
+//!
+//! | Stage | Does |
+//! |-------|------|
+//! | `wipe` | `wipe` |
+//! | `upload-a` | `upload FW_A --bank 0` |
+//! | `upload-b` | `upload FW_B --bank 1`, with `--fw-b` |
+//! | `provision` | `provision --board ID --revision REV --serial SN`, with `--board` and `--unit-serial` |
+//! | `set-bank` | `set-bank 0` |
+//! | `verify` | `verify FW_A --bank 0`, and bank B with `--fw-b` |
+//! | `reboot` | reboot into bank A and wait for the firmware to come back |
+//!
+//! A stage that fails is tried again, up to `--attempts` times, and the run
+//! stops at the first one that keeps failing. `--from STAGE` resumes there
+//! on the next try, skipping what was done. Either way one [`Record`] sums
+//! the unit up: the `result` data with `--json`, and a line appended to
+//! `--log` for the production log.
+//!
+//! A unit provisioned with the same record before passes `provision` as it
+//! is; one with another record fails it unless `--force`.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crispy_common::crc32;
+
+/// One stage, in the order they run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    Wipe,
+    UploadA,
+    UploadB,
+    Provision,
+    SetBank,
+    Verify,
+    Reboot,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Wipe => "wipe",
+            Stage::UploadA => "upload-a",
+            Stage::UploadB => "upload-b",
+            Stage::Provision => "provision",
+            Stage::SetBank => "set-bank",
+            Stage::Verify => "verify",
+            Stage::Reboot => "reboot",
+        }
+    }
+}
+
+/// What to put on the board.
+pub struct Plan<'a> {
+    pub fw_a: &'a Path,
+    pub fw_b: Option<&'a Path>,
+    /// Board ID, hardware revision and serial number to provision.
+    pub provision: Option<(u16, u8, &'a str)>,
+}
+
+impl Plan<'_> {
+    /// Each stage with the subcommands that make it up, as script words;
+    /// `reboot` has none and is run on its own.
+    pub fn stages(&self) -> Vec<(Stage, Vec<Vec<String>>)> {
+        let words = |parts: &[&str]| parts.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let file = |path: &Path| path.display().to_string();
+        // Resuming after a boot confirmed bank A writes it again on purpose
+        let upload = |path: &Path, bank| {
+            words(&["upload", &file(path), "--bank", bank, "--allow-active-bank"])
+        };
+        let verify = |path: &Path, bank| words(&["verify", &file(path), "--bank", bank]);
+
+        let mut stages = vec![
+            (Stage::Wipe, vec![words(&["wipe"])]),
+            (Stage::UploadA, vec![upload(self.fw_a, "0")]),
+        ];
+        if let Some(fw_b) = self.fw_b {
+            stages.push((Stage::UploadB, vec![upload(fw_b, "1")]));
+        }
+        if let Some((board, revision, serial)) = self.provision {
+            let (board, revision) = (board.to_string(), revision.to_string());
+            stages.push((
+                Stage::Provision,
+                vec![words(&[
+                    "provision",
+                    "--board",
+                    &board,
+                    "--revision",
+                    &revision,
+                    "--serial",
+                    serial,
+                ])],
+            ));
+        }
+        stages.push((Stage::SetBank, vec![words(&["set-bank", "0"])]));
+        let mut checks = vec![verify(self.fw_a, "0")];
+        checks.extend(self.fw_b.map(|fw_b| verify(fw_b, "1")));
+        stages.push((Stage::Verify, checks));
+        stages.push((Stage::Reboot, Vec::new()));
+        stages
+    }
+}
+
+/// How a stage went.
+#[derive(Clone, Debug, Serialize)]
+pub struct StageRecord {
+    pub stage: Stage,
+    /// `ok`, `failed` or `skipped`.
+    pub outcome: &'static str,
+    pub attempts: u32,
+    pub ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A firmware file that went on the board.
+#[derive(Clone, Debug, Serialize)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    /// CRC32 of the file, as the history records it.
+    pub crc32: Option<u32>,
+}
+
+impl FileRecord {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            crc32: std::fs::read(path).ok().map(|fw| crc32::checksum(&fw)),
+        }
+    }
+}
+
+/// One unit's pass or fail, for the production log.
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    /// UTC time the run ended, RFC 3339.
+    pub timestamp: String,
+    pub port: String,
+    /// USB serial number of the device, when the port has one.
+    pub device: Option<String>,
+    pub board: Option<u16>,
+    pub unit_serial: Option<String>,
+    pub fw_a: FileRecord,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fw_b: Option<FileRecord>,
+    /// `pass` or `fail`.
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_stage: Option<Stage>,
+    pub stages: Vec<StageRecord>,
+}
+
+/// Append `record` to the production log at `path`, one JSON line.
+pub fn append_log(path: &Path, record: &Record) -> Result<()> {
+    let line = serde_json::to_string(record).expect("records serialize");
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", line).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stages() {
+        let plan = Plan {
+            fw_a: Path::new("a.bin"),
+            fw_b: None,
+            provision: None,
+        };
+        let names: Vec<_> = plan.stages().iter().map(|(s, _)| s.name()).collect();
+        assert_eq!(names, ["wipe", "upload-a", "set-bank", "verify", "reboot"]);
+
+        let plan = Plan {
+            fw_a: Path::new("a.bin"),
+            fw_b: Some(Path::new("b.bin")),
+            provision: Some((2, 1, "SN1")),
+        };
+        let stages = plan.stages();
+        let names: Vec<_> = stages.iter().map(|(s, _)| s.name()).collect();
+        assert_eq!(
+            names,
+            [
+                "wipe",
+                "upload-a",
+                "upload-b",
+                "provision",
+                "set-bank",
+                "verify",
+                "reboot"
+            ]
+        );
+        assert_eq!(
+            stages[2].1,
+            [["upload", "b.bin", "--bank", "1", "--allow-active-bank"]]
+        );
+        assert_eq!(
+            stages[3].1,
+            [[
+                "provision",
+                "--board",
+                "2",
+                "--revision",
+                "1",
+                "--serial",
+                "SN1"
+            ]]
+        );
+        assert_eq!(stages[5].1.len(), 2);
+        // Stages sort in the order they run, for --from
+        assert!(stages.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_record_line() {
+        let record = Record {
+            timestamp: "2026-01-02T03:04:05Z".to_string(),
+            port: "/dev/ttyACM0".to_string(),
+            device: None,
+            board: Some(2),
+            unit_serial: None,
+            fw_a: FileRecord {
+                path: "a.bin".into(),
+                crc32: Some(1),
+            },
+            fw_b: None,
+            result: "fail",
+            failed_stage: Some(Stage::UploadA),
+            stages: vec![StageRecord {
+                stage: Stage::UploadA,
+                outcome: "failed",
+                attempts: 2,
+                ms: 10,
+                error: Some("Timeout".to_string()),
+            }],
+        };
+        assert_eq!(
+            serde_json::to_string(&record).unwrap(),
+            r#"{"timestamp":"2026-01-02T03:04:05Z","port":"/dev/ttyACM0","device":null,"board":2,"unit_serial":null,"fw_a":{"path":"a.bin","crc32":1},"result":"fail","failed_stage":"upload-a","stages":[{"stage":"upload-a","outcome":"failed","attempts":2,"ms":10,"error":"Timeout"}]}"#
+        );
+    }
+}
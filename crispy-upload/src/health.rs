@@ -0,0 +1,408 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `verify-all`: whether a device is consistent with itself, for periodic
+//! fleet checks.
+//!
+//! `GetBootData` has the device recompute the CRC32 of each bank that
+//! records an image and compare it with BootData, so every check below is
+//! the device's own view, not the host's:
+//!
+//! | Check | Fails when |
+//! |-------|------------|
+//! | `record` | BootData does not decode (magic, version, record CRC); warns when none was written yet or the bootloader rebuilt it from the banks |
+//! | `fields` | A field is out of range (see `BootDataIssue`); warns when the bootloader only resets it at boot |
+//! | `bank_a`, `bank_b` | A bank with a non-zero size does not fit or does not match its CRC32 |
+//! | `active_bank` | The active bank is not one that holds a valid image; warns when neither does |
+//! | `confirmed` | The firmware is unconfirmed and out of boot attempts, so the next boot rolls back; warns one attempt before |
+//!
+//! A table gives each check's verdict. The exit status is that of the
+//! worst: 0 when all pass, 1 for a warning, 2 for a failure, and 3 when the
+//! checks could not be made. With `--json` the checks are the `checks`
+//! data, or the `context.checks` of the `inconsistent` error.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use serde_json::json;
+
+use crispy_common::flash::BankVerify;
+use crispy_common::protocol::{AckStatus, BootData, Command, Response};
+
+use crate::commands;
+use crate::output::{self, outln, Failure, Progress};
+use crate::transport::Transport;
+
+/// How a check came out, best first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Verdict {
+    fn name(self) -> &'static str {
+        match self {
+            Verdict::Pass => "pass",
+            Verdict::Warn => "warn",
+            Verdict::Fail => "fail",
+        }
+    }
+}
+
+/// One row of the table.
+#[derive(Debug, Serialize)]
+struct Finding {
+    check: &'static str,
+    verdict: Verdict,
+    detail: String,
+}
+
+impl Finding {
+    fn new(check: &'static str, verdict: Verdict, detail: impl Into<String>) -> Self {
+        Self {
+            check,
+            verdict,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Run every check and report them, failing with `inconsistent` unless all
+/// pass.
+pub fn verify_all(transport: &mut Transport) -> Result<()> {
+    let status = transport.send_recv(&Command::GetStatus)?;
+    let Response::Status {
+        bootdata_reconstructed,
+        ..
+    } = status
+    else {
+        bail!(commands::unexpected(&status));
+    };
+
+    outln!("Checking BootData and both banks...");
+    let pb = Progress::percent("verify")?;
+    let response = transport.send_recv_with_progress(&Command::GetBootData, |done, total| {
+        pb.set_length(total as u64);
+        pb.set_position(done as u64);
+    });
+    pb.finish_and_clear();
+    let (raw, banks) = match response? {
+        Response::BootData { raw, banks } => (raw, banks),
+        Response::Ack(AckStatus::BadCommand) => {
+            bail!("Bootloader is too old to report bank checks")
+        }
+        other => bail!(commands::unexpected(&other)),
+    };
+    summarize(&checks(&raw, &banks, bootdata_reconstructed))
+}
+
+/// The checks of a BootData record as `GetBootData` reports it.
+fn checks(raw: &[u8], banks: &[BankVerify; 2], reconstructed: bool) -> Vec<Finding> {
+    // A blank device has no record yet, and the defaults stand in for it
+    let blank = raw.iter().all(|&b| b == 0xFF);
+    let bd = match commands::decode_boot_data(raw) {
+        Ok(bd) => bd,
+        Err(_) if blank => BootData::default_new(),
+        Err(err) => {
+            let detail = format!(
+                "invalid ({}); the bootloader rebuilds it from the banks",
+                err
+            );
+            return vec![Finding::new("record", Verdict::Fail, detail)];
+        }
+    };
+    let mut findings = vec![if blank {
+        Finding::new("record", Verdict::Warn, "none written yet, a blank device")
+    } else if reconstructed {
+        Finding::new(
+            "record",
+            Verdict::Warn,
+            "valid, rebuilt from the banks after it was found corrupt",
+        )
+    } else {
+        Finding::new(
+            "record",
+            Verdict::Pass,
+            format!("valid, layout version {}", bd.layout_version),
+        )
+    }];
+
+    let issues: Vec<_> = bd.issues().collect();
+    findings.push(if issues.is_empty() {
+        Finding::new("fields", Verdict::Pass, "every field in range")
+    } else {
+        let list = issues
+            .iter()
+            .map(|issue| format!("{:?}", issue))
+            .collect::<Vec<_>>()
+            .join(", ");
+        if issues.iter().all(|issue| issue.is_repairable()) {
+            let detail = format!("{}; the bootloader resets them at boot", list);
+            Finding::new("fields", Verdict::Warn, detail)
+        } else {
+            let detail = format!("{}; the bootloader rebuilds the record", list);
+            Finding::new("fields", Verdict::Fail, detail)
+        }
+    });
+
+    let sizes = [bd.size_a, bd.size_b];
+    let mut valid = [false; 2];
+    for (bank, check) in ["bank_a", "bank_b"].into_iter().enumerate() {
+        let (verdict, detail) = match banks[bank] {
+            _ if sizes[bank] == 0 => (Verdict::Pass, "empty".to_string()),
+            BankVerify::Ok { size, crc } => {
+                valid[bank] = true;
+                let detail = format!("{} bytes, CRC32 0x{:08x} as recorded", size, crc);
+                (Verdict::Pass, detail)
+            }
+            BankVerify::CrcMismatch { expected, computed } => (
+                Verdict::Fail,
+                format!(
+                    "CRC32 0x{:08x} over {} bytes, 0x{:08x} recorded",
+                    computed, sizes[bank], expected
+                ),
+            ),
+            BankVerify::SizeOutOfRange(size) => {
+                (Verdict::Fail, format!("size {} does not fit a bank", size))
+            }
+            BankVerify::NoMetadata | BankVerify::BadBank(_) => (
+                Verdict::Fail,
+                format!("{} bytes recorded, not checked", sizes[bank]),
+            ),
+        };
+        findings.push(Finding::new(check, verdict, detail));
+    }
+
+    let name = |bank: u8| if bank == 0 { "A" } else { "B" };
+    let active = bd.active_bank;
+    findings.push(if active > 1 {
+        Finding::new(
+            "active_bank",
+            Verdict::Fail,
+            format!("{} is no bank", active),
+        )
+    } else if valid[active as usize] {
+        let detail = format!("bank {}, which holds a valid image", name(active));
+        Finding::new("active_bank", Verdict::Pass, detail)
+    } else if valid[1 - active as usize] {
+        let detail = format!(
+            "bank {} holds no valid image; the bootloader falls back to bank {}",
+            name(active),
+            name(1 - active)
+        );
+        Finding::new("active_bank", Verdict::Fail, detail)
+    } else if sizes.iter().any(|&size| size != 0) {
+        let detail = "neither bank holds a valid image; the device stays in the bootloader";
+        Finding::new("active_bank", Verdict::Fail, detail)
+    } else {
+        Finding::new("active_bank", Verdict::Warn, "neither bank holds firmware")
+    });
+
+    let (attempts, limit) = (bd.boot_attempts, bd.boot_attempt_limit());
+    findings.push(if bd.confirmed == 1 {
+        Finding::new("confirmed", Verdict::Pass, "the firmware confirmed itself")
+    } else if attempts >= limit {
+        let detail = format!(
+            "unconfirmed after {} of {} boots; the next boot rolls back",
+            attempts, limit
+        );
+        Finding::new("confirmed", Verdict::Fail, detail)
+    } else if attempts > 0 && attempts + 1 >= limit {
+        let detail = format!(
+            "unconfirmed after {} of {} boots; one more rolls back",
+            attempts, limit
+        );
+        Finding::new("confirmed", Verdict::Warn, detail)
+    } else {
+        let detail = format!("unconfirmed, {} of {} boots", attempts, limit);
+        Finding::new("confirmed", Verdict::Pass, detail)
+    });
+    findings
+}
+
+/// Print the table and report the findings, failing with `inconsistent`
+/// unless all pass.
+fn summarize(findings: &[Finding]) -> Result<()> {
+    outln!("{:<12}  {:<6}  DETAIL", "CHECK", "RESULT");
+    for finding in findings {
+        outln!(
+            "{:<12}  {:<6}  {}",
+            finding.check,
+            finding.verdict.name(),
+            finding.detail
+        );
+    }
+    let worst = findings
+        .iter()
+        .map(|f| f.verdict)
+        .max()
+        .unwrap_or(Verdict::Pass);
+    outln!("{}", worst.name().to_uppercase());
+
+    let records = serde_json::to_value(findings).expect("findings serialize");
+    if worst == Verdict::Pass {
+        output::report(json!({ "worst": worst, "checks": records }));
+        return Ok(());
+    }
+    let flagged: Vec<_> = findings
+        .iter()
+        .filter(|f| f.verdict == worst)
+        .map(|f| f.check)
+        .collect();
+    let what = if worst == Verdict::Fail {
+        "failed"
+    } else {
+        "warned"
+    };
+    bail!(Failure::new(
+        "inconsistent",
+        format!("{} {}: {}", flagged.len(), what, flagged.join(", "))
+    )
+    .with("worst", worst.name())
+    .with("flagged", flagged)
+    .with("checks", records))
+}
+
+/// Exit status for a failed `verify-all`: 1 for a warning, 2 for a failed
+/// check, 3 when the checks could not be made.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    match err.downcast_ref::<Failure>() {
+        Some(failure) if failure.code == "inconsistent" => {
+            if failure.context["worst"] == "warn" {
+                1
+            } else {
+                2
+            }
+        }
+        _ => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkOptions;
+    use crate::test_device::TestDevice;
+    use crispy_common::flash::write_boot_data;
+    use crispy_common::flash_sim::FlashSim;
+    use crispy_common::{crc32, FlashLayout};
+
+    fn verdicts(findings: &[Finding]) -> Vec<(&str, Verdict)> {
+        findings.iter().map(|f| (f.check, f.verdict)).collect()
+    }
+
+    /// A device with `image` in bank A, recorded by `bd`, as the
+    /// checks of a `verify-all` on it find them.
+    fn verify(image: &[u8], bd: BootData) -> Result<()> {
+        let mut flash = FlashSim::new();
+        flash.poke(FlashLayout::DEFAULT.bank_addr(0).unwrap(), image);
+        write_boot_data(&mut flash, &bd).unwrap();
+        let device = TestDevice::with_flash(flash);
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let (result, _) = output::collect(|| verify_all(&mut transport));
+        drop(transport);
+        device.finish();
+        result
+    }
+
+    fn recorded(image: &[u8]) -> BootData {
+        BootData {
+            size_a: image.len() as u32,
+            crc_a: crc32::checksum(image),
+            confirmed: 1,
+            ..BootData::default_new()
+        }
+    }
+
+    fn checks_of(err: &anyhow::Error) -> Vec<(String, String)> {
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        let checks = failure.context["checks"].as_array().unwrap();
+        checks
+            .iter()
+            .filter(|c| c["verdict"] != "pass")
+            .map(|c| {
+                (
+                    c["check"].as_str().unwrap().into(),
+                    c["verdict"].as_str().unwrap().into(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_verify_all() {
+        let image: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        verify(&image, recorded(&image)).unwrap();
+
+        // A bank changed behind BootData's back
+        let mut changed = image.clone();
+        changed[100] ^= 0x01;
+        let err = verify(&changed, recorded(&image)).unwrap_err();
+        assert_eq!(exit_code(&err), 2);
+        let flagged = [("bank_a", "fail"), ("active_bank", "fail")];
+        assert_eq!(checks_of(&err), flagged.map(|(c, v)| (c.into(), v.into())));
+
+        // Unconfirmed, one boot short of rolling back
+        let bd = BootData {
+            confirmed: 0,
+            boot_attempts: 2,
+            ..recorded(&image)
+        };
+        let err = verify(&image, bd).unwrap_err();
+        assert_eq!(exit_code(&err), 1);
+        assert_eq!(checks_of(&err), [("confirmed".into(), "warn".into())]);
+
+        assert_eq!(exit_code(&anyhow::anyhow!("no device")), 3);
+    }
+
+    #[test]
+    fn test_checks() {
+        let ok = [BankVerify::Ok { size: 16, crc: 1 }, BankVerify::NoMetadata];
+        let bd = BootData {
+            size_a: 16,
+            ..BootData::default_new()
+        };
+
+        let findings = checks(&bd.to_bytes(), &ok, false);
+        assert!(findings.iter().all(|f| f.verdict == Verdict::Pass));
+        assert_eq!(findings.len(), 6);
+
+        let findings = checks(&bd.to_bytes(), &ok, true);
+        assert_eq!(verdicts(&findings)[0], ("record", Verdict::Warn));
+
+        // The active bank is the empty one
+        let other = BootData {
+            active_bank: 1,
+            ..bd
+        };
+        let findings = checks(&other.to_bytes(), &ok, false);
+        assert_eq!(verdicts(&findings)[4], ("active_bank", Verdict::Fail));
+
+        // Nothing anywhere: a blank device
+        let blank = [BankVerify::NoMetadata, BankVerify::NoMetadata];
+        let findings = checks(&BootData::default_new().to_bytes(), &blank, false);
+        assert_eq!(verdicts(&findings)[4], ("active_bank", Verdict::Warn));
+        let findings = checks(&[0xFF; 64], &blank, false);
+        let warned = [("record", Verdict::Warn), ("active_bank", Verdict::Warn)];
+        let flagged: Vec<_> = verdicts(&findings)
+            .into_iter()
+            .filter(|(_, verdict)| *verdict != Verdict::Pass)
+            .collect();
+        assert_eq!(flagged, warned);
+
+        // Out of attempts, and a flag no build defines
+        let spent = BootData {
+            boot_attempts: 3,
+            flags: 0x80,
+            ..bd
+        };
+        let findings = checks(&spent.to_bytes(), &ok, false);
+        assert_eq!(verdicts(&findings)[1], ("fields", Verdict::Warn));
+        assert_eq!(verdicts(&findings)[5], ("confirmed", Verdict::Fail));
+
+        let findings = checks(&[0u8; 64], &blank, false);
+        assert_eq!(verdicts(&findings), [("record", Verdict::Fail)]);
+    }
+}
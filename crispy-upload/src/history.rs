@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Local history of the operations run against devices.
+//!
+//! Every command that changes a device (upload, update, set-bank, clone,
+//! wipe, set-boot-attempts, set-version, reboot) appends one JSON line to the
+//! history file, so "what did we last do to this unit, and when" can be
+//! answered later with `crispy-upload history`. Writing is best-effort: a history that cannot be
+//! written prints a warning and never fails the operation itself.
+//!
+//! The file is `--history-file`, else `$CRISPY_HISTORY`, else
+//! `crispy/history.jsonl` under the user data directory. `--no-history` or
+//! `CRISPY_HISTORY=off` turns it off.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable overriding the history path; `off` disables it.
+pub const HISTORY_ENV: &str = "CRISPY_HISTORY";
+
+/// One operation, as stored on one line of the history file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// UTC time the operation finished, RFC 3339.
+    pub timestamp: String,
+    /// USB serial number of the device, when the port has one.
+    pub device: Option<String>,
+    pub port: String,
+    /// Subcommand name, e.g. `upload`.
+    pub command: String,
+    /// Subcommand arguments.
+    pub params: serde_json::Value,
+    /// `ok` or `failed`.
+    pub outcome: String,
+    /// Why the operation failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// CRC32 of the firmware file, for uploads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fw_crc: Option<u32>,
+}
+
+impl Record {
+    /// Record for an operation that just finished with `result`.
+    pub fn new<T>(
+        device: Option<String>,
+        port: &str,
+        command: &str,
+        params: serde_json::Value,
+        fw_crc: Option<u32>,
+        result: &Result<T>,
+    ) -> Self {
+        Self {
+            timestamp: now(),
+            device,
+            port: port.to_string(),
+            command: command.to_string(),
+            params,
+            outcome: if result.is_ok() { "ok" } else { "failed" }.to_string(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            fw_crc,
+        }
+    }
+
+    /// Whether the record is for the device with USB serial `serial`.
+    pub fn is_for_device(&self, serial: &str) -> bool {
+        self.device
+            .as_deref()
+            .is_some_and(|d| d.eq_ignore_ascii_case(serial))
+    }
+}
+
+/// The history file to use, or `None` if history is turned off.
+pub fn path(flag: Option<PathBuf>, disabled: bool) -> Option<PathBuf> {
+    if disabled {
+        return None;
+    }
+    if flag.is_some() {
+        return flag;
+    }
+    match std::env::var_os(HISTORY_ENV) {
+        Some(value) if value == "off" => None,
+        Some(value) if !value.is_empty() => Some(PathBuf::from(value)),
+        _ => data_dir().map(|dir| dir.join("crispy").join("history.jsonl")),
+    }
+}
+
+/// `$XDG_DATA_HOME`, `~/.local/share`, or `%LOCALAPPDATA%` on Windows.
+fn data_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    var("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".local").join("share")))
+        .or_else(|| var("LOCALAPPDATA").map(PathBuf::from))
+}
+
+/// Append `record` to the history at `path`, warning instead of failing.
+pub fn append(path: &Path, record: &Record) {
+    if let Err(err) = try_append(path, record) {
+        eprintln!(
+            "Warning: could not write history to {}: {}",
+            path.display(),
+            err
+        );
+    }
+}
+
+fn try_append(path: &Path, record: &Record) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    // One write per record, so concurrent runs do not interleave lines
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(line.as_bytes())
+}
+
+/// Read every record in the history, oldest first.
+///
+/// A missing file is an empty history. Lines that do not parse (a write cut
+/// short, a hand edit) are skipped.
+pub fn read(path: &Path) -> Result<Vec<Record>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    Ok(parse(&text))
+}
+
+fn parse(text: &str) -> Vec<Record> {
+    text.lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// The records for the device with USB serial `device`, or all of them.
+pub fn filter<'a>(
+    records: &'a [Record],
+    device: Option<&'a str>,
+) -> impl Iterator<Item = &'a Record> {
+    records
+        .iter()
+        .filter(move |r| device.is_none_or(|serial| r.is_for_device(serial)))
+}
+
+/// The time now, as [`Record::timestamp`] has it.
+pub fn now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    utc_timestamp(secs)
+}
+
+/// Format seconds since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_timestamp(secs: u64) -> String {
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (proleptic Gregorian)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(device: Option<&str>, command: &str) -> Record {
+        Record {
+            timestamp: "2026-01-02T03:04:05Z".to_string(),
+            device: device.map(str::to_string),
+            port: "/dev/ttyACM0".to_string(),
+            command: command.to_string(),
+            params: serde_json::json!({ "bank": 1 }),
+            outcome: "ok".to_string(),
+            error: None,
+            fw_crc: None,
+        }
+    }
+
+    #[test]
+    fn test_record_line_format() {
+        let rec = Record {
+            fw_crc: Some(0xDEAD_BEEF),
+            ..record(Some("E6614103E7452D2F"), "upload")
+        };
+        let line = serde_json::to_string(&rec).unwrap();
+        assert_eq!(
+            line,
+            r#"{"timestamp":"2026-01-02T03:04:05Z","device":"E6614103E7452D2F","port":"/dev/ttyACM0","command":"upload","params":{"bank":1},"outcome":"ok","fw_crc":3735928559}"#
+        );
+        assert_eq!(parse(&line), vec![rec]);
+    }
+
+    #[test]
+    fn test_record_outcome() {
+        let ok = Record::new(None, "COM3", "wipe", serde_json::json!({}), None, &Ok(()));
+        assert_eq!((ok.outcome.as_str(), ok.error), ("ok", None));
+
+        let failed: Result<()> = Err(anyhow::anyhow!("Bank 1 has no valid firmware"));
+        let rec = Record::new(
+            None,
+            "COM3",
+            "set-bank",
+            serde_json::json!({}),
+            None,
+            &failed,
+        );
+        assert_eq!(rec.outcome, "failed");
+        assert_eq!(rec.error.as_deref(), Some("Bank 1 has no valid firmware"));
+        assert!(rec.timestamp.ends_with('Z'));
+    }
+
+    #[test]
+    fn test_parse_skips_bad_lines() {
+        let good = serde_json::to_string(&record(None, "reboot")).unwrap();
+        let text = format!("{good}\n{{\"timestamp\":\"2026-\n\nnot json\n{good}\n");
+        assert_eq!(parse(&text).len(), 2);
+    }
+
+    #[test]
+    fn test_filter_by_device() {
+        let records = [
+            record(Some("AAAA"), "upload"),
+            record(None, "wipe"),
+            record(Some("BBBB"), "reboot"),
+            record(Some("aaaa"), "set-bank"),
+        ];
+        let commands = |device| {
+            filter(&records, device)
+                .map(|r| r.command.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(commands(Some("AAAA")), ["upload", "set-bank"]);
+        assert_eq!(commands(Some("CCCC")), Vec::<&str>::new());
+        assert_eq!(commands(None).len(), 4);
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+}
@@ -0,0 +1,347 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Byte streams to a device: a local serial port, or a TCP bridge.
+//!
+//! A `--port` of the form `tcp://host:port` connects to a gateway that
+//! forwards the device's CDC port over TCP (ser2net, a Raspberry Pi next to
+//! the device). Anything else is a serial port name. Both carry the same
+//! COBS frames; [`crate::transport::Transport`] does the framing and
+//! retries on top of a [`Link`].
+//!
+//! A bridge cannot enumerate USB devices, so `--serial` and the USB ID
+//! watching of `update` and `upload --reboot` do not apply; those commands
+//! instead reconnect and ask what answers (see [`crate::boot_watch`]).
+
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serialport::SerialPort;
+
+use crate::output::Failure;
+
+/// Prefix of a `--port` naming a TCP bridge.
+pub const TCP_PREFIX: &str = "tcp://";
+
+/// A byte stream to the device. Reads give up after the timeout with an
+/// error of kind `TimedOut`.
+pub trait Link: Read + Write + Send {
+    /// The `--port` this link was opened with.
+    fn name(&self) -> String;
+    fn timeout(&self) -> Duration;
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+}
+
+/// Timeouts for opening and reading a link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LinkOptions {
+    /// For a TCP connection to be accepted; serial ports open at once.
+    pub connect_timeout: Duration,
+    /// For each read, and for the answer to most commands.
+    pub read_timeout: Duration,
+    /// For the answer to a command that erases flash first (`StartUpdate`,
+    /// `WipeAll`, `CopyBank`).
+    pub erase_timeout: Duration,
+    /// For the answer to a command that hashes or scans whole banks first
+    /// (`FinishUpdate`, `GetBootData`, `BlankCheck`).
+    pub check_timeout: Duration,
+    /// To keep trying to open a port that is not there yet (`--wait`).
+    pub wait: Duration,
+}
+
+impl LinkOptions {
+    pub const DEFAULT: Self = Self {
+        connect_timeout: Duration::from_secs(5),
+        read_timeout: Duration::from_millis(crate::transport::DEFAULT_TIMEOUT_MS),
+        erase_timeout: Duration::from_secs(60),
+        check_timeout: Duration::from_secs(30),
+        wait: Duration::ZERO,
+    };
+
+    /// The same wait for every answer, as `--timeout` asks.
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        Self {
+            read_timeout: timeout,
+            erase_timeout: timeout,
+            check_timeout: timeout,
+            ..self
+        }
+    }
+}
+
+/// The `host:port` of a `tcp://host:port` port name.
+pub fn tcp_address(port: &str) -> Option<&str> {
+    port.strip_prefix(TCP_PREFIX)
+}
+
+/// Open `port`, a serial port name or `tcp://host:port`.
+pub fn open(port: &str, opts: &LinkOptions) -> Result<Box<dyn Link>> {
+    match tcp_address(port) {
+        Some(addr) => Ok(Box::new(TcpLink::connect(addr, opts)?)),
+        None => Ok(Box::new(SerialLink::open(port, opts)?)),
+    }
+}
+
+/// OS errors for a serial port whose device has gone: `EIO`, `ENXIO` and
+/// `ENODEV`.
+#[cfg(unix)]
+const GONE_OS_ERRORS: &[i32] = &[5, 6, 19];
+
+/// OS errors for a serial port whose device has gone. A handle left open
+/// across a reset reports `ERROR_ACCESS_DENIED`; the others are
+/// `ERROR_BAD_COMMAND`, `ERROR_GEN_FAILURE`, `ERROR_OPERATION_ABORTED` and
+/// `ERROR_DEVICE_NOT_CONNECTED`.
+#[cfg(windows)]
+const GONE_OS_ERRORS: &[i32] = &[5, 22, 31, 995, 1167];
+
+#[cfg(not(any(unix, windows)))]
+const GONE_OS_ERRORS: &[i32] = &[];
+
+/// Whether `err` means the other end has gone: the bridge closed the
+/// connection, or the device went away under the serial port (a reset, a
+/// re-enumeration), however the platform reports it.
+pub fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    ) || err
+        .raw_os_error()
+        .is_some_and(|code| GONE_OS_ERRORS.contains(&code))
+}
+
+/// A disconnect from `port` as a [`Failure`].
+pub fn disconnected(port: &str, err: &io::Error) -> Failure {
+    Failure::new(
+        "disconnected",
+        format!("Lost the connection to {}: {}", port, err),
+    )
+    .with("port", port)
+}
+
+/// A link that has been let go of, keeping its name for messages.
+pub struct Closed(pub String);
+
+impl Read for Closed {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+}
+
+impl Write for Closed {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Link for Closed {
+    fn name(&self) -> String {
+        self.0.clone()
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::ZERO
+    }
+
+    fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A local serial port.
+struct SerialLink {
+    port: Box<dyn SerialPort>,
+    name: String,
+}
+
+impl SerialLink {
+    fn open(name: &str, opts: &LinkOptions) -> Result<Self> {
+        let mut port = serialport::new(name, 115200)
+            .timeout(opts.read_timeout)
+            .open()
+            .with_context(|| format!("Failed to open serial port {}", name))?;
+
+        // The bootloader uses DTR to detect that a host is attached
+        port.write_data_terminal_ready(true)
+            .with_context(|| format!("Failed to assert DTR on {}", name))?;
+
+        Ok(Self {
+            port,
+            name: name.to_string(),
+        })
+    }
+}
+
+impl Read for SerialLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Link for SerialLink {
+    fn name(&self) -> String {
+        self.port.name().unwrap_or_else(|| self.name.clone())
+    }
+
+    fn timeout(&self) -> Duration {
+        self.port.timeout()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.port.set_timeout(timeout).map_err(io::Error::from)
+    }
+}
+
+/// A TCP connection to a serial bridge.
+struct TcpLink {
+    stream: TcpStream,
+    addr: String,
+    timeout: Duration,
+}
+
+impl TcpLink {
+    fn connect(addr: &str, opts: &LinkOptions) -> Result<Self> {
+        let name = format!("{}{}", TCP_PREFIX, addr);
+        let targets: Vec<_> = addr
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve {}", name))?
+            .collect();
+
+        let mut last_err = None;
+        for target in &targets {
+            match TcpStream::connect_timeout(target, opts.connect_timeout) {
+                Ok(stream) => {
+                    // Frames are small and every command waits for its answer
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(opts.read_timeout))?;
+                    stream.set_write_timeout(Some(opts.read_timeout))?;
+                    return Ok(Self {
+                        stream,
+                        addr: addr.to_string(),
+                        timeout: opts.read_timeout,
+                    });
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        let err = last_err.unwrap_or_else(|| io::Error::other("no address"));
+        Err(anyhow::Error::new(err).context(format!("Failed to connect to {}", name)))
+    }
+}
+
+impl Read for TcpLink {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            // End of stream: the bridge closed the connection
+            Ok(0) if !buf.is_empty() => Err(io::ErrorKind::UnexpectedEof.into()),
+            // Unix reports a read timeout as WouldBlock, Windows as TimedOut
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                Err(io::ErrorKind::TimedOut.into())
+            }
+            other => other,
+        }
+    }
+}
+
+impl Write for TcpLink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Link for TcpLink {
+    fn name(&self) -> String {
+        format!("{}{}", TCP_PREFIX, self.addr)
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_tcp_address() {
+        assert_eq!(tcp_address("tcp://gw.local:4001"), Some("gw.local:4001"));
+        assert_eq!(tcp_address("/dev/ttyACM0"), None);
+        assert_eq!(tcp_address("COM3"), None);
+    }
+
+    #[test]
+    fn test_tcp_link_timeout_and_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let opts = LinkOptions {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_millis(50),
+            ..LinkOptions::DEFAULT
+        };
+        let mut link = open(&port, &opts).unwrap();
+        assert_eq!(link.name(), port);
+
+        let (peer, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 4];
+        let err = link.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        drop(peer);
+        let err = link.read(&mut buf).unwrap_err();
+        assert!(is_disconnect(&err), "{:?}", err);
+    }
+
+    #[test]
+    fn test_device_gone_os_errors() {
+        for &code in GONE_OS_ERRORS {
+            assert!(is_disconnect(&io::Error::from_raw_os_error(code)), "{code}");
+        }
+        assert!(!is_disconnect(&io::ErrorKind::TimedOut.into()));
+        assert!(!is_disconnect(&io::ErrorKind::NotFound.into()));
+    }
+
+    #[test]
+    fn test_tcp_connect_refused() {
+        // Bind then drop to get a port nothing listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = open(&format!("tcp://{}", addr), &LinkOptions::DEFAULT)
+            .err()
+            .unwrap();
+        assert!(format!("{:#}", err).starts_with("Failed to connect to tcp://"));
+    }
+}
@@ -0,0 +1,305 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `status --watch` and `info --watch`: the device's state, sampled every
+//! interval until stopped.
+//!
+//! On a terminal each sample redraws a compact block in place, with the
+//! values that changed since the previous sample highlighted. Otherwise
+//! (a pipe, a log, several devices at once) every sample is one line with
+//! the changed values starred, and with `--json` a `sample` event.
+//!
+//! When the device stops answering (a reboot, a reset while testing a
+//! rollback) the sample says it is gone and the port is opened again every
+//! interval, by USB serial number when it has one, until the device is back.
+//! `q`, Ctrl-C or Ctrl-] stops the watch on a terminal, leaving the last
+//! sample on screen and in the `result`; `--count` stops it after that many
+//! samples.
+
+use std::io::{self, IsTerminal};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use console::{style, Key, Term};
+use serde_json::{json, Map, Value};
+
+use crate::commands::{self, Unsupported};
+use crate::output::{self, outln};
+use crate::transport::Transport;
+
+/// Ctrl-], as `monitor` quits.
+const CTRL_RIGHT_BRACKET: char = '\x1d';
+
+/// The fields of one sample, in display order.
+type Fields = Vec<(&'static str, Value)>;
+
+/// What to watch, and for how long.
+pub struct LiveOptions {
+    /// Add the bank checks and the last error, as `info` does.
+    pub info: bool,
+    pub interval: Duration,
+    /// Stop after this many samples.
+    pub count: Option<u64>,
+}
+
+/// Sample the device on `transport` every interval until stopped.
+pub fn watch(transport: &mut Transport, opts: &LiveOptions) -> Result<()> {
+    let redraw = !output::is_json() && !output::on_several_devices() && io::stdout().is_terminal();
+    // Without --count only a key stops the watch; with it, leave the
+    // terminal alone so it is not left waiting for a key
+    let keys = (opts.count.is_none() && !output::on_several_devices() && io::stdin().is_terminal())
+        .then(spawn_keys);
+    outln!(
+        "Watching {} every {} s{}",
+        transport.port_name(),
+        opts.interval.as_secs(),
+        if keys.is_some() {
+            " (q or Ctrl-C to stop)"
+        } else {
+            ""
+        }
+    );
+
+    let term = Term::stdout();
+    let started = Instant::now();
+    let mut unsupported = Unsupported::default();
+    let mut last: Option<Fields> = None;
+    let mut gone: Option<String> = None;
+    let mut drawn = 0;
+    let mut n = 0;
+    loop {
+        n += 1;
+        let secs = started.elapsed().as_secs_f64();
+        if gone.is_some() && transport.reconnect(Duration::ZERO).is_ok() {
+            gone = None;
+            // Clear the "Reconnected on" line with the block
+            drawn += usize::from(redraw);
+        }
+        let mut changed = Vec::new();
+        if gone.is_none() {
+            match commands::status_sample(transport, opts.info, &mut unsupported) {
+                Ok(fields) => {
+                    changed = changed_fields(last.as_deref(), &fields);
+                    last = Some(fields);
+                }
+                Err(err) => gone = Some(format!("{:#}", err)),
+            }
+        }
+
+        let data = match (&gone, &last) {
+            (None, Some(fields)) => Some(to_map(fields)),
+            _ => None,
+        };
+        output::sample(n, secs, data.as_ref(), &changed, gone.as_deref());
+        let header = header(n, secs, gone.as_deref());
+        if redraw {
+            let _ = term.clear_last_lines(drawn);
+            let lines = block(&header, last.as_deref().unwrap_or_default(), &changed, true);
+            for line in &lines {
+                outln!("{}", line);
+            }
+            drawn = lines.len();
+        } else if gone.is_some() {
+            outln!("{}", header);
+        } else {
+            outln!(
+                "{} {}",
+                header,
+                line(last.as_deref().unwrap_or_default(), &changed)
+            );
+        }
+
+        if opts.count.is_some_and(|count| n >= count) || stopped(keys.as_ref(), opts.interval) {
+            break;
+        }
+    }
+
+    outln!("Stopped after {} samples", n);
+    output::report(json!({
+        "samples": n,
+        "last": last.as_deref().map(to_map),
+        "gone": gone,
+    }));
+    Ok(())
+}
+
+/// Names of the fields of `fields` whose value is not the one in
+/// `previous`; none for the first sample.
+fn changed_fields(
+    previous: Option<&[(&'static str, Value)]>,
+    fields: &[(&'static str, Value)],
+) -> Vec<&'static str> {
+    let Some(previous) = previous else {
+        return Vec::new();
+    };
+    fields
+        .iter()
+        .filter(|(name, value)| {
+            previous
+                .iter()
+                .find(|(before, _)| before == name)
+                .is_none_or(|(_, before)| before != value)
+        })
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+fn to_map(fields: &[(&'static str, Value)]) -> Map<String, Value> {
+    fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect()
+}
+
+/// A value as text: strings without quotes, `null` as `none`.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "none".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn header(n: u64, secs: f64, gone: Option<&str>) -> String {
+    match gone {
+        None => format!("[#{} +{:.1}s]", n, secs),
+        Some(reason) => format!(
+            "[#{} +{:.1}s] gone ({}); waiting for the device",
+            n, secs, reason
+        ),
+    }
+}
+
+/// The fields on one line, `name=value`, with a `*` after changed values.
+fn line(fields: &[(&'static str, Value)], changed: &[&str]) -> String {
+    fields
+        .iter()
+        .map(|(name, value)| {
+            let mark = if changed.contains(name) { "*" } else { "" };
+            format!("{}={}{}", name, text(value), mark)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The header, then one field per line; changed values in bold yellow
+/// when `styled`.
+fn block(
+    header: &str,
+    fields: &[(&'static str, Value)],
+    changed: &[&str],
+    styled: bool,
+) -> Vec<String> {
+    let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    let mut lines = vec![header.to_string()];
+    for (name, value) in fields {
+        let value = text(value);
+        let value = match changed.contains(name) {
+            true if styled => style(value).yellow().bold().to_string(),
+            true => format!("{} *", value),
+            false => value,
+        };
+        lines.push(format!("  {:<width$}  {}", name, value));
+    }
+    lines
+}
+
+/// Wait `interval` for a quit key; without a terminal to read, just wait.
+fn stopped(keys: Option<&Receiver<()>>, interval: Duration) -> bool {
+    match keys {
+        Some(keys) => !matches!(keys.recv_timeout(interval), Err(RecvTimeoutError::Timeout)),
+        None => {
+            thread::sleep(interval);
+            false
+        }
+    }
+}
+
+/// Read keys on a thread of their own until a quit key, which is sent.
+/// The thread ends with it, so the terminal is not left in raw mode.
+fn spawn_keys() -> Receiver<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let term = Term::stdout();
+        while let Ok(key) = term.read_key_raw() {
+            if matches!(
+                key,
+                Key::CtrlC | Key::Char('q') | Key::Char(CTRL_RIGHT_BRACKET)
+            ) {
+                let _ = tx.send(());
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkOptions;
+    use crate::test_device::TestDevice;
+
+    fn fields(state: &str, attempts: u8) -> Fields {
+        vec![
+            ("active_bank", json!(0)),
+            ("state", json!(state)),
+            ("boot_attempts", json!(attempts)),
+        ]
+    }
+
+    #[test]
+    fn test_changed_fields() {
+        let before = fields("Idle", 0);
+        assert!(changed_fields(None, &before).is_empty());
+        assert!(changed_fields(Some(&before), &before).is_empty());
+        let after = fields("UpdateMode", 1);
+        assert_eq!(
+            changed_fields(Some(&before), &after),
+            ["state", "boot_attempts"]
+        );
+        // A field the previous sample did not have counts as changed
+        let mut more = after.clone();
+        more.push(("last_error", Value::Null));
+        assert_eq!(changed_fields(Some(&after), &more), ["last_error"]);
+    }
+
+    #[test]
+    fn test_rendering() {
+        let sample = fields("UpdateMode", 2);
+        let changed = ["boot_attempts"];
+        assert_eq!(
+            line(&sample, &changed),
+            "active_bank=0 state=UpdateMode boot_attempts=2*"
+        );
+        assert_eq!(
+            block(&header(3, 2.04, None), &sample, &changed, false),
+            [
+                "[#3 +2.0s]",
+                "  active_bank    0",
+                "  state          UpdateMode",
+                "  boot_attempts  2 *",
+            ]
+        );
+        assert_eq!(
+            header(4, 3.0, Some("Timeout waiting for response")),
+            "[#4 +3.0s] gone (Timeout waiting for response); waiting for the device"
+        );
+    }
+
+    #[test]
+    fn test_watch_stops_after_count() {
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let opts = LiveOptions {
+            info: true,
+            interval: Duration::from_millis(10),
+            count: Some(2),
+        };
+        watch(&mut transport, &opts).unwrap();
+        drop(transport);
+        device.finish();
+    }
+}
@@ -4,18 +4,95 @@
 //! Firmware upload tool for crispy-bootloader via USB CDC.
 //!
 //! Usage:
+//!   crispy-upload list-ports
+//!   crispy-upload flash-bootloader bootloader.elf --probe 2e8a:000c   (--features probe-rs)
+//!   crispy-upload status                     (port found by USB IDs)
 //!   crispy-upload --port /dev/ttyACM0 status
-//!   crispy-upload --port /dev/ttyACM0 upload firmware.bin --bank 0 --version 1
+//!   crispy-upload --port /dev/ttyACM0 info
+//!   crispy-upload --port tcp://gateway:4001 status   (through a TCP bridge)
+//!   crispy-upload --trace status             (log every frame to stderr)
+//!   crispy-upload --json upload firmware.bin (JSON events, see `output`)
+//!   crispy-upload --port /dev/ttyACM0 upload firmware.bin --version 1.2.3
+//!   crispy-upload --port /dev/ttyACM0 upload firmware.bin --verify --chunk-size 512
+//!   crispy-upload --port /dev/ttyACM0 upload firmware.bin --reboot --expect-confirm
+//!   crispy-upload update firmware.bin        (from firmware or bootloader)
+//!   crispy-upload --all update firmware.bin  (every device at once)
+//!   crispy-upload --port /dev/ttyACM0 download bank-b.bin --bank 1
+//!   crispy-upload --port /dev/ttyACM0 diff firmware.bin --bank 1
+//!   crispy-upload --port /dev/ttyACM0 verify firmware.bin --bank 1 --quick
+//!   crispy-upload --port /dev/ttyACM0 verify-all
+//!   crispy-upload --port /dev/ttyACM0 clone --from 0 --to 1
+//!   crispy-upload --port /dev/ttyACM0 flash-info
+//!   crispy-upload --port /dev/ttyACM0 blank-check --bank 1
+//!   crispy-upload --port /dev/ttyACM0 set-boot-attempts 10
+//!   crispy-upload --port /dev/ttyACM0 provision --board 2 --serial ABC123
 //!   crispy-upload --port /dev/ttyACM0 reboot
+//!   crispy-upload --port /dev/ttyACM0 reboot --stay
+//!   crispy-upload monitor --reattach
+//!   crispy-upload --port /dev/ttyACM1 probe
+//!   crispy-upload factory --fw-a app.bin --fw-b app.bin --log production.jsonl
+//!   crispy-upload history --device E6614103E7452D2F
+//!   crispy-upload config show                (defaults from crispy.toml)
+//!   crispy-upload inspect firmware.bin
+//!   crispy-upload pack firmware.bin --version 1.2.3 -o firmware.crispy
+//!   crispy-upload boot-data decode bootdata.bin
+//!   crispy-upload run provision.txt --var FW=firmware.bin --dry-run
+//!   crispy-upload apply release/manifest.toml --dry-run
 
+mod backup;
+mod bench;
+mod boot_watch;
 mod cli;
 mod commands;
+mod config;
+mod elf;
+mod factory;
+mod health;
+mod history;
+mod link;
+mod live;
+mod manifest;
+mod monitor;
+mod output;
+mod overrides;
+mod parallel;
+mod probe;
+mod script;
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+mod source;
+#[cfg(feature = "probe-rs")]
+mod swd;
+#[cfg(test)]
+mod test_device;
+mod trace;
 mod transport;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 
 fn main() -> Result<()> {
-    let args = cli::Cli::parse();
-    cli::run(args)
+    let matches = cli::Cli::command().get_matches();
+    let mut args = cli::Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let command = matches.subcommand_name().unwrap_or_default().to_string();
+
+    output::set_json(args.json);
+    let result = config::apply(&mut args, &matches).and_then(|()| cli::run(args));
+    output::finish(&command, &result);
+
+    // `diff` and `verify` tell "differs" from "could not compare", as `cmp`
+    // does, `verify-all` gives its worst finding; a failed pre-flight check
+    // says which one
+    let code = match (&result, command.as_str()) {
+        (Err(err), "diff" | "verify") => Some(commands::compare_exit_code(err)),
+        (Err(err), "verify-all") => Some(health::exit_code(err)),
+        (Err(err), _) => commands::preflight_exit_code(err),
+        _ => None,
+    };
+    if let (Err(err), Some(code)) = (&result, code) {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(code);
+    }
+    result
 }
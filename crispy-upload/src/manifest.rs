@@ -0,0 +1,905 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `apply`: what goes where, from a manifest reviewed like code instead of
+//! flags typed at a prompt.
+//!
+//! A manifest is written in the same TOML subset as the configuration files
+//! (see `config`), with `true` and `false` as well:
+//!
+//! ```toml
+//! # Release 1.4.0 for the gateway board
+//! image = "gateway-1.4.0.crispy"
+//! sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! bank = "inactive"
+//! board = 2
+//! reboot = true
+//! confirm = true
+//! min_bootloader = "0.3.0"
+//! ```
+//!
+//! | Key | Value | Default |
+//! |-----|-------|---------|
+//! | `image` | Firmware file, relative to the manifest, or an http(s) URL | required |
+//! | `sha256` | SHA-256 of `image`, 64 hex digits | required |
+//! | `image_b` | Firmware for bank B, with `bank = "both"` | `image` |
+//! | `sha256_b` | SHA-256 of `image_b` | required with `image_b` |
+//! | `bank` | `"inactive"`, `"both"`, `0` or `1` | `"inactive"` |
+//! | `version` | Version to record, `"MAJOR.MINOR.PATCH"` | from the file |
+//! | `board` | Board ID each image's header must carry | not checked |
+//! | `verify` | Read each bank back after writing it | `false` |
+//! | `activate` | Leave the uploaded bank active (bank A with `"both"`) | `true` |
+//! | `reboot` | Reboot and wait for the firmware | `false` |
+//! | `confirm` | Also wait for the firmware to confirm its boot; needs `reboot` | `false` |
+//! | `min_bootloader` | Refuse a bootloader older than this, or one that does not say | any |
+//!
+//! Everything that can be checked without the device is checked before it
+//! is touched: the keys and their values, then each file, its SHA-256, its
+//! board ID and the pre-flight checks of `upload`. An error names the key
+//! at fault, `manifest.toml:3: sha256: ...`, and a manifest error carries
+//! `context.key` and `context.line`. `--dry-run` stops there and prints the
+//! steps; otherwise they run in order on one connection, and the `result`
+//! data lists each with what it reported.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+
+use crispy_common::protocol::{Command, Response};
+use crispy_common::{FwVersion, FW_BANK_SIZE};
+
+use crate::boot_watch::WatchOptions;
+use crate::commands::{self, InputOptions, UploadOptions, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_GAP};
+use crate::config::{self, Value};
+use crate::output::{self, outln, Failure};
+use crate::overrides::{Overrides, Protection};
+use crate::source::{self, Sha256Digest};
+use crate::transport::Transport;
+
+/// What a key holds.
+#[derive(Clone, Copy)]
+enum Kind {
+    File,
+    Sha256,
+    Bank,
+    Version,
+    Board,
+    Flag,
+}
+
+/// Every key, in the order of the table above.
+const KEYS: &[(&str, Kind)] = &[
+    ("image", Kind::File),
+    ("sha256", Kind::Sha256),
+    ("image_b", Kind::File),
+    ("sha256_b", Kind::Sha256),
+    ("bank", Kind::Bank),
+    ("version", Kind::Version),
+    ("board", Kind::Board),
+    ("verify", Kind::Flag),
+    ("activate", Kind::Flag),
+    ("reboot", Kind::Flag),
+    ("confirm", Kind::Flag),
+    ("min_bootloader", Kind::Version),
+];
+
+/// Which banks an upload goes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BankPolicy {
+    /// The bank that is not active, keeping the running firmware.
+    Inactive,
+    /// Both, over one connection, as `upload-both` does.
+    Both,
+    Bank(u8),
+}
+
+/// A firmware file and the SHA-256 it must have.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub file: PathBuf,
+    pub sha256: Sha256Digest,
+}
+
+/// A manifest, parsed and its keys checked.
+#[derive(Clone, Debug)]
+pub struct Manifest {
+    pub path: PathBuf,
+    pub image: Image,
+    pub image_b: Option<Image>,
+    pub bank: BankPolicy,
+    pub version: Option<FwVersion>,
+    pub board: Option<u16>,
+    pub verify: bool,
+    pub activate: bool,
+    pub reboot: bool,
+    pub confirm: bool,
+    pub min_bootloader: Option<FwVersion>,
+    /// The line of each key given.
+    lines: Vec<(&'static str, usize)>,
+}
+
+/// One thing `apply` does, in the order [`Manifest::plan`] gives them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Refuse a bootloader older than this.
+    CheckBootloader(FwVersion),
+    /// `upload`, to the inactive bank without a bank.
+    Upload { file: PathBuf, bank: Option<u8> },
+    /// `upload-both`, then make `activate` active, or with `None` the bank
+    /// that was active before.
+    UploadBoth {
+        file_a: PathBuf,
+        file_b: PathBuf,
+        activate: Option<u8>,
+    },
+    /// Make the bank that was active before the upload active again.
+    KeepActive,
+    /// Reboot and wait for the firmware, and for its confirmed boot.
+    Reboot { confirm: bool },
+}
+
+impl Step {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Step::CheckBootloader(_) => "check-bootloader",
+            Step::Upload { .. } => "upload",
+            Step::UploadBoth { .. } => "upload-both",
+            Step::KeepActive => "set-bank",
+            Step::Reboot { .. } => "reboot",
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::CheckBootloader(min) => write!(f, "check the bootloader is {} or later", min),
+            Step::Upload { file, bank: None } => {
+                write!(f, "upload {} to the inactive bank", file.display())
+            }
+            Step::Upload {
+                file,
+                bank: Some(bank),
+            } => write!(f, "upload {} to bank {}", file.display(), bank),
+            Step::UploadBoth {
+                file_a,
+                file_b,
+                activate,
+            } => {
+                write!(
+                    f,
+                    "upload {} to bank 0 and {} to bank 1",
+                    file_a.display(),
+                    file_b.display()
+                )?;
+                match activate {
+                    Some(bank) => write!(f, ", then make bank {} active", bank),
+                    None => f.write_str(", keeping the active bank"),
+                }
+            }
+            Step::KeepActive => f.write_str("make the bank active before active again"),
+            Step::Reboot { confirm: false } => f.write_str("reboot and wait for the firmware"),
+            Step::Reboot { confirm: true } => {
+                f.write_str("reboot and wait for the firmware to confirm its boot")
+            }
+        }
+    }
+}
+
+/// A manifest error at `line` of `path`, about `key` if there is one.
+fn error(path: &Path, line: Option<usize>, key: Option<&str>, message: String) -> Failure {
+    let at = match line {
+        Some(line) => format!("{}:{}", path.display(), line),
+        None => path.display().to_string(),
+    };
+    let message = match key {
+        Some(key) => format!("{}: {}: {}", at, key, message),
+        None => format!("{}: {}", at, message),
+    };
+    Failure::new("manifest", message)
+        .with("path", path.display().to_string())
+        .with("line", line)
+        .with("key", key)
+}
+
+impl Manifest {
+    /// Read and parse the manifest at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text, path)
+    }
+
+    /// Parse the text of the manifest at `path`. Files are relative to its
+    /// directory.
+    pub fn parse(text: &str, path: &Path) -> Result<Self> {
+        let mut values: Vec<(&'static str, Value, usize)> = Vec::new();
+        for (index, raw) in text.lines().enumerate() {
+            let line = index + 1;
+            let content = config::strip_comment(raw).trim();
+            if content.is_empty() {
+                continue;
+            }
+            let fail = |key: Option<&str>, message: String| error(path, Some(line), key, message);
+            if content.starts_with('[') {
+                let message = "tables are not supported; keys go at the top level".to_string();
+                bail!(fail(None, message));
+            }
+            let Some((name, value)) = content.split_once('=') else {
+                bail!(fail(
+                    None,
+                    format!("expected `key = value`, found `{}`", content)
+                ));
+            };
+            let name = name.trim();
+            let Some(&(key, _)) = KEYS.iter().find(|(key, _)| *key == name) else {
+                let known: Vec<_> = KEYS.iter().map(|(key, _)| *key).collect();
+                bail!(fail(
+                    Some(name),
+                    format!("unknown key; known keys: {}", known.join(", "))
+                ));
+            };
+            if let Some((.., earlier)) = values.iter().find(|(k, ..)| *k == key) {
+                bail!(fail(Some(key), format!("already set on line {}", earlier)));
+            }
+            let value = config::parse_value(value.trim()).map_err(|m| fail(Some(key), m))?;
+            values.push((key, value, line));
+        }
+        Self::from_values(values, path)
+    }
+
+    /// Check each value against its key, then the keys against each other.
+    fn from_values(values: Vec<(&'static str, Value, usize)>, path: &Path) -> Result<Self> {
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let lines: Vec<_> = values.iter().map(|(key, _, line)| (*key, *line)).collect();
+        let mut manifest = Manifest {
+            path: path.to_path_buf(),
+            image: Image {
+                file: PathBuf::new(),
+                sha256: [0; 32],
+            },
+            image_b: None,
+            bank: BankPolicy::Inactive,
+            version: None,
+            board: None,
+            verify: false,
+            activate: true,
+            reboot: false,
+            confirm: false,
+            min_bootloader: None,
+            lines: lines.clone(),
+        };
+
+        let (mut image, mut sha256, mut image_b, mut sha256_b) = (None, None, None, None);
+        for (key, value, line) in values {
+            let fail = |message: &str| error(path, Some(line), Some(key), message.to_string());
+            let kind = KEYS.iter().find(|(k, _)| *k == key).expect("known key").1;
+            match (kind, value) {
+                (Kind::File, Value::Text(file)) if !file.is_empty() => {
+                    let file = PathBuf::from(file);
+                    let file = if source::is_url(&file) || file.is_absolute() {
+                        file
+                    } else if source::is_stdin(&file) {
+                        bail!(fail("a manifest names files, not stdin"));
+                    } else {
+                        dir.join(file)
+                    };
+                    match key {
+                        "image" => image = Some(file),
+                        _ => image_b = Some(file),
+                    }
+                }
+                (Kind::File, _) => bail!(fail("must be a file name or URL, in double quotes")),
+                (Kind::Sha256, Value::Text(text)) => {
+                    let digest = source::parse_sha256(&text).map_err(|m| fail(&m))?;
+                    match key {
+                        "sha256" => sha256 = Some(digest),
+                        _ => sha256_b = Some(digest),
+                    }
+                }
+                (Kind::Sha256, _) => bail!(fail("must be 64 hex digits, in double quotes")),
+                (Kind::Bank, Value::Text(text)) if text == "inactive" => {
+                    manifest.bank = BankPolicy::Inactive
+                }
+                (Kind::Bank, Value::Text(text)) if text == "both" => {
+                    manifest.bank = BankPolicy::Both
+                }
+                (Kind::Bank, Value::Number(bank @ (0 | 1))) => {
+                    manifest.bank = BankPolicy::Bank(bank as u8)
+                }
+                (Kind::Bank, _) => bail!(fail("must be \"inactive\", \"both\", 0 or 1")),
+                (Kind::Version, Value::Text(text)) => {
+                    let version = text.parse().map_err(|_| {
+                        fail("must be MAJOR.MINOR.PATCH, in double quotes, such as \"1.2.3\"")
+                    })?;
+                    match key {
+                        "version" => manifest.version = Some(version),
+                        _ => manifest.min_bootloader = Some(version),
+                    }
+                }
+                (Kind::Version, _) => bail!(fail(
+                    "must be MAJOR.MINOR.PATCH, in double quotes, such as \"1.2.3\""
+                )),
+                (Kind::Board, Value::Number(board)) if board <= u16::MAX as u64 => {
+                    manifest.board = Some(board as u16)
+                }
+                (Kind::Board, _) => bail!(fail("must be an integer up to 65535")),
+                (Kind::Flag, Value::Bool(set)) => match key {
+                    "verify" => manifest.verify = set,
+                    "activate" => manifest.activate = set,
+                    "reboot" => manifest.reboot = set,
+                    _ => manifest.confirm = set,
+                },
+                (Kind::Flag, _) => bail!(fail("must be true or false")),
+            }
+        }
+
+        let fail = |key: &str, message: &str| {
+            let line = lines.iter().find(|(k, _)| *k == key).map(|(_, l)| *l);
+            error(path, line, Some(key), message.to_string())
+        };
+        manifest.image = match (image, sha256) {
+            (Some(file), Some(sha256)) => Image { file, sha256 },
+            (None, _) => bail!(fail("image", "missing; the manifest must name an image")),
+            (Some(_), None) => bail!(fail(
+                "sha256",
+                "missing; the manifest must give the SHA-256 of `image`"
+            )),
+        };
+        manifest.image_b = match (image_b, sha256_b) {
+            (Some(_), _) if manifest.bank != BankPolicy::Both => {
+                bail!(fail("image_b", "only goes with bank = \"both\""))
+            }
+            (Some(file), Some(sha256)) => Some(Image { file, sha256 }),
+            (Some(_), None) => bail!(fail(
+                "sha256_b",
+                "missing; the manifest must give the SHA-256 of `image_b`"
+            )),
+            (None, Some(_)) => bail!(fail("sha256_b", "given without `image_b`")),
+            (None, None) => None,
+        };
+        if manifest.confirm && !manifest.reboot {
+            bail!(fail(
+                "confirm",
+                "needs reboot = true; a boot is only confirmed after one"
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// The line `key` is on, if it was given.
+    fn line(&self, key: &str) -> Option<usize> {
+        self.lines.iter().find(|(k, _)| *k == key).map(|(_, l)| *l)
+    }
+
+    /// Where `key` is, to put before an error about it.
+    fn at(&self, key: &str) -> String {
+        match self.line(key) {
+            Some(line) => format!("{}:{}: {}", self.path.display(), line, key),
+            None => format!("{}: {}", self.path.display(), key),
+        }
+    }
+
+    /// Each image with the keys that name it and its hash.
+    fn images(&self) -> Vec<(&Image, &'static str, &'static str)> {
+        let mut images = vec![(&self.image, "image", "sha256")];
+        images.extend(self.image_b.as_ref().map(|b| (b, "image_b", "sha256_b")));
+        images
+    }
+
+    /// Check every file without the device: it exists, has its SHA-256 and
+    /// board ID, and passes the pre-flight checks unless they are
+    /// overridden.
+    pub fn check(&self, overrides: Overrides) -> Result<()> {
+        for (image, file_key, sha_key) in self.images() {
+            let file = &image.file;
+            if !source::is_url(file) && !file.exists() {
+                let message = format!("{} does not exist", file.display());
+                bail!(error(
+                    &self.path,
+                    self.line(file_key),
+                    Some(file_key),
+                    message
+                ));
+            }
+            source::read(file, Some(&image.sha256)).with_context(|| self.at(sha_key))?;
+            let input = self.input(Some(image.sha256));
+            if !overrides.allows(Protection::ImageChecks) {
+                commands::preflight(file, &input).with_context(|| self.at(file_key))?;
+            }
+            let Some(board) = self.board else {
+                continue;
+            };
+            let message = match commands::image_header(file, &input)? {
+                Some(header) if header.board_id == board || header.board_id == 0 => continue,
+                Some(header) => format!(
+                    "{} is built for board {}, not {}",
+                    file.display(),
+                    header.board_id,
+                    board
+                ),
+                None => format!(
+                    "{} has no image header to carry a board ID; `crispy-upload pack --board {}` adds one",
+                    file.display(),
+                    board
+                ),
+            };
+            bail!(error(
+                &self.path,
+                self.line("board"),
+                Some("board"),
+                message
+            ));
+        }
+        Ok(())
+    }
+
+    fn input(&self, sha256: Option<Sha256Digest>) -> InputOptions {
+        InputOptions {
+            any_family: false,
+            max_gap: DEFAULT_MAX_GAP,
+            sha256,
+            bank_size: FW_BANK_SIZE,
+        }
+    }
+
+    /// The steps that carry the manifest out, in order.
+    pub fn plan(&self) -> Vec<Step> {
+        let mut steps: Vec<_> = self
+            .min_bootloader
+            .map(Step::CheckBootloader)
+            .into_iter()
+            .collect();
+        match self.bank {
+            BankPolicy::Both => steps.push(Step::UploadBoth {
+                file_a: self.image.file.clone(),
+                file_b: self.image_b.as_ref().unwrap_or(&self.image).file.clone(),
+                activate: self.activate.then_some(0),
+            }),
+            policy => {
+                let bank = match policy {
+                    BankPolicy::Bank(bank) => Some(bank),
+                    _ => None,
+                };
+                let file = self.image.file.clone();
+                steps.push(Step::Upload { file, bank });
+                if !self.activate {
+                    steps.push(Step::KeepActive);
+                }
+            }
+        }
+        if self.reboot {
+            steps.push(Step::Reboot {
+                confirm: self.confirm,
+            });
+        }
+        steps
+    }
+}
+
+/// Carry out `manifest` on the device behind `transport`, checked with
+/// [`Manifest::check`] first.
+pub fn apply(
+    transport: Transport,
+    port: &str,
+    manifest: &Manifest,
+    overrides: Overrides,
+    watch: &WatchOptions,
+) -> Result<()> {
+    let steps = manifest.plan();
+    let total = steps.len();
+    let mut transport = Some(transport);
+    let mut before = None;
+    let mut records = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        outln!("[{}/{}] {}", index + 1, total, step);
+        let (result, data) = output::collect(|| {
+            run_step(
+                step,
+                &mut transport,
+                port,
+                manifest,
+                overrides,
+                watch,
+                &mut before,
+            )
+        });
+        records.push(json!({ "step": step.name(), "data": data }));
+        result.with_context(|| format!("Step {} of {} failed ({})", index + 1, total, step))?;
+        outln!();
+    }
+    outln!("Applied {}", manifest.path.display());
+    output::report(json!({
+        "manifest": manifest.path.display().to_string(),
+        "steps": records,
+    }));
+    Ok(())
+}
+
+fn run_step(
+    step: &Step,
+    transport: &mut Option<Transport>,
+    port: &str,
+    manifest: &Manifest,
+    overrides: Overrides,
+    watch: &WatchOptions,
+    before: &mut Option<u8>,
+) -> Result<()> {
+    let Some(device) = transport.as_mut() else {
+        unreachable!("the reboot is the last step");
+    };
+    let opts = |sha256| UploadOptions {
+        version: manifest.version,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        verify: manifest.verify,
+        input: manifest.input(sha256),
+        overrides,
+        always: false,
+    };
+    match step {
+        Step::CheckBootloader(min) => check_bootloader(device, *min),
+        Step::Upload { file, bank } => {
+            *before = Some(commands::active_bank(
+                &device.send_recv(&Command::GetStatus)?,
+            )?);
+            commands::upload(device, file, *bank, &opts(Some(manifest.image.sha256))).map(|_| ())
+        }
+        // The hashes were checked; one for both would fail the other file
+        Step::UploadBoth {
+            file_a,
+            file_b,
+            activate,
+        } => commands::upload_both(device, file_a, Some(file_b), *activate, &opts(None)),
+        Step::KeepActive => {
+            let before = before.expect("an upload comes first");
+            let active = commands::active_bank(&device.send_recv(&Command::GetStatus)?)?;
+            if active == before {
+                outln!("Bank {} is still active", before);
+                return Ok(());
+            }
+            commands::set_bank(device, before)
+        }
+        Step::Reboot { confirm } => {
+            let bank = commands::active_bank(&device.send_recv(&Command::GetStatus)?)?;
+            let opts = WatchOptions {
+                expect_confirm: watch.expect_confirm || *confirm,
+                ..*watch
+            };
+            let transport = transport.take().expect("the port is open until the reboot");
+            commands::reboot_and_watch(transport, port, bank, &opts)
+        }
+    }
+}
+
+/// Fail unless the bootloader says it is `min` or later.
+fn check_bootloader(transport: &mut Transport, min: FwVersion) -> Result<()> {
+    let found = match commands::optional_query(transport, &Command::GetDeviceInfo)? {
+        Some(Response::DeviceInfo {
+            bootloader_version, ..
+        }) => bootloader_version,
+        _ => bail!(Failure::new(
+            "bootloader_version",
+            format!(
+                "The bootloader does not report its version; the manifest needs {} or later",
+                min
+            )
+        )
+        .with("required", min.to_string())),
+    };
+    output::report(json!({ "bootloader_version": found.to_string() }));
+    if found < min {
+        bail!(Failure::new(
+            "bootloader_version",
+            format!(
+                "The bootloader is {}; the manifest needs {} or later",
+                found, min
+            )
+        )
+        .with("required", min.to_string())
+        .with("found", found.to_string()));
+    }
+    outln!("Bootloader: {} (needs {} or later)", found, min);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::LinkOptions;
+    use crate::test_device::TestDevice;
+    use crispy_common::flash::write_boot_data;
+    use crispy_common::flash_sim::FlashSim;
+    use crispy_common::protocol::BootData;
+    use crispy_common::{crc32, FlashLayout};
+    use sha2::{Digest, Sha256};
+
+    const SHA: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn parse(text: &str) -> Result<Manifest> {
+        Manifest::parse(text, Path::new("release/manifest.toml"))
+    }
+
+    /// The key and line of a manifest error, and its message.
+    fn error(text: &str) -> (Option<String>, Option<u64>, String) {
+        let err = parse(text).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "manifest");
+        let key = failure.context["key"].as_str().map(str::to_string);
+        (
+            key,
+            failure.context["line"].as_u64(),
+            failure.message.clone(),
+        )
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// A raw image the pre-flight checks accept.
+    fn raw_image() -> Vec<u8> {
+        let mut fw = vec![0u8; 3000];
+        fw[0..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+        fw[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+        for (i, byte) in fw.iter_mut().enumerate().skip(8) {
+            *byte = i as u8;
+        }
+        fw
+    }
+
+    #[test]
+    fn test_parse() {
+        let manifest = parse(&format!(
+            "# release\nimage = \"fw.bin\"\nsha256 = \"{}\"\nbank = 1\nversion = \"1.4.0\"\nboard = 2\nverify = true\nreboot = true # then\nconfirm = true\nmin_bootloader = \"0.3.0\"\n",
+            SHA
+        ))
+        .unwrap();
+        assert_eq!(manifest.image.file, Path::new("release/fw.bin"));
+        assert_eq!(manifest.image.sha256, source::parse_sha256(SHA).unwrap());
+        assert_eq!(manifest.bank, BankPolicy::Bank(1));
+        assert_eq!(manifest.version, Some(FwVersion::new(1, 4, 0)));
+        assert_eq!(manifest.board, Some(2));
+        assert!(manifest.verify && manifest.activate && manifest.reboot && manifest.confirm);
+        assert_eq!(manifest.min_bootloader, Some(FwVersion::new(0, 3, 0)));
+        assert_eq!(manifest.line("board"), Some(6));
+
+        let url = parse(&format!(
+            "image = \"https://ci.example.com/fw.uf2\"\nsha256 = \"{}\"\nbank = \"both\"\nimage_b = \"/srv/old.bin\"\nsha256_b = \"{}\"\nactivate = false\n",
+            SHA, SHA
+        ))
+        .unwrap();
+        assert_eq!(url.image.file, Path::new("https://ci.example.com/fw.uf2"));
+        assert_eq!(url.image_b.unwrap().file, Path::new("/srv/old.bin"));
+        assert_eq!(url.bank, BankPolicy::Both);
+        assert!(!url.activate && !url.reboot);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let image = format!("image = \"fw.bin\"\nsha256 = \"{}\"\n", SHA);
+        let cases: [(String, &str, Option<u64>, &str); 13] = [
+            (
+                format!("{}imgae = \"x\"", image),
+                "imgae",
+                Some(3),
+                "unknown key; known keys: image, sha256",
+            ),
+            (
+                format!("{}bank = 2", image),
+                "bank",
+                Some(3),
+                "must be \"inactive\", \"both\", 0 or 1",
+            ),
+            (
+                format!("{}bank = \"active\"", image),
+                "bank",
+                Some(3),
+                "must be \"inactive\"",
+            ),
+            (
+                format!("{}board = 70000", image),
+                "board",
+                Some(3),
+                "up to 65535",
+            ),
+            (
+                format!("{}reboot = 1", image),
+                "reboot",
+                Some(3),
+                "must be true or false",
+            ),
+            (
+                format!("{}version = \"1.2\"", image),
+                "version",
+                Some(3),
+                "MAJOR.MINOR.PATCH",
+            ),
+            (
+                format!("{}confirm = true", image),
+                "confirm",
+                Some(3),
+                "needs reboot = true",
+            ),
+            (
+                format!("{}image_b = \"b.bin\"", image),
+                "image_b",
+                Some(3),
+                "only goes with bank = \"both\"",
+            ),
+            (
+                format!("{}bank = \"both\"\nimage_b = \"b.bin\"", image),
+                "sha256_b",
+                None,
+                "missing",
+            ),
+            (
+                format!("{}sha256_b = \"{}\"", image, SHA),
+                "sha256_b",
+                Some(3),
+                "given without `image_b`",
+            ),
+            (
+                "sha256 = \"abc\"".to_string(),
+                "sha256",
+                Some(1),
+                "expected 64 hex digits",
+            ),
+            (
+                format!("sha256 = \"{}\"", SHA),
+                "image",
+                None,
+                "missing; the manifest must name an image",
+            ),
+            (
+                format!("{}image = \"-\"", image),
+                "image",
+                Some(3),
+                "already set on line 1",
+            ),
+        ];
+        for (text, key, line, message) in cases {
+            let (found_key, found_line, found) = error(&text);
+            assert_eq!(found_key.as_deref(), Some(key), "{}", found);
+            assert_eq!(found_line, line, "{}", found);
+            assert!(found.contains(message), "{}", found);
+        }
+        let (_, _, message) = error(&format!("{}bank = 2", image));
+        assert!(
+            message.starts_with("release/manifest.toml:3: bank: "),
+            "{}",
+            message
+        );
+        let (key, ..) = error("[release]");
+        assert_eq!(key, None);
+        let (key, _, message) = error("image = \"-\"\n");
+        assert_eq!(key.as_deref(), Some("image"));
+        assert!(message.contains("not stdin"), "{}", message);
+    }
+
+    #[test]
+    fn test_plan() {
+        let base = format!("image = \"a.bin\"\nsha256 = \"{}\"\n", SHA);
+        let plan = |extra: &str| parse(&format!("{}{}", base, extra)).unwrap().plan();
+        let upload = |bank| Step::Upload {
+            file: PathBuf::from("release/a.bin"),
+            bank,
+        };
+
+        assert_eq!(plan(""), [upload(None)]);
+        assert_eq!(
+            plan("bank = 0\nactivate = false\nmin_bootloader = \"0.2.0\"\n"),
+            [
+                Step::CheckBootloader(FwVersion::new(0, 2, 0)),
+                upload(Some(0)),
+                Step::KeepActive
+            ]
+        );
+        assert_eq!(
+            plan("bank = \"both\"\nreboot = true\nconfirm = true\n"),
+            [
+                Step::UploadBoth {
+                    file_a: "release/a.bin".into(),
+                    file_b: "release/a.bin".into(),
+                    activate: Some(0),
+                },
+                Step::Reboot { confirm: true }
+            ]
+        );
+        let both = plan(&format!(
+            "bank = \"both\"\nimage_b = \"b.bin\"\nsha256_b = \"{}\"\nactivate = false\n",
+            SHA
+        ));
+        assert_eq!(
+            both,
+            [Step::UploadBoth {
+                file_a: "release/a.bin".into(),
+                file_b: "release/b.bin".into(),
+                activate: None,
+            }]
+        );
+        assert_eq!(
+            both[0].to_string(),
+            "upload release/a.bin to bank 0 and release/b.bin to bank 1, keeping the active bank"
+        );
+    }
+
+    #[test]
+    fn test_check_and_apply() {
+        let dir = std::env::temp_dir().join(format!("crispy-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let image = raw_image();
+        fs::write(dir.join("fw.bin"), &image).unwrap();
+        let path = dir.join("manifest.toml");
+        let manifest = |extra: &str| {
+            let text = format!(
+                "image = \"fw.bin\"\nsha256 = \"{}\"\n{}",
+                hex(&image),
+                extra
+            );
+            Manifest::parse(&text, &path).unwrap()
+        };
+        let fails = |manifest: Manifest| {
+            let err = manifest.check(Overrides::NONE).unwrap_err();
+            format!("{:#}", err)
+        };
+
+        manifest("").check(Overrides::NONE).unwrap();
+        let wrong = Manifest::parse(
+            &format!("image = \"fw.bin\"\nsha256 = \"{}\"\n", SHA),
+            &path,
+        )
+        .unwrap();
+        let message = fails(wrong);
+        assert!(message.contains("manifest.toml:2: sha256: "), "{}", message);
+        assert!(message.contains("SHA-256 is"), "{}", message);
+        let message = fails(manifest("board = 2\n"));
+        assert!(message.contains(":3: board: "), "{}", message);
+        assert!(message.contains("no image header"), "{}", message);
+        let mut missing = manifest("");
+        missing.image.file = dir.join("gone.bin");
+        assert!(fails(missing).contains("manifest.toml:1: image: "));
+
+        // Bank B, then bank A, which holds the running firmware, active again
+        let manifest = manifest("bank = 1\nactivate = false\nverify = true\n");
+        let mut flash = FlashSim::new();
+        flash.poke(FlashLayout::DEFAULT.bank_addr(0).unwrap(), &image);
+        let bd = BootData {
+            size_a: image.len() as u32,
+            crc_a: crc32::checksum(&image),
+            confirmed: 1,
+            ..BootData::default_new()
+        };
+        write_boot_data(&mut flash, &bd).unwrap();
+        let device = TestDevice::with_flash(flash);
+        let transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        let watch = WatchOptions {
+            firmware: crate::boot_watch::UsbId::FIRMWARE,
+            bootloader: crate::boot_watch::UsbId::BOOTLOADER,
+            timeout: std::time::Duration::ZERO,
+            expect_confirm: false,
+            confirm_timeout: std::time::Duration::ZERO,
+        };
+        let (result, data) =
+            output::collect(|| apply(transport, &device.port, &manifest, Overrides::NONE, &watch));
+        result.unwrap();
+        let flash = device.finish();
+        let bd = crispy_common::flash::read_boot_data(&flash).unwrap();
+        assert_eq!((bd.active_bank, bd.size_b), (0, 3000));
+        let steps: Vec<_> = data["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["step"].as_str().unwrap())
+            .collect();
+        assert_eq!(steps, ["upload", "set-bank"]);
+        assert_eq!(data["steps"][0]["data"]["bank"], 1);
+        assert_eq!(data["steps"][0]["data"]["verified"], true);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,416 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! A terminal for the firmware console, for `monitor`.
+//!
+//! Device output is copied to stdout as it comes. On a terminal, keys are
+//! read one at a time in raw mode (the console crate does this on Linux,
+//! macOS and Windows alike) and sent as typed: Enter as a carriage return,
+//! Backspace as DEL, which the firmware's line editor takes. The firmware
+//! echoes what it gets, so nothing is echoed locally. Piped input is sent
+//! line by line, and the session ends once the device has been quiet for a
+//! moment after the input runs out.
+//!
+//! Ctrl-] or Ctrl-C quits. A `~` at the start of a line begins an escape,
+//! as in `ssh` and `cu`:
+//!
+//! | Escape | Does |
+//! |--------|------|
+//! | `~b` | type `bootload`, rebooting into the bootloader |
+//! | `~r` | type `reboot` |
+//! | `~.` | quit |
+//! | `~?` | list the escapes |
+//! | `~~` | send one `~` |
+//!
+//! When the port goes away (a reboot, the cable) the session ends, or with
+//! `--reattach` waits for the firmware to come back, found again by its USB
+//! serial number, and carries on there.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use console::{Key, Term};
+
+use crate::link::{self, Link, LinkOptions};
+use crate::output::{self, outln, Failure};
+use crate::transport::{self, DeviceKind};
+
+/// How long a read of the device waits before keys are looked at again.
+const POLL: Duration = Duration::from_millis(20);
+/// How often a port that went away is looked for with `--reattach`.
+const REATTACH_POLL: Duration = Duration::from_millis(250);
+/// How long the device must be quiet, once piped input has run out, before
+/// the session ends.
+const QUIET: Duration = Duration::from_secs(1);
+
+/// Ctrl-], the quit key of `telnet` and `picocom`.
+const CTRL_RIGHT_BRACKET: char = '\x1d';
+
+const HELP: &str = "\
+--- ~b  type `bootload` (reboot into the bootloader)
+--- ~r  type `reboot`
+--- ~.  quit (or Ctrl-] / Ctrl-C)
+--- ~~  send a ~";
+
+/// What a key does.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Action {
+    Send(Vec<u8>),
+    Help,
+    Quit,
+    Nothing,
+}
+
+/// Turns keys into bytes for the device, spotting escapes at the start of
+/// a line.
+#[derive(Default)]
+struct Escapes {
+    mid_line: bool,
+    tilde: bool,
+}
+
+impl Escapes {
+    fn key(&mut self, key: Key) -> Action {
+        if std::mem::take(&mut self.tilde) {
+            return match key {
+                Key::Char('b') => self.line(b"bootload"),
+                Key::Char('r') => self.line(b"reboot"),
+                Key::Char('.') => Action::Quit,
+                Key::Char('?') => Action::Help,
+                key => {
+                    self.mid_line = true;
+                    let mut bytes = b"~".to_vec();
+                    if key != Key::Char('~') {
+                        bytes.extend(key_bytes(&key));
+                    }
+                    Action::Send(bytes)
+                }
+            };
+        }
+        match key {
+            Key::CtrlC | Key::Char(CTRL_RIGHT_BRACKET) => Action::Quit,
+            Key::Char('~') if !self.mid_line => {
+                self.tilde = true;
+                Action::Nothing
+            }
+            key => {
+                let bytes = key_bytes(&key);
+                if bytes.is_empty() {
+                    return Action::Nothing;
+                }
+                self.mid_line = key != Key::Enter;
+                Action::Send(bytes)
+            }
+        }
+    }
+
+    /// `command` on a line of its own, ending whatever was typed before.
+    fn line(&mut self, command: &[u8]) -> Action {
+        self.mid_line = false;
+        Action::Send([b"\r", command, b"\r"].concat())
+    }
+}
+
+/// The bytes a key sends; keys the firmware has no use for send nothing.
+fn key_bytes(key: &Key) -> Vec<u8> {
+    match key {
+        Key::Char(c) => c.to_string().into_bytes(),
+        Key::Enter => b"\r".to_vec(),
+        Key::Backspace => vec![0x7f],
+        Key::Tab => b"\t".to_vec(),
+        Key::Escape => vec![0x1b],
+        _ => Vec::new(),
+    }
+}
+
+/// Read keys from the terminal, or lines from piped stdin, on a thread of
+/// their own. The channel closes when the input runs out.
+fn spawn_input() -> Receiver<Key> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        if io::stdin().is_terminal() {
+            let term = Term::stdout();
+            while let Ok(key) = term.read_key_raw() {
+                if tx.send(key).is_err() {
+                    break;
+                }
+            }
+            return;
+        }
+        for line in io::stdin().lines().map_while(Result::ok) {
+            let keys = line.chars().map(Key::Char).chain([Key::Enter]);
+            if keys.map(|key| tx.send(key)).any(|sent| sent.is_err()) {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// The port of the one device running crispy firmware, or the one with USB
+/// serial number `serial`.
+pub fn find_console_port(serial: Option<&str>) -> Result<String> {
+    let ports: Vec<_> = transport::list_ports()?
+        .into_iter()
+        .filter(|p| {
+            p.kind == Some(DeviceKind::Firmware)
+                && serial.is_none_or(|sn| {
+                    p.usb.as_ref().and_then(|u| u.serial_number.as_deref()) == Some(sn)
+                })
+        })
+        .collect();
+    match ports.as_slice() {
+        [p] => Ok(p.name.clone()),
+        [] => bail!(Failure::new(
+            "no_device",
+            match serial {
+                Some(sn) => format!("No crispy firmware with serial number {}", sn),
+                None => "No device running crispy firmware found".to_string(),
+            }
+        )),
+        _ => {
+            let names: Vec<_> = ports.iter().map(|p| p.name.as_str()).collect();
+            bail!(Failure::new(
+                "no_device",
+                format!(
+                    "Several devices running crispy firmware found ({}); pass --serial or --port",
+                    names.join(", ")
+                )
+            )
+            .with("ports", names))
+        }
+    }
+}
+
+/// A console session on one device.
+struct Session {
+    port: String,
+    link: LinkOptions,
+    /// USB serial number, to find the device again under another name.
+    serial: Option<String>,
+    reattach: bool,
+}
+
+/// How a session on one connection ended.
+enum End {
+    Quit,
+    Gone,
+}
+
+impl Session {
+    fn new(port: &str, link: &LinkOptions, reattach: bool) -> Self {
+        let serial = match link::tcp_address(port) {
+            Some(_) => None,
+            None => transport::port_serial(port),
+        };
+        Self {
+            port: port.to_string(),
+            link: LinkOptions {
+                read_timeout: POLL,
+                ..*link
+            },
+            serial,
+            reattach,
+        }
+    }
+
+    fn open(&self, port: &str) -> Result<Box<dyn Link>> {
+        link::open(port, &self.link)
+            .with_context(|| format!("Failed to open firmware console {}", port))
+    }
+
+    /// Run until the user quits, the input runs out, or the device goes
+    /// away and is not to be waited for.
+    fn run(&self, keys: &Receiver<Key>, out: &mut dyn Write) -> Result<()> {
+        let mut console = self.open(&self.port)?;
+        let mut escapes = Escapes::default();
+        loop {
+            if let End::Quit = self.attach(console.as_mut(), keys, &mut escapes, out)? {
+                return Ok(());
+            }
+            outln!("--- {} went away ---", console.name());
+            if !self.reattach {
+                return Ok(());
+            }
+            match self.wait_for_device(keys)? {
+                Some(link) => console = link,
+                None => return Ok(()),
+            }
+            outln!("--- Reattached on {} ---", console.name());
+            escapes = Escapes::default();
+        }
+    }
+
+    /// Copy between the keys and `console` until either ends.
+    fn attach(
+        &self,
+        console: &mut dyn Link,
+        keys: &Receiver<Key>,
+        escapes: &mut Escapes,
+        out: &mut dyn Write,
+    ) -> Result<End> {
+        let mut buf = [0u8; 256];
+        let mut last_output = Instant::now();
+        let mut input_done = false;
+        loop {
+            while !input_done {
+                let key = match keys.try_recv() {
+                    Ok(key) => key,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        input_done = true;
+                        break;
+                    }
+                };
+                match escapes.key(key) {
+                    Action::Send(bytes) => {
+                        match console.write_all(&bytes).and_then(|()| console.flush()) {
+                            Ok(()) => {}
+                            Err(e) if link::is_disconnect(&e) => return Ok(End::Gone),
+                            Err(e) => {
+                                return Err(e).context(format!("Failed to write to {}", self.port))
+                            }
+                        }
+                    }
+                    Action::Help => outln!("\r\n{}", HELP.replace('\n', "\r\n")),
+                    Action::Quit => return Ok(End::Quit),
+                    Action::Nothing => {}
+                }
+            }
+
+            match console.read(&mut buf) {
+                Ok(n) => {
+                    out.write_all(&buf[..n])?;
+                    out.flush()?;
+                    last_output = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) if link::is_disconnect(&e) => return Ok(End::Gone),
+                Err(e) => return Err(e).context(format!("Failed to read {}", self.port)),
+            }
+            if input_done && last_output.elapsed() >= QUIET {
+                return Ok(End::Quit);
+            }
+        }
+    }
+
+    /// Wait for the device to come back, by its serial number if it has
+    /// one, until the user quits.
+    fn wait_for_device(&self, keys: &Receiver<Key>) -> Result<Option<Box<dyn Link>>> {
+        outln!("--- Waiting for the firmware to come back (Ctrl-] to quit) ---");
+        loop {
+            let port = match &self.serial {
+                Some(serial) => find_console_port(Some(serial)).ok(),
+                None => Some(self.port.clone()),
+            };
+            if let Some(Ok(link)) = port.map(|port| self.open(&port)) {
+                return Ok(Some(link));
+            }
+            match keys.recv_timeout(REATTACH_POLL) {
+                Ok(Key::CtrlC | Key::Char(CTRL_RIGHT_BRACKET)) => return Ok(None),
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Open the firmware console on `port` and connect the terminal to it.
+pub fn monitor(port: &str, link: &LinkOptions, reattach: bool) -> Result<()> {
+    if output::is_json() {
+        bail!(Failure::new(
+            "invalid_argument",
+            "monitor is interactive and has no JSON output"
+        ));
+    }
+    let session = Session::new(port, link, reattach);
+    outln!(
+        "--- Monitoring {} (Ctrl-] to quit, ~? for escapes) ---",
+        port
+    );
+    let keys = spawn_input();
+    session.run(&keys, &mut io::stdout())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn keys(escapes: &mut Escapes, text: &str) -> Vec<Action> {
+        text.chars()
+            .map(|c| match c {
+                '\n' => Key::Enter,
+                c => Key::Char(c),
+            })
+            .map(|key| escapes.key(key))
+            .filter(|action| *action != Action::Nothing)
+            .collect()
+    }
+
+    fn send(bytes: &[u8]) -> Action {
+        Action::Send(bytes.to_vec())
+    }
+
+    #[test]
+    fn test_escapes_at_line_start() {
+        let mut escapes = Escapes::default();
+        assert_eq!(
+            keys(&mut escapes, "~b~r"),
+            [send(b"\rbootload\r"), send(b"\rreboot\r")]
+        );
+        assert_eq!(
+            keys(&mut escapes, "ab~r\n"),
+            [send(b"a"), send(b"b"), send(b"~"), send(b"r"), send(b"\r")]
+        );
+        assert_eq!(keys(&mut escapes, "~~"), [send(b"~")]);
+        assert_eq!(keys(&mut escapes, "\n~x"), [send(b"\r"), send(b"~x")]);
+        assert_eq!(
+            keys(&mut escapes, "\n~?~."),
+            [send(b"\r"), Action::Help, Action::Quit]
+        );
+    }
+
+    #[test]
+    fn test_quit_and_special_keys() {
+        let mut escapes = Escapes::default();
+        assert_eq!(escapes.key(Key::CtrlC), Action::Quit);
+        assert_eq!(escapes.key(Key::Char(CTRL_RIGHT_BRACKET)), Action::Quit);
+        assert_eq!(escapes.key(Key::Backspace), send(&[0x7f]));
+        assert_eq!(escapes.key(Key::ArrowUp), Action::Nothing);
+    }
+
+    #[test]
+    fn test_session_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = Vec::new();
+            let mut byte = [0u8; 1];
+            while byte[0] != b'\r' {
+                stream.read_exact(&mut byte).unwrap();
+                line.push(byte[0]);
+            }
+            stream.write_all(b"Bank: 1 (B)\r\n").unwrap();
+            // The firmware reboots
+            String::from_utf8(line).unwrap()
+        });
+
+        let (tx, rx) = mpsc::channel();
+        for key in "status".chars().map(Key::Char).chain([Key::Enter]) {
+            tx.send(key).unwrap();
+        }
+        let session = Session::new(&port, &LinkOptions::DEFAULT, false);
+        let mut out = Vec::new();
+        session.run(&rx, &mut out).unwrap();
+
+        assert_eq!(server.join().unwrap(), "status\r");
+        assert_eq!(out, b"Bank: 1 (B)\r\n");
+        drop(tx);
+    }
+}
@@ -0,0 +1,898 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Output for people and for scripts.
+//!
+//! By default commands print text and draw progress bars. With the global
+//! `--json` flag, stdout carries only newline-delimited JSON events instead,
+//! one object per line, and the text goes to stderr. Every run ends with
+//! exactly one `result` event; the others are optional and may be ignored.
+//!
+//! ```text
+//! {"event":"phase","phase":"erase"}
+//! {"event":"progress","phase":"write","done":1024,"total":65536}
+//! {"event":"result","command":"upload","ok":true,"data":{...}}
+//! {"event":"result","command":"upload","ok":false,"error":{"code":"flash","message":"...","context":{"offset":4096}}}
+//! ```
+//!
+//! - `phase`: a step without a byte count has started (`erase`,
+//!   `finalize`, `reboot`, `enter_bootloader`, `wait_confirm`,
+//!   `blank_check`, `wait_device`, `download` without a length, and
+//!   `bank_a` and `bank_b` as `upload-both` moves to each bank).
+//! - `progress`: `done` of `total` bytes (or units, for `copy`) of a long
+//!   step (`download`, `write`, `verify`, `read`, `copy`), sent at most
+//!   about 64 times per step and always at the end.
+//! - `sample`: one sample of `status --watch` or `info --watch`, `n`
+//!   counting from 1 at `secs` since the start: the fields in `data` and
+//!   the names of those that `changed` since the previous sample, or, when
+//!   the device went away, `gone` with the error instead of `data`.
+//! - `override`: a check refused and `flag` went ahead anyway, with the
+//!   `protection` (see `overrides.rs`) and the `message` it refused with.
+//!   The `result` data lists these under `overrides` as well.
+//! - `result`: `command` is the subcommand name as typed. On success `data`
+//!   holds what the command reports, the same fields as its text output;
+//!   on failure `error` holds a `code`, the full `message` and a `context`
+//!   object with details such as an `offset`, a `status` or the `problems`
+//!   found by `inspect`.
+//!
+//! Error codes:
+//!
+//! | Code | Meaning |
+//! |------|---------|
+//! | `invalid_argument` | An option is out of range; nothing was sent |
+//! | `no_device` | No single device matches (`--serial`, `--port`) |
+//! | `wrong_port` | The `--port` of a command that changes the device is not the bootloader (`context.verdict`); `--allow-wrong-port` goes ahead |
+//! | `port` | The serial port could not be opened or used |
+//! | `disconnected` | The device or TCP bridge closed the connection (`context.port`) |
+//! | `config` | A configuration file does not parse (`context.path`, `context.line`) |
+//! | `io` | A file could not be read or written |
+//! | `input` | The firmware file is malformed (UF2, ELF, Intel HEX), or stdin is empty or too large |
+//! | `fetch` | A firmware URL could not be downloaded whole |
+//! | `checksum` | The firmware file's SHA-256 is not the `--sha256` given (`context.expected`, `context.actual`) |
+//! | `rejected` | `inspect` found problems (`context.problems`) |
+//! | `preflight` | `upload` or `update` refused the file or bank before erasing (`context.check`) |
+//! | `timeout` | The device did not answer in time |
+//! | `protocol` | The device answered something unexpected |
+//! | `device` | The device refused a command (`context.status`) |
+//! | `flash` | A flash operation failed (`context.offset`) |
+//! | `crc` | The image CRC did not match on the device |
+//! | `verify` | Read-back, `diff` or `verify` found differences (`context.offset`, `context.mismatched`, `context.regions`; `context.differing` with `--quick`) |
+//! | `not_blank` | `blank-check` found bytes that are not 0xFF (`context.addr`, `context.first_dirty`, `context.dirty_bytes`) |
+//! | `archive` | A `restore` archive is malformed or does not match its manifest |
+//! | `wrong_device` | `restore` was given another device's backup (`context.problems`); `--allow-other-device` goes ahead |
+//! | `provisioned` | `provision` found the unit provisioned with another record (`context.stored`); `--reprovision` replaces it |
+//! | `inconsistent` | `verify-all` found a check that failed or warned (`context.worst`, `context.flagged`, `context.checks`) |
+//! | `empty_bank` | `verify` found no firmware in the bank (`context.bank`) |
+//! | `swd` | `probe-rs` failed to flash, verify or reset the target over SWD (`context.command`, `context.status`) |
+//! | `boot_failed` | After a reboot the device came back in the bootloader |
+//! | `script` | A `run` script does not parse or check (`context.line`) |
+//! | `manifest` | An `apply` manifest does not parse or check (`context.path`, `context.line`, `context.key`) |
+//! | `bootloader_version` | The bootloader is older than the manifest's `min_bootloader`, or does not say (`context.required`, `context.found`) |
+//! | `devices` | A command on several devices failed on some (`context.failed`, `context.devices`) |
+//! | `factory` | A `factory` stage failed (`context.stage`, `context.record`) |
+//! | `selftest` | A `selftest` phase failed (`context.phase`, `context.seed`, `context.phases`) |
+//! | `not_confirmed` | A change needing confirmation was declined, or `--yes` was missing where nobody can be asked |
+//! | `error` | Anything else |
+//!
+//! Fields are only ever added to these objects, so consumers should ignore
+//! keys they do not know.
+//!
+//! Progress on stderr follows `--progress`: a bar, a `phase: N% (done/total)`
+//! line every [`PLAIN_STEP`] percent, or nothing. The default picks the bar
+//! on a terminal and plain lines otherwise (CI logs, a GUI reading a pipe).
+//! JSON `progress` events are sent in every mode.
+//!
+//! When one command runs on several devices at once (see `parallel`), each
+//! device's thread has output of its own: text lines start with `[port]`,
+//! bars share the screen, `phase` and `progress` events carry a `device`
+//! field with the port, and what the command reports goes to that device's
+//! entry in the `devices` list of the one `result` event.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+static DATA: Mutex<Option<Map<String, Value>>> = Mutex::new(None);
+static PROGRESS: AtomicU8 = AtomicU8::new(ProgressMode::Bar as u8);
+
+thread_local! {
+    /// The device this thread works on, when several run at once.
+    static DEVICE: RefCell<Option<Device>> = const { RefCell::new(None) };
+    /// Told of this thread's steps as well, see [`watch_steps`].
+    static WATCHER: RefCell<Option<Watcher>> = const { RefCell::new(None) };
+}
+
+/// Told of each step as it starts (`None`) and of each position of its
+/// progress (`Some((done, total))`).
+pub type Watcher = Box<dyn Fn(&str, Option<(u64, u64)>)>;
+
+/// Output state of one device's thread.
+struct Device {
+    /// Port name, starting each line and bar.
+    label: String,
+    bars: MultiProgress,
+    /// What the command reported, for its entry in `devices`.
+    data: Map<String, Value>,
+    /// Text printed without a newline yet, kept to print as one line.
+    pending: String,
+}
+
+/// How progress is shown on stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ProgressMode {
+    /// `bar` on a terminal, else `plain`
+    Auto,
+    /// Redrawn progress bars
+    Bar,
+    /// A line every 10 percent
+    Plain,
+    /// Nothing
+    None,
+}
+
+/// Percent between `--progress plain` lines.
+pub const PLAIN_STEP: u64 = 10;
+
+/// Show progress as `mode` for the rest of the run.
+pub fn set_progress_mode(mode: ProgressMode) {
+    let mode = match mode {
+        ProgressMode::Auto if std::io::stderr().is_terminal() => ProgressMode::Bar,
+        ProgressMode::Auto => ProgressMode::Plain,
+        mode => mode,
+    };
+    PROGRESS.store(mode as u8, Ordering::Relaxed);
+}
+
+fn progress_mode() -> ProgressMode {
+    match PROGRESS.load(Ordering::Relaxed) {
+        m if m == ProgressMode::Bar as u8 => ProgressMode::Bar,
+        m if m == ProgressMode::Plain as u8 => ProgressMode::Plain,
+        _ => ProgressMode::None,
+    }
+}
+
+/// Switch to JSON events for the rest of the run.
+pub fn set_json(on: bool) {
+    JSON.store(on, Ordering::Relaxed);
+}
+
+/// Whether `--json` is on.
+pub fn is_json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Print a line of text: to stdout normally, to stderr with `--json`.
+macro_rules! outln {
+    () => {
+        $crate::output::outln!("")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::print_text(format_args!($($arg)*), true)
+    };
+}
+
+/// Print text without a newline and flush it, for "Doing X... OK" lines.
+macro_rules! out {
+    ($($arg:tt)*) => {
+        $crate::output::print_text(format_args!($($arg)*), false)
+    };
+}
+
+pub(crate) use {out, outln};
+
+/// Print for [`outln!`] and [`out!`]. On a device's thread a line is held
+/// until it ends, then printed whole with the device's prefix.
+pub fn print_text(text: fmt::Arguments, newline: bool) {
+    let held = DEVICE.with(|device| {
+        let mut device = device.borrow_mut();
+        let device = device.as_mut()?;
+        device.pending.push_str(&text.to_string());
+        if newline {
+            let lines = prefix_lines(&device.label, &std::mem::take(&mut device.pending));
+            device
+                .bars
+                .suspend(|| write_text(format_args!("{}\n", lines)));
+        }
+        Some(())
+    });
+    if held.is_none() && newline {
+        write_text(format_args!("{}\n", text));
+    } else if held.is_none() {
+        write_text(text);
+    }
+}
+
+fn write_text(text: fmt::Arguments) {
+    if is_json() {
+        eprint!("{}", text);
+        let _ = std::io::stderr().flush();
+    } else {
+        print!("{}", text);
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// `text` with `[label] ` at the start of each line.
+fn prefix_lines(label: &str, text: &str) -> String {
+    let lines: Vec<_> = text
+        .split('\n')
+        .map(|line| format!("[{}] {}", label, line))
+        .collect();
+    lines.join("\n")
+}
+
+/// Bars for several devices at once, drawn together.
+pub fn device_bars() -> MultiProgress {
+    let target = if !is_json() && progress_mode() == ProgressMode::Bar {
+        ProgressDrawTarget::stderr()
+    } else {
+        ProgressDrawTarget::hidden()
+    };
+    MultiProgress::with_draw_target(target)
+}
+
+/// Run `f` as the work on device `label`, with its output prefixed and its
+/// bars in `bars`, and return what it reported.
+pub fn for_device<T>(
+    label: &str,
+    bars: &MultiProgress,
+    f: impl FnOnce() -> T,
+) -> (T, Map<String, Value>) {
+    DEVICE.with(|device| {
+        *device.borrow_mut() = Some(Device {
+            label: label.to_string(),
+            bars: bars.clone(),
+            data: Map::new(),
+            pending: String::new(),
+        })
+    });
+    let value = f();
+    let device = DEVICE.with(|device| device.borrow_mut().take());
+    let mut device = device.expect("device output in place");
+    if !device.pending.is_empty() {
+        let lines = prefix_lines(&device.label, &device.pending);
+        bars.suspend(|| write_text(format_args!("{}\n", lines)));
+    }
+    (value, std::mem::take(&mut device.data))
+}
+
+/// This thread runs a command on one of several devices at once.
+pub fn on_several_devices() -> bool {
+    device_label().is_some()
+}
+
+fn device_label() -> Option<String> {
+    DEVICE.with(|device| device.borrow().as_ref().map(|d| d.label.clone()))
+}
+
+/// Ask `question` and go on only if the answer is yes. With `--json`, on
+/// several devices at once, or when stdin is not a terminal, there is
+/// nobody to ask and this fails; the command's `--yes` skips the question.
+pub fn confirm(question: &str) -> Result<()> {
+    if is_json() || device_label().is_some() || !std::io::stdin().is_terminal() {
+        bail!(Failure::new(
+            "not_confirmed",
+            format!("{} Pass --yes to confirm without a prompt", question)
+        ));
+    }
+    out!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        bail!(Failure::new(
+            "not_confirmed",
+            "Cancelled; nothing was changed"
+        ));
+    }
+    Ok(())
+}
+
+/// One line of JSON output.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Phase {
+        phase: &'a str,
+    },
+    Progress {
+        phase: &'a str,
+        done: u64,
+        total: u64,
+    },
+    Sample {
+        n: u64,
+        secs: f64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<&'a Map<String, Value>>,
+        changed: &'a [&'a str],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        gone: Option<&'a str>,
+    },
+    Override {
+        protection: &'a str,
+        flag: &'a str,
+        message: &'a str,
+    },
+    Result {
+        command: &'a str,
+        ok: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<Value>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<ErrorReport>,
+    },
+}
+
+/// An event from a device's thread.
+#[derive(Serialize)]
+struct DeviceEvent<'a> {
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+    device: &'a str,
+}
+
+/// The `error` object of a failed `result` event.
+#[derive(Serialize)]
+struct ErrorReport {
+    code: &'static str,
+    message: String,
+    context: Map<String, Value>,
+}
+
+fn emit(event: &Event) {
+    if !is_json() {
+        return;
+    }
+    let line = match device_label() {
+        Some(device) => serde_json::to_string(&DeviceEvent {
+            event,
+            device: &device,
+        }),
+        None => serde_json::to_string(event),
+    }
+    .expect("events serialize");
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{}", line);
+    let _ = stdout.flush();
+}
+
+/// Announce a step that has no byte count.
+pub fn phase(phase: &str) {
+    watched(phase, None);
+    emit(&Event::Phase { phase });
+}
+
+/// Run `f` with `watcher` told of its steps and their progress, whatever
+/// the output mode; for `serve`, which answers how far an upload has got.
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub fn watch_steps<T>(watcher: Watcher, f: impl FnOnce() -> T) -> T {
+    WATCHER.with(|w| *w.borrow_mut() = Some(watcher));
+    let value = f();
+    WATCHER.with(|w| w.borrow_mut().take());
+    value
+}
+
+fn watched(phase: &str, progress: Option<(u64, u64)>) {
+    WATCHER.with(|w| {
+        if let Some(watcher) = w.borrow().as_ref() {
+            watcher(phase, progress);
+        }
+    });
+}
+
+/// Send a sample of `status --watch` or `info --watch`: its fields, or why
+/// the device is gone.
+pub fn sample(
+    n: u64,
+    secs: f64,
+    data: Option<&Map<String, Value>>,
+    changed: &[&str],
+    gone: Option<&str>,
+) {
+    emit(&Event::Sample {
+        n,
+        secs,
+        data,
+        changed,
+        gone,
+    });
+}
+
+/// Add `fields` (an object) to the data of the final `result` event. Later
+/// calls add to or replace earlier fields.
+pub fn report(fields: Value) {
+    let Value::Object(fields) = fields else {
+        return;
+    };
+    let fields = DEVICE.with(|device| match device.borrow_mut().as_mut() {
+        Some(device) => {
+            device.data.extend(fields);
+            None
+        }
+        None => Some(fields),
+    });
+    let Some(fields) = fields else {
+        return;
+    };
+    let mut data = DATA.lock().unwrap();
+    data.get_or_insert_with(Map::new).extend(fields);
+}
+
+/// Run `f` and return what it reports instead of adding it to the `result`
+/// data, for a command made of several that report the same fields.
+pub fn collect<T>(f: impl FnOnce() -> T) -> (T, Map<String, Value>) {
+    let held = DEVICE.with(|device| {
+        let mut device = device.borrow_mut();
+        device.as_mut().map(|d| std::mem::take(&mut d.data))
+    });
+    let Some(held) = held else {
+        let outer = DATA.lock().unwrap().take();
+        let value = f();
+        let mut data = DATA.lock().unwrap();
+        let collected = std::mem::replace(&mut *data, outer).unwrap_or_default();
+        return (value, collected);
+    };
+    let value = f();
+    let collected = DEVICE.with(|device| {
+        let mut device = device.borrow_mut();
+        let device = device.as_mut().expect("still this device's thread");
+        std::mem::replace(&mut device.data, held)
+    });
+    (value, collected)
+}
+
+/// Say that `flag` overrode `protection`, which refused with `message`: an
+/// `override` event now, and an entry in the `overrides` list of the
+/// `result` data.
+pub fn overridden(protection: &str, flag: &str, message: &str) {
+    emit(&Event::Override {
+        protection,
+        flag,
+        message,
+    });
+    let entry = serde_json::json!({
+        "protection": protection,
+        "flag": flag,
+        "message": message,
+    });
+    let push = |data: &mut Map<String, Value>| {
+        let list = data
+            .entry("overrides")
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(list) = list {
+            list.push(entry.clone());
+        }
+    };
+    let held = DEVICE.with(|device| match device.borrow_mut().as_mut() {
+        Some(device) => {
+            push(&mut device.data);
+            true
+        }
+        None => false,
+    });
+    if !held {
+        push(DATA.lock().unwrap().get_or_insert_with(Map::new));
+    }
+}
+
+/// Emit the final `result` event for `command`.
+pub fn finish(command: &str, result: &Result<()>) {
+    let data = DATA.lock().unwrap().take();
+    emit(&result_event(command, result, data));
+}
+
+fn result_event<'a>(
+    command: &'a str,
+    result: &Result<()>,
+    data: Option<Map<String, Value>>,
+) -> Event<'a> {
+    match result {
+        Ok(()) => Event::Result {
+            command,
+            ok: true,
+            data: Some(Value::Object(data.unwrap_or_default())),
+            error: None,
+        },
+        Err(err) => Event::Result {
+            command,
+            ok: false,
+            data: None,
+            error: Some(error_report(err)),
+        },
+    }
+}
+
+/// One device's entry in the `devices` list of a run on several devices:
+/// `ok` and the `data` it reported, or the `error` object.
+pub fn device_result(port: &str, result: &Result<()>, data: Map<String, Value>) -> Value {
+    match result {
+        Ok(()) => serde_json::json!({ "port": port, "ok": true, "data": data }),
+        Err(err) => {
+            let error = serde_json::to_value(error_report(err)).expect("errors serialize");
+            serde_json::json!({ "port": port, "ok": false, "error": error })
+        }
+    }
+}
+
+/// An error with a code and context for the JSON `result` event. Its text
+/// is the message, so it reads as any other error in text mode.
+#[derive(Debug)]
+pub struct Failure {
+    pub code: &'static str,
+    pub message: String,
+    pub context: Map<String, Value>,
+}
+
+impl Failure {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            context: Map::new(),
+        }
+    }
+
+    /// Add a context field.
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.context.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Code and context from the first [`Failure`] in the chain, else from the
+/// kind of error underneath.
+fn error_report(err: &anyhow::Error) -> ErrorReport {
+    let message = format!("{:#}", err);
+    if let Some(failure) = err.chain().find_map(|e| e.downcast_ref::<Failure>()) {
+        return ErrorReport {
+            code: failure.code,
+            message,
+            context: failure.context.clone(),
+        };
+    }
+    let code = if err.chain().any(|e| e.is::<serialport::Error>()) {
+        "port"
+    } else if err.chain().any(|e| e.is::<std::io::Error>()) {
+        "io"
+    } else {
+        "error"
+    };
+    ErrorReport {
+        code,
+        message,
+        context: Map::new(),
+    }
+}
+
+/// Progress of a long step: a bar or plain lines as `--progress` says,
+/// `progress` events with `--json`.
+pub struct Progress {
+    phase: &'static str,
+    bar: ProgressBar,
+    /// The bars of all devices, on a device's thread.
+    bars: Option<MultiProgress>,
+    /// Prefix of plain lines, on a device's thread.
+    prefix: String,
+    /// Draw the bar; it stays hidden until the total is known.
+    drawn: bool,
+    total: Cell<u64>,
+    reported: Cell<Option<u64>>,
+    /// Last percentage printed as a plain line.
+    printed: Cell<Option<u64>>,
+}
+
+/// Most `progress` events sent per step.
+const PROGRESS_EVENTS: u64 = 64;
+
+impl Progress {
+    /// Progress counting `len` bytes.
+    pub fn bytes(phase: &'static str, len: u32) -> Result<Self> {
+        Self::with_template(
+            phase,
+            len as u64,
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({eta})",
+        )
+    }
+
+    /// Progress shown as a percentage, for totals that are not bytes or not
+    /// known yet (see [`Progress::set_length`]).
+    pub fn percent(phase: &'static str) -> Result<Self> {
+        Self::with_template(
+            phase,
+            0,
+            "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {percent}%",
+        )
+    }
+
+    fn with_template(phase: &'static str, total: u64, template: &str) -> Result<Self> {
+        let drawn = !is_json() && progress_mode() == ProgressMode::Bar;
+        let bar = ProgressBar::hidden();
+        let device = DEVICE.with(|device| {
+            let device = device.borrow();
+            device.as_ref().map(|d| (d.label.clone(), d.bars.clone()))
+        });
+        let template = match &device {
+            Some((label, _)) => {
+                bar.set_prefix(label.clone());
+                format!("{{prefix}} {}", template)
+            }
+            None => template.to_string(),
+        };
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(&template)?
+                .progress_chars("#>-"),
+        );
+        bar.set_length(total);
+        let (prefix, bars) = match device {
+            Some((label, bars)) => (format!("[{}] ", label), Some(bars)),
+            None => (String::new(), None),
+        };
+        let progress = Self {
+            phase,
+            bar,
+            bars,
+            prefix,
+            drawn,
+            total: Cell::new(0),
+            reported: Cell::new(None),
+            printed: Cell::new(None),
+        };
+        progress.set_length(total);
+        Ok(progress)
+    }
+
+    pub fn set_length(&self, total: u64) {
+        self.bar.set_length(total);
+        if self.drawn && total > 0 && self.total.get() == 0 {
+            match &self.bars {
+                Some(bars) => {
+                    bars.add(self.bar.clone());
+                }
+                None => self.bar.set_draw_target(ProgressDrawTarget::stderr()),
+            }
+        }
+        self.total.set(total);
+    }
+
+    pub fn set_position(&self, done: u64) {
+        self.bar.set_position(done);
+
+        let total = self.total.get();
+        watched(self.phase, Some((done, total)));
+        if progress_mode() == ProgressMode::Plain {
+            if let Some(line) = plain_line(self.phase, done, total, self.printed.get()) {
+                self.printed.set(Some(done * 100 / total));
+                eprintln!("{}{}", self.prefix, line);
+            }
+        }
+
+        let step = (total / PROGRESS_EVENTS).max(1);
+        let due = match self.reported.get() {
+            None => true,
+            Some(last) => done >= total || done >= last + step,
+        };
+        if due && self.reported.get() != Some(done) {
+            self.reported.set(Some(done));
+            emit(&Event::Progress {
+                phase: self.phase,
+                done,
+                total,
+            });
+        }
+    }
+
+    /// Leave the bar on screen with `message`.
+    pub fn finish_with_message(&self, message: &'static str) {
+        self.bar.finish_with_message(message);
+    }
+
+    /// Remove the bar.
+    pub fn finish_and_clear(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    /// Stop the bar where it is, for a step that failed.
+    pub fn abandon(&self) {
+        self.bar.abandon();
+    }
+}
+
+/// The `--progress plain` line for `done` of `total`, if it has moved on
+/// [`PLAIN_STEP`] percent or reached the end since `printed`.
+fn plain_line(phase: &str, done: u64, total: u64, printed: Option<u64>) -> Option<String> {
+    if total == 0 {
+        return None;
+    }
+    let percent = (done * 100 / total).min(100);
+    let due = match printed {
+        None => true,
+        Some(last) => percent >= last + PLAIN_STEP || (percent == 100 && last < 100),
+    };
+    due.then(|| format!("{}: {}% ({}/{})", phase, percent, done, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::{anyhow, Context};
+
+    fn line(event: &Event) -> String {
+        serde_json::to_string(event).unwrap()
+    }
+
+    #[test]
+    fn test_progress_events() {
+        assert_eq!(
+            line(&Event::Phase { phase: "erase" }),
+            r#"{"event":"phase","phase":"erase"}"#
+        );
+        assert_eq!(
+            line(&Event::Progress {
+                phase: "write",
+                done: 1024,
+                total: 4096
+            }),
+            r#"{"event":"progress","phase":"write","done":1024,"total":4096}"#
+        );
+    }
+
+    #[test]
+    fn test_result_event() {
+        let data = serde_json::json!({ "bank": 1 }).as_object().cloned();
+        assert_eq!(
+            line(&result_event("upload", &Ok(()), data)),
+            r#"{"event":"result","command":"upload","ok":true,"data":{"bank":1}}"#
+        );
+        assert_eq!(
+            line(&result_event("reboot", &Ok(()), None)),
+            r#"{"event":"result","command":"reboot","ok":true,"data":{}}"#
+        );
+    }
+
+    #[test]
+    fn test_error_event() {
+        let err: anyhow::Error = Failure::new("flash", "Flash hardware error at offset 0x00001000")
+            .with("offset", 4096)
+            .into();
+        assert_eq!(
+            line(&result_event("upload", &Err(err), None)),
+            r#"{"event":"result","command":"upload","ok":false,"error":{"code":"flash","message":"Flash hardware error at offset 0x00001000","context":{"offset":4096}}}"#
+        );
+
+        // Context added on top keeps the code, and the message has it all
+        let err = Err::<(), _>(Failure::new("timeout", "Timeout waiting for response"))
+            .context("Read-back failed")
+            .unwrap_err();
+        assert_eq!(
+            line(&result_event("download", &Err(err), None)),
+            r#"{"event":"result","command":"download","ok":false,"error":{"code":"timeout","message":"Read-back failed: Timeout waiting for response","context":{}}}"#
+        );
+    }
+
+    #[test]
+    fn test_plain_progress_lines() {
+        let mut printed = None;
+        let mut lines = Vec::new();
+        for done in (0..=1000).step_by(64).chain([1000]) {
+            if let Some(line) = plain_line("write", done, 1000, printed) {
+                printed = Some(done * 100 / 1000);
+                lines.push(line);
+            }
+        }
+        assert_eq!(lines.first().unwrap(), "write: 0% (0/1000)");
+        assert_eq!(lines[1], "write: 12% (128/1000)");
+        assert_eq!(lines.last().unwrap(), "write: 100% (1000/1000)");
+        assert_eq!(lines.len(), 9);
+
+        assert_eq!(plain_line("copy", 5, 0, None), None);
+    }
+
+    #[test]
+    fn test_device_output() {
+        assert_eq!(
+            prefix_lines("ACM0", "Erasing... OK"),
+            "[ACM0] Erasing... OK"
+        );
+        assert_eq!(prefix_lines("ACM0", "a\nb"), "[ACM0] a\n[ACM0] b");
+
+        let bars = MultiProgress::with_draw_target(ProgressDrawTarget::hidden());
+        let (value, data) = for_device("ACM0", &bars, || {
+            report(serde_json::json!({ "bank": 1 }));
+            assert_eq!(device_label().as_deref(), Some("ACM0"));
+            assert!(confirm("Really?").is_err());
+            7
+        });
+        assert_eq!(value, 7);
+        assert_eq!(Value::Object(data), serde_json::json!({ "bank": 1 }));
+        assert_eq!(device_label(), None);
+
+        let event = Event::Phase { phase: "erase" };
+        assert_eq!(
+            serde_json::to_string(&DeviceEvent {
+                event: &event,
+                device: "ACM0"
+            })
+            .unwrap(),
+            r#"{"event":"phase","phase":"erase","device":"ACM0"}"#
+        );
+    }
+
+    #[test]
+    fn test_watch_steps() {
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let record = seen.clone();
+        let watcher: Watcher = Box::new(move |phase, progress| {
+            record.borrow_mut().push((phase.to_string(), progress));
+        });
+        watch_steps(watcher, || {
+            phase("erase");
+            let pb = Progress::bytes("write", 100).unwrap();
+            pb.set_position(40);
+            pb.set_position(100);
+        });
+        // Told of the steps only while watching
+        phase("finalize");
+        assert_eq!(
+            *seen.borrow(),
+            [
+                ("erase".to_string(), None),
+                ("write".to_string(), Some((40, 100))),
+                ("write".to_string(), Some((100, 100))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_device_results() {
+        let data = serde_json::json!({ "bank": 1 })
+            .as_object()
+            .cloned()
+            .unwrap();
+        assert_eq!(
+            device_result("ACM0", &Ok(()), data),
+            serde_json::json!({ "port": "ACM0", "ok": true, "data": { "bank": 1 } })
+        );
+        let err = Failure::new("timeout", "Timeout waiting for response").into();
+        assert_eq!(
+            device_result("ACM1", &Err(err), Map::new()),
+            serde_json::json!({
+                "port": "ACM1",
+                "ok": false,
+                "error": {
+                    "code": "timeout",
+                    "message": "Timeout waiting for response",
+                    "context": {}
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_error_codes_from_kind() {
+        let io =
+            anyhow::Error::new(std::io::Error::other("disk full")).context("Failed to write x");
+        assert_eq!(error_report(&io).code, "io");
+        assert_eq!(error_report(&anyhow!("something")).code, "error");
+    }
+}
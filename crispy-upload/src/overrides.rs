@@ -0,0 +1,274 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Going ahead where a check says not to.
+//!
+//! Each protection below refuses, with an error and a hint, something that
+//! is usually a mistake. Each has a flag of its own that overrides it alone,
+//! and the global `--force` overrides them all:
+//!
+//! | Protection | Refuses | Error | Flag |
+//! |------------|---------|-------|------|
+//! | `wrong_port` | Changing a device on a `--port` that is not the bootloader | `wrong_port` | `--allow-wrong-port` |
+//! | `image_checks` | Uploading an image that fails a pre-flight check | `preflight` | `--skip-checks` |
+//! | `active_bank` | Overwriting the active bank while its firmware is confirmed | `preflight` (`active_bank`) | `--allow-active-bank` |
+//! | `other_device` | Restoring a backup onto another device | `wrong_device` | `--allow-other-device` |
+//! | `provisioned` | Replacing the provisioning record of a unit that has one | `provisioned` | `--reprovision` |
+//!
+//! An override is never silent. The check still runs, and only when it
+//! would have refused does the override take effect: an `Override:` line
+//! names the protection and the flag that let it through, `--json` sends an
+//! `override` event at once and lists it under `overrides` in the `result`
+//! data. A check that passes says nothing, so `--force` on a run that
+//! needed nothing overridden reads as a run without it.
+//!
+//! Every protection is the host's. The bootloader checks what it must
+//! whatever the host says (the size of an image, its CRC, the bank
+//! number); no command carries a flag that makes it accept less.
+
+use anyhow::Result;
+
+use crate::output::{self, outln, Failure};
+
+/// A check that an override goes past.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// The `--port` of a command that changes the device is the bootloader.
+    WrongPort,
+    /// The image passes the pre-flight checks.
+    ImageChecks,
+    /// The active bank is not overwritten while its firmware is confirmed.
+    ActiveBank,
+    /// A backup is restored only onto the device it came from.
+    OtherDevice,
+    /// A unit's provisioning record, once written, is not replaced.
+    Provisioned,
+}
+
+impl Protection {
+    pub const ALL: [Protection; 5] = [
+        Protection::WrongPort,
+        Protection::ImageChecks,
+        Protection::ActiveBank,
+        Protection::OtherDevice,
+        Protection::Provisioned,
+    ];
+
+    /// The name in the `override` event and the `overrides` list.
+    pub fn name(self) -> &'static str {
+        match self {
+            Protection::WrongPort => "wrong_port",
+            Protection::ImageChecks => "image_checks",
+            Protection::ActiveBank => "active_bank",
+            Protection::OtherDevice => "other_device",
+            Protection::Provisioned => "provisioned",
+        }
+    }
+
+    /// The flag that overrides this protection alone.
+    pub fn flag(self) -> &'static str {
+        match self {
+            Protection::WrongPort => "--allow-wrong-port",
+            Protection::ImageChecks => "--skip-checks",
+            Protection::ActiveBank => "--allow-active-bank",
+            Protection::OtherDevice => "--allow-other-device",
+            Protection::Provisioned => "--reprovision",
+        }
+    }
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// The protections to go past, and the flag that asked for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Overrides {
+    allowed: u8,
+    force: bool,
+    quiet: bool,
+}
+
+impl Overrides {
+    /// Nothing overridden.
+    pub const NONE: Overrides = Overrides {
+        allowed: 0,
+        force: false,
+        quiet: false,
+    };
+
+    /// What the global `--force` asks for: every protection.
+    pub fn force(force: bool) -> Self {
+        if !force {
+            return Self::NONE;
+        }
+        Self {
+            allowed: Protection::ALL.iter().map(|p| p.bit()).sum(),
+            force: true,
+            quiet: false,
+        }
+    }
+
+    /// Every protection, without a word: for images that are not firmware
+    /// built for the board, such as a restored bank or a test pattern.
+    pub fn unchecked() -> Self {
+        Self {
+            quiet: true,
+            ..Self::force(true)
+        }
+    }
+
+    /// These overrides and `protection` as well, if its own flag is `set`.
+    pub fn with(mut self, protection: Protection, set: bool) -> Self {
+        if set {
+            self.allowed |= protection.bit();
+        }
+        self
+    }
+
+    pub fn allows(self, protection: Protection) -> bool {
+        self.allowed & protection.bit() != 0
+    }
+
+    /// The flag to name for an override of `protection`: its own if it
+    /// was given, else `--force`.
+    fn flag(self, protection: Protection) -> &'static str {
+        if self.force {
+            "--force"
+        } else {
+            protection.flag()
+        }
+    }
+
+    /// Run `check`, the check for `protection`, and pass on what it says
+    /// unless it refused and the protection is overridden: then say so and
+    /// go on. Quiet overrides do not run it at all.
+    pub fn check(self, protection: Protection, check: impl FnOnce() -> Result<()>) -> Result<()> {
+        if self.quiet && self.allows(protection) {
+            return Ok(());
+        }
+        let Err(err) = check() else {
+            return Ok(());
+        };
+        if !self.allows(protection) {
+            return Err(err);
+        }
+        let reason = refusal(&err);
+        let flag = self.flag(protection);
+        outln!("Override: {} ({})", reason, flag);
+        output::overridden(protection.name(), flag, &reason);
+        Ok(())
+    }
+}
+
+/// What `err` refused, without the advice after its last `; `.
+fn refusal(err: &anyhow::Error) -> String {
+    let message = match err.downcast_ref::<Failure>() {
+        Some(failure) => failure.message.clone(),
+        None => format!("{:#}", err),
+    };
+    match message.rsplit_once("; ") {
+        Some((refusal, _)) => refusal.to_string(),
+        None => message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use anyhow::bail;
+    use clap::Parser;
+
+    fn overrides(args: &[&str]) -> Overrides {
+        let cli = Cli::try_parse_from(args).unwrap();
+        crate::cli::overrides(&cli)
+    }
+
+    fn refused() -> Result<()> {
+        bail!(Failure::new(
+            "wrong_port",
+            "x is not the bootloader; pass --force"
+        ))
+    }
+
+    #[test]
+    fn test_flags() {
+        let none = overrides(&["crispy-upload", "upload", "fw.bin"]);
+        assert_eq!(none, Overrides::NONE);
+
+        let all = overrides(&["crispy-upload", "--force", "upload", "fw.bin"]);
+        for protection in Protection::ALL {
+            assert!(all.allows(protection), "{:?}", protection);
+            assert_eq!(all.flag(protection), "--force");
+        }
+
+        let cases: [(&[&str], Protection); 5] = [
+            (
+                &["crispy-upload", "--allow-wrong-port", "status"],
+                Protection::WrongPort,
+            ),
+            (
+                &["crispy-upload", "upload", "fw.bin", "--skip-checks"],
+                Protection::ImageChecks,
+            ),
+            (
+                &["crispy-upload", "upload", "fw.bin", "--allow-active-bank"],
+                Protection::ActiveBank,
+            ),
+            (
+                &["crispy-upload", "restore", "b.tar", "--allow-other-device"],
+                Protection::OtherDevice,
+            ),
+            (
+                &[
+                    "crispy-upload",
+                    "provision",
+                    "--board",
+                    "2",
+                    "--serial",
+                    "A1",
+                    "--reprovision",
+                ],
+                Protection::Provisioned,
+            ),
+        ];
+        for (args, protection) in cases {
+            let overrides = overrides(args);
+            for other in Protection::ALL {
+                assert_eq!(overrides.allows(other), other == protection, "{:?}", args);
+            }
+            assert_eq!(overrides.flag(protection), protection.flag());
+            assert!(args.contains(&protection.flag()));
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let none = Overrides::NONE;
+        assert!(none.check(Protection::WrongPort, || Ok(())).is_ok());
+        assert!(none.check(Protection::WrongPort, refused).is_err());
+
+        let one = none.with(Protection::ActiveBank, true);
+        assert!(one.check(Protection::WrongPort, refused).is_err());
+        assert!(one.check(Protection::ActiveBank, refused).is_ok());
+        assert!(Overrides::force(true)
+            .check(Protection::WrongPort, refused)
+            .is_ok());
+        let unchecked = Overrides::unchecked();
+        let ran = std::cell::Cell::new(false);
+        let check = || {
+            ran.set(true);
+            refused()
+        };
+        assert!(unchecked.check(Protection::ImageChecks, check).is_ok());
+        assert!(!ran.get());
+    }
+
+    #[test]
+    fn test_refusal() {
+        assert_eq!(refusal(&refused().unwrap_err()), "x is not the bootloader");
+        let err = anyhow::anyhow!("no advice");
+        assert_eq!(refusal(&err), "no advice");
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! One command on several devices at once, for a repeated `--port` or
+//! `--all`.
+//!
+//! Each device gets a thread with its own connection and its own output
+//! (see `output`): lines start with `[port]`, bars are drawn together, and
+//! what the command reports is kept apart. A device that fails does not
+//! stop the others. Once all are done a table lists how each went, and the
+//! run fails if any device did.
+
+use std::thread;
+
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+
+use crate::output::{self, outln, Failure};
+
+/// How the command went on one device.
+struct Outcome<'a> {
+    port: &'a str,
+    result: Result<()>,
+    data: Map<String, Value>,
+}
+
+/// Run `run` on every port in `ports` at once, then sum up.
+pub fn run_all<F>(ports: &[String], run: F) -> Result<()>
+where
+    F: Fn(&str) -> Result<()> + Sync,
+{
+    outln!("Running on {} devices: {}", ports.len(), ports.join(", "));
+    let bars = output::device_bars();
+    let outcomes: Vec<_> = thread::scope(|scope| {
+        let threads: Vec<_> = ports
+            .iter()
+            .map(|port| {
+                let (run, bars) = (&run, &bars);
+                scope.spawn(move || output::for_device(port, bars, || run(port)))
+            })
+            .collect();
+        threads
+            .into_iter()
+            .zip(ports)
+            .map(|(thread, port)| {
+                let (result, data) = thread.join().unwrap_or_else(|_| {
+                    (Err(anyhow!("the thread for {} panicked", port)), Map::new())
+                });
+                Outcome { port, result, data }
+            })
+            .collect()
+    });
+    let _ = bars.clear();
+    summarize(outcomes)
+}
+
+/// Print the table of outcomes and report them, failing if any device did.
+fn summarize(outcomes: Vec<Outcome>) -> Result<()> {
+    outln!();
+    for line in table(&outcomes) {
+        outln!("{}", line);
+    }
+    let failed = outcomes.iter().filter(|o| o.result.is_err()).count();
+    let total = outcomes.len();
+    let devices: Vec<_> = outcomes
+        .into_iter()
+        .map(|o| output::device_result(o.port, &o.result, o.data))
+        .collect();
+    if failed > 0 {
+        bail!(
+            Failure::new("devices", format!("{} of {} devices failed", failed, total))
+                .with("failed", failed)
+                .with("devices", devices)
+        );
+    }
+    outln!("All {} devices OK", total);
+    output::report(json!({ "devices": devices }));
+    Ok(())
+}
+
+/// `PORT  RESULT` lines, with the error of each device that failed.
+fn table(outcomes: &[Outcome]) -> Vec<String> {
+    let width = outcomes
+        .iter()
+        .map(|o| o.port.len())
+        .chain(["PORT".len()])
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!("{:<width$}  RESULT", "PORT")];
+    for outcome in outcomes {
+        let result = match &outcome.result {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("failed: {:#}", err),
+        };
+        lines.push(format!("{:<width$}  {}", outcome.port, result));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let outcomes = [
+            Outcome {
+                port: "/dev/ttyACM0",
+                result: Ok(()),
+                data: Map::new(),
+            },
+            Outcome {
+                port: "tcp://x:1",
+                result: Err(anyhow!("Timeout waiting for response")),
+                data: Map::new(),
+            },
+        ];
+        assert_eq!(
+            table(&outcomes),
+            [
+                "PORT          RESULT",
+                "/dev/ttyACM0  ok",
+                "tcp://x:1     failed: Timeout waiting for response",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_one_failure_does_not_stop_the_others() {
+        let ports = ["a".to_string(), "b".to_string(), "c".to_string()];
+        let err = run_all(&ports, |port| {
+            output::report(json!({ "seen": port }));
+            if port == "b" {
+                bail!(Failure::new("timeout", "Timeout waiting for response"));
+            }
+            Ok(())
+        })
+        .unwrap_err();
+
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "devices");
+        assert_eq!(failure.context["failed"], 1);
+        let devices = failure.context["devices"].as_array().unwrap();
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[0]["data"]["seen"], "a");
+        assert_eq!(devices[1]["error"]["code"], "timeout");
+        assert_eq!(devices[2]["ok"], true);
+    }
+}
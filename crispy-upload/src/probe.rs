@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Telling what is on a port, for `probe` and before commands that change
+//! the device.
+//!
+//! A USB port with crispy IDs settles it: the bootloader and the sample
+//! firmware enumerate with product IDs of their own. Otherwise (a TCP
+//! bridge, a port without USB IDs, another VID:PID) the port is asked: a
+//! `GetStatus` with a short timeout, which only the bootloader answers with
+//! a status, then the firmware console's `status`. Neither changes
+//! anything. The protocol query ends with a lone frame delimiter and a
+//! drain, so whatever answered is left without half a frame in its decoder.
+
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use serialport::UsbPortInfo;
+
+use crispy_common::protocol::{Command, Response};
+use crispy_common::FwVersion;
+
+use crate::boot_watch;
+use crate::link::LinkOptions;
+use crate::output::Failure;
+use crate::overrides::{Overrides, Protection};
+use crate::transport::{self, DeviceKind, Transport};
+
+/// How long the probe waits for each answer.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// What a port turned out to be.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// The bootloader, with its version if it reports one.
+    Bootloader(Option<FwVersion>),
+    Firmware,
+    Unknown,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Bootloader(Some(version)) => write!(f, "crispy bootloader v{}", version),
+            Verdict::Bootloader(None) => write!(f, "crispy bootloader"),
+            Verdict::Firmware => write!(f, "crispy sample firmware"),
+            Verdict::Unknown => write!(f, "unknown device"),
+        }
+    }
+}
+
+/// What [`probe`] found on a port, and how.
+pub struct Probe {
+    pub verdict: Verdict,
+    /// `usb` (the USB IDs), `protocol` (a `GetStatus` answer), `console`
+    /// (the firmware's `status`) or `none`.
+    pub by: &'static str,
+    pub usb: Option<UsbPortInfo>,
+}
+
+/// Find out what is on `port`.
+pub fn probe(port: &str, link: &LinkOptions) -> Probe {
+    let usb = transport::list_ports()
+        .ok()
+        .and_then(|ports| ports.into_iter().find(|p| p.name == port))
+        .and_then(|p| p.usb.map(|usb| (usb, p.kind)));
+    let (usb, kind) = match usb {
+        Some((usb, kind)) => (Some(usb), kind),
+        None => (None, None),
+    };
+    let found = |verdict, by| Probe { verdict, by, usb };
+    let opts = LinkOptions {
+        read_timeout: PROBE_TIMEOUT,
+        wait: Duration::ZERO,
+        ..*link
+    };
+
+    match kind {
+        Some(DeviceKind::Firmware) => found(Verdict::Firmware, "usb"),
+        // Still asked, for its version; a protocol port that does not
+        // answer is the bootloader all the same
+        Some(DeviceKind::Bootloader) => {
+            let version = ask_bootloader(port, &opts).flatten();
+            found(Verdict::Bootloader(version), "usb")
+        }
+        None => match ask_bootloader(port, &opts) {
+            Some(version) => found(Verdict::Bootloader(version), "protocol"),
+            None if boot_watch::console_answers(port, &opts) => found(Verdict::Firmware, "console"),
+            None => found(Verdict::Unknown, "none"),
+        },
+    }
+}
+
+/// Ask `port` for a status: `None` unless the bootloader answers, then its
+/// version if it reports one.
+fn ask_bootloader(port: &str, opts: &LinkOptions) -> Option<Option<FwVersion>> {
+    let mut transport = Transport::open(port, opts).ok()?;
+    let answer = match transport.send_recv(&Command::GetStatus) {
+        Ok(Response::Status { .. }) => match transport.send_recv(&Command::GetDeviceInfo) {
+            Ok(Response::DeviceInfo {
+                bootloader_version, ..
+            }) => Some(Some(bootloader_version)),
+            _ => Some(None),
+        },
+        _ => None,
+    };
+    let _ = transport.resync();
+    answer
+}
+
+/// Refuse to change the device on `port` unless it is the bootloader, or
+/// `overrides` say to go ahead anyway.
+pub fn check_bootloader(port: &str, link: &LinkOptions, overrides: Overrides) -> Result<()> {
+    overrides.check(Protection::WrongPort, || is_bootloader(port, link))
+}
+
+fn is_bootloader(port: &str, link: &LinkOptions) -> Result<()> {
+    let found = probe(port, link);
+    if let Verdict::Bootloader(_) = found.verdict {
+        return Ok(());
+    }
+    let hint = match found.verdict {
+        Verdict::Firmware => "use `update`, or `monitor` and ~b to enter the bootloader",
+        _ => "see `list-ports` for the bootloader's port",
+    };
+    bail!(Failure::new(
+        "wrong_port",
+        format!(
+            "{} is not the bootloader ({}); {}, or pass --allow-wrong-port",
+            port, found.verdict, hint
+        )
+    )
+    .with("port", port)
+    .with("verdict", found.verdict.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_device::TestDevice;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_verdict_names() {
+        let version = FwVersion::new(0, 3, 0);
+        assert_eq!(
+            Verdict::Bootloader(Some(version)).to_string(),
+            "crispy bootloader v0.3.0"
+        );
+        assert_eq!(Verdict::Firmware.to_string(), "crispy sample firmware");
+        assert_eq!(Verdict::Unknown.to_string(), "unknown device");
+    }
+
+    #[test]
+    fn test_probe_bootloader_over_tcp() {
+        let device = TestDevice::start();
+        let found = probe(&device.port, &LinkOptions::DEFAULT);
+        // The test device does not report its version
+        assert_eq!(found.verdict, Verdict::Bootloader(None));
+        assert_eq!(found.by, "protocol");
+        device.finish();
+    }
+
+    #[test]
+    fn test_silent_port_is_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            // Accept the protocol and the console probes, answer neither
+            let mut streams = Vec::new();
+            for _ in 0..2 {
+                streams.push(listener.accept().unwrap().0);
+            }
+        });
+
+        let err = check_bootloader(&port, &LinkOptions::DEFAULT, Overrides::NONE).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "wrong_port");
+        assert_eq!(failure.context["verdict"], "unknown device");
+        server.join().unwrap();
+    }
+}
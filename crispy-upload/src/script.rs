@@ -0,0 +1,199 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Scripts for `run`: a sequence of subcommands for one device.
+//!
+//! One subcommand per line, written as on the command line without
+//! `crispy-upload` and the global options:
+//!
+//! ```text
+//! # Factory provisioning
+//! wipe
+//! upload ${FW} --bank 0 --version ${VERSION}
+//! upload ${FW} --bank 1 --version ${VERSION}
+//! set-bank 0
+//! reboot
+//! ```
+//!
+//! Words are split on whitespace; single or double quotes keep spaces in a
+//! word. `#` starts a comment at the beginning of a word. `${NAME}` is
+//! replaced by the value given with `--var NAME=VALUE`, after splitting, so
+//! a value with spaces stays one word. An undefined variable is an error.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+
+use crate::output::Failure;
+
+/// One step of a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    /// 1-based line number in the script.
+    pub number: usize,
+    /// The subcommand and its arguments, variables substituted.
+    pub words: Vec<String>,
+}
+
+impl Line {
+    /// The step as it will run, for messages.
+    pub fn text(&self) -> String {
+        self.words.join(" ")
+    }
+}
+
+/// Parse `--var NAME=VALUE` arguments.
+pub fn parse_vars(vars: &[String]) -> Result<HashMap<String, String>> {
+    let mut map = HashMap::new();
+    for var in vars {
+        match var.split_once('=') {
+            Some((name, value)) if is_name(name) => {
+                map.insert(name.to_string(), value.to_string());
+            }
+            _ => bail!(Failure::new(
+                "invalid_argument",
+                format!("--var {}: expected NAME=VALUE", var)
+            )),
+        }
+    }
+    Ok(map)
+}
+
+/// Split a script into steps, skipping blank lines and comments.
+pub fn parse(text: &str, vars: &HashMap<String, String>) -> Result<Vec<Line>> {
+    let mut lines = Vec::new();
+    for (index, raw) in text.lines().enumerate() {
+        let number = index + 1;
+        let words = split(raw)
+            .and_then(|words| {
+                words
+                    .iter()
+                    .map(|word| substitute(word, vars))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(|problem| error(number, problem))?;
+        if !words.is_empty() {
+            lines.push(Line { number, words });
+        }
+    }
+    Ok(lines)
+}
+
+/// A problem with line `number` of the script.
+pub fn error(number: usize, problem: impl std::fmt::Display) -> Failure {
+    Failure::new("script", format!("Script line {}: {}", number, problem)).with("line", number)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Split a line into words, honouring quotes and dropping a comment.
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '#') if word.is_none() => break,
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(q) = quote {
+        return Err(format!("unterminated {} quote", q));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Replace every `${NAME}` in `word`.
+fn substitute(word: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = word;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start + 2..].find('}') else {
+            return Err(format!("unterminated variable in {}", word));
+        };
+        let name = &rest[start + 2..start + 2 + len];
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => return Err(format!("${{{}}} is not defined (--var {}=...)", name, name)),
+        }
+        rest = &rest[start + 3 + len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let text = "# provisioning\n\
+                    \n\
+                    wipe\n\
+                    upload ${FW} --bank 0 --version ${VERSION}  # bank A\n\
+                    upload \"my dir/fw b.bin\" --bank 1\n";
+        let lines = parse(
+            text,
+            &vars(&[("FW", "build/fw 1.bin"), ("VERSION", "1.2.3")]),
+        )
+        .unwrap();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].number, 3);
+        assert_eq!(lines[0].words, ["wipe"]);
+        assert_eq!(
+            lines[1].words,
+            [
+                "upload",
+                "build/fw 1.bin",
+                "--bank",
+                "0",
+                "--version",
+                "1.2.3"
+            ]
+        );
+        assert_eq!(lines[2].number, 5);
+        assert_eq!(lines[2].words[1], "my dir/fw b.bin");
+    }
+
+    #[test]
+    fn test_parse_script_errors() {
+        let err = parse("wipe\nupload ${FW}\n", &HashMap::new()).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "script");
+        assert_eq!(failure.context["line"], 2);
+        assert!(failure.message.contains("${FW} is not defined"));
+
+        assert!(parse("upload 'fw.bin\n", &HashMap::new()).is_err());
+        assert!(parse("upload ${FW\n", &vars(&[("FW", "x")])).is_err());
+    }
+
+    #[test]
+    fn test_parse_vars() {
+        let map = parse_vars(&["FW=a=b.bin".to_string(), "V=".to_string()]).unwrap();
+        assert_eq!(map["FW"], "a=b.bin");
+        assert_eq!(map["V"], "");
+
+        assert!(parse_vars(&["FW".to_string()]).is_err());
+        assert!(parse_vars(&["A-B=1".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,636 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `selftest`: one pass over the whole stack on a dev board, before a
+//! release or after a protocol change.
+//!
+//! | Phase | Does |
+//! |-------|------|
+//! | `status` | record the status and BootData |
+//! | `backup` | read the inactive bank to a file; the active one too when the inactive bank is empty |
+//! | `upload` | upload a pseudorandom image to the inactive bank and read it back |
+//! | `set-bank` | make that bank active |
+//! | `reboot` | reboot and wait for the device to come back |
+//! | `rollback` | check that the image was not booted: the bootloader fell back to the original bank, or stayed in update mode when there was none |
+//! | `restore` | put the banks and BootData back as they were |
+//!
+//! The image is random but for its first word, a zero stack pointer, which
+//! no bootloader check accepts; `--seed` gives the same image again. When
+//! the bootloader falls back to the firmware, the firmware console is told
+//! to enter the bootloader again, as `update` does.
+//!
+//! Once the backup is taken, the restore runs whatever happens: [`Restore`]
+//! holds the device and restores it when dropped, after an error or a panic
+//! as well. An inactive bank that was empty can only be emptied again by
+//! wiping BootData, so then the active bank is rewritten from its backup
+//! too. The `confirmed` flag cannot be set from the host; the firmware sets
+//! it again the next time it boots. A restore that fails keeps the backup
+//! files and says where they are.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::json;
+
+use crispy_common::protocol::{BootData, Command, Response};
+use crispy_common::{FwVersion, FW_BANK_SIZE};
+
+use crate::boot_watch::{self, Reappeared, WatchOptions};
+use crate::commands::{self, InputOptions, UploadOptions, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_GAP};
+use crate::link::{self, LinkOptions};
+use crate::output::{self, out, outln, Failure};
+use crate::overrides::Overrides;
+use crate::transport::{RetryPolicy, Transport};
+
+/// Bytes of random image unless `--size` says otherwise.
+pub const DEFAULT_SIZE: u32 = 16 * 1024;
+
+/// Every phase, in the order they run.
+const PHASES: [&str; 7] = [
+    "status", "backup", "upload", "set-bank", "reboot", "rollback", "restore",
+];
+
+/// How to run the self-test.
+pub struct SelftestOptions {
+    /// Bytes of random image.
+    pub size: u32,
+    pub seed: u64,
+    pub watch: WatchOptions,
+}
+
+/// A seed for `--seed` when none is given.
+pub fn seed_from_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|t| t.as_nanos() as u64)
+        .unwrap_or(1)
+}
+
+/// How a phase went.
+#[derive(Clone, Debug, Serialize)]
+struct PhaseRecord {
+    phase: &'static str,
+    ok: bool,
+    ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct Phases(Vec<PhaseRecord>);
+
+impl Phases {
+    /// Run phase `name`, timing it; `f` gives its result and a note for
+    /// the summary.
+    fn run<T>(
+        &mut self,
+        name: &'static str,
+        f: impl FnOnce() -> Result<(T, Option<String>)>,
+    ) -> Result<T> {
+        let number = PHASES.iter().position(|p| *p == name).unwrap_or(0) + 1;
+        outln!("[{}/{}] {}", number, PHASES.len(), name);
+        output::phase(name);
+        let started = Instant::now();
+        let result = f();
+        let ms = started.elapsed().as_millis() as u64;
+        let (result, note, error) = match result {
+            Ok((value, note)) => (Ok(value), note, None),
+            Err(err) => {
+                let error = format!("{:#}", err);
+                (Err(err), None, Some(error))
+            }
+        };
+        if let Some(note) = &note {
+            outln!("  {}", note);
+        }
+        outln!();
+        self.0.push(PhaseRecord {
+            phase: name,
+            ok: result.is_ok(),
+            ms,
+            note,
+            error,
+        });
+        result
+    }
+}
+
+/// Run the self-test on the bootloader at `port`, open on `transport`.
+pub fn run(transport: Transport, port: &str, opts: &SelftestOptions) -> Result<()> {
+    let mut phases = Phases::default();
+    outln!(
+        "Self-test on {}: {} bytes of random image, seed {}",
+        port,
+        opts.size,
+        opts.seed
+    );
+    outln!();
+    let result = test(transport, port, opts, &mut phases);
+    summarize(&phases.0, opts, result)
+}
+
+fn test(
+    mut transport: Transport,
+    port: &str,
+    opts: &SelftestOptions,
+    phases: &mut Phases,
+) -> Result<()> {
+    let before = phases.run("status", || {
+        let bd = commands::stored_boot_data(&mut transport)?;
+        let note = format!(
+            "Active bank {}, bank A {} bytes (v{}), bank B {} bytes (v{})",
+            bd.active_bank, bd.size_a, bd.version_a, bd.size_b, bd.version_b
+        );
+        Ok((bd, Some(note)))
+    })?;
+    let backups = phases.run("backup", || {
+        let backups = backup(&mut transport, port, &before, opts.seed)?;
+        let note = match backups.len() {
+            0 => "Both banks are empty; nothing to keep".to_string(),
+            _ => format!(
+                "Kept {}",
+                backups
+                    .iter()
+                    .map(|b| b.file.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        Ok((backups, Some(note)))
+    })?;
+
+    // Nothing was changed until now; from here on the guard puts it back
+    let mut guard = Restore {
+        port: port.to_string(),
+        link: transport.link_options(),
+        retry: transport.retry_policy(),
+        transport: Some(transport),
+        before,
+        backups,
+        tried: false,
+    };
+    let tested = exercise(&mut guard, opts, phases);
+    let restored = phases.run("restore", || guard.restore().map(|note| ((), note)));
+    tested.and(restored)
+}
+
+/// The phases that change the device.
+fn exercise(guard: &mut Restore, opts: &SelftestOptions, phases: &mut Phases) -> Result<()> {
+    let bank = 1 - guard.before.active_bank;
+    let file = temp_file(&guard.port, opts.seed, "image");
+    fs::write(&file, random_image(opts.size, opts.seed))
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+    let uploaded = phases.run("upload", || {
+        let upload_opts = upload_options(Some(FwVersion::from_raw(0)));
+        commands::upload(guard.transport()?, &file, Some(bank), &upload_opts)?;
+        Ok(((), None))
+    });
+    let _ = fs::remove_file(&file);
+    uploaded?;
+
+    phases.run("set-bank", || {
+        commands::set_bank(guard.transport()?, bank)?;
+        Ok(((), None))
+    })?;
+    let fell_back = phases.run("reboot", || {
+        let fell_back = guard.reboot(&opts.watch)?;
+        Ok((fell_back, None))
+    })?;
+    phases.run("rollback", || {
+        let note = guard.check_rollback(fell_back)?;
+        Ok(((), Some(note)))
+    })
+}
+
+/// A bank's firmware as it was, to write back.
+struct Backup {
+    bank: u8,
+    file: PathBuf,
+    version: FwVersion,
+}
+
+/// Read the banks that have to be written back: the inactive one, and the
+/// active one as well when the inactive bank is empty, since emptying it
+/// again means wiping BootData.
+fn backup(
+    transport: &mut Transport,
+    port: &str,
+    before: &BootData,
+    seed: u64,
+) -> Result<Vec<Backup>> {
+    let active = before.active_bank;
+    let inactive = 1 - active;
+    let holds = |bank| bank_record(before, bank).0 > 0;
+    let banks = match holds(inactive) {
+        true => vec![inactive],
+        false => [active].into_iter().filter(|&bank| holds(bank)).collect(),
+    };
+
+    let mut backups = Vec::new();
+    for bank in banks {
+        let file = temp_file(port, seed, &format!("bank-{}", bank));
+        commands::download(transport, bank, &file, None, DEFAULT_CHUNK_SIZE)?;
+        backups.push(Backup {
+            bank,
+            file,
+            version: bank_record(before, bank).1,
+        });
+    }
+    Ok(backups)
+}
+
+/// The device while it is being tested, restored when dropped.
+struct Restore {
+    /// Closed while the device reboots.
+    transport: Option<Transport>,
+    /// The bootloader's port, which may change across the reboot.
+    port: String,
+    link: LinkOptions,
+    retry: RetryPolicy,
+    before: BootData,
+    backups: Vec<Backup>,
+    /// The restore was started, so dropping does not start it again.
+    tried: bool,
+}
+
+impl Restore {
+    /// The connection to the bootloader, opened again after a reboot.
+    fn transport(&mut self) -> Result<&mut Transport> {
+        if self.transport.is_none() {
+            let mut transport = Transport::open(&self.port, &self.link)?;
+            transport.set_retry_policy(self.retry);
+            self.transport = Some(transport);
+        }
+        Ok(self.transport.as_mut().expect("just opened"))
+    }
+
+    /// Reboot and wait for the device, then get back to the bootloader if
+    /// the firmware came up. True when the bootloader fell back to it.
+    fn reboot(&mut self, opts: &WatchOptions) -> Result<bool> {
+        let tcp = link::tcp_address(&self.port).is_some();
+        let before = if tcp {
+            Vec::new()
+        } else {
+            boot_watch::ports_besides(&self.port)?
+        };
+        commands::reboot(self.transport()?)?;
+        // Let go of the port so the device can re-enumerate under the same name
+        self.transport = None;
+
+        out!("Waiting for the device to come back... ");
+        let reappeared = if tcp {
+            boot_watch::wait_for_reboot_tcp(&self.port, &self.link, opts)?
+        } else {
+            boot_watch::wait_for_reboot(&self.port, &before, opts)?
+        };
+        let firmware = match reappeared {
+            Reappeared::Bootloader(name) => {
+                outln!("bootloader on {}", name);
+                self.port = name;
+                return Ok(false);
+            }
+            Reappeared::Firmware(name) => name,
+        };
+        outln!("firmware on {}", firmware);
+        out!("Entering the bootloader... ");
+        if tcp {
+            boot_watch::request_bootloader_tcp(&firmware, &self.link, opts.timeout)?;
+            self.port = firmware;
+        } else {
+            let before = boot_watch::ports_besides(&firmware)?;
+            boot_watch::request_bootloader(&firmware, &self.link, opts.timeout)?;
+            self.port = boot_watch::wait_for_bootloader(&before, opts)?;
+        }
+        outln!("bootloader on {}", self.port);
+        Ok(true)
+    }
+
+    /// Check that the random image was not booted.
+    fn check_rollback(&mut self, fell_back: bool) -> Result<String> {
+        let original = self.before.active_bank;
+        let had_firmware = bank_record(&self.before, original).0 > 0;
+        let active = match self.transport()?.send_recv(&Command::GetStatus)? {
+            Response::Status { active_bank, .. } => active_bank,
+            other => bail!("Unexpected response to GetStatus: {:?}", other),
+        };
+        match (had_firmware, fell_back) {
+            (true, true) if active == original => Ok(format!(
+                "The bootloader fell back to bank {} and booted its firmware",
+                original
+            )),
+            (true, true) => bail!(
+                "The firmware came up, but bank {} is active rather than bank {}",
+                active,
+                original
+            ),
+            (true, false) => bail!(
+                "The bootloader stayed in update mode instead of falling back to bank {}",
+                original
+            ),
+            (false, true) => bail!("A firmware came up, but no bank held any before the test"),
+            (false, false) => {
+                Ok("No bank could boot; the bootloader stayed in update mode".to_string())
+            }
+        }
+    }
+
+    /// Write the banks back and check BootData against the record taken
+    /// before the test.
+    fn restore(&mut self) -> Result<Option<String>> {
+        self.tried = true;
+        let result = self.write_back();
+        if result.is_ok() {
+            for backup in &self.backups {
+                let _ = fs::remove_file(&backup.file);
+            }
+        }
+        result.with_context(|| {
+            let files: Vec<_> = self
+                .backups
+                .iter()
+                .map(|b| format!("bank {} in {} (v{})", b.bank, b.file.display(), b.version))
+                .collect();
+            match files.is_empty() {
+                true => "The device was not restored".to_string(),
+                false => format!("The device was not restored; backups: {}", files.join(", ")),
+            }
+        })
+    }
+
+    fn write_back(&mut self) -> Result<Option<String>> {
+        let before = self.before;
+        let inactive = 1 - before.active_bank;
+        let wipe = bank_record(&before, inactive).0 == 0;
+        self.transport()?;
+        let transport = self.transport.as_mut().expect("just opened");
+        if wipe {
+            commands::wipe(transport)?;
+        }
+        for backup in &self.backups {
+            let upload_opts = upload_options(Some(backup.version));
+            commands::upload(transport, &backup.file, Some(backup.bank), &upload_opts)?;
+        }
+        let now = commands::stored_boot_data(transport)?;
+        if now.active_bank != before.active_bank && bank_record(&before, before.active_bank).0 > 0 {
+            commands::set_bank(transport, before.active_bank)?;
+        }
+        if wipe && before.max_boot_attempts != 0 {
+            commands::set_boot_attempts(transport, before.max_boot_attempts)?;
+        }
+
+        let after = commands::stored_boot_data(transport)?;
+        let differing = differences(&before, &after);
+        if !differing.is_empty() {
+            bail!(
+                "BootData differs from before the test: {}",
+                differing.join(", ")
+            );
+        }
+        Ok(Some(match before.confirmed {
+            0 => "Restored".to_string(),
+            _ => "Restored; the firmware confirms its boot again when it next starts".to_string(),
+        }))
+    }
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        if self.tried {
+            return;
+        }
+        outln!("Restoring the device after the self-test stopped...");
+        if let Err(err) = self.restore() {
+            outln!("Error: {:#}", err);
+        }
+    }
+}
+
+/// Size and version BootData records for `bank`.
+fn bank_record(bd: &BootData, bank: u8) -> (u32, FwVersion) {
+    match bank {
+        0 => (bd.size_a, bd.version_a),
+        _ => (bd.size_b, bd.version_b),
+    }
+}
+
+/// BootData fields the restore has to bring back, named where they differ.
+/// The `confirmed` flag and the boot attempts are the firmware's to set.
+fn differences(before: &BootData, after: &BootData) -> Vec<&'static str> {
+    let fields = [
+        ("active_bank", before.active_bank == after.active_bank),
+        ("size_a", before.size_a == after.size_a),
+        ("size_b", before.size_b == after.size_b),
+        ("crc_a", before.crc_a == after.crc_a),
+        ("crc_b", before.crc_b == after.crc_b),
+        ("version_a", before.version_a == after.version_a),
+        ("version_b", before.version_b == after.version_b),
+        (
+            "max_boot_attempts",
+            before.max_boot_attempts == after.max_boot_attempts,
+        ),
+    ];
+    fields
+        .into_iter()
+        .filter(|(_, same)| !same)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+fn upload_options(version: Option<FwVersion>) -> UploadOptions {
+    UploadOptions {
+        version,
+        chunk_size: DEFAULT_CHUNK_SIZE,
+        verify: true,
+        input: InputOptions {
+            any_family: false,
+            max_gap: DEFAULT_MAX_GAP,
+            sha256: None,
+            bank_size: FW_BANK_SIZE,
+        },
+        // The random image is meant to fail them, the backups came from
+        // the device
+        overrides: Overrides::unchecked(),
+        always: true,
+    }
+}
+
+/// A file of the self-test's own in the temp directory, apart from those
+/// of a run on another device at the same time.
+fn temp_file(port: &str, seed: u64, name: &str) -> PathBuf {
+    let port: String = port
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let file = format!("crispy-selftest-{}-{}-{}.bin", port, seed, name);
+    std::env::temp_dir().join(file)
+}
+
+/// `size` bytes from xorshift64 seeded with `seed`, with a zero first word
+/// so no bootloader takes it for a vector table.
+fn random_image(size: u32, seed: u64) -> Vec<u8> {
+    let mut state = seed.max(1);
+    let mut image: Vec<u8> = (0..size.div_ceil(8))
+        .flat_map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state.to_le_bytes()
+        })
+        .collect();
+    image.truncate(size as usize);
+    let head = image.len().min(4);
+    image[..head].fill(0);
+    image
+}
+
+/// Print the table of phases and report them, failing with `selftest`.
+fn summarize(phases: &[PhaseRecord], opts: &SelftestOptions, result: Result<()>) -> Result<()> {
+    for line in table(phases) {
+        outln!("{}", line);
+    }
+    let records = serde_json::to_value(phases).expect("records serialize");
+    let Err(err) = result else {
+        outln!("PASS");
+        output::report(json!({
+            "size": opts.size,
+            "seed": opts.seed,
+            "phases": records,
+        }));
+        return Ok(());
+    };
+    outln!("FAIL");
+    let failed = phases.iter().find(|p| !p.ok).map(|p| p.phase);
+    bail!(Failure::new(
+        "selftest",
+        format!(
+            "Self-test failed{}: {:#}; --seed {} repeats the same image",
+            failed.map(|p| format!(" at {}", p)).unwrap_or_default(),
+            err,
+            opts.seed
+        )
+    )
+    .with("phase", failed)
+    .with("seed", opts.seed)
+    .with("phases", records))
+}
+
+/// `PHASE  RESULT  TIME` lines, with each phase's note or error.
+fn table(phases: &[PhaseRecord]) -> Vec<String> {
+    let mut lines = vec![format!("{:<8}  {:<6}  {:>7}", "PHASE", "RESULT", "TIME")];
+    for phase in phases {
+        let result = if phase.ok { "ok" } else { "failed" };
+        let mut line = format!(
+            "{:<8}  {:<6}  {:>5.1} s",
+            phase.phase,
+            result,
+            phase.ms as f64 / 1000.0
+        );
+        if let Some(error) = &phase.error {
+            line.push_str(&format!("  {}", error));
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_device::TestDevice;
+    use crispy_common::crc32;
+    use crispy_common::flash::read_boot_data;
+    use std::time::Duration;
+
+    #[test]
+    fn test_random_image() {
+        let image = random_image(1001, 7);
+        assert_eq!(image.len(), 1001);
+        assert_eq!(image[..4], [0, 0, 0, 0]);
+        assert_eq!(image, random_image(1001, 7));
+        assert_ne!(image, random_image(1001, 8));
+        // Random enough that the upload does not skip it as erased
+        assert!(image.chunks(256).all(|c| c.iter().any(|&b| b != 0xFF)));
+    }
+
+    #[test]
+    fn test_table() {
+        let phases = [
+            PhaseRecord {
+                phase: "status",
+                ok: true,
+                ms: 120,
+                note: Some("Active bank 0".to_string()),
+                error: None,
+            },
+            PhaseRecord {
+                phase: "reboot",
+                ok: false,
+                ms: 10_000,
+                note: None,
+                error: Some("Reboot refused: BadCommand".to_string()),
+            },
+        ];
+        assert_eq!(
+            table(&phases),
+            [
+                "PHASE     RESULT     TIME",
+                "status    ok        0.1 s",
+                "reboot    failed   10.0 s  Reboot refused: BadCommand",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failed_reboot_still_restores() {
+        let device = TestDevice::start();
+        let mut transport = Transport::open(&device.port, &LinkOptions::DEFAULT).unwrap();
+        // Bank A holds firmware, bank B is empty: the restore has to wipe
+        // and write bank A back
+        let mut firmware = vec![0u8; 3000];
+        firmware[0..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+        firmware[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+        let file = temp_file(&device.port, 0, "firmware");
+        fs::write(&file, &firmware).unwrap();
+        let version = Some(FwVersion::new(1, 2, 3));
+        commands::upload(&mut transport, &file, Some(0), &upload_options(version)).unwrap();
+        fs::remove_file(&file).unwrap();
+        commands::set_boot_attempts(&mut transport, 5).unwrap();
+
+        let opts = SelftestOptions {
+            size: 4096,
+            seed: 11,
+            watch: WatchOptions {
+                firmware: boot_watch::UsbId::FIRMWARE,
+                bootloader: boot_watch::UsbId::BOOTLOADER,
+                timeout: Duration::from_secs(1),
+                expect_confirm: false,
+                confirm_timeout: Duration::from_secs(1),
+            },
+        };
+        // The test device refuses to reboot
+        let err = run(transport, &device.port, &opts).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "selftest");
+        assert_eq!(failure.context["phase"], "reboot");
+        let phases = failure.context["phases"].as_array().unwrap();
+        let names: Vec<_> = phases.iter().map(|p| p["phase"].clone()).collect();
+        assert_eq!(
+            names,
+            ["status", "backup", "upload", "set-bank", "reboot", "restore"]
+        );
+        assert_eq!(phases[5]["ok"], true);
+
+        let bd = read_boot_data(&device.finish()).unwrap();
+        assert_eq!(bd.active_bank, 0);
+        assert_eq!(bd.size_a, firmware.len() as u32);
+        assert_eq!(bd.crc_a, crc32::checksum(&firmware));
+        assert_eq!(bd.version_a, FwVersion::new(1, 2, 3));
+        assert_eq!(bd.size_b, 0);
+        assert_eq!(bd.max_boot_attempts, 5);
+    }
+}
@@ -0,0 +1,631 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `serve`: updates over HTTP, for devices on headless gateways.
+//!
+//! A small REST API for the bootloader on one port:
+//!
+//! | Request | Does |
+//! |---------|------|
+//! | `GET /status` | `status`, or `info` with `?info` |
+//! | `POST /upload` | `upload` the image in the request (see below) |
+//! | `POST /reboot` | `reboot` |
+//! | `GET /progress` | The operation in flight: its name, step, `done` of `total` and seconds so far, and the answer to the last one |
+//!
+//! The image of `POST /upload` is the `multipart/form-data` part named
+//! `file`, or the whole body when it is not multipart. The options are form
+//! fields or query parameters: `bank` (0 or 1, default the inactive bank),
+//! `version` (`x.y.z`, default from the file), `verify` (`true` reads
+//! the bank back) and `always` (`true` uploads even an image the device
+//! already runs). The file name of the part picks a `.crispy` container;
+//! UF2, ELF and Intel HEX are told by their contents as usual.
+//!
+//! An operation is answered with the JSON object a command on several
+//! devices reports for each: `{"port", "ok": true, "data"}` with status
+//! 200, or `{"port", "ok": false, "error": {"code", "message", "context"}}`
+//! with status 500 (see `output`). A request the server refuses is answered
+//! `{"ok": false, "error": {"code", "message"}}` with its own status: 400,
+//! 401 without the `--token` as `Authorization: Bearer TOKEN`, 404, 405,
+//! 409 while another operation has the device, 413 for a body over
+//! [`MAX_BODY`].
+//!
+//! Each request is served on a thread of its own, but one operation talks
+//! to the device at a time; `GET /progress` is answered throughout. The
+//! port is opened for the first operation and again after a reboot or a
+//! failure, so `serve` keeps running across device resets.
+//!
+//! Built only with the `serve` feature:
+//! `cargo build -p crispy-upload --features serve`.
+
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crispy_common::{FwVersion, FW_BANK_SIZE};
+
+use crate::commands::{self, InputOptions, UploadOptions, DEFAULT_CHUNK_SIZE, DEFAULT_MAX_GAP};
+use crate::link::LinkOptions;
+use crate::output::{self, outln};
+use crate::overrides::Overrides;
+use crate::transport::{RetryPolicy, Transport};
+
+/// Largest request body taken: the largest bank as UF2, which doubles it.
+pub const MAX_BODY: usize = 16 * 1024 * 1024;
+
+/// Where to listen, and who may ask.
+pub struct ServeOptions {
+    /// `ADDR:PORT` to listen on.
+    pub listen: String,
+    /// Bearer token every request must carry.
+    pub token: Option<String>,
+}
+
+/// Serve the bootloader on `port` over HTTP until stopped.
+pub fn run(port: &str, link: &LinkOptions, retry: RetryPolicy, opts: &ServeOptions) -> Result<()> {
+    let server = Server::http(&opts.listen)
+        .map_err(|err| anyhow!("Failed to listen on {}: {}", opts.listen, err))?;
+    let loopback = server
+        .server_addr()
+        .to_ip()
+        .is_some_and(|addr| addr.ip().is_loopback());
+    if opts.token.is_none() && !loopback {
+        outln!(
+            "Warning:  anyone who can reach {} can update the device; pass --token",
+            opts.listen
+        );
+    }
+    outln!("Serving {} on http://{}", port, opts.listen);
+
+    let device = Arc::new(Device::new(port, link, retry, opts.token.clone()));
+    for request in server.incoming_requests() {
+        let device = device.clone();
+        thread::spawn(move || device.handle(request));
+    }
+    Ok(())
+}
+
+/// The device behind the server.
+struct Device {
+    port: String,
+    link: LinkOptions,
+    retry: RetryPolicy,
+    token: Option<String>,
+    /// Open between operations while the device is there; held by the
+    /// operation in flight.
+    transport: Mutex<Option<Transport>>,
+    activity: Mutex<Activity>,
+}
+
+/// `GET /progress`.
+#[derive(Clone, Default, Serialize)]
+struct Activity {
+    /// The operation in flight.
+    operation: Option<&'static str>,
+    step: Option<String>,
+    done: Option<u64>,
+    total: Option<u64>,
+    secs: Option<f64>,
+    /// The answer to the last operation.
+    last: Option<Value>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+/// An answer: HTTP status and JSON body.
+type Answer = (u16, Value);
+
+impl Device {
+    fn new(port: &str, link: &LinkOptions, retry: RetryPolicy, token: Option<String>) -> Self {
+        Self {
+            port: port.to_string(),
+            link: *link,
+            retry,
+            token,
+            transport: Mutex::new(None),
+            activity: Mutex::new(Activity::default()),
+        }
+    }
+
+    fn handle(self: &Arc<Self>, mut request: Request) {
+        let (status, body) = self.answer(&mut request);
+        let header = Header::from_bytes("Content-Type", "application/json").expect("valid header");
+        let response = Response::from_string(body.to_string())
+            .with_status_code(status)
+            .with_header(header);
+        let _ = request.respond(response);
+    }
+
+    fn answer(self: &Arc<Self>, request: &mut Request) -> Answer {
+        if !self.authorized(request) {
+            return refusal(401, "unauthorized", "A valid bearer token is required");
+        }
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let params = query_params(query);
+        match (request.method(), path) {
+            (Method::Get, "/progress") => (200, self.progress()),
+            (Method::Get, "/status") => {
+                let info = params.iter().any(|(key, _)| key == "info");
+                self.operation("status", |transport| match info {
+                    true => commands::info(transport),
+                    false => commands::status(transport),
+                })
+            }
+            (Method::Post, "/upload") => match upload_request(request, params) {
+                Ok(upload) => {
+                    let answer = self.operation("upload", |transport| upload.run(transport));
+                    let _ = fs::remove_file(&upload.file);
+                    answer
+                }
+                Err(refused) => refused,
+            },
+            (Method::Post, "/reboot") => self.operation("reboot", |transport| {
+                commands::reboot(transport)?;
+                // The device leaves the bootloader; open the port again next time
+                Err(Rebooted.into())
+            }),
+            (_, "/progress" | "/status" | "/upload" | "/reboot") => refusal(
+                405,
+                "method",
+                format!("{} is not allowed here", request.method()),
+            ),
+            _ => refusal(404, "not_found", format!("No {}", path)),
+        }
+    }
+
+    fn authorized(&self, request: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let expected = format!("Bearer {}", token);
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Authorization"))
+            .is_some_and(|h| same(h.value.as_str().as_bytes(), expected.as_bytes()))
+    }
+
+    /// Run `f` on the device, unless another operation has it.
+    fn operation(
+        self: &Arc<Self>,
+        name: &'static str,
+        f: impl FnOnce(&mut Transport) -> Result<()>,
+    ) -> Answer {
+        let Ok(mut slot) = self.transport.try_lock() else {
+            let busy = self
+                .activity
+                .lock()
+                .unwrap()
+                .operation
+                .unwrap_or("an operation");
+            return refusal(409, "busy", format!("The device is busy with {}", busy));
+        };
+        {
+            let mut activity = self.activity.lock().unwrap();
+            *activity = Activity {
+                operation: Some(name),
+                started: Some(Instant::now()),
+                last: activity.last.take(),
+                ..Activity::default()
+            };
+        }
+
+        let device = self.clone();
+        let watcher: output::Watcher = Box::new(move |step, progress| {
+            let mut activity = device.activity.lock().unwrap();
+            activity.step = Some(step.to_string());
+            activity.done = progress.map(|(done, _)| done);
+            activity.total = progress.map(|(_, total)| total);
+        });
+        let bars = output::device_bars();
+        let (result, data) = output::for_device(&self.port, &bars, || {
+            output::watch_steps(watcher, || {
+                let transport = match slot.take() {
+                    Some(transport) => transport,
+                    None => {
+                        let mut transport = Transport::open(&self.port, &self.link)?;
+                        transport.set_retry_policy(self.retry);
+                        transport
+                    }
+                };
+                let transport = slot.insert(transport);
+                f(transport)
+            })
+        });
+        // A reboot succeeded; anything else failed and may have left the
+        // port unusable
+        let result = match result {
+            Err(err) if err.is::<Rebooted>() => {
+                *slot = None;
+                Ok(())
+            }
+            Err(err) => {
+                *slot = None;
+                Err(err)
+            }
+            ok => ok,
+        };
+
+        let body = output::device_result(&self.port, &result, data);
+        let mut activity = self.activity.lock().unwrap();
+        *activity = Activity {
+            last: Some(body.clone()),
+            ..Activity::default()
+        };
+        (if result.is_ok() { 200 } else { 500 }, body)
+    }
+
+    fn progress(&self) -> Value {
+        let mut activity = self.activity.lock().unwrap().clone();
+        activity.secs = activity.started.map(|t| t.elapsed().as_secs_f64());
+        serde_json::to_value(activity).expect("activity serializes")
+    }
+}
+
+/// The device was rebooted, so its port has to be opened again.
+#[derive(Debug)]
+struct Rebooted;
+
+impl std::fmt::Display for Rebooted {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("rebooted")
+    }
+}
+
+impl std::error::Error for Rebooted {}
+
+/// A request refused before it reached the device.
+fn refusal(status: u16, code: &str, message: impl Into<String>) -> Answer {
+    let error = json!({ "code": code, "message": message.into() });
+    (status, json!({ "ok": false, "error": error }))
+}
+
+/// `POST /upload`: the image, in a file for `upload`, and its options.
+struct Upload {
+    file: PathBuf,
+    bank: Option<u8>,
+    version: Option<FwVersion>,
+    verify: bool,
+    always: bool,
+}
+
+impl Upload {
+    fn run(&self, transport: &mut Transport) -> Result<()> {
+        let opts = UploadOptions {
+            version: self.version,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            verify: self.verify,
+            input: InputOptions {
+                any_family: false,
+                max_gap: DEFAULT_MAX_GAP,
+                sha256: None,
+                bank_size: FW_BANK_SIZE,
+            },
+            overrides: Overrides::NONE,
+            always: self.always,
+        };
+        commands::upload(transport, &self.file, self.bank, &opts).map(|_| ())
+    }
+}
+
+/// Read the image and options of `POST /upload`.
+fn upload_request(
+    request: &mut Request,
+    mut params: Vec<(String, String)>,
+) -> Result<Upload, Answer> {
+    let mut body = Vec::new();
+    let read = request
+        .as_reader()
+        .take(MAX_BODY as u64 + 1)
+        .read_to_end(&mut body);
+    if let Err(err) = read {
+        return Err(refusal(
+            400,
+            "body",
+            format!("Failed to read the body: {}", err),
+        ));
+    }
+    if body.len() > MAX_BODY {
+        let message = format!("The body is over {} bytes", MAX_BODY);
+        return Err(refusal(413, "too_large", message));
+    }
+
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Content-Type"))
+        .map(|h| h.value.as_str().to_string())
+        .unwrap_or_default();
+    let (image, name) = if content_type.starts_with("multipart/form-data") {
+        let parts =
+            multipart(&content_type, &body).map_err(|problem| refusal(400, "body", problem))?;
+        let mut image = None;
+        for part in parts {
+            match part.name.as_str() {
+                "file" => image = Some((part.data, part.filename)),
+                _ => params.push((part.name, String::from_utf8_lossy(&part.data).into_owned())),
+            }
+        }
+        image.ok_or_else(|| refusal(400, "body", "No `file` part in the form"))?
+    } else {
+        (body, None)
+    };
+    if image.is_empty() {
+        return Err(refusal(400, "body", "The image is empty"));
+    }
+
+    let mut upload = Upload {
+        file: temp_file(name.as_deref()),
+        bank: None,
+        version: None,
+        verify: false,
+        always: false,
+    };
+    for (key, value) in &params {
+        let bad = || refusal(400, "invalid_argument", format!("Bad {}: {:?}", key, value));
+        match key.as_str() {
+            "bank" => match value.as_str() {
+                "0" | "1" => upload.bank = value.parse().ok(),
+                _ => return Err(bad()),
+            },
+            "version" => upload.version = Some(value.parse().map_err(|_| bad())?),
+            "verify" => upload.verify = matches!(value.as_str(), "" | "1" | "true" | "yes"),
+            "always" => upload.always = matches!(value.as_str(), "" | "1" | "true" | "yes"),
+            _ => {}
+        }
+    }
+    fs::write(&upload.file, &image)
+        .map_err(|err| refusal(500, "io", format!("Failed to store the image: {}", err)))?;
+    Ok(upload)
+}
+
+/// A file for an uploaded image, keeping the extension of `name`.
+fn temp_file(name: Option<&str>) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let extension = name
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext)
+        .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric()))
+        .unwrap_or("bin");
+    let file = format!("crispy-serve-{}-{}.{}", std::process::id(), n, extension);
+    std::env::temp_dir().join(file)
+}
+
+/// A part of a `multipart/form-data` body.
+struct Part {
+    name: String,
+    filename: Option<String>,
+    data: Vec<u8>,
+}
+
+/// The parts of a `multipart/form-data` body (RFC 7578).
+fn multipart(content_type: &str, body: &[u8]) -> Result<Vec<Part>, String> {
+    let boundary = content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|param| param.strip_prefix("boundary="))
+        .ok_or("multipart body without a boundary")?
+        .trim_matches('"');
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let separator = [b"\r\n".as_slice(), &delimiter].concat();
+
+    let start = find(body, &delimiter).ok_or("no boundary in the body")?;
+    let mut rest = &body[start + delimiter.len()..];
+    let mut parts = Vec::new();
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        rest = rest
+            .strip_prefix(b"\r\n")
+            .ok_or("malformed multipart body")?;
+        let end = find(rest, &separator).ok_or("unterminated multipart part")?;
+        let part = &rest[..end];
+        let head_end = find(part, b"\r\n\r\n").ok_or("multipart part without headers")?;
+        let head = String::from_utf8_lossy(&part[..head_end]);
+        let disposition = head
+            .lines()
+            .find(|line| {
+                line.to_ascii_lowercase()
+                    .starts_with("content-disposition:")
+            })
+            .ok_or("multipart part without a Content-Disposition")?;
+        let param = |key: &str| {
+            disposition.split(';').map(str::trim).find_map(|param| {
+                let value = param.strip_prefix(key)?.strip_prefix('=')?;
+                Some(value.trim_matches('"').to_string())
+            })
+        };
+        parts.push(Part {
+            name: param("name").ok_or("multipart part without a name")?,
+            filename: param("filename"),
+            data: part[head_end + 4..].to_vec(),
+        });
+        rest = &rest[end + separator.len()..];
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `key=value` pairs of a query string, decoded.
+fn query_params(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                out.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Compare in time independent of where the bytes differ.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_device::TestDevice;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_multipart() {
+        let body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"bank\"\r\n\r\n\
+            1\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"app.crispy\"\r\n\
+            Content-Type: application/octet-stream\r\n\r\n\
+            \x00\r\n\xff--X\r\n--XyZ--\r\n";
+        let parts = multipart("multipart/form-data; boundary=\"XyZ\"", body).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(
+            (parts[0].name.as_str(), &parts[0].data[..]),
+            ("bank", &b"1"[..])
+        );
+        assert_eq!(parts[1].filename.as_deref(), Some("app.crispy"));
+        assert_eq!(parts[1].data, b"\x00\r\n\xff--X");
+
+        assert!(multipart("multipart/form-data", body).is_err());
+        assert!(multipart("multipart/form-data; boundary=XyZ", b"--XyZ\r\nno end").is_err());
+    }
+
+    #[test]
+    fn test_query_params() {
+        assert_eq!(
+            query_params("bank=1&version=1.2.3&verify&note=a+b%2Fc"),
+            [
+                ("bank".to_string(), "1".to_string()),
+                ("version".to_string(), "1.2.3".to_string()),
+                ("verify".to_string(), String::new()),
+                ("note".to_string(), "a b/c".to_string()),
+            ]
+        );
+        assert!(query_params("").is_empty());
+        assert!(same(b"Bearer x", b"Bearer x"));
+        assert!(!same(b"Bearer x", b"Bearer y"));
+        assert!(!same(b"Bearer", b"Bearer x"));
+    }
+
+    /// Send one request and return the status and JSON body of the answer.
+    fn send(addr: &str, head: &str, body: &[u8]) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let request = format!(
+            "{}\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n",
+            head,
+            body.len()
+        );
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).unwrap();
+        let answer = String::from_utf8(answer).unwrap();
+        let status = answer[9..12].parse().unwrap();
+        let (_, body) = answer.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn test_serve() {
+        let device = TestDevice::start();
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap().to_string();
+        let served = Arc::new(Device::new(
+            &device.port,
+            &LinkOptions::DEFAULT,
+            RetryPolicy::DEFAULT,
+            Some("secret".to_string()),
+        ));
+        let handler = served.clone();
+        let thread = thread::spawn(move || {
+            for request in server.incoming_requests().take(6) {
+                handler.handle(request);
+            }
+        });
+        let auth = "Authorization: Bearer secret";
+
+        let (status, body) = send(&addr, "GET /status HTTP/1.1", b"");
+        assert_eq!(
+            (status, &body["error"]["code"]),
+            (401, &json!("unauthorized"))
+        );
+
+        let (status, body) = send(&addr, &format!("GET /status HTTP/1.1\r\n{}", auth), b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["ok"], json!(true));
+        assert_eq!(body["data"]["status"]["active_bank"], json!(0));
+
+        let mut image = vec![0u8; 2048];
+        image[4..8].copy_from_slice(&(crispy_common::FW_A_ADDR | 0x101).to_le_bytes());
+        let head = format!(
+            "POST /upload?bank=1&version=1.2.3 HTTP/1.1\r\n{}\r\nContent-Type: application/octet-stream",
+            auth
+        );
+        let (status, body) = send(&addr, &head, &image);
+        assert_eq!(status, 500, "{}", body);
+        let code = body["error"]["code"].as_str().unwrap().to_string();
+        assert_eq!(code, "preflight");
+
+        let head = format!("POST /upload?bank=2 HTTP/1.1\r\n{}", auth);
+        let (status, body) = send(&addr, &head, &image);
+        assert_eq!(
+            (status, &body["error"]["code"]),
+            (400, &json!("invalid_argument"))
+        );
+
+        let (status, body) = send(&addr, &format!("GET /progress HTTP/1.1\r\n{}", auth), b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["operation"], Value::Null);
+        assert_eq!(body["last"]["error"]["code"], json!("preflight"));
+
+        let (status, _) = send(&addr, &format!("DELETE /reboot HTTP/1.1\r\n{}", auth), b"");
+        assert_eq!(status, 405);
+
+        thread.join().unwrap();
+        drop(served);
+        device.finish();
+    }
+}
@@ -0,0 +1,326 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Where a firmware file comes from.
+//!
+//! Wherever a command takes a firmware FILE, it may also be `-`, the image
+//! on stdin, or an `http://` or `https://` URL, such as an artifact server's.
+//! URLs need the `fetch` feature:
+//! `cargo build -p crispy-upload --features fetch`.
+//!
+//! Both are read whole into memory, as the size and CRC are needed before
+//! anything is sent, and at most [`MAX_INPUT`] bytes. They are read once
+//! per run and kept, so the checks before the port is opened and the upload
+//! see the same bytes. A download shows its own `download` progress; one
+//! that breaks off, or ends short of its `Content-Length`, fails with the
+//! `fetch` error rather than uploading a truncated image. Stdin carries no
+//! length, so `--sha256` is the way to be sure it arrived whole.
+//!
+//! `--sha256 HEX` checks the SHA-256 of the file as read, whatever its
+//! source, and fails with the `checksum` error when it differs.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+
+use crate::output::{outln, Failure};
+
+/// Largest image read from stdin or a URL: far more than a bank.
+pub const MAX_INPUT: usize = 16 * 1024 * 1024;
+
+/// A SHA-256 digest.
+pub type Sha256Digest = [u8; 32];
+
+/// Stdin and downloads read so far, or why they could not be.
+type Cache = HashMap<PathBuf, Result<Arc<Vec<u8>>, (&'static str, String)>>;
+
+static READ: Mutex<Option<Cache>> = Mutex::new(None);
+
+/// Whether `file` is stdin.
+pub fn is_stdin(file: &Path) -> bool {
+    file.as_os_str() == "-"
+}
+
+/// Whether `file` is a URL to download.
+pub fn is_url(file: &Path) -> bool {
+    let file = file.to_string_lossy();
+    file.starts_with("http://") || file.starts_with("https://")
+}
+
+/// Whether `file` names something other than a file on disk.
+pub fn is_stream(file: &Path) -> bool {
+    is_stdin(file) || is_url(file)
+}
+
+/// The contents of `file`, checked against `sha256` if given.
+pub fn read(file: &Path, sha256: Option<&Sha256Digest>) -> Result<Vec<u8>> {
+    let bytes = if is_stream(file) {
+        read_stream(file)?
+    } else {
+        fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?
+    };
+    if let Some(expected) = sha256 {
+        let actual: Sha256Digest = Sha256::digest(&bytes).into();
+        if actual != *expected {
+            bail!(Failure::new(
+                "checksum",
+                format!(
+                    "{}: SHA-256 is {}, not {} (--sha256)",
+                    file.display(),
+                    hex(&actual),
+                    hex(expected)
+                )
+            )
+            .with("expected", hex(expected))
+            .with("actual", hex(&actual)));
+        }
+    }
+    Ok(bytes)
+}
+
+/// Stdin or a download, read on first use.
+fn read_stream(file: &Path) -> Result<Vec<u8>> {
+    let mut cache = READ.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+    let entry = cache.entry(file.to_path_buf()).or_insert_with(|| {
+        let read = if is_stdin(file) {
+            read_stdin()
+        } else {
+            download(&file.to_string_lossy())
+        };
+        read.map(Arc::new)
+    });
+    match entry {
+        Ok(bytes) => Ok(bytes.to_vec()),
+        Err((code, message)) => bail!(Failure::new(code, message.clone())),
+    }
+}
+
+fn read_stdin() -> Result<Vec<u8>, (&'static str, String)> {
+    let fail = |message: String| ("input", message);
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return Err(fail(
+            "stdin is a terminal; pipe the image in, e.g. `curl ... | crispy-upload upload -`"
+                .to_string(),
+        ));
+    }
+    let mut bytes = Vec::new();
+    stdin
+        .lock()
+        .take(MAX_INPUT as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|err| {
+            fail(format!(
+                "Failed to read stdin after {} bytes: {}",
+                bytes.len(),
+                err
+            ))
+        })?;
+    if bytes.is_empty() {
+        return Err(fail("stdin is empty".to_string()));
+    }
+    if bytes.len() > MAX_INPUT {
+        return Err(fail(format!(
+            "stdin holds more than {} bytes, too much for a firmware image",
+            MAX_INPUT
+        )));
+    }
+    outln!("Stdin:    {} bytes", bytes.len());
+    Ok(bytes)
+}
+
+#[cfg(feature = "fetch")]
+fn download(url: &str) -> Result<Vec<u8>, (&'static str, String)> {
+    use crate::output::{self, Progress};
+
+    let fail = |message: String| ("fetch", message);
+    let response = ureq::get(url).call().map_err(|err| match err {
+        ureq::Error::Status(code, response) => fail(format!(
+            "{}: the server answered {} {}",
+            url,
+            code,
+            response.status_text()
+        )),
+        err => fail(format!("{}: {}", url, err)),
+    })?;
+    let length = response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok());
+    if length.is_some_and(|len| len > MAX_INPUT as u64) {
+        return Err(fail(format!(
+            "{}: {} bytes, more than the {} accepted",
+            url,
+            length.unwrap_or_default(),
+            MAX_INPUT
+        )));
+    }
+
+    let progress = match length {
+        Some(len) => Progress::bytes("download", len as u32).ok(),
+        None => {
+            output::phase("download");
+            None
+        }
+    };
+    let mut reader = response.into_reader().take(MAX_INPUT as u64 + 1);
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                return Err(fail(format!(
+                    "{}: the download broke off after {} bytes: {}",
+                    url,
+                    bytes.len(),
+                    err
+                )))
+            }
+        };
+        bytes.extend_from_slice(&buf[..n]);
+        if let Some(progress) = &progress {
+            progress.set_position(bytes.len() as u64);
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.finish_and_clear();
+    }
+
+    if bytes.len() > MAX_INPUT {
+        return Err(fail(format!(
+            "{}: more than {} bytes, too much for a firmware image",
+            url, MAX_INPUT
+        )));
+    }
+    if let Some(len) = length.filter(|&len| len != bytes.len() as u64) {
+        return Err(fail(format!(
+            "{}: the download is truncated, {} of {} bytes",
+            url,
+            bytes.len(),
+            len
+        )));
+    }
+    if bytes.is_empty() {
+        return Err(fail(format!("{}: the download is empty", url)));
+    }
+    outln!(
+        "Fetched:  {} ({} bytes, SHA-256: {})",
+        url,
+        bytes.len(),
+        hex(&Sha256::digest(&bytes).into())
+    );
+    Ok(bytes)
+}
+
+#[cfg(not(feature = "fetch"))]
+fn download(url: &str) -> Result<Vec<u8>, (&'static str, String)> {
+    Err((
+        "fetch",
+        format!(
+            "{}: downloading needs crispy-upload built with `--features fetch`",
+            url
+        ),
+    ))
+}
+
+/// `--sha256`: 64 hex digits.
+pub fn parse_sha256(text: &str) -> Result<Sha256Digest, String> {
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return Err("expected 64 hex digits".to_string());
+    }
+    let mut digest = [0u8; 32];
+    for (byte, pair) in digest.iter_mut().zip(text.as_bytes().chunks(2)) {
+        let pair = std::str::from_utf8(pair).expect("ASCII");
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| "expected 64 hex digits".to_string())?;
+    }
+    Ok(digest)
+}
+
+fn hex(digest: &Sha256Digest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parse_sha256() {
+        let digest = parse_sha256(EMPTY).unwrap();
+        assert_eq!(hex(&digest), EMPTY);
+        assert_eq!(parse_sha256(&EMPTY.to_uppercase()).unwrap(), digest);
+        assert!(parse_sha256(&EMPTY[1..]).is_err());
+        assert!(parse_sha256(&EMPTY.replace('e', "g")).is_err());
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(is_stdin(Path::new("-")));
+        assert!(is_url(Path::new("https://example.com/fw.uf2")));
+        assert!(is_url(Path::new("http://10.0.0.2/fw.bin")));
+        assert!(!is_stream(Path::new("fw.bin")));
+        assert!(!is_stream(Path::new("./-")));
+    }
+
+    #[test]
+    fn test_sha256() {
+        let file = std::env::temp_dir().join(format!("crispy-sha-{}.bin", std::process::id()));
+        fs::write(&file, b"").unwrap();
+        assert!(read(&file, Some(&parse_sha256(EMPTY).unwrap())).is_ok());
+        let err = read(&file, Some(&[0; 32])).unwrap_err();
+        fs::remove_file(&file).unwrap();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "checksum");
+        assert_eq!(failure.context["actual"], EMPTY);
+    }
+
+    #[cfg(not(feature = "fetch"))]
+    #[test]
+    fn test_url_needs_fetch() {
+        let err = read(Path::new("https://example.com/fw.bin"), None).unwrap_err();
+        assert_eq!(err.downcast_ref::<Failure>().unwrap().code, "fetch");
+    }
+
+    #[cfg(feature = "fetch")]
+    #[test]
+    fn test_download() {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        // Whole, then cut short of its Content-Length
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for sent in [4, 2] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                let head = "HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\n";
+                stream.write_all(head.as_bytes()).unwrap();
+                stream.write_all(&b"\x01\x02\x03\x04"[..sent]).unwrap();
+            }
+        });
+
+        let whole = format!("http://{}/whole.bin", addr);
+        assert_eq!(read(Path::new(&whole), None).unwrap(), [1, 2, 3, 4]);
+        // Read once: the server is not asked again
+        assert_eq!(read(Path::new(&whole), None).unwrap(), [1, 2, 3, 4]);
+        let cut = format!("http://{}/cut.bin", addr);
+        let err = read(Path::new(&cut), None).unwrap_err();
+        server.join().unwrap();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "fetch");
+        assert!(failure.message.contains("broke off after 2 bytes"));
+    }
+}
@@ -0,0 +1,344 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! `flash-bootloader`: the bootloader onto a blank board over SWD, built
+//! with `--features probe-rs`.
+//!
+//! The `probe-rs` tool (from `probe-rs-tools`, as the Makefile uses) talks
+//! to the debug probe; this module picks the probe, checks what is about to
+//! be written and runs the steps in order:
+//!
+//! 1. `download --verify` of the bootloader ELF, which reads flash back
+//! 2. with `--boot-data`, the same for a BootData record (as `boot-data
+//!    encode` writes it), at the `__boot_data_addr` the ELF was linked for
+//! 3. `reset`, unless `--no-reset`
+//!
+//! The file is checked before the probe is touched: an ARM ELF whose
+//! loadable segments all lie in the bootloader region, so firmware given by
+//! mistake is refused. Probes are chosen the way ports are: `--probe
+//! VID:PID[:SERIAL]` picks one, and without it a single attached probe is
+//! used; none or several fail with `no_device`. A step that fails is a
+//! `swd` error carrying the last thing `probe-rs` said.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use anyhow::{anyhow, bail, Context, Result};
+use object::elf::{EM_ARM, PT_LOAD};
+use object::read::elf::{ElfFile32, FileHeader, ProgramHeader};
+use object::{Endianness, Object, ObjectSymbol};
+use serde_json::json;
+
+use crispy_common::protocol::{BootData, BOOT_DATA_SIZE};
+use crispy_common::{BOOT_DATA_ADDR, FLASH_BASE, FW_A_ADDR};
+
+use crate::output::{self, out, outln, Failure};
+
+/// The `probe-rs` executable, found on `PATH`.
+const PROBE_RS: &str = "probe-rs";
+/// Target name `probe-rs` knows the chip by.
+const CHIP: &str = "RP2040";
+/// Symbol the bootloader's linker script gives the BootData address in.
+const BOOT_DATA_SYMBOL: &str = "__boot_data_addr";
+
+/// What `flash-bootloader` writes, and with which probe.
+pub struct FlashOptions<'a> {
+    pub probe: Option<&'a str>,
+    /// A raw BootData record to write as well.
+    pub boot_data: Option<&'a Path>,
+    /// Leave the target halted instead of resetting it.
+    pub no_reset: bool,
+}
+
+/// Flash the bootloader ELF `file` over SWD, then the BootData record if
+/// asked, and reset the target.
+pub fn flash_bootloader(file: &Path, opts: &FlashOptions) -> Result<()> {
+    let elf = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let bootloader = check_bootloader(&elf).map_err(|err| {
+        Failure::new(
+            "input",
+            format!("{} is not a bootloader image: {:#}", file.display(), err),
+        )
+    })?;
+    let record = opts.boot_data.map(read_boot_data).transpose()?;
+    let probe = select_probe(opts.probe)?;
+    outln!("Probe:      {}", probe);
+    outln!(
+        "Bootloader: {} ({} bytes at 0x{:08x})",
+        file.display(),
+        bootloader.size,
+        FLASH_BASE
+    );
+
+    output::phase("flash");
+    out!("Flashing and verifying the bootloader... ");
+    run(&download_args(&probe, file, None))?;
+    outln!("OK");
+
+    if let Some(record) = &record {
+        output::phase("boot_data");
+        out!(
+            "Writing BootData at 0x{:08x}... ",
+            bootloader.boot_data_addr
+        );
+        let temp =
+            std::env::temp_dir().join(format!("crispy-boot-data-{}.bin", std::process::id()));
+        fs::write(&temp, record).with_context(|| format!("Failed to write {}", temp.display()))?;
+        let written = run(&download_args(
+            &probe,
+            &temp,
+            Some(bootloader.boot_data_addr),
+        ));
+        let _ = fs::remove_file(&temp);
+        written?;
+        outln!("OK");
+    }
+
+    if !opts.no_reset {
+        output::phase("reset");
+        out!("Resetting the target... ");
+        run(&probe_args("reset", &probe))?;
+        outln!("OK");
+    }
+
+    output::report(json!({
+        "file": file,
+        "probe": probe,
+        "size": bootloader.size,
+        "boot_data_addr": record.is_some().then_some(bootloader.boot_data_addr),
+        "reset": !opts.no_reset,
+    }));
+    Ok(())
+}
+
+/// What the bootloader ELF says about itself.
+#[derive(Debug, PartialEq, Eq)]
+struct Bootloader {
+    /// Bytes from the start of flash to the end of the last segment.
+    size: u32,
+    /// Where this build keeps BootData.
+    boot_data_addr: u32,
+}
+
+/// Check that `file` is an ARM ELF loading only into the bootloader region.
+fn check_bootloader(file: &[u8]) -> Result<Bootloader> {
+    let elf = ElfFile32::<Endianness>::parse(file)
+        .map_err(|err| anyhow!("not a 32-bit ELF file: {}", err))?;
+    let endian = elf.endian();
+    let machine = elf.elf_header().e_machine(endian);
+    if machine != EM_ARM {
+        bail!("ELF is for machine {}, not ARM ({})", machine, EM_ARM);
+    }
+
+    let mut end = None;
+    for ph in elf.elf_program_headers() {
+        if ph.p_type(endian) != PT_LOAD || ph.p_filesz(endian) == 0 {
+            continue;
+        }
+        let start = ph.p_paddr(endian);
+        let stop = u64::from(start) + u64::from(ph.p_filesz(endian));
+        if start < FLASH_BASE || stop > u64::from(FW_A_ADDR) {
+            bail!(
+                "segment at 0x{:08x} lies outside the bootloader region (0x{:08x}..0x{:08x})",
+                start,
+                FLASH_BASE,
+                FW_A_ADDR
+            );
+        }
+        end = end.max(Some(stop as u32));
+    }
+    let end = end.context("ELF has no loadable segments")?;
+
+    let boot_data_addr = elf
+        .symbols()
+        .find(|sym| sym.name() == Ok(BOOT_DATA_SYMBOL))
+        .map_or(BOOT_DATA_ADDR, |sym| sym.address() as u32);
+    Ok(Bootloader {
+        size: end - FLASH_BASE,
+        boot_data_addr,
+    })
+}
+
+/// The BootData record in `file`, which must decode.
+fn read_boot_data(file: &Path) -> Result<Vec<u8>> {
+    let raw = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let Some(record) = raw.first_chunk::<BOOT_DATA_SIZE>() else {
+        bail!(Failure::new(
+            "input",
+            format!(
+                "{} is {} bytes, a BootData record is {}",
+                file.display(),
+                raw.len(),
+                BOOT_DATA_SIZE
+            )
+        ));
+    };
+    BootData::from_bytes(record).map_err(|err| {
+        Failure::new(
+            "input",
+            format!(
+                "{} is not a valid BootData record: {:?}",
+                file.display(),
+                err
+            ),
+        )
+    })?;
+    Ok(record.to_vec())
+}
+
+/// The probe to use: `requested`, else the only one attached.
+fn select_probe(requested: Option<&str>) -> Result<String> {
+    if let Some(probe) = requested {
+        return Ok(probe.to_string());
+    }
+    let listing = run(&["list".to_string()])?;
+    match parse_probes(&String::from_utf8_lossy(&listing.stdout)).as_slice() {
+        [probe] => Ok(probe.clone()),
+        [] => bail!(Failure::new(
+            "no_device",
+            "No debug probe found; check the SWD probe's USB connection"
+        )),
+        probes => bail!(Failure::new(
+            "no_device",
+            format!(
+                "{} debug probes found ({}); pick one with --probe",
+                probes.len(),
+                probes.join(", ")
+            )
+        )
+        .with("probes", probes.to_vec())),
+    }
+}
+
+/// The `VID:PID[:SERIAL]` selectors in the output of `probe-rs list`, whose
+/// lines read `[0]: Name -- 2e8a:000c:SERIAL (CMSIS-DAP)`.
+fn parse_probes(listing: &str) -> Vec<String> {
+    listing
+        .lines()
+        .filter(|line| line.trim_start().starts_with('['))
+        .filter_map(|line| line.split_once(" -- "))
+        .filter_map(|(_, rest)| rest.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Arguments of a `probe-rs` subcommand on the chip through `probe`.
+fn probe_args(subcommand: &str, probe: &str) -> Vec<String> {
+    [subcommand, "--chip", CHIP, "--probe", probe]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+/// Arguments to write and verify `file`: an ELF, or raw bytes at `base`.
+fn download_args(probe: &str, file: &Path, base: Option<u32>) -> Vec<String> {
+    let mut args = probe_args("download", probe);
+    args.extend(["--verify", "--disable-progressbars"].map(String::from));
+    if let Some(base) = base {
+        args.extend(["--binary-format".to_string(), "bin".to_string()]);
+        args.extend(["--base-address".to_string(), format!("0x{:08x}", base)]);
+    }
+    args.push(file.display().to_string());
+    args
+}
+
+/// Run `probe-rs` with `args`, failing with what it said when it fails.
+fn run(args: &[String]) -> Result<Output> {
+    let output = Command::new(PROBE_RS).args(args).output().map_err(|err| {
+        Failure::new(
+            "swd",
+            format!(
+                "Cannot run {}: {}; install it with `cargo install probe-rs-tools`",
+                PROBE_RS, err
+            ),
+        )
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let said = stderr
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or("no output")
+            .trim();
+        bail!(
+            Failure::new("swd", format!("{} {} failed: {}", PROBE_RS, args[0], said))
+                .with("status", output.status.code())
+                .with("command", args.to_vec())
+        );
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_probes() {
+        let listing = "The following debug probes were found:\n\
+            [0]: Debugprobe on Pico (CMSIS-DAP) -- 2e8a:000c:E6614103E7452D2F (CMSIS-DAP)\n\
+            [1]: STLink V2 -- 0483:3748:37FF6B06 (ST-LINK)\n";
+        assert_eq!(
+            parse_probes(listing),
+            ["2e8a:000c:E6614103E7452D2F", "0483:3748:37FF6B06"]
+        );
+        assert!(parse_probes("No debug probes were found.\n").is_empty());
+    }
+
+    #[test]
+    fn test_download_args() {
+        let args = download_args("2e8a:000c", Path::new("bd.bin"), Some(0x1019_0000));
+        assert_eq!(
+            args.join(" "),
+            "download --chip RP2040 --probe 2e8a:000c --verify --disable-progressbars \
+             --binary-format bin --base-address 0x10190000 bd.bin"
+        );
+        let args = download_args("2e8a:000c", Path::new("bl.elf"), None);
+        assert_eq!(args.last().unwrap(), "bl.elf");
+        assert!(!args.contains(&"--base-address".to_string()));
+    }
+
+    /// A minimal ARM ELF with one `PT_LOAD` segment of `len` bytes at `addr`.
+    fn elf(addr: u32, len: u32) -> Vec<u8> {
+        let mut file = vec![0u8; 0x54];
+        file[0..4].copy_from_slice(b"\x7fELF");
+        file[4] = 1; // ELFCLASS32
+        file[5] = 1; // little-endian
+        file[6] = 1; // EV_CURRENT
+        file[16..18].copy_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+        file[18..20].copy_from_slice(&EM_ARM.to_le_bytes());
+        file[20..24].copy_from_slice(&1u32.to_le_bytes());
+        file[28..32].copy_from_slice(&0x34u32.to_le_bytes()); // e_phoff
+        file[40..42].copy_from_slice(&0x34u16.to_le_bytes()); // e_ehsize
+        file[42..44].copy_from_slice(&0x20u16.to_le_bytes()); // e_phentsize
+        file[44..46].copy_from_slice(&1u16.to_le_bytes()); // e_phnum
+        let ph = &mut file[0x34..0x54];
+        ph[0..4].copy_from_slice(&PT_LOAD.to_le_bytes());
+        ph[4..8].copy_from_slice(&0x54u32.to_le_bytes()); // p_offset
+        ph[8..12].copy_from_slice(&addr.to_le_bytes()); // p_vaddr
+        ph[12..16].copy_from_slice(&addr.to_le_bytes()); // p_paddr
+        ph[16..20].copy_from_slice(&len.to_le_bytes()); // p_filesz
+        ph[20..24].copy_from_slice(&len.to_le_bytes()); // p_memsz
+        file.resize(0x54 + len as usize, 0xAB);
+        file
+    }
+
+    #[test]
+    fn test_check_bootloader() {
+        assert_eq!(
+            check_bootloader(&elf(FLASH_BASE, 0x100)).unwrap(),
+            Bootloader {
+                size: 0x100,
+                boot_data_addr: BOOT_DATA_ADDR,
+            }
+        );
+        // Firmware linked for bank A, or for RAM, is not a bootloader
+        let err = check_bootloader(&elf(FW_A_ADDR, 0x100)).unwrap_err();
+        assert!(err.to_string().contains("bootloader region"), "{}", err);
+        assert!(check_bootloader(&elf(0x2000_0000, 0x100)).is_err());
+        assert!(check_bootloader(&elf(FW_A_ADDR - 0x10, 0x100)).is_err());
+        assert!(check_bootloader(b"not an elf").is_err());
+    }
+}
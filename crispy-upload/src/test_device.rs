@@ -0,0 +1,135 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! A simulated bootloader behind a TCP bridge, for tests that run whole
+//! commands against a device.
+//!
+//! It answers `GetStatus` from BootData and hands every other command to the
+//! shared update state machine on a [`FlashSim`], so uploads are checked the
+//! way the bootloader checks them. Platform commands (`Reboot`,
+//! `GetFlashInfo`, `GetDeviceInfo`) are answered `BadCommand`.
+//!
+//! [`TestDevice::baseline`] plays a released bootloader from before the
+//! status flags instead: a four-field `Status`, and no answer to anything
+//! else.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+
+use crispy_common::cobs::CobsFrameDecoder;
+use crispy_common::flash::read_boot_data;
+use crispy_common::flash_sim::FlashSim;
+use crispy_common::protocol::{AckStatus, BootData, BootState, Command, Response};
+use crispy_common::update_fsm::{self, ResponseSink, UpdateState};
+
+/// Largest frame the simulated device accepts, a full `DataBlock` and then
+/// some.
+const FRAME_SIZE: usize = 4096;
+
+/// A simulated device serving one connection.
+pub struct TestDevice {
+    /// `--port` to reach it.
+    pub port: String,
+    server: JoinHandle<FlashSim>,
+}
+
+impl TestDevice {
+    /// Start a device with blank flash.
+    pub fn start() -> Self {
+        Self::with_flash(FlashSim::new())
+    }
+
+    /// Start a device with `flash`, such as one a previous device left.
+    pub fn with_flash(flash: FlashSim) -> Self {
+        Self::spawn(flash, false)
+    }
+
+    /// Start a device with blank flash that answers like a released
+    /// bootloader from before the status flags.
+    pub fn baseline() -> Self {
+        Self::spawn(FlashSim::new(), true)
+    }
+
+    fn spawn(flash: FlashSim, baseline: bool) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, flash, baseline)
+        });
+        Self { port, server }
+    }
+
+    /// Wait for the host to close the connection and return the flash.
+    pub fn finish(self) -> FlashSim {
+        self.server.join().unwrap()
+    }
+}
+
+/// Sends each response as a COBS frame.
+struct StreamSink<'a>(&'a mut TcpStream);
+
+impl ResponseSink for StreamSink<'_> {
+    fn send(&mut self, response: &Response) {
+        let encoded = postcard::to_stdvec_cobs(response).unwrap();
+        let _ = self.0.write_all(&encoded);
+    }
+}
+
+fn serve(mut stream: TcpStream, mut flash: FlashSim, baseline: bool) -> FlashSim {
+    let mut rx = CobsFrameDecoder::<FRAME_SIZE>::new();
+    let mut state = UpdateState::Idle;
+    let mut byte = [0u8; 1];
+    while stream.read(&mut byte).unwrap_or(0) == 1 {
+        let Some(frame) = rx.feed(byte[0]) else {
+            continue;
+        };
+        if baseline {
+            // Commands it does not know fail to decode there and are dropped
+            if let Ok(Command::GetStatus) = postcard::from_bytes::<Command>(frame) {
+                let _ = stream.write_all(&baseline_status(&flash));
+            }
+            continue;
+        }
+        let mut sink = StreamSink(&mut stream);
+        match postcard::from_bytes::<Command>(frame) {
+            Ok(Command::GetStatus) => sink.send(&status(&flash, state)),
+            Ok(cmd) => state = update_fsm::handle_command(&mut flash, &mut sink, state, cmd),
+            Err(_) => sink.send(&Response::Ack(AckStatus::BadCommand)),
+        }
+    }
+    flash
+}
+
+fn status(flash: &FlashSim, state: UpdateState) -> Response {
+    let bd = read_boot_data(flash).unwrap_or_else(|_| BootData::default_new());
+    Response::Status {
+        active_bank: bd.active_bank,
+        version_a: bd.version_a,
+        version_b: bd.version_b,
+        state: match state {
+            UpdateState::Idle => BootState::UpdateMode,
+            UpdateState::Receiving { .. } => BootState::Receiving,
+        },
+        host_connected: true,
+        tx_stalled: false,
+        bootdata_reconstructed: false,
+        update_interrupted: false,
+    }
+}
+
+/// `Status` as the baseline bootloader encodes it, COBS framed: variant 1
+/// with active bank, both versions and state, and no flags. A tuple of the
+/// variant index and the fields has the same postcard encoding.
+fn baseline_status(flash: &FlashSim) -> Vec<u8> {
+    let bd = read_boot_data(flash).unwrap_or_else(|_| BootData::default_new());
+    let fields = (
+        1u8,
+        bd.active_bank,
+        bd.version_a.raw(),
+        bd.version_b.raw(),
+        BootState::UpdateMode,
+    );
+    postcard::to_stdvec_cobs(&fields).unwrap()
+}
@@ -0,0 +1,142 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! Frame-level protocol log for `--trace`.
+//!
+//! [`crate::transport::Transport`] reports every frame it writes or reads,
+//! so all subcommands are covered. Each becomes one line:
+//!
+//! ```text
+//! [   0.004512] -> GetStatus (3 bytes) 02 05 00
+//! [   0.006120] <- Status (17 bytes) 02 01 ... 00
+//! [   0.311087] -> DataBlock at offset 0x400 (1031 bytes) 05 ... (+967 bytes)
+//! ```
+//!
+//! The time is in seconds since tracing started; `->` goes to the device,
+//! `<-` comes from it. The name is the decoded command or response, and the
+//! hex dump shows the COBS-encoded bytes as they crossed the wire, up to
+//! [`DUMP_LIMIT`] of them. Nothing is redacted. Bytes thrown away before a
+//! command (stale answers, noise) are logged as `discarded`, and a frame
+//! that does not decode as `undecodable`.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+
+/// Most bytes shown per frame.
+pub const DUMP_LIMIT: usize = 64;
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+static TRACE: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Which way a frame went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    ToDevice,
+    FromDevice,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::ToDevice => "->",
+            Direction::FromDevice => "<-",
+        }
+    }
+}
+
+/// Trace to `file`, or to stderr without one, for the rest of the run.
+pub fn start(file: Option<&Path>) -> Result<()> {
+    let out: Box<dyn Write + Send> = match file {
+        Some(path) => Box::new(
+            File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(io::stderr()),
+    };
+    *TRACE.lock().unwrap() = Some(Sink {
+        out,
+        start: Instant::now(),
+    });
+    Ok(())
+}
+
+/// Whether frames are being traced.
+pub fn enabled() -> bool {
+    TRACE.lock().unwrap().is_some()
+}
+
+/// Log one frame, if tracing. `name` is only built when it is needed.
+pub fn frame(direction: Direction, name: impl FnOnce() -> String, bytes: &[u8]) {
+    let mut trace = TRACE.lock().unwrap();
+    let Some(sink) = trace.as_mut() else {
+        return;
+    };
+    let text = line(sink.start.elapsed(), direction, &name(), bytes);
+    // A broken trace file must not break the operation being traced
+    let _ = writeln!(sink.out, "{}", text);
+    let _ = sink.out.flush();
+}
+
+/// One trace line, without the newline.
+pub fn line(elapsed: Duration, direction: Direction, name: &str, bytes: &[u8]) -> String {
+    let mut text = format!(
+        "[{:>4}.{:06}] {} {} ({} bytes)",
+        elapsed.as_secs(),
+        elapsed.subsec_micros(),
+        direction.arrow(),
+        name,
+        bytes.len()
+    );
+    for b in &bytes[..bytes.len().min(DUMP_LIMIT)] {
+        let _ = write!(text, " {:02x}", b);
+    }
+    if bytes.len() > DUMP_LIMIT {
+        let _ = write!(text, " ... (+{} bytes)", bytes.len() - DUMP_LIMIT);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_line() {
+        assert_eq!(
+            line(
+                Duration::from_micros(4512),
+                Direction::ToDevice,
+                "GetStatus",
+                &[0x02, 0x05, 0x00]
+            ),
+            "[   0.004512] -> GetStatus (3 bytes) 02 05 00"
+        );
+        assert_eq!(
+            line(
+                Duration::from_millis(12_345),
+                Direction::FromDevice,
+                "Ack(Ok)",
+                &[0x03, 0x00, 0x00]
+            ),
+            "[  12.345000] <- Ack(Ok) (3 bytes) 03 00 00"
+        );
+    }
+
+    #[test]
+    fn test_trace_line_caps_the_dump() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let text = line(Duration::ZERO, Direction::ToDevice, "DataBlock", &bytes);
+        assert!(text.starts_with("[   0.000000] -> DataBlock (256 bytes) 00 01 02"));
+        assert!(text.ends_with(" 3e 3f ... (+192 bytes)"));
+        assert!(!text.contains(" 40 "));
+    }
+}
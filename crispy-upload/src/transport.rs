@@ -1,46 +1,592 @@
 // SPDX-License-Identifier: MIT
 // Copyright (c) 2026 ADNT Sarl <info@adnt.io>
 
-//! Serial transport layer for bootloader communication.
+//! Transport layer for bootloader communication: COBS framing and retries
+//! over a serial port or a TCP bridge (see [`crate::link`]).
 
 use anyhow::{bail, Context, Result};
-use serialport::SerialPort;
-use std::io::{Read, Write};
-use std::time::Duration;
+use serialport::SerialPortType;
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crispy_common::protocol::{Command, Response};
+use crispy_common::cobs::CobsFrameDecoder;
+use crispy_common::protocol::{
+    AckStatus, BootState, Command, Response, BOOTLOADER_PID, FIRMWARE_PID, PROTOCOL_INTERFACE,
+    USB_VID,
+};
+use crispy_common::FwVersion;
+use serde::Deserialize;
+
+use crate::link::{self, Link, LinkOptions};
+use crate::output::{self, outln, Failure};
+use crate::trace::{self, Direction};
 
 /// Default timeout for serial operations in milliseconds.
 pub const DEFAULT_TIMEOUT_MS: u64 = 5000;
 
-/// USB CDC transport for communicating with the bootloader.
+/// How often to re-send a command that timed out or got a retriable answer,
+/// and how long to wait before the first retry; the wait doubles after each.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub const DEFAULT: Self = Self {
+        retries: 3,
+        backoff: Duration::from_millis(100),
+    };
+
+    /// Wait before retry number `attempt` (from 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(1 << (attempt - 1).min(16))
+    }
+}
+
+/// What a connection has done so far. Take a copy before an operation and
+/// [`Stats::since`] after it for the operation's share.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Commands re-sent by [`Transport::send_recv_retry`].
+    pub retries: u32,
+    /// Encoded bytes written to the device.
+    pub bytes_sent: u64,
+    /// Bytes read from the device, including any discarded.
+    pub bytes_received: u64,
+    /// Frames received that were no response: undecodable, truncated or
+    /// oversize. Noise on the link, or another program on the port.
+    pub discarded_frames: u32,
+    /// Commands answered, other than erases and bank checks, which take as
+    /// long as the flash does.
+    pub round_trips: u32,
+    /// Total time those commands took from send to answer.
+    pub round_trip_time: Duration,
+}
+
+impl Stats {
+    /// What happened between `earlier` and `self`.
+    pub fn since(&self, earlier: &Stats) -> Stats {
+        Stats {
+            retries: self.retries - earlier.retries,
+            bytes_sent: self.bytes_sent - earlier.bytes_sent,
+            bytes_received: self.bytes_received - earlier.bytes_received,
+            discarded_frames: self.discarded_frames - earlier.discarded_frames,
+            round_trips: self.round_trips - earlier.round_trips,
+            round_trip_time: self.round_trip_time - earlier.round_trip_time,
+        }
+    }
+
+    /// Average time from sending a command to its answer, if any was timed.
+    pub fn average_round_trip(&self) -> Option<Duration> {
+        (self.round_trips > 0).then(|| self.round_trip_time / self.round_trips)
+    }
+}
+
+/// Whether `err` is the device not answering in time.
+fn is_timeout(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Failure>()
+        .is_some_and(|f| f.code == "timeout")
+}
+
+/// Whether `err` is the device or bridge going away.
+fn is_disconnected(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Failure>()
+        .is_some_and(|f| f.code == "disconnected")
+}
+
+/// How long [`Transport::send_recv_retry`] waits for a device that went
+/// away to come back.
+pub const RECONNECT_WAIT: Duration = Duration::from_secs(10);
+/// Pause before reopening, so a resetting device has dropped off the bus
+/// rather than being reopened on its way out.
+const REOPEN_DELAY: Duration = Duration::from_millis(500);
+
+/// How often `--wait` tries again.
+const WAIT_POLL: Duration = Duration::from_millis(250);
+/// Pause after a device that was waited for turns up, so it can finish
+/// enumerating before the first command.
+const WAIT_SETTLE: Duration = Duration::from_millis(500);
+
+/// Call `attempt` until it succeeds, fails in a way `retriable` rejects, or
+/// `wait` has passed, announcing the wait once. Without a wait the first
+/// outcome is returned.
+pub fn wait_for<T>(
+    wait: Duration,
+    mut attempt: impl FnMut() -> Result<T>,
+    retriable: impl Fn(&anyhow::Error) -> bool,
+) -> Result<T> {
+    let deadline = Instant::now() + wait;
+    let mut waited = false;
+    loop {
+        match attempt() {
+            Ok(found) => {
+                if waited {
+                    thread::sleep(WAIT_SETTLE);
+                }
+                return Ok(found);
+            }
+            Err(err) if retriable(&err) && Instant::now() < deadline => {
+                if !waited {
+                    eprintln!("Waiting for device...");
+                    output::phase("wait_device");
+                    waited = true;
+                }
+                thread::sleep(WAIT_POLL);
+            }
+            Err(err) if waited => {
+                return Err(err.context(format!("Gave up waiting after {:?}", wait)))
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether `err` is auto-detection finding no device at all, as opposed
+/// to several, which waiting does not fix.
+pub fn is_nothing_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Failure>()
+        .is_some_and(|f| f.code == "no_device" && !f.context.contains_key("ports"))
+}
+
+/// Largest decoded response frame accepted from the device.
+const RX_FRAME_SIZE: usize = 4096;
+
+/// Bytes taken from the port per read. A read returns as soon as anything
+/// has arrived, so a bigger buffer never waits longer.
+const RX_BUF_SIZE: usize = 4096;
+
+/// Find the bootloader's protocol port by USB IDs and interface number.
+///
+/// The composite bootloader also exposes a text console; only the CDC on
+/// `PROTOCOL_INTERFACE` speaks the binary protocol. Linux and Windows report
+/// the communication interface, macOS the data interface, so both match.
+/// A device with a single bootloader port (`single-cdc` builds) is used as is.
+///
+/// Ports are grouped into devices by USB serial number; with `serial`, only
+/// that device is considered. More than one candidate device is an error
+/// listing them, as is none (mentioning devices still running firmware).
+pub fn find_bootloader_port(serial: Option<&str>) -> Result<String> {
+    let listed = list_ports()?;
+    let serial_of = |p: &PortListing| p.usb.as_ref().and_then(|u| u.serial_number.clone());
+    let matches = |p: &&PortListing, kind| {
+        p.kind == Some(kind) && serial.is_none_or(|sn| serial_of(p).as_deref() == Some(sn))
+    };
+
+    let ports: Vec<_> = listed
+        .iter()
+        .filter(|p| matches(p, DeviceKind::Bootloader))
+        .collect();
+    if ports.is_empty() {
+        let apps: Vec<_> = listed
+            .iter()
+            .filter(|p| matches(p, DeviceKind::Firmware))
+            .map(describe)
+            .collect();
+        if !apps.is_empty() {
+            bail!(Failure::new(
+                "no_device",
+                format!(
+                    "No crispy-bootloader device found, but crispy firmware is running on {}; \
+                     type 'bootload' in its console to enter update mode",
+                    apps.join(", ")
+                )
+            ));
+        }
+        match serial {
+            Some(sn) => bail!(Failure::new(
+                "no_device",
+                format!("No crispy-bootloader device with serial number {}", sn)
+            )),
+            None => bail!(Failure::new(
+                "no_device",
+                "No crispy-bootloader device found (is it in update mode?)"
+            )),
+        }
+    }
+
+    let mut devices: Vec<_> = ports.iter().map(|p| serial_of(p)).collect();
+    devices.dedup();
+    if devices.len() > 1 {
+        let found: Vec<_> = ports.iter().map(|p| describe(p)).collect();
+        bail!(Failure::new(
+            "no_device",
+            format!(
+                "Several bootloader devices found ({}); pass --serial or --port",
+                found.join(", ")
+            )
+        )
+        .with("ports", found));
+    }
+
+    if let Some(p) = ports.iter().find(|p| is_protocol_port(p)) {
+        return Ok(p.name.clone());
+    }
+
+    match ports.as_slice() {
+        [p] => Ok(p.name.clone()),
+        _ => {
+            let names: Vec<_> = ports.iter().map(|p| p.name.as_str()).collect();
+            bail!(Failure::new(
+                "no_device",
+                format!(
+                    "Several bootloader ports found ({}); pass --port",
+                    names.join(", ")
+                )
+            )
+            .with("ports", names))
+        }
+    }
+}
+
+/// The port of the one attached device, whether it is in the bootloader or
+/// running firmware, for commands that can start from either.
+///
+/// Bootloader ports are picked as by [`find_bootloader_port`]; without any,
+/// a single device running firmware is used.
+pub fn find_device_port(serial: Option<&str>) -> Result<String> {
+    let listed = list_ports()?;
+    let of_kind = |kind| {
+        listed
+            .iter()
+            .filter(move |p| {
+                p.kind == Some(kind)
+                    && serial.is_none_or(|sn| {
+                        p.usb.as_ref().and_then(|u| u.serial_number.as_deref()) == Some(sn)
+                    })
+            })
+            .collect::<Vec<_>>()
+    };
+    if !of_kind(DeviceKind::Bootloader).is_empty() {
+        return find_bootloader_port(serial);
+    }
+
+    match of_kind(DeviceKind::Firmware).as_slice() {
+        [p] => Ok(p.name.clone()),
+        [] => match serial {
+            Some(sn) => bail!(Failure::new(
+                "no_device",
+                format!("No crispy device with serial number {}", sn)
+            )),
+            None => bail!(Failure::new("no_device", "No crispy device found")),
+        },
+        apps => {
+            let found: Vec<_> = apps.iter().map(|p| describe(p)).collect();
+            bail!(Failure::new(
+                "no_device",
+                format!(
+                    "Several devices running crispy firmware found ({}); pass --serial or --port",
+                    found.join(", ")
+                )
+            )
+            .with("ports", found))
+        }
+    }
+}
+
+/// One port per attached crispy device, for `--all`: its bootloader port as
+/// [`find_bootloader_port`] picks it or, with `firmware`, the firmware's
+/// port of a device not in the bootloader.
+pub fn find_all_device_ports(firmware: bool) -> Result<Vec<String>> {
+    let listed = list_ports()?;
+    // Bootloader before firmware, the protocol port before the console
+    let rank = |p: &PortListing| (p.kind != Some(DeviceKind::Bootloader), !is_protocol_port(p));
+    let mut devices: Vec<(String, &PortListing)> = Vec::new();
+    for p in &listed {
+        let wanted = match p.kind {
+            Some(DeviceKind::Bootloader) => true,
+            Some(DeviceKind::Firmware) => firmware,
+            None => false,
+        };
+        if !wanted {
+            continue;
+        }
+        let serial = p.usb.as_ref().and_then(|u| u.serial_number.clone());
+        let device = serial.unwrap_or_else(|| p.name.clone());
+        match devices.iter_mut().find(|(d, _)| *d == device) {
+            Some((_, chosen)) if rank(p) < rank(chosen) => *chosen = p,
+            Some(_) => {}
+            None => devices.push((device, p)),
+        }
+    }
+    if devices.is_empty() {
+        bail!(Failure::new(
+            "no_device",
+            if firmware {
+                "No crispy device found"
+            } else {
+                "No crispy-bootloader device found (is it in update mode?)"
+            }
+        ));
+    }
+    Ok(devices.into_iter().map(|(_, p)| p.name.clone()).collect())
+}
+
+/// Whether `p` is on the bootloader's protocol interface (the communication
+/// or the data half of the CDC).
+pub fn is_protocol_port(p: &PortListing) -> bool {
+    let protocol = [PROTOCOL_INTERFACE, PROTOCOL_INTERFACE + 1];
+    p.usb
+        .as_ref()
+        .and_then(|u| u.interface)
+        .is_some_and(|i| protocol.contains(&i))
+}
+
+/// `name (serial SN)` for error messages.
+fn describe(p: &PortListing) -> String {
+    match p.usb.as_ref().and_then(|u| u.serial_number.as_deref()) {
+        Some(sn) => format!("{} (serial {})", p.name, sn),
+        None => p.name.clone(),
+    }
+}
+
+/// What a serial port belongs to, going by its USB IDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Bootloader,
+    Firmware,
+}
+
+impl DeviceKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceKind::Bootloader => "bootloader",
+            DeviceKind::Firmware => "firmware",
+        }
+    }
+}
+
+/// A serial port as shown by `list-ports`.
+pub struct PortListing {
+    pub name: String,
+    /// USB ports only
+    pub usb: Option<serialport::UsbPortInfo>,
+    pub kind: Option<DeviceKind>,
+}
+
+/// Every serial port on the system, crispy devices first.
+pub fn list_ports() -> Result<Vec<PortListing>> {
+    let mut ports: Vec<_> = serialport::available_ports()
+        .context("Failed to enumerate serial ports")?
+        .into_iter()
+        .map(|p| {
+            let usb = match p.port_type {
+                SerialPortType::UsbPort(info) => Some(info),
+                _ => None,
+            };
+            let kind = usb.as_ref().and_then(|info| match (info.vid, info.pid) {
+                (USB_VID, BOOTLOADER_PID) => Some(DeviceKind::Bootloader),
+                (USB_VID, FIRMWARE_PID) => Some(DeviceKind::Firmware),
+                _ => None,
+            });
+            PortListing {
+                name: p.port_name,
+                usb,
+                kind,
+            }
+        })
+        .collect();
+
+    ports.sort_by(|a, b| (a.kind.is_none(), &a.name).cmp(&(b.kind.is_none(), &b.name)));
+    Ok(ports)
+}
+
+/// USB serial number of `port_name`, if it is a USB port that has one.
+pub fn port_serial(port_name: &str) -> Option<String> {
+    list_ports()
+        .ok()?
+        .into_iter()
+        .find(|p| p.name == port_name)?
+        .usb?
+        .serial_number
+}
+
+/// `Response` as released bootloaders before the status flags encode it:
+/// the same variant order, and a `Status` with four fields.
+#[derive(Deserialize)]
+enum LegacyResponse {
+    #[allow(dead_code)] // only here to keep `Status` at its index
+    Ack(AckStatus),
+    Status {
+        active_bank: u8,
+        version_a: FwVersion,
+        version_b: FwVersion,
+        state: BootState,
+    },
+}
+
+/// Decode `frame` as the `Status` of a bootloader before the status flags,
+/// with the flags it cannot report as `false`.
+fn legacy_status(frame: &[u8]) -> Option<Response> {
+    match postcard::take_from_bytes::<LegacyResponse>(frame) {
+        Ok((
+            LegacyResponse::Status {
+                active_bank,
+                version_a,
+                version_b,
+                state,
+            },
+            [],
+        )) => Some(Response::Status {
+            active_bank,
+            version_a,
+            version_b,
+            state,
+            host_connected: false,
+            tx_stalled: false,
+            bootdata_reconstructed: false,
+            update_interrupted: false,
+        }),
+        _ => None,
+    }
+}
+
+/// Transport for communicating with the bootloader.
 pub struct Transport {
-    port: Box<dyn SerialPort>,
-    rx_buf: Vec<u8>,
+    port: Box<dyn Link>,
+    opts: LinkOptions,
+    rx: Box<CobsFrameDecoder<RX_FRAME_SIZE>>,
+    /// Bytes read from the port; `rx_buf[rx_pos..rx_len]` have not been
+    /// fed to the decoder yet, the start of the next frame.
+    rx_buf: Box<[u8; RX_BUF_SIZE]>,
+    rx_pos: usize,
+    rx_len: usize,
+    /// `rx.dropped_frames()` already counted as discarded.
+    rx_dropped: u32,
+    /// Why the most recently discarded frame was not a response.
+    rx_last_discard: Option<String>,
+    /// Encoded bytes of the frame being received, kept for `--trace`.
+    rx_raw: Vec<u8>,
+    /// The last `Status` came in the legacy encoding, without flags.
+    legacy_status: bool,
+    retry: RetryPolicy,
+    stats: Stats,
+    /// USB serial number of the device, to find it again after it
+    /// re-enumerates.
+    serial: Option<String>,
 }
 
 impl Transport {
-    /// Create a new transport connection to the specified serial port.
-    pub fn new(port_name: &str) -> Result<Self> {
-        Self::with_timeout(port_name, DEFAULT_TIMEOUT_MS)
+    /// Create a new transport connection to the specified port, waiting
+    /// for it to appear for as long as `opts` allows.
+    pub fn open(port_name: &str, opts: &LinkOptions) -> Result<Self> {
+        let port = wait_for(opts.wait, || link::open(port_name, opts), |_| true)?;
+        let serial = match link::tcp_address(port_name) {
+            Some(_) => None,
+            None => port_serial(port_name),
+        };
+        let mut transport = Self {
+            port,
+            opts: *opts,
+            rx: Box::default(),
+            rx_buf: Box::new([0; RX_BUF_SIZE]),
+            rx_pos: 0,
+            rx_len: 0,
+            rx_dropped: 0,
+            rx_last_discard: None,
+            rx_raw: Vec::new(),
+            legacy_status: false,
+            retry: RetryPolicy::DEFAULT,
+            stats: Stats::default(),
+            serial,
+        };
+        // Whatever was on the line before we came (the rest of an answer,
+        // or what a terminal sent) must not be taken for the first answer
+        transport.resync()?;
+        Ok(transport)
     }
 
-    /// Create a new transport connection with a custom timeout.
-    pub fn with_timeout(port_name: &str, timeout_ms: u64) -> Result<Self> {
-        let port = serialport::new(port_name, 115200)
-            .timeout(Duration::from_millis(timeout_ms))
-            .open()
-            .with_context(|| format!("Failed to open serial port {}", port_name))?;
+    /// Reopen the connection after the device reset or re-enumerated,
+    /// waiting up to `within` for it to come back.
+    ///
+    /// A USB device is found again by its serial number among bootloader
+    /// ports, since it may come back under another name (a new COM port on
+    /// Windows); otherwise the same port is reopened.
+    pub fn reconnect(&mut self, within: Duration) -> Result<()> {
+        let name = self.port_name();
+        // Let go of the old handle first, or the port cannot come back
+        // under the same name
+        self.port = Box::new(link::Closed(name.clone()));
+        self.rx.reset();
+        (self.rx_pos, self.rx_len) = (0, 0);
+        self.rx_raw.clear();
+        thread::sleep(REOPEN_DELAY);
 
-        Ok(Self {
-            port,
-            rx_buf: Vec::with_capacity(4096),
-        })
+        let (serial, opts) = (self.serial.clone(), self.opts);
+        let reopen = || {
+            let port = match &serial {
+                Some(serial) => find_bootloader_port(Some(serial))?,
+                None => name.clone(),
+            };
+            link::open(&port, &opts)
+        };
+        self.port = wait_for(within, reopen, |_| true).map_err(|err| {
+            Failure::new(
+                "disconnected",
+                format!(
+                    "{} did not come back within {} s: {:#}",
+                    name,
+                    within.as_secs(),
+                    err
+                ),
+            )
+            .with("port", name.as_str())
+        })?;
+        self.resync()?;
+        outln!("Reconnected on {}", self.port_name());
+        Ok(())
+    }
+
+    /// The timeouts this connection was opened with, to open the next one
+    /// the same way.
+    pub fn link_options(&self) -> LinkOptions {
+        self.opts
+    }
+
+    /// Use `policy` for [`Transport::send_recv_retry`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// The policy [`Transport::send_recv_retry`] uses, to open the next
+    /// connection the same way.
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Retries made so far on this connection.
+    pub fn retries(&self) -> u32 {
+        self.stats.retries
+    }
+
+    /// Traffic and timing on this connection so far.
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Whether the last `Status` came from a bootloader that predates the
+    /// status flags (`host_connected` and the rest), which it then reports
+    /// as `false` without knowing them.
+    pub fn legacy_status(&self) -> bool {
+        self.legacy_status
     }
 
     /// Get the port name.
     pub fn port_name(&self) -> String {
-        self.port.name().unwrap_or_else(|| "?".to_string())
+        self.port.name()
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        let written = self.port.write_all(bytes).and_then(|()| self.port.flush());
+        match written {
+            Ok(()) => {
+                self.stats.bytes_sent += bytes.len() as u64;
+                Ok(())
+            }
+            Err(e) if link::is_disconnect(&e) => bail!(link::disconnected(&self.port_name(), &e)),
+            Err(e) => bail!("Failed to write to {}: {}", self.port_name(), e),
+        }
     }
 
     /// Send a command to the bootloader.
@@ -48,77 +594,938 @@ impl Transport {
         let mut buf = [0u8; 2048];
         let encoded = postcard::to_slice_cobs(cmd, &mut buf)
             .map_err(|e| anyhow::anyhow!("Failed to serialize command: {}", e))?;
-        self.port
-            .write_all(encoded)
-            .map_err(|e| anyhow::anyhow!("Failed to write to serial port: {}", e))?;
-        self.port.flush()?;
-        Ok(())
+        trace::frame(Direction::ToDevice, || command_name(cmd), encoded);
+        self.write_all(encoded)
     }
 
     /// Receive a response from the bootloader.
+    ///
+    /// Reads take whatever has arrived, up to [`RX_BUF_SIZE`] bytes at a
+    /// time; bytes after the end of the frame are kept for the next call.
+    ///
+    /// Garbage on the link (the tail of an answer to someone else, or
+    /// whatever a terminal sent) is discarded up to the next delimiter, and
+    /// reading goes on until a response decodes or the read timeout has
+    /// passed. Discarded frames are counted in [`Stats::discarded_frames`].
+    ///
+    /// The `Status` of a bootloader that predates the status flags is
+    /// accepted too, with the flags `false`; see
+    /// [`legacy_status`](Self::legacy_status).
     pub fn receive(&mut self) -> Result<Response> {
-        self.rx_buf.clear();
-        let mut byte = [0u8; 1];
+        let tracing = trace::enabled();
+        let deadline = Instant::now() + self.port.timeout();
 
-        // Read until we get delimiter (0x00)
+        // Feed bytes to the decoder until it completes a frame
         loop {
-            match self.port.read(&mut byte) {
-                Ok(1) => {
-                    self.rx_buf.push(byte[0]);
-                    if byte[0] == 0 {
-                        break;
+            while self.rx_pos < self.rx_len {
+                let byte = self.rx_buf[self.rx_pos];
+                self.rx_pos += 1;
+                if tracing {
+                    self.rx_raw.push(byte);
+                }
+                let Some(frame) = self.rx.feed(byte) else {
+                    if byte == 0 && self.rx.dropped_frames() != self.rx_dropped {
+                        self.rx_dropped = self.rx.dropped_frames();
+                        let reason = self.rx.last_error().map(|e| e.to_string());
+                        self.discard(reason.unwrap_or_default(), deadline)?;
+                    }
+                    continue;
+                };
+                let response = match postcard::from_bytes::<Response>(frame) {
+                    Ok(response) => {
+                        if matches!(response, Response::Status { .. }) {
+                            self.legacy_status = false;
+                        }
+                        Ok(response)
                     }
+                    Err(e) => legacy_status(frame)
+                        .inspect(|_| self.legacy_status = true)
+                        .ok_or_else(|| {
+                            format!(
+                                "undecodable response: {} (decoded {} bytes: {:02x?})",
+                                e,
+                                frame.len(),
+                                &frame[..frame.len().min(32)]
+                            )
+                        }),
+                };
+                match response {
+                    Ok(response) => {
+                        if tracing {
+                            let name = || response_name(&response);
+                            trace::frame(Direction::FromDevice, name, &self.rx_raw);
+                            self.rx_raw.clear();
+                        }
+                        return Ok(response);
+                    }
+                    Err(reason) => self.discard(reason, deadline)?,
+                }
+            }
+
+            match self.port.read(&mut self.rx_buf[..]) {
+                Ok(n) => {
+                    self.stats.bytes_received += n as u64;
+                    (self.rx_pos, self.rx_len) = (0, n);
                 }
-                Ok(_) => continue,
-                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                    bail!("Timeout waiting for response");
+                Err(e) if e.kind() == std::io::ErrorKind::TimedOut => bail!(self.timed_out()),
+                Err(e) if link::is_disconnect(&e) => {
+                    bail!(link::disconnected(&self.port_name(), &e))
                 }
-                Err(e) => bail!("Serial read error: {}", e),
+                Err(e) => bail!("Read error on {}: {}", self.port_name(), e),
             }
         }
+    }
 
-        // Use postcard's COBS decoder for consistency with bootloader
-        postcard::from_bytes_cobs(&mut self.rx_buf).map_err(|e| {
-            anyhow::anyhow!(
-                "Failed to deserialize response: {} (raw {} bytes: {:02x?})",
-                e,
-                self.rx_buf.len(),
-                &self.rx_buf[..self.rx_buf.len().min(32)]
+    /// Count a frame that was not a response, and give up once `deadline`
+    /// has passed, so a stream of garbage still times out.
+    fn discard(&mut self, reason: String, deadline: Instant) -> Result<()> {
+        self.stats.discarded_frames += 1;
+        if !self.rx_raw.is_empty() {
+            trace::frame(
+                Direction::FromDevice,
+                || "discarded".to_string(),
+                &self.rx_raw,
+            );
+            self.rx_raw.clear();
+        }
+        self.rx_last_discard = Some(reason);
+        if Instant::now() >= deadline {
+            bail!(self.timed_out());
+        }
+        Ok(())
+    }
+
+    fn timed_out(&self) -> Failure {
+        match &self.rx_last_discard {
+            None => Failure::new("timeout", "Timeout waiting for response"),
+            Some(last) => Failure::new(
+                "timeout",
+                format!(
+                    "Timeout waiting for response ({} frames discarded, last: {})",
+                    self.stats.discarded_frames, last
+                ),
             )
-        })
+            .with("discarded_frames", self.stats.discarded_frames),
+        }
+    }
+
+    /// Send a lone frame delimiter, so the device drops whatever partial
+    /// frame a lost or garbled transfer left in its decoder, then discard
+    /// anything still on its way to us.
+    pub fn resync(&mut self) -> Result<()> {
+        trace::frame(Direction::ToDevice, || "resync".to_string(), &[0]);
+        self.write_all(&[0])?;
+        self.drain_rx();
+        Ok(())
     }
 
     fn drain_rx(&mut self) {
+        let pending = &self.rx_buf[self.rx_pos..self.rx_len];
+        self.rx_raw.extend_from_slice(pending);
+        (self.rx_pos, self.rx_len) = (0, 0);
+
         let mut buf = [0u8; 64];
         let old_timeout = self.port.timeout();
         let _ = self.port.set_timeout(Duration::from_millis(10));
-        while self.port.read(&mut buf).unwrap_or(0) > 0 {}
+        loop {
+            match self.port.read(&mut buf) {
+                Ok(n) if n > 0 => {
+                    self.stats.bytes_received += n as u64;
+                    self.rx_raw.extend_from_slice(&buf[..n]);
+                }
+                _ => break,
+            }
+        }
         let _ = self.port.set_timeout(old_timeout);
+        self.rx.reset();
+
+        // Whatever was left of a frame, and anything after it
+        if !self.rx_raw.is_empty() {
+            trace::frame(
+                Direction::FromDevice,
+                || "discarded".to_string(),
+                &self.rx_raw,
+            );
+            self.rx_raw.clear();
+        }
+    }
+
+    /// How long to wait for the answer to `cmd`: commands that erase flash
+    /// or go through whole banks before answering get longer.
+    pub fn timeout_for(&self, cmd: &Command) -> Duration {
+        match cmd {
+            Command::StartUpdate { .. } | Command::WipeAll | Command::CopyBank { .. } => {
+                self.opts.erase_timeout
+            }
+            Command::FinishUpdate | Command::GetBootData | Command::BlankCheck { .. } => {
+                self.opts.check_timeout
+            }
+            _ => self.opts.read_timeout,
+        }
     }
 
-    /// Send a command and wait for the response.
+    /// Send a command and wait for the response, as long as
+    /// [`LinkOptions`] allows for that command.
+    ///
+    /// `Progress` frames sent during long operations are skipped. Once one
+    /// arrives, the wait becomes an inactivity timeout: the read timeout,
+    /// restarted by every frame.
     pub fn send_recv(&mut self, cmd: &Command) -> Result<Response> {
-        self.drain_rx();
-        self.send(cmd)?;
-        self.receive()
+        self.send_recv_with_progress(cmd, |_, _| {})
+    }
+
+    /// Like [`Transport::send_recv`], but re-send `cmd` after a timeout, a
+    /// disconnect or a response `retriable` accepts, as often as the retry
+    /// policy allows.
+    ///
+    /// Each retry is logged, waits out the backoff and resynchronizes the
+    /// framing first; after a disconnect it [reconnects](Self::reconnect)
+    /// instead. Any other response, including refusals such as `BadState`,
+    /// is returned at once; after the last retry the final outcome is
+    /// returned as is.
+    pub fn send_recv_retry(
+        &mut self,
+        cmd: &Command,
+        retriable: impl Fn(&Response) -> bool,
+    ) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let outcome = self.send_recv(cmd);
+            let reason = match &outcome {
+                Err(err) if is_timeout(err) => "timeout".to_string(),
+                Err(err) if is_disconnected(err) => "disconnect".to_string(),
+                Ok(response) if retriable(response) => format!("{:?}", response),
+                _ => return outcome,
+            };
+            if attempt == self.retry.retries {
+                return outcome;
+            }
+
+            attempt += 1;
+            self.stats.retries += 1;
+            let delay = self.retry.delay(attempt);
+            outln!(
+                "Retry {}/{}: {} after {} (waiting {} ms)",
+                attempt,
+                self.retry.retries,
+                command_name(cmd),
+                reason,
+                delay.as_millis()
+            );
+            if is_disconnected(outcome.as_ref().unwrap_err()) {
+                self.reconnect(self.opts.wait.max(RECONNECT_WAIT))?;
+            } else {
+                thread::sleep(delay);
+                self.resync()?;
+            }
+        }
+    }
+
+    /// Like [`Transport::send_recv`], passing each `Progress` frame's
+    /// `(done, total)` to `on_progress`.
+    pub fn send_recv_with_progress(
+        &mut self,
+        cmd: &Command,
+        mut on_progress: impl FnMut(u32, u32),
+    ) -> Result<Response> {
+        let timeout = self.timeout_for(cmd);
+        self.send_recv_within(cmd, timeout, &|_| true, &mut on_progress)
+    }
+
+    /// Read `len` bytes of flash at `addr` with `ReadFlash` requests of at
+    /// most `chunk` bytes, passing each piece and its offset from `addr` to
+    /// `on_data`, which may stop the read early.
+    pub fn read_flash(
+        &mut self,
+        addr: u32,
+        len: u32,
+        chunk: u32,
+        mut on_data: impl FnMut(u32, &[u8]) -> ControlFlow<()>,
+    ) -> Result<()> {
+        let mut offset = 0;
+        while offset < len {
+            let at = addr + offset;
+            let length = chunk.min(len - offset);
+            match self.send_recv_retry(&Command::ReadFlash { addr: at, length }, |_| false)? {
+                Response::FlashData { addr, data }
+                    if addr == at && data.len() == length as usize =>
+                {
+                    if on_data(offset, &data).is_break() {
+                        return Ok(());
+                    }
+                }
+                Response::FlashData { addr, data } => bail!(
+                    "Asked for {} bytes at 0x{:08x}, got {} bytes at 0x{:08x}",
+                    length,
+                    at,
+                    data.len(),
+                    addr
+                ),
+                Response::Ack(AckStatus::BadCommand) => bail!(
+                    "Device refused to read 0x{:08x}..0x{:08x} (bootloader without flash reads?)",
+                    at,
+                    at + length
+                ),
+                other => bail!("Unexpected response to ReadFlash: {:?}", other),
+            }
+            offset += length;
+        }
+        Ok(())
     }
 
-    /// Send a command and wait for the response with a custom timeout.
-    pub fn send_recv_timeout(&mut self, cmd: &Command, timeout_ms: u64) -> Result<Response> {
-        // Save current timeout
+    /// Send a command and wait up to `timeout` for a response `expected`
+    /// accepts. Others, such as the late answer to an earlier query that
+    /// timed out, are discarded like undecodable frames.
+    pub fn send_recv_expecting(
+        &mut self,
+        cmd: &Command,
+        timeout: Duration,
+        expected: impl Fn(&Response) -> bool,
+    ) -> Result<Response> {
+        self.send_recv_within(cmd, timeout, &expected, &mut |_, _| {})
+    }
+
+    fn send_recv_within(
+        &mut self,
+        cmd: &Command,
+        timeout: Duration,
+        expected: &dyn Fn(&Response) -> bool,
+        on_progress: &mut dyn FnMut(u32, u32),
+    ) -> Result<Response> {
         let old_timeout = self.port.timeout();
+        let result = self.exchange(cmd, timeout, expected, on_progress);
+        let _ = self.port.set_timeout(old_timeout);
+        result
+    }
+
+    fn exchange(
+        &mut self,
+        cmd: &Command,
+        timeout: Duration,
+        expected: &dyn Fn(&Response) -> bool,
+        on_progress: &mut dyn FnMut(u32, u32),
+    ) -> Result<Response> {
+        self.set_timeout(timeout)?;
+        self.drain_rx();
+        let sent = Instant::now();
+        self.send(cmd)?;
+        loop {
+            match self.receive()? {
+                Response::Progress { done, total } => {
+                    on_progress(done, total);
+                    // The device is alive and says so; time out on silence
+                    self.set_timeout(self.opts.read_timeout.min(timeout))?;
+                }
+                response if !expected(&response) => {
+                    let left = timeout.saturating_sub(sent.elapsed());
+                    self.discard(
+                        format!("unexpected {}", response_name(&response)),
+                        Instant::now() + left,
+                    )?;
+                    self.set_timeout(left)?;
+                }
+                response => {
+                    if !waits_on_flash(cmd) {
+                        self.stats.round_trips += 1;
+                        self.stats.round_trip_time += sent.elapsed();
+                    }
+                    return Ok(response);
+                }
+            }
+        }
+    }
 
-        // Set new timeout
+    fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
         self.port
-            .set_timeout(Duration::from_millis(timeout_ms))
-            .map_err(|e| anyhow::anyhow!("Failed to set timeout: {}", e))?;
+            .set_timeout(timeout)
+            .map_err(|e| anyhow::anyhow!("Failed to set timeout: {}", e))
+    }
+}
 
-        // Send and receive
-        let result = self.send_recv(cmd);
+/// Whether the device erases flash or goes through whole banks before
+/// answering `cmd`.
+fn waits_on_flash(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::StartUpdate { .. }
+            | Command::WipeAll
+            | Command::CopyBank { .. }
+            | Command::FinishUpdate
+            | Command::GetBootData
+            | Command::BlankCheck { .. }
+    )
+}
 
-        // Restore old timeout
-        let _ = self.port.set_timeout(old_timeout);
+/// Name of a command for log lines, with the offset of a data block.
+fn command_name(cmd: &Command) -> String {
+    match cmd {
+        Command::DataBlock { offset, .. } => format!("DataBlock at offset 0x{:x}", offset),
+        Command::ReadFlash { addr, .. } => format!("ReadFlash at 0x{:08x}", addr),
+        other => format!("{:?}", other)
+            .split([' ', '{', '('])
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
 
-        result
+/// Name of a response for trace lines, with the status of an
+/// acknowledgement and where data or progress stands.
+fn response_name(response: &Response) -> String {
+    match response {
+        Response::Ack(status) => format!("Ack({:?})", status),
+        Response::Nack { status, offset } => format!("Nack({:?} at offset 0x{:x})", status, offset),
+        Response::FlashData { addr, .. } => format!("FlashData at 0x{:08x}", addr),
+        Response::Progress { done, total } => format!("Progress {}/{}", done, total),
+        other => format!("{:?}", other)
+            .split([' ', '{', '('])
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use crispy_common::protocol::BootState;
+    use crispy_common::FwVersion;
+
+    /// A bridge with a fake bootloader behind it: decodes each command and
+    /// sends back what `answer` returns, or nothing for `None`. Stops when
+    /// `answer` has been called `commands` times, closing the connection.
+    fn mock_bridge(
+        commands: usize,
+        mut answer: impl FnMut(Command) -> Option<Response> + Send + 'static,
+    ) -> (String, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut rx = CobsFrameDecoder::<RX_FRAME_SIZE>::new();
+            let mut seen = 0;
+            let mut byte = [0u8; 1];
+            while seen < commands && stream.read(&mut byte).unwrap_or(0) == 1 {
+                // A lone delimiter (resync) completes no frame
+                let Some(frame) = rx.feed(byte[0]) else {
+                    continue;
+                };
+                seen += 1;
+                if let Some(response) = answer(postcard::from_bytes(frame).unwrap()) {
+                    let encoded = postcard::to_stdvec_cobs(&response).unwrap();
+                    stream.write_all(&encoded).unwrap();
+                }
+            }
+        });
+        (port, server)
+    }
+
+    fn quick() -> LinkOptions {
+        LinkOptions {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_millis(200),
+            wait: Duration::ZERO,
+            ..LinkOptions::DEFAULT
+        }
+    }
+
+    fn status() -> Response {
+        Response::Status {
+            active_bank: 1,
+            version_a: FwVersion::new(1, 0, 0),
+            version_b: FwVersion::new(1, 1, 0),
+            state: BootState::UpdateMode,
+            host_connected: true,
+            tx_stalled: false,
+            bootdata_reconstructed: false,
+            update_interrupted: false,
+        }
+    }
+
+    #[test]
+    fn test_tcp_bridge_round_trip() {
+        let (port, server) = mock_bridge(2, |cmd| match cmd {
+            Command::GetStatus => Some(status()),
+            _ => Some(Response::Ack(AckStatus::BadCommand)),
+        });
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+        assert_eq!(transport.port_name(), port);
+
+        let response = transport.send_recv(&Command::GetStatus).unwrap();
+        assert!(matches!(response, Response::Status { active_bank: 1, .. }));
+        let response = transport.send_recv(&Command::Reboot).unwrap();
+        assert!(matches!(response, Response::Ack(AckStatus::BadCommand)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_tcp_bridge_retries_a_lost_answer() {
+        let mut first = true;
+        let (port, server) = mock_bridge(2, move |_| {
+            // Swallow the first command, as if its frame was lost
+            (!std::mem::take(&mut first)).then(status)
+        });
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+        transport.set_retry_policy(RetryPolicy {
+            retries: 2,
+            backoff: Duration::from_millis(1),
+        });
+
+        let response = transport
+            .send_recv_retry(&Command::GetStatus, |_| false)
+            .unwrap();
+        assert!(matches!(response, Response::Status { .. }));
+        assert_eq!(transport.retries(), 1);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let (port, server) = mock_bridge(3, |cmd| match cmd {
+            Command::WipeAll => Some(Response::Ack(AckStatus::Ok)),
+            _ => Some(status()),
+        });
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+
+        transport.send_recv(&Command::GetStatus).unwrap();
+        let before = transport.stats();
+        assert_eq!(before.round_trips, 1);
+        // The delimiter sent on open, then the command
+        assert_eq!(before.bytes_sent, 4);
+        assert!(before.bytes_received > 0);
+        assert!(before.average_round_trip().is_some());
+
+        // An erase is not a round trip worth averaging
+        transport.send_recv(&Command::WipeAll).unwrap();
+        transport.send_recv(&Command::GetStatus).unwrap();
+        let stats = transport.stats().since(&before);
+        assert_eq!(stats.round_trips, 1);
+        assert_eq!(stats.bytes_sent, 6);
+        assert_eq!(stats.retries, 0);
+        assert_eq!(Stats::default().average_round_trip(), None);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_long_operations_wait_longer() {
+        let (port, server) = mock_bridge(2, |cmd| {
+            thread::sleep(Duration::from_millis(500));
+            match cmd {
+                Command::WipeAll => Some(Response::Ack(AckStatus::Ok)),
+                _ => Some(status()),
+            }
+        });
+        let opts = LinkOptions {
+            erase_timeout: Duration::from_secs(5),
+            ..quick()
+        };
+        let mut transport = Transport::open(&port, &opts).unwrap();
+
+        let response = transport.send_recv(&Command::WipeAll).unwrap();
+        assert!(matches!(response, Response::Ack(AckStatus::Ok)));
+        let err = transport.send_recv(&Command::GetStatus).unwrap_err();
+        assert!(is_timeout(&err));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_progress_turns_the_wait_into_an_inactivity_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Wait for the command, past the delimiter sent on open
+            let (mut byte, mut len) = ([0u8; 1], 0);
+            while stream.read(&mut byte).unwrap_or(0) == 1 && (byte[0] != 0 || len == 0) {
+                len += usize::from(byte[0] != 0);
+            }
+            let progress = Response::Progress { done: 1, total: 2 };
+            stream
+                .write_all(&postcard::to_stdvec_cobs(&progress).unwrap())
+                .unwrap();
+            // Then silence, as from a device that hung
+            thread::sleep(Duration::from_millis(1500));
+        });
+        let opts = LinkOptions {
+            check_timeout: Duration::from_secs(10),
+            ..quick()
+        };
+        let mut transport = Transport::open(&port, &opts).unwrap();
+
+        let started = Instant::now();
+        let err = transport.send_recv(&Command::GetBootData).unwrap_err();
+        assert!(is_timeout(&err));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        server.join().unwrap();
+    }
+
+    /// Read one command frame from `stream`.
+    fn read_frame(stream: &mut impl Read) -> Command {
+        let mut rx = CobsFrameDecoder::<RX_FRAME_SIZE>::new();
+        let mut byte = [0u8; 1];
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if let Some(frame) = rx.feed(byte[0]) {
+                return postcard::from_bytes(frame).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_retry_reconnects_after_a_reset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            // The device resets on the first command, dropping the link
+            let (mut stream, _) = listener.accept().unwrap();
+            read_frame(&mut stream);
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().unwrap();
+            assert!(matches!(read_frame(&mut stream), Command::GetStatus));
+            let encoded = postcard::to_stdvec_cobs(&status()).unwrap();
+            stream.write_all(&encoded).unwrap();
+        });
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+
+        let response = transport
+            .send_recv_retry(&Command::GetStatus, |_| false)
+            .unwrap();
+        assert!(matches!(response, Response::Status { .. }));
+        assert_eq!(transport.retries(), 1);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reconnect_gives_up() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+        // The device never comes back
+        drop(listener);
+
+        let err = transport.reconnect(Duration::from_millis(300)).unwrap_err();
+        assert!(is_disconnected(&err));
+        assert!(err.to_string().contains("did not come back"));
+        assert!(transport.send(&Command::GetStatus).is_err());
+    }
+
+    #[test]
+    fn test_tcp_bridge_disconnect() {
+        let (port, server) = mock_bridge(1, |_| None);
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+
+        let err = transport.send_recv(&Command::GetStatus).unwrap_err();
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert_eq!(failure.code, "disconnected");
+        assert!(!is_timeout(&err));
+        server.join().unwrap();
+    }
+
+    /// A link that hands out `chunks` one per read, then `forever` on every
+    /// read if it is not empty, else times out.
+    struct Chunks {
+        chunks: VecDeque<Vec<u8>>,
+        forever: Vec<u8>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            let chunk = match self.chunks.pop_front() {
+                Some(chunk) => chunk,
+                None if !self.forever.is_empty() => self.forever.clone(),
+                None => return Err(std::io::ErrorKind::TimedOut.into()),
+            };
+            buf[..chunk.len()].copy_from_slice(&chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Link for Chunks {
+        fn name(&self) -> String {
+            "chunks".to_string()
+        }
+
+        fn timeout(&self) -> Duration {
+            quick().read_timeout
+        }
+
+        fn set_timeout(&mut self, _timeout: Duration) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A transport reading `chunks` and then `forever`, and the count of
+    /// its reads.
+    fn chunked(chunks: Vec<Vec<u8>>, forever: Vec<u8>) -> (Transport, Arc<AtomicUsize>) {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let transport = Transport {
+            port: Box::new(Chunks {
+                chunks: chunks.into(),
+                forever,
+                reads: reads.clone(),
+            }),
+            opts: quick(),
+            rx: Box::default(),
+            rx_buf: Box::new([0; RX_BUF_SIZE]),
+            rx_pos: 0,
+            rx_len: 0,
+            rx_dropped: 0,
+            rx_last_discard: None,
+            rx_raw: Vec::new(),
+            legacy_status: false,
+            retry: RetryPolicy::DEFAULT,
+            stats: Stats::default(),
+            serial: None,
+        };
+        (transport, reads)
+    }
+
+    fn frame(response: &Response) -> Vec<u8> {
+        postcard::to_stdvec_cobs(response).unwrap()
+    }
+
+    #[test]
+    fn test_receive_reads_in_blocks() {
+        let data = Response::FlashData {
+            addr: crispy_common::FW_A_ADDR,
+            data: (0..1024).map(|i| i as u8).collect(),
+        };
+        let ack = frame(&Response::Ack(AckStatus::Ok));
+        let status_frame = frame(&status());
+
+        // The end of a dropped frame, a whole frame and the start of the
+        // next in one read; the rest of it and one more frame in the next
+        let (head, tail) = status_frame.split_at(3);
+        let first = [&[0x17, 0x42, 0x00][..], &frame(&data), head].concat();
+        let second = [tail, &ack].concat();
+        let total = (first.len() + second.len()) as u64;
+        let (mut transport, reads) = chunked(vec![first, second], Vec::new());
+
+        assert_eq!(transport.receive().unwrap(), data);
+        assert_eq!(reads.load(Ordering::Relaxed), 1);
+        assert!(matches!(
+            transport.receive().unwrap(),
+            Response::Status { .. }
+        ));
+        assert_eq!(transport.receive().unwrap(), Response::Ack(AckStatus::Ok));
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+        assert_eq!(transport.stats().bytes_received, total);
+        assert!(is_timeout(&transport.receive().unwrap_err()));
+    }
+
+    #[test]
+    fn test_receive_accepts_legacy_status() {
+        // The baseline encoding: variant 1 and four fields, no flags
+        let legacy = (
+            1u8,
+            1u8,
+            FwVersion::new(1, 0, 0),
+            FwVersion::new(1, 1, 0),
+            BootState::UpdateMode,
+        );
+        let legacy = postcard::to_stdvec_cobs(&legacy).unwrap();
+        let (mut transport, _) = chunked(vec![[legacy, frame(&status())].concat()], Vec::new());
+
+        assert_eq!(
+            transport.receive().unwrap(),
+            Response::Status {
+                active_bank: 1,
+                version_a: FwVersion::new(1, 0, 0),
+                version_b: FwVersion::new(1, 1, 0),
+                state: BootState::UpdateMode,
+                host_connected: false,
+                tx_stalled: false,
+                bootdata_reconstructed: false,
+                update_interrupted: false,
+            }
+        );
+        assert!(transport.legacy_status());
+        assert_eq!(transport.receive().unwrap(), status());
+        assert!(!transport.legacy_status());
+        assert_eq!(transport.stats().discarded_frames, 0);
+    }
+
+    #[test]
+    fn test_send_recv_expecting_discards_other_answers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = format!("tcp://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut rx = CobsFrameDecoder::<RX_FRAME_SIZE>::new();
+            let mut byte = [0u8; 1];
+            while stream.read(&mut byte).unwrap_or(0) == 1 && rx.feed(byte[0]).is_none() {}
+            // The late answer to an earlier query, then the one asked for
+            let answers = [frame(&status()), frame(&Response::Ack(AckStatus::Ok))];
+            stream.write_all(&answers.concat()).unwrap();
+            let _ = stream.read(&mut byte);
+        });
+        let mut transport = Transport::open(&port, &quick()).unwrap();
+
+        let response = transport
+            .send_recv_expecting(&Command::Reboot, Duration::from_secs(1), |response| {
+                matches!(response, Response::Ack(_))
+            })
+            .unwrap();
+        assert_eq!(response, Response::Ack(AckStatus::Ok));
+        assert_eq!(transport.stats().discarded_frames, 1);
+        drop(transport);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_receive_skips_garbage() {
+        let ack = frame(&Response::Ack(AckStatus::Ok));
+        // No response: a variant tag past the last one
+        let undecodable = postcard::to_stdvec_cobs(&[0xee_u8, 0xee, 0xee, 0x01]).unwrap();
+        // Noise inside a frame, over its variant tag
+        let mut broken = frame(&status());
+        broken[1] = 0xee;
+
+        let stream = [
+            // Before: what a terminal typed, then the tail of an answer
+            &b"AT\r\n"[..],
+            &[0x00, 0x05, 0x01, 0x00],
+            &broken,
+            &ack,
+            // Between
+            &undecodable,
+            &[0x00, 0x00],
+            &frame(&status()),
+        ]
+        .concat();
+        let (mut transport, _) = chunked(vec![stream], Vec::new());
+
+        assert_eq!(transport.receive().unwrap(), Response::Ack(AckStatus::Ok));
+        assert_eq!(transport.stats().discarded_frames, 3);
+        assert!(matches!(
+            transport.receive().unwrap(),
+            Response::Status { .. }
+        ));
+        assert_eq!(transport.stats().discarded_frames, 4);
+
+        let err = transport.receive().unwrap_err();
+        assert!(is_timeout(&err));
+        assert!(err.to_string().contains("4 frames discarded"), "{}", err);
+    }
+
+    #[test]
+    fn test_persistent_garbage_times_out() {
+        // Frames keep coming, none of them a response
+        let noise = postcard::to_stdvec_cobs(&[0xee_u8, 0xee, 0xee, 0x01]).unwrap();
+        let (mut transport, _) = chunked(Vec::new(), noise);
+
+        let begun = Instant::now();
+        let err = transport.receive().unwrap_err();
+        assert!(is_timeout(&err));
+        assert!(begun.elapsed() >= quick().read_timeout);
+        let failure = err.downcast_ref::<Failure>().unwrap();
+        assert!(failure.context["discarded_frames"].as_u64().unwrap() > 1);
+    }
+
+    #[test]
+    fn test_retry_backoff_doubles() {
+        let policy = RetryPolicy {
+            retries: 4,
+            backoff: Duration::from_millis(50),
+        };
+        let delays: Vec<_> = (1..=4).map(|n| policy.delay(n).as_millis()).collect();
+        assert_eq!(delays, [50, 100, 200, 400]);
+    }
+
+    #[test]
+    fn test_timeouts_are_recognised() {
+        let timeout = anyhow::Error::new(Failure::new("timeout", "Timeout waiting for response"));
+        assert!(is_timeout(&timeout));
+        assert!(!is_timeout(&anyhow::anyhow!("Serial read error")));
+        assert!(!is_timeout(&anyhow::Error::new(Failure::new(
+            "device",
+            "Wipe failed: BadState"
+        ))));
+    }
+
+    #[test]
+    fn test_response_name() {
+        assert_eq!(response_name(&Response::Ack(AckStatus::Ok)), "Ack(Ok)");
+        assert_eq!(
+            response_name(&Response::Nack {
+                status: AckStatus::FlashError,
+                offset: 0x1000
+            }),
+            "Nack(FlashError at offset 0x1000)"
+        );
+        assert_eq!(
+            response_name(&Response::Progress { done: 3, total: 8 }),
+            "Progress 3/8"
+        );
+        assert_eq!(response_name(&status()), "Status");
+    }
+
+    #[test]
+    fn test_wait_for_polls_until_found() {
+        let mut attempts = 0;
+        let found = wait_for(
+            Duration::from_secs(5),
+            || {
+                attempts += 1;
+                match attempts {
+                    3 => Ok("/dev/ttyACM0"),
+                    _ => Err(Failure::new("no_device", "No crispy device found").into()),
+                }
+            },
+            is_nothing_found,
+        );
+        assert_eq!(found.unwrap(), "/dev/ttyACM0");
+        assert_eq!(attempts, 3);
+
+        // Several devices is not fixed by waiting, nor is anything without --wait
+        let several = || -> Result<()> {
+            bail!(Failure::new("no_device", "Several found").with("ports", vec!["a", "b"]))
+        };
+        assert!(wait_for(Duration::from_secs(5), several, is_nothing_found).is_err());
+        let mut attempts = 0;
+        let none = wait_for(
+            Duration::ZERO,
+            || -> Result<()> {
+                attempts += 1;
+                bail!(Failure::new("no_device", "No crispy device found"))
+            },
+            is_nothing_found,
+        );
+        assert!(none.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_command_name() {
+        let block = Command::DataBlock {
+            offset: 0x400,
+            data: vec![0; 4],
+        };
+        assert_eq!(command_name(&block), "DataBlock at offset 0x400");
+        assert_eq!(command_name(&Command::GetStatus), "GetStatus");
+        assert_eq!(
+            command_name(&Command::SetActiveBank { bank: 1 }),
+            "SetActiveBank"
+        );
     }
 }
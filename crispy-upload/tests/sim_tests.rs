@@ -0,0 +1,346 @@
+// SPDX-License-Identifier: MIT
+// Copyright (c) 2026 ADNT Sarl <info@adnt.io>
+
+//! The `crispy-upload` binary against a simulated bootloader: each test
+//! starts a `crispy-sim` device on a free port and runs real commands on it
+//! with `--json`, checking their results and what the device reports after.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crispy_sim::{Faults, Sim};
+use serde_json::Value;
+
+/// A device with `faults`, and its `--port`.
+fn device(faults: Faults) -> String {
+    Sim::bind("127.0.0.1:0", faults).unwrap().spawn()
+}
+
+/// A working directory of its own for each test, so no `crispy.toml` is
+/// picked up.
+fn scratch(name: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("crispy-sim-tests-{}-{}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn crispy_upload(dir: &Path, port: &str, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_crispy-upload"));
+    command
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .args([
+            "--port",
+            port,
+            "--json",
+            "--no-history",
+            "--progress",
+            "none",
+        ])
+        .args(args);
+    command
+}
+
+/// Run a command and return whether it succeeded, and its `result` event.
+fn run(dir: &Path, port: &str, args: &[&str]) -> (bool, Value) {
+    let Output { status, stdout, .. } = crispy_upload(dir, port, args).output().unwrap();
+    let stdout = String::from_utf8(stdout).unwrap();
+    let last = stdout.lines().last().unwrap_or_default();
+    let result: Value = serde_json::from_str(last).unwrap_or_else(|_| panic!("{}", stdout));
+    assert_eq!(result["event"], "result");
+    (status.success(), result)
+}
+
+fn status(dir: &Path, port: &str) -> Value {
+    let (ok, result) = run(dir, port, &["status"]);
+    assert!(ok, "{}", result);
+    result["data"]["status"].clone()
+}
+
+/// A raw image the pre-flight checks accept, `len` bytes long.
+fn image(dir: &Path, name: &str, len: usize, seed: u8) -> String {
+    let mut fw = vec![0u8; len];
+    fw[0..4].copy_from_slice(&0x2003_B000u32.to_le_bytes());
+    fw[4..8].copy_from_slice(&0x2000_00C1u32.to_le_bytes());
+    for (i, byte) in fw.iter_mut().enumerate().skip(8) {
+        *byte = (i as u8).wrapping_mul(31) ^ seed;
+    }
+    let path = dir.join(name);
+    fs::write(&path, fw).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn test_upload_verify_set_bank() {
+    let dir = scratch("upload");
+    let port = device(Faults::default());
+    let fw = image(&dir, "fw.bin", 5000, 1);
+    let other = image(&dir, "other.bin", 5000, 2);
+
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &[
+            "upload",
+            &fw,
+            "--bank",
+            "1",
+            "--version",
+            "1.2.3",
+            "--verify",
+        ],
+    );
+    assert!(ok, "{}", result);
+    assert_eq!(result["data"]["bank"], 1);
+    assert_eq!(result["data"]["size"], 5000);
+
+    let (ok, result) = run(&dir, &port, &["verify", &fw, "--bank", "1"]);
+    assert!(ok, "{}", result);
+    let (ok, _) = run(&dir, &port, &["verify", &other, "--bank", "1"]);
+    assert!(!ok);
+
+    // An upload makes its bank the active one
+    let after = status(&dir, &port);
+    assert_eq!(after["active_bank"], 1);
+    assert_eq!(after["version_b"], "1.2.3");
+
+    // The inactive bank is the default target
+    let (ok, result) = run(&dir, &port, &["upload", &other, "--version", "2.0.0"]);
+    assert!(ok, "{}", result);
+    assert_eq!(result["data"]["bank"], 0);
+    let after = status(&dir, &port);
+    assert_eq!(after["active_bank"], 0);
+    assert_eq!(after["version_a"], "2.0.0");
+
+    let (ok, result) = run(&dir, &port, &["set-bank", "1"]);
+    assert!(ok, "{}", result);
+    assert_eq!(status(&dir, &port)["active_bank"], 1);
+}
+
+#[test]
+fn test_upload_both() {
+    let dir = scratch("upload-both");
+    let port = device(Faults::default());
+    let fw_a = image(&dir, "a.bin", 3000, 4);
+    let fw_b = image(&dir, "b.bin", 4000, 5);
+
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &[
+            "upload-both",
+            &fw_a,
+            &fw_b,
+            "--version",
+            "3.1.0",
+            "--verify",
+        ],
+    );
+    assert!(ok, "{}", result);
+    let banks = result["data"]["banks"].as_array().unwrap();
+    assert_eq!(banks[0]["bank"], 0);
+    assert_eq!(banks[0]["size"], 3000);
+    assert_eq!(banks[1]["bank"], 1);
+    assert_eq!(banks[1]["size"], 4000);
+    assert!(banks.iter().all(|bank| bank["verified"] == true));
+    assert_eq!(result["data"]["active_bank"], 0);
+    let after = status(&dir, &port);
+    assert_eq!(after["active_bank"], 0);
+    assert_eq!(
+        (&after["version_a"], &after["version_b"]),
+        (&"3.1.0".into(), &"3.1.0".into())
+    );
+    let (ok, _) = run(&dir, &port, &["verify", &fw_b, "--bank", "1"]);
+    assert!(ok);
+
+    // One image for both, and bank B left active as it was
+    let (ok, result) = run(&dir, &port, &["set-bank", "1"]);
+    assert!(ok, "{}", result);
+    let (ok, result) = run(&dir, &port, &["upload-both", &fw_a, "--no-activate"]);
+    assert!(ok, "{}", result);
+    assert_eq!(result["data"]["active_bank"], 1);
+    assert_eq!(status(&dir, &port)["active_bank"], 1);
+    let (ok, _) = run(&dir, &port, &["verify", &fw_a, "--bank", "1"]);
+    assert!(ok);
+}
+
+#[test]
+fn test_upload_both_stops_at_a_failure() {
+    let dir = scratch("upload-both-fails");
+    let port = device(Faults {
+        corrupt_every: 1,
+        ..Faults::default()
+    });
+    let fw = image(&dir, "fw.bin", 3000, 6);
+
+    // Bank B, the inactive one, goes first and fails its CRC
+    let (ok, result) = run(&dir, &port, &["upload-both", &fw]);
+    assert!(!ok);
+    let error = &result["error"];
+    assert_eq!(error["code"], "crc");
+    let message = error["message"].as_str().unwrap();
+    assert!(
+        message.starts_with("Bank B failed; bank A is untouched"),
+        "{}",
+        message
+    );
+    let after = status(&dir, &port);
+    assert_eq!(after["active_bank"], 0);
+    assert_eq!(after["version_a"], after["version_b"]);
+}
+
+#[test]
+fn test_verify_all() {
+    let dir = scratch("verify-all");
+    let port = device(Faults::default());
+    let verify_all = || {
+        let output = crispy_upload(&dir, &port, &["verify-all"])
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let result: Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+        (output.status.code(), result)
+    };
+
+    // A blank device warns: no record, no firmware
+    let (code, result) = verify_all();
+    assert_eq!(code, Some(1), "{}", result);
+    assert_eq!(result["error"]["code"], "inconsistent");
+    assert_eq!(result["error"]["context"]["worst"], "warn");
+    assert_eq!(
+        result["error"]["context"]["flagged"],
+        serde_json::json!(["record", "active_bank"])
+    );
+
+    let fw = image(&dir, "fw.bin", 5000, 7);
+    let (ok, result) = run(&dir, &port, &["upload", &fw]);
+    assert!(ok, "{}", result);
+    let (code, result) = verify_all();
+    assert_eq!(code, Some(0), "{}", result);
+    assert_eq!(result["data"]["worst"], "pass");
+    let checks = result["data"]["checks"].as_array().unwrap();
+    assert_eq!(checks.len(), 6);
+    assert_eq!(checks[3]["check"], "bank_b");
+}
+
+#[test]
+fn test_wipe() {
+    let dir = scratch("wipe");
+    let port = device(Faults::default());
+    let fw = image(&dir, "fw.bin", 3000, 3);
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &["upload", &fw, "--bank", "1", "--version", "1.0.0"],
+    );
+    assert!(ok, "{}", result);
+
+    let (ok, result) = run(&dir, &port, &["wipe"]);
+    assert!(ok, "{}", result);
+    let after = status(&dir, &port);
+    assert_eq!(after["version_a"], "0.0.0");
+    assert_eq!(after["version_b"], "0.0.0");
+    // Only BootData goes; the bank is left as it was
+    let (ok, result) = run(&dir, &port, &["verify", &fw, "--bank", "1"]);
+    assert!(ok, "{}", result);
+}
+
+#[test]
+fn test_abort_and_resume() {
+    let dir = scratch("abort");
+    // 48 sectors take about a second to erase
+    let port = device(Faults {
+        erase_delay: Duration::from_millis(20),
+        ..Faults::default()
+    });
+    let fw = image(&dir, "fw.bin", 192 * 1024, 4);
+
+    let mut upload = crispy_upload(
+        &dir,
+        &port,
+        &["upload", &fw, "--bank", "1", "--version", "3.0.0"],
+    )
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .unwrap();
+    thread::sleep(Duration::from_millis(500));
+    upload.kill().unwrap();
+    upload.wait().unwrap();
+
+    // The device dropped the update with the connection
+    let after = status(&dir, &port);
+    assert_eq!(after["state"], "UpdateMode");
+    assert_eq!(after["version_b"], "0.0.0");
+
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &[
+            "upload",
+            &fw,
+            "--bank",
+            "1",
+            "--version",
+            "3.0.0",
+            "--verify",
+        ],
+    );
+    assert!(ok, "{}", result);
+    assert_eq!(status(&dir, &port)["version_b"], "3.0.0");
+}
+
+#[test]
+fn test_lost_frames_are_retried() {
+    let dir = scratch("drop");
+    let port = device(Faults {
+        // Each time a data block or a flash read, which are re-sent
+        drop_every: 7,
+        ..Faults::default()
+    });
+    let fw = image(&dir, "fw.bin", 8000, 5);
+
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &[
+            "--read-timeout",
+            "300",
+            "--retry-backoff",
+            "10",
+            "upload",
+            &fw,
+            "--bank",
+            "1",
+            "--version",
+            "1.0.0",
+            "--verify",
+        ],
+    );
+    assert!(ok, "{}", result);
+    assert!(result["data"]["retries"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_corrupt_block_fails_the_crc() {
+    let dir = scratch("corrupt");
+    let port = device(Faults {
+        corrupt_every: 3,
+        ..Faults::default()
+    });
+    let fw = image(&dir, "fw.bin", 8000, 6);
+
+    let (ok, result) = run(
+        &dir,
+        &port,
+        &["upload", &fw, "--bank", "1", "--version", "1.0.0"],
+    );
+    assert!(!ok);
+    assert_eq!(result["error"]["code"], "crc");
+    // Nothing was committed
+    assert_eq!(status(&dir, &port)["version_b"], "0.0.0");
+}
@@ -12,9 +12,10 @@
 /* Modify these values to change memory allocation (must be 4KB sector-aligned) */
 
 __flash_base       = 0x10000000;
+__flash_size       = 0x200000;   /* 2MB - rewritten by build.rs for the flash-* features */
 __boot2_size       = 0x100;      /* 256B - fixed by RP2040 */
 __bootloader_size  = 0x10000;    /* 64KB - adjust as needed */
-__fw_bank_size     = 0xC0000;    /* 768KB per firmware bank */
+__flash_reserved_tail = 0x70000; /* 448KB left unused at the end of flash */
 __boot_data_size   = 0x1000;     /* 4KB for boot metadata */
 __fw_copy_size     = 0x30000;    /* 192KB copied to RAM */
 
@@ -32,6 +33,8 @@ __fw_ram_end       = 0x20042000;
 /* ============================================================================ */
 
 /* Calculated addresses (do not modify) */
+/* Must match crispy_common::protocol; checked by the bootloader at startup */
+__fw_bank_size     = (__flash_size - __bootloader_size - __flash_reserved_tail) / 2;
 __fw_a_entry       = __flash_base + __bootloader_size;
 __fw_b_entry       = __fw_a_entry + __fw_bank_size;
 __boot_data_addr   = __fw_b_entry + __fw_bank_size;
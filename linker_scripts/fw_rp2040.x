@@ -16,3 +16,16 @@ MEMORY {
     FLASH : ORIGIN = 0x20000000, LENGTH = 192K
     RAM   : ORIGIN = 0x20030000, LENGTH = 48K
 }
+
+/*
+* Version block from crispy_common::fw_version!, at a fixed offset right
+* after the largest RP2040 vector table (0xC0 bytes) so crispy-upload finds
+* it in a raw binary too. .text starts after it.
+*/
+SECTIONS {
+    .crispy_fw_version ORIGIN(FLASH) + 0xC0 : {
+        KEEP(*(.crispy_fw_version));
+    } > FLASH
+} INSERT AFTER .vector_table;
+
+_stext = ORIGIN(FLASH) + 0xC8;
@@ -36,9 +36,11 @@ class Command:
         return encode_get_status()
 
     @staticmethod
-    def start_update(bank: int, size: int, crc32: int, version: int) -> bytes:
+    def start_update(
+        bank: int, size: int, crc32: int, version: int, sparse: bool = False
+    ) -> bytes:
         """Create a StartUpdate command."""
-        return encode_start_update(bank, size, crc32, version)
+        return encode_start_update(bank, size, crc32, version, sparse)
 
     @staticmethod
     def data_block(offset: int, data: bytes) -> bytes:
@@ -129,13 +131,20 @@ def encode_get_status() -> bytes:
     return _frame(bytes([CommandType.GET_STATUS]))
 
 
-def encode_start_update(bank: int, size: int, crc32: int, version: int) -> bytes:
-    """Encode a StartUpdate command."""
+def encode_start_update(
+    bank: int, size: int, crc32: int, version: int, sparse: bool = False
+) -> bytes:
+    """Encode a StartUpdate command.
+
+    With ``sparse``, data blocks may skip ahead to a later page, leaving the
+    pages in between erased.
+    """
     payload = (
         bytes([CommandType.START_UPDATE, bank])
         + encode_varint(size)
         + encode_varint(crc32)
         + encode_varint(version)
+        + bytes([1 if sparse else 0])
     )
     return _frame(payload)
 
@@ -155,6 +155,14 @@ class TestEncodeStartUpdate:
         decoded = cobs_decode(encoded[:-1])
         assert decoded[1] == 1  # bank B
 
+    def test_encodes_sparse_flag(self):
+        """StartUpdate ends with the sparse flag."""
+        sequential = cobs_decode(encode_start_update(0, 1024, 0, 1)[:-1])
+        sparse = cobs_decode(encode_start_update(0, 1024, 0, 1, sparse=True)[:-1])
+        assert sequential[-1] == 0
+        assert sparse[-1] == 1
+        assert sequential[:-1] == sparse[:-1]
+
     def test_encodes_large_size(self):
         """StartUpdate with large size value."""
         encoded = encode_start_update(bank=0, size=786432, crc32=0xDEADBEEF, version=100)